@@ -73,6 +73,7 @@ impl rend3_framework::App for AnimationExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            shadow_fade: 0.1,
             resolution: 2048,
         });
 
@@ -139,16 +140,25 @@ impl rend3_framework::App for AnimationExample {
                     pbr: &pbr_routine,
                     skybox: None,
                     tonemapping: &tonemapping_routine,
+                    shadow_cache: None,
+                    post_process: &[],
+                    tiled_lighting: None,
+                    particles: &[],
+                    lens_flare: None,
+                    bloom: None,
+                    object_compute: &[],
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
                     resolution: context.resolution,
                     samples: SAMPLE_COUNT,
+                    render_scale: 1.0,
                 },
             },
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::new(0.10, 0.05, 0.10, 1.0), // Nice scene-referred purple
+                ..Default::default()
             },
         );
 
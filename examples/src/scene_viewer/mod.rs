@@ -242,6 +242,7 @@ Rendering:
   -p --profile                 Choose rendering profile to use ('cpu', 'gpu').
   -v --vsync                   Choose vsync mode ('immediate' [no-vsync], 'fifo' [vsync], 'fifo_relaxed' [adaptive vsync], 'mailbox' [fast vsync])
   --msaa <level>               Level of antialiasing (either 1 or 4). Default 1.
+  --render-scale <scale>       Scale of the resolution the scene is rendered at, relative to the window. Default 1.0.
 
 Windowing:
   --absolute-mouse             Interpret the relative mouse coordinates as absolute. Useful when using things like VNC.
@@ -255,6 +256,7 @@ Assets:
   --ambient <value>                      Set the value of the minimum ambient light. This will be treated as white light of this intensity. Defaults to 0.1.
   --scale <scale>                        Scale all objects loaded by this factor. Defaults to 1.0.
   --shadow-distance <value>              Distance from the camera there will be directional shadows. Lower values means higher quality shadows. Defaults to 100.
+  --shadow-fade <value>                  Fraction of the shadow distance over which shadows fade out. Defaults to 0.1.
   --shadow-resolution <value>            Resolution of the shadow map. Higher values mean higher quality shadows with high performance cost. Defaults to 2048.
 
 Controls:
@@ -281,6 +283,7 @@ pub struct SceneViewer {
     ambient_light_level: f32,
     present_mode: rend3::types::PresentMode,
     samples: SampleCount,
+    render_scale: f32,
 
     fullscreen: bool,
     wait_for_load: bool,
@@ -315,6 +318,7 @@ impl Default for SceneViewer {
             ambient_light_level: 0.1,
             present_mode: wgpu::PresentMode::Fifo,
             samples: SampleCount::One,
+            render_scale: 1.0,
             fullscreen: false,
             wait_for_load: false,
             loading_reciever: None,
@@ -354,6 +358,9 @@ impl SceneViewer {
         if let Some(samples) = option_arg(args.opt_value_from_fn("--msaa", extract_msaa)) {
             app.samples = samples;
         }
+        if let Some(render_scale) = option_arg(args.opt_value_from_str("--render-scale")) {
+            app.render_scale = render_scale;
+        }
         if let Some(present_mode) = option_arg(args.opt_value_from_fn(["-v", "--vsync"], extract_vsync)) {
             app.present_mode = present_mode;
         }
@@ -381,6 +388,9 @@ impl SceneViewer {
         if let Some(shadow_distance) = option_arg(args.opt_value_from_str("--shadow-distance")) {
             app.gltf_settings.directional_light_shadow_distance = shadow_distance;
         }
+        if let Some(shadow_fade) = option_arg(args.opt_value_from_str("--shadow-fade")) {
+            app.gltf_settings.directional_light_shadow_fade = shadow_fade;
+        }
         if let Some(shadow_resolution) = option_arg(args.opt_value_from_str("--shadow-resolution")) {
             app.gltf_settings.directional_light_resolution = shadow_resolution;
         }
@@ -468,6 +478,7 @@ impl rend3_framework::App for SceneViewer {
                 intensity: self.directional_light_intensity,
                 direction,
                 distance: self.gltf_settings.directional_light_shadow_distance,
+                shadow_fade: self.gltf_settings.directional_light_shadow_fade,
                 resolution: 2048,
             }));
         }
@@ -674,16 +685,25 @@ impl rend3_framework::App for SceneViewer {
                     pbr: &pbr_routine,
                     skybox: Some(&skybox_routine),
                     tonemapping: &tonemapping_routine,
+                    shadow_cache: None,
+                    post_process: &[],
+                    tiled_lighting: None,
+                    particles: &[],
+                    lens_flare: None,
+                    bloom: None,
+                    object_compute: &[],
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
                     resolution: context.resolution,
                     samples: self.samples,
+                    render_scale: self.render_scale,
                 },
             },
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: Vec3::splat(self.ambient_light_level).extend(1.0),
                 clear_color: glam::Vec4::new(0.0, 0.0, 0.0, 1.0),
+                ..Default::default()
             },
         );
 
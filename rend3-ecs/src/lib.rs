@@ -0,0 +1,325 @@
+//! Keeps a rend3 scene in sync with the entities of an ECS.
+//!
+//! Entities that should be drawn get a [`Renderable`] and a [`Transform`]
+//! component, entities that light the scene a [`Light`] component. Each
+//! frame the application's render system queries these components and hands
+//! them to a [`SceneSync`], which adds, updates and removes rend3 objects and
+//! lights as needed. Entities whose components didn't change issue no
+//! renderer instructions.
+//!
+//! Any entity id that is `Copy + Eq + Hash` works. The `hecs` and `bevy_ecs`
+//! features sync whole worlds of those ECSes in one call, with
+//! `SceneSync::sync_hecs` and `SceneSync::sync_bevy`. With the `bevy_ecs`
+//! feature the components implement bevy's `Component`.
+//!
+//! ECSes that track changes themselves, like bevy's `Changed` filter, can
+//! skip the comparisons by only passing changed entities to
+//! [`SceneSync::update_objects`] and [`SceneSync::update_lights`], and
+//! despawned entities to [`SceneSync::remove`].
+
+use std::{hash::Hash, ops::Add, sync::Arc};
+
+use rend3::{
+    types::{
+        glam::Mat4, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, MaterialHandle, Object,
+        ObjectHandle, ObjectMeshKind, PointLight, PointLightChange, PointLightHandle,
+    },
+    util::typedefs::FastHashMap,
+    Renderer,
+};
+
+/// Draws the entity as a rend3 object. Needs a [`Transform`] as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct Renderable {
+    pub mesh: ObjectMeshKind,
+    pub material: MaterialHandle,
+}
+
+/// World transform of an entity with a [`Renderable`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct Transform(pub Mat4);
+
+/// Lights the scene from the entity.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub enum Light {
+    Directional(DirectionalLight),
+    Point(PointLight),
+}
+
+/// Renderer instructions issued by a sync.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SyncStats {
+    /// Objects or lights added for new entities.
+    pub added: usize,
+    /// Objects or lights whose entity's components changed.
+    pub updated: usize,
+    /// Objects or lights of entities that are gone.
+    pub removed: usize,
+}
+
+impl Add for SyncStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            added: self.added + other.added,
+            updated: self.updated + other.updated,
+            removed: self.removed + other.removed,
+        }
+    }
+}
+
+struct SyncedObject {
+    handle: ObjectHandle,
+    renderable: Renderable,
+    transform: Mat4,
+    seen: bool,
+}
+
+enum SyncedLightHandle {
+    Directional(DirectionalLightHandle),
+    Point(PointLightHandle),
+}
+
+struct SyncedLight {
+    handle: SyncedLightHandle,
+    light: Light,
+    seen: bool,
+}
+
+/// Objects and lights of entities, and the component values they were last
+/// synced with.
+///
+/// See the crate documentation.
+pub struct SceneSync<E> {
+    objects: FastHashMap<E, SyncedObject>,
+    lights: FastHashMap<E, SyncedLight>,
+}
+
+impl<E: Copy + Eq + Hash> Default for SceneSync<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Copy + Eq + Hash> SceneSync<E> {
+    pub fn new() -> Self {
+        Self { objects: FastHashMap::default(), lights: FastHashMap::default() }
+    }
+
+    /// Syncs the objects of every entity with a [`Renderable`]. Objects of
+    /// entities that aren't given are removed.
+    pub fn sync_objects<'a>(
+        &mut self,
+        renderer: &Arc<Renderer>,
+        entities: impl IntoIterator<Item = (E, &'a Renderable, &'a Transform)>,
+    ) -> SyncStats {
+        profiling::scope!("SceneSync::sync_objects");
+
+        let mut stats = self.update_objects(renderer, entities);
+
+        let count = self.objects.len();
+        self.objects.retain(|_, object| std::mem::take(&mut object.seen));
+        stats.removed += count - self.objects.len();
+        stats
+    }
+
+    /// Syncs the objects of the given entities, leaving the objects of other
+    /// entities alone.
+    pub fn update_objects<'a>(
+        &mut self,
+        renderer: &Arc<Renderer>,
+        entities: impl IntoIterator<Item = (E, &'a Renderable, &'a Transform)>,
+    ) -> SyncStats {
+        profiling::scope!("SceneSync::update_objects");
+
+        let mut stats = SyncStats::default();
+        let mut moved = Vec::new();
+        for (entity, renderable, &Transform(transform)) in entities {
+            match self.objects.get_mut(&entity) {
+                Some(synced) if synced.renderable == *renderable => {
+                    if synced.transform != transform {
+                        synced.transform = transform;
+                        moved.push(entity);
+                        stats.updated += 1;
+                    }
+                    synced.seen = true;
+                }
+                synced => {
+                    let added = synced.is_none();
+                    let handle = renderer.add_object(Object {
+                        mesh_kind: renderable.mesh.clone(),
+                        material: renderable.material.clone(),
+                        transform,
+                    });
+                    self.objects
+                        .insert(entity, SyncedObject { handle, renderable: renderable.clone(), transform, seen: true });
+                    match added {
+                        true => stats.added += 1,
+                        false => stats.updated += 1,
+                    }
+                }
+            }
+        }
+
+        renderer.set_object_transforms(moved.iter().map(|entity| {
+            let synced = &self.objects[entity];
+            (&synced.handle, synced.transform)
+        }));
+
+        stats
+    }
+
+    /// Syncs the lights of every entity with a [`Light`]. Lights of entities
+    /// that aren't given are removed.
+    pub fn sync_lights<'a>(
+        &mut self,
+        renderer: &Arc<Renderer>,
+        entities: impl IntoIterator<Item = (E, &'a Light)>,
+    ) -> SyncStats {
+        profiling::scope!("SceneSync::sync_lights");
+
+        let mut stats = self.update_lights(renderer, entities);
+
+        let count = self.lights.len();
+        self.lights.retain(|_, light| std::mem::take(&mut light.seen));
+        stats.removed += count - self.lights.len();
+        stats
+    }
+
+    /// Syncs the lights of the given entities, leaving the lights of other
+    /// entities alone.
+    pub fn update_lights<'a>(
+        &mut self,
+        renderer: &Arc<Renderer>,
+        entities: impl IntoIterator<Item = (E, &'a Light)>,
+    ) -> SyncStats {
+        profiling::scope!("SceneSync::update_lights");
+
+        let mut stats = SyncStats::default();
+        for (entity, light) in entities {
+            let synced = match self.lights.get_mut(&entity) {
+                Some(synced) => synced,
+                None => {
+                    self.lights.insert(
+                        entity,
+                        SyncedLight { handle: add_light(renderer, light), light: light.clone(), seen: true },
+                    );
+                    stats.added += 1;
+                    continue;
+                }
+            };
+            synced.seen = true;
+
+            match (&synced.handle, &synced.light, light) {
+                (SyncedLightHandle::Directional(handle), Light::Directional(old), Light::Directional(new)) => {
+                    if !directional_eq(old, new) {
+                        renderer.update_directional_light(handle, directional_change(new));
+                        synced.light = light.clone();
+                        stats.updated += 1;
+                    }
+                }
+                (SyncedLightHandle::Point(handle), Light::Point(old), Light::Point(new)) => {
+                    if !point_eq(old, new) {
+                        renderer.update_point_light(handle, point_change(new));
+                        synced.light = light.clone();
+                        stats.updated += 1;
+                    }
+                }
+                _ => {
+                    synced.handle = add_light(renderer, light);
+                    synced.light = light.clone();
+                    stats.updated += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    /// Removes the object and light of an entity.
+    pub fn remove(&mut self, entity: E) {
+        self.objects.remove(&entity);
+        self.lights.remove(&entity);
+    }
+
+    /// Removes all objects and lights.
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.lights.clear();
+    }
+
+    /// The object of an entity.
+    pub fn object(&self, entity: E) -> Option<&ObjectHandle> {
+        self.objects.get(&entity).map(|synced| &synced.handle)
+    }
+}
+
+#[cfg(feature = "hecs")]
+impl SceneSync<hecs::Entity> {
+    /// Syncs the objects and lights of every entity in a hecs world.
+    pub fn sync_hecs(&mut self, renderer: &Arc<Renderer>, world: &hecs::World) -> SyncStats {
+        let mut query = world.query::<(&Renderable, &Transform)>();
+        let objects = self.sync_objects(
+            renderer,
+            query.iter().map(|(entity, (renderable, transform))| (entity, renderable, transform)),
+        );
+        let mut query = world.query::<&Light>();
+        let lights = self.sync_lights(renderer, query.iter());
+        objects + lights
+    }
+}
+
+#[cfg(feature = "bevy_ecs")]
+impl SceneSync<bevy_ecs::entity::Entity> {
+    /// Syncs the objects and lights of every entity in a bevy world.
+    pub fn sync_bevy(&mut self, renderer: &Arc<Renderer>, world: &mut bevy_ecs::world::World) -> SyncStats {
+        use bevy_ecs::entity::Entity;
+
+        let objects = self.sync_objects(renderer, world.query::<(Entity, &Renderable, &Transform)>().iter(world));
+        let lights = self.sync_lights(renderer, world.query::<(Entity, &Light)>().iter(world));
+        objects + lights
+    }
+}
+
+fn add_light(renderer: &Arc<Renderer>, light: &Light) -> SyncedLightHandle {
+    match light {
+        Light::Directional(light) => SyncedLightHandle::Directional(renderer.add_directional_light(light.clone())),
+        Light::Point(light) => SyncedLightHandle::Point(renderer.add_point_light(light.clone())),
+    }
+}
+
+fn directional_eq(a: &DirectionalLight, b: &DirectionalLight) -> bool {
+    a.color == b.color
+        && a.resolution == b.resolution
+        && a.intensity == b.intensity
+        && a.direction == b.direction
+        && a.distance == b.distance
+        && a.shadow_fade == b.shadow_fade
+}
+
+fn directional_change(light: &DirectionalLight) -> DirectionalLightChange {
+    DirectionalLightChange {
+        color: Some(light.color),
+        resolution: Some(light.resolution),
+        intensity: Some(light.intensity),
+        direction: Some(light.direction),
+        distance: Some(light.distance),
+        shadow_fade: Some(light.shadow_fade),
+    }
+}
+
+fn point_eq(a: &PointLight, b: &PointLight) -> bool {
+    a.position == b.position && a.color == b.color && a.radius == b.radius && a.intensity == b.intensity
+}
+
+fn point_change(light: &PointLight) -> PointLightChange {
+    PointLightChange {
+        position: Some(light.position),
+        color: Some(light.color),
+        radius: Some(light.radius),
+        intensity: Some(light.intensity),
+    }
+}
@@ -7,7 +7,9 @@ use std::{mem, sync::Arc};
 use egui::TexturesDelta;
 use glam::Vec4;
 use rend3::{
-    graph::{NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets, RenderTargetHandle,
+    },
     types::SampleCount,
     Renderer,
 };
@@ -59,7 +61,12 @@ impl EguiRenderRoutine {
 
         let rpass_handle = builder.add_renderpass(
             RenderPassTargets {
-                targets: vec![RenderPassTarget { color: output, clear: Vec4::ZERO, resolve: None }],
+                targets: vec![RenderPassTarget {
+                    color: output,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
                 depth_stencil: None,
             },
             NodeResourceUsage::InputOutput,
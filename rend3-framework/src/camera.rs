@@ -0,0 +1,411 @@
+//! Reusable camera controllers driven by winit input.
+//!
+//! Feed every event to [`CameraController::handle_event`], then call
+//! [`CameraController::update`] once per frame and hand the result to
+//! [`Renderer::set_camera_data`](rend3::Renderer::set_camera_data).
+
+use std::collections::HashSet;
+
+use glam::{Mat3A, Mat4, Vec2, Vec3A};
+use rend3::types::{Camera, CameraProjection, Handedness};
+use winit::{
+    event::{DeviceEvent, ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+/// Tunables shared by all camera controllers.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraControllerSettings {
+    /// Projection used for the produced [`Camera`].
+    pub projection: CameraProjection,
+    /// Radians of rotation per pixel of mouse movement.
+    pub rotation_sensitivity: f32,
+    /// Movement speed of the fly camera in units per second.
+    pub movement_speed: f32,
+    /// Multiplier applied to the movement speed while shift is held.
+    pub run_multiplier: f32,
+    /// Fraction of the orbit distance moved per pixel of mouse movement while panning.
+    pub pan_sensitivity: f32,
+    /// Fraction of the orbit distance zoomed per line of scrolling.
+    pub zoom_sensitivity: f32,
+    /// Time in seconds it takes the camera to cover most of the distance to where the input
+    /// wants it to be. Zero disables smoothing.
+    pub smoothing: f32,
+}
+
+impl Default for CameraControllerSettings {
+    fn default() -> Self {
+        Self {
+            projection: CameraProjection::default(),
+            rotation_sensitivity: 0.001,
+            movement_speed: 10.0,
+            run_multiplier: 5.0,
+            pan_sensitivity: 0.002,
+            zoom_sensitivity: 0.1,
+            smoothing: 0.05,
+        }
+    }
+}
+
+impl CameraControllerSettings {
+    /// Blend factor towards the target state for a frame of the given length.
+    fn blend_factor(&self, delta_t_seconds: f32) -> f32 {
+        if self.smoothing <= 0.0 {
+            1.0
+        } else {
+            1.0 - (-delta_t_seconds / self.smoothing).exp()
+        }
+    }
+}
+
+/// A controller that turns window input into [`Camera`] data.
+pub trait CameraController {
+    /// Record any input relevant to this controller.
+    fn handle_event<T>(&mut self, event: &Event<T>);
+
+    /// Advance the controller by a frame and get the resulting camera.
+    fn update(&mut self, delta_t_seconds: f32) -> Camera;
+}
+
+/// Accumulated input state between updates.
+#[derive(Debug, Default)]
+struct InputState {
+    keys: HashSet<KeyCode>,
+    buttons: HashSet<MouseButton>,
+    mouse_delta: Vec2,
+    scroll_delta: f32,
+}
+
+impl InputState {
+    fn handle_event<T>(&mut self, event: &Event<T>) {
+        match *event {
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event: KeyEvent { physical_key: PhysicalKey::Code(code), state, .. }, ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    self.keys.insert(code);
+                }
+                ElementState::Released => {
+                    self.keys.remove(&code);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { button, state, .. }, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons.insert(button);
+                }
+                ElementState::Released => {
+                    self.buttons.remove(&button);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    // Roughly one line per 50 pixels.
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+            }
+            // Losing focus means we'll miss the release events.
+            Event::WindowEvent { event: WindowEvent::Focused(false), .. } => {
+                self.keys.clear();
+                self.buttons.clear();
+            }
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta: (x, y) }, .. } => {
+                self.mouse_delta += Vec2::new(x as f32, y as f32);
+            }
+            _ => {}
+        }
+    }
+
+    fn key(&self, key: KeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    fn axis(&self, positive: KeyCode, negative: KeyCode) -> f32 {
+        self.key(positive) as u8 as f32 - self.key(negative) as u8 as f32
+    }
+
+    fn take_mouse_delta(&mut self) -> Vec2 {
+        std::mem::take(&mut self.mouse_delta)
+    }
+
+    fn take_scroll_delta(&mut self) -> f32 {
+        std::mem::take(&mut self.scroll_delta)
+    }
+}
+
+/// Orientation of a camera as pitch and yaw in radians.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Orientation {
+    pitch: f32,
+    yaw: f32,
+}
+
+impl Orientation {
+    const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.0001;
+
+    fn rotate(&mut self, delta: Vec2, sensitivity: f32) {
+        self.yaw -= delta.x * sensitivity;
+        self.pitch = (self.pitch - delta.y * sensitivity).clamp(-Self::PITCH_LIMIT, Self::PITCH_LIMIT);
+    }
+
+    fn lerp(self, target: Self, t: f32) -> Self {
+        Self { pitch: self.pitch + (target.pitch - self.pitch) * t, yaw: self.yaw + (target.yaw - self.yaw) * t }
+    }
+
+    fn view_rotation(self) -> Mat4 {
+        Mat4::from_euler(glam::EulerRot::XYZ, -self.pitch, -self.yaw, 0.0)
+    }
+
+    /// Returns the (forward, right, up) vectors of this orientation in world space.
+    fn basis(self, handedness: Handedness) -> (Vec3A, Vec3A, Vec3A) {
+        let rotation = Mat3A::from_euler(glam::EulerRot::XYZ, -self.pitch, -self.yaw, 0.0).transpose();
+        let forward = match handedness {
+            Handedness::Left => rotation.z_axis,
+            Handedness::Right => -rotation.z_axis,
+        };
+        (forward, rotation.x_axis, rotation.y_axis)
+    }
+
+    fn view(self, position: Vec3A) -> Mat4 {
+        self.view_rotation() * Mat4::from_translation((-position).into())
+    }
+}
+
+/// First person camera. WASD moves, Q/E move down/up, shift runs, and the mouse looks around.
+#[derive(Debug)]
+pub struct FlyController {
+    pub settings: CameraControllerSettings,
+    /// If set, the camera only looks around while this button is held. Use `None` when the
+    /// cursor is grabbed.
+    pub look_button: Option<MouseButton>,
+    handedness: Handedness,
+    input: InputState,
+    target_position: Vec3A,
+    target_orientation: Orientation,
+    position: Vec3A,
+    orientation: Orientation,
+}
+
+impl FlyController {
+    pub fn new(handedness: Handedness, position: Vec3A, pitch: f32, yaw: f32) -> Self {
+        let orientation = Orientation { pitch, yaw };
+        Self {
+            settings: CameraControllerSettings::default(),
+            look_button: Some(MouseButton::Right),
+            handedness,
+            input: InputState::default(),
+            target_position: position,
+            target_orientation: orientation,
+            position,
+            orientation,
+        }
+    }
+
+    /// Teleport the camera, skipping any smoothing.
+    pub fn set_location(&mut self, position: Vec3A, pitch: f32, yaw: f32) {
+        self.target_position = position;
+        self.position = position;
+        self.target_orientation = Orientation { pitch, yaw };
+        self.orientation = self.target_orientation;
+    }
+
+    pub fn position(&self) -> Vec3A {
+        self.position
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.orientation.pitch
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.orientation.yaw
+    }
+}
+
+impl CameraController for FlyController {
+    fn handle_event<T>(&mut self, event: &Event<T>) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, delta_t_seconds: f32) -> Camera {
+        let mouse_delta = self.input.take_mouse_delta();
+        self.input.take_scroll_delta();
+        if self.look_button.map_or(true, |button| self.input.buttons.contains(&button)) {
+            self.target_orientation.rotate(mouse_delta, self.settings.rotation_sensitivity);
+        }
+
+        let (forward, right, up) = self.target_orientation.basis(self.handedness);
+        let mut speed = self.settings.movement_speed;
+        if self.input.key(KeyCode::ShiftLeft) || self.input.key(KeyCode::ShiftRight) {
+            speed *= self.settings.run_multiplier;
+        }
+        let direction = forward * self.input.axis(KeyCode::KeyW, KeyCode::KeyS)
+            + right * self.input.axis(KeyCode::KeyD, KeyCode::KeyA)
+            + up * self.input.axis(KeyCode::KeyE, KeyCode::KeyQ);
+        self.target_position += direction.normalize_or_zero() * speed * delta_t_seconds;
+
+        let t = self.settings.blend_factor(delta_t_seconds);
+        self.position = self.position.lerp(self.target_position, t);
+        self.orientation = self.orientation.lerp(self.target_orientation, t);
+
+        Camera { projection: self.settings.projection, view: self.orientation.view(self.position) }
+    }
+}
+
+/// Shared state of cameras looking at a focus point from a distance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct FocusState {
+    focus: Vec3A,
+    distance: f32,
+    orientation: Orientation,
+}
+
+impl FocusState {
+    fn lerp(self, target: Self, t: f32) -> Self {
+        Self {
+            focus: self.focus.lerp(target.focus, t),
+            distance: self.distance + (target.distance - self.distance) * t,
+            orientation: self.orientation.lerp(target.orientation, t),
+        }
+    }
+
+    fn pan(&mut self, delta: Vec2, handedness: Handedness, sensitivity: f32) {
+        let (_, right, up) = self.orientation.basis(handedness);
+        self.focus += (-right * delta.x + up * delta.y) * sensitivity * self.distance;
+    }
+
+    fn zoom(&mut self, scroll: f32, sensitivity: f32) {
+        self.distance = (self.distance * (1.0 - scroll * sensitivity).max(0.01)).max(0.01);
+    }
+
+    fn view(self, handedness: Handedness) -> Mat4 {
+        let (forward, _, _) = self.orientation.basis(handedness);
+        self.orientation.view(self.focus - forward * self.distance)
+    }
+}
+
+/// Camera orbiting a focus point. Left drag rotates, middle drag pans, and scrolling zooms.
+#[derive(Debug)]
+pub struct OrbitController {
+    pub settings: CameraControllerSettings,
+    pub rotate_button: MouseButton,
+    pub pan_button: MouseButton,
+    handedness: Handedness,
+    input: InputState,
+    target: FocusState,
+    current: FocusState,
+}
+
+impl OrbitController {
+    pub fn new(handedness: Handedness, focus: Vec3A, distance: f32, pitch: f32, yaw: f32) -> Self {
+        let state = FocusState { focus, distance, orientation: Orientation { pitch, yaw } };
+        Self {
+            settings: CameraControllerSettings::default(),
+            rotate_button: MouseButton::Left,
+            pan_button: MouseButton::Middle,
+            handedness,
+            input: InputState::default(),
+            target: state,
+            current: state,
+        }
+    }
+
+    /// Move the focus point, keeping the current distance and orientation.
+    pub fn set_focus(&mut self, focus: Vec3A) {
+        self.target.focus = focus;
+    }
+
+    pub fn focus(&self) -> Vec3A {
+        self.current.focus
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.current.distance
+    }
+}
+
+impl CameraController for OrbitController {
+    fn handle_event<T>(&mut self, event: &Event<T>) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, delta_t_seconds: f32) -> Camera {
+        let mouse_delta = self.input.take_mouse_delta();
+        if self.input.buttons.contains(&self.rotate_button) {
+            self.target.orientation.rotate(mouse_delta, self.settings.rotation_sensitivity);
+        } else if self.input.buttons.contains(&self.pan_button) {
+            self.target.pan(mouse_delta, self.handedness, self.settings.pan_sensitivity);
+        }
+        self.target.zoom(self.input.take_scroll_delta(), self.settings.zoom_sensitivity);
+
+        self.current = self.current.lerp(self.target, self.settings.blend_factor(delta_t_seconds));
+
+        Camera { projection: self.settings.projection, view: self.current.view(self.handedness) }
+    }
+}
+
+/// Camera with a fixed orientation that pans across the view plane. Dragging or the arrow keys
+/// pan and scrolling zooms. Useful for top-down and 2D views.
+#[derive(Debug)]
+pub struct PanController {
+    pub settings: CameraControllerSettings,
+    pub pan_button: MouseButton,
+    handedness: Handedness,
+    input: InputState,
+    target: FocusState,
+    current: FocusState,
+}
+
+impl PanController {
+    pub fn new(handedness: Handedness, focus: Vec3A, distance: f32, pitch: f32, yaw: f32) -> Self {
+        let state = FocusState { focus, distance, orientation: Orientation { pitch, yaw } };
+        Self {
+            settings: CameraControllerSettings::default(),
+            pan_button: MouseButton::Left,
+            handedness,
+            input: InputState::default(),
+            target: state,
+            current: state,
+        }
+    }
+
+    pub fn set_focus(&mut self, focus: Vec3A) {
+        self.target.focus = focus;
+    }
+
+    pub fn focus(&self) -> Vec3A {
+        self.current.focus
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.current.distance
+    }
+}
+
+impl CameraController for PanController {
+    fn handle_event<T>(&mut self, event: &Event<T>) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, delta_t_seconds: f32) -> Camera {
+        let mouse_delta = self.input.take_mouse_delta();
+        if self.input.buttons.contains(&self.pan_button) {
+            self.target.pan(mouse_delta, self.handedness, self.settings.pan_sensitivity);
+        }
+
+        let (_, right, up) = self.target.orientation.basis(self.handedness);
+        let direction = right * self.input.axis(KeyCode::ArrowRight, KeyCode::ArrowLeft)
+            + up * self.input.axis(KeyCode::ArrowUp, KeyCode::ArrowDown);
+        self.target.focus += direction.normalize_or_zero() * self.settings.movement_speed * delta_t_seconds;
+
+        self.target.zoom(self.input.take_scroll_delta(), self.settings.zoom_sensitivity);
+
+        self.current = self.current.lerp(self.target, self.settings.blend_factor(delta_t_seconds));
+
+        Camera { projection: self.settings.projection, view: self.current.view(self.handedness) }
+    }
+}
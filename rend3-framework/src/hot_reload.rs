@@ -0,0 +1,223 @@
+//! Reloading assets from disk while the application runs.
+//!
+//! A [`HotReloader`] watches the source files of assets and calls a reload
+//! function with the new contents of a file when it changes. The reload
+//! functions put the new data behind the existing handles with
+//! [`Renderer::replace_texture_2d`], [`Renderer::replace_mesh`],
+//! [`Renderer::swap_meshes`] and [`Renderer::swap_materials`], so objects and
+//! materials using them don't have to be recreated.
+//!
+//! glTF scenes can be reloaded with `rend3_gltf::swap_reloaded_scene`. The
+//! buffers and images the scene loads from other files are watched as
+//! dependencies, so changing them reloads the scene too:
+//!
+//! ```ignore
+//! let scene = Arc::new(Mutex::new(loaded));
+//! let reloaded = Arc::clone(&scene);
+//! hot_reloader.watch(path.clone(), move |renderer, data| {
+//!     let new = pollster::block_on(rend3_gltf::load_gltf(renderer, data, &settings, io_func))?;
+//!     rend3_gltf::swap_reloaded_scene(renderer, &mut reloaded.lock(), new)?;
+//!     Ok(())
+//! });
+//! for uri in rend3_gltf::external_uris(&std::fs::read(&path)?)? {
+//!     hot_reloader.watch_dependency(&path, parent_directory.join(&*uri));
+//! }
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rend3::{
+    types::{Texture, Texture2DHandle},
+    util::typedefs::FastHashMap,
+    Renderer,
+};
+
+/// Finds files whose modification time changed by polling it.
+#[derive(Debug, Default)]
+pub struct FileWatcher {
+    files: FastHashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching a file. Missing files are reported once they appear.
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let modified = modified(&path);
+        self.files.insert(path, modified);
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Returns the watched files that changed since the last poll.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        profiling::scope!("FileWatcher::poll");
+
+        let mut changed = Vec::new();
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            // Editors that save by replacing the file make it disappear for a moment.
+            if modified.is_some() && modified != *last_modified {
+                changed.push(path.clone());
+            }
+            *last_modified = modified;
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+type ReloadFn = dyn FnMut(&Arc<Renderer>, &[u8]) -> anyhow::Result<()>;
+
+/// A reload function of a file that failed.
+#[derive(Debug)]
+pub struct ReloadError {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// What [`HotReloader::poll`] reloaded.
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Number of reload functions that succeeded.
+    pub reloaded: usize,
+    pub errors: Vec<ReloadError>,
+}
+
+/// Calls reload functions with the contents of watched files when they
+/// change.
+///
+/// See the [module](self) for documentation.
+pub struct HotReloader {
+    watcher: FileWatcher,
+    reloads: FastHashMap<PathBuf, Vec<Box<ReloadFn>>>,
+    /// The watched files reloaded when each dependency changes.
+    dependents: FastHashMap<PathBuf, Vec<PathBuf>>,
+    interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl HotReloader {
+    /// Creates a reloader checking the files at most once per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            watcher: FileWatcher::new(),
+            reloads: FastHashMap::default(),
+            dependents: FastHashMap::default(),
+            interval,
+            last_poll: None,
+        }
+    }
+
+    /// Calls `reload` with the contents of the file whenever it changes.
+    /// Errors are logged and the old data stays in use.
+    pub fn watch(
+        &mut self,
+        path: impl Into<PathBuf>,
+        reload: impl FnMut(&Arc<Renderer>, &[u8]) -> anyhow::Result<()> + 'static,
+    ) {
+        let path = path.into();
+        self.watcher.watch(path.clone());
+        self.reloads.entry(path).or_default().push(Box::new(reload));
+    }
+
+    /// Also reloads the watched file at `path` when `dependency` changes, like
+    /// the external buffers and images of a glTF file. The reload functions
+    /// of `path` get the contents of `path`, not of the dependency.
+    pub fn watch_dependency(&mut self, path: impl Into<PathBuf>, dependency: impl Into<PathBuf>) {
+        let dependency = dependency.into();
+        self.watcher.watch(dependency.clone());
+        self.dependents.entry(dependency).or_default().push(path.into());
+    }
+
+    /// Replaces a 2D texture with the file whenever it changes, decoding it
+    /// with `decode`. Keeps the texture alive until the file is unwatched.
+    pub fn watch_texture_2d(
+        &mut self,
+        path: impl Into<PathBuf>,
+        handle: Texture2DHandle,
+        mut decode: impl FnMut(&[u8]) -> anyhow::Result<Texture> + 'static,
+    ) {
+        self.watch(path, move |renderer, data| {
+            renderer.replace_texture_2d(&handle, decode(data)?)?;
+            Ok(())
+        });
+    }
+
+    /// Stops watching a file, dropping its reload functions and its
+    /// dependencies.
+    pub fn unwatch(&mut self, path: &Path) {
+        self.watcher.unwatch(path);
+        self.reloads.remove(path);
+        self.dependents.retain(|dependency, dependents| {
+            dependents.retain(|dependent| dependent != path);
+            if dependents.is_empty() && !self.reloads.contains_key(dependency) {
+                self.watcher.unwatch(dependency);
+            }
+            !dependents.is_empty()
+        });
+    }
+
+    /// Reloads the files that changed, or whose dependencies changed, if
+    /// `interval` passed since the last check. Call it once per frame.
+    ///
+    /// Failed reloads are logged and returned, and the old data stays in use.
+    pub fn poll(&mut self, renderer: &Arc<Renderer>) -> ReloadReport {
+        profiling::scope!("HotReloader::poll");
+
+        let mut report = ReloadReport::default();
+        let now = Instant::now();
+        if self.last_poll.is_some_and(|last| now - last < self.interval) {
+            return report;
+        }
+        self.last_poll = Some(now);
+
+        let mut changed = Vec::new();
+        for path in self.watcher.poll() {
+            let dependents = self.dependents.get(&path).into_iter().flatten();
+            for path in self.reloads.get_key_value(&path).map(|(path, _)| path).into_iter().chain(dependents) {
+                if !changed.contains(path) {
+                    changed.push(path.clone());
+                }
+            }
+        }
+
+        for path in changed {
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::error!("Failed to read {} for hot-reloading: {e}", path.display());
+                    report.errors.push(ReloadError { path, error: e.into() });
+                    continue;
+                }
+            };
+            let mut failed = false;
+            for reload in self.reloads.get_mut(&path).into_iter().flatten() {
+                match reload(renderer, &data) {
+                    Ok(()) => report.reloaded += 1,
+                    Err(error) => {
+                        log::error!("Failed to hot-reload {}: {error:#}", path.display());
+                        report.errors.push(ReloadError { path: path.clone(), error });
+                        failed = true;
+                    }
+                }
+            }
+            if !failed {
+                log::info!("Hot-reloaded {}", path.display());
+            }
+        }
+        report
+    }
+}
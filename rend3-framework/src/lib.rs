@@ -5,7 +5,7 @@ use std::{future::Future, pin::Pin, sync::Arc};
 use glam::UVec2;
 use rend3::{
     types::{Handedness, SampleCount, Surface, TextureFormat},
-    InstanceAdapterDevice, Renderer, ShaderPreProcessor,
+    DeviceLostInfo, InstanceAdapterDevice, Renderer, RestoredResources, ShaderPreProcessor,
 };
 use rend3_routine::base::BaseRenderGraph;
 use wgpu::{Instance, PresentMode, SurfaceError};
@@ -17,12 +17,20 @@ use winit::{
 };
 
 mod assets;
+mod camera;
 mod grab;
+#[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+pub mod hot_reload;
 
 pub use assets::*;
+pub use camera::*;
 pub use grab::*;
 pub use parking_lot::{Mutex, MutexGuard};
 
+/// Frame time given to [`RedrawContext::delta_t_seconds`] when the renderer's
+/// determinism mode is enabled.
+pub const DETERMINISTIC_DELTA_T_SECONDS: f32 = 1.0 / 60.0;
+
 pub struct WindowingSetup<'a, T: 'static = ()> {
     pub event_loop: &'a EventLoop<T>,
     pub window: &'a Window,
@@ -46,6 +54,9 @@ pub struct EventContext<'a, T: 'static = ()> {
     pub routines: &'a Arc<DefaultRoutines>,
     pub base_rendergraph: &'a BaseRenderGraph,
     pub resolution: UVec2,
+    /// The window is minimized, so nothing is rendered, see
+    /// [`App::handle_minimize`].
+    pub minimized: bool,
     pub control_flow: &'a mut dyn FnMut(winit::event_loop::ControlFlow),
     pub event_loop_window_target: &'a EventLoopWindowTarget<T>,
 }
@@ -59,9 +70,26 @@ pub struct RedrawContext<'a, T: 'static = ()> {
     pub resolution: UVec2,
     pub control_flow: &'a mut dyn FnMut(winit::event_loop::ControlFlow),
     pub event_loop_window_target: Option<&'a EventLoopWindowTarget<T>>,
+    /// Time since the last frame, or [`DETERMINISTIC_DELTA_T_SECONDS`] in
+    /// determinism mode.
     pub delta_t_seconds: f32,
 }
 
+/// Context passed to [`App::handle_device_lost`].
+pub struct DeviceLostContext<'a> {
+    /// Why the device was lost.
+    pub info: DeviceLostInfo,
+    /// The renderer of the lost device. It can't render anymore.
+    pub old_renderer: &'a Arc<Renderer>,
+    pub renderer: &'a Arc<Renderer>,
+    pub routines: &'a Arc<DefaultRoutines>,
+    pub base_rendergraph: &'a BaseRenderGraph,
+    /// The handles in `renderer` of the resources retained by `old_renderer`.
+    pub restored: &'a RestoredResources,
+    pub surface_format: rend3::types::TextureFormat,
+    pub resolution: UVec2,
+}
+
 pub trait App<T: 'static = ()> {
     /// The handedness of the coordinate system of the renderer.
     const HANDEDNESS: Handedness;
@@ -157,6 +185,45 @@ pub trait App<T: 'static = ()> {
     fn handle_redraw_done(&mut self, window: &Window) {
         window.request_redraw(); // just queue a redraw.
     }
+
+    /// Called when the application is suspended and the surface has been
+    /// destroyed. No redraws will happen until [`App::handle_resume`] is
+    /// called. Use this to pause any of your own work.
+    fn handle_suspend(&mut self, renderer: &Arc<Renderer>) {
+        let _ = renderer;
+    }
+
+    /// Called when the application is resumed and the surface has been
+    /// recreated. Rendering starts again after this returns.
+    fn handle_resume(&mut self, renderer: &Arc<Renderer>) {
+        let _ = renderer;
+    }
+
+    /// Called when the window is minimized, or otherwise shrinks to zero size.
+    /// No redraws will happen until [`App::handle_restore`] is called, but
+    /// the surface and the renderer's resources are kept.
+    fn handle_minimize(&mut self, renderer: &Arc<Renderer>) {
+        let _ = renderer;
+    }
+
+    /// Called when the window is restored after being minimized. Rendering
+    /// starts again after this returns.
+    fn handle_restore(&mut self, renderer: &Arc<Renderer>) {
+        let _ = renderer;
+    }
+
+    /// Called when the device was lost and the framework replaced it with a
+    /// new device, renderer, routines and surface. Rendering starts again
+    /// after this returns.
+    ///
+    /// Resources added to the old renderer with the `_retained` functions are
+    /// already restored into the new one, see [`DeviceLostContext::restored`].
+    /// Everything else, like objects, lights and the camera, has to be added
+    /// again. On the web, a new device can't be made, so the application
+    /// exits instead.
+    fn handle_device_lost(&mut self, context: DeviceLostContext<'_>) {
+        let _ = context;
+    }
 }
 
 pub fn lock<T>(lock: &parking_lot::Mutex<T>) -> parking_lot::MutexGuard<'_, T> {
@@ -183,7 +250,7 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
     let window = Arc::new(window);
     let window_size = window.inner_size();
 
-    let iad = app.create_iad().await.unwrap();
+    let mut iad = app.create_iad().await.unwrap();
 
     // The one line of unsafe needed. We just need to guarentee that the window
     // outlives the use of the surface.
@@ -196,51 +263,8 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
         Some(Arc::new(iad.instance.create_surface(window.clone()).unwrap()))
     };
 
-    // Make us a renderer.
-    let renderer =
-        rend3::Renderer::new(iad.clone(), A::HANDEDNESS, Some(window_size.width as f32 / window_size.height as f32))
-            .unwrap();
-
-    // Get the preferred format for the surface.
-    //
-    // Assume android supports Rgba8Srgb, as it has 100% device coverage
-    let format = surface.as_ref().map_or(TextureFormat::Rgba8UnormSrgb, |s| {
-        let caps = s.get_capabilities(&iad.adapter);
-        let format = caps.formats[0];
-
-        // Configure the surface to be ready for rendering.
-        rend3::configure_surface(
-            s,
-            &iad.device,
-            format,
-            glam::UVec2::new(window_size.width, window_size.height),
-            rend3::types::PresentMode::Fifo,
-        );
-
-        format
-    });
-
-    let mut spp = rend3::ShaderPreProcessor::new();
-    rend3_routine::builtin_shaders(&mut spp);
-
-    let base_rendergraph = app.create_base_rendergraph(&renderer, &spp);
-    let mut data_core = renderer.data_core.lock();
-    let routines = Arc::new(DefaultRoutines {
-        pbr: Mutex::new(rend3_routine::pbr::PbrRoutine::new(
-            &renderer,
-            &mut data_core,
-            &spp,
-            &base_rendergraph.interfaces,
-        )),
-        skybox: Mutex::new(rend3_routine::skybox::SkyboxRoutine::new(&renderer, &spp, &base_rendergraph.interfaces)),
-        tonemapping: Mutex::new(rend3_routine::tonemapping::TonemappingRoutine::new(
-            &renderer,
-            &spp,
-            &base_rendergraph.interfaces,
-            format,
-        )),
-    });
-    drop(data_core);
+    let (mut renderer, mut format, mut base_rendergraph, mut routines) =
+        create_renderer(&mut app, &iad, surface.as_ref(), UVec2::new(window_size.width, window_size.height));
 
     app.setup(SetupContext {
         windowing: Some(WindowingSetup { event_loop: &event_loop, window: &window }),
@@ -262,6 +286,7 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
         sample_count: app.sample_count(),
         present_mode: app.present_mode(),
         requires_reconfigure: true,
+        minimized: window_size.width == 0 || window_size.height == 0,
     };
 
     cfg_if::cfg_if! {
@@ -281,19 +306,39 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
         event_loop,
         move |event: Event<T>, event_loop_window_target: &EventLoopWindowTarget<T>| {
             let mut control_flow = event_loop_window_target.control_flow();
+            let was_minimized = stored_surface_info.minimized;
             if let Some(suspend) =
                 handle_surface(&app, &window, &event, &iad.instance, &mut surface, &renderer, &mut stored_surface_info)
             {
                 suspended = suspend;
             }
 
+            // Like when suspended, we wait while minimized, as there is nothing to draw.
+            match (was_minimized, stored_surface_info.minimized) {
+                (false, true) => {
+                    control_flow = ControlFlow::Wait;
+                    app.handle_minimize(&renderer);
+                }
+                (true, false) => {
+                    control_flow = last_user_control_mode;
+                    app.handle_restore(&renderer);
+                    // Redraws stop while minimized, so start them again.
+                    window.request_redraw();
+                }
+                _ => {}
+            }
+
             // We move to Wait when we get suspended so we don't spin at 50k FPS.
             match event {
                 Event::Suspended => {
                     control_flow = ControlFlow::Wait;
+                    app.handle_suspend(&renderer);
                 }
                 Event::Resumed => {
                     control_flow = last_user_control_mode;
+                    app.handle_resume(&renderer);
+                    // Kick off rendering again, as any redraw requested while suspended was dropped.
+                    window.request_redraw();
                 }
                 _ => {}
             }
@@ -306,7 +351,41 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
 
             // We need to block all updates
             if let Event::WindowEvent { window_id: _, event: winit::event::WindowEvent::RedrawRequested } = event {
-                if suspended {
+                if suspended || stored_surface_info.minimized {
+                    return;
+                }
+
+                if let Some(info) = renderer.device_lost() {
+                    log::warn!("Device lost ({:?}): {}", info.reason, info.message);
+                    let Some(new_iad) = recreate_iad(&mut app) else {
+                        event_loop_window_target.exit();
+                        return;
+                    };
+
+                    // The surface was made by the instance of the lost device.
+                    if surface.take().is_some() {
+                        surface = Some(Arc::new(new_iad.instance.create_surface(window.clone()).unwrap()));
+                    }
+                    iad = new_iad;
+
+                    let new_renderer;
+                    (new_renderer, format, base_rendergraph, routines) =
+                        create_renderer(&mut app, &iad, surface.as_ref(), stored_surface_info.size);
+                    let old_renderer = std::mem::replace(&mut renderer, new_renderer);
+                    let restored = old_renderer.restore_retained_into(&renderer);
+                    stored_surface_info.requires_reconfigure = true;
+
+                    app.handle_device_lost(DeviceLostContext {
+                        info,
+                        old_renderer: &old_renderer,
+                        renderer: &renderer,
+                        routines: &routines,
+                        base_rendergraph: &base_rendergraph,
+                        restored: &restored,
+                        surface_format: format,
+                        resolution: stored_surface_info.size,
+                    });
+                    window.request_redraw();
                     return;
                 }
 
@@ -334,12 +413,23 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
                     Err(SurfaceError::Timeout) => {
                         return;
                     }
-                    Err(SurfaceError::OutOfMemory | SurfaceError::Lost) => panic!("Surface OOM"),
+                    Err(SurfaceError::Lost) => {
+                        // The swapchain is gone, reconfiguring recreates it.
+                        log::warn!("Surface lost, reconfiguring");
+                        stored_surface_info.requires_reconfigure = true;
+                        window.request_redraw();
+                        return;
+                    }
+                    Err(SurfaceError::OutOfMemory) => panic!("Surface OOM"),
                 };
 
                 let current_time = web_time::Instant::now();
-                let delta_t_seconds = (current_time - previous_time).as_secs_f32();
+                let delta_t_seconds = match renderer.determinism_enabled() {
+                    true => DETERMINISTIC_DELTA_T_SECONDS,
+                    false => (current_time - previous_time).as_secs_f32(),
+                };
                 previous_time = current_time;
+                renderer.advance_time(delta_t_seconds);
 
                 app.handle_redraw(RedrawContext {
                     window: Some(&window),
@@ -367,6 +457,7 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
                         routines: &routines,
                         base_rendergraph: &base_rendergraph,
                         resolution: stored_surface_info.size,
+                        minimized: stored_surface_info.minimized,
                         control_flow: &mut |c: ControlFlow| {
                             control_flow = c;
                             last_user_control_mode = c;
@@ -380,12 +471,84 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
     );
 }
 
+/// Makes the renderer and the default routines on the device of `iad`,
+/// configuring `surface` for them.
+fn create_renderer<A: App<T>, T: 'static>(
+    app: &mut A,
+    iad: &InstanceAdapterDevice,
+    surface: Option<&Arc<Surface>>,
+    size: UVec2,
+) -> (Arc<Renderer>, TextureFormat, BaseRenderGraph, Arc<DefaultRoutines>) {
+    let renderer = rend3::Renderer::new(iad.clone(), A::HANDEDNESS, Some(size.x as f32 / size.y as f32)).unwrap();
+    renderer.install_device_lost_callbacks();
+
+    // Get the preferred format for the surface.
+    //
+    // Assume android supports Rgba8Srgb, as it has 100% device coverage
+    let format = surface.map_or(TextureFormat::Rgba8UnormSrgb, |s| {
+        let caps = s.get_capabilities(&iad.adapter);
+        let format = caps.formats[0];
+
+        // Configure the surface to be ready for rendering.
+        rend3::configure_surface(s, &iad.device, format, size, rend3::types::PresentMode::Fifo);
+
+        format
+    });
+
+    let mut spp = rend3::ShaderPreProcessor::new();
+    rend3_routine::builtin_shaders(&mut spp);
+
+    let base_rendergraph = app.create_base_rendergraph(&renderer, &spp);
+    let mut data_core = renderer.data_core.lock();
+    let routines = Arc::new(DefaultRoutines {
+        pbr: Mutex::new(rend3_routine::pbr::PbrRoutine::new(
+            &renderer,
+            &mut data_core,
+            &spp,
+            &base_rendergraph.interfaces,
+        )),
+        skybox: Mutex::new(rend3_routine::skybox::SkyboxRoutine::new(&renderer, &spp, &base_rendergraph.interfaces)),
+        tonemapping: Mutex::new(rend3_routine::tonemapping::TonemappingRoutine::new(
+            &renderer,
+            &spp,
+            &base_rendergraph.interfaces,
+            format,
+        )),
+    });
+    drop(data_core);
+
+    (renderer, format, base_rendergraph, routines)
+}
+
+/// Makes a new device after the last one was lost.
+#[cfg(not(target_arch = "wasm32"))]
+fn recreate_iad<A: App<T>, T: 'static>(app: &mut A) -> Option<InstanceAdapterDevice> {
+    match pollster::block_on(app.create_iad()) {
+        Ok(iad) => Some(iad),
+        Err(e) => {
+            log::error!("Failed to recreate the device after device loss: {e}");
+            None
+        }
+    }
+}
+
+/// Making a device is asynchronous on the web, and the event loop can't
+/// block on it, so the web can't recover from device loss.
+#[cfg(target_arch = "wasm32")]
+fn recreate_iad<A: App<T>, T: 'static>(app: &mut A) -> Option<InstanceAdapterDevice> {
+    let _ = app;
+    log::error!("Device lost, and recreating the device is not supported on the web");
+    None
+}
+
 struct StoredSurfaceInfo {
     size: UVec2,
     scale_factor: f32,
     sample_count: SampleCount,
     present_mode: PresentMode,
     requires_reconfigure: bool,
+    /// The window has zero size, so the surface can't be configured.
+    minimized: bool,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -403,9 +566,22 @@ fn handle_surface<A: App<T>, T: 'static>(
             if surface.is_none() {
                 *surface = Some(Arc::new(instance.create_surface(window.clone()).unwrap()));
             }
+
+            // A new surface has never been configured, and the window may have changed size while we were suspended.
+            let size = window.inner_size();
+            surface_info.minimized = size.width == 0 || size.height == 0;
+            if !surface_info.minimized {
+                surface_info.size = UVec2::new(size.width, size.height);
+                renderer.set_aspect_ratio(size.width as f32 / size.height as f32);
+            }
+            surface_info.scale_factor = app.scale_factor();
+            surface_info.sample_count = app.sample_count();
+            surface_info.present_mode = app.present_mode();
+            surface_info.requires_reconfigure = true;
             Some(false)
         }
         Event::Suspended => {
+            // The native window is about to be destroyed, so the surface must go with it.
             *surface = None;
             Some(true)
         }
@@ -413,7 +589,9 @@ fn handle_surface<A: App<T>, T: 'static>(
             log::debug!("resize {:?}", size);
             let size = UVec2::new(size.width, size.height);
 
-            if size.x == 0 || size.y == 0 {
+            // Windows resizes minimized windows to zero. The last size is kept for when the window is restored.
+            surface_info.minimized = size.x == 0 || size.y == 0;
+            if surface_info.minimized {
                 return Some(false);
             }
 
@@ -14,11 +14,17 @@
 //! - `KHR_punctual_lights`
 //! - `KHR_texture_transform`
 //! - `KHR_material_unlit`
+//! - `KHR_materials_emissive_strength`
+//! - `KHR_materials_pbrSpecularGlossiness`, converted to metallic-roughness
+//!   at load time.
 //!
 //! # Known Limitations
 //! - Only the albedo texture's transform from `KHR_texture_transform` will be
 //!   used.
 //! - Double sided materials are currently unsupported.
+//! - Specular-glossiness textures can only be converted if they can be
+//!   decoded to 8-bit pixels. Block compressed textures fall back to a
+//!   conversion of the material's factors.
 
 use std::{
     borrow::Cow,
@@ -289,6 +295,9 @@ pub struct GltfLoadSettings {
     pub scale: f32,
     /// Size of the shadow map in world space (default: 100)
     pub directional_light_shadow_distance: f32,
+    /// Fraction of the shadow distance over which shadows fade out (default:
+    /// 0.1)
+    pub directional_light_shadow_fade: f32,
     /// Resolution of the shadow map (default: 2048)
     pub directional_light_resolution: u16,
     /// Coordinate space normal maps should use (default Up)
@@ -302,6 +311,7 @@ impl Default for GltfLoadSettings {
         Self {
             scale: 1.0,
             directional_light_shadow_distance: 100.0,
+            directional_light_shadow_fade: 0.1,
             directional_light_resolution: 2048,
             normal_direction: pbr::NormalTextureYDirection::Up,
             enable_directional: true,
@@ -406,6 +416,108 @@ where
     Ok(loaded)
 }
 
+/// Describes why a reloaded gltf scene couldn't replace the loaded one.
+#[derive(Debug, Error)]
+pub enum GltfReloadError {
+    #[error("Reloaded scene has {reloaded} meshes, but the loaded scene has {loaded}")]
+    MeshCountMismatch { loaded: usize, reloaded: usize },
+    #[error("Reloaded mesh {mesh} has {reloaded} primitives, but the loaded mesh has {loaded}")]
+    PrimitiveCountMismatch { mesh: usize, loaded: usize, reloaded: usize },
+    #[error("Reloaded scene has {reloaded} materials, but the loaded scene has {loaded}")]
+    MaterialCountMismatch { loaded: usize, reloaded: usize },
+    #[error("Reloaded scene has {reloaded} skins, but the loaded scene has {loaded}")]
+    SkinCountMismatch { loaded: usize, reloaded: usize },
+    #[error("Reloaded skin {skin} has {reloaded} joints, but the loaded skin has {loaded}")]
+    JointCountMismatch { skin: usize, loaded: usize, reloaded: usize },
+}
+
+/// The URIs of the files a gltf loads its buffers and images from, relative
+/// to the gltf. Data URIs are left out.
+///
+/// Changing these files changes the scene, so they are watched alongside
+/// the gltf when hot-reloading it with [`swap_reloaded_scene`].
+pub fn external_uris(data: &[u8]) -> Result<Vec<SsoString>, gltf::Error> {
+    let file = gltf::Gltf::from_slice_without_validation(data)?;
+
+    let buffers = file.buffers().filter_map(|buffer| match buffer.source() {
+        Source::Uri(uri) => Some(uri),
+        Source::Bin => None,
+    });
+    let images = file.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+
+    let mut uris: Vec<SsoString> = Vec::new();
+    for uri in buffers.chain(images).filter(|uri| !uri.starts_with("data:")) {
+        if !uris.iter().any(|known| **known == *uri) {
+            uris.push(SsoString::from(uri));
+        }
+    }
+    Ok(uris)
+}
+
+/// Puts the meshes and materials of a new load of the same gltf file behind
+/// the handles of `loaded`, so objects added from it show the new data
+/// without being recreated. The images, skins and animations of `loaded` are
+/// replaced by the new ones.
+///
+/// The scenes must have the same meshes, primitives and materials, and skins
+/// must keep their joint count. The old data is freed when `reloaded` drops.
+pub fn swap_reloaded_scene(
+    renderer: &Renderer,
+    loaded: &mut LoadedGltfScene,
+    reloaded: LoadedGltfScene,
+) -> Result<(), GltfReloadError> {
+    profiling::scope!("swap reloaded gltf scene");
+
+    if loaded.meshes.len() != reloaded.meshes.len() {
+        return Err(GltfReloadError::MeshCountMismatch {
+            loaded: loaded.meshes.len(),
+            reloaded: reloaded.meshes.len(),
+        });
+    }
+    for (mesh, (old, new)) in loaded.meshes.iter().zip(&reloaded.meshes).enumerate() {
+        let (loaded, reloaded) = (old.inner.primitives.len(), new.inner.primitives.len());
+        if loaded != reloaded {
+            return Err(GltfReloadError::PrimitiveCountMismatch { mesh, loaded, reloaded });
+        }
+    }
+    if loaded.materials.len() != reloaded.materials.len() {
+        return Err(GltfReloadError::MaterialCountMismatch {
+            loaded: loaded.materials.len(),
+            reloaded: reloaded.materials.len(),
+        });
+    }
+    // Skeletons added from the loaded skins keep their joint count.
+    if loaded.skins.len() != reloaded.skins.len() {
+        return Err(GltfReloadError::SkinCountMismatch { loaded: loaded.skins.len(), reloaded: reloaded.skins.len() });
+    }
+    for (skin, (old, new)) in loaded.skins.iter().zip(&reloaded.skins).enumerate() {
+        let (loaded, reloaded) = (old.inner.joints.len(), new.inner.joints.len());
+        if loaded != reloaded {
+            return Err(GltfReloadError::JointCountMismatch { skin, loaded, reloaded });
+        }
+    }
+
+    for (old, new) in loaded.meshes.iter().zip(&reloaded.meshes) {
+        for (old, new) in old.inner.primitives.iter().zip(&new.inner.primitives) {
+            renderer.swap_meshes(&old.handle, &new.handle);
+        }
+    }
+    for (old, new) in loaded.materials.iter().zip(&reloaded.materials) {
+        renderer.swap_materials(&old.inner, &new.inner);
+    }
+    renderer.swap_materials(&loaded.default_material, &reloaded.default_material);
+
+    // The materials now refer to the new images.
+    loaded.images = reloaded.images;
+    loaded.skins = reloaded.skins;
+    loaded.animations = reloaded.animations;
+
+    Ok(())
+}
+
 /// Adds a single mesh from the [`LoadedGltfScene`] found by its index,
 /// as an object to the scene.
 pub fn add_mesh_by_index<E: std::error::Error + 'static>(
@@ -447,7 +559,11 @@ pub fn add_mesh_by_index<E: std::error::Error + 'static>(
             ObjectMeshKind::Static(prim.handle.clone())
         };
 
-        primitives.push(renderer.add_object(types::Object { mesh_kind, material: mat.clone(), transform }));
+        let object = renderer.add_object(types::Object { mesh_kind, material: mat.clone(), transform });
+        if name.is_some() {
+            renderer.set_object_label(&object, name);
+        }
+        primitives.push(object);
     }
 
     Ok(Labeled::new(
@@ -536,6 +652,7 @@ pub fn instance_loaded_scene<E: std::error::Error + 'static>(
                         intensity: light.intensity(),
                         direction,
                         distance: settings.directional_light_shadow_distance,
+                        shadow_fade: settings.directional_light_shadow_fade,
                         resolution: settings.directional_light_resolution,
                     }))
                 }
@@ -629,6 +746,9 @@ pub fn load_meshes<'a, E: std::error::Error + 'static>(
 
                 // glTF models are right handed, so we must flip their winding order
                 let mut builder = types::MeshBuilder::new(vertex_positions, renderer.handedness);
+                if let Some(name) = mesh.name() {
+                    builder = builder.with_label(name);
+                }
                 if renderer.handedness == Handedness::Left {
                     builder = builder.with_flip_winding_order();
                 }
@@ -779,6 +899,7 @@ pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle
     renderer.add_material(pbr::PbrMaterial {
         albedo: pbr::AlbedoComponent::Value(Vec4::splat(1.0)),
         transparency: pbr::Transparency::Opaque,
+        blend_mode: pbr::BlendMode::Alpha,
         normal: pbr::NormalTexture::None,
         aomr_textures: pbr::AoMRTextures::None,
         ao_factor: Some(1.0),
@@ -788,12 +909,17 @@ pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle
         clearcoat_factor: Some(1.0),
         clearcoat_roughness_factor: Some(1.0),
         emissive: pbr::MaterialComponent::None,
+        emissive_intensity: Some(1.0),
+        emissive_bloom: false,
         reflectance: pbr::MaterialComponent::None,
         anisotropy: pbr::MaterialComponent::None,
         uv_transform0: Mat3::IDENTITY,
         uv_transform1: Mat3::IDENTITY,
+        texture_transforms: pbr::PbrTextureTransforms::default(),
+        texture_uv_sets: pbr::PbrTextureUvSets::default(),
         unlit: false,
         sample_type: pbr::SampleType::Linear,
+        alpha_to_coverage: false,
     })
 }
 
@@ -823,14 +949,18 @@ where
         // profiling::scope!("load material", material.name().unwrap_or_default());
 
         let pbr = material.pbr_metallic_roughness();
-        let albedo = pbr.base_color_texture();
-        let albedo_factor = pbr.base_color_factor();
+        let spec_gloss = material.pbr_specular_glossiness();
+        let albedo = match spec_gloss {
+            Some(ref sg) => sg.diffuse_texture(),
+            None => pbr.base_color_texture(),
+        };
+        let mut albedo_factor = pbr.base_color_factor();
         let occlusion = material.occlusion_texture();
         let emissive = material.emissive_texture();
         let emissive_factor = material.emissive_factor();
         let normals = material.normal_texture();
-        let roughness_factor = pbr.roughness_factor();
-        let metallic_factor = pbr.metallic_factor();
+        let mut roughness_factor = pbr.roughness_factor();
+        let mut metallic_factor = pbr.metallic_factor();
         let metallic_roughness = pbr.metallic_roughness_texture();
 
         let nearest = albedo
@@ -854,10 +984,33 @@ where
             })
             .unwrap_or(Mat3::IDENTITY);
 
-        let albedo_tex = util::texture_option_resolve(
-            albedo.map(|i| load_image_cached(renderer, &mut images, i.texture().source(), true, buffers, io_func)),
-        )
-        .await?;
+        // Metallic roughness and occlusion share the roughness slot when combined.
+        let texture_uv_sets = pbr::PbrTextureUvSets {
+            albedo: util::uv_set(albedo.as_ref().map(|i| i.tex_coord())),
+            normal: util::uv_set(normals.as_ref().map(|i| i.tex_coord())),
+            roughness: util::uv_set(metallic_roughness.as_ref().map(|i| i.tex_coord())),
+            emissive: util::uv_set(emissive.as_ref().map(|i| i.tex_coord())),
+            ambient_occlusion: util::uv_set(occlusion.as_ref().map(|i| i.tex_coord())),
+            ..pbr::PbrTextureUvSets::default()
+        };
+
+        let (albedo_tex, converted_mr_tex) = match spec_gloss {
+            Some(sg) => {
+                let converted = convert_specular_glossiness(renderer, &mut images, &sg, buffers, io_func).await?;
+                albedo_factor = converted.albedo_factor.into();
+                metallic_factor = converted.metallic_factor;
+                roughness_factor = converted.roughness_factor;
+                (converted.albedo, converted.metallic_roughness)
+            }
+            None => {
+                let albedo_tex =
+                    util::texture_option_resolve(albedo.map(|i| {
+                        load_image_cached(renderer, &mut images, i.texture().source(), true, buffers, io_func)
+                    }))
+                    .await?;
+                (albedo_tex, None)
+            }
+        };
         let occlusion_tex = util::texture_option_resolve(
             occlusion.map(|i| load_image_cached(renderer, &mut images, i.texture().source(), false, buffers, io_func)),
         )
@@ -870,11 +1023,16 @@ where
             normals.map(|i| load_image_cached(renderer, &mut images, i.texture().source(), false, buffers, io_func)),
         )
         .await?;
-        let metallic_roughness_tex = util::texture_option_resolve(
-            metallic_roughness
-                .map(|i| load_image_cached(renderer, &mut images, i.texture().source(), false, buffers, io_func)),
-        )
-        .await?;
+        let metallic_roughness_tex =
+            match converted_mr_tex {
+                Some(tex) => Some(tex),
+                None => {
+                    util::texture_option_resolve(metallic_roughness.map(|i| {
+                        load_image_cached(renderer, &mut images, i.texture().source(), false, buffers, io_func)
+                    }))
+                    .await?
+                }
+            };
 
         let handle = renderer.add_material(pbr::PbrMaterial {
             albedo: match albedo_tex {
@@ -892,6 +1050,7 @@ where
                 }
                 gltf::material::AlphaMode::Blend => pbr::Transparency::Blend,
             },
+            blend_mode: pbr::BlendMode::Alpha,
             normal: match normals_tex {
                 Some(tex) if util::format_components(tex.format) == Some(2) => {
                     pbr::NormalTexture::Bicomponent(tex.handle, settings.normal_direction)
@@ -927,13 +1086,18 @@ where
                 }
                 None => pbr::MaterialComponent::Value(Vec3::from(emissive_factor)),
             },
+            emissive_intensity: material.emissive_strength(),
             uv_transform0: uv_transform,
             uv_transform1: uv_transform,
+            texture_uv_sets,
             unlit: material.unlit(),
             sample_type: nearest,
             ..pbr::PbrMaterial::default()
         });
 
+        if let Some(name) = material.name() {
+            renderer.set_material_label(&handle, Some(name));
+        }
         result.push(Labeled::new(handle, material.name()));
     }
 
@@ -988,6 +1152,29 @@ pub async fn load_image<F, Fut, E>(
     buffers: &[Vec<u8>],
     io_func: &mut F,
 ) -> Result<Labeled<Texture>, GltfLoadError<E>>
+where
+    F: FnMut(SsoString) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, E>>,
+    E: std::error::Error + 'static,
+{
+    let name = image.name();
+    let texture = decode_image(image, srgb, buffers, io_func).await?;
+    let format = texture.format;
+    let handle = renderer.add_texture_2d(texture)?;
+
+    Ok(Labeled::new(Texture { handle, format }, name))
+}
+
+/// Decodes a single image from a [`gltf::Image`] without adding it to the
+/// renderer.
+///
+/// io_func determines how URIs are resolved into their underlying data.
+pub async fn decode_image<F, Fut, E>(
+    image: gltf::Image<'_>,
+    srgb: bool,
+    buffers: &[Vec<u8>],
+    io_func: &mut F,
+) -> Result<types::Texture, GltfLoadError<E>>
 where
     F: FnMut(SsoString) -> Fut,
     Fut: Future<Output = Result<Vec<u8>, E>>,
@@ -1120,22 +1307,148 @@ where
         })
     };
 
-    let texture = texture.unwrap();
-    let format = texture.format;
-    let handle = renderer.add_texture_2d(texture)?;
+    Ok(texture.unwrap())
+}
+
+/// A specular-glossiness material converted to metallic-roughness.
+struct ConvertedSpecularGlossiness {
+    albedo: Option<Texture>,
+    metallic_roughness: Option<Texture>,
+    albedo_factor: Vec4,
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+/// Converts a `KHR_materials_pbrSpecularGlossiness` material.
+///
+/// When the textures can be decoded to 8-bit pixels, the conversion is done
+/// per texel and baked into new albedo and metallic-roughness textures with
+/// the factors folded in. Otherwise only the factors are converted and the
+/// diffuse texture is used as the albedo.
+async fn convert_specular_glossiness<F, Fut, E>(
+    renderer: &Arc<Renderer>,
+    images: &mut ImageMap,
+    sg: &gltf::material::PbrSpecularGlossiness<'_>,
+    buffers: &[Vec<u8>],
+    io_func: &mut F,
+) -> Result<ConvertedSpecularGlossiness, GltfLoadError<E>>
+where
+    F: FnMut(SsoString) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, E>>,
+    E: std::error::Error + 'static,
+{
+    let diffuse_factor = Vec4::from(sg.diffuse_factor());
+    let specular_factor = Vec3::from(sg.specular_factor());
+    let glossiness_factor = sg.glossiness_factor();
+
+    let diffuse = sg.diffuse_texture();
+    let specular_glossiness = sg.specular_glossiness_texture();
 
-    Ok(Labeled::new(Texture { handle, format }, image.name()))
+    if diffuse.is_some() || specular_glossiness.is_some() {
+        let diffuse_image = match diffuse {
+            Some(ref i) => Some(decode_image(i.texture().source(), true, buffers, io_func).await?),
+            None => None,
+        };
+        let sg_image = match specular_glossiness {
+            Some(ref i) => Some(decode_image(i.texture().source(), false, buffers, io_func).await?),
+            None => None,
+        };
+
+        let sampleable = |t: &Option<types::Texture>| t.as_ref().map_or(true, |t| util::texel_size(t.format).is_some());
+        if sampleable(&diffuse_image) && sampleable(&sg_image) {
+            profiling::scope!("converting specular-glossiness textures");
+
+            let size = diffuse_image.as_ref().or(sg_image.as_ref()).unwrap().size;
+            let mut albedo_data = Vec::with_capacity((size.x * size.y * 4) as usize);
+            let mut mr_data = Vec::with_capacity((size.x * size.y * 4) as usize);
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let coord = UVec2::new(x, y);
+                    let diffuse_texel = diffuse_image
+                        .as_ref()
+                        .map_or(Vec4::ONE, |t| util::srgb_to_linear(util::sample_nearest(t, coord, size)));
+                    let sg_texel = sg_image.as_ref().map_or(Vec4::ONE, |t| util::sample_nearest(t, coord, size));
+                    let specular = util::srgb_to_linear(sg_texel).truncate();
+
+                    let (albedo, metallic, roughness) = util::specular_glossiness_to_metallic_roughness(
+                        diffuse_texel * diffuse_factor,
+                        specular * specular_factor,
+                        sg_texel.w * glossiness_factor,
+                    );
+
+                    let albedo = util::linear_to_srgb(albedo);
+                    albedo_data.extend(albedo.to_array().map(util::unorm_to_u8));
+                    mr_data.extend([255, util::unorm_to_u8(roughness), util::unorm_to_u8(metallic), 255]);
+                }
+            }
+
+            let add = |data, format, label: &str| -> Result<Texture, GltfLoadError<E>> {
+                let handle = renderer.add_texture_2d(types::Texture {
+                    label: Some(label.to_owned()),
+                    format,
+                    size,
+                    data,
+                    mip_count: types::MipmapCount::Maximum,
+                    mip_source: types::MipmapSource::Generated,
+                })?;
+                Ok(Texture { handle, format })
+            };
+
+            return Ok(ConvertedSpecularGlossiness {
+                albedo: Some(add(albedo_data, types::TextureFormat::Rgba8UnormSrgb, "converted albedo")?),
+                metallic_roughness: Some(add(
+                    mr_data,
+                    types::TextureFormat::Rgba8Unorm,
+                    "converted metallic roughness",
+                )?),
+                albedo_factor: Vec4::ONE,
+                metallic_factor: 1.0,
+                roughness_factor: 1.0,
+            });
+        }
+
+        log::warn!(
+            "Specular-glossiness textures can't be converted as they are compressed, only converting the factors"
+        );
+    }
+
+    let (albedo, metallic_factor, roughness_factor) =
+        util::specular_glossiness_to_metallic_roughness(diffuse_factor, specular_factor, glossiness_factor);
+    let albedo_tex = util::texture_option_resolve(
+        diffuse.map(|i| load_image_cached(renderer, images, i.texture().source(), true, buffers, io_func)),
+    )
+    .await?;
+
+    Ok(ConvertedSpecularGlossiness {
+        albedo: albedo_tex,
+        metallic_roughness: None,
+        albedo_factor: albedo,
+        metallic_factor,
+        roughness_factor,
+    })
 }
 
 /// Implementation utilities.
 pub mod util {
     use std::future::Future;
 
+    use glam::{UVec2, Vec3, Vec4, Vec4Swizzles};
     use image::{buffer::ConvertBuffer, ImageBuffer, Luma, Rgba};
     use rend3::types;
+    use rend3_routine::pbr;
 
     use crate::{Labeled, Texture};
 
+    /// Turns the `TEXCOORD_n` set of a texture into the [`pbr::UvSet`] it is
+    /// sampled with. Sets past `TEXCOORD_1` aren't loaded, so fall back to
+    /// the first.
+    pub fn uv_set(tex_coord: Option<u32>) -> pbr::UvSet {
+        match tex_coord {
+            Some(1) => pbr::UvSet::Uv1,
+            _ => pbr::UvSet::Uv0,
+        }
+    }
+
     /// Turns an `Option<Texture>` into `Option<types::TextureHandle>`
     pub fn extract_handle(texture: Option<Texture>) -> Option<types::Texture2DHandle> {
         texture.map(|t| t.handle)
@@ -1160,6 +1473,108 @@ pub mod util {
         }
     }
 
+    /// Reflectance of dielectrics assumed by the metallic-roughness model.
+    const DIELECTRIC_SPECULAR: f32 = 0.04;
+
+    /// Converts specular-glossiness parameters to metallic-roughness, returning
+    /// the base color, metallic and roughness.
+    ///
+    /// All colors are linear. This is the conversion recommended by Khronos
+    /// for the `KHR_materials_pbrSpecularGlossiness` extension.
+    pub fn specular_glossiness_to_metallic_roughness(
+        diffuse: Vec4,
+        specular: Vec3,
+        glossiness: f32,
+    ) -> (Vec4, f32, f32) {
+        let one_minus_specular_strength = 1.0 - specular.max_element();
+        let metallic = solve_metallic(
+            perceived_brightness(diffuse.xyz()),
+            perceived_brightness(specular),
+            one_minus_specular_strength,
+        );
+
+        let from_diffuse = diffuse.xyz() * one_minus_specular_strength
+            / (1.0 - DIELECTRIC_SPECULAR)
+            / (1.0 - metallic).max(f32::EPSILON);
+        let from_specular =
+            (specular - Vec3::splat(DIELECTRIC_SPECULAR * (1.0 - metallic))) / metallic.max(f32::EPSILON);
+        let base_color = from_diffuse.lerp(from_specular, metallic * metallic).clamp(Vec3::ZERO, Vec3::ONE);
+
+        (base_color.extend(diffuse.w), metallic, 1.0 - glossiness.clamp(0.0, 1.0))
+    }
+
+    fn perceived_brightness(color: Vec3) -> f32 {
+        (0.299 * color.x * color.x + 0.587 * color.y * color.y + 0.114 * color.z * color.z).sqrt()
+    }
+
+    fn solve_metallic(diffuse: f32, specular: f32, one_minus_specular_strength: f32) -> f32 {
+        if specular < DIELECTRIC_SPECULAR {
+            return 0.0;
+        }
+
+        let a = DIELECTRIC_SPECULAR;
+        let b =
+            diffuse * one_minus_specular_strength / (1.0 - DIELECTRIC_SPECULAR) + specular - 2.0 * DIELECTRIC_SPECULAR;
+        let c = DIELECTRIC_SPECULAR - specular;
+        let d = (b * b - 4.0 * a * c).max(0.0);
+        ((-b + d.sqrt()) / (2.0 * a)).clamp(0.0, 1.0)
+    }
+
+    /// Bytes per texel of the uncompressed 8-bit formats that
+    /// [`sample_nearest`] can read, or None for every other format.
+    pub fn texel_size(format: types::TextureFormat) -> Option<usize> {
+        match format {
+            types::TextureFormat::R8Unorm => Some(1),
+            types::TextureFormat::Rgba8Unorm | types::TextureFormat::Rgba8UnormSrgb => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Reads the texel of the first mip of `texture` at `coord` when scaled up
+    /// or down to `size`, without any color space conversion.
+    ///
+    /// Single channel textures are read as grey with an alpha of one.
+    pub fn sample_nearest(texture: &types::Texture, coord: UVec2, size: UVec2) -> Vec4 {
+        let texel_size = texel_size(texture.format).expect("format can't be sampled");
+        let x = (coord.x as u64 * texture.size.x as u64 / size.x as u64) as usize;
+        let y = (coord.y as u64 * texture.size.y as u64 / size.y as u64) as usize;
+        let offset = (y * texture.size.x as usize + x) * texel_size;
+        let texel = &texture.data[offset..offset + texel_size];
+        match *texel {
+            [v] => Vec4::new(v as f32, v as f32, v as f32, 255.0) / 255.0,
+            [r, g, b, a] => Vec4::new(r as f32, g as f32, b as f32, a as f32) / 255.0,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Converts the color channels from sRGB to linear, leaving alpha alone.
+    pub fn srgb_to_linear(color: Vec4) -> Vec4 {
+        let convert = |c: f32| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        Vec4::new(convert(color.x), convert(color.y), convert(color.z), color.w)
+    }
+
+    /// Converts the color channels from linear to sRGB, leaving alpha alone.
+    pub fn linear_to_srgb(color: Vec4) -> Vec4 {
+        let convert = |c: f32| {
+            if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        };
+        Vec4::new(convert(color.x), convert(color.y), convert(color.z), color.w)
+    }
+
+    pub(crate) fn unorm_to_u8(value: f32) -> u8 {
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+
     pub fn convert_dynamic_image(image: image::DynamicImage, srgb: bool) -> (Vec<u8>, rend3::types::TextureFormat) {
         use rend3::types::TextureFormat as r3F;
 
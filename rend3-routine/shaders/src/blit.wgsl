@@ -28,4 +28,8 @@ fn fs_main_scene(vout: VertexOutput) -> @location(0) vec4<f32> {
 fn fs_main_monitor(vout: VertexOutput) -> @location(0) vec4<f32> {
     var sampled = textureSample(source, primary_sampler, vout.tex_coords);
     return vec4<f32>(srgb_scene_to_display(sampled.rgb), sampled.a);
-}
\ No newline at end of file
+}
+@fragment
+fn fs_main_mirror(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, primary_sampler, vec2<f32>(1.0 - vout.tex_coords.x, vout.tex_coords.y));
+}
@@ -0,0 +1,58 @@
+// Blurs the emission written by the opaque passes over a chain of targets of halving resolution, then adds it to the HDR
+// image.
+
+{{include "rend3-routine/post.wgsl"}}
+
+struct BloomUniforms {
+    intensity: f32,
+}
+
+@group(1) @binding(0)
+var src: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> bloom: BloomUniforms;
+
+// The sampler repeats, so keep the samples inside the edge texels.
+fn sample_src(uv: vec2<f32>, texel: vec2<f32>) -> vec3<f32> {
+    let clamped = clamp(uv, texel * 0.5, vec2<f32>(1.0) - texel * 0.5);
+    return textureSampleLevel(src, primary_sampler, clamped, 0.0).rgb;
+}
+
+// Averages the 4x4 texels of the larger target around the pixel, with 4 bilinear samples.
+@fragment
+fn fs_downsample(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(src));
+    let uv = vout.tex_coords;
+    var color = sample_src(uv + texel * vec2<f32>(-1.0, -1.0), texel);
+    color += sample_src(uv + texel * vec2<f32>(1.0, -1.0), texel);
+    color += sample_src(uv + texel * vec2<f32>(-1.0, 1.0), texel);
+    color += sample_src(uv + texel * vec2<f32>(1.0, 1.0), texel);
+    return vec4<f32>(color * 0.25, 1.0);
+}
+
+// 3x3 tent filter of the smaller target, which smooths out its blocks.
+fn tent(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(src));
+    var color = sample_src(uv, texel) * 4.0;
+    color += sample_src(uv + vec2<f32>(-texel.x, 0.0), texel) * 2.0;
+    color += sample_src(uv + vec2<f32>(texel.x, 0.0), texel) * 2.0;
+    color += sample_src(uv + vec2<f32>(0.0, -texel.y), texel) * 2.0;
+    color += sample_src(uv + vec2<f32>(0.0, texel.y), texel) * 2.0;
+    color += sample_src(uv + vec2<f32>(-texel.x, -texel.y), texel);
+    color += sample_src(uv + vec2<f32>(texel.x, -texel.y), texel);
+    color += sample_src(uv + vec2<f32>(-texel.x, texel.y), texel);
+    color += sample_src(uv + vec2<f32>(texel.x, texel.y), texel);
+    return color / 16.0;
+}
+
+// Added to the next larger target.
+@fragment
+fn fs_upsample(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(tent(vout.tex_coords), 0.0);
+}
+
+// Added to the HDR image.
+@fragment
+fn fs_composite(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(tent(vout.tex_coords) * bloom.intensity, 0.0);
+}
@@ -0,0 +1,58 @@
+// Integrates the split-sum environment BRDF into the lookup table sampled by math/brdf_lut.wgsl.
+//
+// Texels are indexed by n·v along x and perceptual roughness along y.
+
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+const SAMPLE_COUNT = 1024u;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around +z, distributed like the GGX distribution with the given roughness.
+fn importance_sample_ggx(xi: vec2<f32>, a: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let nov = max(vout.tex_coords.x, 0.0001);
+    let a = perceptual_roughness_to_roughness(vout.tex_coords.y);
+    let v = vec3<f32>(sqrt(1.0 - nov * nov), 0.0, nov);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let h = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), a);
+        let l = 2.0 * dot(v, h) * h - v;
+
+        let nol = saturate(l.z);
+        let noh = saturate(h.z);
+        let voh = saturate(dot(v, h));
+        if (nol > 0.0) {
+            // The distribution cancels out with the sample's pdf of d * noh / (4 * voh).
+            let g = 4.0 * brdf_v_smith_ggx_correlated(nov, nol, a) * nol * voh / noh;
+            let fc = pow(1.0 - voh, 5.0);
+            scale += (1.0 - fc) * g;
+            bias += fc * g;
+        }
+    }
+    return vec4<f32>(scale, bias, 0.0, 0.0) / f32(SAMPLE_COUNT);
+}
@@ -0,0 +1,24 @@
+// Copies the nearest texel of the source depth into every pixel of the viewport of the target.
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var source: texture_depth_2d;
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @builtin(frag_depth) f32 {
+    let dims = textureDimensions(source);
+    let coords = min(vec2<u32>(vout.tex_coords * vec2<f32>(dims)), dims - 1u);
+    return textureLoad(source, coords, 0);
+}
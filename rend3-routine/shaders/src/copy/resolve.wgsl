@@ -0,0 +1,36 @@
+// Resolves multisampled color to the average of the samples of each pixel.
+
+@group(0) @binding(0)
+var source: texture_multisampled_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<u32>(position.xy);
+    let samples = textureNumSamples(source);
+    var sum = vec4<f32>(0.0);
+    for (var i = 0; i < i32(samples); i += 1) {
+        sum += textureLoad(source, coords, i);
+    }
+    return sum / f32(samples);
+}
+
+// Weighs every sample by the inverse of its brightness, so a single very bright HDR sample doesn't make its whole
+// pixel bright, which would alias the edges of highlights.
+@fragment
+fn fs_main_weighted(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<u32>(position.xy);
+    var sum = vec4<f32>(0.0);
+    var weights = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(source)); i += 1) {
+        let value = textureLoad(source, coords, i);
+        let weight = 1.0 / (1.0 + max(value.r, max(value.g, value.b)));
+        sum += value * weight;
+        weights += weight;
+    }
+    return sum / weights;
+}
@@ -0,0 +1,29 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+@group(0) @binding(3)
+var<uniform> uniforms: UniformData;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) screen_offset: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(vs_in: VertexInput) -> VertexOutput {
+    var clip = uniforms.view_proj * vec4<f32>(vs_in.position, 1.0);
+    // Screen offsets are in pixels, y up, and stay the same size at any distance.
+    clip += vec4<f32>(vs_in.screen_offset * 2.0 / vec2<f32>(uniforms.resolution) * clip.w, 0.0, 0.0);
+
+    return VertexOutput(clip, vs_in.color);
+}
+
+@fragment
+fn fs_main(vs_out: VertexOutput) -> @location(0) vec4<f32> {
+    return vs_out.color;
+}
@@ -0,0 +1,45 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(7)
+var<storage> light_tiles: LightTiles;
+
+// Blue for a single light, through green and yellow, to red for a full tile.
+fn light_count_color(t: f32) -> vec3<f32> {
+    if (t < 0.5) {
+        return mix(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 1.0, 0.0), t * 2.0);
+    }
+    return mix(vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), t * 2.0 - 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    if (light_tiles.tiles_x == 0u) {
+        discard;
+    }
+
+    let pixel = vec2<u32>(position.xy);
+    let tile = pixel / light_tiles.tile_size;
+    let base = (tile.y * light_tiles.tiles_x + tile.x) * (light_tiles.max_lights + 1u);
+    let count = light_tiles.data[base];
+    if (count == 0u) {
+        discard;
+    }
+
+    // Full tiles may have dropped lights, so they stand out in white.
+    var color = vec3<f32>(1.0);
+    if (count < light_tiles.max_lights) {
+        color = light_count_color(f32(count - 1u) / f32(max(light_tiles.max_lights - 1u, 1u)));
+    }
+
+    // Outline the tile, so neighbouring tiles with the same count can be told apart.
+    let in_tile = pixel % light_tiles.tile_size;
+    if (any(in_tile == vec2<u32>(0u))) {
+        return vec4<f32>(color * 0.5, 0.7);
+    }
+    return vec4<f32>(color, 0.5);
+}
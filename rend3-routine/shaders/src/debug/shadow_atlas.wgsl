@@ -0,0 +1,45 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/debug/shadow_tint.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(4)
+var<storage> directional_lights: DirectionalLightData;
+@group(0) @binding(6)
+var shadows: texture_depth_2d;
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let dims = textureDimensions(shadows);
+    let texel = min(vec2<u32>(vout.tex_coords * vec2<f32>(dims)), dims - 1u);
+
+    // Reverse z, so near is white and far (or empty) is black.
+    let depth = textureLoad(shadows, texel, 0);
+    var color = vec3<f32>(depth);
+
+    // Outline each light's region of the atlas in its tint.
+    let border = 2.0 / vec2<f32>(dims);
+    for (var i = 0u; i < directional_lights.count; i += 1u) {
+        let light = directional_lights.data[i];
+        let top_left = light.offset;
+        let bottom_right = light.offset + light.size;
+        let inside = all(vout.tex_coords >= top_left) && all(vout.tex_coords <= bottom_right);
+        let inner = all(vout.tex_coords >= top_left + border) && all(vout.tex_coords <= bottom_right - border);
+        if (inside && !inner) {
+            color = shadow_map_tint(i);
+        }
+    }
+
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,11 @@
+// Distinct tints for each shadow map, repeating after six.
+fn shadow_map_tint(index: u32) -> vec3<f32> {
+    switch (index % 6u) {
+        case 0u: { return vec3<f32>(1.0, 0.2, 0.2); }
+        case 1u: { return vec3<f32>(0.2, 1.0, 0.2); }
+        case 2u: { return vec3<f32>(0.2, 0.4, 1.0); }
+        case 3u: { return vec3<f32>(1.0, 1.0, 0.2); }
+        case 4u: { return vec3<f32>(1.0, 0.2, 1.0); }
+        default: { return vec3<f32>(0.2, 1.0, 1.0); }
+    }
+}
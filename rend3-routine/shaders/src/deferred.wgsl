@@ -0,0 +1,56 @@
+// Shades the pixels written to the G-buffer with every light, see gbuffer.wgsl.
+
+{{include "rend3-routine/lighting.wgsl"}}
+{{include "rend3-routine/gbuffer.wgsl"}}
+{{include "rend3-routine/math/depth.wgsl"}}
+
+@group(1) @binding(0)
+var gbuffer_albedo: texture_2d<f32>;
+@group(1) @binding(1)
+var gbuffer_normal: texture_2d<f32>;
+@group(1) @binding(2)
+var gbuffer_material: texture_2d<f32>;
+@group(1) @binding(3)
+var gbuffer_emissive: texture_2d<f32>;
+@group(1) @binding(4)
+var gbuffer_depth: texture_depth_2d;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(vout.position.xy);
+
+    let depth = textureLoad(gbuffer_depth, coords, 0);
+    if (depth_is_background(depth)) {
+        discard;
+    }
+
+    let world_position = depth_to_world_position(vout.tex_coords, depth, uniforms.inv_view_proj);
+    let view_position = uniforms.view * vec4<f32>(world_position, 1.0);
+
+    let normal = textureLoad(gbuffer_normal, coords, 0);
+    let pixel = gbuffer_unpack(
+        textureLoad(gbuffer_albedo, coords, 0),
+        normal,
+        textureLoad(gbuffer_material, coords, 0),
+        textureLoad(gbuffer_emissive, coords, 0),
+    );
+
+    if (gbuffer_is_unlit(normal)) {
+        return vec4<f32>(pixel.albedo.rgb + pixel.emissive, 1.0);
+    }
+
+    return shade_pixel(pixel, view_position, vout.position.xy, gbuffer_receives_shadows(normal));
+}
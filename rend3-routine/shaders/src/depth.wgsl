@@ -1,6 +1,8 @@
 {{include "rend3-routine/structures.wgsl"}}
 {{include "rend3-routine/structures_object.wgsl"}}
+{{include "rend3-routine/instancing.wgsl"}}
 {{include "rend3-routine/material.wgsl"}}
+{{include "rend3-routine/math/dither.wgsl"}}
 
 @group(0) @binding(0)
 var primary_sampler: sampler;
@@ -13,6 +15,10 @@ var<storage> object_buffer: array<Object>;
 var<storage> vertex_buffer: array<u32>;
 @group(1) @binding(2)
 var<storage> per_camera_uniform: PerCameraUniform;
+@group(1) @binding(4)
+var<storage> instance_buffer: array<Instance>;
+@group(1) @binding(5)
+var<storage> culled_instances: array<CulledInstance>;
 
 {{#if (eq profile "GpuDriven")}}
 @group(1) @binding(3)
@@ -35,6 +41,7 @@ var albedo_tex: texture_2d<f32>;
 
     position
     texture_coords_0
+    texture_coords_1
     color_0
 }}
 
@@ -43,24 +50,31 @@ struct VertexOutput {
     @location(0) coords0: vec2<f32>,
     @location(1) alpha: f32,
     @location(2) @interpolate(flat) material: u32,
+    @location(3) @interpolate(flat) opacity: f32,
+    @location(4) coords1: vec2<f32>,
+    @location(5) @interpolate(flat) lod_fade: f32,
 }
 
 @vertex
 fn vs_main(@builtin(instance_index) instance_index: u32, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
-    let indices = Indices(instance_index, vertex_index);
+    let instance = object_instance(instance_index);
+    let indices = Indices(instance.object, vertex_index);
     
     let data = object_buffer[indices.object];
 
     let vs_in = get_vertices(indices);
 
-    let model_view_proj = per_camera_uniform.view_proj * object_buffer[indices.object].transform;
+    let model_view_proj = per_camera_uniform.view_proj * instance_transform(instance);
 
     let position_vec4 = vec4<f32>(vs_in.position, 1.0);
 
     var vs_out: VertexOutput;
     vs_out.material = data.material_index;
+    vs_out.opacity = data.opacity;
+    vs_out.lod_fade = select(data.lod_fade, -data.lod_fade, (instance_index & LOD_FADE_OUT_BIT) != 0u);
     vs_out.coords0 = vs_in.texture_coords_0;
-    vs_out.alpha = vs_in.color_0.a;
+    vs_out.coords1 = vs_in.texture_coords_1;
+    vs_out.alpha = vs_in.color_0.a * instance_color(instance).a;
     vs_out.position = model_view_proj * position_vec4;
 
     return vs_out;
@@ -71,13 +85,13 @@ alias Material = GpuMaterialData;
 
 fn has_albedo_texture(material: ptr<function, Material>) -> bool { return (*material).albedo_tex != 0u; }
 
-fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, coords, ddx, ddy); }
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[0], 0u); return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
 {{else}}
 alias Material = CpuMaterialData;
 
 fn has_albedo_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 0u) & 0x1u); }
 
-fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(albedo_tex, samp, coords, ddx, ddy); }
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[0], 0u); return textureSampleGrad(albedo_tex, samp, c.coords, c.ddx, c.ddy); }
 {{/if}}
 
 @fragment
@@ -85,14 +99,12 @@ fn fs_main(vs_out: VertexOutput) {
     {{#if discard}}
     var material = materials[vs_out.material];
 
-    let coords = vs_out.coords0;
-    let uvdx = dpdx(coords);
-    let uvdy = dpdx(coords);
+    let coords = material_coords(material.uv_transform0, material.uv_transform1, vs_out.coords0, vs_out.coords1);
 
     var alpha = 1.0;
     if (extract_material_flag(material.flags, FLAGS_ALBEDO_ACTIVE)) {
         if (has_albedo_texture(&material)) {
-            alpha = albedo_texture(&material, primary_sampler, coords, uvdx, uvdy).a;
+            alpha = albedo_texture(&material, primary_sampler, coords).a;
         }
         if (extract_material_flag(material.flags, FLAGS_ALBEDO_BLEND)) {
             alpha *= vs_out.alpha;
@@ -100,7 +112,8 @@ fn fs_main(vs_out: VertexOutput) {
     }
     alpha *= material.albedo.a;
 
-    if (alpha < material.alpha_cutout) {
+    let dithered = dither_discard(vs_out.position.xy, vs_out.opacity) || dither_discard_lod(vs_out.position.xy, vs_out.lod_fade);
+    if (alpha < material.alpha_cutout || dithered) {
         discard;
     }
     {{/if}}
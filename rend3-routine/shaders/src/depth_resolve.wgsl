@@ -0,0 +1,20 @@
+// Resolves multisampled depth to the nearest sample of each pixel.
+
+@group(0) @binding(0)
+var source: texture_depth_multisampled_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let coords = vec2<u32>(position.xy);
+    // Reverse z, so the nearest depth is the largest.
+    var nearest = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(source)); i += 1) {
+        nearest = max(nearest, textureLoad(source, coords, i));
+    }
+    return nearest;
+}
@@ -0,0 +1,61 @@
+// Layout of the thin G-buffer written by deferred shading.
+//
+// 0: Rgba8UnormSrgb - albedo, ambient occlusion
+// 1: Rgba16Float    - view space normal, 1.0 if unlit, 2.0 if lit without receiving shadows
+// 2: Rgba8Unorm     - perceptual roughness, metallic, reflectance, clear coat
+// 3: Rgba16Float    - emissive, clear coat perceptual roughness
+// 4: Rg16Float      - velocity, written by passes with velocity, see velocity.rs
+// 5: Rgba16Float    - emission of materials flagged to bloom, written by passes with emission, see bloom.rs
+
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) material: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+    {{#if velocity}}
+    @location(4) velocity: vec2<f32>,
+    {{/if}}
+    {{#if emission}}
+    @location({{#if velocity}}5{{else}}4{{/if}}) emission: vec4<f32>,
+    {{/if}}
+}
+
+fn gbuffer_pack(pixel: PixelData, unlit: bool, receives_shadows: bool) -> GBufferOutput {
+    var output: GBufferOutput;
+    output.albedo = vec4<f32>(pixel.albedo.rgb, pixel.ambient_occlusion);
+    output.normal = vec4<f32>(pixel.normal, select(select(2.0, 0.0, receives_shadows), 1.0, unlit));
+    output.material = vec4<f32>(pixel.perceptual_roughness, pixel.metallic, pixel.reflectance, pixel.clear_coat);
+    output.emissive = vec4<f32>(pixel.emissive, pixel.clear_coat_perceptual_roughness);
+    return output;
+}
+
+fn gbuffer_is_unlit(normal: vec4<f32>) -> bool {
+    return normal.a > 0.5 && normal.a < 1.5;
+}
+
+fn gbuffer_receives_shadows(normal: vec4<f32>) -> bool {
+    return normal.a < 1.5;
+}
+
+// Rebuilds the pixel from the G-buffer. The perceptual roughness already includes the clear coat.
+fn gbuffer_unpack(albedo: vec4<f32>, normal: vec4<f32>, material: vec4<f32>, emissive: vec4<f32>) -> PixelData {
+    var pixel: PixelData;
+    pixel.albedo = vec4<f32>(albedo.rgb, 1.0);
+    pixel.ambient_occlusion = albedo.a;
+    pixel.normal = normalize(normal.xyz);
+    pixel.perceptual_roughness = material.r;
+    pixel.metallic = material.g;
+    pixel.reflectance = material.b;
+    pixel.clear_coat = material.a;
+    pixel.emissive = emissive.rgb;
+    pixel.clear_coat_perceptual_roughness = emissive.a;
+
+    pixel.diffuse_color = compute_diffuse_color(pixel.albedo.rgb, pixel.metallic);
+    pixel.f0 = compute_f0(pixel.albedo.rgb, pixel.metallic, compute_dielectric_f0(pixel.reflectance));
+    pixel.roughness = perceptual_roughness_to_roughness(pixel.perceptual_roughness);
+    pixel.clear_coat_roughness = perceptual_roughness_to_roughness(pixel.clear_coat_perceptual_roughness);
+    return pixel;
+}
@@ -0,0 +1,22 @@
+// Downsamples depth to half resolution, keeping the nearest depth of each 2x2 block.
+
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    let max_coords = textureDimensions(depth) - 1u;
+    let coords = vec2<u32>(position.xy) * 2u;
+    // Reverse z, so the nearest depth is the largest.
+    var nearest = 0.0;
+    for (var i = 0u; i < 4u; i += 1u) {
+        let sample_coords = min(coords + vec2<u32>(i & 1u, i >> 1u), max_coords);
+        nearest = max(nearest, textureLoad(depth, sample_coords, 0));
+    }
+    return nearest;
+}
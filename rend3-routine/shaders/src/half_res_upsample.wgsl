@@ -0,0 +1,21 @@
+// Upsamples an effect rendered at half resolution to full resolution, see `bilateral_upsample`.
+
+{{include "rend3-routine/math/bilateral_upsample.wgsl"}}
+
+@group(0) @binding(0)
+var src: texture_2d<f32>;
+@group(0) @binding(1)
+var half_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var depth: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let d = textureLoad(depth, vec2<u32>(position.xy), 0);
+    return bilateral_upsample(src, half_depth, position.xy, d);
+}
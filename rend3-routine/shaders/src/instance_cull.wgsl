@@ -0,0 +1,50 @@
+{{include "rend3-routine/structures_object.wgsl"}}
+{{include "rend3-routine/math/frustum.wgsl"}}
+
+// The instances of one instanced object culled by a pass.
+struct InstanceCullJob {
+    // In the space of the mesh.
+    bounding_sphere: Sphere,
+    object: u32,
+    first_instance: u32,
+    instance_count: u32,
+    // Index of the culled instances of the object in `culled_instances`.
+    first_output: u32,
+}
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<storage> instance_buffer: array<Instance>;
+@group(0) @binding(1)
+var<storage> jobs: array<InstanceCullJob>;
+@group(0) @binding(2)
+var<uniform> frustum: Frustum;
+@group(0) @binding(3)
+var<storage, read_write> culled_instances: array<CulledInstance>;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+// Dispatched with one row of workgroups per job.
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let job = jobs[id.y];
+    if (id.x >= job.instance_count) {
+        return;
+    }
+
+    let instance = job.first_instance + id.x;
+    let sphere = sphere_transform_by_mat4(job.bounding_sphere, instance_buffer[instance].transform);
+    if (!frustum_contains_sphere(frustum, sphere)) {
+        return;
+    }
+
+    let slot = atomicAdd(&draws[id.y].instance_count, 1u);
+    culled_instances[job.first_output + slot] = CulledInstance(job.object, instance);
+}
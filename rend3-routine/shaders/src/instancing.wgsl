@@ -0,0 +1,39 @@
+// -- DO NOT VALIDATE --
+
+// Needs `object_buffer`, `instance_buffer` and `culled_instances` to be defined.
+
+// The object and instance drawn by a draw with the given instance index.
+fn object_instance(instance_index: u32) -> CulledInstance {
+    if ((instance_index & INSTANCED_BIT) != 0u) {
+        return culled_instances[instance_index & ~INSTANCED_BIT];
+    }
+    return CulledInstance(instance_index & ~LOD_FADE_OUT_BIT, NO_INSTANCE);
+}
+
+fn instance_transform(instance: CulledInstance) -> mat4x4<f32> {
+    if (instance.instance == NO_INSTANCE) {
+        return object_buffer[instance.object].transform;
+    }
+    return instance_buffer[instance.instance].transform;
+}
+
+fn instance_previous_transform(instance: CulledInstance) -> mat4x4<f32> {
+    if (instance.instance == NO_INSTANCE) {
+        return object_buffer[instance.object].previous_transform;
+    }
+    return instance_buffer[instance.instance].previous_transform;
+}
+
+fn instance_color(instance: CulledInstance) -> vec4<f32> {
+    if (instance.instance == NO_INSTANCE) {
+        return vec4<f32>(1.0);
+    }
+    return instance_buffer[instance.instance].color;
+}
+
+fn instance_custom_data(instance: CulledInstance) -> vec4<f32> {
+    if (instance.instance == NO_INSTANCE) {
+        return object_buffer[instance.object].custom_data;
+    }
+    return instance_buffer[instance.instance].custom_data;
+}
@@ -0,0 +1,50 @@
+// Tests how much of each flare source the depth buffer hides, fading the visibility of the source towards it.
+
+{{include "rend3-routine/lens_flare/structures.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> uniforms: LensFlareUniforms;
+@group(0) @binding(1)
+var<storage> sources: array<LensFlareSource>;
+@group(0) @binding(2)
+var depth: texture_depth_2d;
+@group(0) @binding(3)
+var<storage, read_write> visibility: array<f32>;
+
+// Taps along each axis of the tested area.
+const TAPS: i32 = 4;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= uniforms.source_count) {
+        return;
+    }
+    let source = sources[index];
+
+    var current = 0.0;
+    let clip = uniforms.view_proj * source.position;
+    if (clip.w > 0.0) {
+        let ndc = clip.xyz / clip.w;
+        let pixel = (vec2<f32>(ndc.x, -ndc.y) * 0.5 + 0.5) * vec2<f32>(uniforms.resolution);
+        let max_coords = vec2<i32>(uniforms.resolution) - 1;
+
+        var visible = 0;
+        for (var y = 0; y < TAPS; y += 1) {
+            for (var x = 0; x < TAPS; x += 1) {
+                let offset = (vec2<f32>(f32(x), f32(y)) + 0.5) / f32(TAPS) * 2.0 - 1.0;
+                let coords = vec2<i32>(floor(pixel + offset * source.occlusion_radius));
+                // Taps off screen count as hidden, so flares fade out at the edges.
+                if (all(coords >= vec2<i32>(0)) && all(coords <= max_coords)) {
+                    // Reverse z, anything nearer than the source has a larger depth.
+                    if (textureLoad(depth, coords, 0) <= ndc.z) {
+                        visible += 1;
+                    }
+                }
+            }
+        }
+        current = f32(visible) / f32(TAPS * TAPS);
+    }
+
+    visibility[index] = mix(visibility[index], current, uniforms.fade_rate);
+}
@@ -0,0 +1,42 @@
+// Adds ghosts and a halo of the pixels brighter than the threshold, mirrored through the center of the screen.
+
+{{include "rend3-routine/post.wgsl"}}
+{{include "rend3-routine/lens_flare/structures.wgsl"}}
+
+@group(1) @binding(0)
+var<uniform> flare: LensFlareUniforms;
+@group(1) @binding(1)
+var color: texture_2d<f32>;
+
+fn bright(uv: vec2<f32>) -> vec3<f32> {
+    let sampled = textureSampleLevel(color, primary_sampler, fract(uv), 0.0).rgb;
+    return max(sampled - vec3<f32>(flare.threshold), vec3<f32>(0.0));
+}
+
+// Fades samples out towards the edges of the image.
+fn edge_weight(uv: vec2<f32>, power: f32) -> f32 {
+    return pow(clamp(1.0 - length(vec2<f32>(0.5) - fract(uv)) / 0.70710678, 0.0, 1.0), power);
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSampleLevel(color, primary_sampler, vout.tex_coords, 0.0);
+
+    let uv = vec2<f32>(1.0) - vout.tex_coords;
+    let ghost_vec = (vec2<f32>(0.5) - uv) * flare.ghost_spacing;
+
+    var result = vec3<f32>(0.0);
+    for (var i = 0u; i < flare.ghost_count; i += 1u) {
+        let offset = uv + ghost_vec * f32(i);
+        result += bright(offset) * edge_weight(offset, 10.0);
+    }
+
+    let aspect = vec2<f32>(f32(flare.resolution.x) / f32(flare.resolution.y), 1.0);
+    let halo_dir = ghost_vec * aspect;
+    if (flare.halo_radius > 0.0 && dot(halo_dir, halo_dir) > 0.0) {
+        let halo_uv = uv + normalize(halo_dir) / aspect * flare.halo_radius;
+        result += bright(halo_uv) * edge_weight(halo_uv, 5.0);
+    }
+
+    return vec4<f32>(scene.rgb + result * flare.intensity, scene.a);
+}
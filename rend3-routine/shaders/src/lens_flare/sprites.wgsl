@@ -0,0 +1,65 @@
+// Draws a glow at each flare source and ghosts along the line from it through the center of the screen.
+
+{{include "rend3-routine/lens_flare/structures.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> uniforms: LensFlareUniforms;
+@group(0) @binding(1)
+var<storage> sources: array<LensFlareSource>;
+@group(0) @binding(2)
+var<storage> visibility: array<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) @interpolate(flat) ghost: u32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, @builtin(instance_index) instance: u32) -> VertexOutput {
+    // The first sprite of each source is its glow, the rest are ghosts.
+    let sprites = uniforms.ghost_count + 1u;
+    let index = instance / sprites;
+    let ghost = instance % sprites;
+    let source = sources[index];
+
+    var out: VertexOutput;
+    out.corner = vec2<f32>(f32(vertex & 1u), f32(vertex >> 1u)) * 2.0 - 1.0;
+    out.ghost = ghost;
+
+    let strength = visibility[index] * uniforms.intensity;
+    let clip = uniforms.view_proj * source.position;
+    if (clip.w <= 0.0 || strength <= 0.0) {
+        // Degenerate, so nothing is drawn.
+        out.position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.color = vec3<f32>(0.0);
+        return out;
+    }
+
+    let along = 1.0 - f32(ghost) * uniforms.ghost_spacing;
+    let center = clip.xy / clip.w * along;
+    // Ghosts get smaller and dimmer with an uneven size, like in a real lens.
+    var size = uniforms.ghost_size * 2.0;
+    var brightness = 1.0;
+    if (ghost != 0u) {
+        size = uniforms.ghost_size * (0.4 + fract(f32(ghost) * 0.618034));
+        brightness = 0.3 / f32(ghost);
+    }
+    let aspect = f32(uniforms.resolution.x) / f32(uniforms.resolution.y);
+    out.position = vec4<f32>(center + out.corner * size * vec2<f32>(1.0 / aspect, 1.0), 0.0, 1.0);
+    out.color = source.color * strength * brightness;
+    return out;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(vout.corner);
+    var falloff = 0.0;
+    if (vout.ghost == 0u) {
+        falloff = pow(clamp(1.0 - distance, 0.0, 1.0), 3.0);
+    } else {
+        falloff = smoothstep(1.0, 0.6, distance);
+    }
+    return vec4<f32>(vout.color * falloff, 0.0);
+}
@@ -0,0 +1,20 @@
+struct LensFlareUniforms {
+    view_proj: mat4x4<f32>,
+    resolution: vec2<u32>,
+    source_count: u32,
+    ghost_count: u32,
+    ghost_spacing: f32,
+    ghost_size: f32,
+    halo_radius: f32,
+    threshold: f32,
+    intensity: f32,
+    fade_rate: f32,
+}
+
+struct LensFlareSource {
+    // Zero w for sources infinitely far away in a direction.
+    position: vec4<f32>,
+    // Multiplied by the intensity of the source.
+    color: vec3<f32>,
+    occlusion_radius: f32,
+}
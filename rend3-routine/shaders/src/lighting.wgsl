@@ -0,0 +1,128 @@
+// Group 0 of the forward passes, and the lighting shared by forward and deferred shading.
+
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+{{include "rend3-routine/math/brdf_lut.wgsl"}}
+{{include "rend3-routine/math/noise.wgsl"}}
+{{include "rend3-routine/shading.wgsl"}}
+{{include "rend3-routine/math/sh.wgsl"}}
+{{include "rend3-routine/shadow/pcf.wgsl"}}
+{{include "rend3-routine/shadow/fade.wgsl"}}
+{{include "rend3-routine/shadow/projector.wgsl"}}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(1)
+var nearest_sampler: sampler;
+@group(0) @binding(2)
+var comparison_sampler: sampler_comparison;
+@group(0) @binding(3)
+var<uniform> uniforms: UniformData;
+@group(0) @binding(4)
+var<storage> directional_lights: DirectionalLightData;
+@group(0) @binding(5)
+var<storage> point_lights: PointLightData;
+@group(0) @binding(6)
+var shadows: texture_depth_2d;
+@group(0) @binding(7)
+var<storage> light_tiles: LightTiles;
+@group(0) @binding(8)
+var brdf_lut: texture_2d<f32>;
+@group(0) @binding(9)
+var blue_noise: texture_2d_array<f32>;
+@group(0) @binding(10)
+var bayer: texture_2d<f32>;
+@group(0) @binding(11)
+var<storage> shadow_projectors: ShadowProjectorData;
+
+fn point_light_shading(light: PointLight, pixel: PixelData, view_pos: vec3<f32>, v: vec3<f32>, energy_comp: vec3<f32>) -> vec3<f32> {
+    // Delta to light
+    let delta = (uniforms.view * light.position).xyz - view_pos;
+
+    // Distance
+    let d = length(delta);
+
+    let intensity = light.color * point_light_attenuation(d, light.radius);
+
+    // Calculate light source vector
+    let l = delta / d;
+
+    return max(surface_shading(l, intensity, pixel, v, pixel.ambient_occlusion, energy_comp), vec3<f32>(0.0));
+}
+
+// Shades a lit pixel at the given view space position with every light and the skylight.
+//
+// With tiled lighting, only the point lights of the tile containing frag_coord are used.
+// Directional lights only shadow the pixel if receives_shadows is set.
+fn shade_pixel(pixel: PixelData, view_position: vec4<f32>, frag_coord: vec2<f32>, receives_shadows: bool) -> vec4<f32> {
+    // View vector
+    let v = -normalize(view_position.xyz);
+
+    // Transform vectors into view space
+    let view_mat3 = mat3x3<f32>(uniforms.view[0].xyz, uniforms.view[1].xyz, uniforms.view[2].xyz);
+
+    // Single scattering loses energy at high roughness, which the lookup table gives back.
+    let nov = abs(dot(pixel.normal, v)) + 0.00001;
+    let dfg = brdf_lut_sample(brdf_lut, primary_sampler, nov, pixel.perceptual_roughness);
+    let energy_comp = brdf_energy_compensation(dfg, pixel.f0);
+
+    var color = pixel.emissive.rgb;
+    for (var i = 0; i < i32(directional_lights.count); i += 1) {
+        let light = directional_lights.data[i];
+
+        // Get the shadow ndc coordinates, then convert to texture sample coordinates
+        let shadow_ndc = (light.view_proj * uniforms.inv_view * view_position).xyz;
+        let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+        let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
+
+        // Texture sample coordinates of 
+        var top_left = light.offset;
+        var top_right = top_left + light.size;
+        let shadow_coords = mix(top_left, top_right, shadow_local_coords);
+
+        // The shadow is stored in an atlas, so we need to make sure we don't linear blend
+        // across atlasses. We move our conditional borders in a half a pixel for standard
+        // linear blending (so we're hitting texel centers on the edge). We move it an additional
+        // pixel in so that our pcf5 offsets don't move off the edge of the atlasses.
+        let shadow_border = light.inv_resolution * 1.5;
+        top_left += shadow_border;
+        top_right -= shadow_border;
+
+        var shadow_value = 1.0;
+        if (
+            receives_shadows &&
+            any(shadow_flipped >= top_left) && // XY lower
+            any(shadow_flipped <= top_right) && // XY upper
+            shadow_ndc.z >= 0.0 && // Z lower
+            shadow_ndc.z <= 1.0 // Z upper
+        ) {
+            shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
+            shadow_value = mix(1.0, shadow_value, shadow_fade_strength(shadow_ndc.xy, light.fade_start));
+        }
+
+        // Calculate light source vector
+        let l = normalize(view_mat3 * -light.direction);
+
+        color += surface_shading(l, light.color, pixel, v, shadow_value * pixel.ambient_occlusion, energy_comp);
+    }
+
+    if (light_tiles.tiles_x == 0u) {
+        for (var i = 0u; i < point_lights.count; i += 1u) {
+            color += point_light_shading(point_lights.data[i], pixel, view_position.xyz, v, energy_comp);
+        }
+    } else {
+        let tile = vec2<u32>(frag_coord) / light_tiles.tile_size;
+        let base = (tile.y * light_tiles.tiles_x + tile.x) * (light_tiles.max_lights + 1u);
+        let count = light_tiles.data[base];
+        for (var i = 0u; i < count; i += 1u) {
+            color += point_light_shading(point_lights.data[light_tiles.data[base + 1u + i]], pixel, view_position.xyz, v, energy_comp);
+        }
+    }
+
+    let world_normal = normalize((uniforms.inv_view * vec4<f32>(pixel.normal, 0.0)).xyz);
+    color += sh_irradiance(uniforms.skylight, world_normal) * pixel.diffuse_color * pixel.ambient_occlusion;
+
+    let ambient = uniforms.ambient * pixel.albedo;
+    let shaded = vec4<f32>(color, pixel.albedo.a);
+    return max(ambient, shaded);
+}
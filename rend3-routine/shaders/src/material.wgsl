@@ -13,11 +13,53 @@ const FLAGS_CC_GLTF_SPLIT: u32        = 0x0800u;
 const FLAGS_CC_BW_SPLIT: u32          = 0x1000u;
 const FLAGS_UNLIT: u32                = 0x2000u;
 const FLAGS_NEAREST: u32              = 0x4000u;
+const FLAGS_ALPHA_TO_COVERAGE: u32    = 0x8000u;
+const FLAGS_BLEND_PREMULTIPLIED: u32  = 0x10000u;
+const FLAGS_BLEND_ADDITIVE: u32       = 0x20000u;
+const FLAGS_BLEND_MULTIPLY: u32       = 0x40000u;
+const FLAGS_BLEND_SCREEN: u32         = 0x80000u;
+const FLAGS_EMISSIVE_BLOOM: u32       = 0x100000u;
 
 fn extract_material_flag(data: u32, flag: u32) -> bool {
     return bool(data & flag);
 }
 
+// Texture coordinates and their screen space derivatives.
+struct SampleCoords {
+    coords: vec2<f32>,
+    ddx: vec2<f32>,
+    ddy: vec2<f32>,
+}
+
+// Both UV sets of a fragment, after uv_transform0 and uv_transform1.
+struct MaterialCoords {
+    uv0: SampleCoords,
+    uv1: SampleCoords,
+}
+
+fn sample_coords(transform: mat3x3<f32>, coords: vec2<f32>) -> SampleCoords {
+    let transformed = (transform * vec3<f32>(coords, 1.0)).xy;
+    return SampleCoords(transformed, dpdx(transformed), dpdy(transformed));
+}
+
+fn material_coords(uv_transform0: mat3x3<f32>, uv_transform1: mat3x3<f32>, coords0: vec2<f32>, coords1: vec2<f32>) -> MaterialCoords {
+    return MaterialCoords(sample_coords(uv_transform0, coords0), sample_coords(uv_transform1, coords1));
+}
+
+// Coordinates a texture slot samples with: its UV set, through its transform.
+fn slot_coords(coords: MaterialCoords, uv1_slots: u32, transform: mat3x3<f32>, slot: u32) -> SampleCoords {
+    var uv = coords.uv0;
+    if (((uv1_slots >> slot) & 1u) != 0u) {
+        uv = coords.uv1;
+    }
+    // Derivatives ignore the translation.
+    return SampleCoords(
+        (transform * vec3<f32>(uv.coords, 1.0)).xy,
+        (transform * vec3<f32>(uv.ddx, 0.0)).xy,
+        (transform * vec3<f32>(uv.ddy, 0.0)).xy,
+    );
+}
+
 struct GpuMaterialData {
     albedo_tex: u32,
     normal_tex: u32,
@@ -40,6 +82,9 @@ struct GpuMaterialData {
     // -- 16 --
     uv_transform1: mat3x3<f32>,
     // -- 16 --
+    // Applied after uv_transform0, in the order of the texture slots.
+    texture_transforms: array<mat3x3<f32>, 10>,
+    // -- 16 --
     albedo: vec4<f32>,
     // -- 16 --
     emissive: vec3<f32>,
@@ -54,6 +99,9 @@ struct GpuMaterialData {
     ambient_occlusion: f32,
     alpha_cutout: f32,
     flags: u32,
+    // -- 16 --
+    // Bit per texture slot, set when the slot samples UV1.
+    uv1_slots: u32,
 }
 
 struct CpuMaterialData {
@@ -61,6 +109,9 @@ struct CpuMaterialData {
     // -- 16 --
     uv_transform1: mat3x3<f32>,
     // -- 16 --
+    // Applied after uv_transform0, in the order of the texture slots.
+    texture_transforms: array<mat3x3<f32>, 10>,
+    // -- 16 --
     albedo: vec4<f32>,
     // -- 16 --
     emissive: vec3<f32>,
@@ -75,7 +126,12 @@ struct CpuMaterialData {
     ambient_occlusion: f32,
     alpha_cutout: f32,
     flags: u32,
-    
+    // -- 16 --
+    uv1_slots: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+
     // -- 16 --
     texture_enable: u32,
 };
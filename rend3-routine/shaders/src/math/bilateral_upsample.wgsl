@@ -0,0 +1,35 @@
+// Depth aware upsampling of effects rendered at half resolution.
+//
+// Each full resolution pixel blends the four nearest half resolution texels, weighting their bilinear weights by how
+// close their depth is to the depth of the pixel, so effects don't bleed across the edges of objects.
+
+// How much a difference in depth lowers the weight of a texel. Higher keeps edges sharper.
+const BILATERAL_DEPTH_SHARPNESS: f32 = 32.0;
+
+// Relative difference between the distances of two reversed infinite depths, 0 when both are background.
+fn bilateral_depth_difference(a: f32, b: f32) -> f32 {
+    // Distance is proportional to the inverse of the depth.
+    return abs(a - b) / max(max(a, b), 1e-7);
+}
+
+// Upsampled value of `src` at the full resolution pixel at `position`, which has the given depth. `half_depth` has
+// the depth of each texel of `src`, see `HalfResolutionRoutine::add_depth_to_graph`.
+fn bilateral_upsample(src: texture_2d<f32>, half_depth: texture_2d<f32>, position: vec2<f32>, depth: f32) -> vec4<f32> {
+    let max_coords = vec2<i32>(textureDimensions(src)) - 1;
+    let half_position = position * 0.5 - 0.5;
+    let base = vec2<i32>(floor(half_position));
+    let fraction = half_position - floor(half_position);
+
+    var sum = vec4<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var i = 0; i < 4; i += 1) {
+        let offset = vec2<i32>(i & 1, i >> 1u);
+        let coords = clamp(base + offset, vec2<i32>(0), max_coords);
+        let bilinear = mix(1.0 - fraction, fraction, vec2<f32>(offset));
+        let difference = bilateral_depth_difference(depth, textureLoad(half_depth, coords, 0).r);
+        let weight = bilinear.x * bilinear.y / (1.0 + difference * BILATERAL_DEPTH_SHARPNESS) + 1e-5;
+        sum += textureLoad(src, coords, 0) * weight;
+        weight_sum += weight;
+    }
+    return sum / weight_sum;
+}
@@ -31,3 +31,19 @@ fn brdf_v_smith_ggx_correlated(nov: f32, nol: f32, a: f32) -> f32 {
     let ggxv = nol * sqrt((-nov * a2 + nov) * nov + a2);
     return 0.5 / (ggxl + ggxv);
 }
+
+fn compute_diffuse_color(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
+    return base_color * (1.0 - metallic);
+}
+
+fn compute_f0(base_color: vec3<f32>, metallic: f32, reflectance: f32) -> vec3<f32> {
+    return base_color * metallic + (reflectance * (1.0 - metallic));
+}
+
+fn compute_dielectric_f0(reflectance: f32) -> f32 {
+    return 0.16 * reflectance * reflectance;
+}
+
+fn perceptual_roughness_to_roughness(perceptual_roughness: f32) -> f32 {
+    return perceptual_roughness * perceptual_roughness;
+}
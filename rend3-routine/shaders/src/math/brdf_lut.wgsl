@@ -0,0 +1,19 @@
+// The split-sum environment BRDF, as bound to `brdf_lut` in group 0 of the forward passes.
+
+// Scale and bias of f0 for specular environment lighting at the given n·v and perceptual roughness.
+fn brdf_lut_sample(lut: texture_2d<f32>, lut_sampler: sampler, nov: f32, perceptual_roughness: f32) -> vec2<f32> {
+    // Keep filtering away from the opposite edge of repeating samplers.
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(lut));
+    let coords = clamp(vec2<f32>(nov, perceptual_roughness), half_texel, 1.0 - half_texel);
+    return textureSampleLevel(lut, lut_sampler, coords, 0.0).rg;
+}
+
+// Reflectance of prefiltered environment lighting, given a sample of the lookup table.
+fn brdf_env_specular(dfg: vec2<f32>, f0: vec3<f32>, f90: f32) -> vec3<f32> {
+    return f0 * dfg.x + f90 * dfg.y;
+}
+
+// Scales single scattering specular up by the energy lost by not modeling multiple scattering.
+fn brdf_energy_compensation(dfg: vec2<f32>, f0: vec3<f32>) -> vec3<f32> {
+    return 1.0 + f0 * (1.0 / max(dfg.x + dfg.y, 0.0001) - 1.0);
+}
@@ -0,0 +1,36 @@
+// Reconstruction of positions from the depth target.
+//
+// Depth is reversed and the perspective projection has no far plane: the near
+// plane is at 1 and infinity at 0, so pixels nothing was drawn to keep the
+// clear value of 0. Check for those with `depth_is_background` first, as their
+// positions are infinitely far away.
+
+// Whether nothing was drawn to the pixel.
+fn depth_is_background(depth: f32) -> bool {
+    return depth <= 0.0;
+}
+
+// Normalized device coordinates of a texture coordinate, which has y pointing down.
+fn uv_to_ndc(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+// View space position of the surface at `uv` with depth `depth`.
+fn depth_to_view_position(uv: vec2<f32>, depth: f32, inv_proj: mat4x4<f32>) -> vec3<f32> {
+    let view = inv_proj * vec4<f32>(uv_to_ndc(uv), depth, 1.0);
+    return view.xyz / view.w;
+}
+
+// World space position of the surface at `uv` with depth `depth`.
+fn depth_to_world_position(uv: vec2<f32>, depth: f32, inv_view_proj: mat4x4<f32>) -> vec3<f32> {
+    let world = inv_view_proj * vec4<f32>(uv_to_ndc(uv), depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Distance in front of the camera plane of a depth, whatever the handedness
+// of the view. Works for perspective and orthographic projections, as neither
+// mixes the screen position into depth.
+fn linearize_depth(depth: f32, inv_proj: mat4x4<f32>) -> f32 {
+    let view = inv_proj * vec4<f32>(0.0, 0.0, depth, 1.0);
+    return abs(view.z / view.w);
+}
@@ -0,0 +1,21 @@
+// Interleaved gradient noise, from "Next Generation Post Processing in Call of Duty: Advanced Warfare".
+//
+// Returns a threshold in [0, 1) that varies over neighboring pixels, for dithering.
+fn dither_threshold(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(floor(position), vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Returns true if a pixel of an object with the given opacity should be discarded.
+fn dither_discard(position: vec2<f32>, opacity: f32) -> bool {
+    return opacity <= dither_threshold(position);
+}
+
+// Returns true if a pixel of a level of detail cross-fading in with the given weight should be discarded. A negative
+// weight keeps the complementary pixels, for the level of detail fading out.
+fn dither_discard_lod(position: vec2<f32>, weight: f32) -> bool {
+    let threshold = dither_threshold(position);
+    if (weight < 0.0) {
+        return threshold < -weight;
+    }
+    return weight <= threshold;
+}
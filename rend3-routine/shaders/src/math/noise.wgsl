@@ -0,0 +1,15 @@
+// The noise textures, as bound to `blue_noise` and `bayer` in group 0 of the forward passes.
+
+// Blue noise threshold in (0, 1) at the given pixel. Passing the frame index as `frame` gives noise that also
+// converges over time, as the layers step through a low discrepancy sequence.
+fn blue_noise_load(noise: texture_2d_array<f32>, position: vec2<f32>, frame: u32) -> f32 {
+    let size = textureDimensions(noise);
+    let coords = vec2<u32>(floor(position)) % size;
+    return textureLoad(noise, coords, frame % textureNumLayers(noise), 0).r;
+}
+
+// Ordered dithering threshold in (0, 1) at the given pixel.
+fn bayer_load(dither_matrix: texture_2d<f32>, position: vec2<f32>) -> f32 {
+    let coords = vec2<u32>(floor(position)) % textureDimensions(dither_matrix);
+    return textureLoad(dither_matrix, coords, 0).r;
+}
@@ -0,0 +1,27 @@
+// Real spherical harmonics basis functions of the first three bands, for a normalized direction.
+fn sh_basis(d: vec3<f32>) -> array<f32, 9> {
+    return array<f32, 9>(
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    );
+}
+
+// Evaluates coefficients produced by skylight.wgsl in the given normalized direction.
+//
+// The result is irradiance divided by pi, so multiplying it by the diffuse color gives the outgoing radiance.
+fn sh_irradiance(coefficients: array<vec4<f32>, 9>, n: vec3<f32>) -> vec3<f32> {
+    var basis = sh_basis(n);
+    var sh = coefficients;
+    var result = vec3<f32>(0.0);
+    for (var i = 0; i < 9; i++) {
+        result += sh[i].rgb * basis[i];
+    }
+    return max(result, vec3<f32>(0.0));
+}
@@ -0,0 +1,60 @@
+// -- DO NOT VALIDATE --
+
+// Stable access to the mesh and object data, for shaders pulling vertices themselves, like GPU raymarching or custom
+// culling. Shaders including this declare the buffers it reads, see `rend3_routine::mesh_data`:
+//
+// var<storage> object_buffer: array<Object>;
+// var<storage> vertex_buffer: array<u32>;
+//
+// Shaders using only what is in this file keep working until MESH_DATA_VERSION changes. Everything else, like the
+// fields of `Object`, may change between any two releases.
+
+{{include "rend3-routine/structures_object.wgsl"}}
+{{include "rend3/vertex_attributes.wgsl"}}
+
+const MESH_DATA_VERSION: u32 = 1u;
+
+// Set in the offsets of attributes the mesh stores packed, which are read with the extract function of the packing.
+const PACKED_VERTEX_ATTRIBUTE_BIT: u32 = 0x80000000u;
+// Offset of attributes the mesh doesn't have.
+const MISSING_VERTEX_ATTRIBUTE: u32 = 0xFFFFFFFFu;
+
+// Byte offset in `vertex_buffer` of the attribute with the given index in the supported attributes of the material.
+// Each attribute of a mesh is stored as an array with one element per vertex, read with the `extract_attribute_*`
+// functions. Skinned objects point to their skinned positions, normals and tangents.
+fn object_attribute_offset(object_index: u32, attribute_index: u32) -> u32 {
+    return object_buffer[object_index].vertex_attribute_start_offsets[attribute_index];
+}
+
+// Number of triangles of the mesh of the object.
+fn object_triangle_count(object_index: u32) -> u32 {
+    return object_buffer[object_index].index_count / 3u;
+}
+
+// Vertex index `i` of the mesh of the object, counted from its first index. Indices are 16 or 32 bits, packed in
+// `vertex_buffer`.
+fn object_vertex_index(object_index: u32, i: u32) -> u32 {
+    let index = object_buffer[object_index].first_index + i;
+    if (object_buffer[object_index].index_size == 2u) {
+        return (vertex_buffer[index / 2u] >> ((index & 1u) * 16u)) & 0xFFFFu;
+    }
+    return vertex_buffer[index];
+}
+
+// Position of a vertex of the object in model space.
+fn object_vertex_position(object_index: u32, vertex: u32) -> vec3<f32> {
+    let offset = object_attribute_offset(object_index, {{position_attribute_offset}}u);
+    return extract_attribute_vec3_f32(offset, vertex);
+}
+
+// Triangle of the object, with its vertices in world space.
+fn object_triangle(object_index: u32, triangle: u32) -> Triangle {
+    let transform = object_buffer[object_index].transform;
+    var result: Triangle;
+    for (var corner = 0u; corner < 3u; corner++) {
+        let vertex = object_vertex_index(object_index, triangle * 3u + corner);
+        result.indices[corner] = vertex;
+        result.vertices[corner] = (transform * vec4<f32>(object_vertex_position(object_index, vertex), 1.0)).xyz;
+    }
+    return result;
+}
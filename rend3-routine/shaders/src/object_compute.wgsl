@@ -0,0 +1,27 @@
+// Prelude of the compute shaders of object compute passes.
+//
+// `cs_main` is dispatched with one invocation per object slot, in workgroups of 64.
+
+{{include "rend3-routine/structures_object.wgsl"}}
+
+struct ObjectComputeInfo {
+    // Number of object slots, some of which may belong to removed objects.
+    object_count: u32,
+    delta_time: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> object_buffer: array<Object>;
+@group(0) @binding(1)
+var<uniform> info: ObjectComputeInfo;
+@group(0) @binding(2)
+var<storage> params: array<vec4<f32>>;
+
+// Moves the object, keeping its current transform as the previous transform for motion vectors.
+fn set_object_transform(index: u32, transform: mat4x4<f32>) {
+    let old = object_buffer[index].transform;
+    object_buffer[index].previous_transform = old;
+    object_buffer[index].transform = transform;
+    // Keeps the sphere at the same place relative to the object. Rotation and scale are ignored.
+    object_buffer[index].bounding_sphere.location += transform[3].xyz - old[3].xyz;
+}
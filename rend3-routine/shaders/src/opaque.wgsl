@@ -1,25 +1,15 @@
 {{include "rend3-routine/structures.wgsl"}}
 {{include "rend3-routine/structures_object.wgsl"}}
+{{include "rend3-routine/instancing.wgsl"}}
 {{include "rend3-routine/material.wgsl"}}
+{{include "rend3-routine/gbuffer.wgsl"}}
+{{include "rend3-routine/lighting.wgsl"}}
 {{include "rend3-routine/math/brdf.wgsl"}}
 {{include "rend3-routine/math/color.wgsl"}}
+{{include "rend3-routine/math/dither.wgsl"}}
 {{include "rend3-routine/math/matrix.wgsl"}}
 {{include "rend3-routine/shadow/pcf.wgsl"}}
-
-@group(0) @binding(0)
-var primary_sampler: sampler;
-@group(0) @binding(1)
-var nearest_sampler: sampler;
-@group(0) @binding(2)
-var comparison_sampler: sampler_comparison; 
-@group(0) @binding(3)
-var<uniform> uniforms: UniformData;
-@group(0) @binding(4)
-var<storage> directional_lights: DirectionalLightData;
-@group(0) @binding(5)
-var<storage> point_lights: PointLightData;
-@group(0) @binding(6)
-var shadows: texture_depth_2d;
+{{include "rend3-routine/debug/shadow_tint.wgsl"}}
 
 @group(1) @binding(0)
 var<storage> object_buffer: array<Object>;
@@ -27,6 +17,10 @@ var<storage> object_buffer: array<Object>;
 var<storage> vertex_buffer: array<u32>;
 @group(1) @binding(2)
 var<storage> per_camera_uniform: PerCameraUniform;
+@group(1) @binding(4)
+var<storage> instance_buffer: array<Instance>;
+@group(1) @binding(5)
+var<storage> culled_instances: array<CulledInstance>;
 
 {{#if (eq profile "GpuDriven")}}
 @group(1) @binding(3)
@@ -60,6 +54,14 @@ var anisotropy_tex: texture_2d<f32>;
 var ambient_occlusion_tex: texture_2d<f32>;
 {{/if}}
 
+// Set by fs_alpha_to_coverage, which leaves the cutout of materials with alpha to coverage to the multisampling.
+var<private> alpha_to_coverage: bool = false;
+
+{{#if emission}}
+// Set by forward_color to the emission of materials flagged to bloom, written to the bloom emission target.
+var<private> bloom_emission: vec3<f32> = vec3<f32>(0.0);
+{{/if}}
+
 {{
     vertex_fetch
     
@@ -80,21 +82,31 @@ struct VertexOutput {
     @location(2) tangent: vec3<f32>,
     @location(3) coords0: vec2<f32>,
     @location(4) coords1: vec2<f32>,
+    @location(5) @interpolate(flat) opacity: f32,
     @location(6) color: vec4<f32>,
     @location(7) @interpolate(flat) material: u32,
+    @location(8) @interpolate(flat) lod_fade: f32,
+    @location(9) @interpolate(flat) receives_shadows: u32,
+    {{#if velocity}}
+    // Unjittered clip space positions of this frame and the last, see velocity.rs.
+    @location(10) current_clip: vec4<f32>,
+    @location(11) previous_clip: vec4<f32>,
+    {{/if}}
 }
 
 
 @vertex
 fn vs_main(@builtin(instance_index) instance_index: u32, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
-    let indices = Indices(instance_index, vertex_index);
+    let instance = object_instance(instance_index);
+    let indices = Indices(instance.object, vertex_index);
     
     let data = object_buffer[indices.object];
 
     let vs_in = get_vertices(indices);
 
-    let model_view = per_camera_uniform.view * object_buffer[indices.object].transform;
-    let model_view_proj = per_camera_uniform.view_proj * object_buffer[indices.object].transform;
+    let transform = instance_transform(instance);
+    let model_view = per_camera_uniform.view * transform;
+    let model_view_proj = per_camera_uniform.view_proj * transform;
 
     let position_vec4 = vec4<f32>(vs_in.position, 1.0);
     let mv_mat3 = mat3x3<f32>(model_view[0].xyz, model_view[1].xyz, model_view[2].xyz);
@@ -103,14 +115,27 @@ fn vs_main(@builtin(instance_index) instance_index: u32, @builtin(vertex_index)
 
     var vs_out: VertexOutput;
     vs_out.material = data.material_index;
+    vs_out.opacity = data.opacity;
+    vs_out.lod_fade = select(data.lod_fade, -data.lod_fade, (instance_index & LOD_FADE_OUT_BIT) != 0u);
+    vs_out.receives_shadows = data.receives_shadows;
     vs_out.view_position = model_view * position_vec4;
     vs_out.normal = normalize(mv_mat3 * (inv_scale_sq * vs_in.normal));
     vs_out.tangent = normalize(mv_mat3 * (inv_scale_sq * vs_in.tangent));
-    vs_out.color = vs_in.color_0;
+    vs_out.color = vs_in.color_0 * instance_color(instance);
     vs_out.coords0 = vs_in.texture_coords_0;
     vs_out.coords1 = vs_in.texture_coords_1;
     vs_out.position = model_view_proj * position_vec4;
 
+    {{#if velocity}}
+    // Skinned meshes have the positions of the last frame in a second range.
+    var previous_position = vs_in.position;
+    if (data.previous_position_offset != 0xFFFFFFFFu) {
+        previous_position = extract_attribute_vec3_f32(data.previous_position_offset, indices.vertex);
+    }
+    vs_out.current_clip = uniforms.unjittered_view_proj * transform * position_vec4;
+    vs_out.previous_clip = uniforms.previous_view_proj * instance_previous_transform(instance) * vec4<f32>(previous_position, 1.0);
+    {{/if}}
+
     return vs_out;
 }
 
@@ -128,16 +153,18 @@ fn has_emissive_texture(material: ptr<function, Material>) -> bool { return (*ma
 fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return (*material).anisotropy_tex != 0u; }
 fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return (*material).ambient_occlusion_tex != 0u; }
 
-fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, coords, ddx, ddy); }
-fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).normal_tex - 1u], samp, coords, ddx, ddy); }
-fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).roughness_tex - 1u], samp, coords, ddx, ddy); }
-fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).metallic_tex - 1u], samp, coords, ddx, ddy); }
-fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).reflectance_tex - 1u], samp, coords, ddx, ddy); }
-fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).clear_coat_tex - 1u], samp, coords, ddx, ddy); }
-fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).clear_coat_roughness_tex - 1u], samp, coords, ddx, ddy); }
-fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).emissive_tex - 1u], samp, coords, ddx, ddy); }
-fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).anisotropy_tex - 1u], samp, coords, ddx, ddy); }
-fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).ambient_occlusion_tex - 1u], samp, coords, ddx, ddy); }
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[0], 0u); return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[1], 1u); return textureSampleGrad(textures[(*material).normal_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[2], 2u); return textureSampleGrad(textures[(*material).roughness_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[3], 3u); return textureSampleGrad(textures[(*material).metallic_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[4], 4u); return textureSampleGrad(textures[(*material).reflectance_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[5], 5u); return textureSampleGrad(textures[(*material).clear_coat_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[6], 6u); return textureSampleGrad(textures[(*material).clear_coat_roughness_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[7], 7u); return textureSampleGrad(textures[(*material).emissive_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[8], 8u); return textureSampleGrad(textures[(*material).anisotropy_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[9], 9u); return textureSampleGrad(textures[(*material).ambient_occlusion_tex - 1u], samp, c.coords, c.ddx, c.ddy); }
+
+fn albedo_texture_size(material: ptr<function, Material>) -> vec2<f32> { return vec2<f32>(textureDimensions(textures[(*material).albedo_tex - 1u])); }
 {{else}}
 alias Material = CpuMaterialData;
 
@@ -152,47 +179,31 @@ fn has_emissive_texture(material: ptr<function, Material>) -> bool { return bool
 fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 8u) & 0x1u); }
 fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 9u) & 0x1u); }
 
-fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(albedo_tex, samp, coords, ddx, ddy); }
-fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(normal_tex, samp, coords, ddx, ddy); }
-fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(roughness_tex, samp, coords, ddx, ddy); }
-fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(metallic_tex, samp, coords, ddx, ddy); }
-fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(reflectance_tex, samp, coords, ddx, ddy); }
-fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(clear_coat_tex, samp, coords, ddx, ddy); }
-fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(clear_coat_roughness_tex, samp, coords, ddx, ddy); }
-fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(emissive_tex, samp, coords, ddx, ddy); }
-fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(anisotropy_tex, samp, coords, ddx, ddy); }
-fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(ambient_occlusion_tex, samp, coords, ddx, ddy); }
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[0], 0u); return textureSampleGrad(albedo_tex, samp, c.coords, c.ddx, c.ddy); }
+fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[1], 1u); return textureSampleGrad(normal_tex, samp, c.coords, c.ddx, c.ddy); }
+fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[2], 2u); return textureSampleGrad(roughness_tex, samp, c.coords, c.ddx, c.ddy); }
+fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[3], 3u); return textureSampleGrad(metallic_tex, samp, c.coords, c.ddx, c.ddy); }
+fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[4], 4u); return textureSampleGrad(reflectance_tex, samp, c.coords, c.ddx, c.ddy); }
+fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[5], 5u); return textureSampleGrad(clear_coat_tex, samp, c.coords, c.ddx, c.ddy); }
+fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[6], 6u); return textureSampleGrad(clear_coat_roughness_tex, samp, c.coords, c.ddx, c.ddy); }
+fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[7], 7u); return textureSampleGrad(emissive_tex, samp, c.coords, c.ddx, c.ddy); }
+fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[8], 8u); return textureSampleGrad(anisotropy_tex, samp, c.coords, c.ddx, c.ddy); }
+fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: MaterialCoords) -> vec4<f32> { let c = slot_coords(coords, (*material).uv1_slots, (*material).texture_transforms[9], 9u); return textureSampleGrad(ambient_occlusion_tex, samp, c.coords, c.ddx, c.ddy); }
+
+fn albedo_texture_size(material: ptr<function, Material>) -> vec2<f32> { return vec2<f32>(textureDimensions(albedo_tex)); }
 {{/if}}
 
-fn compute_diffuse_color(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
-    return base_color * (1.0 - metallic);
-}
-
-fn compute_f0(base_color: vec3<f32>, metallic: f32, reflectance: f32) -> vec3<f32> {
-    return base_color * metallic + (reflectance * (1.0 - metallic));
-}
-
-fn compute_dielectric_f0(reflectance: f32) -> f32 {
-    return 0.16 * reflectance * reflectance;
-}
-
-fn perceptual_roughness_to_roughness(perceptual_roughness: f32) -> f32 {
-    return perceptual_roughness * perceptual_roughness;
-}
-
 fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput) -> PixelData {
     var material = material_arg;
     var pixel: PixelData;
 
-    let coords = (material.uv_transform0 * vec3<f32>(vs_out.coords0, 1.0)).xy;
-    let uvdx = dpdx(coords);
-    let uvdy = dpdy(coords);
+    let coords = material_coords(material.uv_transform0, material.uv_transform1, vs_out.coords0, vs_out.coords1);
 
     // --- ALBEDO ---
 
     if (extract_material_flag(material.flags, FLAGS_ALBEDO_ACTIVE)) {
         if (has_albedo_texture(&material)) {
-            pixel.albedo = albedo_texture(&material, s, coords, uvdx, uvdy);
+            pixel.albedo = albedo_texture(&material, s, coords);
         } else {
             pixel.albedo = vec4<f32>(1.0);
         }
@@ -209,22 +220,25 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
     pixel.albedo *= material.albedo;
 
     {{#if discard}}
-    if (pixel.albedo.a < material.alpha_cutout) {
+    let covered = alpha_to_coverage && extract_material_flag(material.flags, FLAGS_ALPHA_TO_COVERAGE);
+    let dithered = dither_discard(vs_out.position.xy, vs_out.opacity) || dither_discard_lod(vs_out.position.xy, vs_out.lod_fade);
+    if ((!covered && pixel.albedo.a < material.alpha_cutout) || dithered) {
         discard;
     }
     {{/if}}
 
-    // --- STOP IF UNLIT ---
+    // --- EMISSIVE ---
 
-    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
-        pixel.normal = normalize(vs_out.normal);
-        return pixel;
+    if (has_emissive_texture(&material)) {
+        pixel.emissive = material.emissive * emissive_texture(&material, s, coords).rgb;
+    } else {
+        pixel.emissive = material.emissive;
     }
 
     // --- NORMAL TEXTURE ---
 
     if (has_normal_texture(&material)) {
-        let texture_read = normal_texture(&material, s, coords, uvdx, uvdy);
+        let texture_read = normal_texture(&material, s, coords);
         var normal: vec3<f32>;
         if (extract_material_flag(material.flags, FLAGS_BICOMPONENT_NORMAL)) {
             var bicomp: vec2<f32>;
@@ -255,6 +269,13 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
     }
     pixel.normal = normalize(pixel.normal);
 
+    // --- STOP IF UNLIT ---
+
+    // Unlit materials still have normals, for the G-buffer and debug views.
+    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
+        return pixel;
+    }
+
     // --- AO, Metallic, and Roughness ---
 
     if (extract_material_flag(material.flags, FLAGS_AOMR_COMBINED)) {
@@ -263,7 +284,7 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         // Green: Roughness
         // Blue: Metallic
         if (has_roughness_texture(&material)) {
-            let aomr = roughness_texture(&material, s, coords, uvdx, uvdy);
+            let aomr = roughness_texture(&material, s, coords);
             pixel.ambient_occlusion = material.ambient_occlusion * aomr[0];
             pixel.perceptual_roughness = material.roughness * aomr[1];
             pixel.metallic = material.metallic * aomr[2];
@@ -280,19 +301,19 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         // In roughness texture:
         // Red: Roughness
         if (has_roughness_texture(&material)) {
-            pixel.perceptual_roughness = material.roughness * roughness_texture(&material, s, coords, uvdx, uvdy).r;
+            pixel.perceptual_roughness = material.roughness * roughness_texture(&material, s, coords).r;
         } else {
             pixel.perceptual_roughness = material.roughness;
         }
 
         if (has_metallic_texture(&material)) {
-            pixel.metallic = material.metallic * metallic_texture(&material, s, coords, uvdx, uvdy).r;
+            pixel.metallic = material.metallic * metallic_texture(&material, s, coords).r;
         } else {
             pixel.metallic = material.metallic;
         }
 
         if (has_ambient_occlusion_texture(&material)) {
-            pixel.ambient_occlusion = material.ambient_occlusion * ambient_occlusion_texture(&material, s, coords, uvdx, uvdy).r;
+            pixel.ambient_occlusion = material.ambient_occlusion * ambient_occlusion_texture(&material, s, coords).r;
         } else {
             pixel.ambient_occlusion = material.ambient_occlusion;
         }
@@ -308,7 +329,7 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         // Green: Roughness
         // Blue: Metallic
         if (has_roughness_texture(&material)) {
-            let texture_read = roughness_texture(&material, s, coords, uvdx, uvdy);
+            let texture_read = roughness_texture(&material, s, coords);
             var rm: vec2<f32>;
             if (extract_material_flag(material.flags, FLAGS_AOMR_SWIZZLED_SPLIT)) {
                 rm = texture_read.gb;
@@ -323,7 +344,7 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         }
 
         if (has_ambient_occlusion_texture(&material)) {
-            let texture_read = ambient_occlusion_texture(&material, s, coords, uvdx, uvdy);
+            let texture_read = ambient_occlusion_texture(&material, s, coords);
             pixel.ambient_occlusion = material.ambient_occlusion * texture_read.r;
         } else {
             pixel.ambient_occlusion = material.ambient_occlusion;
@@ -333,7 +354,7 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
     // --- REFLECTANCE ---
 
     if (has_reflectance_texture(&material)) {
-        pixel.reflectance = material.reflectance * reflectance_texture(&material, s, coords, uvdx, uvdy).r;
+        pixel.reflectance = material.reflectance * reflectance_texture(&material, s, coords).r;
     } else {
         pixel.reflectance = material.reflectance;
     }
@@ -342,7 +363,7 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
 
     if (extract_material_flag(material.flags, FLAGS_CC_GLTF_COMBINED)) {
         if (has_clear_coat_texture(&material)) {
-            let texture_read = clear_coat_texture(&material, s, coords, uvdx, uvdy);
+            let texture_read = clear_coat_texture(&material, s, coords);
             pixel.clear_coat = material.clear_coat * texture_read.r;
             pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture_read.g;
         } else {
@@ -351,13 +372,13 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         }
     } else {
         if (has_clear_coat_texture(&material)) {
-            pixel.clear_coat = material.clear_coat * clear_coat_texture(&material, s, coords, uvdx, uvdy).r;
+            pixel.clear_coat = material.clear_coat * clear_coat_texture(&material, s, coords).r;
         } else {
             pixel.clear_coat = material.clear_coat;
         }
 
         if (has_clear_coat_roughness_texture(&material)) {
-            let texture_read = clear_coat_roughness_texture(&material, s, coords, uvdx, uvdy);
+            let texture_read = clear_coat_roughness_texture(&material, s, coords);
 
             if (extract_material_flag(material.flags, FLAGS_CC_GLTF_SPLIT)) {
                 pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture_read.g;
@@ -369,18 +390,10 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         }
     }
 
-    // --- EMISSIVE ---
-
-    if (has_emissive_texture(&material)) {
-        pixel.emissive = material.emissive * emissive_texture(&material, s, coords, uvdx, uvdy).rgb;
-    } else {
-        pixel.emissive = material.emissive;
-    }
-
     // --- ANISOTROPY ---
 
     if (has_anisotropy_texture(&material)) {
-        pixel.anisotropy = material.anisotropy * anisotropy_texture(&material, s, coords, uvdx, uvdy).r;
+        pixel.anisotropy = material.anisotropy * anisotropy_texture(&material, s, coords).r;
     } else {
         pixel.anisotropy = material.anisotropy;
     }
@@ -417,115 +430,212 @@ fn get_pixel_data(material: Material, vs_out: VertexOutput) -> PixelData {
 }
 {{/if}}
 
-fn surface_shading(light_dir: vec3<f32>, intensity: vec3<f32>, pixel: PixelData, view_pos: vec3<f32>, occlusion: f32) -> vec3<f32> {
-    let n = pixel.normal;
-    let h = normalize(view_pos + light_dir);
-
-    let nov = abs(dot(n, view_pos)) + 0.00001;
-    let nol = saturate(dot(n, light_dir));
-    let noh = saturate(dot(n, h));
-    let loh = saturate(dot(light_dir, h));
-
-    let f90 = saturate(dot(pixel.f0, vec3<f32>(50.0 * 0.33)));
-
-    let d = brdf_d_ggx(noh, pixel.roughness);
-    let f = brdf_f_schlick_vec3(loh, pixel.f0, f90);
-    let v = brdf_v_smith_ggx_correlated(nov, nol, pixel.roughness);
-
-    // TODO: figure out how they generate their lut
-    let energy_comp = 1.0;
+const DEBUG_VIEW_NONE: u32 = 0u;
+const DEBUG_VIEW_ALBEDO: u32 = 1u;
+const DEBUG_VIEW_NORMALS: u32 = 2u;
+const DEBUG_VIEW_ROUGHNESS: u32 = 3u;
+const DEBUG_VIEW_METALLIC: u32 = 4u;
+const DEBUG_VIEW_AMBIENT_OCCLUSION: u32 = 5u;
+const DEBUG_VIEW_EMISSIVE: u32 = 6u;
+const DEBUG_VIEW_TEXTURE_COORDINATES: u32 = 7u;
+const DEBUG_VIEW_MIP_LEVEL: u32 = 8u;
+const DEBUG_VIEW_OVERDRAW: u32 = 9u;
+const DEBUG_VIEW_SHADOW_MAPS: u32 = 10u;
+
+// Blue at mip 0, through green and yellow, to red at mip 4 and above.
+fn mip_level_color(level: f32) -> vec3<f32> {
+    let t = saturate(level / 4.0);
+    if (t < 0.5) {
+        return mix(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 1.0, 0.0), t * 2.0);
+    }
+    return mix(vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), t * 2.0 - 1.0);
+}
 
-    // specular
-    let fr = (d * v) * f;
-    // diffuse
-    let fd = pixel.diffuse_color * brdf_fd_lambert();
+// Tints by the first shadow map covering the pixel, with a checkerboard of its texels.
+fn shadow_maps_color(view_position: vec4<f32>) -> vec3<f32> {
+    for (var i = 0u; i < directional_lights.count; i += 1u) {
+        let light = directional_lights.data[i];
 
-    let color = fd + fr * energy_comp;
+        let shadow_ndc = (light.view_proj * uniforms.inv_view * view_position).xyz;
+        let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+        let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
 
-    let light_attenuation = 1.0;
+        if (
+            all(shadow_local_coords >= vec2<f32>(0.0)) &&
+            all(shadow_local_coords <= vec2<f32>(1.0)) &&
+            shadow_ndc.z >= 0.0 &&
+            shadow_ndc.z <= 1.0
+        ) {
+            let shadow_coords = mix(light.offset, light.offset + light.size, shadow_local_coords);
+            let texel = vec2<u32>(shadow_coords / light.inv_resolution);
+            let checker = select(0.75, 1.0, ((texel.x + texel.y) & 1u) == 0u);
+            let shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
+            return shadow_map_tint(i) * checker * (0.3 + 0.7 * shadow_value);
+        }
+    }
+    return vec3<f32>(0.3, 0.1, 0.1);
+}
 
-    return (color * intensity) * (light_attenuation * nol * occlusion);
+fn debug_view_color(material_arg: Material, pixel: PixelData, view_position: vec4<f32>, coords: vec2<f32>, uvdx: vec2<f32>, uvdy: vec2<f32>) -> vec4<f32> {
+    var material = material_arg;
+    switch (uniforms.debug_view) {
+        case DEBUG_VIEW_ALBEDO: {
+            return vec4<f32>(pixel.albedo.rgb, 1.0);
+        }
+        case DEBUG_VIEW_NORMALS: {
+            let inv_view_mat3 = mat3x3<f32>(uniforms.inv_view[0].xyz, uniforms.inv_view[1].xyz, uniforms.inv_view[2].xyz);
+            let world_normal = normalize(inv_view_mat3 * pixel.normal);
+            return vec4<f32>(world_normal * 0.5 + 0.5, 1.0);
+        }
+        case DEBUG_VIEW_ROUGHNESS: {
+            return vec4<f32>(vec3<f32>(pixel.perceptual_roughness), 1.0);
+        }
+        case DEBUG_VIEW_METALLIC: {
+            return vec4<f32>(vec3<f32>(pixel.metallic), 1.0);
+        }
+        case DEBUG_VIEW_AMBIENT_OCCLUSION: {
+            return vec4<f32>(vec3<f32>(pixel.ambient_occlusion), 1.0);
+        }
+        case DEBUG_VIEW_EMISSIVE: {
+            return vec4<f32>(pixel.emissive, 1.0);
+        }
+        case DEBUG_VIEW_TEXTURE_COORDINATES: {
+            return vec4<f32>(fract(coords), 0.0, 1.0);
+        }
+        case DEBUG_VIEW_MIP_LEVEL: {
+            if (!has_albedo_texture(&material)) {
+                return vec4<f32>(0.5, 0.5, 0.5, 1.0);
+            }
+            let size = albedo_texture_size(&material);
+            let texel_dx = uvdx * size;
+            let texel_dy = uvdy * size;
+            let level = 0.5 * log2(max(dot(texel_dx, texel_dx), dot(texel_dy, texel_dy)));
+            return vec4<f32>(mip_level_color(level), 1.0);
+        }
+        case DEBUG_VIEW_OVERDRAW: {
+            // Accumulated with additive blending.
+            return vec4<f32>(0.1, 0.04, 0.01, 1.0);
+        }
+        case DEBUG_VIEW_SHADOW_MAPS: {
+            return vec4<f32>(shadow_maps_color(view_position), 1.0);
+        }
+        default: {
+            return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+        }
+    }
 }
 
-@fragment
-fn fs_main(vs_out: VertexOutput) -> @location(0) vec4<f32> {
+fn forward_color(vs_out: VertexOutput) -> vec4<f32> {
     let material = materials[vs_out.material];
 
+    // The debug views show the coordinates of the albedo texture.
+    let coords = material_coords(material.uv_transform0, material.uv_transform1, vs_out.coords0, vs_out.coords1);
+    let albedo_coords = slot_coords(coords, material.uv1_slots, material.texture_transforms[0], 0u);
+
     let pixel = get_pixel_data(material, vs_out);
 
-    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
-        return pixel.albedo;
+    {{#if emission}}
+    if (extract_material_flag(material.flags, FLAGS_EMISSIVE_BLOOM)) {
+        bloom_emission = pixel.emissive;
     }
+    {{/if}}
 
-    // View vector
-    let v = -normalize(vs_out.view_position.xyz);
-
-    // Transform vectors into view space
-    let view_mat3 = mat3x3<f32>(uniforms.view[0].xyz, uniforms.view[1].xyz, uniforms.view[2].xyz);
-
-    var color = pixel.emissive.rgb;
-    for (var i = 0; i < i32(directional_lights.count); i += 1) {
-        let light = directional_lights.data[i];
-
-        // Get the shadow ndc coordinates, then convert to texture sample coordinates
-        let shadow_ndc = (light.view_proj * uniforms.inv_view * vs_out.view_position).xyz;
-        let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
-        let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
-
-        // Texture sample coordinates of 
-        var top_left = light.offset;
-        var top_right = top_left + light.size;
-        let shadow_coords = mix(top_left, top_right, shadow_local_coords);
-
-        // The shadow is stored in an atlas, so we need to make sure we don't linear blend
-        // across atlasses. We move our conditional borders in a half a pixel for standard
-        // linear blending (so we're hitting texel centers on the edge). We move it an additional
-        // pixel in so that our pcf5 offsets don't move off the edge of the atlasses.
-        let shadow_border = light.inv_resolution * 1.5;
-        top_left += shadow_border;
-        top_right -= shadow_border;
+    if (uniforms.debug_view != DEBUG_VIEW_NONE) {
+        return debug_view_color(material, pixel, vs_out.view_position, albedo_coords.coords, albedo_coords.ddx, albedo_coords.ddy);
+    }
 
-        var shadow_value = 1.0;
-        if (
-            any(shadow_flipped >= top_left) && // XY lower
-            any(shadow_flipped <= top_right) && // XY upper
-            shadow_ndc.z >= 0.0 && // Z lower
-            shadow_ndc.z <= 1.0 // Z upper
-        ) {
-            shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
-        }
+    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
+        return vec4<f32>(pixel.albedo.rgb + pixel.emissive, pixel.albedo.a);
+    }
 
-        // Calculate light source vector
-        let l = normalize(view_mat3 * -light.direction);
+    return shade_pixel(pixel, vs_out.view_position, vs_out.position.xy, vs_out.receives_shadows != 0u);
+}
 
-        color += surface_shading(l, light.color, pixel, v, shadow_value * pixel.ambient_occlusion);
+// Output of blend passes for the blend mode of the material, matching the blend state of its pipeline. Opaque
+// passes only see alpha blending, whose alpha they ignore.
+fn blend_mode_color(flags: u32, color: vec4<f32>, opacity: f32) -> vec4<f32> {
+    let alpha = color.a * opacity;
+    if (extract_material_flag(flags, FLAGS_BLEND_PREMULTIPLIED)) {
+        return vec4<f32>(color.rgb * opacity, alpha);
+    } else if (extract_material_flag(flags, FLAGS_BLEND_ADDITIVE) || extract_material_flag(flags, FLAGS_BLEND_SCREEN)) {
+        return vec4<f32>(color.rgb * alpha, 0.0);
+    } else if (extract_material_flag(flags, FLAGS_BLEND_MULTIPLY)) {
+        return vec4<f32>(mix(vec3<f32>(1.0), color.rgb, alpha), 0.0);
     }
+    return vec4<f32>(color.rgb, alpha);
+}
 
-    for (var i = 0; i < i32(point_lights.count); i += 1) {
-        let light = point_lights.data[i];
+{{#if velocity}}
+// Texture coordinates of the pixel minus the ones the surface had in the last frame.
+fn pixel_velocity(vs_out: VertexOutput) -> vec2<f32> {
+    let ndc_motion = vs_out.current_clip.xy / vs_out.current_clip.w - vs_out.previous_clip.xy / vs_out.previous_clip.w;
+    return ndc_motion * vec2<f32>(0.5, -0.5);
+}
+{{/if}}
 
-        // Delta to light
-        let delta = (uniforms.view * light.position).xyz - vs_out.view_position.xyz;
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    {{#if velocity}}
+    @location(1) velocity: vec2<f32>,
+    {{/if}}
+    {{#if emission}}
+    @location({{#if velocity}}2{{else}}1{{/if}}) emission: vec4<f32>,
+    {{/if}}
+}
 
-        // Distance
-        let d = length(delta);
+fn fragment_output(vs_out: VertexOutput, color: vec4<f32>) -> FragmentOutput {
+    var output: FragmentOutput;
+    output.color = color;
+    {{#if velocity}}
+    output.velocity = pixel_velocity(vs_out);
+    {{/if}}
+    {{#if emission}}
+    output.emission = vec4<f32>(bloom_emission, 1.0);
+    {{/if}}
+    return output;
+}
 
-        // Attenuate from light and cusp at radius
-        // Derivative is 0 at both d = 0 and d = radius
-        // Source: https://lisyarus.github.io/blog/graphics/2022/07/30/point-light-attenuation.html
-        let s = saturate(d / light.radius);
-        let s2 = s * s;
-        let inv_s2 = 1.0 - s2;
-        let att = inv_s2 * inv_s2 / (1.0 + s2);
-        let intensity = light.color * att;
+@fragment
+fn fs_main(vs_out: VertexOutput) -> FragmentOutput {
+    {{#if discard}}
+    return fragment_output(vs_out, forward_color(vs_out));
+    {{else}}
+    // Blend passes fade objects by their opacity, which opaque passes ignore.
+    let color = forward_color(vs_out);
+    return fragment_output(vs_out, blend_mode_color(materials[vs_out.material].flags, color, vs_out.opacity));
+    {{/if}}
+}
 
-        // Calculate light source vector
-        let l = delta / d;
+// Used instead of fs_main by multisampled cutout passes with alpha to coverage. Materials with alpha to coverage fade
+// their edges over the samples of a pixel, the others cover the whole pixel.
+@fragment
+fn fs_alpha_to_coverage(vs_out: VertexOutput) -> FragmentOutput {
+    alpha_to_coverage = true;
+    var color = forward_color(vs_out);
+    let material = materials[vs_out.material];
 
-        color += max(surface_shading(l, intensity, pixel, v, pixel.ambient_occlusion), vec3<f32>(0.0));
+    // Sharpens the alpha around the cutout, so the edge fades over about a pixel.
+    let width = max(fwidth(color.a), 0.0001);
+    if (extract_material_flag(material.flags, FLAGS_ALPHA_TO_COVERAGE)) {
+        color.a = saturate((color.a - material.alpha_cutout) / width + 0.5);
+    } else {
+        color.a = 1.0;
     }
+    return fragment_output(vs_out, color);
+}
 
-    let ambient = uniforms.ambient * pixel.albedo;
-    let shaded = vec4<f32>(color, pixel.albedo.a);
-    return max(ambient, shaded);
+@fragment
+fn fs_gbuffer(vs_out: VertexOutput) -> GBufferOutput {
+    let material = materials[vs_out.material];
+    let pixel = get_pixel_data(material, vs_out);
+    var output = gbuffer_pack(pixel, extract_material_flag(material.flags, FLAGS_UNLIT), vs_out.receives_shadows != 0u);
+    {{#if velocity}}
+    output.velocity = pixel_velocity(vs_out);
+    {{/if}}
+    {{#if emission}}
+    if (extract_material_flag(material.flags, FLAGS_EMISSIVE_BLOOM)) {
+        output.emission = vec4<f32>(pixel.emissive, 1.0);
+    }
+    {{/if}}
+    return output;
 }
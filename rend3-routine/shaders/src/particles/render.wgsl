@@ -0,0 +1,58 @@
+// Draws the sorted particles as camera facing quads.
+
+{{include "rend3-routine/particles/structures.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> uniforms: ParticleUniforms;
+@group(0) @binding(1)
+var<storage> particles: array<Particle>;
+@group(0) @binding(2)
+var<storage> keys: array<vec2<u32>>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(instance_index) instance: u32, @builtin(vertex_index) vertex: u32) -> VertexOutput {
+    var output: VertexOutput;
+
+    // Dead particles and padding have a key of zero.
+    let key = keys[instance];
+    let particle = particles[min(key.y, uniforms.max_particles - 1u)];
+    if (key.x == 0u) {
+        // Degenerate, so nothing is drawn.
+        output.position = vec4<f32>(0.0);
+        return output;
+    }
+
+    // Two triangles of a quad.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex];
+
+    // Rows of the rotation of the view matrix are the camera axes.
+    let right = vec3<f32>(uniforms.view[0].x, uniforms.view[1].x, uniforms.view[2].x);
+    let up = vec3<f32>(uniforms.view[0].y, uniforms.view[1].y, uniforms.view[2].y);
+    let position = particle.position + (right * corner.x + up * corner.y) * uniforms.size * 0.5;
+
+    output.position = uniforms.view_proj * vec4<f32>(position, 1.0);
+    output.corner = corner;
+    output.color = mix(uniforms.color_start, uniforms.color_end, saturate(particle.age / particle.lifetime));
+    return output;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    // Round particles with soft edges.
+    let falloff = saturate(1.0 - dot(vout.corner, vout.corner));
+    return vec4<f32>(vout.color.rgb, vout.color.a * falloff);
+}
@@ -0,0 +1,124 @@
+// Spawns, moves and collides particles, then writes their sort keys.
+
+{{include "rend3-routine/particles/structures.wgsl"}}
+{{include "rend3-routine/math/depth.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> uniforms: ParticleUniforms;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+// Distance to the camera and particle index, sorted back to front by sort.wgsl.
+@group(0) @binding(2)
+var<storage, read_write> keys: array<vec2<u32>>;
+@group(0) @binding(3)
+{{#if (eq SAMPLES 1)}}
+var depth: texture_depth_2d;
+{{else}}
+var depth: texture_depth_multisampled_2d;
+{{/if}}
+
+fn hash(value: u32) -> u32 {
+    var x = value;
+    x ^= x >> 16u;
+    x *= 0x7feb352du;
+    x ^= x >> 15u;
+    x *= 0x846ca68bu;
+    x ^= x >> 16u;
+    return x;
+}
+
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed >> 8u) / 16777216.0;
+}
+
+fn load_depth(coords: vec2<u32>) -> f32 {
+    return textureLoad(depth, coords, 0);
+}
+
+fn world_position(coords: vec2<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(coords) + 0.5) / vec2<f32>(uniforms.resolution);
+    return depth_to_world_position(uv, load_depth(coords), uniforms.inv_view_proj);
+}
+
+// Bounces the particle off the depth buffer if it moved behind the visible surface.
+fn collide(particle: ptr<function, Particle>, previous_position: vec3<f32>) {
+    let clip = uniforms.view_proj * vec4<f32>((*particle).position, 1.0);
+    if (clip.w <= 0.0) {
+        return;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0))) {
+        return;
+    }
+    let coords = min(vec2<u32>(uv * vec2<f32>(uniforms.resolution)), uniforms.resolution - 2u);
+
+    // Depth is reversed, so the particle is behind the surface when its depth is smaller.
+    let surface_depth = load_depth(coords);
+    if (ndc.z >= surface_depth || surface_depth == 0.0) {
+        return;
+    }
+    let surface = world_position(coords);
+    let view_distance = abs((uniforms.view * vec4<f32>((*particle).position - surface, 0.0)).z);
+    if (view_distance > uniforms.collision_thickness) {
+        return;
+    }
+
+    var normal = cross(world_position(coords + vec2<u32>(1u, 0u)) - surface, world_position(coords + vec2<u32>(0u, 1u)) - surface);
+    if (dot(normal, normal) == 0.0) {
+        return;
+    }
+    normal = normalize(normal);
+    // Face the normal towards where the particle came from.
+    if (dot(normal, previous_position - surface) < 0.0) {
+        normal = -normal;
+    }
+
+    (*particle).position = previous_position;
+    let v = (*particle).velocity;
+    (*particle).velocity = (v - 2.0 * dot(v, normal) * normal) * uniforms.restitution;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= uniforms.sort_count) {
+        return;
+    }
+    if (index >= uniforms.max_particles) {
+        keys[index] = vec2<u32>(0u, index);
+        return;
+    }
+
+    var particle = particles[index];
+
+    // Particles spawn in a ring, replacing the oldest ones.
+    let spawn_offset = (index + uniforms.max_particles - uniforms.spawn_start) % uniforms.max_particles;
+    if (spawn_offset < uniforms.spawn_count) {
+        var seed = hash(index ^ hash(uniforms.seed));
+        let direction = normalize(vec3<f32>(random(&seed), random(&seed), random(&seed)) * 2.0 - 1.0 + vec3<f32>(0.0001));
+        particle.position = uniforms.emitter_position;
+        particle.velocity = uniforms.velocity + direction * uniforms.spread * random(&seed);
+        // Spread the particles spawned this frame over the frame.
+        particle.age = uniforms.delta_time * f32(spawn_offset) / f32(uniforms.spawn_count);
+        particle.lifetime = uniforms.lifetime;
+    } else if (particle_alive(particle)) {
+        let previous_position = particle.position;
+        particle.velocity += uniforms.gravity * uniforms.delta_time;
+        particle.position += particle.velocity * uniforms.delta_time;
+        particle.age += uniforms.delta_time;
+        if (uniforms.collision != 0u) {
+            collide(&particle, previous_position);
+        }
+    }
+    particles[index] = particle;
+
+    if (particle_alive(particle)) {
+        let view_position = uniforms.view * vec4<f32>(particle.position, 1.0);
+        // Distance is positive, so its bits sort the same as the float. Dead particles sort last.
+        keys[index] = vec2<u32>(bitcast<u32>(length(view_position.xyz)) | 1u, index);
+    } else {
+        keys[index] = vec2<u32>(0u, index);
+    }
+}
@@ -0,0 +1,30 @@
+// One step of a bitonic sort of particle keys, in descending order so that particles draw back to front.
+
+struct SortStep {
+    // Size of the sequences being merged.
+    block: u32,
+    // Distance between the compared keys.
+    distance: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> keys: array<vec2<u32>>;
+@group(0) @binding(1)
+var<uniform> sort_step: SortStep;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    let j = i ^ sort_step.distance;
+    if (j <= i || j >= arrayLength(&keys)) {
+        return;
+    }
+
+    let a = keys[i];
+    let b = keys[j];
+    let descending = (i & sort_step.block) == 0u;
+    if ((a.x < b.x) == descending) {
+        keys[i] = b;
+        keys[j] = a;
+    }
+}
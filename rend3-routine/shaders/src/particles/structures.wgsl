@@ -0,0 +1,35 @@
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    // Zero for particles that were never spawned.
+    lifetime: f32,
+}
+
+struct ParticleUniforms {
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    emitter_position: vec3<f32>,
+    delta_time: f32,
+    velocity: vec3<f32>,
+    spread: f32,
+    gravity: vec3<f32>,
+    lifetime: f32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    resolution: vec2<u32>,
+    max_particles: u32,
+    sort_count: u32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+    collision: u32,
+    size: f32,
+    restitution: f32,
+    collision_thickness: f32,
+}
+
+fn particle_alive(particle: Particle) -> bool {
+    return particle.age < particle.lifetime;
+}
@@ -0,0 +1,25 @@
+// Prelude of the fragment shaders of post process passes.
+//
+// Sampled inputs are bound in group 1, in the order they were given to the pass.
+
+{{include "rend3-routine/structures.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(1)
+var nearest_sampler: sampler;
+@group(0) @binding(3)
+var<uniform> uniforms: UniformData;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
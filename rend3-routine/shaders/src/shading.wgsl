@@ -0,0 +1,48 @@
+// Lighting of a pixel by a single light, independent of any bindings.
+//
+// Custom materials and routines can include this to shade exactly like the built-in PBR materials. Vectors are in view
+// space.
+
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+// Light reflected towards the normalized direction view_pos by a light arriving from light_dir.
+//
+// The specular is scaled by energy_comp, the `brdf_energy_compensation` of the pixel from math/brdf_lut.wgsl, or 1.0
+// to leave out multiple scattering.
+fn surface_shading(light_dir: vec3<f32>, intensity: vec3<f32>, pixel: PixelData, view_pos: vec3<f32>, occlusion: f32, energy_comp: vec3<f32>) -> vec3<f32> {
+    let n = pixel.normal;
+    let h = normalize(view_pos + light_dir);
+
+    let nov = abs(dot(n, view_pos)) + 0.00001;
+    let nol = saturate(dot(n, light_dir));
+    let noh = saturate(dot(n, h));
+    let loh = saturate(dot(light_dir, h));
+
+    let f90 = saturate(dot(pixel.f0, vec3<f32>(50.0 * 0.33)));
+
+    let d = brdf_d_ggx(noh, pixel.roughness);
+    let f = brdf_f_schlick_vec3(loh, pixel.f0, f90);
+    let v = brdf_v_smith_ggx_correlated(nov, nol, pixel.roughness);
+
+    // specular
+    let fr = (d * v) * f;
+    // diffuse
+    let fd = pixel.diffuse_color * brdf_fd_lambert();
+
+    let color = fd + fr * energy_comp;
+
+    let light_attenuation = 1.0;
+
+    return (color * intensity) * (light_attenuation * nol * occlusion);
+}
+
+// Falloff of a point light at distance d, cusping to zero at the radius.
+fn point_light_attenuation(d: f32, radius: f32) -> f32 {
+    // Derivative is 0 at both d = 0 and d = radius
+    // Source: https://lisyarus.github.io/blog/graphics/2022/07/30/point-light-attenuation.html
+    let s = saturate(d / radius);
+    let s2 = s * s;
+    let inv_s2 = 1.0 - s2;
+    return inv_s2 * inv_s2 / (1.0 + s2);
+}
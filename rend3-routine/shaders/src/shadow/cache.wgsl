@@ -0,0 +1,14 @@
+// Copies the cached shadow atlas of static objects into the shadow atlas.
+
+@group(0) @binding(0)
+var cache: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    return textureLoad(cache, vec2<i32>(position.xy), 0);
+}
@@ -0,0 +1,15 @@
+// How strongly a shadow shows at the given xy position in the shadow map's [-1, 1] clip space.
+//
+// Shadows fade out exponentially between fade_start and the edge of the map, so they
+// don't pop out of existence at the shadow distance.
+fn shadow_fade_strength(shadow_ndc: vec2<f32>, fade_start: f32) -> f32 {
+    let band = 1.0 - fade_start;
+    if (band <= 0.0) {
+        return 1.0;
+    }
+    let edge = max(abs(shadow_ndc.x), abs(shadow_ndc.y));
+    let t = saturate((edge - fade_start) / band);
+    // Normalized so the strength reaches exactly 0 at the edge.
+    let end = exp(-4.0);
+    return (exp(-4.0 * t) - end) / (1.0 - end);
+}
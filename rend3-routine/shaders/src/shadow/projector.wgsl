@@ -0,0 +1,35 @@
+// Projected textures from the shadow projectors, as bound to `shadow_projectors` in group 0 of the forward passes.
+
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/shadow/pcf.wgsl"}}
+
+// Where the world space position lands in the projector's shadow map. xy are the coordinates in the atlas, z the
+// depth to compare against, and w is 1 if the position is inside the projector's view, 0 otherwise.
+fn shadow_projector_coords(projector: ShadowProjector, world_position: vec3<f32>) -> vec4<f32> {
+    let ndc = (projector.view_proj * vec4<f32>(world_position, 1.0)).xyz;
+    let flipped = (ndc.xy * 0.5) + 0.5;
+    let local_coords = vec2<f32>(flipped.x, 1.0 - flipped.y);
+    let coords = mix(projector.offset, projector.offset + projector.size, local_coords);
+
+    // Keep the pcf5 taps within the projector's part of the atlas, as with the directional lights.
+    let border = projector.inv_resolution * 1.5;
+    let inside = all(coords >= projector.offset + border) &&
+        all(coords <= projector.offset + projector.size - border) &&
+        ndc.z >= 0.0 &&
+        ndc.z <= 1.0;
+    return vec4<f32>(coords, ndc.z, select(0.0, 1.0, inside));
+}
+
+// How visible the world space position is from the projector, in [0, 1]. 1 outside of the projector's view.
+fn shadow_projector_visibility(
+    tex: texture_depth_2d,
+    samp: sampler_comparison,
+    projector: ShadowProjector,
+    world_position: vec3<f32>
+) -> f32 {
+    let coords = shadow_projector_coords(projector, world_position);
+    if (coords.w == 0.0) {
+        return 1.0;
+    }
+    return shadow_sample_pcf5(tex, samp, coords.xy, coords.z);
+}
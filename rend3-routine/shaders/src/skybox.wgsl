@@ -18,6 +18,10 @@ var primary_sampler: sampler;
 var<uniform> uniforms: UniformData;
 @group(1) @binding(0)
 var skybox: texture_cube<f32>;
+@group(1) @binding(1)
+var skybox_blend: texture_cube<f32>;
+@group(1) @binding(2)
+var<uniform> sky_uniforms: SkyboxUniforms;
 
 @fragment
 fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
@@ -26,9 +30,13 @@ fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
     let clip = vec4<f32>(output.clip_position, 1.0, 1.0);
     let world_undiv = uniforms.inv_origin_view_proj * clip;
     let world = world_undiv.xyz / world_undiv.w;
-    let world_dir = normalize(world);
+    let sky_dir = sky_uniforms.inv_rotation * normalize(world);
 
-    let background = textureSample(skybox, primary_sampler, world_dir).rgb;
+    let background = mix(
+        textureSample(skybox, primary_sampler, sky_dir).rgb,
+        textureSample(skybox_blend, primary_sampler, sky_dir).rgb,
+        sky_uniforms.blend,
+    ) * sky_uniforms.intensity;
 
     return vec4<f32>(background, 1.0);
 }
@@ -0,0 +1,96 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/consts.wgsl"}}
+{{include "rend3-routine/math/sh.wgsl"}}
+
+// Projects the skybox onto spherical harmonics, convolved with a cosine lobe, so it can light diffuse surfaces.
+
+@group(0) @binding(0)
+var sky_sampler: sampler;
+@group(0) @binding(1)
+var sky: texture_cube<f32>;
+@group(0) @binding(2)
+var sky_blend: texture_cube<f32>;
+@group(0) @binding(3)
+var<uniform> sky_uniforms: SkyboxUniforms;
+@group(0) @binding(4)
+var<storage, read_write> output: array<vec4<f32>, 9>;
+
+// Samples taken along each side of each cube face.
+const SAMPLES: u32 = 16u;
+const THREADS: u32 = 64u;
+
+var<workgroup> partial_sums: array<array<vec3<f32>, 9>, THREADS>;
+var<workgroup> partial_weights: array<f32, THREADS>;
+
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+// A mip close to the sample resolution, so every texel contributes.
+fn sample_lod(size: u32, levels: u32) -> f32 {
+    return clamp(log2(f32(size) / f32(SAMPLES)), 0.0, f32(levels - 1u));
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(local_invocation_index) thread: u32) {
+    let lod = sample_lod(textureDimensions(sky).x, textureNumLevels(sky));
+    let blend_lod = sample_lod(textureDimensions(sky_blend).x, textureNumLevels(sky_blend));
+
+    var sums: array<vec3<f32>, 9>;
+    var weight_sum = 0.0;
+    for (var i = thread; i < 6u * SAMPLES * SAMPLES; i += THREADS) {
+        let face = i / (SAMPLES * SAMPLES);
+        let texel = i % (SAMPLES * SAMPLES);
+        let uv = (vec2<f32>(f32(texel % SAMPLES), f32(texel / SAMPLES)) + 0.5) / f32(SAMPLES) * 2.0 - 1.0;
+        let unnormalized = face_direction(face, uv);
+        let length_sq = dot(unnormalized, unnormalized);
+        let dir = unnormalized * inverseSqrt(length_sq);
+
+        // Solid angle covered by the sample, up to a constant.
+        let weight = 1.0 / (length_sq * sqrt(length_sq));
+        let sky_dir = sky_uniforms.inv_rotation * dir;
+        let sky_radiance = mix(
+            textureSampleLevel(sky, sky_sampler, sky_dir, lod).rgb,
+            textureSampleLevel(sky_blend, sky_sampler, sky_dir, blend_lod).rgb,
+            sky_uniforms.blend,
+        );
+        let radiance = sky_radiance * sky_uniforms.intensity * weight;
+
+        var basis = sh_basis(dir);
+        for (var c = 0; c < 9; c++) {
+            sums[c] += radiance * basis[c];
+        }
+        weight_sum += weight;
+    }
+    partial_sums[thread] = sums;
+    partial_weights[thread] = weight_sum;
+
+    workgroupBarrier();
+
+    if thread != 0u {
+        return;
+    }
+
+    var total: array<vec3<f32>, 9>;
+    var total_weight = 0.0;
+    for (var t = 0u; t < THREADS; t++) {
+        for (var c = 0; c < 9; c++) {
+            total[c] += partial_sums[t][c];
+        }
+        total_weight += partial_weights[t];
+    }
+
+    // Cosine lobe convolution per band, divided by pi.
+    var bands = array<f32, 9>(1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25);
+    let normalization = 4.0 * PI / total_weight;
+    for (var c = 0; c < 9; c++) {
+        output[c] = vec4<f32>(total[c] * normalization * bands[c], 0.0);
+    }
+}
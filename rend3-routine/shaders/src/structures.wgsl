@@ -32,9 +32,20 @@ struct UniformData {
     inv_view: mat4x4<f32>,
     inv_view_proj: mat4x4<f32>,
     inv_origin_view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
     frustum: Frustum,
     ambient: vec4<f32>,
     resolution: vec2<u32>,
+    debug_view: u32,
+    skylight: array<vec4<f32>, 9>,
+    // Seconds of `Renderer::advance_time`, which effects should wrap as it loses precision over time.
+    time: f32,
+    delta_time: f32,
+    frame_index: u32,
+    // view_proj without the camera jitter.
+    unjittered_view_proj: mat4x4<f32>,
+    // unjittered_view_proj of the last frame, for motion vectors.
+    previous_view_proj: mat4x4<f32>,
 }
 
 struct PerCameraUniform {
@@ -60,6 +71,18 @@ struct DirectionalLight {
     offset: vec2<f32>,
     /// [0, 1] size of the shadow map in the atlas.
     size: vec2<f32>,
+    /// Distance from the center of the shadow map, in clip space, where
+    /// shadows start fading out.
+    fade_start: f32,
+}
+
+struct SkyboxUniforms {
+    /// Turns world space directions into directions in the sky's textures.
+    inv_rotation: mat3x3<f32>,
+    /// Multiplier of the sky's brightness.
+    intensity: f32,
+    /// How far the background is faded into the blend texture.
+    blend: f32,
 }
 
 struct DirectionalLightData {
@@ -67,6 +90,23 @@ struct DirectionalLightData {
     data: array<DirectionalLight>,
 }
 
+/// Indexed by the idx of the projector's handle. Slots without a projector have a zero size.
+struct ShadowProjector {
+    /// View/Projection of the projector, outputting [-1, 1] like the directional lights.
+    view_proj: mat4x4<f32>,
+    /// 1 / resolution of whole shadow map
+    inv_resolution: vec2<f32>,
+    /// [0, 1] offset of the shadow map in the atlas.
+    offset: vec2<f32>,
+    /// [0, 1] size of the shadow map in the atlas.
+    size: vec2<f32>,
+}
+
+struct ShadowProjectorData {
+    count: u32,
+    data: array<ShadowProjector>,
+}
+
 struct PointLight {
     /// The position of the light in world space.
     position: vec4<f32>,
@@ -81,6 +121,17 @@ struct PointLightData {
     data: array<PointLight>,
 }
 
+struct LightTiles {
+    /// Size of a tile in pixels.
+    tile_size: u32,
+    /// Number of tiles in a row. Zero when tiled lighting is disabled.
+    tiles_x: u32,
+    /// Maximum number of point lights in a tile.
+    max_lights: u32,
+    /// The list of each tile, its point light count followed by max_lights indices.
+    data: array<u32>,
+}
+
 struct PixelData {
     albedo: vec4<f32>,
     diffuse_color: vec3<f32>,
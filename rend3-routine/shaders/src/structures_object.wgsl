@@ -1,10 +1,45 @@
 {{include "rend3-routine/math/sphere.wgsl"}}
 
+// Set in the instance index of draws of the level of detail fading out of a cross-fade, see ForwardRoutine.
+const LOD_FADE_OUT_BIT: u32 = 0x80000000u;
+// Set in the instance index of instanced draws, whose instance index is an index into the culled instances.
+const INSTANCED_BIT: u32 = 0x40000000u;
+// Instance of objects that aren't instanced.
+const NO_INSTANCE: u32 = 0xFFFFFFFFu;
+
 struct Object {
     transform: mat4x4<f32>,
+    // Transform of the last frame, for motion vectors.
+    previous_transform: mat4x4<f32>,
     bounding_sphere: Sphere,
+    custom_data: vec4<f32>,
+    opacity: f32,
+    lod_fade: f32,
+    // 1 if shadows darken the object, see ObjectShadowSettings::receives_shadows.
+    receives_shadows: u32,
+    // Offset of the vec3 positions of the last frame, for use with
+    // `extract_attribute_vec3_f32`. Differs from the current positions for
+    // skinned meshes.
+    previous_position_offset: u32,
     first_index: u32,
     index_count: u32,
+    // Bytes per index of the mesh, 2 or 4, which `first_index` counts in.
+    index_size: u32,
     material_index: u32,
     vertex_attribute_start_offsets: array<u32, {{vertex_array_counts}}>,
 }
+
+struct Instance {
+    transform: mat4x4<f32>,
+    // Transform of the last frame, for motion vectors.
+    previous_transform: mat4x4<f32>,
+    color: vec4<f32>,
+    custom_data: vec4<f32>,
+}
+
+// An instance that survived culling.
+struct CulledInstance {
+    object: u32,
+    // Index into the instance buffer, NO_INSTANCE for objects that aren't instanced.
+    instance: u32,
+}
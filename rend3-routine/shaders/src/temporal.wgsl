@@ -0,0 +1,103 @@
+// Accumulates jittered frames into a history at the output resolution.
+
+struct TemporalUniforms {
+    inv_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec2<f32>,
+    render_resolution: vec2<f32>,
+    output_resolution: vec2<f32>,
+    current_weight: f32,
+    has_velocity: u32,
+    checkerboard: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: TemporalUniforms;
+@group(0) @binding(1)
+var linear_sampler: sampler;
+@group(0) @binding(2)
+var color_tex: texture_2d<f32>;
+@group(0) @binding(3)
+var depth_tex: texture_depth_2d;
+@group(0) @binding(4)
+var history_tex: texture_2d<f32>;
+@group(0) @binding(5)
+var reactive_tex: texture_2d<f32>;
+@group(0) @binding(6)
+var velocity_tex: texture_2d<f32>;
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) history: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+fn uv_to_ndc(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+fn ndc_to_uv(ndc: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> FragmentOutput {
+    let ndc = uv_to_ndc(position.xy / params.output_resolution);
+
+    // The jittered frame shows what is at `ndc` at `ndc + jitter`.
+    let jittered_ndc = ndc + params.jitter;
+    let jittered_uv = ndc_to_uv(jittered_ndc);
+    let max_pixel = vec2<i32>(params.render_resolution) - 1;
+    let render_pixel = clamp(vec2<i32>(jittered_uv * params.render_resolution), vec2<i32>(0), max_pixel);
+
+    let current = textureSampleLevel(color_tex, linear_sampler, jittered_uv, 0.0).rgb;
+
+    // Clamping the history to the colors around the pixel rejects most of the
+    // history that doesn't belong to the surface anymore.
+    var minimum = current;
+    var maximum = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = textureLoad(color_tex, clamp(render_pixel + vec2<i32>(x, y), vec2<i32>(0), max_pixel), 0).rgb;
+            minimum = min(minimum, neighbor);
+            maximum = max(maximum, neighbor);
+        }
+    }
+
+    // Reproject with the velocity of the objects, and with the depth where no object was drawn, which only
+    // follows the camera.
+    let depth = textureLoad(depth_tex, render_pixel, 0);
+    let world = params.inv_view_proj * vec4<f32>(jittered_ndc, depth, 1.0);
+    let prev_clip = params.prev_view_proj * world;
+    var prev_uv = ndc_to_uv(prev_clip.xy / prev_clip.w);
+    var behind_camera = prev_clip.w <= 0.0;
+    if params.has_velocity != 0u && depth != 0.0 {
+        prev_uv = ndc_to_uv(ndc) - textureLoad(velocity_tex, render_pixel, 0).xy;
+        behind_camera = false;
+    }
+
+    var weight = params.current_weight;
+    if params.checkerboard != 0u {
+        // Pixels of this frame's half of the checkerboard are taken from the
+        // current frame, the others from the history, unless there is none.
+        // Half of the rows line up with the rendered columns, the other half
+        // fall between them and are interpolated.
+        let frame = params.checkerboard - 1u;
+        let rendered = ((u32(position.x) + u32(position.y) + frame) & 1u) == 0u;
+        weight = select(0.0, 1.0, rendered || params.current_weight == 1.0);
+    }
+    if behind_camera || any(prev_uv < vec2<f32>(0.0)) || any(prev_uv > vec2<f32>(1.0)) {
+        weight = 1.0;
+    }
+    let reactive_pixel = min(render_pixel, vec2<i32>(textureDimensions(reactive_tex)) - 1);
+    weight = max(weight, textureLoad(reactive_tex, reactive_pixel, 0).r);
+
+    let history = clamp(textureSampleLevel(history_tex, linear_sampler, prev_uv, 0.0).rgb, minimum, maximum);
+    let result = vec4<f32>(mix(history, current, weight), 1.0);
+
+    return FragmentOutput(result, result);
+}
@@ -0,0 +1,119 @@
+// Culls the point lights against screen space tiles, bounded by the farthest depth in the tile.
+//
+// Tiles reach from the near plane to the farthest opaque surface, so transparent objects in front are lit correctly.
+
+{{include "rend3-routine/structures.wgsl"}}
+
+struct TileCullingUniforms {
+    view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    resolution: vec2<u32>,
+    deterministic: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> culling_uniforms: TileCullingUniforms;
+@group(0) @binding(1)
+var<storage> point_lights: PointLightData;
+@group(0) @binding(2)
+{{#if (eq SAMPLES 1)}}
+var depth: texture_depth_2d;
+{{else}}
+var depth: texture_depth_multisampled_2d;
+{{/if}}
+@group(0) @binding(3)
+var<storage, read_write> light_tiles: LightTiles;
+
+const THREADS: u32 = 8u;
+
+var<workgroup> farthest_depth: atomic<u32>;
+var<workgroup> light_count: atomic<u32>;
+
+// Reverse z, so the farthest depth is the smallest.
+fn load_farthest_depth(coords: vec2<u32>) -> f32 {
+{{#if (eq SAMPLES 1)}}
+    return textureLoad(depth, coords, 0);
+{{else}}
+    var farthest = 1.0;
+    for (var i = 0; i < i32(textureNumSamples(depth)); i += 1) {
+        farthest = min(farthest, textureLoad(depth, coords, i));
+    }
+    return farthest;
+{{/if}}
+}
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let view = culling_uniforms.inv_proj * vec4<f32>(ndc, 1.0);
+    return view.xyz / view.w;
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(num_workgroups) tile_count: vec3<u32>,
+    @builtin(local_invocation_id) local: vec3<u32>,
+    @builtin(local_invocation_index) thread: u32,
+) {
+    if (thread == 0u) {
+        atomicStore(&farthest_depth, bitcast<u32>(1.0));
+        atomicStore(&light_count, 0u);
+    }
+    workgroupBarrier();
+
+    let tile_start = tile.xy * light_tiles.tile_size;
+    let tile_end = min(tile_start + light_tiles.tile_size, culling_uniforms.resolution);
+
+    // Depth is positive, so the bits sort the same as the floats.
+    var farthest = 1.0;
+    for (var y = tile_start.y + local.y; y < tile_end.y; y += THREADS) {
+        for (var x = tile_start.x + local.x; x < tile_end.x; x += THREADS) {
+            farthest = min(farthest, load_farthest_depth(vec2<u32>(x, y)));
+        }
+    }
+    atomicMin(&farthest_depth, bitcast<u32>(farthest));
+    workgroupBarrier();
+
+    // Where nothing was drawn, the tile reaches very far instead of infinitely far.
+    let far = max(bitcast<f32>(atomicLoad(&farthest_depth)), 1e-7);
+
+    let resolution = vec2<f32>(culling_uniforms.resolution);
+    let ndc_min = vec2<f32>(tile_start) / resolution * 2.0 - 1.0;
+    let ndc_max = vec2<f32>(tile_end) / resolution * 2.0 - 1.0;
+
+    var aabb_min = vec3<f32>(3.40282347e+38);
+    var aabb_max = vec3<f32>(-3.40282347e+38);
+    for (var corner = 0u; corner < 8u; corner += 1u) {
+        let x = select(ndc_min.x, ndc_max.x, (corner & 1u) != 0u);
+        // Texture coordinates go down, NDC goes up.
+        let y = -select(ndc_min.y, ndc_max.y, (corner & 2u) != 0u);
+        let z = select(1.0, far, (corner & 4u) != 0u);
+        let point = unproject(vec3<f32>(x, y, z));
+        aabb_min = min(aabb_min, point);
+        aabb_max = max(aabb_max, point);
+    }
+
+    let base = (tile.y * tile_count.x + tile.x) * (light_tiles.max_lights + 1u);
+    // Threads race for the slots, so in determinism mode one thread adds every light, keeping them in order.
+    var first = thread;
+    var stride = THREADS * THREADS;
+    if (culling_uniforms.deterministic != 0u) {
+        first = select(point_lights.count, 0u, thread == 0u);
+        stride = 1u;
+    }
+    for (var i = first; i < point_lights.count; i += stride) {
+        let light = point_lights.data[i];
+        let center = (culling_uniforms.view * light.position).xyz;
+        let delta = clamp(center, aabb_min, aabb_max) - center;
+        if (dot(delta, delta) <= light.radius * light.radius) {
+            let slot = atomicAdd(&light_count, 1u);
+            if (slot < light_tiles.max_lights) {
+                light_tiles.data[base + 1u + slot] = i;
+            }
+        }
+    }
+    workgroupBarrier();
+
+    if (thread == 0u) {
+        light_tiles.data[base] = min(atomicLoad(&light_count), light_tiles.max_lights);
+    }
+}
@@ -13,7 +13,7 @@
 //! to, or muck with any of the data in there, you are free to, and the
 //! following routines will behave as you configure.
 
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 use glam::{UVec2, Vec4};
 use rend3::{
@@ -21,16 +21,28 @@ use rend3::{
         self, DataHandle, InstructionEvaluationOutput, RenderGraph, RenderPassTargets, RenderTargetDescriptor,
         RenderTargetHandle, ViewportRect,
     },
+    managers::CameraState,
     types::{SampleCount, TextureFormat, TextureUsages},
-    Renderer, ShaderPreProcessor, INTERNAL_SHADOW_DEPTH_FORMAT,
+    util::frustum::Plane,
+    Renderer, RendererProfile, ShaderPreProcessor, INTERNAL_SHADOW_DEPTH_FORMAT,
 };
-use wgpu::BindGroup;
+use wgpu::{BindGroup, Buffer};
 
 use crate::{
-    clear,
+    bloom, clear,
     common::{self, CameraSpecifier},
-    forward::{self, ForwardRoutineArgs},
-    skinning, uniforms,
+    debug::{DebugDraw, DebugDrawRoutine, LightTilesDebugRoutine, ShadowAtlasDebugRoutine, SkeletonGizmos},
+    deferred::{DeferredLightingRoutine, GBufferTargets},
+    depth_partition,
+    depth_resolve::DepthResolveRoutine,
+    forward::{self, DrawSortPolicy, ForwardRoutine, ForwardRoutineArgs, ObjectFilter},
+    pbr::PbrMaterial,
+    post::{PostProcessOutput, PostProcessPass},
+    reflection::{PlanarReflectionRoutine, REFLECTION_FORMAT},
+    skinning,
+    temporal::{TemporalUpscaleRoutine, TemporalUpscaleTargets, TEMPORAL_FORMAT},
+    uniforms::{self, DebugView},
+    velocity::VelocityTargets,
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -69,18 +81,87 @@ impl DepthTargets {
     pub fn rendering_target(&self) -> RenderTargetHandle {
         self.multi_sample.unwrap_or(self.single_sample_mipped.set_mips(0..1))
     }
+
+    /// The single sampled depth of the scene, for passes sampling it. With
+    /// multisampling it only holds the depth once
+    /// [`BaseRenderGraphIntermediateState::resolve_depth`] ran.
+    ///
+    /// See [`depth_resolve`](crate::depth_resolve) for reading it in shaders.
+    pub fn readable(&self) -> RenderTargetHandle {
+        self.single_sample_mipped
+    }
+}
+
+/// What a camera shows where no object was drawn.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CameraBackground {
+    /// The skybox, if there is one, otherwise
+    /// [`BaseRenderGraphSettings::clear_color`].
+    #[default]
+    Skybox,
+    /// [`BaseRenderGraphSettings::clear_color`], even with a skybox.
+    ClearColor,
+    /// Whatever the output held before, with the scene composited on top
+    /// through [`TonemappingRoutine::add_composite_to_graph`]. For viewports
+    /// drawn over other renders, like picture-in-picture views and UI.
+    ///
+    /// [`PostProcessOutput::AfterTonemapping`] passes replace the output
+    /// instead.
+    ///
+    /// [`TonemappingRoutine::add_composite_to_graph`]: crate::tonemapping::TonemappingRoutine::add_composite_to_graph
+    Composite,
 }
 
 pub struct OutputRenderTarget {
     pub handle: RenderTargetHandle,
     pub resolution: UVec2,
+    /// Sample count of the scene targets, which are recreated every frame,
+    /// so it can change from one frame to the next. Routines create the
+    /// pipelines of a sample count the first time it is used.
     pub samples: SampleCount,
+    /// Scale of the resolution the scene is rendered at, relative to
+    /// `resolution`. Every target up to tonemapping is allocated at the
+    /// scaled size, which tonemapping stretches onto the output, so the
+    /// [`PostProcessOutput::AfterTonemapping`] passes and anything drawn on
+    /// the output afterwards, like UI, stay at native resolution. Applies on
+    /// top of any dynamic resolution, see
+    /// [`BaseRenderGraphIntermediateState::temporal_upscale`].
+    pub render_scale: f32,
+}
+
+impl OutputRenderTarget {
+    /// The resolution the scene is rendered at, `resolution` scaled by
+    /// `render_scale` and rounded, at least a pixel wide.
+    pub fn render_resolution(&self) -> UVec2 {
+        (self.resolution.as_vec2() * self.render_scale).round().as_uvec2().max(UVec2::ONE)
+    }
 }
 
 pub struct BaseRenderGraphRoutines<'node> {
     pub pbr: &'node crate::pbr::PbrRoutine,
     pub skybox: Option<&'node crate::skybox::SkyboxRoutine>,
     pub tonemapping: &'node crate::tonemapping::TonemappingRoutine,
+    /// Keeps the shadows of static objects between frames, see
+    /// [`ShadowCacheRoutine`](crate::shadow_cache::ShadowCacheRoutine).
+    pub shadow_cache: Option<&'node crate::shadow_cache::ShadowCacheRoutine>,
+    /// Fullscreen passes run at the stage given by their
+    /// [`PostProcessPass::output`], in order.
+    pub post_process: &'node [&'node PostProcessPass],
+    /// Culls point lights per screen tile, see
+    /// [`TiledLightingRoutine`](crate::tiled_lighting::TiledLightingRoutine).
+    pub tiled_lighting: Option<&'node crate::tiled_lighting::TiledLightingRoutine>,
+    /// Particle systems simulated and drawn after transparent objects, see
+    /// [`ParticleRoutine`](crate::particles::ParticleRoutine).
+    pub particles: &'node [&'node crate::particles::ParticleRoutine],
+    /// Lens flares added before post processing, see
+    /// [`LensFlareRoutine`](crate::lens_flare::LensFlareRoutine).
+    pub lens_flare: Option<&'node crate::lens_flare::LensFlareRoutine>,
+    /// Bloom of emissive materials, added before the lens flares, see
+    /// [`BloomRoutine`](crate::bloom::BloomRoutine).
+    pub bloom: Option<&'node crate::bloom::BloomRoutine>,
+    /// Compute passes moving objects on the GPU, run after skinning, in
+    /// order. See [`ObjectComputePass`](crate::object_compute::ObjectComputePass).
+    pub object_compute: &'node [&'node crate::object_compute::ObjectComputePass<PbrMaterial>],
 }
 
 pub struct BaseRenderGraphInputs<'a, 'node> {
@@ -93,6 +174,51 @@ pub struct BaseRenderGraphInputs<'a, 'node> {
 pub struct BaseRenderGraphSettings {
     pub ambient_color: Vec4,
     pub clear_color: Vec4,
+    /// What the camera shows behind the scene.
+    pub background: CameraBackground,
+    /// Material channel to visualize. [`DebugView::Overdraw`] is best viewed
+    /// with a black clear color.
+    pub debug_view: DebugView,
+    /// Draw the shadow atlas into the bottom left corner of the screen.
+    pub shadow_atlas_overlay: bool,
+    /// Tint each tile of the screen by its number of point lights, see
+    /// [`LightTilesDebugRoutine`]. Needs a
+    /// [`BaseRenderGraphRoutines::tiled_lighting`].
+    pub light_tiles_overlay: bool,
+    /// Debug geometry to draw this frame.
+    pub debug_draw: DebugDraw,
+    /// Draw gizmos for all lights, see [`DebugDraw::lights`].
+    pub light_gizmos: bool,
+    /// Draw the skeletons of skinned objects, see [`DebugDraw::skeletons`].
+    pub skeleton_gizmos: SkeletonGizmos,
+    /// How draws in the PBR passes are ordered.
+    pub draw_sort_policy: DrawSortPolicy,
+    /// Multiplier of the diffuse light from the skybox, see
+    /// [`SkyboxRoutine::skylight`](crate::skybox::SkyboxRoutine::skylight).
+    /// Zero disables it.
+    pub skylight_intensity: f32,
+    /// Spread the frustum culling of the forward passes over multiple threads,
+    /// see [`culling`](crate::culling).
+    pub parallel_culling: bool,
+    /// Shade opaque and cutout objects from a G-buffer, see
+    /// [`deferred`](crate::deferred). Ignored with multisampling and debug
+    /// views, which are always shaded forward.
+    pub deferred: bool,
+    /// Distance from the camera splitting the scene into two depth
+    /// partitions, so scenes spanning huge depth ranges don't z-fight. See
+    /// [`depth_partition`](crate::depth_partition). Ignored with debug views.
+    pub depth_partition: Option<f32>,
+}
+
+impl BaseRenderGraphSettings {
+    /// What the hdr buffer is cleared to. Transparent when compositing, so
+    /// only drawn pixels cover the output.
+    fn hdr_clear_color(&self) -> Vec4 {
+        match self.background {
+            CameraBackground::Composite => Vec4::ZERO,
+            CameraBackground::Skybox | CameraBackground::ClearColor => self.clear_color,
+        }
+    }
 }
 
 /// Starter RenderGraph.
@@ -101,7 +227,15 @@ pub struct BaseRenderGraphSettings {
 pub struct BaseRenderGraph {
     pub interfaces: common::WholeFrameInterfaces,
     pub samplers: common::Samplers,
-    pub gpu_skinner: skinning::GpuSkinner,
+    pub brdf_lut: common::BrdfLut,
+    pub noise: common::NoiseTextures,
+    /// `None` in the CpuDriven profile, which skins on the CPU.
+    pub gpu_skinner: Option<skinning::GpuSkinner>,
+    pub shadow_atlas_debug: ShadowAtlasDebugRoutine,
+    pub light_tiles_debug: LightTilesDebugRoutine,
+    pub debug_draw: DebugDrawRoutine,
+    pub deferred_lighting: DeferredLightingRoutine,
+    pub depth_resolve: DepthResolveRoutine,
 }
 
 impl BaseRenderGraph {
@@ -112,11 +246,37 @@ impl BaseRenderGraph {
 
         let samplers = common::Samplers::new(&renderer.device);
 
+        let brdf_lut = common::BrdfLut::new(renderer, spp);
+
+        let noise = common::NoiseTextures::new(renderer);
+
         // TODO: Support more materials
 
-        let gpu_skinner = skinning::GpuSkinner::new(&renderer.device, spp);
+        let gpu_skinner =
+            (renderer.profile == RendererProfile::GpuDriven).then(|| skinning::GpuSkinner::new(&renderer.device, spp));
+
+        let shadow_atlas_debug = ShadowAtlasDebugRoutine::new(renderer, spp, &interfaces, TextureFormat::Rgba16Float);
+
+        let light_tiles_debug = LightTilesDebugRoutine::new(renderer, spp, &interfaces, TextureFormat::Rgba16Float);
+
+        let debug_draw = DebugDrawRoutine::new(renderer, spp, &interfaces, TextureFormat::Rgba16Float);
+
+        let deferred_lighting = DeferredLightingRoutine::new(renderer, spp, &interfaces);
+
+        let depth_resolve = DepthResolveRoutine::new(renderer, spp);
 
-        Self { interfaces, samplers, gpu_skinner }
+        Self {
+            interfaces,
+            samplers,
+            brdf_lut,
+            noise,
+            gpu_skinner,
+            shadow_atlas_debug,
+            light_tiles_debug,
+            debug_draw,
+            deferred_lighting,
+            depth_resolve,
+        }
     }
 
     /// Add this to the rendergraph. This is the function you should start
@@ -140,11 +300,27 @@ impl BaseRenderGraph {
         // Perform compute based skinning.
         state.skinning(self);
 
+        // Run the user's compute passes writing into the object buffer.
+        state.object_compute();
+
         // Render all the shadows to the shadow map.
+        state.graph.set_group(Some("Shadows"));
         state.pbr_shadow_rendering();
+        state.graph.set_group(None);
 
-        // Do the first pass, rendering the predicted triangles from last frame.
-        state.pbr_render();
+        // Render everything past the depth partition, if asked for.
+        state.far_depth_partition(self);
+
+        // Render the opaque objects, shading them directly or through the G-buffer.
+        if state.use_deferred() {
+            state.pbr_deferred(self);
+        } else {
+            // Cull the lights of each tile against the depth of the opaque objects, if asked for.
+            state.pbr_depth_prepass();
+            state.tiled_light_culling();
+
+            state.pbr_render();
+        }
 
         // Render the skybox.
         state.skybox();
@@ -155,7 +331,31 @@ impl BaseRenderGraph {
         // considered "residual".
         state.pbr_forward_rendering_transparent();
 
-        // Tonemap the HDR inner buffer to the output buffer.
+        // Simulate, sort and draw the particles.
+        state.particles();
+
+        // Draw the debug geometry.
+        state.debug_draw(self);
+
+        // Make the depth readable by the post processing.
+        state.resolve_depth(self);
+
+        // Add the bloom of emissive materials and the lens flares of bright
+        // pixels and light sources, then run the user's post processing on
+        // the HDR buffer.
+        state.graph.set_group(Some("Post Processing"));
+        state.bloom();
+        state.lens_flare();
+        state.post_process_hdr();
+        state.graph.set_group(None);
+
+        // Draw the shadow atlas and light counts on top of the scene, if
+        // asked for.
+        state.light_tiles_overlay(self);
+        state.shadow_atlas_overlay(self);
+
+        // Tonemap the HDR inner buffer to the output buffer, through the
+        // user's post processing of the tonemapped image.
         state.tonemapping();
     }
 }
@@ -172,19 +372,41 @@ pub struct BaseRenderGraphIntermediateState<'a, 'node> {
     pub shadow_uniform_bg: DataHandle<BindGroup>,
     pub forward_uniform_bg: DataHandle<BindGroup>,
 
+    /// Resolution of [`OutputRenderTarget::handle`]. `inputs.target.resolution`
+    /// holds the resolution the scene is rendered at, see
+    /// [`OutputRenderTarget::render_scale`].
+    pub output_resolution: UVec2,
+
     pub shadow: RenderTargetHandle,
     pub depth: DepthTargets,
     pub primary_renderpass: RenderPassTargets,
+    /// Written by the opaque passes of the viewport, see
+    /// [`velocity`](crate::velocity).
+    pub velocity: VelocityTargets,
+    /// The G-buffer of the opaque objects, with
+    /// [`BaseRenderGraphSettings::deferred`]. Unlit objects write their
+    /// normals too.
+    pub gbuffer: Option<GBufferTargets>,
+    /// Written by the opaque passes of the viewport with the emission of the
+    /// materials flagged to bloom, with a [`BaseRenderGraphRoutines::bloom`],
+    /// see [`bloom`](crate::bloom).
+    pub bloom_emission: Option<graph::RenderPassTarget>,
 
     pub pre_skinning_buffers: DataHandle<skinning::PreSkinningBuffers>,
+    /// Point light tiles, with a [`BaseRenderGraphRoutines::tiled_lighting`].
+    pub light_tiles: Option<DataHandle<Buffer>>,
 }
 impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
     /// Create the default setting for all state.
     pub fn new(
         graph: &'a mut RenderGraph<'node>,
-        inputs: BaseRenderGraphInputs<'a, 'node>,
+        mut inputs: BaseRenderGraphInputs<'a, 'node>,
         settings: BaseRenderGraphSettings,
     ) -> Self {
+        // Everything up to tonemapping works at the scaled resolution.
+        let output_resolution = inputs.target.resolution;
+        inputs.target.resolution = inputs.target.render_resolution();
+
         // Create global bind group information
         let shadow_uniform_bg = graph.add_data::<BindGroup>();
         let forward_uniform_bg = graph.add_data::<BindGroup>();
@@ -222,17 +444,31 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             })
         });
         let depth = DepthTargets::new(graph, inputs.target.resolution, inputs.target.samples);
+        let velocity = VelocityTargets::new(graph, inputs.target.resolution, inputs.target.samples);
+        let bloom_emission = (inputs.routines.bloom.is_some() && settings.debug_view == DebugView::None)
+            .then(|| bloom::emission_target(graph, inputs.target.resolution, inputs.target.samples));
         let primary_renderpass = graph::RenderPassTargets {
-            targets: vec![graph::RenderPassTarget { color, resolve, clear: settings.clear_color }],
+            targets: vec![graph::RenderPassTarget {
+                color,
+                resolve,
+                clear: settings.hdr_clear_color(),
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
+            }],
             depth_stencil: Some(graph::RenderPassDepthTarget {
                 target: depth.rendering_target(),
                 depth_clear: Some(0.0),
                 stencil_clear: None,
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
             }),
         };
 
         let pre_skinning_buffers = graph.add_data::<skinning::PreSkinningBuffers>();
 
+        let light_tiles = inputs
+            .routines
+            .tiled_lighting
+            .map(|tiled_lighting| tiled_lighting.add_tiles_to_graph(graph, inputs.target.resolution));
+
         Self {
             graph,
             inputs,
@@ -241,11 +477,17 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             shadow_uniform_bg,
             forward_uniform_bg,
 
+            output_resolution,
+
             shadow,
             depth,
             primary_renderpass,
+            velocity,
+            gbuffer: None,
+            bloom_emission,
 
             pre_skinning_buffers,
+            light_tiles,
         }
     }
 
@@ -254,6 +496,16 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         clear::add_depth_clear_to_graph(self.graph, self.shadow, 0.0);
     }
 
+    /// The skylight coefficients of the skybox, scaled by the skylight
+    /// intensity.
+    fn skylight(&self) -> [Vec4; 9] {
+        let intensity = self.settings.skylight_intensity;
+        match self.inputs.routines.skybox.and_then(|skybox| skybox.skylight()) {
+            Some(sh) if intensity != 0.0 => sh.map(|c| c * intensity),
+            _ => [Vec4::ZERO; 9],
+        }
+    }
+
     /// Create all the uniforms all the shaders in this graph need.
     pub fn create_frame_uniforms(&mut self, base: &'node BaseRenderGraph) {
         uniforms::add_to_graph(
@@ -263,29 +515,71 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                 interfaces: &base.interfaces,
                 shadow_uniform_bg: self.shadow_uniform_bg,
                 forward_uniform_bg: self.forward_uniform_bg,
+                light_tiles: self.light_tiles,
             },
             uniforms::UniformInformation {
                 samplers: &base.samplers,
+                brdf_lut: &base.brdf_lut,
+                noise: &base.noise,
                 ambient: self.settings.ambient_color,
                 resolution: self.inputs.target.resolution,
+                debug_view: self.settings.debug_view,
+                skylight: self.skylight(),
             },
         );
     }
 
+    /// Skin all skinned meshes, on the GPU or, in the CpuDriven profile, on the
+    /// CPU.
+    ///
+    /// GPU skinning is recorded into the prologue command buffer of the graph.
+    /// wgpu exposes a single queue, so it can't overlap with the shadow passes
+    /// that follow it.
     pub fn skinning(&mut self, base: &'node BaseRenderGraph) {
-        skinning::add_skinning_to_graph(self.graph, &base.gpu_skinner);
+        match base.gpu_skinner {
+            Some(ref gpu_skinner) => skinning::add_skinning_to_graph(self.graph, gpu_skinner),
+            None => skinning::add_cpu_skinning_to_graph(self.graph),
+        }
+    }
+
+    /// Run the passes of [`BaseRenderGraphRoutines::object_compute`].
+    ///
+    /// Every pass drawing objects is added after them, so they see the
+    /// transforms written by the passes.
+    pub fn object_compute(&mut self) {
+        for pass in self.inputs.routines.object_compute {
+            pass.add_to_graph(self.graph);
+        }
     }
 
     /// Render all shadows for the PBR materials.
+    ///
+    /// With a [`BaseRenderGraphRoutines::shadow_cache`], static objects are
+    /// only rendered when the cache is out of date.
     pub fn pbr_shadow_rendering(&mut self) {
+        let Some(cache) = self.inputs.routines.shadow_cache else {
+            self.pbr_shadow_passes(self.shadow, ObjectFilter::All, "pbr shadow renderering");
+            return;
+        };
+
+        let cache_target = cache.add_target_to_graph(self.graph);
+        if cache.needs_update() {
+            self.pbr_shadow_passes(cache_target, ObjectFilter::Static, "pbr static shadow renderering");
+        }
+        cache.add_copy_to_graph(self.graph, cache_target, self.shadow);
+        self.pbr_shadow_passes(self.shadow, ObjectFilter::Dynamic, "pbr dynamic shadow renderering");
+    }
+
+    fn pbr_shadow_passes(&mut self, atlas: RenderTargetHandle, object_filter: ObjectFilter, label: &str) {
         for (shadow_index, desc) in self.inputs.eval_output.shadows.iter().enumerate() {
-            let target = self.shadow.set_viewport(ViewportRect::new(desc.map.offset, UVec2::splat(desc.map.size)));
+            let target = atlas.set_viewport(ViewportRect::new(desc.map.offset, UVec2::splat(desc.map.size)));
             let renderpass = graph::RenderPassTargets {
                 targets: vec![],
                 depth_stencil: Some(graph::RenderPassDepthTarget {
                     target,
                     depth_clear: Some(0.0),
                     stencil_clear: None,
+                    load: graph::RenderPassLoadOp::ClearOnFirstUse,
                 }),
             };
 
@@ -293,7 +587,7 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             for routine in routines {
                 routine.add_forward_to_graph(ForwardRoutineArgs {
                     graph: self.graph,
-                    label: &format!("pbr shadow renderering S{shadow_index}"),
+                    label: &format!("{label} S{shadow_index}"),
                     camera: CameraSpecifier::Shadow(shadow_index as u32),
                     binding_data: forward::ForwardRoutineBindingData {
                         whole_frame_uniform_bg: self.shadow_uniform_bg,
@@ -302,13 +596,233 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                     },
                     samples: SampleCount::One,
                     renderpass: renderpass.clone(),
+                    sort_policy: self.settings.draw_sort_policy,
+                    object_filter,
+                    parallel_culling: self.settings.parallel_culling,
                 });
             }
         }
     }
 
-    /// Render the skybox.
+    /// Render the reflection of the scene in `plane` into the texture of
+    /// `reflection`.
+    ///
+    /// Must be called after [`Self::pbr_shadow_rendering`], as the reflection
+    /// uses the same shadow maps.
+    pub fn planar_reflection(
+        &mut self,
+        base: &'node BaseRenderGraph,
+        reflection: &'node PlanarReflectionRoutine,
+        plane: Plane,
+    ) {
+        let camera = self.graph.add_data::<CameraState>();
+        reflection.add_camera_to_graph(self.graph, plane, camera);
+
+        let shadow_uniform_bg = self.graph.add_data::<BindGroup>();
+        let forward_uniform_bg = self.graph.add_data::<BindGroup>();
+        uniforms::add_to_graph_with_camera(
+            self.graph,
+            self.shadow,
+            uniforms::UniformBindingHandles {
+                interfaces: &base.interfaces,
+                shadow_uniform_bg,
+                forward_uniform_bg,
+                light_tiles: None,
+            },
+            uniforms::UniformInformation {
+                samplers: &base.samplers,
+                brdf_lut: &base.brdf_lut,
+                noise: &base.noise,
+                ambient: self.settings.ambient_color,
+                resolution: reflection.resolution(),
+                debug_view: DebugView::None,
+                skylight: self.skylight(),
+            },
+            camera,
+        );
+
+        let color = self.graph.add_render_target(RenderTargetDescriptor {
+            label: Some("planar reflection color".into()),
+            resolution: reflection.resolution(),
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: REFLECTION_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let depth = self.graph.add_render_target(RenderTargetDescriptor {
+            label: Some("planar reflection depth".into()),
+            resolution: reflection.resolution(),
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        });
+        let renderpass = graph::RenderPassTargets {
+            targets: vec![graph::RenderPassTarget {
+                color,
+                resolve: None,
+                clear: self.settings.clear_color,
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
+            }],
+            depth_stencil: Some(graph::RenderPassDepthTarget {
+                target: depth,
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
+            }),
+        };
+
+        let pbr = self.inputs.routines.pbr;
+        for routine in [&pbr.opaque_routine, &pbr.cutout_routine] {
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR Planar Reflection",
+                camera: CameraSpecifier::Custom(camera),
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: forward_uniform_bg,
+                    per_material_bgl: &pbr.per_material,
+                    extra_bgs: None,
+                },
+                samples: SampleCount::One,
+                renderpass: renderpass.clone(),
+                sort_policy: self.settings.draw_sort_policy,
+                object_filter: ObjectFilter::All,
+                parallel_culling: self.settings.parallel_culling,
+            });
+        }
+        if let Some(skybox) = self.inputs.routines.skybox {
+            skybox.add_to_graph(self.graph, renderpass.clone(), forward_uniform_bg, SampleCount::One);
+        }
+        pbr.blend_routine.add_forward_to_graph(ForwardRoutineArgs {
+            graph: self.graph,
+            label: "PBR Planar Reflection Transparent",
+            camera: CameraSpecifier::Custom(camera),
+            binding_data: forward::ForwardRoutineBindingData {
+                whole_frame_uniform_bg: forward_uniform_bg,
+                per_material_bgl: &pbr.per_material,
+                extra_bgs: None,
+            },
+            samples: SampleCount::One,
+            renderpass,
+            sort_policy: self.settings.draw_sort_policy,
+            object_filter: ObjectFilter::All,
+            parallel_culling: self.settings.parallel_culling,
+        });
+
+        reflection.add_output_to_graph(self.graph, color, forward_uniform_bg);
+    }
+
+    /// Render everything past [`BaseRenderGraphSettings::depth_partition`]
+    /// into the hdr buffer, then clip the depth of the viewport to what is
+    /// nearer.
+    ///
+    /// Must be called after [`Self::pbr_shadow_rendering`], and before any
+    /// other pass using the depth buffer.
+    pub fn far_depth_partition(&mut self, base: &'node BaseRenderGraph) {
+        let Some(split) = self.settings.depth_partition else {
+            return;
+        };
+        if self.settings.debug_view != DebugView::None {
+            return;
+        }
+
+        let camera = self.graph.add_data::<CameraState>();
+        depth_partition::add_far_camera_to_graph(self.graph, split, camera);
+
+        let shadow_uniform_bg = self.graph.add_data::<BindGroup>();
+        let forward_uniform_bg = self.graph.add_data::<BindGroup>();
+        uniforms::add_to_graph_with_camera(
+            self.graph,
+            self.shadow,
+            uniforms::UniformBindingHandles {
+                interfaces: &base.interfaces,
+                shadow_uniform_bg,
+                forward_uniform_bg,
+                light_tiles: None,
+            },
+            uniforms::UniformInformation {
+                samplers: &base.samplers,
+                brdf_lut: &base.brdf_lut,
+                noise: &base.noise,
+                ambient: self.settings.ambient_color,
+                resolution: self.inputs.target.resolution,
+                debug_view: DebugView::None,
+                skylight: self.skylight(),
+            },
+            camera,
+        );
+
+        let depth = self.graph.add_render_target(RenderTargetDescriptor {
+            label: Some("far depth partition depth".into()),
+            resolution: self.inputs.target.resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: self.inputs.target.samples,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        });
+        let renderpass = graph::RenderPassTargets {
+            targets: self.primary_renderpass.targets.clone(),
+            depth_stencil: Some(graph::RenderPassDepthTarget {
+                target: depth,
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
+            }),
+        };
+        let mut velocity_renderpass = renderpass.clone();
+        velocity_renderpass.targets.push(self.velocity.renderpass_target());
+
+        let pbr = self.inputs.routines.pbr;
+        for routine in self.viewport_opaque_routines(&mut velocity_renderpass) {
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR Far Depth Partition",
+                camera: CameraSpecifier::Custom(camera),
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: forward_uniform_bg,
+                    per_material_bgl: &pbr.per_material,
+                    extra_bgs: None,
+                },
+                samples: self.inputs.target.samples,
+                renderpass: velocity_renderpass.clone(),
+                sort_policy: self.settings.draw_sort_policy,
+                object_filter: ObjectFilter::All,
+                parallel_culling: self.settings.parallel_culling,
+            });
+        }
+        if let Some(skybox) = self.inputs.routines.skybox {
+            if self.settings.background == CameraBackground::Skybox {
+                skybox.add_to_graph(self.graph, renderpass.clone(), forward_uniform_bg, self.inputs.target.samples);
+            }
+        }
+        pbr.blend_routine.add_forward_to_graph(ForwardRoutineArgs {
+            graph: self.graph,
+            label: "PBR Far Depth Partition Transparent",
+            camera: CameraSpecifier::Custom(camera),
+            binding_data: forward::ForwardRoutineBindingData {
+                whole_frame_uniform_bg: forward_uniform_bg,
+                per_material_bgl: &pbr.per_material,
+                extra_bgs: None,
+            },
+            samples: self.inputs.target.samples,
+            renderpass,
+            sort_policy: self.settings.draw_sort_policy,
+            object_filter: ObjectFilter::All,
+            parallel_culling: self.settings.parallel_culling,
+        });
+
+        depth_partition::add_near_clip_to_graph(self.graph, split, self.depth.rendering_target());
+    }
+
+    /// Render the skybox, if [`BaseRenderGraphSettings::background`] asks
+    /// for it.
     pub fn skybox(&mut self) {
+        if self.settings.debug_view != DebugView::None || self.settings.background != CameraBackground::Skybox {
+            return;
+        }
         if let Some(skybox) = self.inputs.routines.skybox {
             skybox.add_to_graph(
                 self.graph,
@@ -319,9 +833,16 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         }
     }
 
-    /// Render the PBR materials.
+    /// Render the PBR materials, writing the velocity of the opaque ones.
     pub fn pbr_render(&mut self) {
-        let routines = [&self.inputs.routines.pbr.opaque_routine, &self.inputs.routines.pbr.cutout_routine];
+        let pbr = self.inputs.routines.pbr;
+        let mut renderpass = self.primary_renderpass.clone();
+        let routines: Vec<&ForwardRoutine<PbrMaterial>> = if self.settings.debug_view == DebugView::Overdraw {
+            pbr.overdraw_routines.iter().collect()
+        } else {
+            renderpass.targets.push(self.velocity.renderpass_target());
+            self.viewport_opaque_routines(&mut renderpass).to_vec()
+        };
         for routine in routines {
             routine.add_forward_to_graph(ForwardRoutineArgs {
                 graph: self.graph,
@@ -333,13 +854,127 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                     extra_bgs: None,
                 },
                 samples: self.inputs.target.samples,
-                renderpass: self.primary_renderpass.clone(),
+                renderpass: renderpass.clone(),
+                sort_policy: self.settings.draw_sort_policy,
+                object_filter: ObjectFilter::All,
+                parallel_culling: self.settings.parallel_culling,
+            });
+        }
+    }
+
+    /// The forward routines of the opaque PBR materials of the viewport,
+    /// which write velocity, adding [`Self::bloom_emission`] to the
+    /// renderpass if there is one.
+    fn viewport_opaque_routines(&self, renderpass: &mut RenderPassTargets) -> [&'node ForwardRoutine<PbrMaterial>; 2] {
+        let pbr = self.inputs.routines.pbr;
+        match &self.bloom_emission {
+            Some(emission) => {
+                renderpass.targets.push(emission.clone());
+                [&pbr.opaque_bloom, &pbr.cutout_bloom]
+            }
+            None => [&pbr.opaque_velocity, &pbr.cutout_velocity],
+        }
+    }
+
+    /// Whether [`BaseRenderGraphSettings::deferred`] is set and can be used.
+    pub fn use_deferred(&self) -> bool {
+        self.settings.deferred
+            && self.settings.debug_view == DebugView::None
+            && self.inputs.target.samples == SampleCount::One
+    }
+
+    /// Render the opaque PBR materials into a G-buffer, then shade it into
+    /// the primary renderpass.
+    pub fn pbr_deferred(&mut self, base: &'node BaseRenderGraph) {
+        let pbr = self.inputs.routines.pbr;
+        let gbuffer = GBufferTargets::new(self.graph, self.inputs.target.resolution);
+        let mut renderpass = gbuffer.renderpass(&self.depth, &self.velocity);
+        let routines = match &self.bloom_emission {
+            Some(emission) => {
+                renderpass.targets.push(emission.clone());
+                [&pbr.opaque_gbuffer_bloom, &pbr.cutout_gbuffer_bloom]
+            }
+            None => [&pbr.opaque_gbuffer, &pbr.cutout_gbuffer],
+        };
+        for routine in routines {
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR G-Buffer Pass",
+                camera: CameraSpecifier::Viewport,
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: self.forward_uniform_bg,
+                    per_material_bgl: &pbr.per_material,
+                    extra_bgs: None,
+                },
+                samples: SampleCount::One,
+                renderpass: renderpass.clone(),
+                sort_policy: self.settings.draw_sort_policy,
+                object_filter: ObjectFilter::All,
+                parallel_culling: self.settings.parallel_culling,
+            });
+        }
+        self.tiled_light_culling();
+        base.deferred_lighting.add_to_graph(
+            self.graph,
+            gbuffer,
+            self.depth.single_sample_mipped,
+            self.primary_renderpass.targets[0].clone(),
+            self.forward_uniform_bg,
+        );
+        self.gbuffer = Some(gbuffer);
+    }
+
+    /// Render the depth of the opaque PBR materials before they are shaded,
+    /// if there is a [`BaseRenderGraphRoutines::tiled_lighting`].
+    pub fn pbr_depth_prepass(&mut self) {
+        if self.light_tiles.is_none() {
+            return;
+        }
+        let pbr = self.inputs.routines.pbr;
+        for routine in [&pbr.opaque_prepass, &pbr.cutout_prepass] {
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR Depth Prepass",
+                camera: CameraSpecifier::Viewport,
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: self.shadow_uniform_bg,
+                    per_material_bgl: &pbr.per_material,
+                    extra_bgs: None,
+                },
+                samples: self.inputs.target.samples,
+                renderpass: RenderPassTargets {
+                    targets: vec![],
+                    depth_stencil: self.primary_renderpass.depth_stencil.clone(),
+                },
+                sort_policy: self.settings.draw_sort_policy,
+                object_filter: ObjectFilter::All,
+                parallel_culling: self.settings.parallel_culling,
             });
         }
     }
 
+    /// Cull the point lights of each tile against the depth rendered so far,
+    /// if there is a [`BaseRenderGraphRoutines::tiled_lighting`].
+    pub fn tiled_light_culling(&mut self) {
+        let (Some(tiled_lighting), Some(light_tiles)) = (self.inputs.routines.tiled_lighting, self.light_tiles) else {
+            return;
+        };
+        tiled_lighting.add_culling_to_graph(
+            self.graph,
+            light_tiles,
+            self.depth.rendering_target(),
+            self.inputs.target.samples,
+            self.inputs.target.resolution,
+            self.forward_uniform_bg,
+        );
+    }
+
     /// Render the PBR materials.
     pub fn pbr_forward_rendering_transparent(&mut self) {
+        // Transparent objects are drawn by the overdraw routines in pbr_render.
+        if self.settings.debug_view == DebugView::Overdraw {
+            return;
+        }
         self.inputs.routines.pbr.blend_routine.add_forward_to_graph(ForwardRoutineArgs {
             graph: self.graph,
             label: "PBR Forward Transparent",
@@ -351,16 +986,265 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             },
             samples: self.inputs.target.samples,
             renderpass: self.primary_renderpass.clone(),
+            sort_policy: self.settings.draw_sort_policy,
+            object_filter: ObjectFilter::All,
+            parallel_culling: self.settings.parallel_culling,
         });
     }
 
-    /// Tonemap onto the given render target.
-    pub fn tonemapping(&mut self) {
-        self.inputs.routines.tonemapping.add_to_graph(
+    /// Simulate and draw the particles of [`BaseRenderGraphRoutines::particles`],
+    /// colliding with the depth of the scene.
+    pub fn particles(&mut self) {
+        for particles in self.inputs.routines.particles {
+            particles.add_to_graph(
+                self.graph,
+                self.depth.rendering_target(),
+                self.inputs.target.samples,
+                self.inputs.target.resolution,
+                self.primary_renderpass.clone(),
+            );
+        }
+    }
+
+    /// Draw the contents of [`BaseRenderGraphSettings::debug_draw`], light
+    /// gizmos if [`BaseRenderGraphSettings::light_gizmos`] is set, and the
+    /// skeletons in [`BaseRenderGraphSettings::skeleton_gizmos`].
+    pub fn debug_draw(&mut self, base: &'node BaseRenderGraph) {
+        base.debug_draw.add_to_graph(
             self.graph,
+            &mut self.settings.debug_draw,
+            self.primary_renderpass.clone(),
+            self.forward_uniform_bg,
+            self.inputs.target.samples,
+        );
+        if self.settings.light_gizmos {
+            base.debug_draw.add_light_gizmos_to_graph(
+                self.graph,
+                self.primary_renderpass.clone(),
+                self.forward_uniform_bg,
+                self.inputs.target.samples,
+            );
+        }
+        base.debug_draw.add_skeleton_gizmos_to_graph(
+            self.graph,
+            mem::take(&mut self.settings.skeleton_gizmos),
+            self.primary_renderpass.clone(),
+            self.forward_uniform_bg,
+            self.inputs.target.samples,
+        );
+    }
+
+    /// Draw the shadow atlas into the bottom left corner of the hdr buffer,
+    /// if [`BaseRenderGraphSettings::shadow_atlas_overlay`] is set.
+    pub fn shadow_atlas_overlay(&mut self, base: &'node BaseRenderGraph) {
+        if !self.settings.shadow_atlas_overlay {
+            return;
+        }
+        let rect = ShadowAtlasDebugRoutine::overlay_rect(
+            self.inputs.target.resolution,
+            self.inputs.eval_output.shadow_target_size,
+        );
+        base.shadow_atlas_debug.add_to_graph(
+            self.graph,
+            self.shadow,
+            self.primary_renderpass.resolved_color(0),
+            rect,
+            self.forward_uniform_bg,
+        );
+    }
+
+    /// Tint the hdr buffer by the number of point lights in each tile, if
+    /// [`BaseRenderGraphSettings::light_tiles_overlay`] is set and there is a
+    /// [`BaseRenderGraphRoutines::tiled_lighting`].
+    pub fn light_tiles_overlay(&mut self, base: &'node BaseRenderGraph) {
+        let Some(light_tiles) = self.light_tiles.filter(|_| self.settings.light_tiles_overlay) else {
+            return;
+        };
+        base.light_tiles_debug.add_to_graph(
+            self.graph,
+            light_tiles,
             self.primary_renderpass.resolved_color(0),
-            self.inputs.target.handle,
             self.forward_uniform_bg,
         );
     }
+
+    /// Upscale the hdr buffer to the output resolution of `routine`, which
+    /// becomes the hdr buffer. Must be called right before tonemapping.
+    ///
+    /// The scene is rendered at [`OutputRenderTarget::render_resolution`],
+    /// which should be the render resolution given to
+    /// [`TemporalUpscaleRoutine::prepare`], without multisampling.
+    pub fn temporal_upscale(
+        &mut self,
+        routine: &'node TemporalUpscaleRoutine,
+        reactive_mask: Option<RenderTargetHandle>,
+    ) {
+        let output = self.graph.add_render_target(RenderTargetDescriptor {
+            label: Some("hdr upscaled".into()),
+            resolution: routine.output_resolution(),
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: TEMPORAL_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        routine.add_to_graph(
+            self.graph,
+            TemporalUpscaleTargets {
+                color: self.primary_renderpass.resolved_color(0),
+                depth: self.depth.readable(),
+                velocity: Some(self.velocity.single_sample),
+                reactive_mask,
+                output,
+            },
+        );
+        self.primary_renderpass = graph::RenderPassTargets {
+            targets: vec![graph::RenderPassTarget {
+                color: output,
+                resolve: None,
+                clear: self.settings.clear_color,
+                load: graph::RenderPassLoadOp::ClearOnFirstUse,
+            }],
+            depth_stencil: None,
+        };
+        // Everything after this works on the upscaled image.
+        self.inputs.target.resolution = routine.output_resolution();
+    }
+
+    /// Resolve the multisampled depth into [`DepthTargets::readable`]. Does
+    /// nothing without multisampling.
+    pub fn resolve_depth(&mut self, base: &'node BaseRenderGraph) {
+        base.depth_resolve.add_to_graph(self.graph, self.depth);
+    }
+
+    /// Add the bloom of [`BaseRenderGraphRoutines::bloom`] to the hdr buffer,
+    /// blurring [`Self::bloom_emission`].
+    pub fn bloom(&mut self) {
+        let (Some(bloom), Some(emission)) = (self.inputs.routines.bloom, &self.bloom_emission) else {
+            return;
+        };
+        let emission = emission.resolve.unwrap_or(emission.color);
+        let color = self.primary_renderpass.resolved_color(0);
+        bloom.add_to_graph(self.graph, emission, color, self.inputs.target.resolution, self.forward_uniform_bg);
+        // Later passes draw over the resolved image, so resolving again doesn't lose the bloom.
+        if color != self.primary_renderpass.targets[0].color {
+            self.primary_renderpass = graph::RenderPassTargets {
+                targets: vec![graph::RenderPassTarget {
+                    color,
+                    resolve: None,
+                    clear: self.settings.clear_color,
+                    load: graph::RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            };
+        }
+    }
+
+    /// Add the lens flares of [`BaseRenderGraphRoutines::lens_flare`] to the
+    /// hdr buffer. Needs the depth to be readable, see [`Self::resolve_depth`].
+    pub fn lens_flare(&mut self) {
+        let Some(lens_flare) = self.inputs.routines.lens_flare else {
+            return;
+        };
+        let color = lens_flare.add_to_graph(
+            self.graph,
+            self.primary_renderpass.resolved_color(0),
+            self.depth.readable(),
+            self.inputs.target.resolution,
+            self.forward_uniform_bg,
+        );
+        if color != self.primary_renderpass.resolved_color(0) {
+            self.primary_renderpass = graph::RenderPassTargets {
+                targets: vec![graph::RenderPassTarget {
+                    color,
+                    resolve: None,
+                    clear: self.settings.clear_color,
+                    load: graph::RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            };
+        }
+    }
+
+    /// Run the [`PostProcessOutput::AfterTransparency`] passes, each of which
+    /// replaces the hdr buffer.
+    pub fn post_process_hdr(&mut self) {
+        let passes = self.inputs.routines.post_process;
+        for pass in passes.iter().filter(|pass| pass.output() == PostProcessOutput::AfterTransparency) {
+            let dst = self.graph.add_render_target(RenderTargetDescriptor {
+                label: Some("hdr post processed".into()),
+                resolution: self.inputs.target.resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            });
+            pass.add_to_graph(
+                self.graph,
+                self.primary_renderpass.resolved_color(0),
+                self.depth.readable(),
+                dst,
+                self.forward_uniform_bg,
+            );
+            self.primary_renderpass = graph::RenderPassTargets {
+                targets: vec![graph::RenderPassTarget {
+                    color: dst,
+                    resolve: None,
+                    clear: self.settings.clear_color,
+                    load: graph::RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            };
+        }
+    }
+
+    /// Tonemap onto the given render target, then run the
+    /// [`PostProcessOutput::AfterTonemapping`] passes on it. Tonemapping
+    /// stretches the hdr buffer over the output, from the render resolution to
+    /// [`Self::output_resolution`].
+    pub fn tonemapping(&mut self) {
+        let passes: Vec<_> = self
+            .inputs
+            .routines
+            .post_process
+            .iter()
+            .copied()
+            .filter(|pass| matches!(pass.output(), PostProcessOutput::AfterTonemapping(_)))
+            .collect();
+
+        // Every pass but the last writes into an intermediate target.
+        let intermediate = |graph: &mut RenderGraph<'node>, pass: &PostProcessPass| {
+            graph.add_render_target(RenderTargetDescriptor {
+                label: Some("tonemapped".into()),
+                resolution: self.output_resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format: pass.output().format(),
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            })
+        };
+
+        let mut dst = match passes.first() {
+            Some(pass) => intermediate(self.graph, pass),
+            None => self.inputs.target.handle,
+        };
+        let tonemapping = self.inputs.routines.tonemapping;
+        let src = self.primary_renderpass.resolved_color(0);
+        if self.settings.background == CameraBackground::Composite && passes.is_empty() {
+            tonemapping.add_composite_to_graph(self.graph, src, dst, self.forward_uniform_bg);
+        } else {
+            tonemapping.add_to_graph(self.graph, src, dst, self.forward_uniform_bg);
+        }
+
+        for (i, pass) in passes.iter().enumerate() {
+            let src = dst;
+            dst = match passes.get(i + 1) {
+                Some(next) => intermediate(self.graph, next),
+                None => self.inputs.target.handle,
+            };
+            pass.add_to_graph(self.graph, src, self.depth.readable(), dst, self.forward_uniform_bg);
+        }
+    }
 }
@@ -0,0 +1,331 @@
+//! Bloom of emissive materials.
+//!
+//! Materials with [`PbrMaterial::emissive_bloom`] glow into their
+//! surroundings. With bloom, the opaque passes of the viewport use the bloom
+//! routines of [`PbrRoutine`], which also write the emission of the flagged
+//! materials into an extra target from [`emission_target`], and black for
+//! the others. The emission is written along with the color, so the scene
+//! isn't drawn again, and bloom doesn't shine through what covers it.
+//!
+//! A [`BloomRoutine`] then blurs the emission by downsampling it over a chain
+//! of targets of halving resolution and adding each back into the larger one,
+//! and adds the result to the HDR image before post processing.
+//!
+//! Pass the routine to the base render graph through
+//! [`BaseRenderGraphRoutines::bloom`](crate::base::BaseRenderGraphRoutines::bloom).
+//!
+//! [`PbrMaterial::emissive_bloom`]: crate::pbr::PbrMaterial::emissive_bloom
+//! [`PbrRoutine`]: crate::pbr::PbrRoutine
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer,
+    BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat,
+    TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Format of the emission and of the blurred targets.
+pub const BLOOM_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Look of the bloom of a [`BloomRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+    /// Multiplier of the blurred emission added to the image.
+    pub intensity: f32,
+    /// Targets of halving resolution the emission is blurred over. More
+    /// levels spread the bloom further.
+    pub levels: u32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self { intensity: 0.2, levels: 5 }
+    }
+}
+
+#[derive(ShaderType)]
+struct BloomUniforms {
+    intensity: f32,
+}
+
+/// Target the bloom routines of [`PbrRoutine`](crate::pbr::PbrRoutine) write
+/// the emission into, resolved with multisampling. The emission to blur is
+/// the resolve target if there is one, the color target otherwise.
+pub fn emission_target(graph: &mut RenderGraph<'_>, resolution: UVec2, samples: SampleCount) -> RenderPassTarget {
+    let single_sample = graph.add_render_target(RenderTargetDescriptor {
+        label: Some("bloom emission".into()),
+        resolution,
+        depth: 1,
+        mip_levels: Some(1),
+        samples: SampleCount::One,
+        format: BLOOM_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+    });
+    let multi_sample = samples.needs_resolve().then(|| {
+        graph.add_render_target(RenderTargetDescriptor {
+            label: Some("bloom emission multisampled".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples,
+            format: BLOOM_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        })
+    });
+
+    RenderPassTarget {
+        color: multi_sample.unwrap_or(single_sample),
+        resolve: multi_sample.map(|_| single_sample),
+        clear: Vec4::ZERO,
+        load: RenderPassLoadOp::ClearOnFirstUse,
+    }
+}
+
+/// Blurs the emission of materials flagged to bloom and adds it to the image.
+///
+/// See module for documentation.
+pub struct BloomRoutine {
+    settings: BloomSettings,
+
+    uniform_buffer: Buffer,
+    bgl: BindGroupLayout,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+}
+
+impl BloomRoutine {
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        settings: BloomSettings,
+    ) -> Self {
+        profiling::scope!("BloomRoutine::new");
+
+        let uniform_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("bloom uniforms"),
+            size: BloomUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, BloomUniforms::SHADER_SIZE.get())
+            .build(&renderer.device, Some("bloom bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("bloom"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/bloom.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("bloom"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+        // Adds the color, keeping the alpha of the target.
+        let additive = BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::Zero,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        };
+        let create_pipeline =
+            |entry_point: &str, blend: Option<BlendState>| create_pipeline(renderer, &pll, &module, entry_point, blend);
+
+        Self {
+            settings,
+
+            uniform_buffer,
+            downsample_pipeline: create_pipeline("fs_downsample", None),
+            upsample_pipeline: create_pipeline("fs_upsample", Some(additive)),
+            composite_pipeline: create_pipeline("fs_composite", Some(additive)),
+            bgl,
+        }
+    }
+
+    pub fn settings(&self) -> &BloomSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut BloomSettings {
+        &mut self.settings
+    }
+
+    /// Blurs `emission`, which must be single sampled, and adds it to
+    /// `color`, which must be single sampled and [`BLOOM_FORMAT`].
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        emission: RenderTargetHandle,
+        color: RenderTargetHandle,
+        resolution: UVec2,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_upload_to_graph(graph);
+
+        // Stop before the targets shrink to a single pixel.
+        let max_levels = 32 - resolution.max_element().max(1).leading_zeros();
+        let levels: Vec<RenderTargetHandle> = (1..=self.settings.levels.clamp(1, max_levels))
+            .map(|level| {
+                graph.add_render_target(RenderTargetDescriptor {
+                    label: Some(format!("bloom level {level}").into()),
+                    resolution: (resolution >> level).max(UVec2::ONE),
+                    depth: 1,
+                    mip_levels: Some(1),
+                    samples: SampleCount::One,
+                    format: BLOOM_FORMAT,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                })
+            })
+            .collect();
+
+        let mut src = emission;
+        for &dst in &levels {
+            self.add_pass_to_graph(graph, "Bloom Downsample", &self.downsample_pipeline, src, dst, forward_uniform_bg);
+            src = dst;
+        }
+        for pair in levels.windows(2).rev() {
+            self.add_pass_to_graph(
+                graph,
+                "Bloom Upsample",
+                &self.upsample_pipeline,
+                pair[1],
+                pair[0],
+                forward_uniform_bg,
+            );
+        }
+        self.add_pass_to_graph(
+            graph,
+            "Bloom Composite",
+            &self.composite_pipeline,
+            levels[0],
+            color,
+            forward_uniform_bg,
+        );
+    }
+
+    fn add_upload_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>) {
+        let mut builder = graph.add_node("Bloom Upload");
+        builder.add_side_effect();
+
+        builder.build(move |ctx| {
+            let uniforms = BloomUniforms { intensity: self.settings.intensity };
+            let mut data = UniformBuffer::new(Vec::with_capacity(BloomUniforms::SHADER_SIZE.get() as usize));
+            data.write(&uniforms).unwrap();
+            ctx.renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+        });
+    }
+
+    /// Draws `src` into `dst` with a fullscreen pass of the pipeline.
+    fn add_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        pipeline: &'node RenderPipeline,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node(label);
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let src = ctx.graph_data.get_render_target(src_handle);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new().append_texture_view(src).append_buffer(&self.uniform_buffer).build(
+                    &ctx.renderer.device,
+                    Some("bloom bg"),
+                    &self.bgl,
+                ),
+            );
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+fn create_pipeline(
+    renderer: &Renderer,
+    pll: &wgpu::PipelineLayout,
+    module: &ShaderModule,
+    entry_point: &str,
+    blend: Option<BlendState>,
+) -> RenderPipeline {
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("bloom"),
+        layout: Some(pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module,
+            entry_point,
+            targets: &[Some(ColorTargetState { format: BLOOM_FORMAT, blend, write_mask: ColorWrites::all() })],
+        }),
+        multiview: None,
+    })
+}
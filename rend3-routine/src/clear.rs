@@ -1,4 +1,8 @@
-use rend3::graph::{NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTargets, RenderTargetHandle};
+use glam::Vec4;
+use rend3::graph::{
+    NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+    RenderTargetHandle,
+};
 
 /// Due to limitations of how we auto-clear buffers, we need to explicitly clear the shadow depth buffer.
 pub fn add_depth_clear_to_graph(graph: &mut RenderGraph<'_>, depth: RenderTargetHandle, depth_clear: f32) {
@@ -11,6 +15,7 @@ pub fn add_depth_clear_to_graph(graph: &mut RenderGraph<'_>, depth: RenderTarget
                 target: depth,
                 depth_clear: Some(depth_clear),
                 stencil_clear: None,
+                load: RenderPassLoadOp::ClearOnFirstUse,
             }),
         },
         NodeResourceUsage::Output,
@@ -18,3 +23,19 @@ pub fn add_depth_clear_to_graph(graph: &mut RenderGraph<'_>, depth: RenderTarget
 
     builder.build(|_| ())
 }
+
+/// Clears a color target, so nodes that only write parts of it can rely on the
+/// rest being `clear`.
+pub fn add_clear_to_graph(graph: &mut RenderGraph<'_>, color: RenderTargetHandle, clear: Vec4) {
+    let mut builder = graph.add_node("Clear Color");
+
+    let _rpass_handle = builder.add_renderpass(
+        RenderPassTargets {
+            targets: vec![RenderPassTarget { color, clear, resolve: None, load: RenderPassLoadOp::ClearOnFirstUse }],
+            depth_stencil: None,
+        },
+        NodeResourceUsage::Output,
+    );
+
+    builder.build(|_| ())
+}
@@ -0,0 +1,123 @@
+use std::borrow::Cow;
+
+use rend3::{util::bind_merge::BindGroupLayoutBuilder, Renderer, ShaderConfig, ShaderPreProcessor};
+use wgpu::{
+    BindingType, Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, Extent3d, FragmentState, FrontFace,
+    LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, StoreOp, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Format of the [`BrdfLut`].
+pub const BRDF_LUT_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+/// Width and height of the [`BrdfLut`].
+pub const BRDF_LUT_SIZE: u32 = 128;
+
+/// The split-sum environment BRDF, integrated once on creation.
+///
+/// Indexed by n·v along x and perceptual roughness along y, each texel holds
+/// the scale and bias of f0 in the reflectance of prefiltered environment
+/// lighting. It is bound as `brdf_lut` in group 0 of the forward passes, and
+/// sampled with the functions of `rend3-routine/math/brdf_lut.wgsl`. The
+/// built-in lighting uses it for the energy compensation passed to the
+/// single light shading of `rend3-routine/shading.wgsl`, for custom materials
+/// to match.
+pub struct BrdfLut {
+    pub texture: Texture,
+    pub view: TextureView,
+}
+
+impl BrdfLut {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("BrdfLut::new");
+
+        let texture = renderer.device.create_texture(&TextureDescriptor {
+            label: Some("brdf lut"),
+            size: Extent3d { width: BRDF_LUT_SIZE, height: BRDF_LUT_SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: BRDF_LUT_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("brdf lut"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/brdf_lut.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("brdf lut"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("brdf lut"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: BRDF_LUT_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        let mut encoder =
+            renderer.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("brdf lut integration") });
+        {
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("brdf lut"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations { load: LoadOp::Clear(Color::TRANSPARENT), store: StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&pipeline);
+            rpass.draw(0..3, 0..1);
+        }
+        renderer.queue.submit([encoder.finish()]);
+
+        Self { texture, view }
+    }
+
+    /// Add the lookup table to the given bind group layout builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            None,
+        );
+    }
+}
@@ -1,8 +1,13 @@
+use rend3::{graph::DataHandle, managers::CameraState};
+
 /// Specifier representing which camera we're referring to.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum CameraSpecifier {
     Viewport,
     Shadow(u32),
+    /// A camera written to the graph by an earlier node, such as the mirrored
+    /// camera of a [`PlanarReflectionRoutine`](crate::reflection::PlanarReflectionRoutine).
+    Custom(DataHandle<CameraState>),
 }
 
 impl CameraSpecifier {
@@ -22,11 +27,11 @@ impl CameraSpecifier {
         matches!(self, Self::Shadow(..))
     }
 
-    /// Returns a shader compatible index for the camera, using u32::MAX for the viewport camera.
+    /// Returns a shader compatible index for the camera, using u32::MAX for the viewport and custom cameras.
     #[must_use]
     pub fn to_shader_index(&self) -> u32 {
         match *self {
-            Self::Viewport => u32::MAX,
+            Self::Viewport | Self::Custom(..) => u32::MAX,
             Self::Shadow(index) => {
                 assert_ne!(index, u32::MAX, "Shadow camera index cannot be 0xFFFF_FFFF");
                 index
@@ -12,7 +12,7 @@ use wgpu::{
 };
 
 use crate::{
-    common::samplers::Samplers,
+    common::{samplers::Samplers, BrdfLut, NoiseTextures},
     uniforms::{FrameUniforms, PerCameraUniform},
 };
 
@@ -62,6 +62,21 @@ impl WholeFrameInterfaces {
             None,
         );
 
+        // Point light tiles, see crate::tiled_lighting
+        uniform_bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            None,
+        );
+
+        BrdfLut::add_to_bgl(&mut uniform_bglb);
+        NoiseTextures::add_to_bgl(&mut uniform_bglb);
+        DirectionalLightManager::add_projectors_to_bgl(&mut uniform_bglb);
+
         let forward_uniform_bgl = uniform_bglb.build(device, Some("forward uniform bgl"));
 
         Self { depth_uniform_bgl: shadow_uniform_bgl, forward_uniform_bgl }
@@ -133,6 +148,26 @@ impl<M: Material> PerMaterialArchetypeInterface<M> {
                 },
                 None,
             )
+            // Instance buffer
+            .append(
+                ShaderStages::VERTEX_FRAGMENT,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                None,
+            )
+            // Culled instances
+            .append(
+                ShaderStages::VERTEX_FRAGMENT,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                None,
+            )
             .build(device, Some("per material bgl"));
 
         Self { bgl, _phantom: PhantomData }
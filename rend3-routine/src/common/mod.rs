@@ -1,9 +1,13 @@
 //! Common utilities used throughout the crate.
 
+mod brdf_lut;
 mod camera;
 mod interfaces;
+mod noise;
 mod samplers;
 
+pub use brdf_lut::*;
 pub use camera::*;
 pub use interfaces::*;
+pub use noise::*;
 pub use samplers::*;
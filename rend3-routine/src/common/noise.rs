@@ -0,0 +1,294 @@
+use std::sync::OnceLock;
+
+use rend3::{
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer,
+};
+use wgpu::{
+    util::{DeviceExt, TextureDataOrder},
+    BindingType, Extent3d, ShaderStages, Texture, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+/// Width and height of the layers of [`NoiseTextures::blue_noise`].
+pub const BLUE_NOISE_SIZE: u32 = 64;
+/// Number of layers of [`NoiseTextures::blue_noise`].
+pub const BLUE_NOISE_LAYERS: u32 = 16;
+/// Width and height of [`NoiseTextures::bayer`].
+pub const BAYER_SIZE: u32 = 8;
+
+/// Standard deviation of the gaussian measuring how clustered the points of
+/// the blue noise are, in texels.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Noise textures shared by every effect needing high quality noise.
+///
+/// - `blue_noise` is an array of [`BLUE_NOISE_LAYERS`] tileable blue noise
+///   textures of [`BLUE_NOISE_SIZE`]², each texel a threshold in (0, 1).
+///   Every layer is blue noise, and each texel steps through the layers in a
+///   low discrepancy sequence, so indexing the layer with the frame index
+///   gives noise that also converges over time, as SSAO and TAA want.
+/// - `bayer` is the [`BAYER_SIZE`]² ordered dithering matrix, with the same
+///   thresholds.
+///
+/// Both are bound as `blue_noise` and `bayer` in group 0 of the forward
+/// passes, after `brdf_lut`. They are `R8Unorm` and meant to be read with
+/// `textureLoad`, through the functions of `rend3-routine/math/noise.wgsl`.
+pub struct NoiseTextures {
+    pub blue_noise: Texture,
+    pub blue_noise_view: TextureView,
+    pub bayer: Texture,
+    pub bayer_view: TextureView,
+}
+
+impl NoiseTextures {
+    pub fn new(renderer: &Renderer) -> Self {
+        profiling::scope!("NoiseTextures::new");
+
+        let blue_noise = renderer.device.create_texture_with_data(
+            &renderer.queue,
+            &TextureDescriptor {
+                label: Some("blue noise"),
+                size: Extent3d {
+                    width: BLUE_NOISE_SIZE,
+                    height: BLUE_NOISE_SIZE,
+                    depth_or_array_layers: BLUE_NOISE_LAYERS,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            blue_noise_data(),
+        );
+        let blue_noise_view = blue_noise.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..TextureViewDescriptor::default()
+        });
+
+        let bayer = renderer.device.create_texture_with_data(
+            &renderer.queue,
+            &TextureDescriptor {
+                label: Some("bayer matrix"),
+                size: Extent3d { width: BAYER_SIZE, height: BAYER_SIZE, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            &bayer_data(),
+        );
+        let bayer_view = bayer.create_view(&TextureViewDescriptor::default());
+
+        Self { blue_noise, blue_noise_view, bayer, bayer_view }
+    }
+
+    /// Add the noise textures to the given bind group layout builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        for view_dimension in [TextureViewDimension::D2Array, TextureViewDimension::D2] {
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension,
+                    multisampled: false,
+                },
+                None,
+            );
+        }
+    }
+
+    /// Add the noise textures to the given bind group builder.
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_texture_view(&self.blue_noise_view).append_texture_view(&self.bayer_view);
+    }
+}
+
+/// Threshold in (0, 1) of the point with the given rank out of `count`, in
+/// unorm bytes.
+fn rank_to_unorm(rank: usize, count: usize) -> u8 {
+    ((2 * rank + 1) * 128 / count) as u8
+}
+
+/// Thresholds of the ordered dithering matrix, row by row.
+fn bayer_data() -> Vec<u8> {
+    let size = BAYER_SIZE as usize;
+    let bits = BAYER_SIZE.trailing_zeros();
+    (0..size * size)
+        .map(|idx| {
+            let (x, y) = (idx % size, idx / size);
+            // The finest level of the recursive matrix comes from the lowest
+            // bits of the coordinates.
+            let rank = (0..bits).fold(0, |rank, bit| {
+                let (x_bit, y_bit) = ((x >> bit) & 1, (y >> bit) & 1);
+                (rank << 2) | ((x_bit ^ y_bit) << 1) | y_bit
+            });
+            rank_to_unorm(rank, size * size)
+        })
+        .collect()
+}
+
+/// Thresholds of all layers of the blue noise, layer by layer.
+///
+/// Generating the noise takes a moment, so it is only done once.
+fn blue_noise_data() -> &'static [u8] {
+    static DATA: OnceLock<Vec<u8>> = OnceLock::new();
+    DATA.get_or_init(|| {
+        profiling::scope!("generate blue noise");
+
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE as usize);
+        let count = ranks.len() as f32;
+        (0..BLUE_NOISE_LAYERS)
+            .flat_map(|layer| {
+                // Offsetting by the golden ratio keeps every layer blue, while
+                // each texel steps through a low discrepancy sequence.
+                let offset = layer as f32 * 0.618_034;
+                ranks.iter().map(move |&rank| {
+                    let value = ((rank as f32 + 0.5) / count + offset).fract();
+                    (value * 256.0).min(255.0) as u8
+                })
+            })
+            .collect()
+    })
+}
+
+/// Ranks every texel of a tileable `size`² square with Ulichney's
+/// void-and-cluster method, so the texels ranked below any threshold are
+/// evenly spread out.
+fn void_and_cluster(size: usize) -> Vec<usize> {
+    let count = size * size;
+    let sigma_factor = 1.0 / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA);
+    let kernel: Vec<f32> = (0..count)
+        .map(|idx| {
+            let (x, y) = (idx % size, idx / size);
+            let dx = x.min(size - x) as f32;
+            let dy = y.min(size - y) as f32;
+            (-(dx * dx + dy * dy) * sigma_factor).exp()
+        })
+        .collect();
+
+    let mut points = Points { size, kernel, set: vec![false; count], energy: vec![0.0; count] };
+
+    // Start from a tenth of the texels, placed with a fixed xorshift sequence
+    // so the noise is the same every run.
+    let mut state = 0x9E37_79B9_u32;
+    let mut initial = 0;
+    while initial < count / 10 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let idx = state as usize % count;
+        if !points.set[idx] {
+            points.toggle(idx);
+            initial += 1;
+        }
+    }
+
+    // Move the point of the tightest cluster into the largest void until that
+    // doesn't move it anymore.
+    loop {
+        let cluster = points.tightest_cluster();
+        points.toggle(cluster);
+        let void = points.largest_void();
+        points.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+
+    // Rank the initial points by removing the tightest cluster, from the last.
+    let mut removing = points.clone();
+    for rank in (0..initial).rev() {
+        let cluster = removing.tightest_cluster();
+        removing.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+
+    // Rank the other texels by filling the largest void. Past half the
+    // texels, this is the tightest cluster of the remaining ones.
+    for rank in initial..count {
+        let void = points.largest_void();
+        points.toggle(void);
+        ranks[void] = rank;
+    }
+
+    ranks
+}
+
+/// Points on a torus, with the gaussian weighted count of points around every
+/// texel.
+#[derive(Clone)]
+struct Points {
+    size: usize,
+    kernel: Vec<f32>,
+    set: Vec<bool>,
+    energy: Vec<f32>,
+}
+
+impl Points {
+    fn toggle(&mut self, idx: usize) {
+        self.set[idx] = !self.set[idx];
+        let sign = if self.set[idx] { 1.0 } else { -1.0 };
+
+        let size = self.size;
+        let (px, py) = (idx % size, idx / size);
+        for y in 0..size {
+            let dy = (y + size - py) % size;
+            let kernel_row = &self.kernel[dy * size..(dy + 1) * size];
+            let energy_row = &mut self.energy[y * size..(y + 1) * size];
+            for (x, energy) in energy_row.iter_mut().enumerate() {
+                let dx = (x + size - px) % size;
+                *energy += sign * kernel_row[dx];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    /// The texel which is or isn't set, with the energy winning `better`.
+    fn extreme(&self, set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best: Option<(usize, f32)> = None;
+        for (idx, &energy) in self.energy.iter().enumerate() {
+            if self.set[idx] == set && best.map_or(true, |(_, best)| better(energy, best)) {
+                best = Some((idx, energy));
+            }
+        }
+        best.unwrap().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bayer_data, void_and_cluster};
+
+    #[test]
+    fn void_and_cluster_ranks_every_texel_once() {
+        let mut ranks = void_and_cluster(16);
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..256));
+    }
+
+    #[test]
+    fn bayer_matches_recursive_matrix() {
+        let data = bayer_data();
+        // The 2x2 matrix is [0, 2; 3, 1], scaled up to 8x8 by the lowest bits.
+        assert_eq!(data[0], 2);
+        assert_eq!(data[1], 2 + 32 * 4);
+        assert_eq!(data[8], 2 + 48 * 4);
+        assert_eq!(data[9], 2 + 16 * 4);
+    }
+}
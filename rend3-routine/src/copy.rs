@@ -0,0 +1,306 @@
+//! Ready-made nodes for moving images between render targets.
+//!
+//! [`CopyRoutine`] adds the small passes custom graphs keep needing:
+//!
+//! - [`CopyRoutine::add_blit_to_graph`] samples a color target into another,
+//!   scaling it to the viewport of the destination and converting it to the
+//!   format of the routine.
+//! - [`CopyRoutine::add_resolve_to_graph`] resolves a multisampled color
+//!   target in a shader, optionally weighting samples so bright HDR samples
+//!   don't alias, which the resolve of a renderpass can't do.
+//! - [`CopyRoutine::add_depth_copy_to_graph`] copies depth into another
+//!   depth target, scaled to its viewport. Multisampled depth is resolved with
+//!   [`DepthResolveRoutine`](crate::depth_resolve::DepthResolveRoutine)
+//!   instead.
+//!
+//! Like [`TonemappingRoutine`](crate::tonemapping::TonemappingRoutine), a
+//! routine only writes color in a single format, so writing to two formats
+//! takes two routines.
+
+use std::borrow::Cow;
+
+use glam::Vec4;
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, FilterMode, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, Sampler,
+    SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState,
+    TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+/// How [`CopyRoutine::add_blit_to_graph`] samples the source when scaling.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BlitFilter {
+    /// The nearest texel, keeping hard pixel edges.
+    Nearest,
+    /// Bilinear filtering of the four nearest texels.
+    #[default]
+    Linear,
+}
+
+/// How [`CopyRoutine::add_resolve_to_graph`] combines the samples of a pixel.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ResolveMode {
+    /// The average of the samples, like the resolve of a renderpass.
+    #[default]
+    Average,
+    /// The average of the samples weighted by the inverse of their
+    /// brightness, so a single very bright HDR sample doesn't light up its
+    /// whole pixel.
+    Weighted,
+}
+
+fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    shader: &str,
+    fs_entry_point: &str,
+    bgl: &BindGroupLayout,
+    output_format: Option<TextureFormat>,
+) -> RenderPipeline {
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(shader),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(shader, &ShaderConfig::default(), None))),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(shader),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    // Depth passes have no color target.
+    let targets: Vec<_> = output_format
+        .map(|format| Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::all() }))
+        .into_iter()
+        .collect();
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(shader),
+        layout: Some(&pll),
+        vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: output_format.is_none().then(|| DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState { module: &module, entry_point: fs_entry_point, targets: &targets }),
+        multiview: None,
+    })
+}
+
+fn texture_bgl(device: &Device, sample_type: TextureSampleType, multisampled: bool, sampler: bool) -> BindGroupLayout {
+    let mut bglb = BindGroupLayoutBuilder::new();
+    bglb.append(
+        ShaderStages::FRAGMENT,
+        BindingType::Texture { sample_type, view_dimension: TextureViewDimension::D2, multisampled },
+        None,
+    );
+    if sampler {
+        bglb.append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None);
+    }
+    bglb.build(device, Some("copy bgl"))
+}
+
+fn create_sampler(device: &Device, filter: FilterMode) -> Sampler {
+    device.create_sampler(&SamplerDescriptor {
+        label: Some("copy sampler"),
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: FilterMode::Nearest,
+        ..SamplerDescriptor::default()
+    })
+}
+
+/// Blits, resolves, and copies depth between render targets.
+///
+/// See module for documentation.
+pub struct CopyRoutine {
+    blit_bgl: BindGroupLayout,
+    resolve_bgl: BindGroupLayout,
+    depth_bgl: BindGroupLayout,
+    linear: Sampler,
+    nearest: Sampler,
+    blit_pipeline: RenderPipeline,
+    resolve_pipeline: RenderPipeline,
+    weighted_resolve_pipeline: RenderPipeline,
+    depth_pipeline: RenderPipeline,
+}
+
+impl CopyRoutine {
+    /// Creates the passes writing color in `output_format`. Depth is always
+    /// written as [`TextureFormat::Depth32Float`].
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, output_format: TextureFormat) -> Self {
+        profiling::scope!("CopyRoutine::new");
+
+        let device = &renderer.device;
+
+        let blit_bgl = texture_bgl(device, TextureSampleType::Float { filterable: true }, false, true);
+        let resolve_bgl = texture_bgl(device, TextureSampleType::Float { filterable: false }, true, false);
+        let depth_bgl = texture_bgl(device, TextureSampleType::Depth, false, false);
+
+        let linear = create_sampler(device, FilterMode::Linear);
+        let nearest = create_sampler(device, FilterMode::Nearest);
+
+        let color = Some(output_format);
+        let blit_pipeline = create_pipeline(device, spp, "rend3-routine/copy/blit.wgsl", "fs_main", &blit_bgl, color);
+        let resolve_pipeline =
+            create_pipeline(device, spp, "rend3-routine/copy/resolve.wgsl", "fs_main", &resolve_bgl, color);
+        let weighted_resolve_pipeline =
+            create_pipeline(device, spp, "rend3-routine/copy/resolve.wgsl", "fs_main_weighted", &resolve_bgl, color);
+        let depth_pipeline = create_pipeline(device, spp, "rend3-routine/copy/depth.wgsl", "fs_main", &depth_bgl, None);
+
+        Self {
+            blit_bgl,
+            resolve_bgl,
+            depth_bgl,
+            linear,
+            nearest,
+            blit_pipeline,
+            resolve_pipeline,
+            weighted_resolve_pipeline,
+            depth_pipeline,
+        }
+    }
+
+    /// Sample all of `src` over the viewport of `dst`, which must be single
+    /// sampled. The rest of `dst` is kept.
+    pub fn add_blit_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        filter: BlitFilter,
+    ) {
+        let sampler = match filter {
+            BlitFilter::Nearest => &self.nearest,
+            BlitFilter::Linear => &self.linear,
+        };
+        self.add_color_pass_to_graph(graph, "Blit", src, dst, &self.blit_pipeline, &self.blit_bgl, Some(sampler));
+    }
+
+    /// Resolve the multisampled `src` into `dst`, a single sampled target of
+    /// the same resolution.
+    pub fn add_resolve_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        mode: ResolveMode,
+    ) {
+        let pipeline = match mode {
+            ResolveMode::Average => &self.resolve_pipeline,
+            ResolveMode::Weighted => &self.weighted_resolve_pipeline,
+        };
+        self.add_color_pass_to_graph(graph, "Resolve", src, dst, pipeline, &self.resolve_bgl, None);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_color_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        pipeline: &'node RenderPipeline,
+        bgl: &'node BindGroupLayout,
+        sampler: Option<&'node Sampler>,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let source_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let source = ctx.graph_data.get_render_target(source_handle);
+
+            let mut bgb = BindGroupBuilder::new();
+            bgb.append_texture_view(source);
+            if let Some(sampler) = sampler {
+                bgb.append_sampler(sampler);
+            }
+            let bg = ctx.temps.add(bgb.build(&ctx.renderer.device, Some("copy bg"), bgl));
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    /// Copy the single sampled depth of `src` over the viewport of `dst`,
+    /// taking the nearest texel when scaling. The rest of `dst` is kept.
+    pub fn add_depth_copy_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("Depth Copy");
+
+        let source_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(RenderPassDepthTarget {
+                    target: dst,
+                    depth_clear: Some(0.0),
+                    stencil_clear: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }),
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let source = ctx.graph_data.get_render_target(source_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(source).build(
+                &ctx.renderer.device,
+                Some("depth copy bg"),
+                &self.depth_bgl,
+            ));
+
+            rpass.set_pipeline(&self.depth_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,116 @@
+//! Coarse CPU culling of objects against a view frustum.
+//!
+//! The forward passes cull every object they are given against the frustum of
+//! their camera before generating draw calls, first with the bounding sphere
+//! of the object, then with its bounding box. Large scenes can spread this work
+//! over multiple threads by enabling
+//! [`ForwardRoutineArgs::parallel_culling`](crate::forward::ForwardRoutineArgs::parallel_culling).
+//!
+//! Shadow passes also cull the objects that don't cast into their shadow map
+//! or are too far from the camera, as set with
+//! [`Renderer::set_object_shadow_settings`](rend3::Renderer::set_object_shadow_settings).
+//!
+//! The number of objects submitted to and culled by every view are recorded in
+//! [`FrameStats::views`](rend3::FrameStats::views).
+
+use glam::Vec3;
+use rend3::{
+    managers::InternalObject,
+    types::{Material, ObjectShadowSettings},
+    util::frustum::Frustum,
+};
+
+/// Below this many objects per thread, culling isn't worth spawning threads.
+const MIN_OBJECTS_PER_THREAD: usize = 1024;
+
+/// Whether any part of the object may be visible within the frustum.
+pub fn is_visible<M: Material>(frustum: &Frustum, object: &InternalObject<M>) -> bool {
+    frustum.contains_sphere(object.inner.bounding_sphere) && frustum.contains_box(object.bounding_box)
+}
+
+/// Whether the object casts shadows into the given shadow map, seen from a
+/// camera at `viewer`.
+pub fn casts_shadow<M: Material>(
+    settings: &ObjectShadowSettings,
+    object: &InternalObject<M>,
+    shadow_map: usize,
+    viewer: Vec3,
+) -> bool {
+    let sphere = object.inner.bounding_sphere;
+    let distance = (viewer.distance(sphere.center) - sphere.radius).max(0.0);
+    settings.casts_into(shadow_map, distance)
+}
+
+/// Removes the objects outside of the frustum, keeping the order of the rest.
+///
+/// If `parallel` is set and there are enough objects, the objects are split
+/// between scoped threads. Always runs on the current thread on wasm.
+pub fn cull_objects<'a, T, M>(
+    frustum: &Frustum,
+    objects: Vec<(T, &'a InternalObject<M>)>,
+    parallel: bool,
+) -> Vec<(T, &'a InternalObject<M>)>
+where
+    T: Send + Sync,
+    M: Material,
+{
+    profiling::scope!("Frustum Culling");
+
+    let threads = match parallel {
+        true => thread_count(objects.len()),
+        false => 1,
+    };
+    if threads <= 1 {
+        return objects.into_iter().filter(|(_, object)| is_visible(frustum, object)).collect();
+    }
+
+    cull_parallel(frustum, objects, threads)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn thread_count(objects: usize) -> usize {
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    available.min(objects / MIN_OBJECTS_PER_THREAD)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn thread_count(_objects: usize) -> usize {
+    1
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn cull_parallel<'a, T, M>(
+    frustum: &Frustum,
+    objects: Vec<(T, &'a InternalObject<M>)>,
+    threads: usize,
+) -> Vec<(T, &'a InternalObject<M>)>
+where
+    T: Send + Sync,
+    M: Material,
+{
+    let chunk_size = (objects.len() + threads - 1) / threads;
+    let visible: Vec<bool> = std::thread::scope(|scope| {
+        let handles: Vec<_> = objects
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(|(_, object)| is_visible(frustum, object)).collect::<Vec<_>>())
+            })
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("culling thread panicked")).collect()
+    });
+
+    objects.into_iter().zip(visible).filter_map(|(object, visible)| visible.then_some(object)).collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn cull_parallel<'a, T, M>(
+    frustum: &Frustum,
+    objects: Vec<(T, &'a InternalObject<M>)>,
+    _threads: usize,
+) -> Vec<(T, &'a InternalObject<M>)>
+where
+    T: Send + Sync,
+    M: Material,
+{
+    objects.into_iter().filter(|(_, object)| is_visible(frustum, object)).collect()
+}
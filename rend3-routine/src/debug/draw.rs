@@ -0,0 +1,429 @@
+use std::{borrow::Cow, f32::consts::TAU, mem};
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use rend3::{
+    graph::{DataHandle, InstructionEvaluationOutput, NodeResourceUsage, RenderGraph, RenderPassTargets},
+    types::SampleCount,
+    Renderer, RendererDataCore, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BlendState, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+    StencilState, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+};
+
+use crate::{
+    common::WholeFrameInterfaces,
+    debug::{font, SkeletonGizmos},
+};
+
+const SPHERE_SEGMENTS: u32 = 32;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct DebugVertex {
+    position: [f32; 3],
+    screen_offset: [f32; 2],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Zeroable for DebugVertex {}
+unsafe impl bytemuck::Pod for DebugVertex {}
+
+/// Immediate mode debug geometry.
+///
+/// Fill this every frame and hand it to the [`DebugDrawRoutine`], which draws
+/// and clears it. All positions are in world space and colors are linear.
+///
+/// Everything drawn is depth tested against the scene unless
+/// [`DebugDraw::set_depth_test`] is turned off.
+#[derive(Debug, Clone)]
+pub struct DebugDraw {
+    depth_tested: Vec<DebugVertex>,
+    overlay: Vec<DebugVertex>,
+    pub(super) depth_test: bool,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self { depth_tested: Vec::new(), overlay: Vec::new(), depth_test: true }
+    }
+
+    /// Whether anything will be drawn.
+    pub fn is_empty(&self) -> bool {
+        self.depth_tested.is_empty() && self.overlay.is_empty()
+    }
+
+    /// Remove everything drawn so far.
+    pub fn clear(&mut self) {
+        self.depth_tested.clear();
+        self.overlay.clear();
+    }
+
+    /// Whether the following calls are hidden behind scene geometry.
+    pub fn set_depth_test(&mut self, enabled: bool) {
+        self.depth_test = enabled;
+    }
+
+    fn push(&mut self, position: Vec3, screen_offset: Vec2, color: Vec4) {
+        let vertex =
+            DebugVertex { position: position.into(), screen_offset: screen_offset.into(), color: color.into() };
+        match self.depth_test {
+            true => self.depth_tested.push(vertex),
+            false => self.overlay.push(vertex),
+        }
+    }
+
+    /// Draw a line between two points.
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.push(start, Vec2::ZERO, color);
+        self.push(end, Vec2::ZERO, color);
+    }
+
+    /// Draw a line with an arrowhead at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.line(start, end, color);
+
+        let delta = end - start;
+        let length = delta.length();
+        let (u, v) = (delta / length).any_orthonormal_pair();
+        if !u.is_finite() {
+            return;
+        }
+        let base = end - delta * 0.2;
+        for side in [u, -u, v, -v] {
+            self.line(end, base + side * length * 0.08, color);
+        }
+    }
+
+    /// Draw lines through each point in order, closing the loop if `closed`.
+    pub fn polyline(&mut self, points: &[Vec3], closed: bool, color: Vec4) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], color);
+        }
+        if let (true, [first, .., last]) = (closed, points) {
+            self.line(*last, *first, color);
+        }
+    }
+
+    /// Draw the edges of an axis aligned box.
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec4) {
+        let center = (min + max) * 0.5;
+        self.wire_box(Mat4::from_translation(center) * Mat4::from_scale(max - min), color);
+    }
+
+    /// Draw the edges of the unit cube (from -0.5 to 0.5) transformed by `transform`.
+    pub fn wire_box(&mut self, transform: Mat4, color: Vec4) {
+        let corners = [-0.5, 0.5]
+            .into_iter()
+            .flat_map(|x| [-0.5, 0.5].into_iter().flat_map(move |y| [-0.5, 0.5].map(|z| Vec3::new(x, y, z))))
+            .map(|corner| transform.transform_point3(corner))
+            .collect::<Vec<_>>();
+        self.box_edges(&corners, color);
+    }
+
+    /// Corners are indexed by bits, x is bit 2, y is bit 1, z is bit 0.
+    fn box_edges(&mut self, corners: &[Vec3], color: Vec4) {
+        for a in 0..8 {
+            for bit in [1, 2, 4] {
+                let b = a | bit;
+                if a != b {
+                    self.line(corners[a], corners[b], color);
+                }
+            }
+        }
+    }
+
+    /// Draw a sphere as three circles around its axes.
+    pub fn wire_sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        self.circle(center, Vec3::X, radius, color);
+        self.circle(center, Vec3::Y, radius, color);
+        self.circle(center, Vec3::Z, radius, color);
+    }
+
+    /// Draw a circle facing along `normal`.
+    pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: Vec4) {
+        let (u, v) = normal.normalize_or_zero().any_orthonormal_pair();
+        let points = (0..SPHERE_SEGMENTS)
+            .map(|i| {
+                let (sin, cos) = (i as f32 / SPHERE_SEGMENTS as f32 * TAU).sin_cos();
+                center + (u * cos + v * sin) * radius
+            })
+            .collect::<Vec<_>>();
+        self.polyline(&points, true, color);
+    }
+
+    /// Draw the x, y, and z axes of `transform` in red, green, and blue.
+    pub fn axes(&mut self, transform: Mat4, length: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        let axes = [
+            (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes {
+            self.line(origin, transform.transform_point3(axis * length), color);
+        }
+    }
+
+    /// Draw the frustum of a view projection matrix.
+    ///
+    /// Infinite projections, like rend3's perspective cameras, have no far
+    /// plane. Their frustum is cut off at `infinite_distance` from the near
+    /// plane instead.
+    pub fn frustum(&mut self, view_proj: Mat4, infinite_distance: f32, color: Vec4) {
+        let inv_view_proj = view_proj.inverse();
+        let unproject = |ndc: Vec3| {
+            let world = inv_view_proj * ndc.extend(1.0);
+            world.truncate() / world.w
+        };
+
+        let mut corners = [Vec3::ZERO; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let x = if i & 4 != 0 { 1.0 } else { -1.0 };
+            let y = if i & 2 != 0 { 1.0 } else { -1.0 };
+            // Reverse z, so the far plane is at 0.
+            let z = if i & 1 != 0 { 1.0 } else { 0.0 };
+            *corner = unproject(Vec3::new(x, y, z));
+        }
+
+        for i in (0..8).step_by(2) {
+            if !corners[i].is_finite() {
+                let near = corners[i + 1];
+                let ndc_middle =
+                    Vec3::new(if i & 4 != 0 { 1.0 } else { -1.0 }, if i & 2 != 0 { 1.0 } else { -1.0 }, 0.5);
+                let direction = (unproject(ndc_middle) - near).normalize_or_zero();
+                corners[i] = near + direction * infinite_distance;
+            }
+        }
+
+        self.box_edges(&corners, color);
+    }
+
+    /// Draw text at a point, `size` pixels tall, no matter the distance.
+    ///
+    /// Only digits, letters, and some punctuation are supported. Letters are
+    /// drawn in upper case.
+    pub fn text(&mut self, position: Vec3, text: &str, size: f32, color: Vec4) {
+        let scale = size / 8.0;
+        let mut cursor = Vec2::ZERO;
+        for c in text.chars() {
+            if c == '\n' {
+                cursor = Vec2::new(0.0, cursor.y - 12.0);
+                continue;
+            }
+            for &[x0, y0, x1, y1] in font::glyph(c) {
+                let start = (cursor + Vec2::new(x0 as f32, y0 as f32)) * scale;
+                let end = (cursor + Vec2::new(x1 as f32, y1 as f32)) * scale;
+                self.push(position, start, color);
+                self.push(position, end, color);
+            }
+            cursor.x += 6.0;
+        }
+    }
+}
+
+/// Draws the contents of a [`DebugDraw`] as lines.
+pub struct DebugDrawRoutine {
+    depth_tested: [RenderPipeline; 2],
+    overlay: [RenderPipeline; 2],
+}
+
+impl DebugDrawRoutine {
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        output_format: TextureFormat,
+    ) -> Self {
+        profiling::scope!("DebugDrawRoutine::new");
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("debug draw"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/debug/debug_draw.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("debug draw"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let attributes = [
+            VertexAttribute { format: VertexFormat::Float32x3, offset: 0, shader_location: 0 },
+            VertexAttribute { format: VertexFormat::Float32x2, offset: 12, shader_location: 1 },
+            VertexAttribute { format: VertexFormat::Float32x4, offset: 20, shader_location: 2 },
+        ];
+
+        let inner = |samples: SampleCount, depth_compare| {
+            renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("debug draw"),
+                layout: Some(&pll),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[VertexBufferLayout {
+                        array_stride: mem::size_of::<DebugVertex>() as u64,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: &attributes,
+                    }],
+                },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::LineList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Cw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState { count: samples as u32, ..Default::default() },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: output_format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::all(),
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            depth_tested: SampleCount::ARRAY.map(|samples| inner(samples, CompareFunction::GreaterEqual)),
+            overlay: SampleCount::ARRAY.map(|samples| inner(samples, CompareFunction::Always)),
+        }
+    }
+
+    /// Draw everything in `draw` into the given renderpass, then clear it.
+    ///
+    /// The renderpass must have a depth target.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        draw: &mut DebugDraw,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        if draw.is_empty() {
+            return;
+        }
+
+        let mut taken = DebugDraw::new();
+        mem::swap(&mut taken.depth_tested, &mut draw.depth_tested);
+        mem::swap(&mut taken.overlay, &mut draw.overlay);
+
+        self.add_node(graph, "Debug Draw", renderpass, forward_uniform_bg, samples, move |draw, _, _| *draw = taken);
+    }
+
+    /// Draw gizmos for every light in the scene into the given renderpass.
+    ///
+    /// See [`DebugDraw::lights`] for what is drawn. The renderpass must have a
+    /// depth target.
+    pub fn add_light_gizmos_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        self.add_node(
+            graph,
+            "Light Gizmos",
+            renderpass,
+            forward_uniform_bg,
+            samples,
+            |draw, data_core, eval_output| draw.lights(data_core, &eval_output.shadows),
+        );
+    }
+
+    /// Draw the skeletons of every object whose skeleton has a rig in
+    /// `gizmos` into the given renderpass.
+    ///
+    /// See [`DebugDraw::skeletons`] for what is drawn. The renderpass must
+    /// have a depth target.
+    pub fn add_skeleton_gizmos_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        gizmos: SkeletonGizmos,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        if gizmos.is_empty() {
+            return;
+        }
+
+        self.add_node(graph, "Skeleton Gizmos", renderpass, forward_uniform_bg, samples, move |draw, data_core, _| {
+            draw.skeletons(data_core, &gizmos)
+        });
+    }
+
+    fn add_node<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+        fill: impl FnOnce(&mut DebugDraw, &RendererDataCore, &InstructionEvaluationOutput) + 'node,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            let mut draw = DebugDraw::new();
+            fill(&mut draw, ctx.data_core, ctx.eval_output);
+
+            let sample_index = match samples {
+                SampleCount::One => 0,
+                SampleCount::Four => 1,
+            };
+
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            for (vertices, pipelines) in [(draw.depth_tested, &self.depth_tested), (draw.overlay, &self.overlay)] {
+                if vertices.is_empty() {
+                    continue;
+                }
+
+                let buffer = ctx.temps.add(ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("debug draw vertices"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: BufferUsages::VERTEX,
+                }));
+
+                rpass.set_pipeline(&pipelines[sample_index]);
+                rpass.set_vertex_buffer(0, buffer.slice(..));
+                rpass.draw(0..vertices.len() as u32, 0..1);
+            }
+        });
+    }
+}
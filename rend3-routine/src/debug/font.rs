@@ -0,0 +1,71 @@
+/// Segments of a glyph, as `[x0, y0, x1, y1]` in half units of a 2x4 cell, y up from the baseline.
+type Glyph = &'static [[u8; 4]];
+
+/// Line segment font covering digits, upper case letters, and some punctuation.
+///
+/// Lower case letters use the upper case glyphs and unknown characters use `?`.
+pub(super) fn glyph(c: char) -> Glyph {
+    match c.to_ascii_uppercase() {
+        ' ' => &[],
+        '0' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 8]],
+        '1' => &[[2, 0, 2, 8], [2, 8, 0, 6], [0, 0, 4, 0]],
+        '2' => &[[0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4], [0, 4, 0, 0], [0, 0, 4, 0]],
+        '3' => &[[0, 8, 4, 8], [4, 8, 4, 0], [4, 0, 0, 0], [0, 4, 4, 4]],
+        '4' => &[[0, 8, 0, 4], [0, 4, 4, 4], [4, 8, 4, 0]],
+        '5' => &[[4, 8, 0, 8], [0, 8, 0, 4], [0, 4, 4, 4], [4, 4, 4, 0], [4, 0, 0, 0]],
+        '6' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 4], [4, 4, 0, 4]],
+        '7' => &[[0, 8, 4, 8], [4, 8, 2, 0]],
+        '8' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0], [0, 4, 4, 4]],
+        '9' => &[[4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 4], [0, 4, 4, 4], [0, 0, 4, 0]],
+        'A' => &[[0, 0, 0, 6], [0, 6, 2, 8], [2, 8, 4, 6], [4, 6, 4, 0], [0, 4, 4, 4]],
+        'B' => &[
+            [0, 0, 0, 8],
+            [0, 8, 3, 8],
+            [3, 8, 4, 7],
+            [4, 7, 4, 5],
+            [4, 5, 3, 4],
+            [0, 4, 3, 4],
+            [3, 4, 4, 3],
+            [4, 3, 4, 1],
+            [4, 1, 3, 0],
+            [3, 0, 0, 0],
+        ],
+        'C' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0]],
+        'D' => &[[0, 0, 0, 8], [0, 8, 2, 8], [2, 8, 4, 6], [4, 6, 4, 2], [4, 2, 2, 0], [2, 0, 0, 0]],
+        'E' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [0, 4, 3, 4]],
+        'F' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 4, 3, 4]],
+        'G' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 4], [4, 4, 2, 4]],
+        'H' => &[[0, 0, 0, 8], [4, 0, 4, 8], [0, 4, 4, 4]],
+        'I' => &[[0, 8, 4, 8], [2, 8, 2, 0], [0, 0, 4, 0]],
+        'J' => &[[4, 8, 4, 0], [4, 0, 0, 0], [0, 0, 0, 2]],
+        'K' => &[[0, 0, 0, 8], [0, 4, 4, 8], [0, 4, 4, 0]],
+        'L' => &[[0, 8, 0, 0], [0, 0, 4, 0]],
+        'M' => &[[0, 0, 0, 8], [0, 8, 2, 4], [2, 4, 4, 8], [4, 8, 4, 0]],
+        'N' => &[[0, 0, 0, 8], [0, 8, 4, 0], [4, 0, 4, 8]],
+        'O' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0]],
+        'P' => &[[0, 0, 0, 8], [0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4]],
+        'Q' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0], [2, 2, 4, 0]],
+        'R' => &[[0, 0, 0, 8], [0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4], [1, 4, 4, 0]],
+        'S' => &[[4, 8, 0, 8], [0, 8, 0, 4], [0, 4, 4, 4], [4, 4, 4, 0], [4, 0, 0, 0]],
+        'T' => &[[0, 8, 4, 8], [2, 8, 2, 0]],
+        'U' => &[[0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 8]],
+        'V' => &[[0, 8, 2, 0], [2, 0, 4, 8]],
+        'W' => &[[0, 8, 0, 0], [0, 0, 2, 4], [2, 4, 4, 0], [4, 0, 4, 8]],
+        'X' => &[[0, 0, 4, 8], [0, 8, 4, 0]],
+        'Y' => &[[0, 8, 2, 4], [4, 8, 2, 4], [2, 4, 2, 0]],
+        'Z' => &[[0, 8, 4, 8], [4, 8, 0, 0], [0, 0, 4, 0]],
+        '-' => &[[0, 4, 4, 4]],
+        '+' => &[[0, 4, 4, 4], [2, 2, 2, 6]],
+        '=' => &[[0, 3, 4, 3], [0, 5, 4, 5]],
+        '.' => &[[2, 0, 2, 1]],
+        ',' => &[[2, 1, 1, 0]],
+        ':' => &[[2, 2, 2, 3], [2, 5, 2, 6]],
+        '_' => &[[0, 0, 4, 0]],
+        '/' => &[[0, 0, 4, 8]],
+        '(' => &[[3, 8, 1, 6], [1, 6, 1, 2], [1, 2, 3, 0]],
+        ')' => &[[1, 8, 3, 6], [3, 6, 3, 2], [3, 2, 1, 0]],
+        '!' => &[[2, 8, 2, 3], [2, 1, 2, 0]],
+        '?' => &[[0, 6, 1, 8], [1, 8, 3, 8], [3, 8, 4, 6], [4, 6, 2, 4], [2, 4, 2, 3], [2, 1, 2, 0]],
+        _ => glyph('?'),
+    }
+}
@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+use glam::Vec4;
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BlendState, Buffer, ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, TextureFormat, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Tints every tile of
+/// [`TiledLightingRoutine`](crate::tiled_lighting::TiledLightingRoutine) by
+/// the number of point lights shading it, to find the hotspots of lighting
+/// cost.
+///
+/// Counts go from blue for a single light, through green and yellow, to red
+/// for nearly full tiles. Full tiles, which may have dropped lights, are
+/// white. Tiles without lights are left untouched.
+pub struct LightTilesDebugRoutine {
+    pipeline: RenderPipeline,
+}
+
+impl LightTilesDebugRoutine {
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        output_format: TextureFormat,
+    ) -> Self {
+        profiling::scope!("LightTilesDebugRoutine::new");
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("light tiles debug"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/debug/light_tiles.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("light tiles debug"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("light tiles debug"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: output_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { pipeline }
+    }
+
+    /// Draw the light counts over `dst`, which must be single sampled and at
+    /// the resolution the tiles were culled at.
+    ///
+    /// `light_tiles` must be the tiles bound in `forward_uniform_bg`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        light_tiles: DataHandle<Buffer>,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Light Tiles Debug");
+
+        // The tiles are read through the forward uniform bind group.
+        builder.add_data(light_tiles, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
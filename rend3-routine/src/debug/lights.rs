@@ -0,0 +1,73 @@
+use glam::{Vec3, Vec4};
+use rend3::{managers::ShadowDesc, types::Handedness, RendererDataCore};
+
+use crate::debug::DebugDraw;
+
+/// Matches `shadow_map_tint` in `debug/shadow_tint.wgsl`.
+const SHADOW_MAP_TINTS: [Vec4; 6] = [
+    Vec4::new(1.0, 0.2, 0.2, 1.0),
+    Vec4::new(0.2, 1.0, 0.2, 1.0),
+    Vec4::new(0.2, 0.4, 1.0, 1.0),
+    Vec4::new(1.0, 1.0, 0.2, 1.0),
+    Vec4::new(1.0, 0.2, 1.0, 1.0),
+    Vec4::new(0.2, 1.0, 1.0, 1.0),
+];
+
+/// Distance in front of the camera that directional light arrows are drawn.
+const DIRECTIONAL_ARROW_DISTANCE: f32 = 5.0;
+
+/// Light colors are normalized so dim and bright lights are equally visible.
+fn gizmo_color(color: Vec3) -> Vec4 {
+    let max = color.max_element();
+    match max > 0.0 {
+        true => (color / max).extend(1.0),
+        false => Vec4::ONE,
+    }
+}
+
+impl DebugDraw {
+    /// Draw gizmos for every light in the scene.
+    ///
+    /// - Directional lights are arrows in front of the camera pointing along
+    ///   the light, with their shadow frustum outlined in the same tint as
+    ///   [`DebugView::ShadowMaps`](crate::uniforms::DebugView::ShadowMaps).
+    /// - Point lights are a sphere of their radius with a cross at the center.
+    ///
+    /// Gizmos are in the color of their light and ignore the depth test.
+    /// `shadows` should be [`InstructionEvaluationOutput::shadows`](rend3::graph::InstructionEvaluationOutput::shadows).
+    pub fn lights(&mut self, data_core: &RendererDataCore, shadows: &[ShadowDesc]) {
+        let depth_test = self.depth_test;
+        self.set_depth_test(false);
+
+        let camera = &data_core.viewport_camera_state;
+        let camera_transform = camera.view().inverse();
+        let forward = match camera.handedness() {
+            Handedness::Left => camera_transform.z_axis.truncate(),
+            Handedness::Right => -camera_transform.z_axis.truncate(),
+        };
+        let arrow_center = camera.location() + forward.normalize_or_zero() * DIRECTIONAL_ARROW_DISTANCE;
+
+        for light in data_core.directional_light_manager.lights() {
+            let direction = light.direction.normalize_or_zero();
+            self.arrow(arrow_center - direction, arrow_center + direction, gizmo_color(light.color));
+        }
+
+        for (index, shadow) in shadows.iter().enumerate() {
+            self.frustum(shadow.camera.view_proj(), 0.0, SHADOW_MAP_TINTS[index % SHADOW_MAP_TINTS.len()]);
+        }
+
+        for light in data_core.point_light_manager.lights() {
+            let color = gizmo_color(light.color);
+            self.wire_sphere(light.position, light.radius, color);
+            self.cross(light.position, light.radius * 0.1, color);
+        }
+
+        self.set_depth_test(depth_test);
+    }
+
+    fn cross(&mut self, center: Vec3, half_size: f32, color: Vec4) {
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.line(center - axis * half_size, center + axis * half_size, color);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Routines for visualizing renderer internals while debugging.
+
+mod draw;
+mod font;
+mod light_tiles;
+mod lights;
+mod physics;
+mod shadow_atlas;
+mod skeleton;
+
+pub use draw::*;
+pub use light_tiles::*;
+pub use physics::*;
+pub use shadow_atlas::*;
+pub use skeleton::*;
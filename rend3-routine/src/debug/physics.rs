@@ -0,0 +1,275 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use crate::debug::DebugDraw;
+
+/// Anything that can draw debug lines.
+///
+/// Physics engines draw their debug views through traits like this, so
+/// geometry drawn through it can go to [`DebugDraw`] or anything else.
+pub trait DebugLineSink {
+    /// Draw a line between two points in world space. Colors are linear.
+    fn draw_line(&mut self, start: Vec3, end: Vec3, color: Vec4);
+}
+
+impl DebugLineSink for DebugDraw {
+    fn draw_line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.line(start, end, color);
+    }
+}
+
+/// Which part of a simulation a line belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PhysicsDebugObject {
+    Collider,
+    ColliderAabb,
+    RigidBody,
+    Contact,
+    Joint,
+}
+
+/// Colors and visibility of each [`PhysicsDebugObject`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicsDebugStyle {
+    pub collider: Option<Vec4>,
+    pub collider_aabb: Option<Vec4>,
+    pub rigid_body: Option<Vec4>,
+    pub contact: Option<Vec4>,
+    pub joint: Option<Vec4>,
+}
+
+impl Default for PhysicsDebugStyle {
+    fn default() -> Self {
+        Self {
+            collider: Some(Vec4::new(0.2, 0.9, 0.2, 1.0)),
+            collider_aabb: None,
+            rigid_body: Some(Vec4::new(0.9, 0.9, 0.9, 1.0)),
+            contact: Some(Vec4::new(0.9, 0.2, 0.2, 1.0)),
+            joint: Some(Vec4::new(0.2, 0.4, 0.9, 1.0)),
+        }
+    }
+}
+
+impl PhysicsDebugStyle {
+    /// The color of an object, or `None` if it's hidden.
+    pub fn color(&self, object: PhysicsDebugObject) -> Option<Vec4> {
+        match object {
+            PhysicsDebugObject::Collider => self.collider,
+            PhysicsDebugObject::ColliderAabb => self.collider_aabb,
+            PhysicsDebugObject::RigidBody => self.rigid_body,
+            PhysicsDebugObject::Contact => self.contact,
+            PhysicsDebugObject::Joint => self.joint,
+        }
+    }
+}
+
+/// Draws collider wireframes, contacts and joints into a [`DebugLineSink`],
+/// colored by a [`PhysicsDebugStyle`].
+///
+/// With the `rapier3d` feature it is rapier's `DebugRenderBackend`, so a
+/// rapier scene is drawn with:
+///
+/// ```ignore
+/// debug_render_pipeline.render(
+///     &mut PhysicsDebugRenderer::new(&mut debug_draw, PhysicsDebugStyle::default()),
+///     &bodies,
+///     &colliders,
+///     &impulse_joints,
+///     &multibody_joints,
+///     &narrow_phase,
+/// );
+/// ```
+///
+/// Lines keep rapier's colors, but objects the style hides aren't drawn.
+pub struct PhysicsDebugRenderer<'a, S: DebugLineSink> {
+    sink: &'a mut S,
+    style: PhysicsDebugStyle,
+}
+
+impl<'a, S: DebugLineSink> PhysicsDebugRenderer<'a, S> {
+    pub fn new(sink: &'a mut S, style: PhysicsDebugStyle) -> Self {
+        Self { sink, style }
+    }
+
+    /// Draw a line of the given object. `color` overrides the style's color,
+    /// but hidden objects stay hidden.
+    pub fn line(&mut self, object: PhysicsDebugObject, start: Vec3, end: Vec3, color: Option<Vec4>) {
+        if let Some(style_color) = self.style.color(object) {
+            self.sink.draw_line(start, end, color.unwrap_or(style_color));
+        }
+    }
+
+    /// Draw a box collider with the given half extents.
+    pub fn cuboid(&mut self, transform: Mat4, half_extents: Vec3) {
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let sign = Vec3::new(
+                    if i & 4 != 0 { 1.0 } else { -1.0 },
+                    if i & 2 != 0 { 1.0 } else { -1.0 },
+                    if i & 1 != 0 { 1.0 } else { -1.0 },
+                );
+                transform.transform_point3(sign * half_extents)
+            })
+            .collect();
+        for a in 0..8 {
+            for bit in [1, 2, 4] {
+                if a & bit == 0 {
+                    self.line(PhysicsDebugObject::Collider, corners[a], corners[a | bit], None);
+                }
+            }
+        }
+    }
+
+    /// Draw a sphere collider as three circles around its axes.
+    pub fn ball(&mut self, transform: Mat4, radius: f32) {
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.arc(transform, axis, radius, Vec3::ZERO, 0.0, 1.0);
+        }
+    }
+
+    /// Draw a capsule collider along the local y axis, with the centers of its
+    /// caps `half_height` from its center.
+    pub fn capsule(&mut self, transform: Mat4, half_height: f32, radius: f32) {
+        let top = Vec3::Y * half_height;
+        for (center, start) in [(top, 0.0), (-top, 0.5)] {
+            self.arc(transform, Vec3::Y, radius, center, 0.0, 1.0);
+            self.arc(transform, Vec3::X, radius, center, start, 0.5);
+            self.arc(transform, Vec3::Z, radius, center, start, 0.5);
+        }
+        for side in [Vec3::X, -Vec3::X, Vec3::Z, -Vec3::Z] {
+            let offset = side * radius;
+            self.line(
+                PhysicsDebugObject::Collider,
+                transform.transform_point3(top + offset),
+                transform.transform_point3(-top + offset),
+                None,
+            );
+        }
+    }
+
+    /// Draw a contact point with its normal, scaled by the penetration depth
+    /// if it's positive.
+    pub fn contact(&mut self, point: Vec3, normal: Vec3, depth: f32) {
+        let length = depth.max(0.1);
+        self.line(PhysicsDebugObject::Contact, point, point + normal * length, None);
+    }
+
+    /// Draw a joint between its anchors on its two bodies.
+    pub fn joint(&mut self, anchor1: Vec3, anchor2: Vec3) {
+        self.line(PhysicsDebugObject::Joint, anchor1, anchor2, None);
+    }
+
+    /// Draw part of a circle around `normal`, from `start` to `start + turns`
+    /// of a full turn.
+    fn arc(&mut self, transform: Mat4, normal: Vec3, radius: f32, center: Vec3, start: f32, turns: f32) {
+        const SEGMENTS: u32 = 32;
+
+        let rotation = Quat::from_rotation_arc(Vec3::Z, normal);
+        let segments = (SEGMENTS as f32 * turns).ceil() as u32;
+        let point = |i: u32| {
+            let angle = (start + turns * i as f32 / segments as f32) * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            transform.transform_point3(center + rotation * Vec3::new(cos, sin, 0.0) * radius)
+        };
+        for i in 0..segments {
+            self.line(PhysicsDebugObject::Collider, point(i), point(i + 1), None);
+        }
+    }
+}
+
+#[cfg(feature = "rapier3d")]
+impl<S: DebugLineSink> rapier3d::pipeline::DebugRenderBackend for PhysicsDebugRenderer<'_, S> {
+    fn filter_object(&self, object: rapier3d::pipeline::DebugRenderObject) -> bool {
+        self.style.color(rapier_object(object)).is_some()
+    }
+
+    fn draw_line(
+        &mut self,
+        object: rapier3d::pipeline::DebugRenderObject,
+        a: rapier3d::math::Point<rapier3d::math::Real>,
+        b: rapier3d::math::Point<rapier3d::math::Real>,
+        color: [f32; 4],
+    ) {
+        let start = Vec3::new(a.x, a.y, a.z);
+        let end = Vec3::new(b.x, b.y, b.z);
+        self.line(rapier_object(object), start, end, Some(hsla_to_linear(color)));
+    }
+}
+
+#[cfg(feature = "rapier3d")]
+fn rapier_object(object: rapier3d::pipeline::DebugRenderObject) -> PhysicsDebugObject {
+    use rapier3d::pipeline::DebugRenderObject;
+
+    match object {
+        DebugRenderObject::Collider(..) => PhysicsDebugObject::Collider,
+        DebugRenderObject::ColliderAabb(..) => PhysicsDebugObject::ColliderAabb,
+        DebugRenderObject::RigidBody(..) => PhysicsDebugObject::RigidBody,
+        DebugRenderObject::ContactPair(..) => PhysicsDebugObject::Contact,
+        DebugRenderObject::ImpulseJoint(..) | DebugRenderObject::MultibodyJoint(..) => PhysicsDebugObject::Joint,
+    }
+}
+
+/// Converts a hue, saturation, lightness and alpha color, as given by
+/// rapier's debug renderer, to linear RGBA. Hue is in degrees.
+pub fn hsla_to_linear([h, s, l, a]: [f32; 4]) -> Vec4 {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - chroma / 2.0;
+    let srgb_to_linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec4::new(srgb_to_linear(r + m), srgb_to_linear(g + m), srgb_to_linear(b + m), a)
+}
+
+#[cfg(all(test, feature = "rapier3d"))]
+mod tests {
+    use glam::{Vec3, Vec4};
+    use rapier3d::prelude::*;
+
+    use super::{DebugLineSink, PhysicsDebugRenderer, PhysicsDebugStyle};
+
+    impl DebugLineSink for Vec<(Vec3, Vec3)> {
+        fn draw_line(&mut self, start: Vec3, end: Vec3, _color: Vec4) {
+            self.push((start, end));
+        }
+    }
+
+    #[test]
+    fn rapier_colliders_are_drawn_unless_hidden() {
+        let bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+        colliders.insert(ColliderBuilder::cuboid(1.0, 2.0, 3.0).translation(vector![10.0, 0.0, 0.0]).build());
+
+        let render = |style| {
+            let mut lines = Vec::new();
+            DebugRenderPipeline::new(DebugRenderStyle::default(), DebugRenderMode::COLLIDER_SHAPES).render(
+                &mut PhysicsDebugRenderer::new(&mut lines, style),
+                &bodies,
+                &colliders,
+                &ImpulseJointSet::new(),
+                &MultibodyJointSet::new(),
+                &NarrowPhase::new(),
+            );
+            lines
+        };
+
+        let lines = render(PhysicsDebugStyle::default());
+        assert_eq!(lines.len(), 12);
+        assert!(lines.iter().all(|&(a, b)| (a.x - 10.0).abs() <= 1.0 && (b.x - 10.0).abs() <= 1.0));
+        assert!(lines.iter().any(|&(a, _)| (a.z - 3.0).abs() < 1e-4));
+
+        assert!(render(PhysicsDebugStyle { collider: None, ..Default::default() }).is_empty());
+    }
+}
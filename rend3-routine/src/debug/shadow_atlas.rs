@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle, ViewportRect,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, TextureFormat, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Draws the contents of the shadow atlas into a corner of a render target.
+///
+/// Depth is shown in grayscale, with near being white. Each directional
+/// light's region of the atlas is outlined in the same tint that
+/// [`DebugView::ShadowMaps`](crate::uniforms::DebugView::ShadowMaps) uses.
+pub struct ShadowAtlasDebugRoutine {
+    pipeline: RenderPipeline,
+}
+
+impl ShadowAtlasDebugRoutine {
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        output_format: TextureFormat,
+    ) -> Self {
+        profiling::scope!("ShadowAtlasDebugRoutine::new");
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("shadow atlas debug"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/debug/shadow_atlas.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("shadow atlas debug"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("shadow atlas debug"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: output_format,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { pipeline }
+    }
+
+    /// Compute where to put the overlay: the bottom left corner of the
+    /// target, a third of its height, keeping the aspect ratio of the atlas.
+    pub fn overlay_rect(target_resolution: UVec2, atlas_resolution: UVec2) -> ViewportRect {
+        let height = (target_resolution.y / 3).max(1);
+        let width = ((atlas_resolution.x as u64 * height as u64) / atlas_resolution.y.max(1) as u64) as u32;
+        let size = UVec2::new(width.clamp(1, target_resolution.x.max(1)), height);
+        ViewportRect::new(UVec2::new(0, target_resolution.y.saturating_sub(height)), size)
+    }
+
+    /// Draw the shadow atlas into `rect` of `dst`, which must be single sampled.
+    ///
+    /// `shadow` must be the shadow target bound in `forward_uniform_bg`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        shadow: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        rect: ViewportRect,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Shadow Atlas Debug");
+
+        // The atlas is read through the forward uniform bind group.
+        builder.add_render_target(shadow, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst.set_viewport(rect),
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
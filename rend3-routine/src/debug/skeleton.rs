@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    types::{RawSkeletonHandle, SkeletonHandle},
+    util::typedefs::FastHashMap,
+    RendererDataCore,
+};
+
+use crate::debug::DebugDraw;
+
+/// Size of joints without a parent or children, in units of the object.
+const LONE_JOINT_SIZE: f32 = 0.1;
+/// Distance along a bone of its widest point, as a fraction of its length.
+const BONE_WAIST: f32 = 0.1;
+
+/// The joint hierarchy and bind pose of a skeleton, which rend3 doesn't keep.
+///
+/// Joints are in the order of the joint matrices of the
+/// [`Skeleton`](rend3::types::Skeleton) they describe.
+#[derive(Debug, Clone)]
+pub struct SkeletonRig {
+    parents: Vec<Option<usize>>,
+    names: Vec<String>,
+    bind_transforms: Vec<Mat4>,
+}
+
+impl SkeletonRig {
+    /// Creates a rig from the parent and name of every joint, and the inverse
+    /// bind transforms the skeleton was made with. Parents past the end of the
+    /// joints are ignored.
+    pub fn new(parents: Vec<Option<usize>>, names: Vec<String>, inverse_bind_transforms: &[Mat4]) -> Self {
+        let joint_count = parents.len();
+        let parents = parents.into_iter().map(|parent| parent.filter(|&parent| parent < joint_count)).collect();
+        let bind_transforms = inverse_bind_transforms.iter().map(Mat4::inverse).collect();
+        Self { parents, names, bind_transforms }
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.parents.len().min(self.bind_transforms.len())
+    }
+
+    pub fn parent(&self, joint: usize) -> Option<usize> {
+        self.parents.get(joint).copied().flatten()
+    }
+
+    pub fn name(&self, joint: usize) -> Option<&str> {
+        self.names.get(joint).map(String::as_str)
+    }
+
+    /// World transforms of the joints of an object with the given transform,
+    /// posed by `joint_matrices`.
+    pub fn joint_transforms(&self, object_transform: Mat4, joint_matrices: &[Mat4]) -> Vec<Mat4> {
+        self.bind_transforms
+            .iter()
+            .zip(joint_matrices)
+            .take(self.joint_count())
+            .map(|(bind, joint_matrix)| object_transform * *joint_matrix * *bind)
+            .collect()
+    }
+}
+
+/// What [`DebugDraw::skeleton`] draws.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkeletonDebugStyle {
+    /// Color of the bones from every joint to its parent, or `None` to hide
+    /// them.
+    pub bones: Option<Vec4>,
+    /// Draw the x, y, and z axes of every joint in red, green, and blue.
+    pub joint_axes: bool,
+    /// Color of the joint names, or `None` to hide them.
+    pub names: Option<Vec4>,
+    /// Height of the joint names in pixels.
+    pub name_size: f32,
+}
+
+impl Default for SkeletonDebugStyle {
+    fn default() -> Self {
+        Self { bones: Some(Vec4::new(1.0, 0.6, 0.1, 1.0)), joint_axes: true, names: Some(Vec4::ONE), name_size: 12.0 }
+    }
+}
+
+/// Skeletons to draw with [`DebugDraw::skeletons`], by their rig.
+#[derive(Debug, Clone, Default)]
+pub struct SkeletonGizmos {
+    rigs: FastHashMap<RawSkeletonHandle, Arc<SkeletonRig>>,
+    pub style: SkeletonDebugStyle,
+}
+
+impl SkeletonGizmos {
+    pub fn new(style: SkeletonDebugStyle) -> Self {
+        Self { rigs: FastHashMap::default(), style }
+    }
+
+    /// Draw every object deformed by `skeleton` with the given rig.
+    pub fn insert(&mut self, skeleton: &SkeletonHandle, rig: Arc<SkeletonRig>) {
+        self.rigs.insert(skeleton.get_raw(), rig);
+    }
+
+    pub fn remove(&mut self, skeleton: &SkeletonHandle) {
+        self.rigs.remove(&skeleton.get_raw());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rigs.is_empty()
+    }
+}
+
+impl DebugDraw {
+    /// Draw the skeletons of every object whose skeleton has a rig in
+    /// `gizmos`, posed by the joint matrices the GPU skins them with this
+    /// frame.
+    ///
+    /// Skeletons ignore the depth test, so they show through their mesh.
+    pub fn skeletons(&mut self, data_core: &RendererDataCore, gizmos: &SkeletonGizmos) {
+        if gizmos.is_empty() {
+            return;
+        }
+
+        let depth_test = self.depth_test;
+        self.set_depth_test(false);
+
+        for (_, transform, skeleton) in data_core.object_manager.skinned_objects() {
+            let Some(rig) = gizmos.rigs.get(&skeleton) else {
+                continue;
+            };
+            let joint_matrices = &data_core.skeleton_manager.internal_data(skeleton).joint_matrices;
+            self.skeleton(transform, joint_matrices, rig, &gizmos.style);
+        }
+
+        self.set_depth_test(depth_test);
+    }
+
+    /// Draw a skeleton posed by `joint_matrices` on an object with the given
+    /// transform.
+    ///
+    /// Bones are octahedrons from each joint to its child, widest near the
+    /// parent and rolled with it. Joint axes are scaled to the length of
+    /// their bone, ignoring the scale of the joint.
+    pub fn skeleton(
+        &mut self,
+        transform: Mat4,
+        joint_matrices: &[Mat4],
+        rig: &SkeletonRig,
+        style: &SkeletonDebugStyle,
+    ) {
+        let joints = rig.joint_transforms(transform, joint_matrices);
+        let positions: Vec<Vec3> = joints.iter().map(|joint| joint.transform_point3(Vec3::ZERO)).collect();
+
+        // Skeletons with fewer joint matrices than joints stop early.
+        let parent_of = |joint: usize| rig.parent(joint).filter(|&parent| parent < joints.len());
+
+        // Roots are as large as their longest bone.
+        let mut sizes: Vec<f32> = vec![0.0; joints.len()];
+        for (joint, position) in positions.iter().enumerate() {
+            if let Some(parent) = parent_of(joint) {
+                let length = position.distance(positions[parent]);
+                sizes[joint] = length;
+                sizes[parent] = sizes[parent].max(length);
+            }
+        }
+
+        for (joint, &transform) in joints.iter().enumerate() {
+            let size = match sizes[joint] > 0.0 {
+                true => sizes[joint],
+                false => LONE_JOINT_SIZE,
+            };
+
+            if let (Some(color), Some(parent)) = (style.bones, parent_of(joint)) {
+                self.bone(positions[parent], positions[joint], joints[parent].x_axis.truncate(), color);
+            }
+            if style.joint_axes {
+                let (_, rotation, translation) = transform.to_scale_rotation_translation();
+                self.axes(Mat4::from_rotation_translation(rotation, translation), size * 0.25);
+            }
+            if let (Some(color), Some(name)) = (style.names, rig.name(joint)) {
+                self.text(positions[joint], name, style.name_size, color);
+            }
+        }
+    }
+
+    /// Draw an octahedral bone from `head` to `tail`, with its square waist
+    /// aligned to `roll` where possible.
+    fn bone(&mut self, head: Vec3, tail: Vec3, roll: Vec3, color: Vec4) {
+        let delta = tail - head;
+        let length = delta.length();
+        let direction = delta / length;
+        if !direction.is_finite() {
+            return;
+        }
+
+        let u = roll.reject_from(direction).normalize_or_zero();
+        let (u, v) = match u == Vec3::ZERO {
+            true => direction.any_orthonormal_pair(),
+            false => (u, direction.cross(u)),
+        };
+
+        let waist = head + delta * BONE_WAIST;
+        let radius = length * BONE_WAIST;
+        let corners = [u, v, -u, -v].map(|side| waist + side * radius);
+        for &corner in &corners {
+            self.line(head, corner, color);
+            self.line(corner, tail, color);
+        }
+        self.polyline(&corners, true, color);
+    }
+}
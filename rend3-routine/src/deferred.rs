@@ -0,0 +1,207 @@
+//! Deferred shading of opaque objects.
+//!
+//! Instead of shading every fragment as it is drawn, the opaque and cutout
+//! PBR passes write the material of the closest surface into a thin G-buffer.
+//! A single fullscreen pass then shades each pixel once with every light, so
+//! the cost of lighting doesn't grow with overdraw.
+//!
+//! The G-buffer holds four targets, see [`GBufferTargets`]. The pass also
+//! writes the [`velocity`](crate::velocity). Transparent objects are still
+//! shaded while they are drawn.
+//!
+//! Enable it in the base render graph with
+//! [`BaseRenderGraphSettings::deferred`](crate::base::BaseRenderGraphSettings::deferred).
+
+use std::borrow::Cow;
+
+use glam::UVec2;
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassLoadOp, RenderPassTarget,
+        RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDimension, VertexState,
+};
+
+use crate::{base::DepthTargets, common::WholeFrameInterfaces, velocity::VelocityTargets};
+
+/// Formats of the G-buffer targets, in the order they are bound.
+pub const GBUFFER_FORMATS: [TextureFormat; 4] =
+    [TextureFormat::Rgba8UnormSrgb, TextureFormat::Rgba16Float, TextureFormat::Rgba8Unorm, TextureFormat::Rgba16Float];
+
+/// The targets of the G-buffer pass.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GBufferTargets {
+    /// Albedo, and ambient occlusion in alpha.
+    pub albedo: RenderTargetHandle,
+    /// View space normal, and whether the material is unlit in alpha.
+    pub normal: RenderTargetHandle,
+    /// Perceptual roughness, metallic, reflectance and clear coat.
+    pub material: RenderTargetHandle,
+    /// Emissive, and clear coat perceptual roughness in alpha.
+    pub emissive: RenderTargetHandle,
+}
+
+impl GBufferTargets {
+    pub fn new(graph: &mut RenderGraph<'_>, resolution: UVec2) -> Self {
+        let mut add = |label: &'static str, format| {
+            graph.add_render_target(RenderTargetDescriptor {
+                label: Some(label.into()),
+                resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            })
+        };
+        Self {
+            albedo: add("gbuffer albedo", GBUFFER_FORMATS[0]),
+            normal: add("gbuffer normal", GBUFFER_FORMATS[1]),
+            material: add("gbuffer material", GBUFFER_FORMATS[2]),
+            emissive: add("gbuffer emissive", GBUFFER_FORMATS[3]),
+        }
+    }
+
+    fn handles(&self) -> [RenderTargetHandle; 4] {
+        [self.albedo, self.normal, self.material, self.emissive]
+    }
+
+    /// Renderpass writing the G-buffer, velocity and depth.
+    pub fn renderpass(&self, depth: &DepthTargets, velocity: &VelocityTargets) -> RenderPassTargets {
+        RenderPassTargets {
+            targets: self
+                .handles()
+                .into_iter()
+                .map(|color| RenderPassTarget {
+                    color,
+                    clear: glam::Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                })
+                .chain([velocity.renderpass_target()])
+                .collect(),
+            depth_stencil: Some(RenderPassDepthTarget {
+                target: depth.rendering_target(),
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+                load: RenderPassLoadOp::ClearOnFirstUse,
+            }),
+        }
+    }
+}
+
+/// Shades the G-buffer into the HDR target.
+///
+/// See module for documentation.
+pub struct DeferredLightingRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl DeferredLightingRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("DeferredLightingRoutine::new");
+
+        let mut bglb = BindGroupLayoutBuilder::new();
+        for sample_type in
+            [TextureSampleType::Float { filterable: true }; 4].into_iter().chain([TextureSampleType::Depth])
+        {
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture { sample_type, view_dimension: TextureViewDimension::D2, multisampled: false },
+                None,
+            );
+        }
+        let bgl = bglb.build(&renderer.device, Some("deferred lighting bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("deferred lighting"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/deferred.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("deferred lighting"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("deferred lighting"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::Rgba16Float,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { bgl, pipeline }
+    }
+
+    /// Shades every pixel the G-buffer pass drew into `output`. The rest of
+    /// `output` is left as it is.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        gbuffer: GBufferTargets,
+        depth: RenderTargetHandle,
+        output: RenderPassTarget,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Deferred Lighting");
+
+        let gbuffer_handles =
+            gbuffer.handles().map(|handle| builder.add_render_target(handle, NodeResourceUsage::Input));
+        let depth_handle = builder.add_render_target(depth.set_mips(0..1), NodeResourceUsage::Input);
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets { targets: vec![output], depth_stencil: None },
+            NodeResourceUsage::InputOutput,
+        );
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            let mut bgb = BindGroupBuilder::new();
+            for handle in gbuffer_handles {
+                bgb.append_texture_view(ctx.graph_data.get_render_target(handle));
+            }
+            bgb.append_texture_view(ctx.graph_data.get_render_target(depth_handle));
+            let bg = ctx.temps.add(bgb.build(&ctx.renderer.device, Some("deferred lighting bg"), &self.bgl));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
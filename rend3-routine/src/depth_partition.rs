@@ -0,0 +1,76 @@
+//! Depth partitioning for scenes spanning huge depth ranges.
+//!
+//! Space and flight games see from centimeters to thousands of kilometers,
+//! more than a single depth buffer resolves without z-fighting. Splitting the
+//! scene at a distance gives each side its own depth range: everything past
+//! the split is rendered first, from a copy of the viewport camera with its
+//! near plane at the split. The depth buffer of the viewport is then cleared
+//! to the depth of the split, so the regular passes only draw what is nearer,
+//! on top of the far partition.
+//!
+//! Set [`BaseRenderGraphSettings::depth_partition`](crate::base::BaseRenderGraphSettings::depth_partition)
+//! to partition the base render graph. Only perspective cameras are
+//! partitioned. Depth based effects, like SSAO and fog, only see the near
+//! partition, with everything past the split at the split.
+
+use glam::Vec3;
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
+    managers::CameraState,
+    types::{CameraProjection, Handedness},
+};
+use wgpu::{LoadOp, Operations, RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp};
+
+/// Depth of the viewport at `split` units in front of `camera`, in the
+/// reverse-z depth buffer.
+pub fn split_depth(camera: &CameraState, split: f32) -> f32 {
+    let forward = match camera.handedness() {
+        Handedness::Left => split,
+        Handedness::Right => -split,
+    };
+    camera.proj().project_point3(Vec3::new(0.0, 0.0, forward)).z.clamp(0.0, 1.0)
+}
+
+/// Writes the camera of the far partition into `camera`. Cameras that aren't
+/// perspective cameras are written unchanged, drawing the whole scene, which
+/// the viewport then draws over.
+pub fn add_far_camera_to_graph(graph: &mut RenderGraph<'_>, split: f32, camera: DataHandle<CameraState>) {
+    let mut builder = graph.add_node("Far Depth Partition Camera");
+    let camera_handle = builder.add_data(camera, NodeResourceUsage::Output);
+
+    builder.build(move |ctx| {
+        let viewport = &ctx.data_core.viewport_camera_state;
+        let camera = viewport.with_near_plane(split).unwrap_or_else(|| viewport.clone());
+        ctx.graph_data.set_data(camera_handle, Some(camera));
+    });
+}
+
+/// Clears `depth` to the depth of the split, so the passes of the viewport
+/// camera only draw the near partition. Must be the first node using `depth`.
+pub fn add_near_clip_to_graph(graph: &mut RenderGraph<'_>, split: f32, depth: RenderTargetHandle) {
+    let mut builder = graph.add_node("Near Depth Partition Clip");
+    let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Output);
+
+    builder.build(move |mut ctx| {
+        let encoder = ctx.encoder_or_pass.take_encoder();
+        let depth = ctx.graph_data.get_render_target(depth_handle);
+
+        let camera = &ctx.data_core.viewport_camera_state;
+        let clear = match camera.get_data().projection {
+            CameraProjection::Perspective { .. } => split_depth(camera, split),
+            CameraProjection::Orthographic { .. } | CameraProjection::Raw(_) => 0.0,
+        };
+
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("near depth partition clip"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(Operations { load: LoadOp::Clear(clear), store: StoreOp::Store }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+    });
+}
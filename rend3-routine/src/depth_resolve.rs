@@ -0,0 +1,154 @@
+//! Resolving multisampled depth so later passes can sample it.
+//!
+//! The scene depth is read through [`DepthTargets::readable`], which is only
+//! rendered to directly without multisampling. With multisampling,
+//! [`DepthResolveRoutine`] fills it with the nearest sample of each pixel.
+//!
+//! Shaders reading it can include `rend3-routine/math/depth.wgsl`, which
+//! reconstructs positions and linear depth from the reverse infinite depth:
+//!
+//! ```wgsl
+//! {{include "rend3-routine/post.wgsl"}}
+//! {{include "rend3-routine/math/depth.wgsl"}}
+//!
+//! @group(1) @binding(0)
+//! var color: texture_2d<f32>;
+//! @group(1) @binding(1)
+//! var depth: texture_depth_2d;
+//!
+//! @fragment
+//! fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+//!     let sampled = textureSample(color, primary_sampler, vout.tex_coords);
+//!     let d = textureLoad(depth, vec2<i32>(vout.position.xy), 0);
+//!     if (depth_is_background(d)) {
+//!         return sampled;
+//!     }
+//!     let fog = 1.0 - exp(-linearize_depth(d, uniforms.inv_proj) * 0.05);
+//!     return vec4<f32>(mix(sampled.rgb, vec3<f32>(0.5), fog), sampled.a);
+//! }
+//! ```
+//!
+//! [`DepthTargets::readable`]: crate::base::DepthTargets::readable
+
+use std::borrow::Cow;
+
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassLoadOp, RenderPassTargets},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, CompareFunction, DepthBiasState, DepthStencilState, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureFormat,
+    TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::base::DepthTargets;
+
+/// Copies the nearest sample of multisampled depth into a single sampled
+/// depth target.
+pub struct DepthResolveRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl DepthResolveRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("DepthResolveRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("depth resolve bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("depth resolve"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/depth_resolve.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("depth resolve"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("depth resolve"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState { module: &module, entry_point: "fs_main", targets: &[] }),
+            multiview: None,
+        });
+
+        Self { bgl, pipeline }
+    }
+
+    /// Resolve the multisampled depth of `depth` into
+    /// [`DepthTargets::readable`]. Does nothing without multisampling.
+    pub fn add_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>, depth: DepthTargets) {
+        let Some(multi_sample) = depth.multi_sample else {
+            return;
+        };
+
+        let mut builder = graph.add_node("Depth Resolve");
+
+        let source_handle = builder.add_render_target(multi_sample, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(RenderPassDepthTarget {
+                    target: depth.readable().set_mips(0..1),
+                    depth_clear: Some(0.0),
+                    stencil_clear: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }),
+            },
+            NodeResourceUsage::Output,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let source = ctx.graph_data.get_render_target(source_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(source).build(
+                &ctx.renderer.device,
+                Some("depth resolve bg"),
+                &self.bgl,
+            ));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,261 @@
+//! Smooth transitions between environment lighting settings.
+//!
+//! [`EnvironmentTransition`] holds the ambient color, skylight intensity and
+//! skybox of the scene, and fades them into new settings over a given time,
+//! for day-night cycles or walking between areas lit differently. Every
+//! frame, [`EnvironmentTransition::update`] advances the fade by the time the
+//! renderer's clock advanced, so it pauses with the clock, and sets the
+//! skybox. [`EnvironmentTransition::apply`] then writes the rest into the
+//! settings of the base render graph.
+//!
+//! Skyboxes are cross-faded with
+//! [`SkyboxRoutine::set_blend_texture`](crate::skybox::SkyboxRoutine::set_blend_texture),
+//! and the skylight follows them. Fading from or to no skybox can't blend, so
+//! the skybox is switched when the fade ends. A fade started while skyboxes
+//! are cross-fading first finishes that cross-fade into the closer skybox,
+//! then cross-fades into the new one.
+
+use glam::{Quat, Vec4};
+use rend3::{types::TextureCubeHandle, Renderer};
+
+use crate::{base::BaseRenderGraphSettings, skybox::SkyboxRoutine};
+
+/// Environment lighting settings, see the [module](self) for documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment {
+    /// See [`BaseRenderGraphSettings::ambient_color`].
+    pub ambient_color: Vec4,
+    /// See [`BaseRenderGraphSettings::skylight_intensity`].
+    pub skylight_intensity: f32,
+    pub skybox: Option<TextureCubeHandle>,
+    /// See [`SkyboxRoutine::set_intensity`].
+    pub skybox_intensity: f32,
+    /// See [`SkyboxRoutine::set_rotation`].
+    pub skybox_rotation: Quat,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            ambient_color: Vec4::ZERO,
+            skylight_intensity: 0.0,
+            skybox: None,
+            skybox_intensity: 1.0,
+            skybox_rotation: Quat::IDENTITY,
+        }
+    }
+}
+
+struct Fade {
+    target: Environment,
+    duration: f32,
+    elapsed: f32,
+    /// The skybox an interrupted fade was cross-fading the current one into,
+    /// and how far it got.
+    interrupted_skybox: Option<(TextureCubeHandle, f32)>,
+}
+
+/// Skybox textures and the blend factor between them.
+type SkyboxBlend = (Option<TextureCubeHandle>, Option<TextureCubeHandle>, f32);
+
+/// Fades between [`Environment`]s, see the [module](self) for documentation.
+pub struct EnvironmentTransition {
+    /// The environment the current fade started from.
+    current: Environment,
+    fade: Option<Fade>,
+    /// Skybox textures last given to the skybox routine.
+    applied_skybox: Option<(Option<TextureCubeHandle>, Option<TextureCubeHandle>)>,
+}
+
+impl EnvironmentTransition {
+    pub fn new(environment: Environment) -> Self {
+        Self { current: environment, fade: None, applied_skybox: None }
+    }
+
+    /// Switches to `environment` at once, stopping any fade.
+    pub fn set(&mut self, environment: Environment) {
+        self.current = environment;
+        self.fade = None;
+    }
+
+    /// Fades from the environment as it is now into `environment` over
+    /// `seconds` of renderer time. Starting a fade during another one starts
+    /// from where the other one got to.
+    pub fn fade_to(&mut self, environment: Environment, seconds: f32) {
+        if seconds <= 0.0 {
+            self.set(environment);
+            return;
+        }
+        let mut interrupted_skybox = None;
+        if let Some(fade) = &self.fade {
+            let progress = fade.elapsed / fade.duration;
+            if progress > 0.0 {
+                let (background, blend, factor) = self.skybox_blend(fade);
+                let mut from = self.blended(fade, progress);
+                from.skybox = background;
+                interrupted_skybox = blend.filter(|_| factor > 0.0).map(|blend| (blend, factor));
+                self.current = from;
+            }
+        }
+        self.fade = Some(Fade { target: environment, duration: seconds, elapsed: 0.0, interrupted_skybox });
+    }
+
+    /// Whether a fade is in progress.
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// The environment being faded into, or the current one.
+    pub fn target(&self) -> &Environment {
+        self.fade.as_ref().map_or(&self.current, |fade| &fade.target)
+    }
+
+    /// The environment as of the last [`Self::update`], with the skybox being
+    /// faded from.
+    pub fn environment(&self) -> Environment {
+        match &self.fade {
+            Some(fade) => {
+                Environment { skybox: self.skybox_blend(fade).0, ..self.blended(fade, fade.elapsed / fade.duration) }
+            }
+            None => self.current.clone(),
+        }
+    }
+
+    /// The skybox textures shown during `fade`, and the blend factor between
+    /// them.
+    fn skybox_blend(&self, fade: &Fade) -> SkyboxBlend {
+        let progress = fade.elapsed / fade.duration;
+        let (from, to) = (&self.current.skybox, &fade.target.skybox);
+        let Some((blend, factor)) = &fade.interrupted_skybox else {
+            return match (from, to) {
+                (Some(_), Some(_)) => (from.clone(), to.clone(), progress),
+                _ => (from.clone(), None, 0.0),
+            };
+        };
+
+        let blend = Some(blend.clone());
+        // Continue the interrupted cross-fade if it was between the same skyboxes.
+        if blend == *to {
+            return (from.clone(), blend, factor + (1.0 - factor) * progress);
+        }
+        if from == to {
+            return (from.clone(), blend, factor * (1.0 - progress));
+        }
+
+        // Otherwise finish it into the closer skybox in the first half, and
+        // cross-fade that into the new one in the second half.
+        let closer_is_blend = *factor >= 0.5;
+        if progress < 0.5 {
+            let toward = if closer_is_blend { 1.0 } else { 0.0 };
+            return (from.clone(), blend, factor + (toward - factor) * progress * 2.0);
+        }
+        let closer = if closer_is_blend { blend } else { from.clone() };
+        match (&closer, to) {
+            (Some(_), Some(_)) => (closer, to.clone(), progress * 2.0 - 1.0),
+            _ => (closer, None, 0.0),
+        }
+    }
+
+    fn blended(&self, fade: &Fade, progress: f32) -> Environment {
+        let (from, to) = (&self.current, &fade.target);
+        Environment {
+            ambient_color: from.ambient_color.lerp(to.ambient_color, progress),
+            skylight_intensity: from.skylight_intensity + (to.skylight_intensity - from.skylight_intensity) * progress,
+            skybox: from.skybox.clone(),
+            skybox_intensity: from.skybox_intensity + (to.skybox_intensity - from.skybox_intensity) * progress,
+            skybox_rotation: from.skybox_rotation.slerp(to.skybox_rotation, progress),
+        }
+    }
+
+    /// Advances the fade by the time the renderer's clock advanced this
+    /// frame, and sets the skybox of `skybox`. Call once per frame, after
+    /// [`Renderer::evaluate_instructions`] and before
+    /// [`SkyboxRoutine::evaluate`].
+    pub fn update(&mut self, renderer: &Renderer, skybox: Option<&mut SkyboxRoutine>) {
+        let delta = renderer.data_core.lock().clock.delta();
+
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed = (fade.elapsed + delta).min(fade.duration);
+            if fade.elapsed >= fade.duration {
+                self.current = self.fade.take().unwrap().target;
+            }
+        }
+
+        let Some(skybox) = skybox else {
+            return;
+        };
+
+        let environment = self.environment();
+        let (background, blend, progress) = match &self.fade {
+            Some(fade) => self.skybox_blend(fade),
+            None => (self.current.skybox.clone(), None, 0.0),
+        };
+
+        let textures = (background, blend);
+        if self.applied_skybox.as_ref() != Some(&textures) {
+            if self.applied_skybox.as_ref().map(|(background, _)| background) != Some(&textures.0) {
+                skybox.set_background_texture(textures.0.clone());
+            }
+            skybox.set_blend_texture(textures.1.clone());
+            self.applied_skybox = Some(textures);
+        }
+        skybox.set_blend_factor(progress);
+        skybox.set_intensity(environment.skybox_intensity);
+        skybox.set_rotation(environment.skybox_rotation);
+    }
+
+    /// Writes the ambient color and skylight intensity into `settings`.
+    pub fn apply(&self, settings: &mut BaseRenderGraphSettings) {
+        let environment = self.environment();
+        settings.ambient_color = environment.ambient_color;
+        settings.skylight_intensity = environment.skylight_intensity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rend3::types::TextureCubeHandle;
+
+    use super::{Environment, EnvironmentTransition};
+
+    fn skybox(idx: usize) -> Environment {
+        Environment { skybox: Some(TextureCubeHandle::new(|_| {}, idx)), ..Default::default() }
+    }
+
+    fn advance(transition: &mut EnvironmentTransition, progress: f32) {
+        let fade = transition.fade.as_mut().unwrap();
+        fade.elapsed = fade.duration * progress;
+    }
+
+    fn skybox_blend(transition: &EnvironmentTransition) -> (Option<usize>, Option<usize>, f32) {
+        let (background, blend, factor) = transition.skybox_blend(transition.fade.as_ref().unwrap());
+        (background.map(|h| h.get_raw().idx), blend.map(|h| h.get_raw().idx), factor)
+    }
+
+    #[test]
+    fn interrupted_past_halfway_continues_cross_fade() {
+        let mut transition = EnvironmentTransition::new(skybox(0));
+        transition.fade_to(skybox(1), 1.0);
+        advance(&mut transition, 0.75);
+
+        // Back to the first skybox, from where the cross-fade got to.
+        transition.fade_to(skybox(0), 1.0);
+        assert_eq!(skybox_blend(&transition), (Some(0), Some(1), 0.75));
+        advance(&mut transition, 0.5);
+        assert_eq!(skybox_blend(&transition), (Some(0), Some(1), 0.375));
+    }
+
+    #[test]
+    fn interrupted_into_third_skybox_finishes_closer_first() {
+        let mut transition = EnvironmentTransition::new(skybox(0));
+        transition.fade_to(skybox(1), 1.0);
+        advance(&mut transition, 0.75);
+
+        transition.fade_to(skybox(2), 1.0);
+        assert_eq!(skybox_blend(&transition), (Some(0), Some(1), 0.75));
+        advance(&mut transition, 0.25);
+        assert_eq!(skybox_blend(&transition), (Some(0), Some(1), 0.875));
+        advance(&mut transition, 0.75);
+        assert_eq!(skybox_blend(&transition), (Some(1), Some(2), 0.5));
+    }
+}
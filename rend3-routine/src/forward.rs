@@ -2,28 +2,46 @@
 //!
 //! Will default to the PBR shader code if custom code is not specified.
 
-use std::{cmp::Ordering, marker::PhantomData, sync::Arc};
+use std::{
+    cmp::Ordering,
+    marker::PhantomData,
+    sync::{Arc, OnceLock},
+};
 
 use arrayvec::ArrayVec;
 use encase::{ShaderSize, StorageBuffer};
+use glam::Vec3;
 use ordered_float::OrderedFloat;
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTargets},
-    managers::{CameraState, InternalObject, MaterialArchetypeView, TextureBindGroupIndex},
-    types::{Material, RawObjectHandle, SampleCount, SortingOrder, SortingReason},
+    managers::{
+        CameraState, InternalObject, MaterialArchetypeView, MaterialManager, ObjectManager, ShaderInstance,
+        TextureBindGroupIndex,
+    },
+    types::{Material, RawMaterialHandle, RawObjectHandle, SampleCount, SortingOrder, SortingReason},
     util::bind_merge::BindGroupBuilder,
-    ProfileData, Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor,
+    Capability, CapabilityFallback, PassStats, ProfileData, Renderer, RendererDataCore, RendererProfile,
+    ShaderPreProcessor,
 };
 use serde::Serialize;
 use wgpu::{
-    BindGroup, BindGroupLayout, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState,
-    FragmentState, IndexFormat, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
-    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState, TextureFormat,
-    VertexState,
+    BindGroup, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, FragmentState, FrontFace, IndexFormat, MultisampleState, PipelineLayout,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, StencilState, TextureFormat, VertexState,
 };
 
+use crate::bloom::BLOOM_FORMAT;
 use crate::common::{CameraSpecifier, PerMaterialArchetypeInterface, WholeFrameInterfaces};
+use crate::culling;
+use crate::deferred::GBUFFER_FORMATS;
+use crate::instancing::{self, CulledInstances, InstanceCuller, InstanceDraw};
 use crate::uniforms::PerCameraUniform;
+use crate::velocity::VELOCITY_FORMAT;
+
+/// Set in the instance index of draws of the level of detail fading out of a
+/// cross-fade. Mirrors `LOD_FADE_OUT_BIT` in `structures_object.wgsl`.
+const LOD_FADE_OUT_BIT: u32 = 0x8000_0000;
 
 #[derive(Serialize)]
 struct ForwardPreprocessingArguments {
@@ -31,19 +49,26 @@ struct ForwardPreprocessingArguments {
     vertex_array_counts: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum RoutineType {
     Depth,
     Forward,
+    /// Writes the G-buffer of [`deferred`](crate::deferred) shading.
+    GBuffer,
 }
 
 pub struct ShaderModulePair<'a> {
     pub vs_entry: &'a str,
-    pub vs_module: &'a ShaderModule,
+    pub vs_module: &'a Arc<ShaderModule>,
     pub fs_entry: &'a str,
-    pub fs_module: &'a ShaderModule,
+    pub fs_module: &'a Arc<ShaderModule>,
 }
 
+/// Customizes the pipelines of a [`ForwardRoutine`], see
+/// [`ForwardRoutineCreateArgs::descriptor_callback`].
+pub type DescriptorCallback =
+    dyn Fn(u64, &mut RenderPipelineDescriptor<'_>, &mut [Option<ColorTargetState>]) + Send + Sync;
+
 pub struct ForwardRoutineCreateArgs<'a, M> {
     pub name: &'a str,
 
@@ -53,14 +78,33 @@ pub struct ForwardRoutineCreateArgs<'a, M> {
 
     pub interfaces: &'a WholeFrameInterfaces,
     pub per_material: &'a PerMaterialArchetypeInterface<M>,
-    pub material_key: u64,
+    /// Keys of the materials the routine draws, each with its own pipeline.
+    /// Objects of all the keys are sorted together, grouping the draws of
+    /// each pipeline as far as their sorting allows, so blended objects stay
+    /// back to front across keys.
+    pub material_keys: &'a [u64],
 
     pub routine_type: RoutineType,
     pub shaders: ShaderModulePair<'a>,
 
     pub extra_bgls: &'a [&'a BindGroupLayout],
-    #[allow(clippy::type_complexity)]
-    pub descriptor_callback: Option<&'a dyn Fn(&mut RenderPipelineDescriptor<'_>, &mut [Option<ColorTargetState>])>,
+    /// Whether the shaders dither between both levels of detail of objects
+    /// cross-fading with [`Renderer::set_object_lod`]. Otherwise only the
+    /// dominant level is drawn.
+    pub lod_cross_fade: bool,
+    /// Whether the pipelines also write the motion of each pixel into a
+    /// [`VELOCITY_FORMAT`] target after the others, see
+    /// [`velocity`](crate::velocity). The fragment shader must output it at
+    /// the next location.
+    pub velocity: bool,
+    /// Whether the pipelines also write emission into a
+    /// [`BLOOM_FORMAT`] target after the others, see [`bloom`](crate::bloom).
+    /// The fragment shader must output it at the next location.
+    pub emission: bool,
+    /// Called with the material key on the descriptor of the pipeline of each
+    /// key and sample count before it is created, which happens the first
+    /// time they are drawn.
+    pub descriptor_callback: Option<Arc<DescriptorCallback>>,
 }
 
 pub struct ForwardRoutineBindingData<'node, M> {
@@ -77,6 +121,46 @@ pub struct ForwardRoutineBindingData<'node, M> {
     pub extra_bgs: Option<&'node [BindGroup]>,
 }
 
+/// How draws within a forward pass are ordered.
+///
+/// Objects that must be sorted for correctness, such as blended objects, are
+/// always sorted by depth first, regardless of the policy.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DrawSortPolicy {
+    /// Sort by bind groups, then material, then front-to-back depth. Fewest
+    /// state changes and the least overdraw, at the cost of the most CPU time.
+    #[default]
+    Full,
+    /// Sort by bind groups and material only, skipping the distance
+    /// computation.
+    StateOnly,
+    /// Draw in the order objects are stored. Cheapest on the CPU.
+    None,
+}
+
+/// Which objects a forward pass draws, based on whether they were marked
+/// static with [`Renderer::set_object_static`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ObjectFilter {
+    /// Draw every object.
+    #[default]
+    All,
+    /// Only draw static objects.
+    Static,
+    /// Only draw objects that aren't static.
+    Dynamic,
+}
+
+impl ObjectFilter {
+    fn accepts(self, object_manager: &ObjectManager, handle: RawObjectHandle) -> bool {
+        match self {
+            Self::All => true,
+            Self::Static => object_manager.is_static(handle),
+            Self::Dynamic => !object_manager.is_static(handle),
+        }
+    }
+}
+
 pub struct ForwardRoutineArgs<'a, 'node, M> {
     pub graph: &'a mut RenderGraph<'node>,
 
@@ -88,13 +172,31 @@ pub struct ForwardRoutineArgs<'a, 'node, M> {
     /// Source of culling information, determines which triangles are rendered this pass.
     pub samples: SampleCount,
     pub renderpass: RenderPassTargets,
+    /// How draws are ordered.
+    pub sort_policy: DrawSortPolicy,
+    /// Which objects are drawn.
+    pub object_filter: ObjectFilter,
+    /// Spread frustum culling over multiple threads, see [`crate::culling`].
+    pub parallel_culling: bool,
 }
 
 /// A set of pipelines for rendering a specific combination of a material.
+///
+/// The pipeline of each sample count is created the first time the routine
+/// is added to a graph with it, so the sample count can change between frames
+/// without paying for sample counts that are never used. The instance culling
+/// pipeline is likewise created the first time instances are culled.
 pub struct ForwardRoutine<M: Material> {
-    pipeline_s1: RenderPipeline,
-    pipeline_s4: RenderPipeline,
-    material_key: u64,
+    source: ForwardPipelineSource,
+    material_keys: Vec<u64>,
+    /// Pipelines of each material key, by sample count.
+    pipelines: Vec<[OnceLock<RenderPipeline>; SampleCount::ARRAY.len()]>,
+    lod_cross_fade: bool,
+    /// Culls the instances of instanced objects on the GPU, see
+    /// [`crate::instancing`]. `None` when culling them on the CPU.
+    instance_culler: Option<InstanceCuller>,
+    /// Bound in place of the instance buffers when there are no instances.
+    empty_instances: Buffer,
     _phantom: PhantomData<M>,
 }
 impl<M: Material> ForwardRoutine<M> {
@@ -119,12 +221,17 @@ impl<M: Material> ForwardRoutine<M> {
         let mut bgls: ArrayVec<&BindGroupLayout, 8> = ArrayVec::new();
         bgls.push(match args.routine_type {
             RoutineType::Depth => &args.interfaces.depth_uniform_bgl,
-            RoutineType::Forward => &args.interfaces.forward_uniform_bgl,
+            RoutineType::Forward | RoutineType::GBuffer => &args.interfaces.forward_uniform_bgl,
         });
         bgls.push(&args.per_material.bgl);
         if args.renderer.profile == RendererProfile::GpuDriven {
             bgls.push(args.data_core.d2_texture_manager.gpu_bgl())
         } else {
+            args.renderer.register_capability_fallback(CapabilityFallback {
+                capability: Capability::Bindless,
+                routine: "ForwardRoutine",
+                fallback: "binds the textures of each material separately and draws objects one at a time",
+            });
             bgls.push(args.data_core.material_manager.get_bind_group_layout_cpu::<M>());
         }
         bgls.extend(args.extra_bgls.iter().copied());
@@ -135,26 +242,77 @@ impl<M: Material> ForwardRoutine<M> {
             push_constant_ranges: &[],
         });
 
+        let source = ForwardPipelineSource {
+            device: Arc::clone(&args.renderer.device),
+            label: args.name.to_owned(),
+            layout: pll,
+            routine_type: args.routine_type,
+            front_face: args.renderer.handedness.into(),
+            vs_module: Arc::clone(args.shaders.vs_module),
+            vs_entry: args.shaders.vs_entry.to_owned(),
+            fs_module: Arc::clone(args.shaders.fs_module),
+            fs_entry: args.shaders.fs_entry.to_owned(),
+            velocity: args.velocity,
+            emission: args.emission,
+            descriptor_callback: args.descriptor_callback,
+        };
+
+        let empty_instances = args.renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("empty instances"),
+            size: ShaderInstance::SHADER_SIZE.get(),
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
         Self {
-            pipeline_s1: build_forward_pipeline_inner(&pll, &args, SampleCount::One),
-            pipeline_s4: build_forward_pipeline_inner(&pll, &args, SampleCount::Four),
-            material_key: args.material_key,
+            source,
+            material_keys: args.material_keys.to_vec(),
+            pipelines: args.material_keys.iter().map(|_| Default::default()).collect(),
+            lod_cross_fade: args.lod_cross_fade,
+            instance_culler: InstanceCuller::new(args.renderer, args.spp, "ForwardRoutine"),
+            empty_instances,
             _phantom: PhantomData,
         }
     }
 
+    /// The pipeline of the given material key and sample count, created if it
+    /// is the first time it is used. `None` if the routine doesn't draw the
+    /// key.
+    pub fn pipeline(&self, material_key: u64, samples: SampleCount) -> Option<&RenderPipeline> {
+        let key_idx = self.material_keys.iter().position(|&k| k == material_key)?;
+        Some(self.pipeline_at(key_idx, samples))
+    }
+
+    fn pipeline_at(&self, key_idx: usize, samples: SampleCount) -> &RenderPipeline {
+        let idx = SampleCount::ARRAY.iter().position(|&s| s == samples).unwrap();
+        self.pipelines[key_idx][idx]
+            .get_or_init(|| build_forward_pipeline_inner(&self.source, self.material_keys[key_idx], samples))
+    }
+
     /// Add the given routine to the graph with the given settings.
-    pub fn add_forward_to_graph<'node>(&'node self, args: ForwardRoutineArgs<'_, 'node, M>) {
+    pub fn add_forward_to_graph<'node>(&'node self, mut args: ForwardRoutineArgs<'_, 'node, M>) {
+        let culled_instances = args.graph.add_data::<CulledInstances>();
+        self.add_instance_culling_to_graph(&mut args, culled_instances);
+
         let mut builder = args.graph.add_node(args.label);
 
         let rpass_handle = builder.add_renderpass(args.renderpass.clone(), NodeResourceUsage::InputOutput);
 
         let whole_frame_uniform_handle =
             builder.add_data(args.binding_data.whole_frame_uniform_bg, NodeResourceUsage::Input);
+        let custom_camera_handle = match args.camera {
+            CameraSpecifier::Custom(handle) => Some(builder.add_data(handle, NodeResourceUsage::Input)),
+            _ => None,
+        };
+
+        let culled_instances_handle = builder.add_data(culled_instances, NodeResourceUsage::Input);
+
+        let label = args.label.to_owned();
 
         builder.build(move |mut ctx| {
             let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
             let whole_frame_uniform_bg = ctx.graph_data.get_data(ctx.temps, whole_frame_uniform_handle).unwrap();
+            let culled_instances = ctx.graph_data.get_data(ctx.temps, culled_instances_handle);
 
             let Some(objects) = ctx.data_core.object_manager.enumerated_objects::<M>() else {
                 return;
@@ -165,9 +323,33 @@ impl<M: Material> ForwardRoutine<M> {
             let camera = match args.camera {
                 CameraSpecifier::Viewport => &ctx.data_core.viewport_camera_state,
                 CameraSpecifier::Shadow(idx) => &ctx.eval_output.shadows[idx as usize].camera,
+                CameraSpecifier::Custom(_) => match ctx.graph_data.get_data(ctx.temps, custom_camera_handle.unwrap()) {
+                    Some(camera) => camera,
+                    None => return,
+                },
             };
 
-            let objects = sort(objects, archetype_view, self.material_key, camera);
+            let shadow_map = match args.camera {
+                CameraSpecifier::Shadow(idx) => Some(idx as usize),
+                _ => None,
+            };
+            let filter = ObjectSelection {
+                filter: args.object_filter,
+                object_manager: &ctx.data_core.object_manager,
+                shadow_map: shadow_map.map(|idx| (idx, ctx.data_core.viewport_camera_state.location())),
+            };
+            let (objects, objects_submitted) = sort(
+                objects,
+                archetype_view,
+                &self.material_keys,
+                camera,
+                args.sort_policy,
+                filter,
+                args.parallel_culling,
+            );
+            if args.camera == CameraSpecifier::Viewport {
+                ctx.renderer.record_visible_objects(objects.iter().map(|&(handle, _)| handle));
+            }
 
             let per_camera_uniform_values = PerCameraUniform {
                 view: camera.view(),
@@ -193,15 +375,13 @@ impl<M: Material> ForwardRoutine<M> {
                     .append_buffer(&ctx.eval_output.mesh_buffer)
                     .append_buffer(per_camera_uniform_buffer)
                     .append_buffer(ctx.data_core.material_manager.archetype_view::<M>().buffer())
+                    .append_buffer(ctx.data_core.object_manager.instance_buffer().unwrap_or(&self.empty_instances))
+                    .append_buffer(culled_instances.map_or(&self.empty_instances, |culled| &culled.buffer))
                     .build(&ctx.renderer.device, Some("Per-Material BG"), &args.binding_data.per_material_bgl.bgl),
             );
 
-            let pipeline = match args.samples {
-                SampleCount::One => &self.pipeline_s1,
-                SampleCount::Four => &self.pipeline_s4,
-            };
-            rpass.set_index_buffer(ctx.eval_output.mesh_buffer.slice(..), IndexFormat::Uint32);
-            rpass.set_pipeline(pipeline);
+            let mut bound_index_format = IndexFormat::Uint32;
+            rpass.set_index_buffer(ctx.eval_output.mesh_buffer.slice(..), bound_index_format);
             rpass.set_bind_group(0, whole_frame_uniform_bg, &[]);
             if let Some(v) = args.binding_data.extra_bgs {
                 for (idx, bg) in v.iter().enumerate() {
@@ -211,69 +391,340 @@ impl<M: Material> ForwardRoutine<M> {
             if let ProfileData::Gpu(ref bg) = ctx.eval_output.d2_texture.bg {
                 rpass.set_bind_group(2, bg, &[]);
             }
+            rpass.set_bind_group(1, per_material_bg, &[]);
+
+            let mut stats = PassStats {
+                label,
+                view: view_label(args.camera),
+                objects_submitted,
+                objects_culled: objects_submitted - objects.len() as u32,
+                ..PassStats::default()
+            };
 
+            profiling::scope!("Draw Call Generation");
+            let mut bound_texture_bind_group = None;
+            let mut bound_pipeline = None;
             for (idx, object) in objects.into_iter() {
                 let material = archetype_view.material(*object.material_handle);
-                if material.inner.key() != self.material_key {
+                let (key, _) = ctx.data_core.object_manager.material_key(idx, object, &material.inner);
+                let Some(key_idx) = self.material_keys.iter().position(|&k| k == key) else {
                     continue;
+                };
+                if bound_pipeline != Some(key_idx) {
+                    rpass.set_pipeline(self.pipeline_at(key_idx, args.samples));
+                    bound_pipeline = Some(key_idx);
                 }
 
                 // If we're in cpu driven mode, we need to update the texture bind group.
                 if ctx.renderer.profile.is_cpu_driven() {
                     let texture_bind_group = material.bind_group_index.into_cpu();
-                    rpass.set_bind_group(2, ctx.data_core.material_manager.texture_bind_group(texture_bind_group), &[]);
+                    if bound_texture_bind_group != Some(texture_bind_group) {
+                        rpass.set_bind_group(
+                            2,
+                            ctx.data_core.material_manager.texture_bind_group(texture_bind_group),
+                            &[],
+                        );
+                        bound_texture_bind_group = Some(texture_bind_group);
+                    }
                 }
-                rpass.set_bind_group(1, per_material_bg, &[]);
-                rpass.draw_indexed(
-                    object.inner.first_index..object.inner.first_index + object.inner.index_count,
-                    0,
-                    idx.idx as u32..idx.idx as u32 + 1,
-                )
+                if object.index_format != bound_index_format {
+                    rpass.set_index_buffer(ctx.eval_output.mesh_buffer.slice(..), object.index_format);
+                    bound_index_format = object.index_format;
+                }
+                // Shadow passes draw lower levels of detail.
+                let bias = match shadow_map {
+                    Some(_) => ctx.data_core.object_manager.shadow_settings(idx).lod_bias,
+                    None => 0,
+                };
+                let debug_group = debug_group_label(
+                    &ctx.data_core.object_manager,
+                    &ctx.data_core.material_manager,
+                    idx,
+                    *object.material_handle,
+                );
+                if let Some(ref label) = debug_group {
+                    rpass.push_debug_group(label);
+                }
+                let lod = ctx.data_core.object_manager.lod(idx);
+                let instance = idx.idx as u32;
+                if ctx.data_core.object_manager.is_instanced(idx) {
+                    // Objects without instances left after culling aren't drawn.
+                    match culled_instances.and_then(|culled| Some((culled, culled.draws.get(&idx)?))) {
+                        Some((culled, InstanceDraw::Indirect { offset, submitted })) => {
+                            // How many instances are left is only known on the GPU, so this
+                            // counts the instances before culling, and no triangles.
+                            rpass.draw_indexed_indirect(culled.indirect.as_ref().unwrap(), *offset);
+                            stats.draw_calls += 1;
+                            stats.instances += submitted;
+                        }
+                        Some((_, InstanceDraw::Direct { indices, instances })) => {
+                            stats.triangles += (indices.len() * instances.len()) as u64 / 3;
+                            rpass.draw_indexed(indices.clone(), 0, instances.clone());
+                            stats.draw_calls += 1;
+                            stats.instances += instances.len() as u32;
+                        }
+                        None => {}
+                    }
+                    if debug_group.is_some() {
+                        rpass.pop_debug_group();
+                    }
+                    continue;
+                }
+                if self.lod_cross_fade && lod.is_cross_fading() {
+                    let indices = object.lod_index_range(lod.lod + bias);
+                    stats.triangles += indices.len() as u64 / 3;
+                    rpass.draw_indexed(indices, 0, instance..instance + 1);
+
+                    let indices = object.lod_index_range(lod.previous + bias);
+                    stats.triangles += indices.len() as u64 / 3;
+                    let instance = instance | LOD_FADE_OUT_BIT;
+                    rpass.draw_indexed(indices, 0, instance..instance + 1);
+
+                    stats.draw_calls += 2;
+                } else {
+                    let indices = object.lod_index_range(lod.dominant() + bias);
+                    stats.triangles += indices.len() as u64 / 3;
+                    rpass.draw_indexed(indices, 0, instance..instance + 1);
+
+                    stats.draw_calls += 1;
+                }
+                if debug_group.is_some() {
+                    rpass.pop_debug_group();
+                }
+                stats.instances += 1;
+            }
+
+            ctx.renderer.record_pass_stats(stats);
+        });
+    }
+
+    /// Adds the node culling the instances of the instanced objects the pass
+    /// draws, see [`crate::instancing`].
+    fn add_instance_culling_to_graph<'node>(
+        &'node self,
+        args: &mut ForwardRoutineArgs<'_, 'node, M>,
+        culled_instances: DataHandle<CulledInstances>,
+    ) {
+        let mut builder = args.graph.add_node(format!("{} instance culling", args.label));
+
+        let culled_instances_handle = builder.add_data(culled_instances, NodeResourceUsage::Output);
+        let custom_camera_handle = match args.camera {
+            CameraSpecifier::Custom(handle) => Some(builder.add_data(handle, NodeResourceUsage::Input)),
+            _ => None,
+        };
+
+        let label = format!("{} instance culling", args.label);
+        let camera_specifier = args.camera;
+        let object_filter = args.object_filter;
+
+        builder.build(move |mut ctx| {
+            let object_manager = &ctx.data_core.object_manager;
+            if object_manager.instance_buffer().is_none() {
+                return;
             }
+            let Some(objects) = object_manager.enumerated_objects::<M>() else {
+                return;
+            };
+
+            let camera = match camera_specifier {
+                CameraSpecifier::Viewport => &ctx.data_core.viewport_camera_state,
+                CameraSpecifier::Shadow(idx) => &ctx.eval_output.shadows[idx as usize].camera,
+                CameraSpecifier::Custom(_) => match ctx.graph_data.get_data(ctx.temps, custom_camera_handle.unwrap()) {
+                    Some(camera) => camera,
+                    None => return,
+                },
+            };
+            let shadow_map = match camera_specifier {
+                CameraSpecifier::Shadow(idx) => Some(idx as usize),
+                _ => None,
+            };
+            let selection = ObjectSelection {
+                filter: object_filter,
+                object_manager,
+                shadow_map: shadow_map.map(|idx| (idx, ctx.data_core.viewport_camera_state.location())),
+            };
+
+            let instanced = objects.filter(|&(handle, _)| object_manager.is_instanced(handle));
+            let (visible, _) = visible_objects(
+                instanced,
+                ctx.data_core.material_manager.archetype_view::<M>(),
+                &self.material_keys,
+                camera,
+                selection,
+                false,
+            );
+            let objects = visible
+                .into_iter()
+                .map(|(handle, object)| {
+                    let bias = match shadow_map {
+                        Some(_) => object_manager.shadow_settings(handle).lod_bias,
+                        None => 0,
+                    };
+                    (handle, object, object.lod_index_range(object_manager.lod(handle).dominant() + bias))
+                })
+                .collect();
+
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let culled = instancing::cull_instances(
+                ctx.renderer,
+                encoder,
+                &label,
+                object_manager,
+                self.instance_culler.as_ref(),
+                &camera.world_frustum(),
+                objects,
+            );
+            if culled.is_some() && self.instance_culler.is_some() {
+                ctx.renderer.record_pass_stats(PassStats { label, compute_dispatches: 1, ..PassStats::default() });
+            }
+            ctx.graph_data.set_data(culled_instances_handle, culled);
         });
     }
 }
 
-fn sort<'a, M, I>(
+/// Name of the view of a camera in the per-view frame stats.
+fn view_label(camera: CameraSpecifier) -> String {
+    match camera {
+        CameraSpecifier::Viewport => String::from("viewport"),
+        CameraSpecifier::Shadow(idx) => format!("shadow {idx}"),
+        CameraSpecifier::Custom(_) => String::from("custom"),
+    }
+}
+
+/// Name of the debug group around the draws of an object in graphics
+/// debuggers, made of the labels of the object, its mesh and its material.
+/// `None` if none of them have a label.
+fn debug_group_label(
+    object_manager: &ObjectManager,
+    material_manager: &MaterialManager,
+    handle: RawObjectHandle,
+    material: RawMaterialHandle,
+) -> Option<String> {
+    let object_label = object_manager.label(handle);
+    let mesh_label = object_manager.mesh_label(handle);
+    let material_label = material_manager.label(material);
+    if object_label.is_none() && mesh_label.is_none() && material_label.is_none() {
+        return None;
+    }
+
+    Some(format!(
+        "{} (mesh {}, material {})",
+        object_label.map_or_else(|| format!("object {}", handle.idx), str::to_owned),
+        mesh_label.unwrap_or("unlabeled"),
+        material_label.unwrap_or("unlabeled"),
+    ))
+}
+
+/// Which objects a pass considers drawing, before culling.
+struct ObjectSelection<'a> {
+    filter: ObjectFilter,
+    object_manager: &'a ObjectManager,
+    /// For shadow passes, the index of the shadow map and the location of the
+    /// viewport camera, which shadow distances are measured from.
+    shadow_map: Option<(usize, Vec3)>,
+}
+
+impl ObjectSelection<'_> {
+    /// Whether the object belongs in the view at all. Shadow-only objects
+    /// are only part of shadow passes.
+    fn in_view(&self, handle: RawObjectHandle) -> bool {
+        self.shadow_map.is_some() || !self.object_manager.shadow_settings(handle).shadow_only
+    }
+}
+
+/// The objects with the requested material key that survive culling, also
+/// returning how many objects had the requested material key. Fading objects
+/// use the faded key of their material.
+fn visible_objects<'a, M, I>(
     objects: I,
     material_archetype: MaterialArchetypeView<'_, M>,
-    requested_material_key: u64,
+    requested_material_keys: &[u64],
     camera: &CameraState,
-) -> Vec<(RawObjectHandle, &'a InternalObject<M>)>
+    selection: ObjectSelection<'_>,
+    parallel_culling: bool,
+) -> (Vec<(RawObjectHandle, &'a InternalObject<M>)>, u32)
 where
     M: Material,
     I: IntoIterator<Item = (RawObjectHandle, &'a InternalObject<M>)>,
-    I::IntoIter: ExactSizeIterator,
 {
     let objects = objects.into_iter();
 
-    let mut sorted_objects = Vec::with_capacity(objects.len());
+    let mut candidates = Vec::with_capacity(objects.size_hint().0);
+    for (raw_handle, object) in objects {
+        let material = material_archetype.material(*object.material_handle);
+        let (key, _) = selection.object_manager.material_key(raw_handle, object, &material.inner);
+        if requested_material_keys.contains(&key)
+            && selection.filter.accepts(selection.object_manager, raw_handle)
+            && selection.in_view(raw_handle)
+        {
+            candidates.push((raw_handle, object));
+        }
+    }
+    let submitted = candidates.len() as u32;
+
+    if let Some((shadow_map, viewer)) = selection.shadow_map {
+        let object_manager = selection.object_manager;
+        candidates.retain(|&(raw_handle, object)| {
+            culling::casts_shadow(&object_manager.shadow_settings(raw_handle), object, shadow_map, viewer)
+        });
+    }
+
+    (culling::cull_objects(&camera.world_frustum(), candidates, parallel_culling), submitted)
+}
+
+/// Sorts the objects with the requested material key that survive culling,
+/// also returning how many objects had the requested material key. Fading
+/// objects use the faded key of their material.
+#[allow(clippy::too_many_arguments)]
+fn sort<'a, M, I>(
+    objects: I,
+    material_archetype: MaterialArchetypeView<'_, M>,
+    requested_material_keys: &[u64],
+    camera: &CameraState,
+    policy: DrawSortPolicy,
+    selection: ObjectSelection<'_>,
+    parallel_culling: bool,
+) -> (Vec<(RawObjectHandle, &'a InternalObject<M>)>, u32)
+where
+    M: Material,
+    I: IntoIterator<Item = (RawObjectHandle, &'a InternalObject<M>)>,
+{
+    let object_manager = selection.object_manager;
+    let (visible, submitted) =
+        visible_objects(objects, material_archetype, requested_material_keys, camera, selection, parallel_culling);
+
+    let mut sorted_objects = Vec::with_capacity(visible.len());
     {
         profiling::scope!("Sort Key Creation");
-        for (raw_handle, object) in objects {
+        for (raw_handle, object) in visible {
             let material = material_archetype.material(*object.material_handle);
-            let object_material_key = material.inner.key();
-            let sorting = material.inner.sorting();
-
-            if object_material_key != requested_material_key {
-                continue;
-            }
-
-            // Frustum culling
-            if !camera.world_frustum().contains_sphere(object.inner.bounding_sphere) {
-                continue;
-            }
+            let (key, sorting) = object_manager.material_key(raw_handle, object, &material.inner);
+            let pipeline = requested_material_keys.iter().position(|&k| k == key).unwrap();
 
             let bind_group_index = material.bind_group_index.map_gpu(|_| TextureBindGroupIndex::DUMMY).into_common();
 
-            let mut distance_sq = camera.location().distance_squared(object.location.into());
+            let order = object_manager.render_order(raw_handle);
+
+            let needs_distance = sorting.reason == SortingReason::Requirement || policy == DrawSortPolicy::Full;
+            let mut distance_sq = match needs_distance {
+                true => camera.location().distance_squared(object.location.into()),
+                false => 0.0,
+            };
+            if needs_distance && order.sort_bias != 0.0 {
+                // Keeps the sign, so biases can move objects past the camera.
+                let distance = distance_sq.sqrt() + order.sort_bias;
+                distance_sq = distance * distance.abs();
+            }
 
             if sorting.order == SortingOrder::BackToFront {
                 distance_sq = -distance_sq;
             }
             sorted_objects.push((
                 ObjectSortingKey {
+                    render_order: order.key,
+                    pipeline,
                     bind_group_index,
+                    material_index: object.material_handle.idx,
                     distance: OrderedFloat(distance_sq),
                     sorting_reason: sorting.reason,
                 },
@@ -282,17 +733,23 @@ where
         }
     }
 
+    if policy != DrawSortPolicy::None
+        || sorted_objects.iter().any(|(k, _)| k.sorting_reason == SortingReason::Requirement || k.render_order != 0)
     {
         profiling::scope!("Sorting");
         sorted_objects.sort_unstable_by_key(|(k, _)| *k);
     }
 
-    sorted_objects.into_iter().map(|(_, o)| o).collect()
+    (sorted_objects.into_iter().map(|(_, o)| o).collect(), submitted)
 }
 
 #[derive(Debug, Clone, Copy, Eq)]
 pub(super) struct ObjectSortingKey {
+    pub render_order: i32,
+    /// Index of the material key, and so the pipeline, of the object.
+    pub pipeline: usize,
     pub bind_group_index: TextureBindGroupIndex,
+    pub material_index: usize,
     pub distance: OrderedFloat<f32>,
     pub sorting_reason: SortingReason,
 }
@@ -311,51 +768,92 @@ impl PartialOrd for ObjectSortingKey {
 
 impl Ord for ObjectSortingKey {
     fn cmp(&self, other: &Self) -> Ordering {
+        match self.render_order.cmp(&other.render_order) {
+            Ordering::Equal => {}
+            ord => return ord,
+        }
         match self.sorting_reason.cmp(&other.sorting_reason) {
             Ordering::Equal => {}
             ord => return ord,
         }
         // The above comparison means that both sides are equal
         if self.sorting_reason == SortingReason::Requirement {
-            match self.distance.cmp(&other.distance) {
-                Ordering::Equal => {}
-                ord => return ord,
-            }
-            self.bind_group_index.cmp(&other.bind_group_index)
+            self.distance
+                .cmp(&other.distance)
+                .then(self.pipeline.cmp(&other.pipeline))
+                .then(self.bind_group_index.cmp(&other.bind_group_index))
+                .then(self.material_index.cmp(&other.material_index))
         } else {
-            match self.bind_group_index.cmp(&other.bind_group_index) {
-                Ordering::Equal => {}
-                ord => return ord,
-            }
-            self.distance.cmp(&other.distance)
+            self.pipeline
+                .cmp(&other.pipeline)
+                .then(self.bind_group_index.cmp(&other.bind_group_index))
+                .then(self.material_index.cmp(&other.material_index))
+                .then(self.distance.cmp(&other.distance))
         }
     }
 }
 
-fn build_forward_pipeline_inner<M: Material>(
-    pll: &wgpu::PipelineLayout,
-    args: &ForwardRoutineCreateArgs<'_, M>,
+/// What the pipelines of a [`ForwardRoutine`] are created from.
+struct ForwardPipelineSource {
+    device: Arc<Device>,
+    label: String,
+    layout: PipelineLayout,
+    routine_type: RoutineType,
+    front_face: FrontFace,
+    vs_module: Arc<ShaderModule>,
+    vs_entry: String,
+    fs_module: Arc<ShaderModule>,
+    fs_entry: String,
+    velocity: bool,
+    emission: bool,
+    descriptor_callback: Option<Arc<DescriptorCallback>>,
+}
+
+fn build_forward_pipeline_inner(
+    source: &ForwardPipelineSource,
+    material_key: u64,
     samples: SampleCount,
 ) -> RenderPipeline {
-    let mut render_targets: ArrayVec<_, 1> = ArrayVec::new();
-    if matches!(args.routine_type, RoutineType::Forward) {
-        render_targets.push(Some(ColorTargetState {
+    profiling::scope!("build forward pipeline");
+
+    let mut render_targets: ArrayVec<_, 6> = ArrayVec::new();
+    match source.routine_type {
+        RoutineType::Depth => {}
+        RoutineType::Forward => render_targets.push(Some(ColorTargetState {
             format: TextureFormat::Rgba16Float,
             blend: None,
             write_mask: ColorWrites::all(),
+        })),
+        RoutineType::GBuffer => render_targets.extend(
+            GBUFFER_FORMATS
+                .map(|format| Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::all() })),
+        ),
+    }
+    if source.velocity {
+        render_targets.push(Some(ColorTargetState {
+            format: VELOCITY_FORMAT,
+            blend: None,
+            write_mask: ColorWrites::all(),
+        }));
+    }
+    if source.emission {
+        render_targets.push(Some(ColorTargetState {
+            format: BLOOM_FORMAT,
+            blend: None,
+            write_mask: ColorWrites::all(),
         }));
     }
     let mut desc = RenderPipelineDescriptor {
-        label: Some(args.name),
-        layout: Some(pll),
-        vertex: VertexState { module: args.shaders.vs_module, entry_point: args.shaders.vs_entry, buffers: &[] },
+        label: Some(&source.label),
+        layout: Some(&source.layout),
+        vertex: VertexState { module: &source.vs_module, entry_point: &source.vs_entry, buffers: &[] },
         primitive: PrimitiveState {
             topology: PrimitiveTopology::TriangleList,
             strip_index_format: None,
-            front_face: args.renderer.handedness.into(),
-            cull_mode: Some(match args.routine_type {
+            front_face: source.front_face,
+            cull_mode: Some(match source.routine_type {
                 RoutineType::Depth => wgpu::Face::Front,
-                RoutineType::Forward => wgpu::Face::Back,
+                RoutineType::Forward | RoutineType::GBuffer => wgpu::Face::Back,
             }),
             unclipped_depth: false,
             polygon_mode: PolygonMode::Fill,
@@ -366,23 +864,19 @@ fn build_forward_pipeline_inner<M: Material>(
             depth_write_enabled: true,
             depth_compare: CompareFunction::GreaterEqual,
             stencil: StencilState::default(),
-            bias: match args.routine_type {
+            bias: match source.routine_type {
                 // TODO: figure out what to put here
                 RoutineType::Depth => DepthBiasState { constant: 0, slope_scale: 0.0, clamp: 0.0 },
-                RoutineType::Forward => DepthBiasState::default(),
+                RoutineType::Forward | RoutineType::GBuffer => DepthBiasState::default(),
             },
         }),
         multisample: MultisampleState { count: samples as u32, ..Default::default() },
-        fragment: Some(FragmentState {
-            module: args.shaders.fs_module,
-            entry_point: args.shaders.fs_entry,
-            targets: &[],
-        }),
+        fragment: Some(FragmentState { module: &source.fs_module, entry_point: &source.fs_entry, targets: &[] }),
         multiview: None,
     };
-    if let Some(desc_callback) = args.descriptor_callback {
-        desc_callback(&mut desc, &mut render_targets);
+    if let Some(ref desc_callback) = source.descriptor_callback {
+        desc_callback(material_key, &mut desc, &mut render_targets);
     }
     desc.fragment.as_mut().unwrap().targets = &render_targets;
-    args.renderer.device.create_render_pipeline(&desc)
+    source.device.create_render_pipeline(&desc)
 }
@@ -0,0 +1,324 @@
+//! Adaptive quality, to hold a frame rate on unknown hardware.
+//!
+//! [`PerformanceGovernor`] watches the GPU time of every frame, and steps
+//! quality knobs down while frames take longer than the target, and back up
+//! once there is room again. The knobs are given in priority order: the
+//! first knob is lowered first, down to its lowest level before the next one
+//! is touched, and raised last.
+//!
+//! The governor only picks the levels, the application applies them before
+//! rendering every frame, like [`PerformanceGovernor::render_scale`] to
+//! [`OutputRenderTarget::render_scale`](crate::base::OutputRenderTarget::render_scale)
+//! and [`PerformanceGovernor::shadow_resolution`] to the directional lights.
+//!
+//! GPU times are read back a few frames late, so the governor ignores the
+//! frames right after a change. Quality is only raised after frames stayed
+//! well under the target for a while, and every raise that has to be undone
+//! doubles that wait, so the levels don't oscillate.
+
+use std::time::Duration;
+
+use rend3::Renderer;
+
+/// Weight of the newest frame in the running average of frame times.
+const SMOOTHING: f32 = 0.1;
+/// Undone raises double the wait before the next raise up to this many times.
+const MAX_RAISE_BACKOFF: u32 = 3;
+
+/// A setting the governor can lower, with its levels going from the lowest
+/// quality at level 0 to full quality.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QualityKnob {
+    /// Render scale from 1.0 down to `min`, in `steps` even steps.
+    RenderScale { min: f32, steps: u32 },
+    /// Directional light shadow map resolution, halved at every level down
+    /// from `max`, but not below `min`.
+    ShadowResolution { max: u16, min: u16 },
+    /// An effect named by the application, on at full quality and off
+    /// otherwise.
+    Effect(String),
+}
+
+impl QualityKnob {
+    /// The level of full quality.
+    pub fn max_level(&self) -> u32 {
+        match *self {
+            Self::RenderScale { steps, .. } => steps,
+            Self::ShadowResolution { max, min } => (max / min.max(1)).max(1).ilog2(),
+            Self::Effect(_) => 1,
+        }
+    }
+}
+
+/// When the [`PerformanceGovernor`] changes levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GovernorSettings {
+    /// GPU time a frame should stay under.
+    pub target_frame_time: Duration,
+    /// Fraction of the target frames must stay under before quality is
+    /// raised. With 0.2, quality is only raised while frames take less than
+    /// 80% of the target.
+    pub headroom: f32,
+    /// Frames in a row over the target before quality is lowered.
+    pub lower_frames: u32,
+    /// Frames in a row under the headroom before quality is raised.
+    pub raise_frames: u32,
+    /// Frames ignored after every change, while the times of frames rendered
+    /// with the previous levels are still being read back.
+    pub settle_frames: u32,
+}
+
+impl GovernorSettings {
+    /// Settings holding `frame_rate` frames per second.
+    pub fn from_frame_rate(frame_rate: f32) -> Self {
+        Self { target_frame_time: Duration::from_secs_f32(1.0 / frame_rate), ..Self::default() }
+    }
+}
+
+impl Default for GovernorSettings {
+    fn default() -> Self {
+        Self {
+            target_frame_time: Duration::from_micros(16_667),
+            headroom: 0.2,
+            lower_frames: 10,
+            raise_frames: 120,
+            settle_frames: 5,
+        }
+    }
+}
+
+/// Adjusts quality knobs to hold a target frame time. See the module
+/// documentation.
+pub struct PerformanceGovernor {
+    settings: GovernorSettings,
+    knobs: Vec<QualityKnob>,
+    levels: Vec<u32>,
+    /// Running average of the frame time in seconds, since the last change.
+    average: Option<f32>,
+    frames_over: u32,
+    frames_under: u32,
+    settling: u32,
+    /// Frames since the last raise, until it is known to hold.
+    since_raise: Option<u32>,
+    raise_backoff: u32,
+}
+
+impl PerformanceGovernor {
+    /// Creates a governor of the given knobs, in the order they are lowered,
+    /// starting at full quality.
+    pub fn new(settings: GovernorSettings, knobs: Vec<QualityKnob>) -> Self {
+        let levels = knobs.iter().map(QualityKnob::max_level).collect();
+        Self {
+            settings,
+            knobs,
+            levels,
+            average: None,
+            frames_over: 0,
+            frames_under: 0,
+            settling: 0,
+            since_raise: None,
+            raise_backoff: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &GovernorSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: GovernorSettings) {
+        self.settings = settings;
+    }
+
+    /// Records the GPU time of the latest frame read back from `renderer`.
+    /// Call once per frame. Returns true if a level changed.
+    ///
+    /// Does nothing on devices without
+    /// [`Features::TIMESTAMP_QUERY`](wgpu::Features::TIMESTAMP_QUERY), where
+    /// [`Self::record_frame_time`] can be given a frame time measured some
+    /// other way.
+    pub fn update(&mut self, renderer: &Renderer) -> bool {
+        let times = renderer.last_frame_gpu_times();
+        if times.is_empty() {
+            return false;
+        }
+        let frame_time = times.iter().filter(|time| time.depth == 0).map(|time| time.duration).sum();
+        self.record_frame_time(frame_time)
+    }
+
+    /// Records the time of a frame. Returns true if a level changed.
+    pub fn record_frame_time(&mut self, frame_time: Duration) -> bool {
+        if self.settling > 0 {
+            self.settling -= 1;
+            return false;
+        }
+
+        let frame_time = frame_time.as_secs_f32();
+        let average = match self.average {
+            Some(average) => average + (frame_time - average) * SMOOTHING,
+            None => frame_time,
+        };
+        self.average = Some(average);
+
+        if let Some(frames) = &mut self.since_raise {
+            *frames += 1;
+            if *frames > self.settings.raise_frames {
+                self.since_raise = None;
+                self.raise_backoff = 0;
+            }
+        }
+
+        let target = self.settings.target_frame_time.as_secs_f32();
+        if average > target {
+            self.frames_under = 0;
+            self.frames_over += 1;
+            if self.frames_over >= self.settings.lower_frames {
+                self.frames_over = 0;
+                return self.lower();
+            }
+        } else if average < target * (1.0 - self.settings.headroom) {
+            self.frames_over = 0;
+            self.frames_under += 1;
+            if self.frames_under >= self.settings.raise_frames << self.raise_backoff {
+                self.frames_under = 0;
+                return self.raise();
+            }
+        } else {
+            self.frames_over = 0;
+            self.frames_under = 0;
+        }
+        false
+    }
+
+    /// Lowers the first knob that isn't at its lowest level.
+    fn lower(&mut self) -> bool {
+        let Some(level) = self.levels.iter_mut().find(|level| **level > 0) else {
+            return false;
+        };
+        *level -= 1;
+        if self.since_raise.take().is_some() {
+            self.raise_backoff = (self.raise_backoff + 1).min(MAX_RAISE_BACKOFF);
+        }
+        self.changed();
+        true
+    }
+
+    /// Raises the last knob that isn't at full quality.
+    fn raise(&mut self) -> bool {
+        let Some(index) = (0..self.knobs.len()).rev().find(|&i| self.levels[i] < self.knobs[i].max_level()) else {
+            return false;
+        };
+        self.levels[index] += 1;
+        self.since_raise = Some(0);
+        self.changed();
+        true
+    }
+
+    fn changed(&mut self) {
+        self.average = None;
+        self.frames_over = 0;
+        self.frames_under = 0;
+        self.settling = self.settings.settle_frames;
+    }
+
+    /// Puts every knob back to full quality, like after loading a new scene.
+    pub fn reset(&mut self) {
+        for (level, knob) in self.levels.iter_mut().zip(&self.knobs) {
+            *level = knob.max_level();
+        }
+        self.since_raise = None;
+        self.raise_backoff = 0;
+        self.changed();
+    }
+
+    /// The knobs, in the order they are lowered.
+    pub fn knobs(&self) -> &[QualityKnob] {
+        &self.knobs
+    }
+
+    /// Current level of the knob at `index`, from 0 to
+    /// [`QualityKnob::max_level`].
+    pub fn level(&self, index: usize) -> u32 {
+        self.levels[index]
+    }
+
+    /// Render scale of the first [`QualityKnob::RenderScale`], if any.
+    pub fn render_scale(&self) -> Option<f32> {
+        self.knobs.iter().zip(&self.levels).find_map(|(knob, &level)| match *knob {
+            QualityKnob::RenderScale { min, steps } => Some(min + (1.0 - min) * level as f32 / steps.max(1) as f32),
+            _ => None,
+        })
+    }
+
+    /// Shadow map resolution of the first [`QualityKnob::ShadowResolution`],
+    /// if any.
+    pub fn shadow_resolution(&self) -> Option<u16> {
+        self.knobs.iter().zip(&self.levels).find_map(|(knob, &level)| match *knob {
+            QualityKnob::ShadowResolution { max, min } => Some((max >> (knob.max_level() - level)).max(min)),
+            _ => None,
+        })
+    }
+
+    /// Whether the [`QualityKnob::Effect`] called `name` is on. Effects
+    /// without a knob are always on.
+    pub fn effect_enabled(&self, name: &str) -> bool {
+        self.knobs.iter().zip(&self.levels).all(|(knob, &level)| match knob {
+            QualityKnob::Effect(effect) => effect != name || level > 0,
+            _ => true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{GovernorSettings, PerformanceGovernor, QualityKnob};
+
+    fn governor() -> PerformanceGovernor {
+        let settings = GovernorSettings {
+            target_frame_time: Duration::from_millis(10),
+            headroom: 0.2,
+            lower_frames: 2,
+            raise_frames: 4,
+            settle_frames: 1,
+        };
+        let knobs = vec![QualityKnob::Effect("bloom".into()), QualityKnob::RenderScale { min: 0.5, steps: 2 }];
+        PerformanceGovernor::new(settings, knobs)
+    }
+
+    /// Feeds frames of `millis` until a level changes, returning how many it took.
+    fn frames_until_change(governor: &mut PerformanceGovernor, millis: u64) -> Option<u32> {
+        (1..=1000).find(|_| governor.record_frame_time(Duration::from_millis(millis)))
+    }
+
+    #[test]
+    fn lowers_in_priority_order_and_raises_in_reverse() {
+        let mut governor = governor();
+        assert_eq!(governor.render_scale(), Some(1.0));
+
+        frames_until_change(&mut governor, 20).unwrap();
+        assert!(!governor.effect_enabled("bloom"));
+        assert_eq!(governor.render_scale(), Some(1.0));
+
+        frames_until_change(&mut governor, 20).unwrap();
+        frames_until_change(&mut governor, 20).unwrap();
+        assert_eq!(governor.render_scale(), Some(0.5));
+        assert_eq!(frames_until_change(&mut governor, 20), None);
+
+        frames_until_change(&mut governor, 5).unwrap();
+        assert_eq!(governor.render_scale(), Some(0.75));
+        assert!(!governor.effect_enabled("bloom"));
+        assert!(governor.effect_enabled("fog"));
+    }
+
+    #[test]
+    fn undone_raises_wait_longer() {
+        let mut governor = governor();
+        frames_until_change(&mut governor, 20).unwrap();
+
+        // Settling, then the raise wait.
+        assert_eq!(frames_until_change(&mut governor, 5), Some(1 + 4));
+        frames_until_change(&mut governor, 20).unwrap();
+        assert_eq!(frames_until_change(&mut governor, 5), Some(1 + 8));
+    }
+}
@@ -0,0 +1,269 @@
+//! Rendering expensive effects at half resolution.
+//!
+//! Effects like SSAO, volumetrics and SSR are often smooth enough to render at
+//! a quarter of the pixels. [`HalfResolutionRoutine`] provides the two nodes
+//! around such an effect:
+//!
+//! - [`add_depth_to_graph`](HalfResolutionRoutine::add_depth_to_graph)
+//!   downsamples the scene depth to [`half_resolution`], keeping the nearest
+//!   depth of each 2x2 block, for the effect to read.
+//! - [`add_upsample_to_graph`](HalfResolutionRoutine::add_upsample_to_graph)
+//!   brings the result of the effect back to full resolution. Each pixel blends
+//!   the nearest half resolution texels whose depth matches its own, so the
+//!   effect doesn't bleed across the edges of objects.
+//!
+//! Effects upsampling in their own shaders, for example to combine the result
+//! with the scene color, can include `rend3-routine/math/bilateral_upsample.wgsl`
+//! and call `bilateral_upsample` with the same textures:
+//!
+//! ```wgsl
+//! {{include "rend3-routine/math/bilateral_upsample.wgsl"}}
+//!
+//! @group(0) @binding(0)
+//! var occlusion: texture_2d<f32>;
+//! @group(0) @binding(1)
+//! var half_depth: texture_2d<f32>;
+//! @group(0) @binding(2)
+//! var depth: texture_depth_2d;
+//!
+//! @fragment
+//! fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+//!     let d = textureLoad(depth, vec2<u32>(position.xy), 0);
+//!     let ao = bilateral_upsample(occlusion, half_depth, position.xy, d).r;
+//!     return vec4<f32>(0.0, 0.0, 0.0, 1.0 - ao);
+//! }
+//! ```
+
+use std::borrow::Cow;
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor,
+        RenderTargetHandle,
+    },
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, BlendState, ColorTargetState, ColorWrites, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType,
+    TextureUsages, TextureViewDimension, VertexState,
+};
+
+/// Format of the half resolution depth made by
+/// [`HalfResolutionRoutine::add_depth_to_graph`].
+pub const HALF_DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+/// Half of the given resolution, rounded up so every pixel is covered.
+pub fn half_resolution(resolution: UVec2) -> UVec2 {
+    (resolution + 1) / 2
+}
+
+/// Downsamples depth for, and upsamples the results of, effects rendered at
+/// half resolution.
+///
+/// See module for documentation.
+pub struct HalfResolutionRoutine {
+    depth_bgl: BindGroupLayout,
+    depth_pipeline: RenderPipeline,
+    upsample_bgl: BindGroupLayout,
+    upsample_pipeline: RenderPipeline,
+}
+
+impl HalfResolutionRoutine {
+    /// Creates the routine, upsampling into targets of the given format,
+    /// blended with `blend`.
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        format: TextureFormat,
+        blend: Option<BlendState>,
+    ) -> Self {
+        profiling::scope!("HalfResolutionRoutine::new");
+
+        let texture = |sample_type| BindingType::Texture {
+            sample_type,
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+
+        let depth_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Depth), None)
+            .build(&renderer.device, Some("half res depth bgl"));
+
+        // Loaded texel by texel, so the source may be any float format.
+        let upsample_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Float { filterable: false }), None)
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Float { filterable: false }), None)
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Depth), None)
+            .build(&renderer.device, Some("half res upsample bgl"));
+
+        let depth_pipeline = create_pipeline(
+            renderer,
+            spp,
+            "half res depth",
+            "rend3-routine/half_res_depth.wgsl",
+            &depth_bgl,
+            ColorTargetState { format: HALF_DEPTH_FORMAT, blend: None, write_mask: ColorWrites::all() },
+        );
+        let upsample_pipeline = create_pipeline(
+            renderer,
+            spp,
+            "half res upsample",
+            "rend3-routine/half_res_upsample.wgsl",
+            &upsample_bgl,
+            ColorTargetState { format, blend, write_mask: ColorWrites::all() },
+        );
+
+        Self { depth_bgl, depth_pipeline, upsample_bgl, upsample_pipeline }
+    }
+
+    /// Downsamples `depth`, of the given full resolution, into a new
+    /// [`HALF_DEPTH_FORMAT`] target at [`half_resolution`], keeping the
+    /// nearest depth of each 2x2 block.
+    ///
+    /// `depth` must not be multisampled, see
+    /// [`DepthTargets::readable`](crate::base::DepthTargets::readable).
+    pub fn add_depth_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        resolution: UVec2,
+    ) -> RenderTargetHandle {
+        let half_depth = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("half res depth".into()),
+            resolution: half_resolution(resolution),
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: HALF_DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let mut builder = graph.add_node("Half Res Depth");
+
+        let depth_handle = builder.add_render_target(depth.set_mips(0..1), NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: half_depth,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::DontCare,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::Output,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(depth).build(
+                &ctx.renderer.device,
+                Some("half res depth bg"),
+                &self.depth_bgl,
+            ));
+
+            rpass.set_pipeline(&self.depth_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+
+        half_depth
+    }
+
+    /// Upsamples `src`, rendered at half resolution, into the full resolution
+    /// `dst`. `half_depth` comes from
+    /// [`add_depth_to_graph`](Self::add_depth_to_graph) with the same `depth`.
+    pub fn add_upsample_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        half_depth: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        dst: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("Half Res Upsample");
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+        let half_depth_handle = builder.add_render_target(half_depth, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(depth.set_mips(0..1), NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src = ctx.graph_data.get_render_target(src_handle);
+            let half_depth = ctx.graph_data.get_render_target(half_depth_handle);
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(src)
+                    .append_texture_view(half_depth)
+                    .append_texture_view(depth)
+                    .build(&ctx.renderer.device, Some("half res upsample bg"), &self.upsample_bgl),
+            );
+
+            rpass.set_pipeline(&self.upsample_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+fn create_pipeline(
+    renderer: &Renderer,
+    spp: &ShaderPreProcessor,
+    label: &str,
+    shader: &str,
+    bgl: &BindGroupLayout,
+    target: ColorTargetState,
+) -> RenderPipeline {
+    let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(shader, &ShaderConfig::default(), None))),
+    });
+
+    let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState { module: &module, entry_point: "fs_main", targets: &[Some(target)] }),
+        multiview: None,
+    })
+}
@@ -0,0 +1,373 @@
+//! Culling of the instances of instanced objects.
+//!
+//! Objects added with [`Renderer::add_instanced_object`] are culled as a
+//! whole on the CPU like every other object, with bounds around all of their
+//! instances. Before each forward pass, the instances of the objects that
+//! survived are culled one at a time against the frustum of the pass. The
+//! instances left are written to a buffer of [`CulledInstance`]s, and every
+//! object is drawn with a single instanced draw over them.
+//!
+//! With [`Capability::ComputeCulling`], a compute shader culls the instances
+//! and writes the instance counts of indirect draws. Otherwise the instances
+//! are culled on the CPU and uploaded.
+//!
+//! [`Renderer::add_instanced_object`]: rend3::Renderer::add_instanced_object
+
+use std::{
+    borrow::Cow,
+    mem,
+    ops::Range,
+    sync::{Arc, OnceLock},
+};
+
+use encase::{ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
+use parking_lot::Mutex;
+use rend3::{
+    managers::{InternalObject, ObjectManager, ShaderInstance},
+    types::{Material, RawObjectHandle},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        frustum::{BoundingSphere, Frustum},
+        math::div_round_up,
+        typedefs::FastHashMap,
+    },
+    Capability, CapabilityFallback, Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::DrawIndexedIndirectArgs, BindGroupLayout, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device,
+    PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Set in the instance index of instanced draws. Mirrors `INSTANCED_BIT` in
+/// `structures_object.wgsl`.
+pub const INSTANCED_BIT: u32 = 0x4000_0000;
+
+/// An instance that survived culling, read by the vertex shaders.
+#[derive(Debug, Copy, Clone, ShaderType)]
+pub struct CulledInstance {
+    pub object: u32,
+    /// Index into [`ObjectManager::instance_buffer`].
+    pub instance: u32,
+}
+
+/// The instances of one instanced object culled by a pass.
+#[derive(ShaderType)]
+struct InstanceCullJob {
+    /// In the space of the mesh.
+    bounding_sphere: BoundingSphere,
+    object: u32,
+    first_instance: u32,
+    instance_count: u32,
+    first_output: u32,
+}
+
+/// How an instanced object is drawn by a pass.
+#[derive(Debug, Clone)]
+pub enum InstanceDraw {
+    /// Drawn with `draw_indexed_indirect` at the given offset of
+    /// [`CulledInstances::indirect`], out of `submitted` instances.
+    Indirect { offset: u64, submitted: u32 },
+    /// Drawn with `draw_indexed`.
+    Direct { indices: Range<u32>, instances: Range<u32> },
+}
+
+/// The instances of a pass that survived culling.
+pub struct CulledInstances {
+    /// The [`CulledInstance`]s of every instanced object.
+    pub buffer: Arc<Buffer>,
+    /// Indirect draws written when culling on the GPU.
+    pub indirect: Option<Arc<Buffer>>,
+    pub draws: FastHashMap<RawObjectHandle, InstanceDraw>,
+}
+
+/// Buffer reused every frame, recreated only when it is too small.
+struct GrowingBuffer {
+    label: &'static str,
+    usage: BufferUsages,
+    buffer: Option<Arc<Buffer>>,
+}
+
+impl GrowingBuffer {
+    fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self { label, usage: usage | BufferUsages::COPY_DST, buffer: None }
+    }
+
+    /// The buffer, grown to the next power of two if it is smaller than
+    /// `size` bytes.
+    fn get(&mut self, device: &Device, size: u64) -> Arc<Buffer> {
+        match &self.buffer {
+            Some(buffer) if buffer.size() >= size => Arc::clone(buffer),
+            _ => {
+                let buffer = Arc::new(device.create_buffer(&BufferDescriptor {
+                    label: Some(self.label),
+                    size: size.next_power_of_two(),
+                    usage: self.usage,
+                    mapped_at_creation: false,
+                }));
+                self.buffer = Some(Arc::clone(&buffer));
+                buffer
+            }
+        }
+    }
+}
+
+/// Buffers of the instance culling of one pass.
+struct PassBuffers {
+    culled: GrowingBuffer,
+    jobs: GrowingBuffer,
+    indirect: GrowingBuffer,
+    frustum: GrowingBuffer,
+}
+
+impl PassBuffers {
+    fn new() -> Self {
+        Self {
+            culled: GrowingBuffer::new("culled instances", BufferUsages::STORAGE),
+            jobs: GrowingBuffer::new("instance culling jobs", BufferUsages::STORAGE),
+            indirect: GrowingBuffer::new("instance indirect draws", BufferUsages::STORAGE | BufferUsages::INDIRECT),
+            frustum: GrowingBuffer::new("instance culling frustum", BufferUsages::UNIFORM),
+        }
+    }
+}
+
+/// Compute pipeline culling instances on the GPU.
+///
+/// The pipeline is created the first time instances are culled, so routines
+/// that never draw, like the overdraw routines of a [`PbrRoutine`] whose debug
+/// view is never turned on, don't compile it.
+///
+/// [`PbrRoutine`]: crate::pbr::PbrRoutine
+pub struct InstanceCuller {
+    bgl: BindGroupLayout,
+    source: String,
+    pipeline: OnceLock<ComputePipeline>,
+    /// Buffers of each pass, by label, kept between frames.
+    buffers: Mutex<FastHashMap<String, PassBuffers>>,
+}
+
+impl InstanceCuller {
+    /// Prepares the culling pipeline if the renderer has
+    /// [`Capability::ComputeCulling`], registering the CPU fallback of the
+    /// given routine otherwise.
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, routine: &'static str) -> Option<Self> {
+        if !renderer.supports(Capability::ComputeCulling) {
+            renderer.register_capability_fallback(CapabilityFallback {
+                capability: Capability::ComputeCulling,
+                routine,
+                fallback: "culls the instances of instanced objects on the CPU and uploads the ones left",
+            });
+            return None;
+        }
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: true },
+                false,
+                ShaderInstance::SHADER_SIZE.get(),
+            )
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: true },
+                false,
+                InstanceCullJob::SHADER_SIZE.get(),
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, false, Frustum::SHADER_SIZE.get())
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: false },
+                false,
+                CulledInstance::SHADER_SIZE.get(),
+            )
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: false },
+                false,
+                mem::size_of::<DrawIndexedIndirectArgs>() as u64,
+            )
+            .build(&renderer.device, Some("instance culling bgl"));
+
+        let source = spp.render_valid_shader("rend3-routine/instance_cull.wgsl", &ShaderConfig::default(), None);
+
+        Some(Self { bgl, source, pipeline: OnceLock::new(), buffers: Mutex::new(FastHashMap::default()) })
+    }
+
+    fn pipeline(&self, device: &Device) -> &ComputePipeline {
+        self.pipeline.get_or_init(|| {
+            let module = device.create_shader_module(ShaderModuleDescriptor {
+                label: Some("instance culling"),
+                source: ShaderSource::Wgsl(Cow::Borrowed(&self.source)),
+            });
+            let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("instance culling"),
+                bind_group_layouts: &[&self.bgl],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("instance culling"),
+                layout: Some(&pll),
+                module: &module,
+                entry_point: "cs_main",
+            })
+        })
+    }
+}
+
+/// Culls the instances of the given instanced objects, each drawn with the
+/// given indices. `None` if none of the objects have instances.
+///
+/// When culling on the GPU, the buffers of the pass with the given label are
+/// reused from the last frame it was culled in.
+#[allow(clippy::too_many_arguments)]
+pub fn cull_instances<M: Material>(
+    renderer: &Renderer,
+    encoder: &mut CommandEncoder,
+    label: &str,
+    object_manager: &ObjectManager,
+    culler: Option<&InstanceCuller>,
+    frustum: &Frustum,
+    objects: Vec<(RawObjectHandle, &InternalObject<M>, Range<u32>)>,
+) -> Option<CulledInstances> {
+    profiling::scope!("Instance Culling");
+
+    let instance_buffer = object_manager.instance_buffer()?;
+
+    let mut jobs = Vec::with_capacity(objects.len());
+    let mut total = 0;
+    for (handle, object, indices) in objects {
+        let Some((first_instance, instances)) = object_manager.instances(handle) else {
+            continue;
+        };
+        if instances.is_empty() {
+            continue;
+        }
+        jobs.push((handle, object, indices, first_instance, instances, total));
+        total += instances.len() as u32;
+    }
+    if jobs.is_empty() {
+        return None;
+    }
+
+    match culler {
+        Some(culler) => Some(cull_on_gpu(renderer, encoder, culler, label, instance_buffer, frustum, jobs, total)),
+        None => Some(cull_on_cpu(&renderer.device, frustum, jobs)),
+    }
+}
+
+type Job<'a, M> = (RawObjectHandle, &'a InternalObject<M>, Range<u32>, u32, &'a [ShaderInstance], u32);
+
+#[allow(clippy::too_many_arguments)]
+fn cull_on_gpu<M: Material>(
+    renderer: &Renderer,
+    encoder: &mut CommandEncoder,
+    culler: &InstanceCuller,
+    label: &str,
+    instance_buffer: &Buffer,
+    frustum: &Frustum,
+    jobs: Vec<Job<'_, M>>,
+    total: u32,
+) -> CulledInstances {
+    let device = &renderer.device;
+    let queue: &Queue = &renderer.queue;
+
+    let mut pass_buffers = culler.buffers.lock();
+    let pass_buffers = pass_buffers.entry(label.to_owned()).or_insert_with(PassBuffers::new);
+
+    let buffer = pass_buffers.culled.get(device, total as u64 * CulledInstance::SHADER_SIZE.get());
+
+    let mut draws = FastHashMap::default();
+    let mut shader_jobs = Vec::with_capacity(jobs.len());
+    let mut args = Vec::with_capacity(jobs.len() * mem::size_of::<DrawIndexedIndirectArgs>());
+    let mut max_instances = 0;
+    for (idx, (handle, object, indices, first_instance, instances, first_output)) in jobs.into_iter().enumerate() {
+        let instance_count = instances.len() as u32;
+        shader_jobs.push(InstanceCullJob {
+            bounding_sphere: object.mesh_bounding_sphere,
+            object: handle.idx as u32,
+            first_instance,
+            instance_count,
+            first_output,
+        });
+        args.extend_from_slice(
+            DrawIndexedIndirectArgs {
+                index_count: indices.len() as u32,
+                instance_count: 0,
+                first_index: indices.start,
+                base_vertex: 0,
+                first_instance: INSTANCED_BIT | first_output,
+            }
+            .as_bytes(),
+        );
+        let offset = (idx * mem::size_of::<DrawIndexedIndirectArgs>()) as u64;
+        draws.insert(handle, InstanceDraw::Indirect { offset, submitted: instance_count });
+        max_instances = max_instances.max(instance_count);
+    }
+
+    let mut jobs_data = StorageBuffer::new(Vec::new());
+    jobs_data.write(&shader_jobs).unwrap();
+    let jobs_data = jobs_data.into_inner();
+    let jobs_buffer = pass_buffers.jobs.get(device, jobs_data.len() as u64);
+    queue.write_buffer(&jobs_buffer, 0, &jobs_data);
+
+    let indirect = pass_buffers.indirect.get(device, args.len() as u64);
+    queue.write_buffer(&indirect, 0, &args);
+
+    let mut frustum_data = UniformBuffer::new(Vec::new());
+    frustum_data.write(frustum).unwrap();
+    let frustum_buffer = pass_buffers.frustum.get(device, Frustum::SHADER_SIZE.get());
+    queue.write_buffer(&frustum_buffer, 0, &frustum_data.into_inner());
+
+    let bg = BindGroupBuilder::new()
+        .append_buffer(instance_buffer)
+        .append_buffer(&jobs_buffer)
+        .append_buffer(&frustum_buffer)
+        .append_buffer(&buffer)
+        .append_buffer(&indirect)
+        .build(device, Some("instance culling bg"), &culler.bgl);
+
+    let mut cpass =
+        encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("instance culling"), timestamp_writes: None });
+    cpass.set_pipeline(culler.pipeline(device));
+    cpass.set_bind_group(0, &bg, &[]);
+    cpass.dispatch_workgroups(div_round_up(max_instances, WORKGROUP_SIZE), shader_jobs.len() as u32, 1);
+    drop(cpass);
+
+    CulledInstances { buffer, indirect: Some(indirect), draws }
+}
+
+fn cull_on_cpu<M: Material>(device: &Device, frustum: &Frustum, jobs: Vec<Job<'_, M>>) -> CulledInstances {
+    let mut draws = FastHashMap::default();
+    let mut culled = Vec::new();
+    for (handle, object, indices, first_instance, instances, _) in jobs {
+        let start = culled.len() as u32;
+        culled.extend(instances.iter().enumerate().filter_map(|(idx, instance)| {
+            let sphere = object.mesh_bounding_sphere.apply_transform(instance.transform);
+            frustum
+                .contains_sphere(sphere)
+                .then_some(CulledInstance { object: handle.idx as u32, instance: first_instance + idx as u32 })
+        }));
+        let end = culled.len() as u32;
+        if start != end {
+            draws.insert(
+                handle,
+                InstanceDraw::Direct { indices, instances: INSTANCED_BIT | start..INSTANCED_BIT | end },
+            );
+        }
+    }
+
+    // Empty storage buffers can't be bound.
+    let size = culled.len().max(1) as u64 * CulledInstance::SHADER_SIZE.get();
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("culled instances"),
+        size,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: true,
+    });
+    StorageBuffer::new(&mut *buffer.slice(..).get_mapped_range_mut()).write(&culled).unwrap();
+    buffer.unmap();
+
+    CulledInstances { buffer: Arc::new(buffer), indirect: None, draws }
+}
@@ -0,0 +1,527 @@
+//! Lens flares from bright parts of the image and registered light sources.
+//!
+//! A [`LensFlareRoutine`] draws two kinds of flares, both added to the HDR
+//! image before post processing:
+//!
+//! - Screen space flares, with [`LensFlareSettings::threshold`] set, take the
+//!   pixels brighter than the threshold, like emissive surfaces and the sun
+//!   in the skybox, and add ghosts and a halo of them mirrored through the
+//!   center of the screen.
+//! - Each registered [`LensFlareSource`] gets a glow and a row of ghosts
+//!   through the center of the screen. The depth buffer around the source is
+//!   tested for occlusion every frame, and the flare fades towards how much
+//!   of the source is visible.
+//!
+//! Pass the routine to the base render graph through
+//! [`BaseRenderGraphRoutines::lens_flare`](crate::base::BaseRenderGraphRoutines::lens_flare).
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
+use glam::{Mat4, UVec2, Vec3, Vec4};
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer,
+    BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, ComputePassDescriptor,
+    ComputePipeline, ComputePipelineDescriptor, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Where a [`LensFlareSource`] is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LensFlarePosition {
+    /// A point in world space, like a lamp.
+    Point(Vec3),
+    /// Infinitely far away in the given direction, like the sun. Only hidden
+    /// by objects, not the skybox.
+    Direction(Vec3),
+}
+
+/// A light source that flares when it is visible.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LensFlareSource {
+    pub position: LensFlarePosition,
+    /// Linear color of the flare.
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Radius in pixels of the area around the source tested for occlusion.
+    /// Larger sources fade more gradually as they are covered.
+    pub occlusion_radius: f32,
+}
+
+impl Default for LensFlareSource {
+    fn default() -> Self {
+        Self { position: LensFlarePosition::Point(Vec3::ZERO), color: Vec3::ONE, intensity: 1.0, occlusion_radius: 4.0 }
+    }
+}
+
+/// Look of the flares of a [`LensFlareRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LensFlareSettings {
+    /// Add screen space flares of the pixels brighter than this. `None` only
+    /// flares the registered sources.
+    pub threshold: Option<f32>,
+    /// Ghosts of each flare.
+    pub ghost_count: u32,
+    /// Distance between ghosts, as a fraction of the distance to the center
+    /// of the screen.
+    pub ghost_spacing: f32,
+    /// Radius of the ghosts of registered sources, as a fraction of the
+    /// height of the screen.
+    pub ghost_size: f32,
+    /// Radius of the halo of screen space flares, as a fraction of the screen.
+    /// Zero disables it.
+    pub halo_radius: f32,
+    /// Multiplier of every flare.
+    pub intensity: f32,
+    /// Fraction of the way the visibility of a source moves towards its
+    /// current visibility each frame. One changes it instantly.
+    pub occlusion_fade: f32,
+}
+
+impl Default for LensFlareSettings {
+    fn default() -> Self {
+        Self {
+            threshold: None,
+            ghost_count: 4,
+            ghost_spacing: 0.4,
+            ghost_size: 0.05,
+            halo_radius: 0.45,
+            intensity: 1.0,
+            occlusion_fade: 0.2,
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct LensFlareUniforms {
+    view_proj: Mat4,
+    resolution: UVec2,
+    source_count: u32,
+    ghost_count: u32,
+    ghost_spacing: f32,
+    ghost_size: f32,
+    halo_radius: f32,
+    threshold: f32,
+    intensity: f32,
+    fade_rate: f32,
+}
+
+#[derive(ShaderType)]
+struct GpuLensFlareSource {
+    position: Vec4,
+    color: Vec3,
+    occlusion_radius: f32,
+}
+
+impl From<&LensFlareSource> for GpuLensFlareSource {
+    fn from(source: &LensFlareSource) -> Self {
+        let position = match source.position {
+            LensFlarePosition::Point(point) => point.extend(1.0),
+            LensFlarePosition::Direction(direction) => direction.normalize_or_zero().extend(0.0),
+        };
+        Self { position, color: source.color * source.intensity, occlusion_radius: source.occlusion_radius }
+    }
+}
+
+/// Draws the lens flares of the bright parts of the image and of up to a
+/// fixed number of registered sources.
+///
+/// See module for documentation.
+pub struct LensFlareRoutine {
+    sources: Vec<LensFlareSource>,
+    settings: LensFlareSettings,
+    max_sources: u32,
+
+    uniform_buffer: Buffer,
+    source_buffer: Buffer,
+    visibility_buffer: Buffer,
+
+    occlusion_bgl: BindGroupLayout,
+    occlusion_pipeline: ComputePipeline,
+    sprite_bg: BindGroup,
+    sprite_pipeline: RenderPipeline,
+    screen_bgl: BindGroupLayout,
+    screen_pipeline: RenderPipeline,
+}
+
+impl LensFlareRoutine {
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        settings: LensFlareSettings,
+        max_sources: u32,
+    ) -> Self {
+        profiling::scope!("LensFlareRoutine::new");
+
+        let max_sources = max_sources.max(1);
+        let uniform_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("lens flare uniforms"),
+            size: LensFlareUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let source_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("lens flare sources"),
+            size: max_sources as u64 * GpuLensFlareSource::SHADER_SIZE.get(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Sources start hidden, so they fade in.
+        let visibility_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("lens flare visibility"),
+            size: max_sources as u64 * 4,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let uniform_size = LensFlareUniforms::SHADER_SIZE.get();
+        let source_size = GpuLensFlareSource::SHADER_SIZE.get();
+
+        let occlusion_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, false, uniform_size)
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, source_size)
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, 4)
+            .build(&renderer.device, Some("lens flare occlusion bgl"));
+        let occlusion_module = create_module(renderer, spp, "occlusion");
+        let occlusion_pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("lens flare occlusion"),
+            bind_group_layouts: &[&occlusion_bgl],
+            push_constant_ranges: &[],
+        });
+        let occlusion_pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("lens flare occlusion"),
+            layout: Some(&occlusion_pll),
+            module: &occlusion_module,
+            entry_point: "cs_main",
+        });
+
+        let sprite_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::VERTEX_FRAGMENT, BufferBindingType::Uniform, false, uniform_size)
+            .append_buffer(ShaderStages::VERTEX, BufferBindingType::Storage { read_only: true }, false, source_size)
+            .append_buffer(ShaderStages::VERTEX, BufferBindingType::Storage { read_only: true }, false, 4)
+            .build(&renderer.device, Some("lens flare sprite bgl"));
+        let sprite_bg = BindGroupBuilder::new()
+            .append_buffer(&uniform_buffer)
+            .append_buffer(&source_buffer)
+            .append_buffer(&visibility_buffer)
+            .build(&renderer.device, Some("lens flare sprite bg"), &sprite_bgl);
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let keep = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let sprite_pipeline = create_pipeline(
+            renderer,
+            &create_module(renderer, spp, "sprites"),
+            &[&sprite_bgl],
+            PrimitiveTopology::TriangleStrip,
+            Some(BlendState { color: additive, alpha: keep }),
+        );
+
+        let screen_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, uniform_size)
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("lens flare screen bgl"));
+        let screen_pipeline = create_pipeline(
+            renderer,
+            &create_module(renderer, spp, "screen"),
+            &[&interfaces.forward_uniform_bgl, &screen_bgl],
+            PrimitiveTopology::TriangleList,
+            None,
+        );
+
+        Self {
+            sources: Vec::new(),
+            settings,
+            max_sources,
+
+            uniform_buffer,
+            source_buffer,
+            visibility_buffer,
+
+            occlusion_bgl,
+            occlusion_pipeline,
+            sprite_bg,
+            sprite_pipeline,
+            screen_bgl,
+            screen_pipeline,
+        }
+    }
+
+    pub fn settings(&self) -> &LensFlareSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut LensFlareSettings {
+        &mut self.settings
+    }
+
+    pub fn sources(&self) -> &[LensFlareSource] {
+        &self.sources
+    }
+
+    /// Replaces the registered sources. Sources past
+    /// [`Self::max_sources`] are ignored.
+    ///
+    /// The visibility of a source is faded by its index, so keep sources in
+    /// the same order between frames.
+    pub fn set_sources(&mut self, sources: &[LensFlareSource]) {
+        if sources.len() > self.max_sources as usize {
+            log::warn!("{} lens flare sources given, only {} are drawn", sources.len(), self.max_sources);
+        }
+        self.sources.clear();
+        self.sources.extend(sources.iter().take(self.max_sources as usize));
+    }
+
+    pub fn max_sources(&self) -> u32 {
+        self.max_sources
+    }
+
+    /// Adds the flares to `color`, testing the sources against `depth`, which
+    /// must be single sampled. Returns the image with the flares, which is a
+    /// new target with screen space flares and `color` otherwise.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        resolution: UVec2,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) -> RenderTargetHandle {
+        self.add_upload_to_graph(graph, resolution);
+
+        let mut color = color;
+        if self.settings.threshold.is_some() {
+            let dst = graph.add_render_target(RenderTargetDescriptor {
+                label: Some("hdr lens flare".into()),
+                resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            });
+            self.add_screen_to_graph(graph, color, dst, forward_uniform_bg);
+            color = dst;
+        }
+        if !self.sources.is_empty() {
+            self.add_sources_to_graph(graph, color, depth);
+        }
+        color
+    }
+
+    fn add_upload_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>, resolution: UVec2) {
+        let mut builder = graph.add_node("Lens Flare Upload");
+        builder.add_side_effect();
+
+        builder.build(move |ctx| {
+            let settings = &self.settings;
+            let uniforms = LensFlareUniforms {
+                view_proj: ctx.data_core.viewport_camera_state.view_proj(),
+                resolution,
+                source_count: self.sources.len() as u32,
+                ghost_count: settings.ghost_count,
+                ghost_spacing: settings.ghost_spacing,
+                ghost_size: settings.ghost_size,
+                halo_radius: settings.halo_radius,
+                threshold: settings.threshold.unwrap_or(0.0),
+                intensity: settings.intensity,
+                fade_rate: settings.occlusion_fade.clamp(0.0, 1.0),
+            };
+            let mut data = UniformBuffer::new(Vec::with_capacity(LensFlareUniforms::SHADER_SIZE.get() as usize));
+            data.write(&uniforms).unwrap();
+            ctx.renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+
+            if !self.sources.is_empty() {
+                let sources: Vec<GpuLensFlareSource> = self.sources.iter().map(GpuLensFlareSource::from).collect();
+                let mut data = StorageBuffer::new(Vec::new());
+                data.write(&sources).unwrap();
+                ctx.renderer.queue.write_buffer(&self.source_buffer, 0, &data.into_inner());
+            }
+        });
+    }
+
+    fn add_screen_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Lens Flare Screen");
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::Output,
+        );
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let color = ctx.graph_data.get_render_target(color_handle);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new().append_buffer(&self.uniform_buffer).append_texture_view(color).build(
+                    &ctx.renderer.device,
+                    Some("lens flare screen bg"),
+                    &self.screen_bgl,
+                ),
+            );
+
+            rpass.set_pipeline(&self.screen_pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    fn add_sources_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("Lens Flare Occlusion");
+        let depth_handle = builder.add_render_target(depth.set_mips(0..1), NodeResourceUsage::Input);
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            let bg = BindGroupBuilder::new()
+                .append_buffer(&self.uniform_buffer)
+                .append_buffer(&self.source_buffer)
+                .append_texture_view(depth)
+                .append_buffer(&self.visibility_buffer)
+                .build(&ctx.renderer.device, Some("lens flare occlusion bg"), &self.occlusion_bgl);
+
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("lens flare occlusion"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.occlusion_pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups((self.sources.len() as u32 + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+        });
+
+        let mut builder = graph.add_node("Lens Flare Sprites");
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        let instances = self.sources.len() as u32 * (self.settings.ghost_count + 1);
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            rpass.set_pipeline(&self.sprite_pipeline);
+            rpass.set_bind_group(0, &self.sprite_bg, &[]);
+            rpass.draw(0..4, 0..instances);
+        });
+    }
+}
+
+fn create_module(renderer: &Renderer, spp: &ShaderPreProcessor, name: &str) -> wgpu::ShaderModule {
+    renderer.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(&format!("lens flare {name}")),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+            &format!("rend3-routine/lens_flare/{name}.wgsl"),
+            &ShaderConfig::default(),
+            None,
+        ))),
+    })
+}
+
+fn create_pipeline(
+    renderer: &Renderer,
+    module: &wgpu::ShaderModule,
+    bgls: &[&BindGroupLayout],
+    topology: PrimitiveTopology,
+    blend: Option<BlendState>,
+) -> RenderPipeline {
+    let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("lens flare"),
+        bind_group_layouts: bgls,
+        push_constant_ranges: &[],
+    });
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("lens flare"),
+        layout: Some(&pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba16Float,
+                blend,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
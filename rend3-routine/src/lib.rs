@@ -19,14 +19,36 @@
 //! too much user side boilerplate.
 
 pub mod base;
+pub mod bloom;
 pub mod clear;
 pub mod common;
+pub mod copy;
+pub mod culling;
+pub mod debug;
+pub mod deferred;
+pub mod depth_partition;
+pub mod depth_resolve;
+pub mod environment;
 pub mod forward;
+pub mod governor;
+pub mod half_res;
+pub mod instancing;
+pub mod lens_flare;
+pub mod mesh_data;
+pub mod object_compute;
+pub mod panorama;
+pub mod particles;
 pub mod pbr;
+pub mod post;
+pub mod reflection;
 mod shaders;
+pub mod shadow_cache;
 pub mod skinning;
 pub mod skybox;
+pub mod temporal;
+pub mod tiled_lighting;
 pub mod tonemapping;
 pub mod uniforms;
+pub mod velocity;
 
 pub use shaders::builtin_shaders;
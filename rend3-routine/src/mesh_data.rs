@@ -0,0 +1,83 @@
+//! Stable access to the mesh and object data from custom shaders.
+//!
+//! rend3 pulls vertices in its shaders instead of binding vertex buffers: the
+//! vertices and indices of every mesh live in the mesh data buffer, and the
+//! objects of each material type in an object buffer. Custom shaders can read
+//! both, to raymarch or cull on the GPU, through the functions of
+//! `rend3-routine/mesh_data.wgsl`. Those keep working across releases until
+//! [`MESH_DATA_VERSION`] changes, while the layouts behind them may change in
+//! any release.
+//!
+//! The shader declares the buffers, bound with [`add_to_bgl`] and
+//! [`add_to_bg`]:
+//!
+//! ```wgsl
+//! {{include "rend3-routine/mesh_data.wgsl"}}
+//!
+//! @group(0) @binding(0)
+//! var<storage> object_buffer: array<Object>;
+//! @group(0) @binding(1)
+//! var<storage> vertex_buffer: array<u32>;
+//!
+//! @compute @workgroup_size(64)
+//! fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+//!     let object = id.y;
+//!     if (id.x >= object_triangle_count(object)) {
+//!         return;
+//!     }
+//!     let triangle = object_triangle(object, id.x);
+//!     // ...
+//! }
+//! ```
+//!
+//! It is rendered with [`ShaderVertexBufferConfig::from_material`] of the
+//! material whose objects are read, and [`ShaderConfig::position_attribute_offset`]
+//! set to the index of the position in its supported attributes, 0 for
+//! [`PbrMaterial`](crate::pbr::PbrMaterial).
+//!
+//! Both buffers are replaced when they grow, so the bind group must be made
+//! again every frame, from the buffers of the frame being rendered. Removed
+//! objects keep their last data in the object buffer.
+//!
+//! [`ShaderVertexBufferConfig::from_material`]: rend3::ShaderVertexBufferConfig::from_material
+//! [`ShaderConfig::position_attribute_offset`]: rend3::ShaderConfig::position_attribute_offset
+
+use encase::ShaderType;
+use rend3::{
+    graph::NodeExecutionContext,
+    managers::ShaderObject,
+    types::Material,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+};
+use wgpu::{BufferBindingType, ShaderStages};
+
+/// Version of the functions of `rend3-routine/mesh_data.wgsl`, also
+/// available to shaders as `MESH_DATA_VERSION`. Bumped by any change that
+/// breaks shaders using them.
+pub const MESH_DATA_VERSION: u32 = 1;
+
+/// Adds the object buffer of material `M` and the mesh data buffer, as read
+/// only storage buffers, to the given bind group layout builder.
+pub fn add_to_bgl<M: Material>(bglb: &mut BindGroupLayoutBuilder, visibility: ShaderStages) {
+    bglb.append_buffer(
+        visibility,
+        BufferBindingType::Storage { read_only: true },
+        false,
+        ShaderObject::<M>::min_size().get(),
+    )
+    .append_buffer(visibility, BufferBindingType::Storage { read_only: true }, false, 4);
+}
+
+/// Adds the object buffer of material `M` and the mesh data buffer of the
+/// frame to the given bind group builder. Returns false if there are no
+/// objects of material `M`, and so no buffer to read.
+pub fn add_to_bg<'pass, M: Material>(
+    ctx: &NodeExecutionContext<'_, 'pass, '_>,
+    bgb: &mut BindGroupBuilder<'pass>,
+) -> bool {
+    let Some(object_buffer) = ctx.data_core.object_manager.buffer::<M>() else {
+        return false;
+    };
+    bgb.append_buffer(object_buffer).append_buffer(&ctx.eval_output.mesh_buffer);
+    true
+}
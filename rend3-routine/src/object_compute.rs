@@ -0,0 +1,205 @@
+//! User compute passes writing into the object buffer.
+//!
+//! An [`ObjectComputePass`] runs a compute shader over every object of one
+//! material, with the object buffer bound as read-write storage. It can be used
+//! to animate objects on the GPU, like flocks and crowds, without round trips
+//! through the CPU. Passes are inserted into the base render graph through
+//! [`BaseRenderGraphRoutines::object_compute`](crate::base::BaseRenderGraphRoutines::object_compute),
+//! right after skinning, so every shadow, depth and forward pass of the frame
+//! draws the objects where the pass moved them.
+//!
+//! The shader must be added to the [`ShaderPreProcessor`], include
+//! `rend3-routine/object_compute.wgsl` and define `cs_main`:
+//!
+//! ```wgsl
+//! {{include "rend3-routine/object_compute.wgsl"}}
+//!
+//! @compute @workgroup_size(64)
+//! fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+//!     let index = id.x;
+//!     if (index >= info.object_count || object_buffer[index].custom_data.w == 0.0) {
+//!         return;
+//!     }
+//!     var transform = object_buffer[index].transform;
+//!     transform[3] += vec4<f32>(params[0].xyz * info.delta_time, 0.0);
+//!     set_object_transform(index, transform);
+//! }
+//! ```
+//!
+//! The shader picks the objects it moves, for example through their
+//! [custom data](rend3::Renderer::set_object_custom_data). It is also
+//! dispatched over the slots of removed objects.
+//!
+//! Culling and sorting happen on the CPU, with the transforms set through the
+//! [`Renderer`], so objects must not be moved outside of the bounds they have
+//! there. Setting the transform, custom data or opacity of an object on the
+//! CPU uploads the whole object again, replacing what the pass wrote.
+
+use std::{borrow::Cow, marker::PhantomData, mem};
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::Vec4;
+use rend3::{
+    graph::RenderGraph,
+    managers::ShaderObject,
+    types::Material,
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+    },
+    PassStats, Renderer, ShaderConfig, ShaderPreProcessor, ShaderVertexBufferConfig,
+};
+use wgpu::{
+    BindGroupLayout, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(ShaderType)]
+struct ObjectComputeInfo {
+    object_count: u32,
+    delta_time: f32,
+}
+
+/// Compute pass running a user shader over the objects of material `M`.
+///
+/// See module for documentation.
+pub struct ObjectComputePass<M: Material> {
+    label: String,
+    delta_time: f32,
+    params: Vec<Vec4>,
+
+    info_buffer: Buffer,
+    params_buffer: Buffer,
+    bgl: BindGroupLayout,
+    pipeline: ComputePipeline,
+    _phantom: PhantomData<M>,
+}
+
+impl<M: Material> ObjectComputePass<M> {
+    /// Creates a pass running the shader with the given name in the
+    /// preprocessor, with room for `param_count` parameters.
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, shader: &str, param_count: usize) -> Self {
+        profiling::scope!("ObjectComputePass::new");
+
+        // Empty storage buffers can't be bound.
+        let param_count = param_count.max(1);
+
+        let info_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("object compute info"),
+            size: ObjectComputeInfo::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("object compute params"),
+            size: (param_count * mem::size_of::<Vec4>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: false },
+                false,
+                ShaderObject::<M>::min_size().get(),
+            )
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Uniform,
+                false,
+                ObjectComputeInfo::SHADER_SIZE.get(),
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, 16)
+            .build(&renderer.device, Some("object compute bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(shader),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                shader,
+                &ShaderConfig::default(),
+                Some(&ShaderVertexBufferConfig::from_material::<M>()),
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(shader),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(shader),
+            layout: Some(&pll),
+            module: &module,
+            entry_point: "cs_main",
+        });
+
+        Self {
+            label: shader.to_owned(),
+            delta_time: 0.0,
+            params: vec![Vec4::ZERO; param_count],
+
+            info_buffer,
+            params_buffer,
+            bgl,
+            pipeline,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets the time the next frame advances by, read by the shader as
+    /// `info.delta_time`. Must be called once a frame before the render graph
+    /// is built.
+    pub fn update(&mut self, delta_time: f32) {
+        self.delta_time = delta_time;
+    }
+
+    /// The parameters read by the shader as `params`, such as flock targets.
+    /// Changes are uploaded when the pass next runs.
+    pub fn params_mut(&mut self) -> &mut [Vec4] {
+        &mut self.params
+    }
+
+    /// Runs the shader over the objects of material `M`.
+    pub fn add_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>) {
+        let mut builder = graph.add_node(self.label.as_str());
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+
+            let Some(objects) = ctx.data_core.object_manager.enumerated_objects::<M>() else {
+                return;
+            };
+            let Some(object_count) = objects.map(|(handle, _)| handle.idx as u32 + 1).max() else {
+                return;
+            };
+            let object_buffer = ctx.data_core.object_manager.buffer::<M>().unwrap();
+
+            let mut data = UniformBuffer::new(Vec::with_capacity(ObjectComputeInfo::SHADER_SIZE.get() as usize));
+            data.write(&ObjectComputeInfo { object_count, delta_time: self.delta_time }).unwrap();
+            ctx.renderer.queue.write_buffer(&self.info_buffer, 0, &data.into_inner());
+            ctx.renderer.queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&self.params));
+
+            let bg = BindGroupBuilder::new()
+                .append_buffer(object_buffer)
+                .append_buffer(&self.info_buffer)
+                .append_buffer(&self.params_buffer)
+                .build(&ctx.renderer.device, Some("object compute bg"), &self.bgl);
+
+            let mut cpass =
+                encoder.begin_compute_pass(&ComputePassDescriptor { label: Some(&self.label), timestamp_writes: None });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups(div_round_up(object_count, WORKGROUP_SIZE), 1, 1);
+            drop(cpass);
+
+            ctx.renderer.record_pass_stats(PassStats {
+                label: self.label.clone(),
+                compute_dispatches: 1,
+                ..PassStats::default()
+            });
+        });
+    }
+}
@@ -0,0 +1,403 @@
+//! Rendering the scene around a point into a panorama.
+//!
+//! [`capture_panorama`] renders the six faces of a cube around
+//! [`PanoramaSettings::position`] and resamples them into an equirectangular
+//! image or a cubemap, for skyboxes, environment maps and photo spheres.
+//! Faces are rendered in tiles no larger than
+//! [`PanoramaSettings::max_tile_size`], so panoramas can be larger than the
+//! largest texture the device supports.
+//!
+//! Every tile is a frame of its own: the viewport camera is set to the tile,
+//! instructions are evaluated, and a [`PanoramaGraph`] renders the frame. Set
+//! the camera again once the capture is done. Effects carried over between
+//! frames, like temporal upscaling, show up as seams between tiles and should
+//! be disabled while capturing.
+//!
+//! ```ignore
+//! struct Scene<'a> {
+//!     base: &'a BaseRenderGraph,
+//!     pbr: &'a PbrRoutine,
+//!     skybox: &'a SkyboxRoutine,
+//!     tonemapping: &'a TonemappingRoutine,
+//! }
+//!
+//! impl PanoramaGraph for Scene<'_> {
+//!     fn add_to_graph<'node>(
+//!         &'node self,
+//!         graph: &mut RenderGraph<'node>,
+//!         eval_output: &InstructionEvaluationOutput,
+//!         target: OutputRenderTarget,
+//!     ) {
+//!         let routines = BaseRenderGraphRoutines {
+//!             pbr: self.pbr,
+//!             skybox: Some(self.skybox),
+//!             tonemapping: self.tonemapping,
+//!             shadow_cache: None,
+//!             post_process: &[],
+//!             tiled_lighting: None,
+//!             particles: &[],
+//!             lens_flare: None,
+//!             bloom: None,
+//!             object_compute: &[],
+//!         };
+//!         let inputs = BaseRenderGraphInputs { eval_output, routines, target };
+//!         self.base.add_to_graph(graph, inputs, BaseRenderGraphSettings::default());
+//!     }
+//! }
+//!
+//! let panorama = capture_panorama(&renderer, &scene, &PanoramaSettings::default()).await?;
+//! ```
+
+use std::sync::Arc;
+
+use glam::{Mat4, UVec2, Vec3, Vec4, Vec4Swizzles};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph, ViewportRect},
+    types::{
+        Camera, CameraProjection, Handedness, MipmapCount, MipmapSource, SampleCount, Texture, TextureFormat,
+        TextureUsages,
+    },
+    util::math::round_up,
+    Renderer,
+};
+use wgpu::{
+    BufferAsyncError, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+    ImageDataLayout, Maintain, MapMode, TextureDescriptor, TextureDimension, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::base::OutputRenderTarget;
+
+/// Format of the tiles and of [`Panorama::data`].
+pub const PANORAMA_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Adds the nodes rendering one tile of a panorama.
+pub trait PanoramaGraph {
+    /// Adds the nodes rendering the frame into `target`.
+    fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    );
+}
+
+/// How the directions around the camera are laid out in the image.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum PanoramaProjection {
+    /// Longitude along x and latitude along y, with +z in the center, or -z
+    /// with a right handed renderer. Twice as wide as high for square texels.
+    #[default]
+    Equirectangular,
+    /// Six square faces in the order +x, -x, +y, -y, +z, -z, as
+    /// [`Renderer::add_texture_cube`] expects them.
+    Cubemap,
+}
+
+/// Describes a panorama to capture.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PanoramaSettings {
+    pub projection: PanoramaProjection,
+    /// Size of the image. Each face of a cubemap is `resolution.x` square.
+    pub resolution: UVec2,
+    /// Where the panorama is captured from.
+    pub position: Vec3,
+    /// Near plane distance.
+    pub near: f32,
+    /// Largest tile rendered at once. Limited to the largest texture the
+    /// device supports.
+    pub max_tile_size: u32,
+    pub samples: SampleCount,
+}
+
+impl Default for PanoramaSettings {
+    fn default() -> Self {
+        Self {
+            projection: PanoramaProjection::Equirectangular,
+            resolution: UVec2::new(4096, 2048),
+            position: Vec3::ZERO,
+            near: 0.1,
+            max_tile_size: 2048,
+            samples: SampleCount::One,
+        }
+    }
+}
+
+/// A captured panorama.
+#[derive(Debug, Clone)]
+pub struct Panorama {
+    pub projection: PanoramaProjection,
+    /// Size of the image, or of each face of a cubemap.
+    pub resolution: UVec2,
+    /// Tightly packed texels in [`PANORAMA_FORMAT`], face after face for
+    /// cubemaps.
+    pub data: Vec<u8>,
+}
+
+impl Panorama {
+    /// The panorama as a texture, to add with [`Renderer::add_texture_2d`],
+    /// or [`Renderer::add_texture_cube`] for cubemaps.
+    pub fn into_texture(self) -> Texture {
+        Texture {
+            label: Some("panorama".into()),
+            data: self.data,
+            format: PANORAMA_FORMAT,
+            size: self.resolution,
+            mip_count: MipmapCount::ONE,
+            mip_source: MipmapSource::Uploaded,
+        }
+    }
+}
+
+/// Forward and up direction of each face, in the order of the faces of a
+/// cube texture.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::NEG_Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+/// A rendered face of the cube, in linear color.
+struct Face {
+    view_proj: Mat4,
+    size: u32,
+    texels: Vec<Vec4>,
+}
+
+impl Face {
+    /// Bilinearly filtered color seen in `direction`, which must be in front
+    /// of the face.
+    fn sample(&self, position: Vec3, direction: Vec3) -> Vec4 {
+        let clip = self.view_proj * (position + direction).extend(1.0);
+        let ndc = clip.xy() / clip.w;
+        let size = self.size as f32;
+        let x = ((ndc.x * 0.5 + 0.5) * size - 0.5).clamp(0.0, size - 1.0);
+        let y = ((0.5 - ndc.y * 0.5) * size - 0.5).clamp(0.0, size - 1.0);
+
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let texel = |x: u32, y: u32| self.texels[(y * self.size + x) as usize];
+        let top = texel(x0, y0).lerp(texel(x1, y0), x.fract());
+        let bottom = texel(x0, y1).lerp(texel(x1, y1), x.fract());
+        top.lerp(bottom, y.fract())
+    }
+}
+
+/// Renders the scene around [`PanoramaSettings::position`] into a panorama.
+///
+/// See the [module](self) for documentation.
+pub async fn capture_panorama(
+    renderer: &Arc<Renderer>,
+    graph: &impl PanoramaGraph,
+    settings: &PanoramaSettings,
+) -> Result<Panorama, BufferAsyncError> {
+    profiling::scope!("capture_panorama");
+
+    let face_size = match settings.projection {
+        PanoramaProjection::Equirectangular => (settings.resolution.x + 3) / 4,
+        PanoramaProjection::Cubemap => settings.resolution.x,
+    }
+    .max(1);
+    let tile_size = settings.max_tile_size.min(renderer.limits.max_texture_dimension_2d).max(1);
+
+    let mut faces = Vec::with_capacity(6);
+    for (forward, up) in FACES {
+        let (view, proj) = match renderer.handedness {
+            Handedness::Left => (
+                Mat4::look_at_lh(settings.position, settings.position + forward, up),
+                Mat4::perspective_infinite_reverse_lh(std::f32::consts::FRAC_PI_2, 1.0, settings.near),
+            ),
+            Handedness::Right => (
+                Mat4::look_at_rh(settings.position, settings.position + forward, up),
+                Mat4::perspective_infinite_reverse_rh(std::f32::consts::FRAC_PI_2, 1.0, settings.near),
+            ),
+        };
+
+        let mut texels = vec![Vec4::ZERO; (face_size * face_size) as usize];
+        for y in (0..face_size).step_by(tile_size as usize) {
+            for x in (0..face_size).step_by(tile_size as usize) {
+                let start = UVec2::new(x, y);
+                let size = (UVec2::splat(face_size) - start).min(UVec2::splat(tile_size));
+                let tile_proj = tile_projection(proj, face_size, start, size);
+                let tile = render_tile(renderer, graph, settings, view, tile_proj, size).await?;
+                for (row, data) in tile.chunks_exact(size.x as usize * 4).enumerate() {
+                    let offset = ((y + row as u32) * face_size + x) as usize;
+                    for (texel, srgb) in texels[offset..offset + size.x as usize].iter_mut().zip(data.chunks_exact(4)) {
+                        *texel = srgb_to_linear(srgb);
+                    }
+                }
+            }
+        }
+        faces.push(Face { view_proj: proj * view, size: face_size, texels });
+    }
+
+    let resolution = match settings.projection {
+        PanoramaProjection::Equirectangular => settings.resolution.max(UVec2::ONE),
+        PanoramaProjection::Cubemap => UVec2::splat(face_size),
+    };
+    let sample = |direction: Vec3| {
+        let abs = direction.abs();
+        let face = if abs.x >= abs.y && abs.x >= abs.z {
+            (direction.x < 0.0) as usize
+        } else if abs.y >= abs.z {
+            2 + (direction.y < 0.0) as usize
+        } else {
+            4 + (direction.z < 0.0) as usize
+        };
+        linear_to_srgb(faces[face].sample(settings.position, direction))
+    };
+
+    let mut data = Vec::new();
+    match settings.projection {
+        PanoramaProjection::Equirectangular => {
+            let forward = match renderer.handedness {
+                Handedness::Left => Vec3::Z,
+                Handedness::Right => Vec3::NEG_Z,
+            };
+            data.reserve((resolution.x * resolution.y * 4) as usize);
+            for y in 0..resolution.y {
+                let latitude = (0.5 - (y as f32 + 0.5) / resolution.y as f32) * std::f32::consts::PI;
+                for x in 0..resolution.x {
+                    let longitude = ((x as f32 + 0.5) / resolution.x as f32 - 0.5) * std::f32::consts::TAU;
+                    let direction = Vec3::X * latitude.cos() * longitude.sin()
+                        + Vec3::Y * latitude.sin()
+                        + forward * latitude.cos() * longitude.cos();
+                    data.extend_from_slice(&sample(direction));
+                }
+            }
+        }
+        PanoramaProjection::Cubemap => {
+            data.reserve((face_size * face_size * 4 * 6) as usize);
+            for face in 0..6 {
+                for y in 0..face_size {
+                    let v = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                    for x in 0..face_size {
+                        let u = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                        data.extend_from_slice(&sample(cube_direction(face, u, v)));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Panorama { projection: settings.projection, resolution, data })
+}
+
+/// Projection of the part of a face starting at `start` with the given size.
+fn tile_projection(proj: Mat4, face_size: u32, start: UVec2, size: UVec2) -> Mat4 {
+    let face_size = face_size as f32;
+    let scale = face_size / size.as_vec2();
+    // Center of the tile in normalized device coordinates, with y up.
+    let center = (start.as_vec2() + size.as_vec2() * 0.5) / face_size * 2.0 - 1.0;
+    let center = center * glam::Vec2::new(1.0, -1.0);
+    let tile = Mat4::from_cols(
+        Vec4::new(scale.x, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, scale.y, 0.0, 0.0),
+        Vec4::Z,
+        Vec4::new(-center.x * scale.x, -center.y * scale.y, 0.0, 1.0),
+    );
+    tile * proj
+}
+
+/// Renders a tile of a face and reads it back, tightly packed.
+async fn render_tile(
+    renderer: &Arc<Renderer>,
+    graph: &impl PanoramaGraph,
+    settings: &PanoramaSettings,
+    view: Mat4,
+    proj: Mat4,
+    size: UVec2,
+) -> Result<Vec<u8>, BufferAsyncError> {
+    let texture = renderer.device.create_texture(&TextureDescriptor {
+        label: Some("panorama tile"),
+        size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: PANORAMA_FORMAT,
+        usage: TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    renderer.set_camera_data(Camera { projection: CameraProjection::Raw(proj), view });
+    renderer.swap_instruction_buffers();
+    let mut eval_output = renderer.evaluate_instructions();
+
+    let mut render_graph = RenderGraph::new();
+    let handle = render_graph.add_imported_render_target(&texture, 0..1, 0..1, ViewportRect::from_size(size));
+    graph.add_to_graph(
+        &mut render_graph,
+        &eval_output,
+        OutputRenderTarget { handle, resolution: size, samples: settings.samples, render_scale: 1.0 },
+    );
+    render_graph.execute(renderer, &mut eval_output);
+
+    let bytes_per_row = size.x * 4;
+    let padded_bytes_per_row = round_up(bytes_per_row, COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = renderer.device.create_buffer(&BufferDescriptor {
+        label: Some("panorama tile readback"),
+        size: (padded_bytes_per_row * size.y) as u64,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder =
+        renderer.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("panorama tile readback") });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout { offset: 0, bytes_per_row: Some(padded_bytes_per_row), rows_per_image: None },
+        },
+        Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+    );
+    let submit_index = renderer.queue.submit(Some(encoder.finish()));
+
+    let (sender, receiver) = flume::bounded(1);
+    buffer.slice(..).map_async(MapMode::Read, move |res| sender.send(res).unwrap());
+    renderer.device.poll(Maintain::WaitForSubmissionIndex(submit_index));
+    receiver.recv_async().await.expect("map_async callback dropped")?;
+
+    let mapping = buffer.slice(..).get_mapped_range();
+    Ok(mapping
+        .chunks_exact(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..bytes_per_row as usize])
+        .copied()
+        .collect())
+}
+
+/// Direction of the texel at `u`, `v` in [-1, 1] of a face of a cube
+/// texture, with `v` pointing down.
+fn cube_direction(face: usize, u: f32, v: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        _ => Vec3::new(-u, -v, -1.0),
+    }
+    .normalize()
+}
+
+fn srgb_to_linear(srgb: &[u8]) -> Vec4 {
+    let channel = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec4::new(channel(srgb[0]), channel(srgb[1]), channel(srgb[2]), srgb[3] as f32 / 255.0)
+}
+
+fn linear_to_srgb(linear: Vec4) -> [u8; 4] {
+    let channel = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let srgb = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+        (srgb * 255.0 + 0.5) as u8
+    };
+    [channel(linear.x), channel(linear.y), channel(linear.z), (linear.w.clamp(0.0, 1.0) * 255.0 + 0.5) as u8]
+}
@@ -0,0 +1,520 @@
+//! GPU particles, sorted for blending and colliding with the depth buffer.
+//!
+//! A [`ParticleRoutine`] owns the particles of a single [`ParticleEmitter`].
+//! Everything happens in compute nodes of the render graph:
+//!
+//! - Simulation spawns new particles, applies gravity, and bounces particles
+//!   off the scene where they pass behind the depth buffer, using the normal
+//!   of the surface reconstructed from depth.
+//! - A bitonic sort orders the alive particles back to front, so that alpha
+//!   blending is correct.
+//!
+//! The particles are then drawn as camera facing quads into the HDR target.
+//!
+//! Particles only collide with what is visible on screen. Call
+//! [`ParticleRoutine::update`] once a frame, then pass the routine to the base
+//! render graph through
+//! [`BaseRenderGraphRoutines::particles`](crate::base::BaseRenderGraphRoutines::particles).
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec3, Vec4};
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderPassTargets, RenderTargetHandle},
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, CompareFunction, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    DepthBiasState, DepthStencilState, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+/// Size in bytes of a particle on the GPU.
+const PARTICLE_SIZE: u64 = 32;
+/// Size in bytes of a sort key on the GPU.
+const KEY_SIZE: u64 = 8;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Describes how particles spawn and move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParticleEmitter {
+    /// Where particles spawn.
+    pub position: Vec3,
+    /// Initial velocity of every particle.
+    pub velocity: Vec3,
+    /// Maximum speed added to the velocity in a random direction.
+    pub spread: f32,
+    /// Acceleration applied to every particle.
+    pub gravity: Vec3,
+    /// Particles spawned per second.
+    pub spawn_rate: f32,
+    /// Seconds a particle lives for.
+    pub lifetime: f32,
+    /// Width and height of a particle.
+    pub size: f32,
+    /// Color of a particle when it spawns, multiplied by its alpha when blended.
+    pub color_start: Vec4,
+    /// Color of a particle when it dies.
+    pub color_end: Vec4,
+    /// Bounce off the depth buffer.
+    pub collision: bool,
+    /// Fraction of the velocity kept after bouncing.
+    pub restitution: f32,
+    /// How far behind the depth buffer a particle still collides, in world
+    /// units. Anything farther behind is considered occluded, not colliding.
+    pub collision_thickness: f32,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            velocity: Vec3::new(0.0, 4.0, 0.0),
+            spread: 2.0,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            spawn_rate: 100.0,
+            lifetime: 2.0,
+            size: 0.05,
+            color_start: Vec4::new(4.0, 2.0, 0.5, 1.0),
+            color_end: Vec4::new(1.0, 0.1, 0.0, 0.0),
+            collision: true,
+            restitution: 0.5,
+            collision_thickness: 0.5,
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct ParticleUniforms {
+    view: Mat4,
+    view_proj: Mat4,
+    inv_view_proj: Mat4,
+    emitter_position: Vec3,
+    delta_time: f32,
+    velocity: Vec3,
+    spread: f32,
+    gravity: Vec3,
+    lifetime: f32,
+    color_start: Vec4,
+    color_end: Vec4,
+    resolution: UVec2,
+    max_particles: u32,
+    sort_count: u32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+    collision: u32,
+    size: f32,
+    restitution: f32,
+    collision_thickness: f32,
+}
+
+#[derive(Serialize)]
+struct ParticleShaderConfig {
+    #[serde(rename = "SAMPLES")]
+    samples: u32,
+}
+
+/// What the next frame simulates, set by [`ParticleRoutine::update`].
+#[derive(Debug, Default, Copy, Clone)]
+struct ParticleFrame {
+    delta_time: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+}
+
+struct SimulatePipeline {
+    bgl: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+/// Simulates, sorts and draws the particles of one emitter.
+///
+/// See module for documentation.
+pub struct ParticleRoutine {
+    emitter: ParticleEmitter,
+    max_particles: u32,
+    sort_count: u32,
+
+    frame: ParticleFrame,
+    spawn_cursor: u32,
+    spawn_remainder: f32,
+
+    uniform_buffer: Buffer,
+    particle_buffer: Buffer,
+    key_buffer: Buffer,
+    sort_step_stride: u64,
+    sort_steps: u32,
+
+    simulate_s1: SimulatePipeline,
+    simulate_s4: SimulatePipeline,
+    sort_bg: BindGroup,
+    sort_pipeline: ComputePipeline,
+    render_bg: BindGroup,
+    render_s1: RenderPipeline,
+    render_s4: RenderPipeline,
+}
+
+impl ParticleRoutine {
+    /// Creates a routine with room for `max_particles` alive particles. When
+    /// more are spawned, the oldest are replaced.
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, emitter: ParticleEmitter, max_particles: u32) -> Self {
+        profiling::scope!("ParticleRoutine::new");
+
+        let max_particles = max_particles.max(1);
+        let sort_count = max_particles.next_power_of_two();
+
+        let uniform_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("particle uniforms"),
+            size: ParticleUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Never spawned particles have a lifetime of zero, so they start dead.
+        let particle_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("particles"),
+            size: max_particles as u64 * PARTICLE_SIZE,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let key_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("particle sort keys"),
+            size: sort_count as u64 * KEY_SIZE,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Every step of the bitonic sort, each at an offset usable as a dynamic offset.
+        let mut steps = Vec::new();
+        let mut block = 2;
+        while block <= sort_count {
+            let mut distance = block / 2;
+            while distance > 0 {
+                steps.push([block, distance]);
+                distance /= 2;
+            }
+            block *= 2;
+        }
+        let sort_step_stride = renderer.limits.min_uniform_buffer_offset_alignment.max(8) as u64;
+        let sort_step_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("particle sort steps"),
+            size: sort_step_stride * steps.len().max(1) as u64,
+            usage: BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        {
+            let mut mapping = sort_step_buffer.slice(..).get_mapped_range_mut();
+            for (idx, step) in steps.iter().enumerate() {
+                let offset = idx * sort_step_stride as usize;
+                mapping[offset..offset + 8].copy_from_slice(bytemuck::bytes_of(step));
+            }
+        }
+        sort_step_buffer.unmap();
+
+        let sort_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, KEY_SIZE)
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, true, 8)
+            .build(&renderer.device, Some("particle sort bgl"));
+        let sort_bg = BindGroupBuilder::new()
+            .append_buffer(&key_buffer)
+            .append_buffer_with_size(&sort_step_buffer, 8)
+            .build(&renderer.device, Some("particle sort bg"), &sort_bgl);
+        let sort_module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("particle sort"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/particles/sort.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let sort_pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("particle sort"),
+            bind_group_layouts: &[&sort_bgl],
+            push_constant_ranges: &[],
+        });
+        let sort_pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("particle sort"),
+            layout: Some(&sort_pll),
+            module: &sort_module,
+            entry_point: "cs_main",
+        });
+
+        let render_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::VERTEX_FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                ParticleUniforms::SHADER_SIZE.get(),
+            )
+            .append_buffer(ShaderStages::VERTEX, BufferBindingType::Storage { read_only: true }, false, PARTICLE_SIZE)
+            .append_buffer(ShaderStages::VERTEX, BufferBindingType::Storage { read_only: true }, false, KEY_SIZE)
+            .build(&renderer.device, Some("particle render bgl"));
+        let render_bg = BindGroupBuilder::new()
+            .append_buffer(&uniform_buffer)
+            .append_buffer(&particle_buffer)
+            .append_buffer(&key_buffer)
+            .build(&renderer.device, Some("particle render bg"), &render_bgl);
+        let render_module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("particle render"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/particles/render.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let render_pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("particle render"),
+            bind_group_layouts: &[&render_bgl],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            emitter,
+            max_particles,
+            sort_count,
+
+            frame: ParticleFrame::default(),
+            spawn_cursor: 0,
+            spawn_remainder: 0.0,
+
+            uniform_buffer,
+            particle_buffer,
+            key_buffer,
+            sort_step_stride,
+            sort_steps: steps.len() as u32,
+
+            simulate_s1: create_simulate_pipeline(renderer, spp, SampleCount::One),
+            simulate_s4: create_simulate_pipeline(renderer, spp, SampleCount::Four),
+            sort_bg,
+            sort_pipeline,
+            render_bg,
+            render_s1: create_render_pipeline(renderer, &render_pll, &render_module, SampleCount::One),
+            render_s4: create_render_pipeline(renderer, &render_pll, &render_module, SampleCount::Four),
+        }
+    }
+
+    pub fn emitter(&self) -> &ParticleEmitter {
+        &self.emitter
+    }
+
+    pub fn emitter_mut(&mut self) -> &mut ParticleEmitter {
+        &mut self.emitter
+    }
+
+    pub fn max_particles(&self) -> u32 {
+        self.max_particles
+    }
+
+    /// Advances the simulation of the next frame by `delta_time` seconds,
+    /// spawning the particles emitted in that time. Must be called once a
+    /// frame before the render graph is built.
+    pub fn update(&mut self, delta_time: f32) {
+        let spawn = self.emitter.spawn_rate.max(0.0) * delta_time.max(0.0) + self.spawn_remainder;
+        let spawn_count = (spawn.floor() as u32).min(self.max_particles);
+        self.spawn_remainder = spawn.fract();
+
+        self.frame = ParticleFrame {
+            delta_time,
+            spawn_start: self.spawn_cursor,
+            spawn_count,
+            seed: self.frame.seed.wrapping_add(1),
+        };
+        self.spawn_cursor = (self.spawn_cursor + spawn_count) % self.max_particles;
+    }
+
+    /// Simulates and sorts the particles, colliding with `depth`, then draws
+    /// them with `renderpass`, which must use `depth` as its depth target.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        samples: SampleCount,
+        resolution: UVec2,
+        renderpass: RenderPassTargets,
+    ) {
+        self.add_simulation_to_graph(graph, depth, samples, resolution);
+        self.add_render_to_graph(graph, samples, renderpass);
+    }
+
+    fn add_simulation_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        samples: SampleCount,
+        resolution: UVec2,
+    ) {
+        let mut builder = graph.add_node("Particle Simulation");
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let view_proj = camera.view_proj();
+            let emitter = &self.emitter;
+            let uniforms = ParticleUniforms {
+                view: camera.view(),
+                view_proj,
+                inv_view_proj: view_proj.inverse(),
+                emitter_position: emitter.position,
+                delta_time: self.frame.delta_time,
+                velocity: emitter.velocity,
+                spread: emitter.spread,
+                gravity: emitter.gravity,
+                lifetime: emitter.lifetime,
+                color_start: emitter.color_start,
+                color_end: emitter.color_end,
+                resolution,
+                max_particles: self.max_particles,
+                sort_count: self.sort_count,
+                spawn_start: self.frame.spawn_start,
+                spawn_count: self.frame.spawn_count,
+                seed: self.frame.seed,
+                collision: emitter.collision as u32,
+                size: emitter.size,
+                restitution: emitter.restitution,
+                collision_thickness: emitter.collision_thickness,
+            };
+            let mut data = UniformBuffer::new(Vec::with_capacity(ParticleUniforms::SHADER_SIZE.get() as usize));
+            data.write(&uniforms).unwrap();
+            ctx.renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+
+            let simulate = match samples {
+                SampleCount::One => &self.simulate_s1,
+                SampleCount::Four => &self.simulate_s4,
+            };
+            let simulate_bg = BindGroupBuilder::new()
+                .append_buffer(&self.uniform_buffer)
+                .append_buffer(&self.particle_buffer)
+                .append_buffer(&self.key_buffer)
+                .append_texture_view(depth)
+                .build(&ctx.renderer.device, Some("particle simulation bg"), &simulate.bgl);
+
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("particle simulation"),
+                timestamp_writes: None,
+            });
+            let workgroups = (self.sort_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+
+            cpass.set_pipeline(&simulate.pipeline);
+            cpass.set_bind_group(0, &simulate_bg, &[]);
+            cpass.dispatch_workgroups(workgroups, 1, 1);
+
+            cpass.set_pipeline(&self.sort_pipeline);
+            for step in 0..self.sort_steps {
+                let offset = step as u64 * self.sort_step_stride;
+                cpass.set_bind_group(0, &self.sort_bg, &[offset as u32]);
+                cpass.dispatch_workgroups(workgroups, 1, 1);
+            }
+        });
+    }
+
+    fn add_render_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        samples: SampleCount,
+        renderpass: RenderPassTargets,
+    ) {
+        let mut builder = graph.add_node("Particles");
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let pipeline = match samples {
+                SampleCount::One => &self.render_s1,
+                SampleCount::Four => &self.render_s4,
+            };
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, &self.render_bg, &[]);
+            rpass.draw(0..6, 0..self.max_particles);
+        });
+    }
+}
+
+fn create_simulate_pipeline(renderer: &Renderer, spp: &ShaderPreProcessor, samples: SampleCount) -> SimulatePipeline {
+    let bgl = BindGroupLayoutBuilder::new()
+        .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, false, ParticleUniforms::SHADER_SIZE.get())
+        .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, PARTICLE_SIZE)
+        .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, KEY_SIZE)
+        .append(
+            ShaderStages::COMPUTE,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Depth,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: samples.needs_resolve(),
+            },
+            None,
+        )
+        .build(&renderer.device, Some("particle simulation bgl"));
+
+    let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("particle simulation"),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+            "rend3-routine/particles/simulate.wgsl",
+            &ParticleShaderConfig { samples: samples as u32 },
+            None,
+        ))),
+    });
+    let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("particle simulation"),
+        bind_group_layouts: &[&bgl],
+        push_constant_ranges: &[],
+    });
+    let pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("particle simulation"),
+        layout: Some(&pll),
+        module: &module,
+        entry_point: "cs_main",
+    });
+
+    SimulatePipeline { bgl, pipeline }
+}
+
+fn create_render_pipeline(
+    renderer: &Renderer,
+    pll: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    samples: SampleCount,
+) -> RenderPipeline {
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("particle render"),
+        layout: Some(pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::GreaterEqual,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState { count: samples as u32, ..Default::default() },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba16Float,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
@@ -1,12 +1,13 @@
 //! Types which make up `rend3-routine`'s material [`PbrMaterial`]
 
 use encase::ShaderType;
-use glam::{Mat3, Vec3, Vec4};
+use glam::{Mat3, Vec2, Vec3, Vec4};
 use rend3::types::{
-    Material, RawTexture2DHandle, Sorting, Texture2DHandle, VertexAttributeId, VERTEX_ATTRIBUTE_COLOR_0,
-    VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+    Material, MaterialParameter, ObjectFadeMode, RawTexture2DHandle, Sorting, Texture2DHandle, VertexAttributeId,
+    VERTEX_ATTRIBUTE_COLOR_0, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
     VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0, VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1,
 };
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState};
 
 bitflags::bitflags! {
     /// Flags which shaders use to determine properties of a material
@@ -27,6 +28,12 @@ bitflags::bitflags! {
         const CC_BW_SPLIT =         0b0001_0000_0000_0000;
         const UNLIT =               0b0010_0000_0000_0000;
         const NEAREST =             0b0100_0000_0000_0000;
+        const ALPHA_TO_COVERAGE =   0b1000_0000_0000_0000;
+        const BLEND_PREMULTIPLIED = 0b0001_0000_0000_0000_0000;
+        const BLEND_ADDITIVE =      0b0010_0000_0000_0000_0000;
+        const BLEND_MULTIPLY =      0b0100_0000_0000_0000_0000;
+        const BLEND_SCREEN =        0b1000_0000_0000_0000_0000;
+        const EMISSIVE_BLOOM =      0b0001_0000_0000_0000_0000_0000;
     }
 }
 
@@ -367,6 +374,127 @@ impl Default for ClearcoatTextures {
     }
 }
 
+/// Offset, scale and rotation of texture coordinates, as used by glTF's
+/// `KHR_texture_transform`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    /// Counter-clockwise rotation in radians.
+    pub rotation: f32,
+}
+
+impl UvTransform {
+    pub const IDENTITY: Self = Self { offset: Vec2::ZERO, scale: Vec2::ONE, rotation: 0.0 };
+
+    /// Scales, then rotates, then offsets the coordinates.
+    pub fn to_mat3(self) -> Mat3 {
+        Mat3::from_scale_angle_translation(self.scale, self.rotation, self.offset)
+    }
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<UvTransform> for Mat3 {
+    fn from(transform: UvTransform) -> Self {
+        transform.to_mat3()
+    }
+}
+
+/// Transforms of the texture coordinates of each texture of a [`PbrMaterial`],
+/// applied after the transform of their UV set. All default to the identity.
+///
+/// Combined textures use the transform of the slot holding them: `roughness`
+/// for [`AoMRTextures::Combined`] and `clearcoat` for
+/// [`ClearcoatTextures::GltfCombined`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PbrTextureTransforms {
+    pub albedo: Mat3,
+    pub normal: Mat3,
+    pub roughness: Mat3,
+    pub metallic: Mat3,
+    pub reflectance: Mat3,
+    pub clearcoat: Mat3,
+    pub clearcoat_roughness: Mat3,
+    pub emissive: Mat3,
+    pub anisotropy: Mat3,
+    pub ambient_occlusion: Mat3,
+}
+
+impl PbrTextureTransforms {
+    /// The transforms in the order of the texture slots.
+    fn to_array(self) -> [Mat3; 10] {
+        [
+            self.albedo,
+            self.normal,
+            self.roughness,
+            self.metallic,
+            self.reflectance,
+            self.clearcoat,
+            self.clearcoat_roughness,
+            self.emissive,
+            self.anisotropy,
+            self.ambient_occlusion,
+        ]
+    }
+}
+
+/// Set of texture coordinates a texture is sampled with.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum UvSet {
+    /// `VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0`, through
+    /// [`PbrMaterial::uv_transform0`].
+    #[default]
+    Uv0,
+    /// `VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1`, through
+    /// [`PbrMaterial::uv_transform1`]. Used by lightmaps and detail maps.
+    Uv1,
+}
+
+/// The [`UvSet`] of each texture of a [`PbrMaterial`]. All default to
+/// [`UvSet::Uv0`].
+///
+/// Combined textures use the set of the slot holding them, like
+/// [`PbrTextureTransforms`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PbrTextureUvSets {
+    pub albedo: UvSet,
+    pub normal: UvSet,
+    pub roughness: UvSet,
+    pub metallic: UvSet,
+    pub reflectance: UvSet,
+    pub clearcoat: UvSet,
+    pub clearcoat_roughness: UvSet,
+    pub emissive: UvSet,
+    pub anisotropy: UvSet,
+    pub ambient_occlusion: UvSet,
+}
+
+impl PbrTextureUvSets {
+    /// Bit per texture slot, in the order of the slots, set for [`UvSet::Uv1`].
+    fn to_bits(self) -> u32 {
+        [
+            self.albedo,
+            self.normal,
+            self.roughness,
+            self.metallic,
+            self.reflectance,
+            self.clearcoat,
+            self.clearcoat_roughness,
+            self.emissive,
+            self.anisotropy,
+            self.ambient_occlusion,
+        ]
+        .into_iter()
+        .enumerate()
+        .fold(0, |bits, (slot, set)| bits | (((set == UvSet::Uv1) as u32) << slot))
+    }
+}
+
 /// How textures should be sampled.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SampleType {
@@ -414,6 +542,76 @@ impl TransparencyType {
             Self::Blend => Sorting::BLENDING,
         }
     }
+
+    /// The transparency objects are drawn with while they fade. Only the
+    /// cutout and blend pipelines fade objects.
+    pub fn to_faded(self, mode: ObjectFadeMode) -> Self {
+        match (self, mode) {
+            (Self::Blend, _) | (_, ObjectFadeMode::Blend) => Self::Blend,
+            (Self::Opaque | Self::Cutout, ObjectFadeMode::Dither) => Self::Cutout,
+        }
+    }
+}
+
+/// How [`Transparency::Blend`] materials combine with what is behind them.
+///
+/// Objects of every blend mode are drawn by the same pass, sorted back to
+/// front together.
+#[repr(u8)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The color is faded over what is behind by alpha.
+    #[default]
+    Alpha,
+    /// The color is already multiplied by alpha and added, while alpha only
+    /// darkens what is behind, like reflections on glass.
+    PremultipliedAlpha,
+    /// The color, scaled by alpha, is added to what is behind, like fire and
+    /// holograms.
+    Additive,
+    /// What is behind is multiplied by the color, faded to white by alpha,
+    /// like tinted glass.
+    Multiply,
+    /// What is behind is brightened by the color, scaled by alpha, without
+    /// going past white, like glowing UI in the world.
+    Screen,
+}
+impl BlendMode {
+    pub const ARRAY: [Self; 5] = [Self::Alpha, Self::PremultipliedAlpha, Self::Additive, Self::Multiply, Self::Screen];
+
+    /// Material key of the objects of the blend mode. Alpha blending keeps
+    /// the key of [`TransparencyType::Blend`].
+    pub fn key(self) -> u64 {
+        TransparencyType::Blend as u64 + self as u64
+    }
+
+    /// Blend state of the color target.
+    pub fn to_blend_state(self) -> BlendState {
+        // Modes other than alpha blending keep the alpha of what is behind.
+        let keep = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let color = |src_factor, dst_factor| BlendComponent { src_factor, dst_factor, operation: BlendOperation::Add };
+        match self {
+            Self::Alpha => BlendState::ALPHA_BLENDING,
+            Self::PremultipliedAlpha => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            Self::Additive => BlendState { color: color(BlendFactor::One, BlendFactor::One), alpha: keep },
+            Self::Multiply => BlendState { color: color(BlendFactor::Zero, BlendFactor::Src), alpha: keep },
+            Self::Screen => BlendState { color: color(BlendFactor::One, BlendFactor::OneMinusSrc), alpha: keep },
+        }
+    }
+
+    fn to_flags(self) -> MaterialFlags {
+        match self {
+            Self::Alpha => MaterialFlags::empty(),
+            Self::PremultipliedAlpha => MaterialFlags::BLEND_PREMULTIPLIED,
+            Self::Additive => MaterialFlags::BLEND_ADDITIVE,
+            Self::Multiply => MaterialFlags::BLEND_MULTIPLY,
+            Self::Screen => MaterialFlags::BLEND_SCREEN,
+        }
+    }
 }
 
 #[allow(clippy::cmp_owned)] // This thinks making a temporary TransparencyType is the end of the world
@@ -455,6 +653,10 @@ impl Default for Transparency {
 pub struct PbrMaterial {
     pub albedo: AlbedoComponent,
     pub transparency: Transparency,
+    /// How the material blends with what is behind it, if
+    /// [`Transparency::Blend`]. Faded objects of other materials use
+    /// [`BlendMode::Alpha`].
+    pub blend_mode: BlendMode,
     pub normal: NormalTexture,
     pub aomr_textures: AoMRTextures,
     pub ao_factor: Option<f32>,
@@ -464,13 +666,47 @@ pub struct PbrMaterial {
     pub clearcoat_factor: Option<f32>,
     pub clearcoat_roughness_factor: Option<f32>,
     pub emissive: MaterialComponent<Vec3>,
+    /// Multiplier of the emissive color, defaulting to 1. Values above 1 give
+    /// HDR emission, which stays bright after tonemapping.
+    pub emissive_intensity: Option<f32>,
+    /// Spread the emission of the material into its surroundings with a
+    /// [`BloomRoutine`](crate::bloom::BloomRoutine). Only opaque and cutout
+    /// materials bloom.
+    pub emissive_bloom: bool,
     pub reflectance: MaterialComponent<f32>,
     pub anisotropy: MaterialComponent<f32>,
     pub uv_transform0: Mat3,
     pub uv_transform1: Mat3,
+    /// Per texture transforms, applied after `uv_transform0` or
+    /// `uv_transform1`, depending on the UV set of the texture.
+    pub texture_transforms: PbrTextureTransforms,
+    /// Which texture coordinates each texture samples with.
+    pub texture_uv_sets: PbrTextureUvSets,
     // TODO: Make unlit a different shader entirely.
+    /// Skip lighting, outputting the albedo plus the emissive color.
     pub unlit: bool,
     pub sample_type: SampleType,
+    /// With multisampling, fade the edges of [`Transparency::Cutout`] over
+    /// the samples of each pixel instead of discarding, which softens foliage
+    /// and fences. Without multisampling, this is a regular cutout.
+    pub alpha_to_coverage: bool,
+}
+
+impl PbrMaterial {
+    /// Material key of the material drawn with the given transparency.
+    fn transparency_key(&self, transparency: TransparencyType) -> u64 {
+        match transparency {
+            TransparencyType::Blend => self.effective_blend_mode().key(),
+            TransparencyType::Opaque | TransparencyType::Cutout => transparency as u64,
+        }
+    }
+
+    fn effective_blend_mode(&self) -> BlendMode {
+        match self.transparency {
+            Transparency::Blend => self.blend_mode,
+            Transparency::Opaque | Transparency::Cutout { .. } => BlendMode::Alpha,
+        }
+    }
 }
 
 impl Material for PbrMaterial {
@@ -495,13 +731,21 @@ impl Material for PbrMaterial {
     }
 
     fn key(&self) -> u64 {
-        TransparencyType::from(self.transparency) as u64
+        self.transparency_key(TransparencyType::from(self.transparency))
     }
 
     fn sorting(&self) -> Sorting {
         TransparencyType::from(self.transparency).to_sorting()
     }
 
+    fn faded_key(&self, mode: ObjectFadeMode) -> u64 {
+        self.transparency_key(TransparencyType::from(self.transparency).to_faded(mode))
+    }
+
+    fn faded_sorting(&self, mode: ObjectFadeMode) -> Sorting {
+        TransparencyType::from(self.transparency).to_faded(mode).to_sorting()
+    }
+
     fn to_textures(&self) -> Self::TextureArrayType {
         [
             self.albedo.to_texture(),
@@ -521,12 +765,37 @@ impl Material for PbrMaterial {
     fn to_data(&self) -> Self::DataType {
         ShaderMaterial::from_material(self)
     }
+
+    fn parameters(&self) -> Vec<(&'static str, MaterialParameter)> {
+        type P = MaterialParameter;
+
+        // Values as the shader sees them, with defaults filled in.
+        vec![
+            ("albedo", P::Vec4(self.albedo.to_value())),
+            ("transparency", P::Other(format!("{:?}", self.transparency))),
+            ("blend_mode", P::Other(format!("{:?}", self.effective_blend_mode()))),
+            ("ao_factor", P::Float(self.ao_factor.unwrap_or(1.0))),
+            ("metallic_factor", P::Float(self.metallic_factor.unwrap_or(0.0))),
+            ("roughness_factor", P::Float(self.roughness_factor.unwrap_or(0.0))),
+            ("clearcoat_factor", P::Float(self.clearcoat_factor.unwrap_or(0.0))),
+            ("clearcoat_roughness_factor", P::Float(self.clearcoat_roughness_factor.unwrap_or(0.0))),
+            ("emissive", P::Vec3(self.emissive.to_value(Vec3::ZERO))),
+            ("emissive_intensity", P::Float(self.emissive_intensity.unwrap_or(1.0))),
+            ("emissive_bloom", P::Bool(self.emissive_bloom)),
+            ("reflectance", P::Float(self.reflectance.to_value(0.5))),
+            ("anisotropy", P::Float(self.anisotropy.to_value(0.0))),
+            ("unlit", P::Bool(self.unlit)),
+            ("sample_type", P::Other(format!("{:?}", self.sample_type))),
+            ("alpha_to_coverage", P::Bool(self.alpha_to_coverage)),
+        ]
+    }
 }
 
 #[derive(Debug, Default, Copy, Clone, ShaderType)]
 pub struct ShaderMaterial {
     uv_transform0: Mat3,
     uv_transform1: Mat3,
+    texture_transforms: [Mat3; 10],
 
     albedo: Vec4,
     emissive: Vec3,
@@ -540,6 +809,7 @@ pub struct ShaderMaterial {
     alpha_cutout: f32,
 
     material_flags: u32,
+    uv1_slots: u32,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderMaterial {}
@@ -550,13 +820,14 @@ impl ShaderMaterial {
         Self {
             uv_transform0: material.uv_transform0,
             uv_transform1: material.uv_transform1,
+            texture_transforms: material.texture_transforms.to_array(),
             albedo: material.albedo.to_value(),
             roughness: material.roughness_factor.unwrap_or(0.0),
             metallic: material.metallic_factor.unwrap_or(0.0),
             reflectance: material.reflectance.to_value(0.5),
             clear_coat: material.clearcoat_factor.unwrap_or(0.0),
             clear_coat_roughness: material.clearcoat_roughness_factor.unwrap_or(0.0),
-            emissive: material.emissive.to_value(Vec3::ZERO),
+            emissive: material.emissive.to_value(Vec3::ZERO) * material.emissive_intensity.unwrap_or(1.0),
             anisotropy: material.anisotropy.to_value(0.0),
             ambient_occlusion: material.ao_factor.unwrap_or(1.0),
             alpha_cutout: match material.transparency {
@@ -569,6 +840,9 @@ impl ShaderMaterial {
                 flags |= material.aomr_textures.to_flags();
                 flags |= material.clearcoat_textures.to_flags();
                 flags.set(MaterialFlags::UNLIT, material.unlit);
+                flags.set(MaterialFlags::EMISSIVE_BLOOM, material.emissive_bloom);
+                flags.set(MaterialFlags::ALPHA_TO_COVERAGE, material.alpha_to_coverage);
+                flags |= material.effective_blend_mode().to_flags();
                 flags.set(
                     MaterialFlags::NEAREST,
                     match material.sample_type {
@@ -578,6 +852,7 @@ impl ShaderMaterial {
                 );
                 flags.bits()
             },
+            uv1_slots: material.texture_uv_sets.to_bits(),
         }
     }
 }
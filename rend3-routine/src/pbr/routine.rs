@@ -2,27 +2,67 @@ use std::{borrow::Cow, sync::Arc};
 
 use rend3::{Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor, ShaderVertexBufferConfig};
 use serde::Serialize;
-use wgpu::{BlendState, ShaderModuleDescriptor, ShaderSource};
+use wgpu::{
+    BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState, CompareFunction, Face,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource,
+};
 
 use crate::{
     common::{PerMaterialArchetypeInterface, WholeFrameInterfaces},
     forward::{ForwardRoutine, ForwardRoutineCreateArgs, RoutineType, ShaderModulePair},
-    pbr::{PbrMaterial, TransparencyType},
+    pbr::{BlendMode, PbrMaterial, TransparencyType},
 };
 
+/// What a routine of [`PbrRoutine`] is for, besides its transparency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PassKind {
+    Regular,
+    Overdraw,
+    Prepass,
+    /// Also writes the emission of materials flagged to bloom.
+    Bloom,
+}
+
 #[derive(Serialize)]
 struct BlendModeWrapper {
     profile: RendererProfile,
     discard: bool,
+    velocity: bool,
+    emission: bool,
 }
 
 /// Render routine that renders the using PBR materials
 pub struct PbrRoutine {
     pub opaque_depth: ForwardRoutine<PbrMaterial>,
     pub cutout_depth: ForwardRoutine<PbrMaterial>,
+    /// Depth routines of the viewport, used before
+    /// [`tiled_lighting`](crate::tiled_lighting).
+    pub opaque_prepass: ForwardRoutine<PbrMaterial>,
+    pub cutout_prepass: ForwardRoutine<PbrMaterial>,
     pub opaque_routine: ForwardRoutine<PbrMaterial>,
     pub cutout_routine: ForwardRoutine<PbrMaterial>,
+    /// Routines of the viewport, also writing the motion of each pixel, see
+    /// [`velocity`](crate::velocity).
+    pub opaque_velocity: ForwardRoutine<PbrMaterial>,
+    pub cutout_velocity: ForwardRoutine<PbrMaterial>,
     pub blend_routine: ForwardRoutine<PbrMaterial>,
+    /// Routines writing the G-buffer of [`deferred`](crate::deferred)
+    /// shading, and the motion of each pixel.
+    pub opaque_gbuffer: ForwardRoutine<PbrMaterial>,
+    pub cutout_gbuffer: ForwardRoutine<PbrMaterial>,
+    /// Routines of the viewport writing velocity, also writing the emission
+    /// of materials with [`PbrMaterial::emissive_bloom`] into a target after
+    /// the others, see [`bloom`](crate::bloom).
+    pub opaque_bloom: ForwardRoutine<PbrMaterial>,
+    pub cutout_bloom: ForwardRoutine<PbrMaterial>,
+    /// Routines writing the G-buffer and the motion of each pixel, also
+    /// writing the emission of materials flagged to bloom.
+    pub opaque_gbuffer_bloom: ForwardRoutine<PbrMaterial>,
+    pub cutout_gbuffer_bloom: ForwardRoutine<PbrMaterial>,
+    /// Routines used for [`DebugView::Overdraw`](crate::uniforms::DebugView::Overdraw),
+    /// one per transparency type. These ignore depth and additively blend.
+    /// Their pipelines are only created once the debug view is turned on.
+    pub overdraw_routines: [ForwardRoutine<PbrMaterial>; 3],
     pub per_material: PerMaterialArchetypeInterface<PbrMaterial>,
 }
 
@@ -40,86 +80,241 @@ impl PbrRoutine {
 
         let per_material = PerMaterialArchetypeInterface::<PbrMaterial>::new(&renderer.device);
 
-        let pbr_depth_cutout = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        let pbr_depth_cutout = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("pbr depth cutout sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: true },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_depth = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/depth.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: true, velocity: false, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_depth = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("pbr depth sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: false },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_cutout = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/depth.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: false, velocity: false, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_cutout = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("pbr opaque cutout sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: true },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_forward = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: true, velocity: false, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_forward = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("pbr opaque sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: false },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let mut inner = |routine_type, module, transparency| {
-            ForwardRoutine::new(ForwardRoutineCreateArgs {
-                name: &format!("pbr {routine_type:?} {transparency:?}"),
-                renderer,
-                data_core,
-                spp,
-                interfaces,
-                per_material: &per_material,
-                material_key: transparency as u64,
-                routine_type,
-                shaders: ShaderModulePair {
-                    vs_entry: "vs_main",
-                    vs_module: module,
-                    fs_entry: "fs_main",
-                    fs_module: module,
-                },
-                extra_bgls: &[],
-                descriptor_callback: Some(&|desc, targets| {
-                    if transparency == TransparencyType::Blend {
-                        desc.depth_stencil.as_mut().unwrap().depth_write_enabled = false;
-                        targets[0].as_mut().unwrap().blend = Some(BlendState::ALPHA_BLENDING)
-                    }
-                }),
-            })
-        };
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: false, velocity: false, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_cutout_velocity = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("pbr opaque cutout velocity sm"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: true, velocity: true, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_forward_velocity = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("pbr opaque velocity sm"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: false, velocity: true, emission: false },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_cutout_bloom = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("pbr opaque cutout bloom sm"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: true, velocity: true, emission: true },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let pbr_forward_bloom = Arc::new(renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("pbr opaque bloom sm"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/opaque.wgsl",
+                &BlendModeWrapper { profile: renderer.profile, discard: false, velocity: true, emission: true },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            ))),
+        }));
+
+        let mut inner =
+            |routine_type: RoutineType, module: &Arc<ShaderModule>, transparency, kind: PassKind, velocity: bool| {
+                let fs_entry = match routine_type {
+                    RoutineType::GBuffer => "fs_gbuffer",
+                    RoutineType::Depth | RoutineType::Forward => "fs_main",
+                };
+                let forward = matches!(routine_type, RoutineType::Forward);
+                // Blend routines draw every blend mode, sorted together.
+                let blend_keys = BlendMode::ARRAY.map(BlendMode::key);
+                let single_key = [transparency as u64];
+                let material_keys: &[u64] = match transparency {
+                    TransparencyType::Blend => &blend_keys,
+                    TransparencyType::Opaque | TransparencyType::Cutout => &single_key,
+                };
+                ForwardRoutine::new(ForwardRoutineCreateArgs {
+                    name: &format!(
+                        "pbr {routine_type:?} {transparency:?}{}{}",
+                        match kind {
+                            PassKind::Regular => "",
+                            PassKind::Overdraw => " overdraw",
+                            PassKind::Prepass => " prepass",
+                            PassKind::Bloom => " bloom",
+                        },
+                        if velocity { " velocity" } else { "" }
+                    ),
+                    renderer,
+                    data_core,
+                    spp,
+                    interfaces,
+                    per_material: &per_material,
+                    material_keys,
+                    routine_type,
+                    shaders: ShaderModulePair { vs_entry: "vs_main", vs_module: module, fs_entry, fs_module: module },
+                    extra_bgls: &[],
+                    lod_cross_fade: transparency == TransparencyType::Cutout,
+                    velocity,
+                    emission: kind == PassKind::Bloom,
+                    descriptor_callback: Some(Arc::new(
+                        move |key: u64,
+                              desc: &mut RenderPipelineDescriptor<'_>,
+                              targets: &mut [Option<ColorTargetState>]| {
+                            let multisampled = desc.multisample.count > 1;
+                            if kind == PassKind::Prepass {
+                                // Unlike shadows, the viewport sees the front faces.
+                                desc.primitive.cull_mode = Some(Face::Back);
+                            } else if kind == PassKind::Overdraw {
+                                let depth_stencil = desc.depth_stencil.as_mut().unwrap();
+                                depth_stencil.depth_write_enabled = false;
+                                depth_stencil.depth_compare = CompareFunction::Always;
+                                let additive = BlendComponent {
+                                    src_factor: BlendFactor::One,
+                                    dst_factor: BlendFactor::One,
+                                    operation: BlendOperation::Add,
+                                };
+                                targets[0].as_mut().unwrap().blend =
+                                    Some(BlendState { color: additive, alpha: additive });
+                            } else if transparency == TransparencyType::Blend {
+                                let mode = BlendMode::ARRAY.into_iter().find(|mode| mode.key() == key).unwrap();
+                                desc.depth_stencil.as_mut().unwrap().depth_write_enabled = false;
+                                targets[0].as_mut().unwrap().blend = Some(mode.to_blend_state())
+                            } else if transparency == TransparencyType::Cutout && forward && multisampled {
+                                // See PbrMaterial::alpha_to_coverage.
+                                desc.multisample.alpha_to_coverage_enabled = true;
+                                desc.fragment.as_mut().unwrap().entry_point = "fs_alpha_to_coverage";
+                            }
+                        },
+                    )),
+                })
+            };
 
         Self {
-            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque),
-            cutout_depth: inner(RoutineType::Depth, &pbr_depth_cutout, TransparencyType::Cutout),
-            opaque_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque),
-            cutout_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout),
-            blend_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend),
+            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque, PassKind::Regular, false),
+            cutout_depth: inner(
+                RoutineType::Depth,
+                &pbr_depth_cutout,
+                TransparencyType::Cutout,
+                PassKind::Regular,
+                false,
+            ),
+            opaque_prepass: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque, PassKind::Prepass, false),
+            cutout_prepass: inner(
+                RoutineType::Depth,
+                &pbr_depth_cutout,
+                TransparencyType::Cutout,
+                PassKind::Prepass,
+                false,
+            ),
+            opaque_routine: inner(
+                RoutineType::Forward,
+                &pbr_forward,
+                TransparencyType::Opaque,
+                PassKind::Regular,
+                false,
+            ),
+            cutout_routine: inner(
+                RoutineType::Forward,
+                &pbr_cutout,
+                TransparencyType::Cutout,
+                PassKind::Regular,
+                false,
+            ),
+            opaque_velocity: inner(
+                RoutineType::Forward,
+                &pbr_forward_velocity,
+                TransparencyType::Opaque,
+                PassKind::Regular,
+                true,
+            ),
+            cutout_velocity: inner(
+                RoutineType::Forward,
+                &pbr_cutout_velocity,
+                TransparencyType::Cutout,
+                PassKind::Regular,
+                true,
+            ),
+            blend_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend, PassKind::Regular, false),
+            opaque_gbuffer: inner(
+                RoutineType::GBuffer,
+                &pbr_forward_velocity,
+                TransparencyType::Opaque,
+                PassKind::Regular,
+                true,
+            ),
+            cutout_gbuffer: inner(
+                RoutineType::GBuffer,
+                &pbr_cutout_velocity,
+                TransparencyType::Cutout,
+                PassKind::Regular,
+                true,
+            ),
+            opaque_bloom: inner(
+                RoutineType::Forward,
+                &pbr_forward_bloom,
+                TransparencyType::Opaque,
+                PassKind::Bloom,
+                true,
+            ),
+            cutout_bloom: inner(
+                RoutineType::Forward,
+                &pbr_cutout_bloom,
+                TransparencyType::Cutout,
+                PassKind::Bloom,
+                true,
+            ),
+            opaque_gbuffer_bloom: inner(
+                RoutineType::GBuffer,
+                &pbr_forward_bloom,
+                TransparencyType::Opaque,
+                PassKind::Bloom,
+                true,
+            ),
+            cutout_gbuffer_bloom: inner(
+                RoutineType::GBuffer,
+                &pbr_cutout_bloom,
+                TransparencyType::Cutout,
+                PassKind::Bloom,
+                true,
+            ),
+            overdraw_routines: [
+                inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque, PassKind::Overdraw, false),
+                inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout, PassKind::Overdraw, false),
+                inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend, PassKind::Overdraw, false),
+            ],
             per_material,
         }
     }
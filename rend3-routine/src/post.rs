@@ -0,0 +1,220 @@
+//! Fullscreen post process passes.
+//!
+//! A [`PostProcessPass`] runs a fragment shader over every pixel of the image,
+//! sampling the graph targets it asks for, and can be inserted into the base
+//! render graph through
+//! [`BaseRenderGraphRoutines::post_process`](crate::base::BaseRenderGraphRoutines::post_process)
+//! without copying [`BaseRenderGraph::add_to_graph`](crate::base::BaseRenderGraph::add_to_graph).
+//!
+//! The shader must be added to the [`ShaderPreProcessor`], include
+//! `rend3-routine/post.wgsl` and define `fs_main`:
+//!
+//! ```wgsl
+//! {{include "rend3-routine/post.wgsl"}}
+//!
+//! @group(1) @binding(0)
+//! var color: texture_2d<f32>;
+//!
+//! @fragment
+//! fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+//!     let sampled = textureSample(color, primary_sampler, vout.tex_coords);
+//!     return vec4<f32>(1.0 - sampled.rgb, sampled.a);
+//! }
+//! ```
+//!
+//! The inputs are bound in group 1 in the order they were given, [`Color`]
+//! as a `texture_2d<f32>` and [`Depth`] as a `texture_depth_2d`. Group 0 has
+//! the samplers and the frame uniforms. `rend3-routine/math/depth.wgsl` has
+//! helpers for reading the depth, see [`depth_resolve`](crate::depth_resolve).
+//!
+//! [`Color`]: PostProcessInput::Color
+//! [`Depth`]: PostProcessInput::Depth
+
+use std::borrow::Cow;
+
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension,
+    VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// A graph target sampled by a post process pass.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostProcessInput {
+    /// The image the pass replaces.
+    Color,
+    /// The single sampled depth of the scene, see
+    /// [`DepthTargets::readable`](crate::base::DepthTargets::readable).
+    Depth,
+}
+
+/// Where in the base render graph a post process pass runs, and so what image
+/// it replaces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostProcessOutput {
+    /// Runs on the HDR image after transparent objects and debug geometry are
+    /// drawn, before tonemapping.
+    AfterTransparency,
+    /// Runs on the tonemapped image, which has the given format, the format of
+    /// the output.
+    AfterTonemapping(TextureFormat),
+}
+
+impl PostProcessOutput {
+    /// Format of the images read and written at this stage.
+    pub fn format(self) -> TextureFormat {
+        match self {
+            Self::AfterTransparency => TextureFormat::Rgba16Float,
+            Self::AfterTonemapping(format) => format,
+        }
+    }
+}
+
+/// Fullscreen pass running a user shader.
+///
+/// See module for documentation.
+pub struct PostProcessPass {
+    label: String,
+    inputs: Vec<PostProcessInput>,
+    output: PostProcessOutput,
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl PostProcessPass {
+    /// Creates a pass running the shader with the given name in the
+    /// preprocessor.
+    pub fn new(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        shader: &str,
+        inputs: &[PostProcessInput],
+        output: PostProcessOutput,
+    ) -> Self {
+        profiling::scope!("PostProcessPass::new");
+
+        let mut bglb = BindGroupLayoutBuilder::new();
+        for input in inputs {
+            let sample_type = match input {
+                PostProcessInput::Color => TextureSampleType::Float { filterable: true },
+                PostProcessInput::Depth => TextureSampleType::Depth,
+            };
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture { sample_type, view_dimension: TextureViewDimension::D2, multisampled: false },
+                None,
+            );
+        }
+        let bgl = bglb.build(&renderer.device, Some("post process bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(shader),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(shader, &ShaderConfig::default(), None))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(shader),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(shader),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: output.format(),
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { label: shader.to_owned(), inputs: inputs.to_vec(), output, bgl, pipeline }
+    }
+
+    pub fn inputs(&self) -> &[PostProcessInput] {
+        &self.inputs
+    }
+
+    pub fn output(&self) -> PostProcessOutput {
+        self.output
+    }
+
+    /// Adds the pass, reading `color` and `depth` as the inputs ask, and
+    /// writing `dst`, which must be a different target than `color`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node(self.label.as_str());
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+        let depth_handle = self
+            .inputs
+            .contains(&PostProcessInput::Depth)
+            .then(|| builder.add_render_target(depth.set_mips(0..1), NodeResourceUsage::Input));
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: glam::Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            let mut bgb = BindGroupBuilder::new();
+            for input in &self.inputs {
+                let view = match input {
+                    PostProcessInput::Color => ctx.graph_data.get_render_target(color_handle),
+                    PostProcessInput::Depth => ctx.graph_data.get_render_target(depth_handle.unwrap()),
+                };
+                bgb.append_texture_view(view);
+            }
+            let bg = ctx.temps.add(bgb.build(&ctx.renderer.device, Some("post process bg"), &self.bgl));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,239 @@
+//! Planar reflections for mirrors and calm water.
+//!
+//! The scene is rendered a second time from the viewport camera mirrored
+//! about a plane, with everything behind the plane clipped away, into a
+//! texture that can be used in any material slot. The texture lines up with
+//! the screen, so shaders should sample it at the fragment's position in the
+//! viewport, divided by the viewport resolution.
+//!
+//! Use [`BaseRenderGraphIntermediateState::planar_reflection`](crate::base::BaseRenderGraphIntermediateState::planar_reflection)
+//! to add the passes to the base render graph.
+
+use std::{borrow::Cow, sync::Arc};
+
+use glam::{Mat4, UVec2, Vec3, Vec4};
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
+    managers::CameraState,
+    types::{Camera, CameraProjection, Texture2DHandle},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        frustum::Plane,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, Color, ColorTargetState, ColorWrites, Extent3d, FragmentState, FrontFace,
+    LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StoreOp, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Format of the reflection texture.
+pub const REFLECTION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Builds the camera that sees the reflection of the scene in `plane`.
+///
+/// Only what is on the same side of the plane as `camera` is visible, using an
+/// oblique near plane that lies on `plane`. The image is flipped horizontally
+/// so that mirroring doesn't change the winding order of triangles.
+pub fn reflection_camera(camera: &CameraState, plane: Plane) -> CameraState {
+    let plane = plane.normalize();
+    let n = plane.abc;
+    let reflection = Mat4::from_cols(
+        (Vec3::X - 2.0 * n.x * n).extend(0.0),
+        (Vec3::Y - 2.0 * n.y * n).extend(0.0),
+        (Vec3::Z - 2.0 * n.z * n).extend(0.0),
+        (-2.0 * plane.d * n).extend(1.0),
+    );
+    let view = camera.view() * reflection;
+
+    // Keep what is on the side of the plane the real camera is on.
+    let side = if plane.distance(camera.location()) < 0.0 { -1.0 } else { 1.0 };
+    let view_plane = view.inverse().transpose() * n.extend(plane.d) * side;
+
+    let proj = Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)) * camera.proj();
+    let proj = oblique_near_plane(proj, view_plane);
+
+    CameraState::new(Camera { projection: CameraProjection::Raw(proj), view }, camera.handedness(), None)
+}
+
+/// Moves the near plane of a reverse-z projection onto the view space `plane`.
+///
+/// The far plane is put through the far corner of the frustum, see
+/// <https://terathon.com/lengyel/Lengyel-Oblique.pdf>.
+fn oblique_near_plane(proj: Mat4, plane: Vec4) -> Mat4 {
+    let inv_proj = proj.inverse();
+    let clip_plane = inv_proj.transpose() * plane;
+    let corner = inv_proj * Vec4::new(clip_plane.x.signum(), clip_plane.y.signum(), 0.0, 1.0);
+
+    let mut rows = proj.transpose();
+    let scale = rows.w_axis.dot(corner) / plane.dot(corner);
+    if !scale.is_finite() || scale <= 0.0 {
+        return proj;
+    }
+    rows.z_axis = rows.w_axis - plane * scale;
+    rows.transpose()
+}
+
+/// Renders the reflection of the scene in a plane into a texture usable by
+/// materials.
+///
+/// See module for documentation.
+pub struct PlanarReflectionRoutine {
+    texture: Texture2DHandle,
+    resolution: UVec2,
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl PlanarReflectionRoutine {
+    /// Creates the reflection texture with the given resolution. It should
+    /// have the aspect ratio of the viewport.
+    pub fn new(
+        renderer: &Arc<Renderer>,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        resolution: UVec2,
+    ) -> Self {
+        profiling::scope!("PlanarReflectionRoutine::new");
+
+        let desc = TextureDescriptor {
+            label: Some("planar reflection"),
+            size: Extent3d { width: resolution.x, height: resolution.y, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: REFLECTION_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        };
+        let texture = renderer.device.create_texture(&desc);
+        let texture = renderer.add_texture_2d_from_wgpu(texture, desc).expect("reflection texture is valid");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("planar reflection bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("planar reflection"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/blit.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("planar reflection"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("planar reflection"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main_mirror",
+                targets: &[Some(ColorTargetState {
+                    format: REFLECTION_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { texture, resolution, bgl, pipeline }
+    }
+
+    /// The texture the reflection is rendered into.
+    pub fn texture(&self) -> &Texture2DHandle {
+        &self.texture
+    }
+
+    pub fn resolution(&self) -> UVec2 {
+        self.resolution
+    }
+
+    /// Writes the viewport camera mirrored about `plane` into `camera`.
+    pub fn add_camera_to_graph(&self, graph: &mut RenderGraph<'_>, plane: Plane, camera: DataHandle<CameraState>) {
+        let mut builder = graph.add_node("Planar Reflection Camera");
+        let camera_handle = builder.add_data(camera, NodeResourceUsage::Output);
+
+        builder.build(move |ctx| {
+            let camera = reflection_camera(&ctx.data_core.viewport_camera_state, plane);
+            ctx.graph_data.set_data(camera_handle, Some(camera));
+        });
+    }
+
+    /// Copies the reflection rendered into `src` into the reflection texture,
+    /// undoing the horizontal flip of [`reflection_camera`].
+    pub fn add_output_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Planar Reflection Output");
+        let input_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let reflection = ctx.graph_data.get_render_target(input_handle);
+
+            // The texture is only added once the instructions that created it are evaluated.
+            let Some(output) = ctx.data_core.d2_texture_manager.try_get_internal(self.texture.get_raw()) else {
+                return;
+            };
+            let output_view = output.texture.create_view(&TextureViewDescriptor::default());
+
+            let src_bg = BindGroupBuilder::new().append_texture_view(reflection).build(
+                &ctx.renderer.device,
+                Some("planar reflection src bg"),
+                &self.bgl,
+            );
+
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("planar reflection output"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &output_view,
+                    resolve_target: None,
+                    ops: Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, &src_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -76,6 +76,8 @@ mod tests {
                         "profile": Some(RendererProfile::GpuDriven),
                         "position_attribute_offset": 0,
                         "SAMPLES": 1,
+                        "velocity": true,
+                        "emission": true,
                     }),
                     json!({
                         "profile": Some(RendererProfile::CpuDriven),
@@ -104,26 +106,69 @@ mod tests {
                 assert!(output.is_ok(), "Expected preprocessing success, got {output:?}");
                 let output = output.unwrap_or_else(|e| panic!("Expected preprocessing success, got {e:?}"));
 
-                let sm = match naga::front::wgsl::parse_str(&output) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        e.emit_to_stderr_with_path(&output, shader);
-                        panic!();
-                    }
-                };
-
-                let mut validator =
-                    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all());
-
-                match validator.validate(&sm) {
-                    Ok(_) => {}
-                    Err(err) => {
-                        emit_annotated_error(&err, shader, &output);
-                        print_err(&err);
-                        panic!()
-                    }
-                };
+                validate(shader, &output);
+
+                let checked =
+                    pp.check_shader(shader, &config, Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()));
+                if let Err(e) = checked {
+                    panic!("Expected checked shader to be valid, got {e}");
+                }
             }
         }
     }
+
+    #[test]
+    fn validate_mesh_data() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shaders_embed::<Rend3RoutineShaderSources>("rend3-routine");
+        pp.add_shader(
+            "mesh_data_user.wgsl",
+            r#"{{include "rend3-routine/mesh_data.wgsl"}}
+
+            @group(0) @binding(0)
+            var<storage> object_buffer: array<Object>;
+            @group(0) @binding(1)
+            var<storage> vertex_buffer: array<u32>;
+
+            @compute @workgroup_size(64)
+            fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+                if (id.x < object_triangle_count(id.y)) {
+                    let triangle = object_triangle(id.y, id.x);
+                }
+            }"#,
+        );
+
+        let config = json!({ "position_attribute_offset": 0 });
+        let output = pp
+            .render_shader(
+                "mesh_data_user.wgsl",
+                &config,
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            )
+            .unwrap();
+        assert!(output.contains(&format!("MESH_DATA_VERSION: u32 = {}u;", crate::mesh_data::MESH_DATA_VERSION)));
+        validate("mesh_data_user.wgsl", &output);
+    }
+
+    fn validate(shader: &str, output: &str) {
+        let sm = match naga::front::wgsl::parse_str(output) {
+            Ok(m) => m,
+            Err(e) => {
+                e.emit_to_stderr_with_path(output, shader);
+                panic!();
+            }
+        };
+
+        let mut validator =
+            naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all());
+
+        match validator.validate(&sm) {
+            Ok(_) => {}
+            Err(err) => {
+                emit_annotated_error(&err, shader, output);
+                print_err(&err);
+                panic!()
+            }
+        };
+    }
 }
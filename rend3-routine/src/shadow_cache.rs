@@ -0,0 +1,232 @@
+//! Caching of static shadow casters.
+//!
+//! Objects marked static with [`Renderer::set_object_static`] are rendered
+//! into a cached copy of the shadow atlas only when it is out of date. Every
+//! frame the cache is copied into the shadow atlas, and only the dynamic
+//! objects are rendered on top.
+//!
+//! The cache is out of date when a static object changes, when the atlas is
+//! repacked, or when a shadow camera moves. Shadow cameras follow the viewport
+//! camera in steps of one shadow texel, so the cache is reused as long as the
+//! viewport camera stays within a texel. When anything changes, the static
+//! objects of every light are rendered again.
+//!
+//! Call [`ShadowCacheRoutine::evaluate`] once a frame after evaluating
+//! instructions, then pass the routine to the base render graph through
+//! [`BaseRenderGraphRoutines::shadow_cache`](crate::base::BaseRenderGraphRoutines::shadow_cache).
+
+use std::borrow::Cow;
+
+use glam::{Mat4, UVec2};
+use rend3::{
+    graph::{
+        InstructionEvaluationOutput, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassLoadOp,
+        RenderPassTargets, RenderTargetHandle, ViewportRect,
+    },
+    managers::ShadowSource,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor, INTERNAL_SHADOW_DEPTH_FORMAT,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, CompareFunction, DepthBiasState, DepthStencilState, Extent3d, FragmentState,
+    FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState,
+    Texture, TextureDescriptor, TextureDimension, TextureSampleType, TextureUsages, TextureViewDimension, VertexState,
+};
+
+/// What a shadow map in the cache was rendered with.
+#[derive(Debug, Clone, PartialEq)]
+struct CachedShadowMap {
+    handle: ShadowSource,
+    offset: UVec2,
+    size: u32,
+    view_proj: Mat4,
+}
+
+impl CachedShadowMap {
+    fn matches(&self, other: &Self) -> bool {
+        // A tenth of a texel leaves room for rounding in the texel snapping of
+        // the shadow camera.
+        let tolerance = 0.2 / self.size as f32;
+        self.handle == other.handle
+            && self.offset == other.offset
+            && self.size == other.size
+            && self.view_proj.abs_diff_eq(other.view_proj, tolerance)
+    }
+}
+
+/// Keeps the shadows of static objects between frames.
+///
+/// See module for documentation.
+pub struct ShadowCacheRoutine {
+    texture: Texture,
+    size: UVec2,
+    maps: Vec<CachedShadowMap>,
+    static_generation: Option<u64>,
+    needs_update: bool,
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl ShadowCacheRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("ShadowCacheRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("shadow cache bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("shadow cache"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/shadow/cache.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("shadow cache"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("shadow cache"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: INTERNAL_SHADOW_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState { module: &module, entry_point: "fs_main", targets: &[] }),
+            multiview: None,
+        });
+
+        let size = UVec2::ONE;
+        Self {
+            texture: create_cache_texture(renderer, size),
+            size,
+            maps: Vec::new(),
+            static_generation: None,
+            needs_update: true,
+            bgl,
+            pipeline,
+        }
+    }
+
+    /// Checks whether the cache is out of date. Must be called once a frame,
+    /// after the instructions are evaluated and before the render graph is
+    /// built.
+    pub fn evaluate(&mut self, renderer: &Renderer, eval_output: &InstructionEvaluationOutput) {
+        profiling::scope!("ShadowCacheRoutine::evaluate");
+
+        let static_generation = renderer.data_core.lock().object_manager.static_generation();
+
+        let resized = self.size != eval_output.shadow_target_size;
+        if resized {
+            self.size = eval_output.shadow_target_size;
+            self.texture = create_cache_texture(renderer, self.size);
+        }
+
+        let maps: Vec<_> = eval_output
+            .shadows
+            .iter()
+            .map(|desc| CachedShadowMap {
+                handle: desc.map.handle,
+                offset: desc.map.offset,
+                size: desc.map.size,
+                view_proj: desc.camera.view_proj(),
+            })
+            .collect();
+        let maps_match = maps.len() == self.maps.len() && maps.iter().zip(&self.maps).all(|(a, b)| a.matches(b));
+
+        self.needs_update = resized || !maps_match || self.static_generation != Some(static_generation);
+        if self.needs_update {
+            self.maps = maps;
+            self.static_generation = Some(static_generation);
+        }
+    }
+
+    /// Whether the static objects have to be rendered into the cache this
+    /// frame.
+    pub fn needs_update(&self) -> bool {
+        self.needs_update
+    }
+
+    /// Adds the cache to the graph. It has the same size and layout as the
+    /// shadow atlas.
+    pub fn add_target_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>) -> RenderTargetHandle {
+        graph.add_imported_render_target(&self.texture, 0..1, 0..1, ViewportRect::from_size(self.size))
+    }
+
+    /// Copies the cache into the shadow atlas, clearing it.
+    pub fn add_copy_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        cache: RenderTargetHandle,
+        shadow: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("Copy Shadow Cache");
+        let cache_handle = builder.add_render_target(cache, NodeResourceUsage::Input);
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(RenderPassDepthTarget {
+                    target: shadow,
+                    depth_clear: Some(0.0),
+                    stencil_clear: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }),
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let cache = ctx.graph_data.get_render_target(cache_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(cache).build(
+                &ctx.renderer.device,
+                Some("shadow cache bg"),
+                &self.bgl,
+            ));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+fn create_cache_texture(renderer: &Renderer, size: UVec2) -> Texture {
+    renderer.device.create_texture(&TextureDescriptor {
+        label: Some("shadow cache"),
+        size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: INTERNAL_SHADOW_DEPTH_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
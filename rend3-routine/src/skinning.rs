@@ -1,9 +1,10 @@
 use std::{borrow::Cow, mem};
 
 use encase::{ShaderSize, ShaderType};
-use glam::Mat4;
+use glam::{Mat3, Mat4, Vec3};
 use rend3::{
     graph::{NodeExecutionContext, RenderGraph},
+    managers::{InternalSkeleton, SkinningSource},
     types::{
         VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL,
         VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
@@ -11,8 +12,9 @@ use rend3::{
     util::{
         bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
         math::div_round_up,
+        upload::UploadChainer,
     },
-    ShaderPreProcessor,
+    PassStats, ShaderPreProcessor,
 };
 use wgpu::{
     BindGroupLayout, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor,
@@ -38,9 +40,14 @@ pub struct GpuSkinningInput {
     updated_normal_offset: u32,
     /// Byte offset into vertex buffer of tangent attribute of skinned mesh.
     updated_tangent_offset: u32,
+    /// Byte offset into vertex buffer of position attribute of skinned mesh
+    /// in the last frame.
+    previous_position_offset: u32,
 
     /// Index into the matrix buffer that joint_indices is relative to.
     joint_matrix_base_offset: u32,
+    /// Index into the matrix buffer of the joint matrices of the last frame.
+    previous_joint_matrix_base_offset: u32,
     /// Count of vertices in this mesh.
     vertex_count: u32,
 }
@@ -65,7 +72,8 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
 
     let joint_matrices = ctx.renderer.device.create_buffer(&BufferDescriptor {
         label: Some("joint matrices"),
-        size: (ctx.data_core.skeleton_manager.global_joint_count() * mem::size_of::<Mat4>()) as u64,
+        // The current matrices of all skeletons, then the previous matrices.
+        size: (ctx.data_core.skeleton_manager.global_joint_count() * 2 * mem::size_of::<Mat4>()) as u64,
         usage: BufferUsages::STORAGE,
         mapped_at_creation: true,
     });
@@ -77,6 +85,7 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
     // Skeletons have a variable number of joints, so we need to keep track of
     // the global index here.
     let mut joint_matrix_idx = 0;
+    let previous_base = ctx.data_core.skeleton_manager.global_joint_count() as u32;
 
     // Iterate over the skeletons, fill the buffers
     for skeleton in ctx.data_core.skeleton_manager.skeletons() {
@@ -92,7 +101,12 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
                 updated_position_offset: u32::MAX,
                 updated_normal_offset: u32::MAX,
                 updated_tangent_offset: u32::MAX,
+                previous_position_offset: skeleton
+                    .previous_position_range
+                    .as_ref()
+                    .map_or(u32::MAX, |r| r.start as u32),
                 joint_matrix_base_offset: joint_matrix_idx,
+                previous_joint_matrix_base_offset: previous_base + joint_matrix_idx,
                 vertex_count: skeleton.vertex_count,
             };
 
@@ -119,12 +133,16 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
             skinning_input_data.write(&input).unwrap();
 
             let joint_matrices_ptr = joint_matrices_data.as_mut_ptr() as *mut [[f32; 4]; 4];
-            for joint_matrix in &skeleton.joint_matrices {
+            for (joint_matrix, previous_matrix) in skeleton.joint_matrices.iter().zip(&skeleton.previous_joint_matrices)
+            {
                 // Here, the access can't be OOB either: The joint_matrix_idx
                 // will get incremented once for every joint matrix, and the
-                // length of the buffer is exactly the sum of all joint matrix
-                // vector lengths.
+                // length of the buffer is exactly twice the sum of all joint
+                // matrix vector lengths.
                 joint_matrices_ptr.add(joint_matrix_idx as usize).write_unaligned(joint_matrix.to_cols_array_2d());
+                joint_matrices_ptr
+                    .add((previous_base + joint_matrix_idx) as usize)
+                    .write_unaligned(previous_matrix.to_cols_array_2d());
                 joint_matrix_idx += 1;
             }
         }
@@ -163,9 +181,11 @@ impl GpuSkinner {
 
         let module = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Gpu skinning compute shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader("rend3-routine/skinning.wgsl", &(), None).unwrap(),
-            )),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/skinning.wgsl",
+                &(),
+                None,
+            ))),
         });
 
         let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
@@ -188,13 +208,21 @@ impl GpuSkinner {
         let mut cpass =
             encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("GPU Skinning"), timestamp_writes: None });
         cpass.set_pipeline(&self.pipeline);
+        let mut compute_dispatches = 0;
         for (i, skel) in ctx.data_core.skeleton_manager.skeletons().enumerate() {
             let offset = (i as u64 * GpuSkinningInput::SHADER_SIZE.get()) as u32;
             cpass.set_bind_group(0, &bg, &[offset]);
 
             let num_workgroups = div_round_up(skel.vertex_count, Self::WORKGROUP_SIZE);
             cpass.dispatch_workgroups(num_workgroups, 1, 1);
+            compute_dispatches += 1;
         }
+
+        ctx.renderer.record_pass_stats(PassStats {
+            label: String::from("GPU Skinning"),
+            compute_dispatches,
+            ..PassStats::default()
+        });
     }
 }
 
@@ -207,10 +235,75 @@ impl GpuSkinner {
 /// render graph (before any culling happens).
 pub struct SkinningOutput;
 
+/// The skinned vertex data of one skeleton, computed on the CPU.
+struct CpuSkinningOutput {
+    positions: Vec<Vec3>,
+    previous_positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    tangents: Vec<Vec3>,
+}
+
+fn skin_on_cpu(skeleton: &InternalSkeleton, source: &SkinningSource) -> CpuSkinningOutput {
+    let vertex_count = source.positions.len();
+    let mut output = CpuSkinningOutput {
+        positions: Vec::with_capacity(vertex_count),
+        previous_positions: Vec::with_capacity(vertex_count),
+        normals: Vec::with_capacity(source.normals.len()),
+        tangents: Vec::with_capacity(source.tangents.len()),
+    };
+
+    for (idx, (&position, (joint_indices, joint_weights))) in
+        source.positions.iter().zip(source.joint_indices.iter().zip(&source.joint_weights)).enumerate()
+    {
+        let normal = source.normals.get(idx).copied().unwrap_or_default();
+        let tangent = source.tangents.get(idx).copied().unwrap_or_default();
+
+        let mut position_acc = Vec3::ZERO;
+        let mut previous_position_acc = Vec3::ZERO;
+        let mut normal_acc = Vec3::ZERO;
+        let mut tangent_acc = Vec3::ZERO;
+        for (&joint_index, weight) in joint_indices.iter().zip(joint_weights.to_array()) {
+            if weight <= 0.0 {
+                continue;
+            }
+            // Joint indices can be updated past the joints of the skeleton after it was created.
+            let joint_index = joint_index as usize;
+            let joint_matrix = skeleton.joint_matrices.get(joint_index).copied().unwrap_or(Mat4::ZERO);
+            let previous_matrix = skeleton.previous_joint_matrices.get(joint_index).copied().unwrap_or(Mat4::ZERO);
+
+            position_acc += joint_matrix.transform_point3(position) * weight;
+            previous_position_acc += previous_matrix.transform_point3(position) * weight;
+
+            let joint_matrix3 = Mat3::from_mat4(joint_matrix);
+            let inv_scale_sq = Vec3::ONE
+                / Vec3::new(
+                    joint_matrix3.x_axis.length_squared(),
+                    joint_matrix3.y_axis.length_squared(),
+                    joint_matrix3.z_axis.length_squared(),
+                );
+            normal_acc += joint_matrix3 * (inv_scale_sq * normal) * weight;
+            tangent_acc += joint_matrix3 * (inv_scale_sq * tangent) * weight;
+        }
+
+        output.positions.push(position_acc);
+        output.previous_positions.push(previous_position_acc);
+        if !source.normals.is_empty() {
+            output.normals.push(normal_acc.normalize_or_zero());
+        }
+        if !source.tangents.is_empty() {
+            output.tangents.push(tangent_acc.normalize_or_zero());
+        }
+    }
+
+    output
+}
+
 /// Performs skinning on the GPU.
 pub fn add_skinning_to_graph<'node>(graph: &mut RenderGraph<'node>, gpu_skinner: &'node GpuSkinner) {
     let mut builder = graph.add_node("skinning");
     builder.add_side_effect();
+    // Everything reading the skinned vertices comes after it.
+    builder.set_prologue();
 
     builder.build(move |mut ctx| {
         let encoder = ctx.encoder_or_pass.take_encoder();
@@ -224,3 +317,111 @@ pub fn add_skinning_to_graph<'node>(graph: &mut RenderGraph<'node>, gpu_skinner:
         }
     });
 }
+
+/// Performs skinning on the CPU, for devices without compute shaders.
+///
+/// Needs the [`SkinningSource`] of the skinned meshes, which the mesh manager
+/// only keeps in the CpuDriven profile. Skeletons of meshes without one, like
+/// meshes created from GPU buffers, are left unskinned.
+pub fn add_cpu_skinning_to_graph(graph: &mut RenderGraph<'_>) {
+    let mut builder = graph.add_node("cpu skinning");
+    builder.add_side_effect();
+
+    builder.build(move |mut ctx| {
+        profiling::scope!("CPU Skinning");
+
+        let encoder = ctx.encoder_or_pass.take_encoder();
+
+        let meshes = ctx.renderer.mesh_manager.lock_internal_data();
+        let outputs: Vec<_> = ctx
+            .data_core
+            .skeleton_manager
+            .skeletons()
+            .filter_map(|skeleton| {
+                let source = meshes[skeleton.mesh_handle.get_raw()].skinning_source.as_ref()?;
+                Some((skeleton, skin_on_cpu(skeleton, source)))
+            })
+            .collect();
+        drop(meshes);
+        if outputs.is_empty() {
+            return;
+        }
+
+        let mut upload = UploadChainer::new();
+        for (skeleton, output) in &outputs {
+            for (attribute, range) in &skeleton.overridden_attribute_ranges {
+                let data = match attribute {
+                    a if *a == *VERTEX_ATTRIBUTE_POSITION => &output.positions,
+                    a if *a == *VERTEX_ATTRIBUTE_NORMAL => &output.normals,
+                    a if *a == *VERTEX_ATTRIBUTE_TANGENT => &output.tangents,
+                    a => unreachable!("Unknown skinning output attribute {a:?}"),
+                };
+                upload.add(range.start, bytemuck::cast_slice(data));
+            }
+            if let Some(range) = &skeleton.previous_position_range {
+                upload.add(range.start, bytemuck::cast_slice(&output.previous_positions));
+            }
+        }
+
+        if let Err(e) = upload.create_staging_buffer(&ctx.renderer.device) {
+            log::error!("Failed to allocate the staging buffer for CPU skinning: {e}");
+            return;
+        }
+        upload.stage();
+        upload.encode_upload(encoder, &ctx.eval_output.mesh_buffer);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Quat, Vec3, Vec4};
+    use rend3::{
+        managers::{InternalSkeleton, SkinningSource},
+        types::MeshHandle,
+    };
+
+    use super::skin_on_cpu;
+
+    fn assert_close(actual: &[Vec3], expected: &[Vec3]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(actual.abs_diff_eq(*expected, 1e-5), "{actual} != {expected}");
+        }
+    }
+
+    #[test]
+    fn two_joints() {
+        let stretch = Mat4::from_translation(Vec3::X) * Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0));
+        let turn = Mat4::from_quat(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        let skeleton = InternalSkeleton {
+            mesh_handle: MeshHandle::new(|_| {}, 0),
+            joint_matrices: vec![stretch, turn],
+            previous_joint_matrices: vec![Mat4::IDENTITY; 2],
+            source_attribute_ranges: Default::default(),
+            overridden_attribute_ranges: Default::default(),
+            previous_position_range: None,
+            vertex_count: 3,
+            source: None,
+        };
+        // Fully on the first joint, fully on the second, and halfway between.
+        let source = SkinningSource {
+            positions: vec![Vec3::X; 3],
+            normals: vec![Vec3::new(1.0, 1.0, 0.0).normalize(), Vec3::X, Vec3::Z],
+            tangents: Vec::new(),
+            joint_indices: vec![[0, 1, 0, 0]; 3],
+            joint_weights: vec![
+                Vec4::new(1.0, 0.0, 0.0, 0.0),
+                Vec4::new(0.0, 1.0, 0.0, 0.0),
+                Vec4::new(0.5, 0.5, 0.0, 0.0),
+            ],
+        };
+
+        let output = skin_on_cpu(&skeleton, &source);
+
+        assert_close(&output.positions, &[Vec3::new(3.0, 0.0, 0.0), Vec3::Y, Vec3::new(1.5, 0.5, 0.0)]);
+        assert_close(&output.previous_positions, &source.positions);
+        // Normals are scaled by the inverse of the joint's scale.
+        assert_close(&output.normals, &[Vec3::new(0.5, 1.0, 0.0).normalize(), Vec3::Y, Vec3::Z]);
+        assert!(output.tangents.is_empty());
+    }
+}
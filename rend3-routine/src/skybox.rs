@@ -1,18 +1,36 @@
 //! Routine that renders a cubemap as a skybox.
+//!
+//! The skybox can also light the scene. Whenever the background texture
+//! changes, it is projected onto spherical harmonics on the GPU, and read back
+//! a couple frames later. Set
+//! [`BaseRenderGraphSettings::skylight_intensity`](crate::base::BaseRenderGraphSettings::skylight_intensity)
+//! to add the resulting diffuse light to the PBR materials.
+//!
+//! The skybox can be rotated, scaled in intensity, and cross-faded into a
+//! second cubemap for time of day or weather transitions. The skylight follows
+//! all of these.
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    sync::{Arc, OnceLock},
+};
 
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat3, Quat, Vec4};
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTargets},
     types::{SampleCount, TextureCubeHandle},
     util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
-    Renderer, ShaderConfig, ShaderPreProcessor,
+    ReadbackFuture, Renderer, ShaderConfig, ShaderPreProcessor,
 };
 use wgpu::{
-    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
-    DepthStencilState, Face, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
-    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
-    ShaderStages, StencilState, TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+    AddressMode, BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompareFunction, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, DepthBiasState, DepthStencilState, Device, Face, FilterMode, FragmentState, FrontFace,
+    MultisampleState, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureFormat, TextureSampleType,
+    TextureViewDimension, VertexState,
 };
 
 use crate::common::WholeFrameInterfaces;
@@ -20,6 +38,161 @@ use crate::common::WholeFrameInterfaces;
 struct StoredSkybox {
     bg: Option<BindGroup>,
     handle: Option<TextureCubeHandle>,
+    blend_handle: Option<TextureCubeHandle>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, ShaderType)]
+struct SkyboxUniforms {
+    inv_rotation: Mat3,
+    intensity: f32,
+    blend: f32,
+}
+
+/// Binds the background texture, the texture blended into, and the uniforms.
+fn sky_bgl_entries(builder: &mut BindGroupLayoutBuilder, stages: ShaderStages) {
+    let cube = BindingType::Texture {
+        sample_type: TextureSampleType::Float { filterable: true },
+        view_dimension: TextureViewDimension::Cube,
+        multisampled: false,
+    };
+    builder.append(stages, cube, None).append(stages, cube, None).append(
+        stages,
+        BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: Some(SkyboxUniforms::SHADER_SIZE),
+        },
+        None,
+    );
+}
+
+/// Size of the spherical harmonics written by the skylight shader.
+const SKYLIGHT_SIZE: u64 = 9 * 16;
+
+/// Projects the skybox onto spherical harmonics.
+struct SkylightProjection {
+    pipeline: ComputePipeline,
+    bgl: BindGroupLayout,
+    sampler: Sampler,
+    buffer: Buffer,
+    dirty: bool,
+    pending: Option<ReadbackFuture>,
+    coefficients: Option<[Vec4; 9]>,
+}
+
+impl SkylightProjection {
+    fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("build skylight pipeline");
+
+        let mut bgl = BindGroupLayoutBuilder::new();
+        bgl.append(ShaderStages::COMPUTE, BindingType::Sampler(SamplerBindingType::Filtering), None);
+        sky_bgl_entries(&mut bgl, ShaderStages::COMPUTE);
+        let bgl = bgl
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(SKYLIGHT_SIZE),
+                },
+                None,
+            )
+            .build(&renderer.device, Some("skylight bgl"));
+
+        let sm = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("skylight"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/skylight.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("skylight"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("skylight"),
+            layout: Some(&pll),
+            module: &sm,
+            entry_point: "cs_main",
+        });
+
+        let sampler = renderer.device.create_sampler(&SamplerDescriptor {
+            label: Some("skylight sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..SamplerDescriptor::default()
+        });
+        let buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("skylight coefficients"),
+            size: SKYLIGHT_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Self { pipeline, bgl, sampler, buffer, dirty: false, pending: None, coefficients: None }
+    }
+
+    /// Starts projecting the sky, as drawn with the given bindings.
+    fn project(
+        &mut self,
+        renderer: &Renderer,
+        view: &wgpu::TextureView,
+        blend_view: &wgpu::TextureView,
+        uniforms: &Buffer,
+    ) {
+        profiling::scope!("Project Skylight");
+
+        let bg = BindGroupBuilder::new()
+            .append_sampler(&self.sampler)
+            .append_texture_view(view)
+            .append_texture_view(blend_view)
+            .append_buffer(uniforms)
+            .append_buffer(&self.buffer)
+            .build(&renderer.device, Some("skylight"), &self.bgl);
+
+        let mut encoder =
+            renderer.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("skylight projection") });
+        {
+            let mut cpass =
+                encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("skylight"), timestamp_writes: None });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups(1, 1, 1);
+        }
+        self.pending = match renderer.readback_buffer(&mut encoder, &self.buffer, 0..SKYLIGHT_SIZE) {
+            Ok(future) => Some(future),
+            Err(e) => {
+                log::error!("Failed to read back skylight: {e}");
+                None
+            }
+        };
+        renderer.queue.submit([encoder.finish()]);
+    }
+
+    /// Stores the coefficients if they have been read back.
+    fn receive(&mut self) {
+        let Some(result) = self.pending.as_ref().and_then(ReadbackFuture::try_take) else {
+            return;
+        };
+        self.pending = None;
+        match result {
+            Ok(data) => {
+                let mut coefficients = [Vec4::ZERO; 9];
+                for (c, bytes) in coefficients.iter_mut().zip(data.chunks_exact(16)) {
+                    *c = Vec4::from_array(bytemuck::pod_read_unaligned(bytes));
+                }
+                self.coefficients = Some(coefficients);
+            }
+            Err(e) => log::error!("Failed to read back skylight: {e}"),
+        }
+    }
 }
 
 /// Skybox rendering routine.
@@ -29,33 +202,102 @@ pub struct SkyboxRoutine {
     pipelines: SkyboxPipelines,
     bgl: BindGroupLayout,
     current_skybox: StoredSkybox,
+    skylight: SkylightProjection,
+    uniforms: SkyboxUniforms,
+    uniform_buffer: Buffer,
+    uniforms_dirty: bool,
 }
 
 impl SkyboxRoutine {
     /// Create the routine.
     pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
-        let bgl = BindGroupLayoutBuilder::new()
-            .append(
-                ShaderStages::FRAGMENT,
-                BindingType::Texture {
-                    sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::Cube,
-                    multisampled: false,
-                },
-                None,
-            )
-            .build(&renderer.device, Some("skybox bgl"));
+        let mut bgl = BindGroupLayoutBuilder::new();
+        sky_bgl_entries(&mut bgl, ShaderStages::FRAGMENT);
+        let bgl = bgl.build(&renderer.device, Some("skybox bgl"));
 
         let pipelines = SkyboxPipelines::new(renderer, spp, interfaces, &bgl);
 
-        Self { current_skybox: StoredSkybox { bg: None, handle: None }, bgl, pipelines }
+        let skylight = SkylightProjection::new(renderer, spp);
+
+        let uniform_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("skybox uniforms"),
+            size: SkyboxUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            current_skybox: StoredSkybox { bg: None, handle: None, blend_handle: None },
+            bgl,
+            pipelines,
+            skylight,
+            uniforms: SkyboxUniforms { inv_rotation: Mat3::IDENTITY, intensity: 1.0, blend: 0.0 },
+            uniform_buffer,
+            uniforms_dirty: true,
+        }
     }
 
     /// Set the current background texture. Bad things will happen if this isn't
     /// a cube texture.
     pub fn set_background_texture(&mut self, texture: Option<TextureCubeHandle>) {
+        if texture.is_none() {
+            self.skylight.coefficients = None;
+            self.skylight.pending = None;
+        }
         self.current_skybox.handle = texture;
         self.current_skybox.bg = None;
+        self.skylight.dirty = true;
+    }
+
+    /// Set the texture the background cross-fades into, by the factor given to
+    /// [`Self::set_blend_factor`]. Once the fade is done, make it the
+    /// background texture and remove it here.
+    pub fn set_blend_texture(&mut self, texture: Option<TextureCubeHandle>) {
+        self.current_skybox.blend_handle = texture;
+        self.current_skybox.bg = None;
+        self.skylight.dirty = true;
+    }
+
+    /// Set how far the background is cross-faded into the blend texture, from
+    /// 0 (only the background) to 1 (only the blend texture).
+    pub fn set_blend_factor(&mut self, factor: f32) {
+        self.set_uniforms(SkyboxUniforms { blend: factor.clamp(0.0, 1.0), ..self.uniforms });
+    }
+
+    /// Set the rotation of the sky in the world, to line up the sun of an
+    /// HDRI with a directional light.
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        self.set_uniforms(SkyboxUniforms { inv_rotation: Mat3::from_quat(rotation.inverse()), ..self.uniforms });
+    }
+
+    /// Set the multiplier of the sky's brightness. Defaults to 1.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.set_uniforms(SkyboxUniforms { intensity, ..self.uniforms });
+    }
+
+    fn set_uniforms(&mut self, uniforms: SkyboxUniforms) {
+        if uniforms != self.uniforms {
+            self.uniforms = uniforms;
+            self.uniforms_dirty = true;
+            self.skylight.dirty = true;
+        }
+    }
+
+    /// Projects the current background texture onto spherical harmonics again
+    /// during the next [`Self::evaluate`]. Only needed if the texture's
+    /// contents were changed on the GPU.
+    pub fn update_skylight(&mut self) {
+        self.skylight.dirty = true;
+    }
+
+    /// Diffuse light from the current background texture, as the coefficients
+    /// of the first three bands of real spherical harmonics in RGB, convolved
+    /// with a cosine lobe and divided by pi.
+    ///
+    /// Lags the background texture by a couple frames. `None` until the first
+    /// projection has been read back.
+    pub fn skylight(&self) -> Option<[Vec4; 9]> {
+        self.skylight.coefficients
     }
 
     /// Evaluate any changes that have happened to the skybox routine.
@@ -65,14 +307,40 @@ impl SkyboxRoutine {
 
         profiling::scope!("Update Skybox");
 
+        if self.uniforms_dirty {
+            let mut data = UniformBuffer::new(Vec::with_capacity(SkyboxUniforms::SHADER_SIZE.get() as usize));
+            data.write(&self.uniforms).unwrap();
+            renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+            self.uniforms_dirty = false;
+        }
+
+        // Receive first, so a projection started while another was pending runs once the old one lands.
+        self.skylight.receive();
+
         if let Some(ref handle) = self.current_skybox.handle {
+            let view = d2c_texture_manager.get_view(handle.get_raw());
+            let blend_view = match self.current_skybox.blend_handle {
+                Some(ref blend_handle) => d2c_texture_manager.get_view(blend_handle.get_raw()),
+                None => view,
+            };
+
             if self.current_skybox.bg.is_none() {
                 let bg = BindGroupBuilder::new()
-                    .append_texture_view(d2c_texture_manager.get_view(handle.get_raw()))
+                    .append_texture_view(view)
+                    .append_texture_view(blend_view)
+                    .append_buffer(&self.uniform_buffer)
                     .build(&renderer.device, Some("skybox"), &self.bgl);
 
                 self.current_skybox.bg = Some(bg)
             }
+
+            // Changing the sky every frame, like during a fade, would otherwise restart the readback before it lands.
+            if self.skylight.dirty && self.skylight.pending.is_none() {
+                self.skylight.project(renderer, view, blend_view, &self.uniform_buffer);
+                self.skylight.dirty = false;
+            }
+        } else {
+            self.skylight.dirty = false;
         }
     }
 
@@ -84,6 +352,8 @@ impl SkyboxRoutine {
         forward_uniform_bg: DataHandle<BindGroup>,
         samples: SampleCount,
     ) {
+        let pipeline = self.pipelines.get(samples);
+
         let mut builder = graph.add_node("Skybox");
 
         let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
@@ -96,11 +366,6 @@ impl SkyboxRoutine {
             let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
 
             if let Some(ref bg) = self.current_skybox.bg {
-                let pipeline = match samples {
-                    SampleCount::One => &self.pipelines.pipeline_s1,
-                    SampleCount::Four => &self.pipelines.pipeline_s4,
-                };
-
                 rpass.set_pipeline(pipeline);
                 rpass.set_bind_group(0, forward_uniform_bg, &[]);
                 rpass.set_bind_group(1, bg, &[]);
@@ -110,10 +375,14 @@ impl SkyboxRoutine {
     }
 }
 
-/// Container for all needed skybox pipelines
+/// Container for all needed skybox pipelines.
+///
+/// The pipeline of each sample count is created the first time it is used.
 pub struct SkyboxPipelines {
-    pub pipeline_s1: RenderPipeline,
-    pub pipeline_s4: RenderPipeline,
+    device: Arc<Device>,
+    module: ShaderModule,
+    layout: PipelineLayout,
+    pipelines: [OnceLock<RenderPipeline>; SampleCount::ARRAY.len()],
 }
 impl SkyboxPipelines {
     pub fn new(
@@ -123,24 +392,33 @@ impl SkyboxPipelines {
         bgl: &BindGroupLayout,
     ) -> Self {
         profiling::scope!("build skybox pipeline");
-        let skybox_sm = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("skybox vert"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader("rend3-routine/skybox.wgsl", &ShaderConfig::default(), None).unwrap(),
-            )),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/skybox.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
         });
 
-        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        let layout = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("skybox pass"),
             bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
             push_constant_ranges: &[],
         });
 
-        let inner = |samples| {
-            renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        Self { device: Arc::clone(&renderer.device), module, layout, pipelines: Default::default() }
+    }
+
+    /// The pipeline of the given sample count, created if it is the first
+    /// time it is used.
+    pub fn get(&self, samples: SampleCount) -> &RenderPipeline {
+        let idx = SampleCount::ARRAY.iter().position(|&s| s == samples).unwrap();
+        self.pipelines[idx].get_or_init(|| {
+            self.device.create_render_pipeline(&RenderPipelineDescriptor {
                 label: Some("skybox pass"),
-                layout: Some(&pll),
-                vertex: VertexState { module: &skybox_sm, entry_point: "vs_main", buffers: &[] },
+                layout: Some(&self.layout),
+                vertex: VertexState { module: &self.module, entry_point: "vs_main", buffers: &[] },
                 primitive: PrimitiveState {
                     topology: PrimitiveTopology::TriangleList,
                     strip_index_format: None,
@@ -159,7 +437,7 @@ impl SkyboxPipelines {
                 }),
                 multisample: MultisampleState { count: samples as u32, ..Default::default() },
                 fragment: Some(FragmentState {
-                    module: &skybox_sm,
+                    module: &self.module,
                     entry_point: "fs_main",
                     targets: &[Some(ColorTargetState {
                         format: TextureFormat::Rgba16Float,
@@ -169,8 +447,6 @@ impl SkyboxPipelines {
                 }),
                 multiview: None,
             })
-        };
-
-        Self { pipeline_s1: inner(SampleCount::One), pipeline_s4: inner(SampleCount::Four) }
+        })
     }
 }
@@ -0,0 +1,419 @@
+//! Temporal upscaling.
+//!
+//! The camera is jittered by a different sub-pixel offset every frame, and
+//! every frame is accumulated into a history at the output resolution, so a
+//! frame rendered at a low resolution is reconstructed into a sharper, high
+//! resolution image. This also antialiases the image, in place of MSAA.
+//!
+//! The history is reprojected with the [`velocity`](crate::velocity) of the
+//! opaque objects, which follows the motion of the camera, objects and
+//! skinned meshes. Where no object was drawn, it is reprojected with the
+//! motion of the camera alone, using the depth. Colors that stray too far
+//! from the current frame are clamped away, which limits ghosting on what the
+//! velocity misses.
+//!
+//! Transparent objects and particles don't write depth, so they smear when the
+//! camera moves. Routines drawing them can write how much the history should
+//! be ignored, from 0 to 1, into a reactive mask created with
+//! [`TemporalUpscaleRoutine::add_reactive_mask_to_graph`].
+//!
+//! In checkerboard mode, enabled with
+//! [`TemporalUpscaleRoutine::set_checkerboard`], the scene is rendered at half
+//! the output width, see [`TemporalUpscaleRoutine::checkerboard_resolution`].
+//! Each frame renders every other column of the output, alternating between
+//! frames. The output pixels taken from the current frame form a
+//! checkerboard, flipping every frame, and the rest are taken from the
+//! history. This halves the shading cost on slow GPUs, at the price of some
+//! blur in fast motion, without the combing of replacing whole columns.
+//!
+//! Call [`TemporalUpscaleRoutine::prepare`] every frame before evaluating
+//! instructions, as it sets the camera jitter, then
+//! [`BaseRenderGraphIntermediateState::temporal_upscale`](crate::base::BaseRenderGraphIntermediateState::temporal_upscale)
+//! before tonemapping.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec2, Vec4};
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderTargetDescriptor, RenderTargetHandle},
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites,
+    Extent3d, FilterMode, FragmentState, FrontFace, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, StoreOp, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::{clear, velocity::VELOCITY_FORMAT};
+
+/// Format of the upscaled image and the history.
+pub const TEMPORAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Format of the reactive mask.
+pub const REACTIVE_MASK_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+/// Weight of the current frame when the history is valid.
+const CURRENT_WEIGHT: f32 = 0.1;
+
+#[derive(ShaderType)]
+struct TemporalUniforms {
+    inv_view_proj: Mat4,
+    prev_view_proj: Mat4,
+    jitter: Vec2,
+    render_resolution: Vec2,
+    output_resolution: Vec2,
+    current_weight: f32,
+    /// 1 if there is a velocity target.
+    has_velocity: u32,
+    /// Zero outside of checkerboard mode, otherwise one plus the parity of the
+    /// frame, which picks the output columns rendered and the half of the
+    /// checkerboard taken from this frame.
+    checkerboard: u32,
+}
+
+/// Targets read and written by the temporal upscale.
+pub struct TemporalUpscaleTargets {
+    /// Single sampled HDR color, at the render resolution.
+    pub color: RenderTargetHandle,
+    /// Depth matching `color`.
+    pub depth: RenderTargetHandle,
+    /// Velocity matching `color`, see [`velocity`](crate::velocity). Without
+    /// it, only the motion of the camera is followed.
+    pub velocity: Option<RenderTargetHandle>,
+    /// Reactive mask matching `color`, see module for documentation.
+    pub reactive_mask: Option<RenderTargetHandle>,
+    /// Upscaled output, in [`TEMPORAL_FORMAT`] at the output resolution.
+    pub output: RenderTargetHandle,
+}
+
+/// Temporal upscaling routine.
+///
+/// See module for documentation.
+pub struct TemporalUpscaleRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    empty_reactive_mask: TextureView,
+    empty_velocity: TextureView,
+    history: [TextureView; 2],
+
+    render_resolution: UVec2,
+    output_resolution: UVec2,
+    frame: u64,
+    jitter: Vec2,
+    prev_view_proj: Mat4,
+    history_valid: bool,
+    reset_requested: bool,
+    checkerboard: bool,
+}
+
+impl TemporalUpscaleRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, output_resolution: UVec2) -> Self {
+        profiling::scope!("TemporalUpscaleRoutine::new");
+
+        let float_texture = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(TemporalUniforms::SHADER_SIZE),
+                },
+                None,
+            )
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(ShaderStages::FRAGMENT, float_texture, None)
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(ShaderStages::FRAGMENT, float_texture, None)
+            .append(ShaderStages::FRAGMENT, float_texture, None)
+            .append(ShaderStages::FRAGMENT, float_texture, None)
+            .build(&renderer.device, Some("temporal upscale bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("temporal upscale"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/temporal.wgsl",
+                &ShaderConfig::default(),
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("temporal upscale"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let target = Some(ColorTargetState { format: TEMPORAL_FORMAT, blend: None, write_mask: ColorWrites::all() });
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("temporal upscale"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[target.clone(), target],
+            }),
+            multiview: None,
+        });
+
+        let sampler = renderer.device.create_sampler(&SamplerDescriptor {
+            label: Some("temporal upscale sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..SamplerDescriptor::default()
+        });
+
+        Self {
+            bgl,
+            pipeline,
+            sampler,
+            empty_reactive_mask: create_texture(renderer, "empty reactive mask", UVec2::ONE, REACTIVE_MASK_FORMAT),
+            empty_velocity: create_texture(renderer, "empty velocity", UVec2::ONE, VELOCITY_FORMAT),
+            history: create_history(renderer, output_resolution),
+            render_resolution: UVec2::ZERO,
+            output_resolution,
+            frame: 0,
+            jitter: Vec2::ZERO,
+            prev_view_proj: Mat4::IDENTITY,
+            history_valid: false,
+            reset_requested: true,
+            checkerboard: false,
+        }
+    }
+
+    /// Advances to the next frame, and jitters the camera. Must be called
+    /// every frame before the instructions are evaluated.
+    pub fn prepare(&mut self, renderer: &Renderer, render_resolution: UVec2, output_resolution: UVec2) {
+        profiling::scope!("TemporalUpscaleRoutine::prepare");
+
+        if output_resolution != self.output_resolution {
+            self.output_resolution = output_resolution;
+            self.history = create_history(renderer, output_resolution);
+            self.reset_requested = true;
+        }
+        if render_resolution != self.render_resolution {
+            self.render_resolution = render_resolution;
+            self.reset_requested = true;
+        }
+
+        // The camera of the last frame, as this frame's instructions haven't
+        // been evaluated yet.
+        self.prev_view_proj = renderer.data_core.lock().viewport_camera_state.unjittered_view_proj();
+        self.history_valid = !self.reset_requested;
+        self.reset_requested = false;
+        self.frame += 1;
+
+        // More output pixels per rendered pixel need more frames to cover.
+        let scale = output_resolution.as_vec2() / render_resolution.max(UVec2::ONE).as_vec2();
+        let phases = (8.0 * scale.x * scale.y).ceil().max(1.0) as u64;
+        let index = self.frame % phases + 1;
+        let offset = Vec2::new(halton(index, 2), halton(index, 3)) - 0.5;
+        self.jitter = match (self.checkerboard, renderer.determinism_enabled()) {
+            // Centers the rendered pixels on the left or right column they
+            // cover. Needed even when deterministic, or half of the columns
+            // would never be rendered.
+            (true, _) => Vec2::new(0.5 - self.checkerboard_parity() as f32, 0.0) / render_resolution.x.max(1) as f32,
+            (false, true) => Vec2::ZERO,
+            (false, false) => offset * Vec2::new(2.0, -2.0) / render_resolution.max(UVec2::ONE).as_vec2(),
+        };
+
+        renderer.set_camera_jitter(self.jitter);
+    }
+
+    /// Enables or disables checkerboard mode, see module for documentation.
+    /// Takes effect with the next [`Self::prepare`], which must be given
+    /// [`Self::checkerboard_resolution`] as the render resolution.
+    pub fn set_checkerboard(&mut self, enabled: bool) {
+        if self.checkerboard != enabled {
+            self.checkerboard = enabled;
+            self.reset_requested = true;
+        }
+    }
+
+    pub fn checkerboard(&self) -> bool {
+        self.checkerboard
+    }
+
+    /// Render resolution of checkerboard mode for the given output
+    /// resolution: half the width, rounded up, at the full height.
+    pub fn checkerboard_resolution(output_resolution: UVec2) -> UVec2 {
+        UVec2::new((output_resolution.x + 1) / 2, output_resolution.y)
+    }
+
+    /// Parity of the output columns rendered in the current frame.
+    fn checkerboard_parity(&self) -> u32 {
+        (self.frame % 2) as u32
+    }
+
+    /// Discards the history, for camera cuts.
+    pub fn reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    /// Jitter of the current frame, in normalized device coordinates.
+    pub fn jitter(&self) -> Vec2 {
+        self.jitter
+    }
+
+    pub fn output_resolution(&self) -> UVec2 {
+        self.output_resolution
+    }
+
+    /// Creates a cleared reactive mask at the render resolution, for routines
+    /// to write into.
+    pub fn add_reactive_mask_to_graph(&self, graph: &mut RenderGraph<'_>) -> RenderTargetHandle {
+        let mask = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("reactive mask".into()),
+            resolution: self.render_resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: REACTIVE_MASK_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        clear::add_clear_to_graph(graph, mask, Vec4::ZERO);
+        mask
+    }
+
+    /// Adds the upscale of `targets.color` into `targets.output`.
+    pub fn add_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>, targets: TemporalUpscaleTargets) {
+        let mut builder = graph.add_node("Temporal Upscale");
+        let color_handle = builder.add_render_target(targets.color, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(targets.depth.set_mips(0..1), NodeResourceUsage::Input);
+        let velocity_handle = builder.add_optional_render_target(targets.velocity, NodeResourceUsage::Input);
+        let reactive_handle = builder.add_optional_render_target(targets.reactive_mask, NodeResourceUsage::Input);
+        let output_handle = builder.add_render_target(targets.output, NodeResourceUsage::Output);
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let uniforms = TemporalUniforms {
+                inv_view_proj: camera.view_proj().inverse(),
+                prev_view_proj: self.prev_view_proj,
+                jitter: self.jitter,
+                render_resolution: self.render_resolution.as_vec2(),
+                output_resolution: self.output_resolution.as_vec2(),
+                current_weight: if self.history_valid { CURRENT_WEIGHT } else { 1.0 },
+                has_velocity: velocity_handle.is_some() as u32,
+                checkerboard: match self.checkerboard {
+                    true => 1 + self.checkerboard_parity(),
+                    false => 0,
+                },
+            };
+            let uniform_buffer = ctx.temps.add(ctx.renderer.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("temporal upscale uniforms"),
+                size: TemporalUniforms::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            }));
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniforms).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+
+            let (read, write) = match self.frame % 2 {
+                0 => (&self.history[0], &self.history[1]),
+                _ => (&self.history[1], &self.history[0]),
+            };
+            let velocity = match velocity_handle {
+                Some(handle) => ctx.graph_data.get_render_target(handle),
+                None => &self.empty_velocity,
+            };
+            let reactive_mask = match reactive_handle {
+                Some(handle) => ctx.graph_data.get_render_target(handle),
+                None => &self.empty_reactive_mask,
+            };
+
+            let bg = BindGroupBuilder::new()
+                .append_buffer(uniform_buffer)
+                .append_sampler(&self.sampler)
+                .append_texture_view(ctx.graph_data.get_render_target(color_handle))
+                .append_texture_view(ctx.graph_data.get_render_target(depth_handle))
+                .append_texture_view(read)
+                .append_texture_view(reactive_mask)
+                .append_texture_view(velocity)
+                .build(&ctx.renderer.device, Some("temporal upscale bg"), &self.bgl);
+
+            let output = ctx.graph_data.get_render_target(output_handle);
+            let ops = Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store };
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("temporal upscale"),
+                color_attachments: &[
+                    Some(RenderPassColorAttachment { view: output, resolve_target: None, ops }),
+                    Some(RenderPassColorAttachment { view: write, resolve_target: None, ops }),
+                ],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+/// Element `index` of the Halton sequence with the given base, in [0, 1).
+fn halton(mut index: u64, base: u64) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_history(renderer: &Renderer, resolution: UVec2) -> [TextureView; 2] {
+    [
+        create_texture(renderer, "temporal history 0", resolution, TEMPORAL_FORMAT),
+        create_texture(renderer, "temporal history 1", resolution, TEMPORAL_FORMAT),
+    ]
+}
+
+fn create_texture(renderer: &Renderer, label: &str, resolution: UVec2, format: TextureFormat) -> TextureView {
+    let texture = renderer.device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d { width: resolution.x.max(1), height: resolution.y.max(1), depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,267 @@
+//! Tiled culling of point lights (Forward+).
+//!
+//! The screen is split into square tiles, and every point light is tested
+//! against the part of the view frustum covered by each tile, from the near
+//! plane to the farthest opaque surface in the tile. The opaque, transparent
+//! and deferred shaders then only loop over the lights of the tile their pixel
+//! is in, instead of every light in the scene.
+//!
+//! This needs the depth of the opaque objects before they are shaded, so in
+//! forward mode the base render graph renders a depth prepass when tiled
+//! lighting is used.
+//!
+//! A tile keeps at most [`TiledLightingSettings::max_lights_per_tile`]
+//! lights; the rest are dropped, which shows up as tiles of missing light.
+//!
+//! Pass the routine to the base render graph through
+//! [`BaseRenderGraphRoutines::tiled_lighting`](crate::base::BaseRenderGraphRoutines::tiled_lighting).
+
+use std::{borrow::Cow, mem};
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2};
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType, TextureViewDimension,
+};
+
+/// Tile layout used by [`TiledLightingRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TiledLightingSettings {
+    /// Width and height of a tile in pixels.
+    pub tile_size: u32,
+    /// Maximum number of point lights affecting a single tile.
+    pub max_lights_per_tile: u32,
+}
+
+impl Default for TiledLightingSettings {
+    fn default() -> Self {
+        Self { tile_size: 16, max_lights_per_tile: 64 }
+    }
+}
+
+/// Size in bytes of the header of the light tile buffer, before the lists of
+/// the tiles.
+const LIGHT_TILES_HEADER_SIZE: u64 = 3 * mem::size_of::<u32>() as u64;
+
+#[derive(ShaderType)]
+struct TileCullingUniforms {
+    view: Mat4,
+    inv_proj: Mat4,
+    resolution: UVec2,
+    /// Non-zero to add lights to the tiles in order, on one thread.
+    deterministic: u32,
+}
+
+#[derive(Serialize)]
+struct TiledLightingShaderConfig {
+    #[serde(rename = "SAMPLES")]
+    samples: u32,
+}
+
+struct TileCullingPipeline {
+    bgl: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl TileCullingPipeline {
+    fn new(renderer: &Renderer, spp: &ShaderPreProcessor, samples: SampleCount) -> Self {
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(TileCullingUniforms::SHADER_SIZE),
+                },
+                None,
+            )
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                None,
+            )
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: samples.needs_resolve(),
+                },
+                None,
+            )
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("tile light culling bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("tile light culling"),
+            source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+                "rend3-routine/tiled_lighting.wgsl",
+                &TiledLightingShaderConfig { samples: samples as u32 },
+                None,
+            ))),
+        });
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("tile light culling"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = renderer.device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("tile light culling"),
+            layout: Some(&pll),
+            module: &module,
+            entry_point: "cs_main",
+        });
+
+        Self { bgl, pipeline }
+    }
+}
+
+/// Builds the per-tile point light lists read by the forward and deferred
+/// shaders.
+///
+/// See module for documentation.
+pub struct TiledLightingRoutine {
+    settings: TiledLightingSettings,
+    culling_s1: TileCullingPipeline,
+    culling_s4: TileCullingPipeline,
+}
+
+impl TiledLightingRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, settings: TiledLightingSettings) -> Self {
+        profiling::scope!("TiledLightingRoutine::new");
+
+        assert!(settings.tile_size > 0, "tile size must be at least one pixel");
+
+        Self {
+            settings,
+            culling_s1: TileCullingPipeline::new(renderer, spp, SampleCount::One),
+            culling_s4: TileCullingPipeline::new(renderer, spp, SampleCount::Four),
+        }
+    }
+
+    pub fn settings(&self) -> TiledLightingSettings {
+        self.settings
+    }
+
+    /// Number of tiles covering the given resolution.
+    pub fn tile_count(&self, resolution: UVec2) -> UVec2 {
+        (resolution + self.settings.tile_size - 1) / self.settings.tile_size
+    }
+
+    /// Allocates the light tiles for the given resolution. The handle is given
+    /// to the frame uniforms to bind it, then filled by
+    /// [`Self::add_culling_to_graph`].
+    pub fn add_tiles_to_graph(&self, graph: &mut RenderGraph<'_>, resolution: UVec2) -> DataHandle<Buffer> {
+        let tiles = graph.add_data::<Buffer>();
+
+        let mut builder = graph.add_node("Allocate Light Tiles");
+        let tiles_handle = builder.add_data(tiles, NodeResourceUsage::Output);
+
+        let settings = self.settings;
+        // Keep the buffer big enough to bind when there are no tiles.
+        let tile_count = self.tile_count(resolution).max(UVec2::ONE);
+        builder.build(move |ctx| {
+            let list_size = (settings.max_lights_per_tile as u64 + 1) * mem::size_of::<u32>() as u64;
+            let buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("light tiles"),
+                size: LIGHT_TILES_HEADER_SIZE + tile_count.x as u64 * tile_count.y as u64 * list_size,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: true,
+            });
+            let header = [settings.tile_size, tile_count.x, settings.max_lights_per_tile];
+            buffer.slice(..LIGHT_TILES_HEADER_SIZE).get_mapped_range_mut().copy_from_slice(bytemuck::bytes_of(&header));
+            buffer.unmap();
+
+            ctx.graph_data.set_data(tiles_handle, Some(buffer));
+        });
+
+        tiles
+    }
+
+    /// Culls the point lights against the tiles, bounded by `depth`, which
+    /// must hold the depth of the opaque objects.
+    ///
+    /// Shaders reading the tiles through `forward_uniform_bg` run after this.
+    pub fn add_culling_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        tiles: DataHandle<Buffer>,
+        depth: RenderTargetHandle,
+        samples: SampleCount,
+        resolution: UVec2,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Tile Light Culling");
+        let tiles_handle = builder.add_data(tiles, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        // The forward uniforms bind the tiles, so everything reading them must
+        // come after the culling.
+        builder.add_data(forward_uniform_bg, NodeResourceUsage::InputOutput);
+
+        let tile_count = self.tile_count(resolution);
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let tiles = ctx.graph_data.get_data(ctx.temps, tiles_handle).unwrap();
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let uniforms = TileCullingUniforms {
+                view: camera.view(),
+                inv_proj: camera.proj().inverse(),
+                resolution,
+                deterministic: ctx.renderer.determinism_enabled() as u32,
+            };
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("tile light culling uniforms"),
+                size: TileCullingUniforms::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniforms).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+
+            let culling = match samples {
+                SampleCount::One => &self.culling_s1,
+                SampleCount::Four => &self.culling_s4,
+            };
+
+            let mut bgb = BindGroupBuilder::new();
+            bgb.append_buffer(&uniform_buffer);
+            ctx.data_core.point_light_manager.add_to_bg(&mut bgb);
+            bgb.append_texture_view(depth);
+            bgb.append_buffer(tiles);
+            let bg = bgb.build(&ctx.renderer.device, Some("tile light culling bg"), &culling.bgl);
+
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("tile light culling"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&culling.pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups(tile_count.x, tile_count.y, 1);
+        });
+    }
+}
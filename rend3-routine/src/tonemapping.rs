@@ -8,20 +8,26 @@
 //! output. Each TonemappingRoutine instance only has a single pipeline, so if
 //! you need to render to two different formats potentially, use two different
 //! routines.
+//!
+//! [`TonemappingRoutine::add_composite_to_graph`] blends the image over what
+//! the output already holds, for views drawn on top of other renders.
 
 use std::borrow::Cow;
 
 use glam::Vec4;
 use rend3::{
-    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
     util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
     Renderer, ShaderConfig, ShaderPreProcessor,
 };
 use wgpu::{
-    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, Device, FragmentState, FrontFace,
-    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
-    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType,
-    TextureViewDimension, VertexState,
+    BindGroup, BindGroupLayout, BindingType, BlendState, ColorTargetState, ColorWrites, Device, FragmentState,
+    FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat,
+    TextureSampleType, TextureViewDimension, VertexState,
 };
 
 use crate::common::WholeFrameInterfaces;
@@ -32,13 +38,16 @@ fn create_pipeline(
     interfaces: &WholeFrameInterfaces,
     bgl: &BindGroupLayout,
     output_format: TextureFormat,
+    blend: Option<BlendState>,
 ) -> RenderPipeline {
     profiling::scope!("TonemappingPass::new");
     let module = device.create_shader_module(ShaderModuleDescriptor {
         label: Some("tonemapping"),
-        source: ShaderSource::Wgsl(Cow::Owned(
-            spp.render_shader("rend3-routine/blit.wgsl", &ShaderConfig::default(), None).unwrap(),
-        )),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_valid_shader(
+            "rend3-routine/blit.wgsl",
+            &ShaderConfig::default(),
+            None,
+        ))),
     });
 
     let fs_entry_point = if output_format.is_srgb() { "fs_main_scene" } else { "fs_main_monitor" };
@@ -67,7 +76,7 @@ fn create_pipeline(
         fragment: Some(FragmentState {
             module: &module,
             entry_point: fs_entry_point,
-            targets: &[Some(ColorTargetState { format: output_format, blend: None, write_mask: ColorWrites::all() })],
+            targets: &[Some(ColorTargetState { format: output_format, blend, write_mask: ColorWrites::all() })],
         }),
         multiview: None,
     })
@@ -79,6 +88,7 @@ fn create_pipeline(
 pub struct TonemappingRoutine {
     bgl: BindGroupLayout,
     pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
 }
 
 impl TonemappingRoutine {
@@ -100,9 +110,17 @@ impl TonemappingRoutine {
             )
             .build(&renderer.device, Some("bind bgl"));
 
-        let pipeline = create_pipeline(&renderer.device, spp, interfaces, &bgl, output_format);
+        let pipeline = create_pipeline(&renderer.device, spp, interfaces, &bgl, output_format, None);
+        let composite_pipeline = create_pipeline(
+            &renderer.device,
+            spp,
+            interfaces,
+            &bgl,
+            output_format,
+            Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        );
 
-        Self { bgl, pipeline }
+        Self { bgl, pipeline, composite_pipeline }
     }
 
     pub fn add_to_graph<'node>(
@@ -111,6 +129,32 @@ impl TonemappingRoutine {
         src: RenderTargetHandle,
         dst: RenderTargetHandle,
         forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_to_graph_inner(graph, src, dst, forward_uniform_bg, &self.pipeline)
+    }
+
+    /// Tonemap `src` over the contents of `dst`, blending by the alpha of
+    /// `src`, which must be premultiplied.
+    ///
+    /// `dst` is only kept if something rendered to it earlier in the graph,
+    /// otherwise it's cleared to transparent black.
+    pub fn add_composite_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_to_graph_inner(graph, src, dst, forward_uniform_bg, &self.composite_pipeline)
+    }
+
+    fn add_to_graph_inner<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        pipeline: &'node RenderPipeline,
     ) {
         let mut builder = graph.add_node("Tonemapping");
 
@@ -118,7 +162,12 @@ impl TonemappingRoutine {
 
         let rpass_handle = builder.add_renderpass(
             RenderPassTargets {
-                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ZERO, resolve: None }],
+                targets: vec![RenderPassTarget {
+                    color: dst,
+                    clear: Vec4::ZERO,
+                    resolve: None,
+                    load: RenderPassLoadOp::ClearOnFirstUse,
+                }],
                 depth_stencil: None,
             },
             NodeResourceUsage::InputOutput,
@@ -139,7 +188,7 @@ impl TonemappingRoutine {
                 &self.bgl,
             ));
 
-            rpass.set_pipeline(&self.pipeline);
+            rpass.set_pipeline(pipeline);
             rpass.set_bind_group(0, forward_uniform_bg, &[]);
             rpass.set_bind_group(1, blit_src_bg, &[]);
             rpass.draw(0..3, 0..1);
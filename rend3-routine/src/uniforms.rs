@@ -5,12 +5,12 @@ use encase::{ShaderSize, ShaderType, UniformBuffer};
 use glam::{Mat4, UVec2, Vec4};
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
-    managers::CameraState,
+    managers::{CameraState, FrameClock},
     util::{bind_merge::BindGroupBuilder, frustum::Frustum},
 };
-use wgpu::{BindGroup, BufferUsages};
+use wgpu::{BindGroup, Buffer, BufferUsages};
 
-use crate::common::{Samplers, WholeFrameInterfaces};
+use crate::common::{BrdfLut, NoiseTextures, Samplers, WholeFrameInterfaces};
 
 #[derive(ShaderType)]
 pub struct PerCameraUniform {
@@ -22,6 +22,41 @@ pub struct PerCameraUniform {
     pub object_count: u32,
 }
 
+/// Material channel to visualize instead of the shaded output.
+///
+/// Used to debug materials. Anything other than [`DebugView::None`] replaces
+/// the output of the PBR forward shaders.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum DebugView {
+    /// Normal shaded output.
+    #[default]
+    None = 0,
+    /// Albedo after texture, vertex color, and material factors.
+    Albedo = 1,
+    /// World space shading normals, remapped to [0, 1].
+    Normals = 2,
+    /// Perceptual roughness.
+    Roughness = 3,
+    /// Metallic factor.
+    Metallic = 4,
+    /// Ambient occlusion.
+    AmbientOcclusion = 5,
+    /// Emissive color.
+    Emissive = 6,
+    /// The first set of texture coordinates, wrapped to [0, 1].
+    TextureCoordinates = 7,
+    /// Mip level the albedo texture is sampled at. Blue is mip 0, through
+    /// green and yellow, to red at mip 4 and above. Gray without a texture.
+    MipLevel = 8,
+    /// Heatmap of how many times each pixel is shaded.
+    Overdraw = 9,
+    /// Which directional light shadow map covers each pixel, tinted per
+    /// light, with a checkerboard of shadow map texels to show resolution.
+    /// Shadowed areas are darker. Red-gray when outside every shadow map.
+    ShadowMaps = 10,
+}
+
 /// Set of uniforms that are useful for the whole frame.
 #[derive(Debug, Copy, Clone, ShaderType)]
 pub struct FrameUniforms {
@@ -31,13 +66,26 @@ pub struct FrameUniforms {
     pub inv_view: Mat4,
     pub inv_view_proj: Mat4,
     pub inv_origin_view_proj: Mat4,
+    pub inv_proj: Mat4,
     pub frustum: Frustum,
     pub ambient: Vec4,
     pub resolution: UVec2,
+    pub debug_view: u32,
+    pub skylight: [Vec4; 9],
+    /// Seconds the [`FrameClock`] advanced since the renderer was created.
+    /// Loses precision after a few hours, so effects should wrap it.
+    pub time: f32,
+    /// Seconds the [`FrameClock`] advanced this frame.
+    pub delta_time: f32,
+    pub frame_index: u32,
+    /// `view_proj` without the camera jitter.
+    pub unjittered_view_proj: Mat4,
+    /// `unjittered_view_proj` of the last frame, for motion vectors.
+    pub previous_view_proj: Mat4,
 }
 impl FrameUniforms {
-    /// Use the given camera to generate these uniforms.
-    pub fn new(camera: &CameraState, info: &UniformInformation<'_>) -> Self {
+    /// Use the given camera and clock to generate these uniforms.
+    pub fn new(camera: &CameraState, clock: &FrameClock, info: &UniformInformation<'_>) -> Self {
         profiling::scope!("create uniforms");
 
         let view = camera.view();
@@ -51,9 +99,17 @@ impl FrameUniforms {
             inv_view: view.inverse(),
             inv_view_proj: view_proj.inverse(),
             inv_origin_view_proj: origin_view_proj.inverse(),
+            inv_proj: camera.proj().inverse(),
             frustum: Frustum::from_matrix(camera.proj()),
             ambient: info.ambient,
             resolution: info.resolution,
+            debug_view: info.debug_view as u32,
+            skylight: info.skylight,
+            time: clock.elapsed() as f32,
+            delta_time: clock.delta(),
+            frame_index: clock.frame_index(),
+            unjittered_view_proj: camera.unjittered_view_proj(),
+            previous_view_proj: camera.previous_view_proj(),
         }
     }
 }
@@ -62,10 +118,20 @@ impl FrameUniforms {
 pub struct UniformInformation<'node> {
     /// Struct containing the default set of samplers.
     pub samplers: &'node Samplers,
+    /// The split-sum environment BRDF bound as `brdf_lut`.
+    pub brdf_lut: &'node BrdfLut,
+    /// The noise textures bound as `blue_noise` and `bayer`.
+    pub noise: &'node NoiseTextures,
     /// Ambient light color.
     pub ambient: Vec4,
     /// Resolution of the viewport.
     pub resolution: UVec2,
+    /// Material channel to visualize.
+    pub debug_view: DebugView,
+    /// Spherical harmonics of the diffuse light from the sky, see
+    /// [`SkyboxRoutine::skylight`](crate::skybox::SkyboxRoutine::skylight).
+    /// All zeros to disable.
+    pub skylight: [Vec4; 9],
 }
 
 pub struct UniformBindingHandles<'node> {
@@ -77,6 +143,10 @@ pub struct UniformBindingHandles<'node> {
     /// The output bind group handle for the forward uniform data. This does
     /// include the shadow map texture.
     pub forward_uniform_bg: DataHandle<BindGroup>,
+    /// The point light tiles bound in the forward uniform data, see
+    /// [`TiledLightingRoutine`](crate::tiled_lighting::TiledLightingRoutine).
+    /// Without them, shaders loop over every point light.
+    pub light_tiles: Option<DataHandle<Buffer>>,
 }
 
 /// Add the creation of these uniforms to the graph.
@@ -85,10 +155,35 @@ pub fn add_to_graph<'node>(
     shadow_target: RenderTargetHandle,
     binding_handles: UniformBindingHandles<'node>,
     info: UniformInformation<'node>,
+) {
+    add_to_graph_inner(graph, shadow_target, binding_handles, info, None)
+}
+
+/// Add the creation of these uniforms to the graph, using the camera written
+/// to `camera` by an earlier node instead of the viewport camera.
+pub fn add_to_graph_with_camera<'node>(
+    graph: &mut RenderGraph<'node>,
+    shadow_target: RenderTargetHandle,
+    binding_handles: UniformBindingHandles<'node>,
+    info: UniformInformation<'node>,
+    camera: DataHandle<CameraState>,
+) {
+    add_to_graph_inner(graph, shadow_target, binding_handles, info, Some(camera))
+}
+
+fn add_to_graph_inner<'node>(
+    graph: &mut RenderGraph<'node>,
+    shadow_target: RenderTargetHandle,
+    binding_handles: UniformBindingHandles<'node>,
+    info: UniformInformation<'node>,
+    camera: Option<DataHandle<CameraState>>,
 ) {
     let mut builder = graph.add_node("build uniform data");
     let shadow_handle = builder.add_data(binding_handles.shadow_uniform_bg, NodeResourceUsage::Output);
     let forward_handle = builder.add_data(binding_handles.forward_uniform_bg, NodeResourceUsage::Output);
+    let camera_handle = camera.map(|camera| builder.add_data(camera, NodeResourceUsage::Input));
+    let light_tiles_handle =
+        binding_handles.light_tiles.map(|light_tiles| builder.add_data(light_tiles, NodeResourceUsage::Input));
 
     // Get the shadow target and declare it a dependency of the forward_uniform_bg
     let shadow_target_handle = builder.add_render_target(shadow_target, NodeResourceUsage::Reference);
@@ -101,7 +196,11 @@ pub fn add_to_graph<'node>(
 
         info.samplers.add_to_bg(&mut bgb);
 
-        let uniforms = FrameUniforms::new(&ctx.data_core.viewport_camera_state, &info);
+        let camera = match camera_handle {
+            Some(handle) => ctx.graph_data.get_data(ctx.temps, handle).unwrap(),
+            None => &ctx.data_core.viewport_camera_state,
+        };
+        let uniforms = FrameUniforms::new(camera, &ctx.data_core.clock, &info);
         let uniform_buffer = ctx.renderer.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Frame Uniforms"),
             size: FrameUniforms::SHADER_SIZE.get(),
@@ -123,6 +222,21 @@ pub fn add_to_graph<'node>(
 
         bgb.append_texture_view(shadow_target);
 
+        let light_tiles = match light_tiles_handle {
+            Some(handle) => ctx.graph_data.get_data(ctx.temps, handle).unwrap(),
+            // A header of zeros disables tiling.
+            None => &*ctx.temps.add(ctx.renderer.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Disabled Light Tiles"),
+                size: 16,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })),
+        };
+        bgb.append_buffer(light_tiles);
+        bgb.append_texture_view(&info.brdf_lut.view);
+        info.noise.add_to_bg(&mut bgb);
+        ctx.data_core.directional_light_manager.add_projectors_to_bg(&mut bgb);
+
         let forward_uniform_bg = bgb.build(
             &ctx.renderer.device,
             Some("forward uniform bg"),
@@ -0,0 +1,68 @@
+//! Per-pixel motion of the opaque objects.
+//!
+//! The opaque and cutout passes of the viewport write how far the surface
+//! drawn in each pixel moved on screen since the last frame into a velocity
+//! target, alongside the color or the G-buffer. The motion follows the camera,
+//! the transforms of objects and instances, and skinning, through the
+//! transforms and positions of the last frame the renderer keeps.
+//!
+//! The velocity is the texture coordinates of the pixel minus the texture
+//! coordinates the surface had in the last frame, without the camera jitter.
+//! Pixels no object was drawn into are left at zero, so readers should tell
+//! them apart with the depth, as the [`temporal`](crate::temporal) upscale
+//! does.
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{RenderGraph, RenderPassLoadOp, RenderPassTarget, RenderTargetDescriptor, RenderTargetHandle},
+    types::{SampleCount, TextureFormat, TextureUsages},
+};
+
+/// Format of the velocity target.
+pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+/// The velocity target, and its multisampled version it is resolved from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VelocityTargets {
+    pub single_sample: RenderTargetHandle,
+    pub multi_sample: Option<RenderTargetHandle>,
+}
+
+impl VelocityTargets {
+    pub fn new(graph: &mut RenderGraph<'_>, resolution: UVec2, samples: SampleCount) -> Self {
+        let single_sample = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("velocity".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: VELOCITY_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let multi_sample = samples.needs_resolve().then(|| {
+            graph.add_render_target(RenderTargetDescriptor {
+                label: Some("velocity multisampled".into()),
+                resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples,
+                format: VELOCITY_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+            })
+        });
+
+        Self { single_sample, multi_sample }
+    }
+
+    /// Target of the passes writing the velocity, resolved into
+    /// [`Self::single_sample`] with multisampling.
+    pub fn renderpass_target(&self) -> RenderPassTarget {
+        RenderPassTarget {
+            color: self.multi_sample.unwrap_or(self.single_sample),
+            resolve: self.multi_sample.map(|_| self.single_sample),
+            clear: Vec4::ZERO,
+            load: RenderPassLoadOp::ClearOnFirstUse,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Times adding and deleting tens of thousands of objects in one frame, as
+//! chunk streaming does, one object at a time and in batches.
+//!
+//! Deletes are always removed together at the end of a frame, so the delete
+//! baseline deletes one object per frame instead, on fewer objects.
+//!
+//! Run with `cargo bench -p rend3-test --bench object_streaming`.
+
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Vec3, Vec4};
+use rend3::types::{Handedness, MeshBuilder, Object, ObjectHandle, ObjectMeshKind};
+use rend3_test::TestRunner;
+
+const OBJECT_COUNT: usize = 50_000;
+const ITERATIONS: u32 = 5;
+/// Objects deleted one per frame for the delete baseline.
+const SINGLE_DELETE_COUNT: usize = 1_000;
+
+/// Time to record the instructions, and to evaluate them.
+#[derive(Default)]
+struct Timings {
+    record: Duration,
+    evaluate: Duration,
+}
+
+impl Timings {
+    fn report(&self, name: &str, object_count: usize) {
+        let record = self.record / ITERATIONS;
+        let evaluate = self.evaluate / ITERATIONS;
+        let per_object = (record + evaluate) / object_count as u32;
+        println!("{name:<24} record {record:>10.2?}  evaluate {evaluate:>10.2?}  per object {per_object:>8.2?}");
+    }
+}
+
+fn evaluate(runner: &TestRunner) -> Duration {
+    let start = Instant::now();
+    runner.swap_instruction_buffers();
+    let eval_output = runner.evaluate_instructions();
+    let elapsed = start.elapsed();
+
+    runner.queue.submit(eval_output.cmd_bufs);
+    runner.device.poll(wgpu::Maintain::Wait);
+    elapsed
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let Ok(runner) = runtime.block_on(TestRunner::builder().build()) else {
+        eprintln!("No adapter found, skipping benchmark");
+        return;
+    };
+
+    let mesh = MeshBuilder::new(vec![Vec3::ZERO, Vec3::X, Vec3::Y], Handedness::Left).build().unwrap();
+    let mesh = runner.add_mesh(mesh).unwrap();
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let objects = || {
+        (0..OBJECT_COUNT).map(|idx| Object {
+            mesh_kind: ObjectMeshKind::Static(mesh.clone()),
+            material: material.clone(),
+            transform: Mat4::from_translation(Vec3::new(idx as f32, 0.0, 0.0)),
+        })
+    };
+
+    let mut single_add = Timings::default();
+    let mut batch_add = Timings::default();
+    let mut delete = Timings::default();
+    let mut single_delete = Timings::default();
+    for _ in 0..ITERATIONS {
+        for (timings, batched) in [(&mut single_add, false), (&mut batch_add, true)] {
+            let start = Instant::now();
+            let handles: Vec<ObjectHandle> = match batched {
+                true => runner.add_objects(objects()),
+                false => objects().map(|object| runner.add_object(object)).collect(),
+            };
+            timings.record += start.elapsed();
+            timings.evaluate += evaluate(&runner);
+
+            let start = Instant::now();
+            drop(handles);
+            delete.record += start.elapsed();
+            delete.evaluate += evaluate(&runner);
+        }
+
+        let mut handles = runner.add_objects(objects().take(SINGLE_DELETE_COUNT));
+        evaluate(&runner);
+        while let Some(handle) = handles.pop() {
+            let start = Instant::now();
+            drop(handle);
+            single_delete.record += start.elapsed();
+            single_delete.evaluate += evaluate(&runner);
+        }
+    }
+
+    println!("{OBJECT_COUNT} objects, average of {ITERATIONS} frames");
+    single_add.report("add_object", OBJECT_COUNT);
+    batch_add.report("add_objects", OBJECT_COUNT);
+    // Deletes are batched however the objects were added.
+    delete.evaluate /= 2;
+    delete.record /= 2;
+    delete.report("delete", OBJECT_COUNT);
+    single_delete.report("delete (one per frame)", SINGLE_DELETE_COUNT);
+}
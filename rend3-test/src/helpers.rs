@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use glam::{Mat4, Vec3, Vec4};
-use rend3::types::{DirectionalLightHandle, MaterialHandle, MeshBuilder, ObjectHandle};
+use rend3::types::{DirectionalLightHandle, MaterialHandle, MeshBuilder, MeshHandle, ObjectHandle};
 use rend3_routine::pbr::{PbrMaterial, Transparency};
 use wgpu::Device;
 
@@ -30,6 +30,7 @@ impl TestRunner {
             color: glam::Vec3::ONE,
             resolution: 256,
             distance: 5.0,
+            shadow_fade: 0.0,
             intensity: 1.0,
             direction,
         })
@@ -62,6 +63,15 @@ impl TestRunner {
 
     /// Creates a plane object that is [-1, 1]
     pub fn plane(&self, material: MaterialHandle, transform: Mat4) -> ObjectHandle {
+        self.add_object(rend3::types::Object {
+            mesh_kind: rend3::types::ObjectMeshKind::Static(self.plane_mesh()),
+            material,
+            transform,
+        })
+    }
+
+    /// Creates a plane mesh that is [-1, 1]
+    pub fn plane_mesh(&self) -> MeshHandle {
         let mesh = MeshBuilder::new(
             vec![
                 glam::Vec3::new(-1.0, -1.0, 0.0),
@@ -75,11 +85,7 @@ impl TestRunner {
         .build()
         .unwrap();
 
-        self.add_object(rend3::types::Object {
-            mesh_kind: rend3::types::ObjectMeshKind::Static(self.add_mesh(mesh).unwrap()),
-            material,
-            transform,
-        })
+        self.add_mesh(mesh).unwrap()
     }
 
     /// Creates a cube object that is [-1, 1]
@@ -1,8 +1,20 @@
+//! Image comparison tests of rend3 and routines built on it.
+//!
+//! [`TestRunner`] builds a headless renderer with the base render graph,
+//! renders frames into a texture and reads them back. [`compare_image_to_path`]
+//! compares a frame to a reference image with the FLIP perceptual metric,
+//! passing if the error is within the given [`Threshold`]s. Reference images
+//! that don't exist yet are written, so the first run of a test records it.
+//!
+//! Routines under test are added to the frame by a [`TestGraph`], rendered
+//! with [`TestRunner::render_and_compare_with`], usually by extending
+//! [`TestRunner::base_routines`].
+
 mod helpers;
 mod runner;
 mod threshold;
 
-pub use runner::{compare_image_to_path, download_image, FrameRenderSettings, TestRunner};
+pub use runner::{compare_image_to_path, download_image, BaseTestGraph, FrameRenderSettings, TestGraph, TestRunner};
 pub use threshold::{Threshold, ThresholdSet};
 
 #[macro_export]
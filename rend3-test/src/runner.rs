@@ -6,10 +6,17 @@ use anyhow::{bail, ensure, Context, Result};
 use glam::UVec2;
 use image::buffer::ConvertBuffer;
 use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph},
     types::{Handedness, SampleCount},
     Renderer,
 };
-use rend3_routine::{base::BaseRenderGraph, pbr::PbrRoutine, tonemapping::TonemappingRoutine};
+use rend3_routine::{
+    base::{
+        BaseRenderGraph, BaseRenderGraphInputs, BaseRenderGraphRoutines, BaseRenderGraphSettings, OutputRenderTarget,
+    },
+    pbr::PbrRoutine,
+    tonemapping::TonemappingRoutine,
+};
 use wgpu::{
     Extent3d, ImageCopyBuffer, ImageDataLayout, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
 };
@@ -91,6 +98,39 @@ impl TestRunnerBuilder {
     }
 }
 
+/// Builds the render graph of a test frame. Tests of routines implement this
+/// to add the routines to the frame.
+pub trait TestGraph {
+    /// Adds the nodes rendering the frame into `target`.
+    fn add_to_graph<'node>(
+        &'node self,
+        runner: &'node TestRunner,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    );
+}
+
+/// The base render graph with [`TestRunner::base_routines`] and
+/// [`TestRunner::base_settings`].
+pub struct BaseTestGraph;
+
+impl TestGraph for BaseTestGraph {
+    fn add_to_graph<'node>(
+        &'node self,
+        runner: &'node TestRunner,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    ) {
+        runner.base_rendergraph.add_to_graph(
+            graph,
+            BaseRenderGraphInputs { eval_output, routines: runner.base_routines(), target },
+            TestRunner::base_settings(),
+        );
+    }
+}
+
 pub struct TestRunner {
     pub renderer: Arc<Renderer>,
     pub pbr: PbrRoutine,
@@ -112,7 +152,35 @@ impl TestRunner {
         TestRunnerBuilder::new()
     }
 
+    /// The routines the default frame renders with, to extend with the
+    /// routines under test.
+    pub fn base_routines(&self) -> BaseRenderGraphRoutines<'_> {
+        BaseRenderGraphRoutines {
+            pbr: &self.pbr,
+            skybox: None,
+            tonemapping: &self.tonemapping,
+            shadow_cache: None,
+            post_process: &[],
+            tiled_lighting: None,
+            particles: &[],
+            lens_flare: None,
+            bloom: None,
+            object_compute: &[],
+        }
+    }
+
+    /// Settings the default frame renders with.
+    pub fn base_settings() -> BaseRenderGraphSettings {
+        BaseRenderGraphSettings { ambient_color: glam::Vec4::ZERO, clear_color: glam::Vec4::ZERO, ..Default::default() }
+    }
+
+    /// Renders a frame with [`BaseTestGraph`].
     pub fn process_events(&self, settings: FrameRenderSettings) -> wgpu::Texture {
+        self.process_events_with(settings, &BaseTestGraph)
+    }
+
+    /// Renders a frame with the given graph.
+    pub fn process_events_with(&self, settings: FrameRenderSettings, test_graph: &impl TestGraph) -> wgpu::Texture {
         let texture = self.renderer.device.create_texture(&TextureDescriptor {
             label: Some("Test output image"),
             size: Extent3d { width: settings.size, height: settings.size, depth_or_array_layers: 1 },
@@ -136,24 +204,15 @@ impl TestRunner {
             rend3::graph::ViewportRect::from_size(UVec2::splat(settings.size)),
         );
 
-        self.base_rendergraph.add_to_graph(
+        test_graph.add_to_graph(
+            self,
             &mut graph,
-            rend3_routine::base::BaseRenderGraphInputs {
-                eval_output: &eval_output,
-                routines: rend3_routine::base::BaseRenderGraphRoutines {
-                    pbr: &self.pbr,
-                    skybox: None,
-                    tonemapping: &self.tonemapping,
-                },
-                target: rend3_routine::base::OutputRenderTarget {
-                    handle: frame_handle,
-                    resolution: UVec2::splat(settings.size),
-                    samples: settings.samples,
-                },
-            },
-            rend3_routine::base::BaseRenderGraphSettings {
-                ambient_color: glam::Vec4::ZERO,
-                clear_color: glam::Vec4::ZERO,
+            &eval_output,
+            OutputRenderTarget {
+                handle: frame_handle,
+                resolution: UVec2::splat(settings.size),
+                samples: settings.samples,
+                render_scale: 1.0,
             },
         );
 
@@ -168,6 +227,18 @@ impl TestRunner {
         download_image(&self.renderer, texture, UVec2::splat(settings.size)).await
     }
 
+    /// Renders a frame with the given graph and reads it back.
+    pub async fn render_frame_with(
+        &self,
+        settings: FrameRenderSettings,
+        test_graph: &impl TestGraph,
+    ) -> Result<image::RgbaImage> {
+        let size = settings.size;
+        let texture = self.process_events_with(settings, test_graph);
+
+        download_image(&self.renderer, texture, UVec2::splat(size)).await
+    }
+
     pub async fn render_and_compare(
         &self,
         settings: FrameRenderSettings,
@@ -178,6 +249,20 @@ impl TestRunner {
 
         compare_image_to_path(&test_rgba, path.as_ref(), threshold)
     }
+
+    /// Renders a frame with the given graph and compares it to the image at
+    /// `path`. If there is no image at `path`, the frame is saved there.
+    pub async fn render_and_compare_with(
+        &self,
+        settings: FrameRenderSettings,
+        test_graph: &impl TestGraph,
+        path: impl AsRef<Path>,
+        threshold: impl Into<ThresholdSet>,
+    ) -> Result<()> {
+        let test_rgba = self.render_frame_with(settings, test_graph).await?;
+
+        compare_image_to_path(&test_rgba, path.as_ref(), threshold)
+    }
 }
 
 pub async fn download_image(
@@ -16,10 +16,16 @@ impl ThresholdSet {
     }
 }
 
+/// Maximum FLIP error of a comparison. FLIP errors range from 0, identical,
+/// to 1, as different as possible.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Threshold {
+    /// Maximum mean error over all pixels.
     Mean(f32),
+    /// Maximum error of the given fraction of the pixels with the least error.
     Percentile { percentile: f32, threshold: f32 },
+    /// Maximum error of any pixel.
+    Max(f32),
 }
 
 impl Threshold {
@@ -37,6 +43,17 @@ impl Threshold {
                 );
                 within
             }
+            Self::Max(v) => {
+                let max = pool.max_value();
+                let within = max <= v;
+                println!(
+                    "    Expected Max ({:.6}) to be under expected maximum ({}): {}",
+                    max,
+                    v,
+                    if within { "PASS" } else { "FAIL" }
+                );
+                within
+            }
             Self::Percentile { percentile: p, threshold: v } => {
                 let percentile = pool.get_percentile(p, true);
                 let within = percentile <= v;
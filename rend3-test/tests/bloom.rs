@@ -0,0 +1,83 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph},
+    types::{Camera, Handedness},
+    ShaderPreProcessor,
+};
+use rend3_routine::{
+    base::{BaseRenderGraphInputs, BaseRenderGraphRoutines, BaseRenderGraphSettings, OutputRenderTarget},
+    bloom::{BloomRoutine, BloomSettings},
+    pbr::{AlbedoComponent, MaterialComponent, PbrMaterial},
+};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestGraph, TestRunner};
+
+/// The base render graph with a bloom routine, shading deferred if asked.
+struct BloomGraph(BloomRoutine, bool);
+
+impl TestGraph for BloomGraph {
+    fn add_to_graph<'node>(
+        &'node self,
+        runner: &'node TestRunner,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    ) {
+        let routines = BaseRenderGraphRoutines { bloom: Some(&self.0), ..runner.base_routines() };
+        runner.base_rendergraph.add_to_graph(
+            graph,
+            BaseRenderGraphInputs { eval_output, routines, target },
+            BaseRenderGraphSettings { deferred: self.1, ..TestRunner::base_settings() },
+        );
+    }
+}
+
+/// Ensure that only the emission of materials flagged to bloom spreads past
+/// the edges of their objects, with forward and deferred shading.
+#[test_attr]
+pub async fn emissive_bloom() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let mut spp = ShaderPreProcessor::new();
+    rend3_routine::builtin_shaders(&mut spp);
+    let mut graph = BloomGraph(
+        BloomRoutine::new(&runner.renderer, &spp, &runner.base_rendergraph.interfaces, BloomSettings::default()),
+        false,
+    );
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let quarter = Mat4::from_scale(Vec3::new(0.25, 0.25, 1.0));
+    for (deferred, emissive_bloom) in [(false, false), (false, true), (true, false), (true, true)] {
+        graph.1 = deferred;
+        let material = runner.add_material(PbrMaterial {
+            albedo: AlbedoComponent::Value(Vec4::new(0.0, 0.0, 0.0, 1.0)),
+            emissive: MaterialComponent::Value(Vec3::ONE),
+            emissive_intensity: Some(4.0),
+            emissive_bloom,
+            unlit: true,
+            ..Default::default()
+        });
+        let _object = runner.plane(material, quarter);
+
+        // The plane covers the pixels 24 to 40.
+        let image = runner.render_frame_with(FrameRenderSettings::new(), &graph).await?;
+        assert_eq!(image.get_pixel(32, 32).0, [255, 255, 255, 255]);
+        let glow = image.get_pixel(20, 32).0;
+        if emissive_bloom {
+            assert!(glow[0] > 0, "no bloom around the plane (deferred: {deferred}): {glow:?}");
+        } else {
+            assert_eq!(glow, [0, 0, 0, 0], "bloom without the flag (deferred: {deferred})");
+        }
+    }
+
+    Ok(())
+}
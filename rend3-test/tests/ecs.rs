@@ -0,0 +1,107 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::types::{Camera, Handedness, ObjectMeshKind, PointLight};
+use rend3_ecs::{Light, Renderable, SceneSync, SyncStats, Transform};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner};
+
+fn renderable(runner: &TestRunner) -> Renderable {
+    Renderable { mesh: ObjectMeshKind::Static(runner.plane_mesh()), material: runner.add_unlit_material(Vec4::ONE) }
+}
+
+fn light() -> Light {
+    Light::Point(PointLight { position: Vec3::Z, color: Vec3::ONE, radius: 1.0, intensity: 1.0 })
+}
+
+/// Scales the plane to cover the pixels 24 to 40, moved off screen if `moved`.
+fn transform(moved: bool) -> Transform {
+    let x = if moved { 2.0 } else { 0.0 };
+    Transform(Mat4::from_translation(Vec3::new(x, 0.0, 0.0)) * Mat4::from_scale(Vec3::new(0.25, 0.25, 1.0)))
+}
+
+async fn center_pixel(runner: &TestRunner) -> anyhow::Result<[u8; 4]> {
+    Ok(runner.render_frame(FrameRenderSettings::new()).await?.get_pixel(32, 32).0)
+}
+
+/// Ensure that spawning, moving and despawning hecs entities adds, updates
+/// and removes their objects and lights.
+#[test_attr]
+pub async fn hecs_spawn_update_despawn() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let mut world = hecs::World::new();
+    let mut sync = SceneSync::new();
+
+    let plane = world.spawn((renderable(&runner), transform(false)));
+    let lamp = world.spawn((light(),));
+    let stats = sync.sync_hecs(&runner.renderer, &world);
+    assert_eq!(stats, SyncStats { added: 2, updated: 0, removed: 0 });
+    assert!(sync.object(plane).is_some());
+    assert_eq!(center_pixel(&runner).await?, [255, 255, 255, 255]);
+
+    assert_eq!(sync.sync_hecs(&runner.renderer, &world), SyncStats::default());
+
+    *world.get::<&mut Transform>(plane)? = transform(true);
+    let stats = sync.sync_hecs(&runner.renderer, &world);
+    assert_eq!(stats, SyncStats { added: 0, updated: 1, removed: 0 });
+    assert_eq!(center_pixel(&runner).await?, [0, 0, 0, 0]);
+
+    world.despawn(plane)?;
+    world.despawn(lamp)?;
+    let stats = sync.sync_hecs(&runner.renderer, &world);
+    assert_eq!(stats, SyncStats { added: 0, updated: 0, removed: 2 });
+    assert!(sync.object(plane).is_none());
+
+    Ok(())
+}
+
+/// Ensure that spawning, moving and despawning bevy entities adds, updates
+/// and removes their objects and lights.
+#[test_attr]
+pub async fn bevy_spawn_update_despawn() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let mut world = bevy_ecs::world::World::new();
+    let mut sync = SceneSync::new();
+
+    let plane = world.spawn((renderable(&runner), transform(false))).id();
+    let lamp = world.spawn(light()).id();
+    let stats = sync.sync_bevy(&runner.renderer, &mut world);
+    assert_eq!(stats, SyncStats { added: 2, updated: 0, removed: 0 });
+    assert!(sync.object(plane).is_some());
+    assert_eq!(center_pixel(&runner).await?, [255, 255, 255, 255]);
+
+    assert_eq!(sync.sync_bevy(&runner.renderer, &mut world), SyncStats::default());
+
+    *world.get_mut::<Transform>(plane).context("plane has no transform")? = transform(true);
+    let stats = sync.sync_bevy(&runner.renderer, &mut world);
+    assert_eq!(stats, SyncStats { added: 0, updated: 1, removed: 0 });
+    assert_eq!(center_pixel(&runner).await?, [0, 0, 0, 0]);
+
+    world.despawn(plane);
+    world.despawn(lamp);
+    let stats = sync.sync_bevy(&runner.renderer, &mut world);
+    assert_eq!(stats, SyncStats { added: 0, updated: 0, removed: 2 });
+    assert!(sync.object(plane).is_none());
+
+    Ok(())
+}
@@ -1,9 +1,13 @@
 use anyhow::Context;
-use glam::{Mat4, Quat, Vec3, Vec4};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
 use rend3::{
-    types::{Camera, Handedness, ObjectChange},
+    types::{
+        Camera, Handedness, Instance, InstancedObject, MeshBuilder, Object, ObjectChange, ObjectMeshKind, ObjectParent,
+    },
     util::freelist::FreelistDerivedBuffer,
+    DiagnosticKind,
 };
+use rend3_routine::pbr::PbrMaterial;
 use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner, Threshold};
 
 /// Ensure that duplicate_object doesn't retain the object for an extra frame.
@@ -107,3 +111,268 @@ pub async fn multi_frame_add() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Objects added with `add_objects` are built on several threads once the
+/// batch is large enough, and deleted objects are removed together at the end
+/// of the frame. Ensure batches on both sides of the threshold are all added,
+/// and are all gone once their handles are dropped.
+#[test_attr]
+pub async fn batch_add_delete() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let mesh = runner.plane_mesh();
+
+    let object_count = |runner: &TestRunner| {
+        let data_core = runner.renderer.data_core.lock();
+        data_core.object_manager.enumerated_objects::<PbrMaterial>().map_or(0, |objects| objects.len())
+    };
+
+    for count in [100, 10_000] {
+        let objects = runner.add_objects((0..count).map(|idx| Object {
+            mesh_kind: ObjectMeshKind::Static(mesh.clone()),
+            material: material.clone(),
+            transform: Mat4::from_translation(Vec3::new(idx as f32, 0.0, 0.0)),
+        }));
+        runner.render_frame(FrameRenderSettings::new()).await?;
+        assert_eq!(object_count(&runner), count);
+
+        drop(objects);
+        runner.render_frame(FrameRenderSettings::new()).await?;
+        assert_eq!(object_count(&runner), 0);
+    }
+
+    Ok(())
+}
+
+/// Ensure that the diagnostics mode reports bad transforms.
+#[test_attr]
+pub async fn diagnostics_bad_transforms() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_diagnostics_enabled(true);
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let _zero = runner.plane(material.clone(), Mat4::from_scale(Vec3::new(1.0, 0.0, 1.0)));
+    let _nan = runner.plane(material.clone(), Mat4::from_translation(Vec3::new(f32::NAN, 0.0, 0.0)));
+    let _fine = runner.plane(material, Mat4::IDENTITY);
+
+    runner.swap_instruction_buffers();
+    let _ = runner.evaluate_instructions();
+
+    let kinds: Vec<_> = runner.take_diagnostics().into_iter().map(|d| d.kind).collect();
+    assert_eq!(kinds, [DiagnosticKind::ZeroScaleTransform, DiagnosticKind::NonFiniteTransform]);
+
+    Ok(())
+}
+
+/// Ensure that the determinism mode ignores camera jitter.
+#[test_attr]
+pub async fn determinism_ignores_jitter() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_determinism_enabled(true);
+    runner.set_camera_jitter(Vec2::splat(0.5));
+
+    runner.swap_instruction_buffers();
+    let _ = runner.evaluate_instructions();
+
+    assert_eq!(runner.data_core.lock().viewport_camera_state.jitter(), Vec2::ZERO);
+
+    Ok(())
+}
+
+/// Ensure that retained resources are restored into a new renderer.
+#[test_attr]
+pub async fn restore_retained_resources() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let mesh = rend3::types::MeshBuilder::new(vec![Vec3::ZERO, Vec3::X, Vec3::Y], Handedness::Left)
+        .with_indices(vec![0, 1, 2])
+        .build()
+        .unwrap();
+    let old_mesh = runner.add_mesh_retained(mesh).unwrap();
+    let _not_retained = runner.add_unlit_material(Vec4::ONE);
+
+    let renderer = rend3::Renderer::new(iad, Handedness::Left, None).unwrap();
+    let restored = runner.restore_retained_into(&renderer);
+
+    assert!(restored.mesh(&old_mesh).is_some());
+    assert_eq!(restored.meshes.len(), 1);
+    assert!(restored.materials.is_empty());
+
+    Ok(())
+}
+
+/// Ensure that children follow their parent as it moves.
+#[test_attr]
+pub async fn child_follows_parent() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    // The parent is off screen, the child is where the planes of the
+    // duplicate_object test are.
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let parent = runner.plane(material.clone(), Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)));
+    let child = runner.plane(
+        material,
+        Mat4::from_scale_rotation_translation(Vec3::new(-0.25, 0.25, 0.25), Quat::IDENTITY, Vec3::new(-10.5, 0.0, 0.0)),
+    );
+    runner.set_object_parent(&child, Some(ObjectParent::Object(parent.clone())));
+
+    runner
+        .render_and_compare(
+            FrameRenderSettings::new(),
+            "tests/results/object/duplicate-object-retain-left.png",
+            Threshold::Mean(0.0),
+        )
+        .await?;
+
+    runner.set_object_transform(&parent, Mat4::from_translation(Vec3::new(11.0, 0.0, 0.0)));
+
+    runner
+        .render_and_compare(
+            FrameRenderSettings::new(),
+            "tests/results/object/duplicate-object-retain-right.png",
+            Threshold::Mean(0.0),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Ensure that objects pick up the new data of a replaced mesh.
+#[test_attr]
+pub async fn replace_mesh() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let plane = |offset: f32| {
+        MeshBuilder::new(
+            [
+                Vec3::new(-1.0, -1.0, 0.0),
+                Vec3::new(-1.0, 1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(1.0, -1.0, 0.0),
+            ]
+            .map(|v| v + Vec3::X * offset)
+            .to_vec(),
+            Handedness::Left,
+        )
+        .with_indices(vec![0, 2, 1, 0, 3, 2])
+        .build()
+        .unwrap()
+    };
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let mesh = runner.add_mesh(plane(0.0)).unwrap();
+    let _object = runner.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(mesh.clone()),
+        material,
+        transform: Mat4::from_scale_rotation_translation(
+            Vec3::new(-0.25, 0.25, 0.25),
+            Quat::IDENTITY,
+            Vec3::new(-0.5, 0.0, 0.0),
+        ),
+    });
+
+    runner
+        .render_and_compare(
+            FrameRenderSettings::new(),
+            "tests/results/object/duplicate-object-retain-left.png",
+            Threshold::Mean(0.0),
+        )
+        .await?;
+
+    // Moves the plane from -0.5 to 0.5 through the negative x scale.
+    runner.replace_mesh(&mesh, plane(-4.0)).unwrap();
+
+    runner
+        .render_and_compare(
+            FrameRenderSettings::new(),
+            "tests/results/object/duplicate-object-retain-right.png",
+            Threshold::Mean(0.0),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Ensure that instances are drawn where they are, culled one at a time, and
+/// that out of range transform updates are rejected.
+#[test_attr]
+pub async fn instanced_object() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let quarter = |x: f32| {
+        Mat4::from_scale_rotation_translation(Vec3::new(-0.25, 0.25, 0.25), Quat::IDENTITY, Vec3::new(x, 0.0, 0.0))
+    };
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let object = runner.add_instanced_object(InstancedObject {
+        mesh_kind: ObjectMeshKind::Static(runner.plane_mesh()),
+        material,
+        instances: vec![Instance::new(quarter(-0.5)), Instance::new(quarter(10.0))],
+    });
+    assert_eq!(object.len(), 2);
+
+    let image = runner.render_frame(FrameRenderSettings::new()).await?;
+    assert_eq!(image.get_pixel(16, 32).0, [255, 255, 255, 255]);
+    assert_eq!(image.get_pixel(48, 32).0, [0, 0, 0, 0]);
+
+    runner.set_instance_transforms(&object, 1, &[quarter(0.5)])?;
+    assert!(runner.set_instance_transforms(&object, 1, &[quarter(0.5), quarter(0.5)]).is_err());
+
+    let image = runner.render_frame(FrameRenderSettings::new()).await?;
+    assert_eq!(image.get_pixel(16, 32).0, [255, 255, 255, 255]);
+    assert_eq!(image.get_pixel(48, 32).0, [255, 255, 255, 255]);
+
+    Ok(())
+}
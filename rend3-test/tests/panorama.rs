@@ -0,0 +1,54 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph},
+    types::Handedness,
+};
+use rend3_routine::{
+    base::{BaseRenderGraphInputs, BaseRenderGraphSettings, OutputRenderTarget},
+    panorama::{capture_panorama, PanoramaGraph, PanoramaSettings},
+};
+use rend3_test::{no_gpu_return, test_attr, TestRunner};
+
+struct Graph<'a>(&'a TestRunner);
+
+impl PanoramaGraph for Graph<'_> {
+    fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    ) {
+        self.0.base_rendergraph.add_to_graph(
+            graph,
+            BaseRenderGraphInputs { eval_output, routines: self.0.base_routines(), target },
+            BaseRenderGraphSettings { clear_color: Vec4::new(1.0, 0.0, 0.0, 1.0), ..TestRunner::base_settings() },
+        );
+    }
+}
+
+/// Ensure tiled equirectangular panoramas see the scene in every direction.
+#[test_attr]
+pub async fn equirectangular() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let material = runner.add_unlit_material(Vec4::new(0.0, 1.0, 0.0, 1.0));
+    let _cube = runner.cube(material, Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0)));
+
+    let settings =
+        PanoramaSettings { resolution: glam::UVec2::new(256, 128), max_tile_size: 32, ..PanoramaSettings::default() };
+    let panorama = capture_panorama(&runner.renderer, &Graph(&runner), &settings).await?;
+    let image = image::RgbaImage::from_raw(256, 128, panorama.data).context("Panorama has the wrong size")?;
+
+    // +x is a quarter turn right of the center.
+    assert_eq!(image.get_pixel(192, 64).0, [0, 255, 0, 255]);
+    assert_eq!(image.get_pixel(64, 64).0, [255, 0, 0, 255]);
+    assert!(image.rows().next().unwrap().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+
+    Ok(())
+}
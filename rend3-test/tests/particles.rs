@@ -0,0 +1,120 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph},
+    types::{Camera, Handedness},
+    ShaderPreProcessor,
+};
+use rend3_routine::{
+    base::{BaseRenderGraphInputs, BaseRenderGraphRoutines, OutputRenderTarget},
+    particles::{ParticleEmitter, ParticleRoutine},
+};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestGraph, TestRunner};
+
+/// The base render graph with a particle routine.
+struct ParticleGraph<'a>([&'a ParticleRoutine; 1]);
+
+impl TestGraph for ParticleGraph<'_> {
+    fn add_to_graph<'node>(
+        &'node self,
+        runner: &'node TestRunner,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    ) {
+        let routines = BaseRenderGraphRoutines { particles: &self.0, ..runner.base_routines() };
+        runner.base_rendergraph.add_to_graph(
+            graph,
+            BaseRenderGraphInputs { eval_output, routines, target },
+            TestRunner::base_settings(),
+        );
+    }
+}
+
+/// An emitter whose particles don't move or change color, nor collide.
+fn still_emitter(position: Vec3, color: Vec4) -> ParticleEmitter {
+    ParticleEmitter {
+        position,
+        velocity: Vec3::ZERO,
+        spread: 0.0,
+        gravity: Vec3::ZERO,
+        size: 0.5,
+        color_start: color,
+        color_end: color,
+        collision: false,
+        ..Default::default()
+    }
+}
+
+/// Ensure that spawned particles are drawn as quads around the emitter.
+#[test_attr]
+pub async fn particles_render() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let mut spp = ShaderPreProcessor::new();
+    rend3_routine::builtin_shaders(&mut spp);
+    let emitter = still_emitter(Vec3::new(0.0, 0.0, 0.5), Vec4::new(1.0, 0.0, 0.0, 1.0));
+    let mut routine = ParticleRoutine::new(&runner.renderer, &spp, emitter, 16);
+    routine.update(0.1);
+
+    // The quads cover the pixels 24 to 40.
+    let image = runner.render_frame_with(FrameRenderSettings::new(), &ParticleGraph([&routine])).await?;
+    let [r, g, b, _] = image.get_pixel(32, 32).0;
+    assert!(r > 0 && g == 0 && b == 0, "no particle at the emitter: {:?}", [r, g, b]);
+    assert_eq!(image.get_pixel(8, 8).0, [0, 0, 0, 0]);
+
+    Ok(())
+}
+
+/// Ensure that particles are sorted back to front by their distance to the
+/// camera, not drawn in the order they were spawned in.
+#[test_attr]
+pub async fn particles_sort_back_to_front() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    let mut spp = ShaderPreProcessor::new();
+    rend3_routine::builtin_shaders(&mut spp);
+    // One opaque particle is spawned a frame, turning from green to red as it
+    // moves towards the camera, so the oldest particle is both the reddest and
+    // the closest. Spawn order would draw the youngest, greenest one last.
+    let emitter = ParticleEmitter {
+        velocity: Vec3::new(0.0, 0.0, -1.0),
+        spawn_rate: 8.0,
+        lifetime: 1.0,
+        color_start: Vec4::new(0.0, 1.0, 0.0, 1.0),
+        color_end: Vec4::new(1.0, 0.0, 0.0, 1.0),
+        ..still_emitter(Vec3::new(0.0, 0.0, 0.9), Vec4::ZERO)
+    };
+    let mut routine = ParticleRoutine::new(&runner.renderer, &spp, emitter, 16);
+
+    let mut image = None;
+    for _ in 0..6 {
+        routine.update(0.125);
+        image = Some(runner.render_frame_with(FrameRenderSettings::new(), &ParticleGraph([&routine])).await?);
+    }
+
+    let [r, g, _, _] = image.unwrap().get_pixel(32, 32).0;
+    assert!(r > g, "the closest particle is not drawn last: red {r}, green {g}");
+
+    Ok(())
+}
@@ -1,5 +1,12 @@
+mod bloom;
+mod ecs;
 mod msaa;
 mod object;
+mod panorama;
+mod particles;
+mod scene;
+mod shading;
 mod shadow;
 mod simple;
+mod texture;
 mod transparency;
@@ -0,0 +1,59 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    scene::{SceneAssets, SceneSnapshot},
+    types::{Camera, CameraProjection, Handedness, MeshBuilder, Object, ObjectMeshKind},
+};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner};
+
+/// A scene serialized and restored into another renderer renders the same.
+#[test_attr]
+pub async fn snapshot_restore() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(first) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+    let Ok(second) = TestRunner::builder().iad(iad).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let assets = |runner: &TestRunner| -> anyhow::Result<SceneAssets<String>> {
+        let mesh = MeshBuilder::new(
+            vec![Vec3::new(-1.0, -1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0)],
+            Handedness::Left,
+        )
+        .build()
+        .context("Failed to create mesh")?;
+
+        let mut assets = SceneAssets::new();
+        assets.insert_mesh(String::from("triangle"), &runner.add_mesh(mesh).unwrap());
+        assets.insert_material(String::from("red"), &runner.add_unlit_material(Vec4::new(1.0, 0.0, 0.0, 1.0)));
+        Ok(assets)
+    };
+    let first_assets = assets(&first)?;
+    let second_assets = assets(&second)?;
+
+    first.set_camera_data(Camera { projection: CameraProjection::Raw(Mat4::IDENTITY), view: Mat4::IDENTITY });
+    let _object = first.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(first_assets.mesh(&String::from("triangle")).unwrap().clone()),
+        material: first_assets.material(&String::from("red")).unwrap().clone(),
+        transform: Mat4::from_scale(Vec3::splat(0.5)),
+    });
+    let first_image = first.render_frame(FrameRenderSettings::new()).await?;
+
+    let snapshot = first.snapshot_scene(&first_assets)?;
+    assert_eq!(snapshot.objects.len(), 1);
+
+    let json = serde_json::to_string(&snapshot)?;
+    let snapshot: SceneSnapshot<String> = serde_json::from_str(&json)?;
+
+    let restored = second.restore_scene(&snapshot, &second_assets)?;
+    assert_eq!(restored.objects.len(), 1);
+    let second_image = second.render_frame(FrameRenderSettings::new()).await?;
+
+    assert!(first_image == second_image, "restored scene renders differently");
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec3A, Vec4};
+use rend3::types::{Camera, CameraProjection, Handedness, PointLight};
+use rend3_routine::pbr::{AlbedoComponent, PbrMaterial};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner};
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Ensure that the specular of a rough metal is brightened by the energy
+/// lost to single scattering, which the BRDF lookup table gives back.
+#[test_attr]
+pub async fn energy_compensation() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: CameraProjection::Orthographic { size: Vec3A::new(2.5, 2.5, 5.0) },
+        view: Mat4::look_at_lh(Vec3::new(0.0, 0.0, -1.0), Vec3::ZERO, Vec3::Y),
+    });
+
+    // Light and view straight on, so n·l, n·v, n·h and l·h are all 1.
+    let _light = runner.add_point_light(PointLight {
+        position: Vec3::new(0.0, 0.0, -1.0),
+        color: Vec3::ONE,
+        radius: 1000.0,
+        intensity: std::f32::consts::PI,
+    });
+    let material = runner.add_material(PbrMaterial {
+        albedo: AlbedoComponent::Value(Vec4::ONE),
+        metallic_factor: Some(1.0),
+        roughness_factor: Some(1.0),
+        ..Default::default()
+    });
+    let _plane = runner.plane(material, Mat4::IDENTITY);
+
+    // Without energy compensation, GGX with a roughness of 1 straight on has
+    // D = 1/π, V = 1/4 and F = 1, reflecting 1/4 of a light of intensity π.
+    let single_scattering = 0.25;
+
+    let image = runner.render_frame(FrameRenderSettings::new()).await?;
+    let pixel = image.get_pixel(32, 32).0;
+    let linear = srgb_to_linear(pixel[0]);
+    assert!(
+        linear > single_scattering * 1.2 && linear < single_scattering * 3.0,
+        "rough metal isn't energy compensated: {linear} ({pixel:?}), single scattering is {single_scattering}"
+    );
+
+    Ok(())
+}
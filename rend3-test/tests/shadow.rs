@@ -2,7 +2,7 @@ use std::f32::consts::FRAC_PI_2;
 
 use anyhow::Context;
 use glam::{Mat4, Quat, Vec3, Vec3A, Vec4};
-use rend3::types::{Camera, Handedness};
+use rend3::types::{Camera, Handedness, ShadowFitting};
 use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner, Threshold};
 
 #[test_attr]
@@ -52,3 +52,41 @@ pub async fn shadows() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Ensure that a shadow map clamped to the scene fits tightly around it.
+#[test_attr]
+pub async fn shadow_fitting_clamps_to_scene() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    let light = runner.add_directional_light(Vec3::new(-1.0, -1.0, 1.0));
+    runner.set_directional_light_shadow_fitting(&light, ShadowFitting { clamp_to_scene: true, ..Default::default() });
+
+    let material = runner.add_lit_material(Vec4::new(0.25, 0.5, 0.75, 1.0));
+    let _plane = runner.plane(material, Mat4::from_rotation_x(-FRAC_PI_2));
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Orthographic { size: Vec3A::new(2.5, 2.5, 5.0) },
+        view: Mat4::look_at_lh(Vec3::new(0.0, 1.0, -1.0), Vec3::ZERO, Vec3::Y),
+    });
+
+    runner.render_frame(FrameRenderSettings::new().size(256)?).await?;
+
+    let views = runner.directional_shadow_views();
+    assert_eq!(views.len(), 1);
+    let corners =
+        [Vec3::new(-1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0)]
+            .map(|corner| views[0].view_proj.project_point3(corner));
+    for corner in corners {
+        assert!(corner.x.abs() <= 1.001 && corner.y.abs() <= 1.001, "{corner} is outside the shadow map");
+        assert!((-0.001..=1.001).contains(&corner.z), "{corner} is outside the shadow map's depth range");
+    }
+    let max_x = corners.iter().map(|corner| corner.x.abs()).fold(0.0, f32::max);
+    assert!(max_x > 0.9, "shadow map is not fit to the scene, plane only reaches {max_x}");
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
 use anyhow::Context;
 use glam::{Mat4, Vec3, Vec4};
-use rend3::types::{Camera, Handedness, MeshBuilder, Object, ObjectMeshKind};
-use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner, Threshold};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph},
+    types::{Camera, Handedness, MeshBuilder, Object, ObjectMeshKind},
+};
+use rend3_routine::base::{BaseRenderGraphInputs, OutputRenderTarget};
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestGraph, TestRunner, Threshold};
 use wgpu::FrontFace;
 
 #[test_attr]
@@ -26,6 +30,52 @@ pub async fn empty() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The base render graph, built by the test instead of the runner.
+struct ClearGraph;
+
+impl TestGraph for ClearGraph {
+    fn add_to_graph<'node>(
+        &'node self,
+        runner: &'node TestRunner,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        target: OutputRenderTarget,
+    ) {
+        runner.base_rendergraph.add_to_graph(
+            graph,
+            BaseRenderGraphInputs { eval_output, routines: runner.base_routines(), target },
+            TestRunner::base_settings(),
+        );
+    }
+}
+
+#[test_attr]
+pub async fn empty_custom_graph() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(Camera {
+        projection: rend3::types::CameraProjection::Raw(Mat4::IDENTITY),
+        view: Mat4::IDENTITY,
+    });
+
+    runner
+        .render_and_compare_with(
+            FrameRenderSettings::new(),
+            &ClearGraph,
+            "tests/results/simple/empty.png",
+            Threshold::Max(0.0),
+        )
+        .await
+        .context("Image Comparison Failed")?;
+
+    Ok(())
+}
+
 #[test_attr]
 pub async fn triangle() -> anyhow::Result<()> {
     let tests = [
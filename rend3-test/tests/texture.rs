@@ -0,0 +1,58 @@
+use anyhow::Context;
+use glam::UVec2;
+use rend3::types::{Handedness, MipmapCount, MipmapSource, Texture, TextureFormat};
+use rend3_test::{no_gpu_return, test_attr};
+use wgpu::{BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer, ImageDataLayout};
+
+/// Ensure that an update in the frame a texture is added isn't overwritten by
+/// its generated mipmaps.
+#[test_attr]
+pub async fn update_after_add() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let renderer = rend3::Renderer::new(iad, Handedness::Left, None).unwrap();
+
+    let size = 64;
+    let texture = renderer.add_texture_2d(Texture {
+        label: None,
+        data: [255, 0, 0, 255].repeat(size * size),
+        format: TextureFormat::Rgba8Unorm,
+        size: UVec2::splat(size as u32),
+        mip_count: MipmapCount::Maximum,
+        mip_source: MipmapSource::Generated,
+    })?;
+    let green = [0, 255, 0, 255].repeat(size * size);
+    renderer.update_texture_2d(&texture, 0, UVec2::ZERO, UVec2::splat(size as u32), green.clone());
+
+    renderer.swap_instruction_buffers();
+    let mut eval_output = renderer.evaluate_instructions();
+
+    let buffer = renderer.device.create_buffer(&BufferDescriptor {
+        label: Some("Test output buffer"),
+        size: (size * size * 4) as u64,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder =
+        renderer.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Test output encoder") });
+    encoder.copy_texture_to_buffer(
+        renderer.data_core.lock().d2_texture_manager.get_internal(texture.get_raw()).texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout { offset: 0, bytes_per_row: Some(size as u32 * 4), rows_per_image: None },
+        },
+        Extent3d { width: size as u32, height: size as u32, depth_or_array_layers: 1 },
+    );
+    eval_output.cmd_bufs.push(encoder.finish());
+    let submit_index = renderer.queue.submit(eval_output.cmd_bufs.drain(..));
+
+    let (sender, receiver) = flume::bounded(1);
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |_| sender.send(()).unwrap());
+    renderer.device.poll(wgpu::Maintain::WaitForSubmissionIndex(submit_index));
+    receiver.recv_async().await.context("Failed to recieve message from map_async")?;
+
+    assert!(*buffer.slice(..).get_mapped_range() == green[..]);
+
+    Ok(())
+}
@@ -13,6 +13,7 @@ pub struct VertexAttributeId {
     default_value: Option<&'static str>,
     name: &'static str,
     metadata: &'static VertexFormatMetadata,
+    packing: Option<VertexPacking>,
 }
 
 impl PartialEq for VertexAttributeId {
@@ -35,16 +36,39 @@ impl VertexAttributeId {
     pub fn default_value(&self) -> Option<&'static str> {
         self.default_value
     }
+
+    /// How meshes asking for packed attributes store this attribute.
+    pub fn packing(&self) -> Option<VertexPacking> {
+        self.packing
+    }
 }
 
 static VERTEX_ATTRIBUTE_INDEX_ALLOCATOR: AtomicUsize = AtomicUsize::new(0);
 
+/// A per-vertex attribute of a mesh, identified by its name.
+///
+/// Besides the builtin `VERTEX_ATTRIBUTE_*` attributes, any attribute can be
+/// declared as a static and attached with
+/// [`MeshBuilder::with_attribute`](crate::MeshBuilder::with_attribute):
+///
+/// ```
+/// # use rend3_types::VertexAttribute;
+/// pub static VERTEX_ATTRIBUTE_WIND_WEIGHT: VertexAttribute<f32> = VertexAttribute::new("wind_weight", Some("0.0"));
+/// ```
+///
+/// Materials list the attributes they read in
+/// [`Material::supported_attributes`](crate::Material::supported_attributes),
+/// and their vertex shaders fetch them by name with the `vertex_fetch`
+/// helper of the shader preprocessor. The name must therefore be a valid WGSL
+/// identifier. The default value is a WGSL expression used when an optional
+/// attribute is missing from a mesh.
 pub struct VertexAttribute<T>
 where
     T: VertexFormat,
 {
     name: &'static str,
     default_value: Option<&'static str>,
+    packing: Option<VertexPacking>,
     id: OnceCell<VertexAttributeId>,
     _phantom: PhantomData<T>,
 }
@@ -53,7 +77,7 @@ where
     T: VertexFormat,
 {
     pub const fn new(name: &'static str, default_value: Option<&'static str>) -> Self {
-        Self { name, default_value, id: OnceCell::new(), _phantom: PhantomData }
+        Self { name, default_value, packing: None, id: OnceCell::new(), _phantom: PhantomData }
     }
 
     pub fn name(&self) -> &'static str {
@@ -66,10 +90,28 @@ where
             default_value: self.default_value,
             inner: VERTEX_ATTRIBUTE_INDEX_ALLOCATOR.fetch_add(1, Ordering::Relaxed),
             metadata: &T::METADATA,
+            packing: self.packing,
         })
     }
 }
 
+impl VertexAttribute<glam::Vec3> {
+    /// Lets meshes store this attribute, which must hold unit vectors, with
+    /// [`VertexPacking::Octahedral`].
+    pub const fn with_octahedral_packing(mut self) -> Self {
+        self.packing = Some(VertexPacking::Octahedral);
+        self
+    }
+}
+
+impl VertexAttribute<glam::Vec2> {
+    /// Lets meshes store this attribute with [`VertexPacking::Half`].
+    pub const fn with_half_packing(mut self) -> Self {
+        self.packing = Some(VertexPacking::Half);
+        self
+    }
+}
+
 impl<T> Deref for VertexAttribute<T>
 where
     T: VertexFormat,
@@ -92,8 +134,119 @@ pub trait VertexFormat: Pod + Send + Sync + 'static {
     const METADATA: VertexFormatMetadata;
 }
 
+/// Compressed storage of an attribute in the mesh buffer, used by meshes
+/// built with [`MeshBuilder::with_packed_attributes`](crate::MeshBuilder::with_packed_attributes).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VertexPacking {
+    /// Unit `Vec3`s stored as two 16-bit snorm octahedral coordinates, 4 bytes
+    /// instead of 12.
+    Octahedral,
+    /// `Vec2`s stored as two half precision floats, 4 bytes instead of 8.
+    Half,
+}
+
+impl VertexPacking {
+    /// Metadata of the packed format. The shader type is the same as the
+    /// unpacked one.
+    pub fn metadata(self) -> &'static VertexFormatMetadata {
+        match self {
+            Self::Octahedral => &VertexFormatMetadata {
+                size: 4,
+                shader_extract_fn: "extract_attribute_vec3_f32_octahedral",
+                shader_type: "vec3<f32>",
+            },
+            Self::Half => &VertexFormatMetadata {
+                size: 4,
+                shader_extract_fn: "extract_attribute_vec2_f32_half",
+                shader_type: "vec2<f32>",
+            },
+        }
+    }
+
+    /// Packs the bytes of the unpacked attribute.
+    pub fn pack(self, data: &[u8]) -> Vec<u8> {
+        let floats = data.chunks_exact(4).map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()));
+        let packed: Vec<u32> = match self {
+            Self::Octahedral => {
+                let floats: Vec<f32> = floats.collect();
+                floats
+                    .chunks_exact(3)
+                    .map(|v| {
+                        let [x, y] = octahedral_encode(glam::Vec3::from_slice(v));
+                        pack_snorm16(x) | pack_snorm16(y) << 16
+                    })
+                    .collect()
+            }
+            Self::Half => {
+                let halves: Vec<u32> = floats.map(|v| f32_to_f16(v) as u32).collect();
+                halves.chunks_exact(2).map(|v| v[0] | v[1] << 16).collect()
+            }
+        };
+        bytemuck::cast_slice(&packed).to_vec()
+    }
+}
+
+fn octahedral_encode(v: glam::Vec3) -> [f32; 2] {
+    let sum = v.x.abs() + v.y.abs() + v.z.abs();
+    if sum == 0.0 {
+        return [0.0; 2];
+    }
+    let n = v / sum;
+    if n.z >= 0.0 {
+        [n.x, n.y]
+    } else {
+        [(1.0 - n.y.abs()) * n.x.signum(), (1.0 - n.x.abs()) * n.y.signum()]
+    }
+}
+
+fn pack_snorm16(v: f32) -> u32 {
+    (v.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16 as u16 as u32
+}
+
+/// Converts to the bits of a half precision float, rounding to nearest.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if exponent == 0xFF {
+        // Infinity or NaN.
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1F {
+        return sign | 0x7C00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small to represent.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    // A carry out of the mantissa correctly rounds up the exponent.
+    let round = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
 // TODO: More formats
 
+impl VertexFormat for f32 {
+    const METADATA: VertexFormatMetadata =
+        VertexFormatMetadata { size: 4, shader_extract_fn: "extract_attribute_f32", shader_type: "f32" };
+}
+
+impl VertexFormat for u32 {
+    const METADATA: VertexFormatMetadata =
+        VertexFormatMetadata { size: 4, shader_extract_fn: "extract_attribute_u32", shader_type: "u32" };
+}
+
 impl VertexFormat for glam::Vec2 {
     const METADATA: VertexFormatMetadata =
         VertexFormatMetadata { size: 8, shader_extract_fn: "extract_attribute_vec2_f32", shader_type: "vec2<f32>" };
@@ -109,6 +262,11 @@ impl VertexFormat for glam::Vec4 {
         VertexFormatMetadata { size: 16, shader_extract_fn: "extract_attribute_vec4_f32", shader_type: "vec4<f32>" };
 }
 
+impl VertexFormat for glam::UVec4 {
+    const METADATA: VertexFormatMetadata =
+        VertexFormatMetadata { size: 16, shader_extract_fn: "extract_attribute_vec4_u32", shader_type: "vec4<u32>" };
+}
+
 impl VertexFormat for [u16; 4] {
     const METADATA: VertexFormatMetadata =
         VertexFormatMetadata { size: 8, shader_extract_fn: "extract_attribute_vec4_u16", shader_type: "vec4<u32>" };
@@ -116,19 +274,21 @@ impl VertexFormat for [u16; 4] {
 
 impl VertexFormat for [u8; 4] {
     const METADATA: VertexFormatMetadata = VertexFormatMetadata {
-        size: 8,
+        size: 4,
         shader_extract_fn: "extract_attribute_vec4_u8_unorm",
         shader_type: "vec4<f32>",
     };
 }
 
 pub static VERTEX_ATTRIBUTE_POSITION: VertexAttribute<glam::Vec3> = VertexAttribute::new("position", None);
-pub static VERTEX_ATTRIBUTE_NORMAL: VertexAttribute<glam::Vec3> = VertexAttribute::new("normal", None);
-pub static VERTEX_ATTRIBUTE_TANGENT: VertexAttribute<glam::Vec3> = VertexAttribute::new("tangent", None);
+pub static VERTEX_ATTRIBUTE_NORMAL: VertexAttribute<glam::Vec3> =
+    VertexAttribute::new("normal", None).with_octahedral_packing();
+pub static VERTEX_ATTRIBUTE_TANGENT: VertexAttribute<glam::Vec3> =
+    VertexAttribute::new("tangent", None).with_octahedral_packing();
 pub static VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0: VertexAttribute<glam::Vec2> =
-    VertexAttribute::new("texture_coords_0", None);
+    VertexAttribute::new("texture_coords_0", None).with_half_packing();
 pub static VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1: VertexAttribute<glam::Vec2> =
-    VertexAttribute::new("texture_coords_1", None);
+    VertexAttribute::new("texture_coords_1", None).with_half_packing();
 pub static VERTEX_ATTRIBUTE_COLOR_0: VertexAttribute<[u8; 4]> = VertexAttribute::new("color_0", Some("vec4<f32>(1.0)"));
 pub static VERTEX_ATTRIBUTE_COLOR_1: VertexAttribute<[u8; 4]> = VertexAttribute::new("color_1", Some("vec4<f32>(1.0)"));
 pub static VERTEX_ATTRIBUTE_JOINT_INDICES: VertexAttribute<[u16; 4]> = VertexAttribute::new("joint_indices", None);
@@ -21,6 +21,7 @@ use bytemuck::Zeroable;
 /// Reexport of the glam version rend3 is using.
 pub use glam;
 use glam::{Mat4, UVec2, Vec2, Vec3, Vec3A, Vec4};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 mod attribute;
@@ -158,6 +159,9 @@ pub struct Texture2DTag;
 /// Tag type for differentiating TextureCubes on the type level.
 #[doc(hidden)]
 pub struct TextureCubeTag;
+/// Tag type for differentiating TextureCubeArrays on the type level.
+#[doc(hidden)]
+pub struct TextureCubeArrayTag;
 /// Tag type for differentiating Materials on the type level.
 #[doc(hidden)]
 pub struct MaterialTag;
@@ -171,6 +175,8 @@ pub type MeshHandle = ResourceHandle<Mesh>;
 pub type Texture2DHandle = ResourceHandle<Texture2DTag>;
 /// Refcounted handle to a TextureCube
 pub type TextureCubeHandle = ResourceHandle<TextureCubeTag>;
+/// Refcounted handle to a TextureCubeArray
+pub type TextureCubeArrayHandle = ResourceHandle<TextureCubeArrayTag>;
 /// Refcounted handle to a Material
 pub type MaterialHandle = ResourceHandle<MaterialTag>;
 /// Refcounted handle to an Object
@@ -179,6 +185,8 @@ pub type ObjectHandle = ResourceHandle<Object>;
 pub type DirectionalLightHandle = ResourceHandle<DirectionalLight>;
 /// Refcounted handle to a PointLight
 pub type PointLightHandle = ResourceHandle<PointLight>;
+/// Refcounted handle to a ShadowProjector
+pub type ShadowProjectorHandle = ResourceHandle<ShadowProjector>;
 /// Refcounted handle to a Skeleton
 pub type SkeletonHandle = ResourceHandle<Skeleton>;
 /// Refcounted handle to an instance of GraphData with the type erased
@@ -204,6 +212,8 @@ pub type RawMeshHandle = RawResourceHandle<Mesh>;
 pub type RawTexture2DHandle = RawResourceHandle<Texture2DTag>;
 /// Internal non-owning handle to a TextureCube
 pub type RawTextureCubeHandle = RawResourceHandle<TextureCubeTag>;
+/// Internal non-owning handle to a TextureCubeArray
+pub type RawTextureCubeArrayHandle = RawResourceHandle<TextureCubeArrayTag>;
 /// Internal non-owning handle to a Material
 pub type RawMaterialHandle = RawResourceHandle<MaterialTag>;
 /// Internal non-owning handle to an Object
@@ -212,6 +222,8 @@ pub type RawObjectHandle = RawResourceHandle<Object>;
 pub type RawDirectionalLightHandle = RawResourceHandle<DirectionalLight>;
 /// Internal non-owning handle to a PointLight
 pub type RawPointLightHandle = RawResourceHandle<PointLight>;
+/// Internal non-owning handle to a ShadowProjector
+pub type RawShadowProjectorHandle = RawResourceHandle<ShadowProjector>;
 /// Internal non-owning handle to a Skeleton
 pub type RawSkeletonHandle = RawResourceHandle<Skeleton>;
 /// Internal non-owning handle to an instance of GraphData with the type erased
@@ -250,7 +262,7 @@ macro_rules! changeable_struct {
 // WGPU REEXPORTS
 #[doc(inline)]
 pub use wgt::{
-    AstcBlock, AstcChannel, Backend, Backends, Color, DeviceType, PresentMode, TextureFormat,
+    AstcBlock, AstcChannel, Backend, Backends, Color, DeviceType, IndexFormat, PresentMode, TextureFormat,
     TextureFormatFeatureFlags, TextureUsages,
 };
 
@@ -263,6 +275,12 @@ pub use wgt::{
 pub const MAX_VERTEX_COUNT: u32 = (1 << 24) - 1;
 /// The maximum amount of indices any one object can have.
 pub const MAX_INDEX_COUNT: u32 = u32::MAX;
+/// The maximum amount of vertices a mesh with [`IndexFormat::Uint16`] indices
+/// can have.
+pub const MAX_UINT16_VERTEX_COUNT: u32 = u16::MAX as u32 + 1;
+/// Bit set in the attribute offsets given to shaders when the attribute is
+/// stored with its [`VertexPacking`]. This limits the mesh buffer to 2GiB.
+pub const PACKED_VERTEX_ATTRIBUTE_BIT: u32 = 1 << 31;
 
 /// Error returned from mesh validation.
 #[derive(Debug, Error)]
@@ -279,6 +297,17 @@ pub enum MeshValidationError {
         "Index at position {index} has the value {value} which is out of bounds for vertex buffers of {max} length"
     )]
     IndexOutOfBounds { index: usize, value: u32, max: u32 },
+    #[error("Level of detail {lod} has {count} indices which is not a multiple of three")]
+    LodIndexCountNotMultipleOfThree { lod: usize, count: usize },
+    #[error(
+        "Index at position {index} of level of detail {lod} has the value {value} which is out of bounds for vertex \
+         buffers of {max} length"
+    )]
+    LodIndexOutOfBounds { lod: usize, index: usize, value: u32, max: u32 },
+    #[error("Mesh has {count} vertices, more than 16-bit indices can address ({MAX_UINT16_VERTEX_COUNT})")]
+    ExceededUint16VertexCount { count: usize },
+    #[error("Mesh has more than one vertex attribute named {name:?}")]
+    DuplicateAttribute { name: &'static str },
 }
 
 #[derive(Debug)]
@@ -287,15 +316,24 @@ pub struct StoredVertexAttributeData {
     data: WasmVecAny,
     ptr: *const u8,
     bytes: u64,
+    clone_fn: fn(&Self) -> Self,
 }
 impl StoredVertexAttributeData {
     pub fn new<T>(attribute: &'static VertexAttribute<T>, data: Vec<T>) -> Self
     where
         T: VertexFormat,
     {
+        Self::from_id(attribute.id(), data)
+    }
+
+    fn from_id<T: VertexFormat>(id: &'static VertexAttributeId, data: Vec<T>) -> Self {
         let bytes = (data.len() * size_of::<T>()) as u64;
         let ptr = data.as_ptr() as *const u8;
-        Self { id: attribute.id(), data: WasmVecAny::from(data), ptr, bytes }
+        Self { id, data: WasmVecAny::from(data), ptr, bytes, clone_fn: Self::clone_typed::<T> }
+    }
+
+    fn clone_typed<T: VertexFormat>(&self) -> Self {
+        Self::from_id(self.id, self.data.downcast_slice::<T>().unwrap().to_vec())
     }
 
     pub fn id(&self) -> &'static VertexAttributeId {
@@ -328,6 +366,12 @@ impl StoredVertexAttributeData {
     }
 }
 
+impl Clone for StoredVertexAttributeData {
+    fn clone(&self) -> Self {
+        (self.clone_fn)(self)
+    }
+}
+
 unsafe impl Send for StoredVertexAttributeData {}
 unsafe impl Sync for StoredVertexAttributeData {}
 
@@ -339,11 +383,16 @@ pub struct MeshBuilder {
     vertex_count: usize,
 
     indices: Option<Vec<u32>>,
+    lods: Vec<Vec<u32>>,
+    index_format: Option<IndexFormat>,
+    packed_attributes: bool,
     without_validation: bool,
 
     handedness: Handedness,
     flip_winding_order: bool,
     double_sided: bool,
+
+    label: Option<String>,
 }
 impl MeshBuilder {
     /// Create a new [`MeshBuilder`] with a given set of positions.
@@ -358,11 +407,24 @@ impl MeshBuilder {
         }
     }
 
+    /// Add the values of any vertex attribute, including ones declared
+    /// outside of rend3, to the given mesh.
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the length is different from the position buffer length.
     pub fn with_attribute<T: VertexFormat>(mut self, attribute: &'static VertexAttribute<T>, values: Vec<T>) -> Self {
         self.vertex_attributes.push(StoredVertexAttributeData::new(attribute, values));
         self
     }
 
+    /// Sets the label of the mesh, shown in graphics debuggers on the draws
+    /// of the objects using it.
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
     /// Add vertex normals to the given mesh.
     ///
     /// # Panic
@@ -436,6 +498,27 @@ impl MeshBuilder {
         self
     }
 
+    /// Add a lower level of detail, drawing the same vertices with fewer
+    /// triangles. Levels are added from most to least detailed, after the
+    /// mesh itself, which is level 0.
+    ///
+    /// Shadow passes draw lower levels, see
+    /// [`ObjectShadowSettings::lod_bias`].
+    pub fn with_lod(mut self, indices: Vec<u32>) -> Self {
+        self.lods.push(indices);
+        self
+    }
+
+    /// Store the indices of the mesh on the GPU in the given format.
+    ///
+    /// Defaults to [`IndexFormat::Uint16`] for meshes with at most
+    /// [`MAX_UINT16_VERTEX_COUNT`] vertices, and [`IndexFormat::Uint32`]
+    /// otherwise.
+    pub fn with_index_format(mut self, index_format: IndexFormat) -> Self {
+        self.index_format = Some(index_format);
+        self
+    }
+
     /// Flip the winding order
     ///
     /// See [`Mesh::flip_winding_order`] for more information.
@@ -452,6 +535,16 @@ impl MeshBuilder {
         self
     }
 
+    /// Store the attributes that support it in a packed format, such as
+    /// octahedral normals and tangents and half precision texture
+    /// coordinates, roughly halving the memory of the mesh.
+    ///
+    /// Skinned meshes are always stored unpacked.
+    pub fn with_packed_attributes(mut self) -> Self {
+        self.packed_attributes = true;
+        self
+    }
+
     /// Doesn't run validation on the mesh.
     ///
     /// # Safety
@@ -463,6 +556,7 @@ impl MeshBuilder {
     /// - All indexes are in bounds for the given vertex arrays.
     /// - There is a non-zero count of indices.
     /// - There is a multiple-of-three count of indices.
+    /// - The same is true for the indices of every level of detail.
     pub unsafe fn without_validation(mut self) -> Self {
         self.without_validation = true;
         self
@@ -479,6 +573,10 @@ impl MeshBuilder {
             attributes: self.vertex_attributes,
             vertex_count: self.vertex_count,
             indices: self.indices.unwrap_or_else(|| (0..self.vertex_count as u32).collect()),
+            lods: self.lods,
+            index_format: self.index_format.unwrap_or_else(|| Mesh::smallest_index_format(self.vertex_count)),
+            packed_attributes: self.packed_attributes,
+            label: self.label,
         };
 
         if self.double_sided {
@@ -520,25 +618,48 @@ impl MeshBuilder {
 ///
 /// These can be annoying to construct, so use the [`MeshBuilder`] to make it
 /// easier.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Mesh {
     pub attributes: Vec<StoredVertexAttributeData>,
     pub vertex_count: usize,
 
     pub indices: Vec<u32>,
+    /// Indices of the lower levels of detail, see [`MeshBuilder::with_lod`].
+    pub lods: Vec<Vec<u32>>,
+    /// Format of the indices on the GPU. [`IndexFormat::Uint16`] halves their
+    /// size, but only addresses [`MAX_UINT16_VERTEX_COUNT`] vertices.
+    pub index_format: IndexFormat,
+    /// Whether to store the attributes that support it packed, see
+    /// [`MeshBuilder::with_packed_attributes`].
+    pub packed_attributes: bool,
+    /// Label shown in graphics debuggers, see [`MeshBuilder::with_label`].
+    pub label: Option<String>,
 }
 
 impl Mesh {
+    /// The smallest index format able to address the given count of vertices.
+    pub fn smallest_index_format(vertex_count: usize) -> IndexFormat {
+        match vertex_count <= MAX_UINT16_VERTEX_COUNT as usize {
+            true => IndexFormat::Uint16,
+            false => IndexFormat::Uint32,
+        }
+    }
+
     /// Validates that all vertex attributes have the same length.
     pub fn validate(&self) -> Result<(), MeshValidationError> {
         let position_length = self.vertex_count;
         let indices_length = self.indices.len();
+        let total_indices_length = indices_length + self.lods.iter().map(Vec::len).sum::<usize>();
 
         if position_length > MAX_VERTEX_COUNT as usize {
             return Err(MeshValidationError::ExceededMaxVertexCount { count: position_length });
         }
 
-        for attribute in &self.attributes {
+        for (idx, attribute) in self.attributes.iter().enumerate() {
+            if self.attributes[..idx].iter().any(|other| other.id.name() == attribute.id.name()) {
+                return Err(MeshValidationError::DuplicateAttribute { name: attribute.id.name() });
+            }
+
             let attribute_len = attribute.data.len();
             if attribute_len != position_length {
                 return Err(MeshValidationError::MismatchedVertexCount {
@@ -553,8 +674,12 @@ impl Mesh {
             return Err(MeshValidationError::IndexCountNotMultipleOfThree { count: indices_length });
         }
 
-        if indices_length >= MAX_INDEX_COUNT as usize {
-            return Err(MeshValidationError::ExceededMaxIndexCount { count: indices_length });
+        if total_indices_length >= MAX_INDEX_COUNT as usize {
+            return Err(MeshValidationError::ExceededMaxIndexCount { count: total_indices_length });
+        }
+
+        if self.index_format == IndexFormat::Uint16 && position_length > MAX_UINT16_VERTEX_COUNT as usize {
+            return Err(MeshValidationError::ExceededUint16VertexCount { count: position_length });
         }
 
         for (index, &value) in self.indices.iter().enumerate() {
@@ -563,9 +688,30 @@ impl Mesh {
             }
         }
 
+        for (lod, indices) in (1..).zip(&self.lods) {
+            if indices.len() % 3 != 0 {
+                return Err(MeshValidationError::LodIndexCountNotMultipleOfThree { lod, count: indices.len() });
+            }
+            for (index, &value) in indices.iter().enumerate() {
+                if value as usize >= position_length {
+                    return Err(MeshValidationError::LodIndexOutOfBounds {
+                        lod,
+                        index,
+                        value,
+                        max: position_length as u32,
+                    });
+                }
+            }
+        }
+
         Ok(())
     }
 
+    /// Returns the values of the given attribute, if the mesh has it.
+    pub fn attribute<T: VertexFormat>(&self, attribute: &'static VertexAttribute<T>) -> Option<&[T]> {
+        self.attributes.iter().find_map(|stored| stored.typed_data(attribute))
+    }
+
     /// Returns the index in to the attribute array for a given attribute. If
     /// there is no such attribute, returns None.
     pub fn find_attribute_index(&self, desired_attribute: &'static VertexAttributeId) -> Option<usize> {
@@ -838,34 +984,9 @@ impl Mesh {
 
     /// Converts the mesh from single sided to double sided.
     pub fn double_side(&mut self) {
-        let starting_len = self.indices.len();
-        // This floors, so the following unsafe is in-bounds.
-        let primative_count = starting_len / 3;
-        // reserve additional space -- this "doubles" the capasity
-        self.indices.reserve(starting_len);
-
-        let ptr = self.indices.as_mut_ptr();
-
-        #[allow(clippy::identity_op)]
-        unsafe {
-            // Iterate in reverse as to not stomp on ourself
-            for prim in (0..primative_count).rev() {
-                let i1 = *ptr.add(prim * 3 + 0);
-                let i2 = *ptr.add(prim * 3 + 1);
-                let i3 = *ptr.add(prim * 3 + 2);
-
-                // One triangle forward.
-                ptr.add(prim * 6 + 0).write(i1);
-                ptr.add(prim * 6 + 1).write(i2);
-                ptr.add(prim * 6 + 2).write(i3);
-
-                // One triangle reverse.
-                ptr.add(prim * 6 + 3).write(i3);
-                ptr.add(prim * 6 + 4).write(i2);
-                ptr.add(prim * 6 + 5).write(i1);
-            }
-
-            self.indices.set_len(starting_len * 2);
+        double_side_indices(&mut self.indices);
+        for lod in &mut self.lods {
+            double_side_indices(lod);
         }
     }
 
@@ -877,17 +998,52 @@ impl Mesh {
     /// system. This will also not change the vertex normals. Calling
     /// [`Mesh::calculate_normals`] is advised after calling this function.
     pub fn flip_winding_order(&mut self) {
-        for indices in self.indices.chunks_exact_mut(3) {
-            if let [left, _, right] = indices {
-                mem::swap(left, right);
-            } else {
-                // SAFETY: chunks_exact(3) guarantees us 3 value long slices
-                unsafe { std::hint::unreachable_unchecked() }
+        for indices in std::iter::once(&mut self.indices).chain(&mut self.lods) {
+            for indices in indices.chunks_exact_mut(3) {
+                if let [left, _, right] = indices {
+                    mem::swap(left, right);
+                } else {
+                    // SAFETY: chunks_exact(3) guarantees us 3 value long slices
+                    unsafe { std::hint::unreachable_unchecked() }
+                }
             }
         }
     }
 }
 
+/// Duplicates every triangle with the opposite winding order.
+fn double_side_indices(indices: &mut Vec<u32>) {
+    let starting_len = indices.len();
+    // This floors, so the following unsafe is in-bounds.
+    let primative_count = starting_len / 3;
+    // reserve additional space -- this "doubles" the capasity
+    indices.reserve(starting_len);
+
+    let ptr = indices.as_mut_ptr();
+
+    #[allow(clippy::identity_op)]
+    unsafe {
+        // Iterate in reverse as to not stomp on ourself
+        for prim in (0..primative_count).rev() {
+            let i1 = *ptr.add(prim * 3 + 0);
+            let i2 = *ptr.add(prim * 3 + 1);
+            let i3 = *ptr.add(prim * 3 + 2);
+
+            // One triangle forward.
+            ptr.add(prim * 6 + 0).write(i1);
+            ptr.add(prim * 6 + 1).write(i2);
+            ptr.add(prim * 6 + 2).write(i3);
+
+            // One triangle reverse.
+            ptr.add(prim * 6 + 3).write(i3);
+            ptr.add(prim * 6 + 4).write(i2);
+            ptr.add(prim * 6 + 5).write(i1);
+        }
+
+        indices.set_len(starting_len * 2);
+    }
+}
+
 /// The count of mipmap levels a texture should have.
 #[derive(Debug, Clone)]
 pub enum MipmapCount {
@@ -1013,6 +1169,20 @@ impl<const C: usize, T> MaterialArray<T> for [T; C] {
     }
 }
 
+/// A value of a material, as listed by [`Material::parameters`] for tools
+/// inspecting the renderer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MaterialParameter {
+    Bool(bool),
+    U32(u32),
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    /// Anything else, formatted for display.
+    Other(String),
+}
+
 /// Interface that all materials must use.
 ///
 /// The material will provide a set of textures, and a pile of bytes. It will
@@ -1050,15 +1220,38 @@ pub trait Material: WasmNotSend + WasmNotSync + 'static {
     /// How objects with this material should be sorted.
     fn sorting(&self) -> Sorting;
 
+    /// Key of objects with this material while they fade, with an opacity
+    /// below 1. Materials whose pipelines can't all fade objects return the
+    /// key of one that can, like an opaque material switching to its cutout
+    /// pipelines. Defaults to [`Self::key`].
+    fn faded_key(&self, mode: ObjectFadeMode) -> u64 {
+        let _ = mode;
+        self.key()
+    }
+
+    /// How objects with this material should be sorted while they fade. Must
+    /// match [`Self::faded_key`]. Defaults to [`Self::sorting`].
+    fn faded_sorting(&self, mode: ObjectFadeMode) -> Sorting {
+        let _ = mode;
+        self.sorting()
+    }
+
     /// The array of textures that should be bound. Rend3 supports up to 32.
     fn to_textures(&self) -> Self::TextureArrayType;
 
     /// Fill up the given slice with data. This can be whatever data the shader expects.
     fn to_data(&self) -> Self::DataType;
+
+    /// Named values of the material, for editors and debugging tools to show.
+    /// Textures are listed separately, from [`Self::to_textures`]. Defaults
+    /// to none.
+    fn parameters(&self) -> Vec<(&'static str, MaterialParameter)> {
+        Vec::new()
+    }
 }
 
 /// Source of a mesh for an object.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ObjectMeshKind {
     Animated(SkeletonHandle),
     Static(MeshHandle),
@@ -1073,8 +1266,236 @@ changeable_struct! {
     }
 }
 
+/// One copy of the mesh of an [`InstancedObject`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Instance {
+    /// Model -> World transform of the instance.
+    pub transform: Mat4,
+    /// Multiplied with the vertex color of the mesh. White by default.
+    pub color: Vec4,
+    /// Replaces the custom data of the object for this instance, see
+    /// `Renderer::set_object_custom_data`. Zero by default.
+    pub custom_data: Vec4,
+}
+
+impl Instance {
+    /// An instance with the given transform and the default color and custom
+    /// data.
+    pub fn new(transform: Mat4) -> Self {
+        Self { transform, color: Vec4::ONE, custom_data: Vec4::ZERO }
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new(Mat4::IDENTITY)
+    }
+}
+
+impl From<Mat4> for Instance {
+    fn from(transform: Mat4) -> Self {
+        Self::new(transform)
+    }
+}
+
+/// Many copies of the same mesh and material, drawn together.
+///
+/// The instances are stored in a single storage buffer and culled one at a
+/// time on the GPU, so the object only takes a single handle no matter how
+/// many instances it has.
+#[derive(Debug, Clone)]
+pub struct InstancedObject {
+    pub mesh_kind: ObjectMeshKind,
+    pub material: MaterialHandle,
+    pub instances: Vec<Instance>,
+}
+
+/// What an object is attached to, see `Renderer::set_object_parent`.
+#[derive(Debug, Clone)]
+pub enum ObjectParent {
+    /// The transform of the child is relative to the parent object.
+    Object(ObjectHandle),
+    /// The transform of the child is relative to the parent object, in the
+    /// space of the parent's mesh at its bind pose, and follows the given joint
+    /// of the parent's skeleton.
+    Joint { object: ObjectHandle, joint: usize },
+}
+
+/// How an object casts and receives shadows.
+///
+/// Large worlds can keep distant and small objects out of shadow maps, and
+/// draw shadows with less detailed meshes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ObjectShadowSettings {
+    /// The object is drawn into shadow maps.
+    pub casts_shadows: bool,
+    /// Shadows darken the object. Objects that don't receive shadows are still
+    /// lit by every light.
+    pub receives_shadows: bool,
+    /// The object is only drawn into shadow maps, not into any other view.
+    /// Lets a simple proxy mesh cast the shadow of a detailed object, which
+    /// itself doesn't cast shadows.
+    pub shadow_only: bool,
+    /// The object only casts shadows when the closest point of its bounding
+    /// sphere is at most this far from the camera.
+    pub max_distance: f32,
+    /// Bit `i` is set if the object casts shadows into shadow map `i` of the
+    /// frame. Shadow maps past the 32nd are always cast into.
+    pub shadow_map_mask: u32,
+    /// How many levels of detail lower than the main passes the shadow
+    /// passes draw the mesh at, see [`ObjectLod`]. Clamped to the least detailed level of the
+    /// mesh, see [`MeshBuilder::with_lod`].
+    pub lod_bias: u32,
+}
+
+impl Default for ObjectShadowSettings {
+    fn default() -> Self {
+        Self {
+            casts_shadows: true,
+            receives_shadows: true,
+            shadow_only: false,
+            max_distance: f32::INFINITY,
+            shadow_map_mask: u32::MAX,
+            lod_bias: 1,
+        }
+    }
+}
+
+impl ObjectShadowSettings {
+    /// Whether the object casts shadows into the given shadow map, at the
+    /// given distance of its bounding sphere from the camera.
+    pub fn casts_into(&self, shadow_map: usize, distance: f32) -> bool {
+        let in_mask = shadow_map >= 32 || self.shadow_map_mask & (1 << shadow_map) != 0;
+        self.casts_shadows && in_mask && distance <= self.max_distance
+    }
+}
+
+/// Where an object is drawn relative to other objects of the same pass.
+///
+/// Lets view models draw over the world, or layered transparent effects draw
+/// in a fixed order, without separate routines.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ObjectRenderOrder {
+    /// Objects with a lower key are drawn before objects with a higher key,
+    /// whatever their material's sorting.
+    pub key: i32,
+    /// World space distance added to the object's distance from the camera
+    /// when objects are sorted by distance, such as transparent objects.
+    pub sort_bias: f32,
+}
+
+/// How an object fades while its opacity is below 1, see
+/// `Renderer::set_object_fade_mode`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ObjectFadeMode {
+    /// Discards more pixels the lower the opacity, with a dither pattern.
+    /// The object still writes depth, casts shadows and doesn't need sorting,
+    /// which suits fading objects in as they spawn.
+    #[default]
+    Dither,
+    /// Blends the object over what is behind it, sorted back to front. The
+    /// object no longer writes depth or casts shadows while it fades, which
+    /// suits fading out objects hiding the camera's target.
+    Blend,
+}
+
+/// The level of detail an object is drawn with, see
+/// [`MeshBuilder::with_lod`].
+///
+/// Switching levels at once makes the object pop. With `fade` between 0 and
+/// 1, both `lod` and `previous` are drawn, with complementary dither
+/// patterns, so animating `fade` up to 1 over a few frames cross-fades them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ObjectLod {
+    /// Level of detail drawn, 0 being the mesh itself. Clamped to the least
+    /// detailed level of the mesh.
+    pub lod: u32,
+    /// Level of detail fading out while `lod` fades in.
+    pub previous: u32,
+    /// Weight of `lod` against `previous`, from 0 to 1.
+    pub fade: f32,
+}
+
+impl Default for ObjectLod {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl ObjectLod {
+    /// Draws only the given level of detail.
+    pub fn new(lod: u32) -> Self {
+        Self { lod, previous: lod, fade: 1.0 }
+    }
+
+    /// Whether both levels of detail are drawn.
+    pub fn is_cross_fading(&self) -> bool {
+        self.lod != self.previous && self.fade > 0.0 && self.fade < 1.0
+    }
+
+    /// The level of detail with the larger weight, drawn by passes that can't
+    /// cross-fade.
+    pub fn dominant(&self) -> u32 {
+        match self.fade < 0.5 {
+            true => self.previous,
+            false => self.lod,
+        }
+    }
+}
+
+/// Maps the joints of a target skeleton to the joints of a source skeleton
+/// with a different bone order, to play the source's animations on the
+/// target.
+///
+/// Remap the global joint transforms, then combine them with the target's
+/// inverse bind transforms using [`Skeleton::compute_joint_matrices`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JointRemap {
+    /// For each target joint, the index of the source joint driving it.
+    source_indices: Vec<Option<usize>>,
+}
+
+impl JointRemap {
+    /// Creates a remap from the source joint index of every target joint.
+    pub fn new(source_indices: Vec<Option<usize>>) -> Self {
+        Self { source_indices }
+    }
+
+    /// Matches joints by name. Target joints without a source joint of the
+    /// same name are not driven.
+    pub fn from_names<S: AsRef<str>, T: AsRef<str>>(source_names: &[S], target_names: &[T]) -> Self {
+        let source_indices = target_names
+            .iter()
+            .map(|target| source_names.iter().position(|source| source.as_ref() == target.as_ref()))
+            .collect();
+        Self { source_indices }
+    }
+
+    /// Number of joints in the target skeleton.
+    pub fn target_joint_count(&self) -> usize {
+        self.source_indices.len()
+    }
+
+    /// Source joint driving the given target joint.
+    pub fn source_index(&self, target_joint: usize) -> Option<usize> {
+        self.source_indices.get(target_joint).copied().flatten()
+    }
+
+    /// Reorders per-joint values of the source skeleton into the target's
+    /// order. Target joints that aren't driven, or are driven by joints past
+    /// the end of `source`, get the matching value of `fallback`, such as the
+    /// target's rest pose.
+    pub fn remap<T: Copy>(&self, source: &[T], fallback: &[T]) -> Vec<T> {
+        self.source_indices
+            .iter()
+            .enumerate()
+            .map(|(target, source_index)| source_index.and_then(|i| source.get(i)).copied().unwrap_or(fallback[target]))
+            .collect()
+    }
+}
+
 /// Describes how the camera should look at the scene.
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Camera {
     pub projection: CameraProjection,
     /// View matrix
@@ -1082,7 +1503,7 @@ pub struct Camera {
 }
 
 /// Describes how the world should be projected into the camera.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum CameraProjection {
     Orthographic {
         /// Size assumes the location is at the center of the camera area.
@@ -1105,10 +1526,12 @@ impl Default for CameraProjection {
 
 changeable_struct! {
     /// Describes how directional lights (sun lights) and their shadows should be processed.
+    #[derive(Serialize, Deserialize)]
     pub struct DirectionalLight <- DirectionalLightChange {
         /// Color of the light.
         pub color: Vec3,
-        /// Resolution of the shadow map cascades (in pix)
+        /// Resolution of the shadow map (in pix). Rounded up to a power of two,
+        /// and lowered if all shadow maps don't fit in the shadow atlas.
         pub resolution: u16,
         /// Constant multiplier for the light.
         pub intensity: f32,
@@ -1116,11 +1539,54 @@ changeable_struct! {
         pub direction: Vec3,
         /// Distance from the camera that shadows should be calculated.
         pub distance: f32,
+        /// Fraction of the shadow distance over which shadows fade out
+        /// towards its edge. 0 cuts shadows off at the edge.
+        pub shadow_fade: f32,
+    }
+}
+
+/// How the shadow map of a directional light is fit around the camera. The
+/// shadow map covers [`DirectionalLight::distance`] around the camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowFitting {
+    /// World space distance the shadow map is grown by on every side, so
+    /// casters just outside of it still cast shadows.
+    pub padding: f32,
+    /// Move the shadow map in whole texels as the camera moves, so shadow
+    /// edges don't shimmer.
+    pub texel_snapping: bool,
+    /// Shrink the shadow map to the bounding box of all objects where it's
+    /// smaller, and fit its depth range to them, for more detailed shadows in
+    /// small scenes.
+    pub clamp_to_scene: bool,
+}
+
+impl Default for ShadowFitting {
+    fn default() -> Self {
+        Self { padding: 0.0, texel_snapping: true, clamp_to_scene: false }
     }
 }
 
+/// A shadow map rendered from an arbitrary camera into the shadow atlas, for
+/// effects other than lights, like a top-down projector for blob shadows or
+/// a caustics projector.
+///
+/// Projectors don't light anything by themselves. Materials read them as
+/// projected textures through the `shadow_projectors` of the forward passes.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowProjector {
+    /// Camera the shadow map is rendered from. Orthographic projections cover
+    /// the box of the given size centered on the camera. Raw projections must
+    /// use reversed depth, like the built-in ones.
+    pub camera: Camera,
+    /// Resolution of the shadow map (in pix). Rounded up to a power of two,
+    /// and lowered if all shadow maps don't fit in the shadow atlas.
+    pub resolution: u16,
+}
+
 changeable_struct! {
     /// Describes how point lights and their shadows should be processed.
+    #[derive(Serialize, Deserialize)]
     pub struct PointLight <- PointLightChange {
         /// The position of the light in the world.
         pub position: Vec3,
@@ -12,6 +12,14 @@ struct Triangle {
     indices: TriangleIndices,
 }
 
+fn extract_attribute_f32(byte_base_offset: u32, vertex_index: u32) -> f32 {
+    return bitcast<f32>(vertex_buffer[byte_base_offset / 4u + vertex_index]);
+}
+
+fn extract_attribute_u32(byte_base_offset: u32, vertex_index: u32) -> u32 {
+    return vertex_buffer[byte_base_offset / 4u + vertex_index];
+}
+
 fn extract_attribute_vec2_f32(byte_base_offset: u32, vertex_index: u32) -> vec2<f32> {
     let first_element_idx = byte_base_offset / 4u + vertex_index * 2u;
     return vec2<f32>(
@@ -20,6 +28,10 @@ fn extract_attribute_vec2_f32(byte_base_offset: u32, vertex_index: u32) -> vec2<
     );
 }
 
+fn extract_attribute_vec2_f32_half(byte_base_offset: u32, vertex_index: u32) -> vec2<f32> {
+    return unpack2x16float(vertex_buffer[byte_base_offset / 4u + vertex_index]);
+}
+
 fn extract_attribute_vec3_f32(byte_base_offset: u32, vertex_index: u32) -> vec3<f32> {
     let first_element_idx = byte_base_offset / 4u + vertex_index * 3u;
     return vec3<f32>(
@@ -29,6 +41,15 @@ fn extract_attribute_vec3_f32(byte_base_offset: u32, vertex_index: u32) -> vec3<
     );
 }
 
+fn extract_attribute_vec3_f32_octahedral(byte_base_offset: u32, vertex_index: u32) -> vec3<f32> {
+    let encoded = unpack2x16snorm(vertex_buffer[byte_base_offset / 4u + vertex_index]);
+    var n = vec3<f32>(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    let fold = max(-n.z, 0.0);
+    n.x += select(fold, -fold, n.x >= 0.0);
+    n.y += select(fold, -fold, n.y >= 0.0);
+    return normalize(n);
+}
+
 fn extract_attribute_vec4_f32(byte_base_offset: u32, vertex_index: u32) -> vec4<f32> {
     let first_element_idx = byte_base_offset / 4u + vertex_index * 4u;
     return vec4<f32>(
@@ -39,6 +60,16 @@ fn extract_attribute_vec4_f32(byte_base_offset: u32, vertex_index: u32) -> vec4<
     );
 }
 
+fn extract_attribute_vec4_u32(byte_base_offset: u32, vertex_index: u32) -> vec4<u32> {
+    let first_element_idx = byte_base_offset / 4u + vertex_index * 4u;
+    return vec4<u32>(
+        vertex_buffer[first_element_idx],
+        vertex_buffer[first_element_idx + 1u],
+        vertex_buffer[first_element_idx + 2u],
+        vertex_buffer[first_element_idx + 3u],
+    );
+}
+
 fn extract_attribute_vec4_u16(byte_base_offset: u32, vertex_index: u32) -> vec4<u32> {
     let first_element_idx = byte_base_offset / 4u + vertex_index * 2u;
     let value_0 = vertex_buffer[first_element_idx];
@@ -9,10 +9,11 @@ use std::{
 
 use glam::UVec2;
 use wgpu::{
-    Buffer, CommandBuffer, CommandEncoder, CommandEncoderDescriptor, LoadOp, Operations, RenderPass,
-    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp, SurfaceTexture,
-    Texture, TextureView, TextureViewDescriptor,
+    Buffer, CommandBuffer, CommandEncoder, CommandEncoderDescriptor, Operations, RenderPass, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp, SurfaceTexture, Texture, TextureView,
+    TextureViewDescriptor,
 };
+use wgpu_profiler::{GpuProfilerQuery, ProfilerCommandRecorder};
 
 use super::ViewportRect;
 use crate::{
@@ -23,9 +24,13 @@ use crate::{
     },
     managers::{ShadowDesc, TextureManagerEvaluateOutput},
     util::typedefs::{FastHashMap, FastHashSet, RendererStatistics, SsoString},
-    Renderer,
+    DiagnosticKind, GpuScopeVerbosity, PassStats, Renderer, RendererDataCore,
 };
 
+/// Label of the GPU time of the nodes recorded into the prologue command
+/// buffer, see [`RenderGraphNodeBuilder::set_prologue`].
+pub const PROLOGUE_GROUP: &str = "Prologue";
+
 /// Result of evaluating all instructions.
 pub struct InstructionEvaluationOutput {
     pub cmd_bufs: Vec<CommandBuffer>,
@@ -77,6 +82,7 @@ pub struct RenderGraph<'node> {
     pub(super) imported_targets: Vec<&'node dyn AsTextureReference>,
     pub(super) data: Vec<DataContents>,
     pub(super) nodes: Vec<RenderGraphNode<'node>>,
+    pub(super) group: Option<SsoString>,
 }
 impl<'node> RenderGraph<'node> {
     pub fn new() -> Self {
@@ -85,20 +91,34 @@ impl<'node> RenderGraph<'node> {
             imported_targets: Vec::with_capacity(32),
             data: Vec::with_capacity(32),
             nodes: Vec::with_capacity(64),
+            group: None,
         }
     }
 
+    /// Puts the nodes added from now on in the group with the given label,
+    /// such as "Shadows", or in no group with `None`.
+    ///
+    /// GPU profilers and [`Renderer::last_frame_gpu_times`] show each run of
+    /// consecutive nodes in the same group as one scope containing the
+    /// scopes of the nodes. Nodes of different groups never share a
+    /// renderpass, so groups should wrap nodes that don't share one anyway.
+    pub fn set_group(&mut self, group: Option<&str>) {
+        self.group = group.map(SsoString::from);
+    }
+
     pub fn add_node<'a, S>(&'a mut self, label: S) -> RenderGraphNodeBuilder<'a, 'node>
     where
         SsoString: From<S>,
     {
         RenderGraphNodeBuilder {
             label: SsoString::from(label),
+            group: self.group.clone(),
             graph: self,
             inputs: Vec::with_capacity(16),
             outputs: Vec::with_capacity(16),
             references: Vec::with_capacity(16),
             rpass: None,
+            prologue: false,
         }
     }
 
@@ -166,6 +186,20 @@ impl<'node> RenderGraph<'node> {
     ) -> Option<RendererStatistics> {
         profiling::scope!("RenderGraph::execute");
 
+        // Nothing can be rendered with a lost device.
+        if renderer.is_device_lost() {
+            return None;
+        }
+
+        // Nothing can be rendered into a zero sized target, like the output of a minimized window. The frame still
+        // runs without any nodes, so uploads are submitted, and the stored render targets are kept for when rendering
+        // resumes.
+        let zero_sized = self.targets.iter().any(|desc| desc.resolution.x == 0 || desc.resolution.y == 0);
+        if zero_sized {
+            log::trace!("Skipping the nodes of a graph with zero sized render targets");
+            self.nodes.clear();
+        }
+
         // Because data handles have dependencies, we flatten the inputs and outputs ahead of time to simplify things.
         // We do it in place to save a bunch of allocations.
         for node in &mut self.nodes {
@@ -201,6 +235,10 @@ impl<'node> RenderGraph<'node> {
             pruned_node_list.reverse();
         }
 
+        if renderer.diagnostics.enabled() {
+            Self::check_unwritten_targets(&self.targets, renderer, &pruned_node_list);
+        }
+
         let mut resource_spans = FastHashMap::<_, ResourceSpan>::default();
         {
             profiling::scope!("Resource Span Analysis");
@@ -272,7 +310,9 @@ impl<'node> RenderGraph<'node> {
         let graph_texture_store = &mut data_core.graph_texture_store;
         // Mark all textures as unused, so the ones that are unused can be culled after
         // this pass.
-        graph_texture_store.mark_unused();
+        if !zero_sized {
+            graph_texture_store.mark_unused();
+        }
 
         // Stores the Texture while a node is using it
         let mut active_textures = FastHashMap::default();
@@ -355,7 +395,9 @@ impl<'node> RenderGraph<'node> {
 
         // All textures that were ever returned are marked as used, so anything in here
         // that wasn't ever returned, was unused throughout the whole graph.
-        graph_texture_store.remove_unused();
+        if !zero_sized {
+            graph_texture_store.remove_unused();
+        }
 
         // Iterate through all nodes and describe the node when they _end_
         let mut renderpass_ends = Vec::with_capacity(16);
@@ -367,14 +409,17 @@ impl<'node> RenderGraph<'node> {
                 // We always assume the first node is incompatible so the codepaths below are
                 // consistent.
                 let previous = match idx.checked_sub(1) {
-                    Some(prev) => pruned_node_list[prev].rpass.as_ref(),
+                    Some(prev) => &pruned_node_list[prev],
                     None => {
                         compatible.push(false);
                         continue;
                     }
                 };
 
-                compatible.push(RenderPassTargets::compatible(previous, node.rpass.as_ref()))
+                // Group scopes can't begin or end within a renderpass.
+                let same_group = previous.group == node.group;
+                compatible
+                    .push(same_group && RenderPassTargets::compatible(previous.rpass.as_ref(), node.rpass.as_ref()))
             }
 
             for (idx, &compatible) in compatible.iter().enumerate() {
@@ -386,24 +431,83 @@ impl<'node> RenderGraph<'node> {
             }
         }
 
+        // Nodes asking for the prologue are moved to its command buffer, unless they would then run before a node they
+        // depend on, or after a node that depends on their render targets staying as they were.
+        let in_prologue: Vec<bool> = {
+            let mut main_inputs = FastHashSet::default();
+            let mut main_outputs = FastHashSet::default();
+            let tracked = |resource: &&GraphSubResource| {
+                matches!(resource, GraphSubResource::Texture(_) | GraphSubResource::ImportedTexture(_))
+            };
+            pruned_node_list
+                .iter()
+                .map(|node| {
+                    let movable = node.prologue
+                        && node.rpass.is_none()
+                        && !node.inputs.iter().filter(tracked).any(|input| main_outputs.contains(input))
+                        && !node.outputs.iter().filter(tracked).any(|output| main_inputs.contains(output));
+                    if node.prologue && !movable {
+                        log::debug!("Node {} can't move to the prologue, running it in order", node.label);
+                    }
+                    if !movable {
+                        main_inputs.extend(node.inputs.iter().filter(tracked).copied());
+                        main_outputs.extend(node.outputs.iter().filter(tracked).copied());
+                    }
+                    movable
+                })
+                .collect()
+        };
+
         profiling::scope!("Run Nodes");
 
+        // Declared before the encoder, as the renderpasses borrow the query set.
+        let pipeline_stats = renderer
+            .pipeline_stats
+            .begin_frame(&renderer.device, pruned_node_list.iter().filter(|n| n.rpass.is_some()).count());
+
         let encoder_cell =
             UnsafeCell::new(renderer.device.create_command_encoder(&CommandEncoderDescriptor::default()));
+        let mut prologue_encoder = in_prologue.contains(&true).then(|| {
+            renderer.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("prologue encoder") })
+        });
+        let prologue_query = prologue_encoder
+            .as_mut()
+            .and_then(|encoder| begin_group_scope(renderer, data_core, encoder, PROLOGUE_GROUP));
         let rpass_temps_cell = UnsafeCell::new(RpassTemporaryPool::new());
 
         let mut next_rpass_idx = 0;
         let mut rpass = None;
 
+        let cpu_scopes = renderer.profiler_state.settings().cpu_scopes;
+
+        // The group of the previous node, with its profiler scope. Groups only
+        // change between renderpasses, so their scopes are on the encoder.
+        let mut group: Option<(SsoString, Option<GpuProfilerQuery>)> = None;
+
         // Iterate through all the nodes and actually execute them.
         for (idx, node) in pruned_node_list.into_iter().enumerate() {
+            let prologue = in_prologue[idx];
             if !compatible[idx] {
                 // SAFETY: this drops the renderpass, letting us into everything it was
                 // borrowing when we make the new renderpass.
                 rpass = None;
 
+                // Prologue nodes are in their own group.
+                if !prologue && group.as_ref().map(|(label, _)| label) != node.group.as_ref() {
+                    // SAFETY: There is no active renderpass to borrow this.
+                    let encoder = unsafe { &mut *encoder_cell.get() };
+                    if let Some((_, query)) = group.take() {
+                        end_group_scope(renderer, data_core, encoder, query);
+                    }
+                    group = node.group.as_ref().map(|label| {
+                        let query = begin_group_scope(renderer, data_core, encoder, label);
+                        (label.clone(), query)
+                    });
+                }
+
                 if let Some(ref desc) = node.rpass {
                     rpass = Some(Self::create_rpass_from_desc(
+                        &node.label,
                         desc,
                         // SAFETY: There are two things which borrow this encoder: the renderpass and the node's
                         // encoder reference. Both of these have died by this point.
@@ -426,8 +530,13 @@ impl<'node> RenderGraph<'node> {
                     data: &self.data,
                 };
 
-                let mut encoder_or_rpass = match rpass {
-                    Some(ref mut rpass) => {
+                if let (Some(ref mut rpass), Some(ref pipeline_stats)) = (&mut rpass, &pipeline_stats) {
+                    pipeline_stats.begin(rpass, &node.label);
+                }
+
+                let mut encoder_or_rpass = match (rpass.as_mut(), prologue_encoder.as_mut()) {
+                    (_, Some(encoder)) if prologue => RenderGraphEncoderOrPassInner::Encoder(encoder),
+                    (Some(rpass), _) => {
                         let rpass_desc = node.rpass.unwrap();
 
                         let viewport = rpass_desc
@@ -452,16 +561,26 @@ impl<'node> RenderGraph<'node> {
                     }
                     // SAFETY: There is no active renderpass to borrow this. This reference lasts for the duration of
                     // the call to exec.
-                    None => RenderGraphEncoderOrPassInner::Encoder(unsafe { &mut *encoder_cell.get() }),
+                    (None, _) => RenderGraphEncoderOrPassInner::Encoder(unsafe { &mut *encoder_cell.get() }),
+                };
+                let parent_query = match prologue {
+                    true => prologue_query.as_ref(),
+                    false => group.as_ref().and_then(|(_, query)| query.as_ref()),
                 };
 
-                profiling::scope!(&format!("Node: {}", node.label));
-
-                let profiler_query = data_core.profiler.try_lock().unwrap().begin_query(
-                    node.label,
+                let profiler_query = renderer.profiler_state.begin_nested_gpu_scope(
+                    &data_core.profiler,
+                    GpuScopeVerbosity::Nodes,
+                    &node.label,
+                    parent_query,
                     &mut encoder_or_rpass,
                     &renderer.device,
                 );
+                // Profiler scopes push a debug group with the label themselves.
+                let debug_group = profiler_query.is_none();
+                if debug_group {
+                    encoder_or_rpass.push_debug_group(&node.label);
+                }
 
                 let ctx = NodeExecutionContext {
                     renderer,
@@ -475,16 +594,29 @@ impl<'node> RenderGraph<'node> {
                     _phantom: PhantomData,
                 };
 
-                (node.exec)(ctx);
+                if cpu_scopes {
+                    profiling::scope!(&format!("Node: {}", node.label));
+                    (node.exec)(ctx);
+                } else {
+                    (node.exec)(ctx);
+                }
+
+                if let (Some(ref mut rpass), Some(ref pipeline_stats)) = (&mut rpass, &pipeline_stats) {
+                    pipeline_stats.end(rpass);
+                }
 
-                let mut encoder_or_rpass = match rpass {
-                    Some(ref mut rpass) => RenderGraphEncoderOrPassInner::RenderPass(rpass),
+                let mut encoder_or_rpass = match (rpass.as_mut(), prologue_encoder.as_mut()) {
+                    (_, Some(encoder)) if prologue => RenderGraphEncoderOrPassInner::Encoder(encoder),
+                    (Some(rpass), _) => RenderGraphEncoderOrPassInner::RenderPass(rpass),
                     // SAFETY: There is no active renderpass to borrow this. This reference lasts for the duration of
                     // the call to exec.
-                    None => RenderGraphEncoderOrPassInner::Encoder(unsafe { &mut *encoder_cell.get() }),
+                    (None, _) => RenderGraphEncoderOrPassInner::Encoder(unsafe { &mut *encoder_cell.get() }),
                 };
 
-                data_core.profiler.try_lock().unwrap().end_query(&mut encoder_or_rpass, profiler_query);
+                if debug_group {
+                    encoder_or_rpass.pop_debug_group();
+                }
+                renderer.profiler_state.end_gpu_scope(&data_core.profiler, &mut encoder_or_rpass, profiler_query);
             }
         }
 
@@ -492,11 +624,22 @@ impl<'node> RenderGraph<'node> {
         // and output_cell safely
         drop(rpass);
 
+        if let Some((_, query)) = group {
+            // SAFETY: The renderpass has dropped.
+            end_group_scope(renderer, data_core, unsafe { &mut *encoder_cell.get() }, query);
+        }
+
         // SAFETY: the renderpass has dropped, and so has all the uses of the data, and
         // the immutable borrows of the allocator.
         unsafe { (*rpass_temps_cell.get()).clear() }
         drop(rpass_temps_cell);
 
+        // Submitted ahead of the main command buffer, see RenderGraphNodeBuilder::set_prologue.
+        if let Some(mut encoder) = prologue_encoder {
+            end_group_scope(renderer, data_core, &mut encoder, prologue_query);
+            eval_output.cmd_bufs.push(encoder.finish());
+        }
+
         // SAFETY: this is safe as we've dropped all renderpasses that possibly borrowed
         // it
         eval_output.cmd_bufs.push(encoder_cell.into_inner().finish());
@@ -505,21 +648,70 @@ impl<'node> RenderGraph<'node> {
             .device
             .create_command_encoder(&CommandEncoderDescriptor { label: Some("profile resolve encoder") });
         data_core.profiler.try_lock().unwrap().resolve_queries(&mut resolve_encoder);
+        if let Some(pipeline_stats) = pipeline_stats {
+            renderer.pipeline_stats.resolve(&renderer.device, &mut resolve_encoder, pipeline_stats);
+        }
         eval_output.cmd_bufs.push(resolve_encoder.finish());
 
         renderer.queue.submit(eval_output.cmd_bufs.drain(..));
 
+        let (scatter_bytes, scatter_dispatches) = renderer.scatter.take_stats();
+        if scatter_dispatches != 0 {
+            renderer.record_pass_stats(PassStats {
+                label: String::from("Scatter Copy"),
+                compute_dispatches: scatter_dispatches,
+                ..PassStats::default()
+            });
+        }
+        renderer.record_buffer_upload(scatter_bytes);
+        renderer.frame_stats.finish_frame();
+        renderer.pipeline_stats.end_frame();
+        renderer.readback.end_frame();
+        renderer.gpu_capture.end_frame(&renderer.device);
+
         data_core.profiler.try_lock().unwrap().end_frame().unwrap();
 
         // This variable seems superfluous, but solves borrow checker issues with the borrow of data_core.
         let timers =
             data_core.profiler.try_lock().unwrap().process_finished_frame(renderer.queue.get_timestamp_period());
 
+        if let Some(ref timers) = timers {
+            renderer.frame_stats.set_gpu_times(timers);
+        }
+
         timers
     }
 
+    /// Reports nodes that read a render target that no earlier node wrote.
+    fn check_unwritten_targets(targets: &[RenderTargetDescriptor], renderer: &Renderer, nodes: &[RenderGraphNode<'_>]) {
+        let mut written = FastHashSet::default();
+        for node in nodes {
+            for input in &node.inputs {
+                let GraphSubResource::Texture(region) = *input else {
+                    continue;
+                };
+                if written.contains(&region.idx) || node.outputs.contains(input) {
+                    continue;
+                }
+                let target = targets[region.idx].label.as_deref().unwrap_or("unlabeled");
+                renderer.diagnostics.report(
+                    DiagnosticKind::UnwrittenRenderTarget,
+                    format!(
+                        "render graph node \"{}\" reads render target \"{target}\" which no earlier node wrote",
+                        node.label
+                    ),
+                );
+            }
+            written.extend(node.outputs.iter().filter_map(|output| match *output {
+                GraphSubResource::Texture(region) => Some(region.idx),
+                _ => None,
+            }));
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn create_rpass_from_desc<'rpass>(
+        label: &str,
         desc: &RenderPassTargets,
         encoder: &'rpass mut CommandEncoder,
         node_idx: usize,
@@ -536,17 +728,9 @@ impl<'node> RenderGraph<'node> {
 
                 let first_usage = view_span.first_usage.expect("internal rendergraph error: renderpass attachment counts as a usage, but no first usage registered on texture");
 
-                let load = if first_usage == node_idx {
-                    let clear_f64 = target.clear.as_dvec4();
-                    LoadOp::Clear(wgpu::Color {
-                        r: clear_f64.x,
-                        g: clear_f64.y,
-                        b: clear_f64.z,
-                        a: clear_f64.w,
-                    })
-                } else {
-                    LoadOp::Load
-                };
+                let clear_f64 = target.clear.as_dvec4();
+                let clear = wgpu::Color { r: clear_f64.x, g: clear_f64.y, b: clear_f64.z, a: clear_f64.w };
+                let load = target.load.to_wgpu(clear, first_usage == node_idx);
 
                 let store = if view_span.last_reference == Some(pass_end_idx) { StoreOp::Discard } else { StoreOp::Store };
 
@@ -579,24 +763,14 @@ impl<'node> RenderGraph<'node> {
 
             let store = if view_span.last_reference == Some(pass_end_idx) { StoreOp::Discard } else { StoreOp::Store };
 
-            let depth_ops = ds_target.depth_clear.map(|clear| {
-                let load = if first_usage == node_idx {
-                    LoadOp::Clear(clear)
-                } else {
-                    LoadOp::Load
-                };
-
-                Operations { load, store }
+            let depth_ops = ds_target.depth_clear.map(|clear| Operations {
+                load: ds_target.load.to_wgpu(clear, first_usage == node_idx),
+                store,
             });
 
-            let stencil_load = ds_target.stencil_clear.map(|clear| {
-                let load = if first_usage == node_idx {
-                    LoadOp::Clear(clear)
-                } else {
-                    LoadOp::Load
-                };
-
-                Operations { load, store }
+            let stencil_load = ds_target.stencil_clear.map(|clear| Operations {
+                load: ds_target.load.to_wgpu(clear, first_usage == node_idx),
+                store,
             });
 
             RenderPassDepthStencilAttachment {
@@ -612,7 +786,7 @@ impl<'node> RenderGraph<'node> {
             }
         });
         encoder.begin_render_pass(&RenderPassDescriptor {
-            label: None,
+            label: Some(label),
             color_attachments: &color_attachments,
             depth_stencil_attachment,
             timestamp_writes: None,
@@ -626,3 +800,36 @@ impl<'node> Default for RenderGraph<'node> {
         Self::new()
     }
 }
+
+/// Begins the scope of a group of nodes. Like the scopes of nodes, it is a
+/// debug group when there is no profiler scope.
+fn begin_group_scope(
+    renderer: &Renderer,
+    data_core: &RendererDataCore,
+    encoder: &mut CommandEncoder,
+    label: &str,
+) -> Option<GpuProfilerQuery> {
+    let query = renderer.profiler_state.begin_gpu_scope(
+        &data_core.profiler,
+        GpuScopeVerbosity::Nodes,
+        label,
+        encoder,
+        &renderer.device,
+    );
+    if query.is_none() {
+        encoder.push_debug_group(label);
+    }
+    query
+}
+
+fn end_group_scope(
+    renderer: &Renderer,
+    data_core: &RendererDataCore,
+    encoder: &mut CommandEncoder,
+    query: Option<GpuProfilerQuery>,
+) {
+    match query {
+        Some(query) => renderer.profiler_state.end_gpu_scope(&data_core.profiler, encoder, Some(query)),
+        None => encoder.pop_debug_group(),
+    }
+}
@@ -42,6 +42,14 @@
 //! If, inside the node, you need to create a temporary, you can put that temporary on
 //! the pool, and it will automatically have lifetime `'rpass`. The temporary is
 //! destroyed right after the renderpass is.
+//!
+//! # Prologue
+//!
+//! Compute nodes that only depend on work from outside the graph, like
+//! skinning, can be recorded into a separate command buffer with
+//! [`RenderGraphNodeBuilder::set_prologue`]. It is submitted on the same queue
+//! ahead of the rest of the frame. wgpu exposes a single queue, so this
+//! doesn't run the nodes concurrently with the render passes.
 
 use std::ops::Range;
 
@@ -111,7 +119,9 @@ impl RenderTargetCore {
                 // D2 doesn't care about depth
                 depth_or_array_layers: 1,
             }
-            .max_mips(TextureDimension::D2) as u8,
+            // Zero sized targets have no mips, but are still described by one.
+            .max_mips(TextureDimension::D2)
+            .max(1) as u8,
         }
     }
 }
@@ -274,7 +284,7 @@ impl RenderPassTargets {
                             (None, None) => true,
                             _ => false,
                         };
-                        color_compat && resolve_compat
+                        color_compat && resolve_compat && you.load.continues_renderpass()
                     });
 
                 let depth_compatible = match (&this.depth_stencil, &other.depth_stencil) {
@@ -282,6 +292,7 @@ impl RenderPassTargets {
                         this_depth.target.compatible(&other_depth.target)
                             && this_depth.depth_clear == other_depth.depth_clear
                             && this_depth.stencil_clear == other_depth.stencil_clear
+                            && other_depth.load.continues_renderpass()
                     }
                     (None, None) => true,
                     _ => false,
@@ -301,11 +312,14 @@ pub struct RenderPassTarget {
     /// Color attachment. Must be declared as a dependency of the node before it
     /// can be used.
     pub color: RenderTargetHandle,
-    /// Color the attachment will be cleared with if this is the first use.
+    /// Color the attachment will be cleared with, see [`Self::load`].
     pub clear: Vec4,
     /// Resolve attachment. Can only be present if color attachment has > 1
     /// sample.
     pub resolve: Option<RenderTargetHandle>,
+    /// What happens to the previous contents of the attachment when the node
+    /// starts.
+    pub load: RenderPassLoadOp,
 }
 
 /// Depth target in a renderpass.
@@ -313,10 +327,59 @@ pub struct RenderPassTarget {
 pub struct RenderPassDepthTarget {
     /// The target to use as depth.
     pub target: RenderTargetHandle,
-    /// Depth value the attachment will be cleared with if this is the first
-    /// use.
+    /// Depth value the attachment will be cleared with, see [`Self::load`].
+    /// If `None`, the depth aspect is read only.
     pub depth_clear: Option<f32>,
-    /// Stencil value the attachment will be cleared with if this is the first
-    /// use.
+    /// Stencil value the attachment will be cleared with, see [`Self::load`].
+    /// If `None`, the stencil aspect is read only.
     pub stencil_clear: Option<u32>,
+    /// What happens to the previous contents of the depth and stencil aspects
+    /// when the node starts.
+    pub load: RenderPassLoadOp,
+}
+
+/// What happens to the previous contents of a renderpass attachment when a
+/// node starts.
+///
+/// Nodes loading their targets, or clearing them on first use, are merged into
+/// the renderpass of the previous node if the targets match. A node explicitly
+/// clearing or discarding a target always starts a new renderpass.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RenderPassLoadOp {
+    /// Clears the attachment with the clear value if this node is the first
+    /// use of the target in the graph, loads it otherwise.
+    #[default]
+    ClearOnFirstUse,
+    /// Always clears the attachment with the clear value.
+    Clear,
+    /// Always loads the previous contents of the attachment, such as to
+    /// composite UI layers or accumulate over frames. The contents of a
+    /// non-imported target are undefined on its first use in the graph.
+    Load,
+    /// The node overwrites every pixel it uses, so the previous contents are
+    /// not needed. wgpu has no such load op, so the attachment is cleared,
+    /// which is the fastest option on tiled GPUs.
+    DontCare,
+}
+
+impl RenderPassLoadOp {
+    /// Resolves the load op of an attachment whose first use in the graph is
+    /// `first_use`.
+    pub(crate) fn to_wgpu<V>(self, clear: V, first_use: bool) -> wgpu::LoadOp<V>
+    where
+        V: Default,
+    {
+        match self {
+            Self::ClearOnFirstUse if first_use => wgpu::LoadOp::Clear(clear),
+            Self::ClearOnFirstUse | Self::Load => wgpu::LoadOp::Load,
+            Self::Clear => wgpu::LoadOp::Clear(clear),
+            Self::DontCare => wgpu::LoadOp::Clear(V::default()),
+        }
+    }
+
+    /// If a node with this load op can continue the renderpass of the previous
+    /// node.
+    fn continues_renderpass(self) -> bool {
+        matches!(self, Self::ClearOnFirstUse | Self::Load)
+    }
 }
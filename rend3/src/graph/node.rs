@@ -37,7 +37,9 @@ pub(super) struct RenderGraphNode<'node> {
     pub outputs: Vec<GraphSubResource>,
     pub references: Vec<GraphSubResource>,
     pub label: SsoString,
+    pub group: Option<SsoString>,
     pub rpass: Option<RenderPassTargets>,
+    pub prologue: bool,
     pub exec: Box<dyn for<'a, 'pass> FnOnce(NodeExecutionContext<'a, 'pass, 'node>) + 'node>,
 }
 
@@ -59,10 +61,12 @@ pub enum NodeResourceUsage {
 pub struct RenderGraphNodeBuilder<'a, 'node> {
     pub(super) graph: &'a mut RenderGraph<'node>,
     pub(super) label: SsoString,
+    pub(super) group: Option<SsoString>,
     pub(super) inputs: Vec<GraphSubResource>,
     pub(super) outputs: Vec<GraphSubResource>,
     pub(super) references: Vec<GraphSubResource>,
     pub(super) rpass: Option<RenderPassTargets>,
+    pub(super) prologue: bool,
 }
 impl<'a, 'node> RenderGraphNodeBuilder<'a, 'node> {
     /// Declares a rendertarget to be read from but not writen to.
@@ -186,6 +190,25 @@ impl<'a, 'node> RenderGraphNodeBuilder<'a, 'node> {
             .extend(render_targets.into_iter().map(|hdl| GraphSubResource::Data(hdl.idx)));
     }
 
+    /// Puts this node in the group with the given label, instead of the group
+    /// set with [`RenderGraph::set_group`]. See there for details.
+    pub fn set_group(&mut self, group: &str) {
+        self.group = Some(SsoString::from(group));
+    }
+
+    /// Records this node into the prologue command buffer, which is
+    /// submitted on the same queue ahead of the rest of the frame. Its GPU
+    /// time is reported under [`PROLOGUE_GROUP`](crate::graph::PROLOGUE_GROUP).
+    ///
+    /// The node must not have a renderpass. The graph moves it only if no
+    /// earlier node left on the main command buffer uses its render targets.
+    /// Buffers aren't tracked, so the node must only read buffers written
+    /// outside the graph or by other prologue nodes, and must only write
+    /// buffers no earlier node reads.
+    pub fn set_prologue(&mut self) {
+        self.prologue = true;
+    }
+
     /// Declares that this node has some unknowable side effect, so can't be removed.
     pub fn add_side_effect(&mut self) {
         self.inputs.push(GraphSubResource::External);
@@ -203,10 +226,12 @@ impl<'a, 'node> RenderGraphNodeBuilder<'a, 'node> {
     {
         self.graph.nodes.push(RenderGraphNode {
             label: self.label,
+            group: self.group,
             inputs: self.inputs,
             outputs: self.outputs,
             references: self.references,
             rpass: self.rpass,
+            prologue: self.prologue,
             exec: Box::new(exec),
         });
     }
@@ -35,6 +35,14 @@ impl<T> PartialEq for DataHandle<T> {
     }
 }
 
+impl<T> Eq for DataHandle<T> {}
+
+impl<T> std::hash::Hash for DataHandle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
+    }
+}
+
 /// Provides read-only access to the renderer and access to graph resources.
 ///
 /// This is how you turn [DeclaredDependency] into actual wgpu resources.
@@ -0,0 +1,159 @@
+//! Listing the resources of a renderer for editors and debugging tools.
+//!
+//! [`Renderer::inspect`] lists every live mesh, texture and material with
+//! its parameters and the GPU memory it takes, in a form that can be
+//! serialized with any serde format and sent to an external panel.
+//!
+//! Resources are listed by the index of their handle, `handle.get_raw().idx`.
+//! The renderer doesn't keep the handles themselves, so tools editing what
+//! they are shown send changes back to the application, which finds its
+//! handle by index and calls the regular update functions, like
+//! [`Renderer::update_material`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    managers::{InternalMesh, InternalTexture},
+    types::MaterialParameter,
+    Renderer,
+};
+
+/// A mesh, see the [module](self) for documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeshInspection {
+    pub index: usize,
+    pub label: Option<String>,
+    pub vertex_count: u32,
+    pub index_count: u32,
+    /// Levels of detail after the mesh itself.
+    pub lod_count: usize,
+    /// Names of the vertex attributes of the mesh.
+    pub attributes: Vec<String>,
+    /// Bytes of the mesh in the mesh data buffer.
+    pub memory: u64,
+}
+
+impl MeshInspection {
+    pub(crate) fn new(index: usize, mesh: &InternalMesh) -> Self {
+        let vertex_memory: u64 = mesh.vertex_attribute_ranges.iter().map(|(_, range)| range.end - range.start).sum();
+        Self {
+            index,
+            label: mesh.label.as_deref().map(String::from),
+            vertex_count: mesh.vertex_count,
+            index_count: mesh.index_count,
+            lod_count: mesh.lod_index_ranges.len(),
+            attributes: mesh.vertex_attribute_ranges.iter().map(|(id, _)| id.name().to_owned()).collect(),
+            memory: vertex_memory + mesh.index_range.end - mesh.index_range.start,
+        }
+    }
+}
+
+/// A texture, see the [module](self) for documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextureInspection {
+    pub index: usize,
+    pub label: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// Layers of the texture, 6 per cube.
+    pub layers: u32,
+    pub mip_level_count: u32,
+    /// Debug name of the [`TextureFormat`](crate::types::TextureFormat).
+    pub format: String,
+    /// Bytes of every mip level of every layer.
+    pub memory: u64,
+}
+
+impl TextureInspection {
+    pub(crate) fn new(index: usize, texture: &InternalTexture) -> Self {
+        let desc = &texture.desc;
+        let (block_width, block_height) = desc.format.block_dimensions();
+        let block_size = desc.format.block_copy_size(None).unwrap_or(0) as u64;
+        let memory = (0..desc.mip_level_count)
+            .filter_map(|mip| desc.mip_level_size(mip))
+            .map(|size| {
+                let blocks_wide = (size.width + block_width - 1) / block_width;
+                let blocks_high = (size.height + block_height - 1) / block_height;
+                blocks_wide as u64 * blocks_high as u64 * size.depth_or_array_layers as u64 * block_size
+            })
+            .sum();
+
+        Self {
+            index,
+            label: desc.label.map(String::from),
+            width: desc.size.width,
+            height: desc.size.height,
+            layers: desc.size.depth_or_array_layers,
+            mip_level_count: desc.mip_level_count,
+            format: format!("{:?}", desc.format),
+            memory,
+        }
+    }
+}
+
+/// A material, see the [module](self) for documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialInspection {
+    pub index: usize,
+    pub label: Option<String>,
+    /// Type name of the material.
+    pub ty: String,
+    /// See [`Material::parameters`](crate::types::Material::parameters).
+    pub parameters: Vec<(String, MaterialParameter)>,
+    /// Index of the 2D texture in each texture slot of the material.
+    pub textures: Vec<Option<usize>>,
+    /// Bytes of the material in its material buffer.
+    pub memory: u64,
+}
+
+/// Every live resource of a renderer.
+///
+/// See the [module](self) for documentation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RendererInspection {
+    /// Meshes, ordered by the index of their handle.
+    pub meshes: Vec<MeshInspection>,
+    /// 2D textures, ordered by the index of their handle.
+    pub textures_2d: Vec<TextureInspection>,
+    /// Cube textures, ordered by the index of their handle.
+    pub textures_cube: Vec<TextureInspection>,
+    /// Cube array textures, ordered by the index of their handle.
+    pub textures_cube_array: Vec<TextureInspection>,
+    /// Materials of every type, ordered by the index of their handle.
+    pub materials: Vec<MaterialInspection>,
+}
+
+impl RendererInspection {
+    /// Bytes of all listed resources.
+    pub fn memory(&self) -> u64 {
+        let meshes = self.meshes.iter().map(|mesh| mesh.memory);
+        let textures = [&self.textures_2d, &self.textures_cube, &self.textures_cube_array]
+            .into_iter()
+            .flatten()
+            .map(|texture| texture.memory);
+        let materials = self.materials.iter().map(|material| material.memory);
+        meshes.chain(textures).chain(materials).sum()
+    }
+}
+
+impl Renderer {
+    /// Lists every live mesh, texture and material.
+    ///
+    /// Holds the resources as of the last call to
+    /// [`Self::evaluate_instructions`]; changes made since are missing.
+    pub fn inspect(&self) -> RendererInspection {
+        profiling::scope!("Renderer::inspect");
+
+        let meshes = self.mesh_manager.inspect();
+
+        let data_core = self.data_core.lock();
+
+        RendererInspection {
+            meshes,
+            textures_2d: data_core.d2_texture_manager.inspect(),
+            textures_cube: data_core.d2c_texture_manager.inspect(),
+            textures_cube_array: data_core.d2ca_texture_manager.inspect(),
+            materials: data_core.material_manager.inspect(),
+        }
+    }
+}
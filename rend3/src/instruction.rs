@@ -1,22 +1,29 @@
-use std::{mem, panic::Location};
+use std::{mem, panic::Location, sync::Arc};
 
-use glam::Mat4;
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use parking_lot::Mutex;
 use rend3_types::{
     trait_supertrait_alias, ObjectChange, PointLight, PointLightChange, RawDirectionalLightHandle,
     RawGraphDataHandleUntyped, RawMaterialHandle, RawMeshHandle, RawPointLightHandle, RawSkeletonHandle,
-    RawTexture2DHandle, RawTextureCubeHandle, TextureFromTexture, WasmNotSend, WasmNotSync,
+    RawTexture2DHandle, RawTextureCubeArrayHandle, RawTextureCubeHandle, TextureFromTexture, WasmNotSend, WasmNotSync,
 };
 use wgpu::{CommandBuffer, Device};
 
 use crate::{
-    managers::{GraphStorage, InternalSkeleton, InternalTexture, MaterialManager, TextureManager},
-    types::{Camera, DirectionalLight, DirectionalLightChange, Object, RawObjectHandle},
-    RendererProfile,
+    managers::{
+        GraphStorage, InternalMesh, InternalSkeleton, InternalTexture, MaterialManager, TextureManager, TextureUpdate,
+    },
+    types::{
+        Camera, DirectionalLight, DirectionalLightChange, Instance, Object, ObjectFadeMode, ObjectLod,
+        ObjectRenderOrder, ObjectShadowSettings, RawObjectHandle, RawShadowProjectorHandle, ShadowFitting,
+        ShadowProjector,
+    },
+    InstructionOperation, RendererProfile,
 };
 
 trait_supertrait_alias!(pub AddMaterialFillInvoke: FnOnce(&mut MaterialManager, &Device, RendererProfile, &mut TextureManager<crate::types::Texture2DTag>, RawMaterialHandle) + WasmNotSend + WasmNotSync);
 trait_supertrait_alias!(pub ChangeMaterialChangeInvoke: FnOnce(&mut MaterialManager, &Device, &TextureManager<crate::types::Texture2DTag>, RawMaterialHandle) + WasmNotSend + WasmNotSync);
+trait_supertrait_alias!(pub ModifyMaterialsInvoke: FnOnce(&mut MaterialManager, &Device, &TextureManager<crate::types::Texture2DTag>, &[RawMaterialHandle]) + WasmNotSend + WasmNotSync);
 trait_supertrait_alias!(pub AddGraphDataAddInvoke: FnOnce(&mut GraphStorage) + WasmNotSend);
 
 pub struct Instruction {
@@ -37,6 +44,11 @@ pub enum InstructionKind {
         internal_texture: InternalTexture,
         cmd_buf: Option<CommandBuffer>,
     },
+    ReplaceTexture2D {
+        handle: RawTexture2DHandle,
+        internal_texture: InternalTexture,
+        cmd_buf: Option<CommandBuffer>,
+    },
     AddTexture2DFromTexture {
         handle: RawTexture2DHandle,
         texture: TextureFromTexture,
@@ -46,6 +58,11 @@ pub enum InstructionKind {
         internal_texture: InternalTexture,
         cmd_buf: Option<CommandBuffer>,
     },
+    AddTextureCubeArray {
+        handle: RawTextureCubeArrayHandle,
+        internal_texture: InternalTexture,
+        cmd_buf: Option<CommandBuffer>,
+    },
     AddMaterial {
         handle: RawMaterialHandle,
         fill_invoke: Box<dyn AddMaterialFillInvoke>,
@@ -54,14 +71,25 @@ pub enum InstructionKind {
         handle: RawObjectHandle,
         object: Object,
     },
+    AddObjects {
+        objects: Vec<(RawObjectHandle, Object)>,
+    },
     AddDirectionalLight {
         handle: RawDirectionalLightHandle,
         light: DirectionalLight,
     },
+    SetDirectionalShadowFitting {
+        handle: RawDirectionalLightHandle,
+        fitting: ShadowFitting,
+    },
     AddPointLight {
         handle: RawPointLightHandle,
         light: PointLight,
     },
+    AddShadowProjector {
+        handle: RawShadowProjectorHandle,
+        projector: ShadowProjector,
+    },
     AddGraphData {
         add_invoke: Box<dyn AddGraphDataAddInvoke>,
     },
@@ -69,6 +97,22 @@ pub enum InstructionKind {
         handle: RawMaterialHandle,
         change_invoke: Box<dyn ChangeMaterialChangeInvoke>,
     },
+    UpdateTexture2D {
+        handle: RawTexture2DHandle,
+        update: TextureUpdate,
+    },
+    UpdateTextureCube {
+        handle: RawTextureCubeHandle,
+        update: TextureUpdate,
+    },
+    UpdateTextureCubeArray {
+        handle: RawTextureCubeArrayHandle,
+        update: TextureUpdate,
+    },
+    ModifyMaterials {
+        handles: Vec<RawMaterialHandle>,
+        modify_invoke: Box<dyn ModifyMaterialsInvoke>,
+    },
     ChangeDirectionalLight {
         handle: RawDirectionalLightHandle,
         change: DirectionalLightChange,
@@ -77,6 +121,23 @@ pub enum InstructionKind {
         handle: RawPointLightHandle,
         change: PointLightChange,
     },
+    UpdateShadowProjector {
+        handle: RawShadowProjectorHandle,
+        projector: ShadowProjector,
+    },
+    ReplaceMesh {
+        handle: RawMeshHandle,
+        // Boxed for size
+        internal_mesh: Box<InternalMesh>,
+    },
+    SwapMeshes {
+        a: RawMeshHandle,
+        b: RawMeshHandle,
+    },
+    SwapMaterials {
+        a: RawMaterialHandle,
+        b: RawMaterialHandle,
+    },
     DeleteMesh {
         handle: RawMeshHandle,
     },
@@ -89,6 +150,9 @@ pub enum InstructionKind {
     DeleteTextureCube {
         handle: RawTextureCubeHandle,
     },
+    DeleteTextureCubeArray {
+        handle: RawTextureCubeArrayHandle,
+    },
     DeleteMaterial {
         handle: RawMaterialHandle,
     },
@@ -101,6 +165,9 @@ pub enum InstructionKind {
     DeletePointLight {
         handle: RawPointLightHandle,
     },
+    DeleteShadowProjector {
+        handle: RawShadowProjectorHandle,
+    },
     DeleteGraphData {
         handle: RawGraphDataHandleUntyped,
     },
@@ -108,6 +175,58 @@ pub enum InstructionKind {
         handle: RawObjectHandle,
         transform: Mat4,
     },
+    SetObjectCustomData {
+        handle: RawObjectHandle,
+        data: Vec4,
+    },
+    SetObjectOpacity {
+        handle: RawObjectHandle,
+        opacity: f32,
+    },
+    SetObjectStatic {
+        handle: RawObjectHandle,
+        is_static: bool,
+    },
+    SetObjectShadowSettings {
+        handle: RawObjectHandle,
+        settings: ObjectShadowSettings,
+    },
+    SetObjectRenderOrder {
+        handle: RawObjectHandle,
+        order: ObjectRenderOrder,
+    },
+    SetObjectLod {
+        handle: RawObjectHandle,
+        lod: ObjectLod,
+    },
+    SetObjectFadeMode {
+        handle: RawObjectHandle,
+        mode: ObjectFadeMode,
+    },
+    SetObjectLabel {
+        handle: RawObjectHandle,
+        label: Option<Arc<str>>,
+    },
+    SetMaterialLabel {
+        handle: RawMaterialHandle,
+        label: Option<Arc<str>>,
+    },
+    SetObjectParent {
+        handle: RawObjectHandle,
+        parent: Option<(RawObjectHandle, Option<usize>)>,
+    },
+    SetObjectTransforms {
+        transforms: Vec<(RawObjectHandle, Mat4)>,
+    },
+    SetInstances {
+        handle: RawObjectHandle,
+        instances: Vec<Instance>,
+    },
+    SetInstanceTransforms {
+        handle: RawObjectHandle,
+        start: usize,
+        transforms: Vec<Mat4>,
+    },
     SetSkeletonJointDeltas {
         handle: RawSkeletonHandle,
         joint_matrices: Vec<Mat4>,
@@ -118,6 +237,21 @@ pub enum InstructionKind {
     SetCameraData {
         data: Camera,
     },
+    SetCameraJitter {
+        jitter: Vec2,
+    },
+    RebaseWorld {
+        offset: Vec3,
+    },
+    AdvanceTime {
+        delta_time: f32,
+    },
+    SetTimePaused {
+        paused: bool,
+    },
+    SetTime {
+        elapsed: f64,
+    },
     DuplicateObject {
         src_handle: RawObjectHandle,
         dst_handle: RawObjectHandle,
@@ -125,6 +259,104 @@ pub enum InstructionKind {
     },
 }
 
+impl InstructionKind {
+    /// Name of the variant, used to group instructions in the frame stats.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AddSkeleton { .. } => "AddSkeleton",
+            Self::AddTexture2D { .. } => "AddTexture2D",
+            Self::ReplaceTexture2D { .. } => "ReplaceTexture2D",
+            Self::AddTexture2DFromTexture { .. } => "AddTexture2DFromTexture",
+            Self::AddTextureCube { .. } => "AddTextureCube",
+            Self::AddTextureCubeArray { .. } => "AddTextureCubeArray",
+            Self::AddMaterial { .. } => "AddMaterial",
+            Self::AddObject { .. } => "AddObject",
+            Self::AddObjects { .. } => "AddObjects",
+            Self::AddDirectionalLight { .. } => "AddDirectionalLight",
+            Self::SetDirectionalShadowFitting { .. } => "SetDirectionalShadowFitting",
+            Self::AddPointLight { .. } => "AddPointLight",
+            Self::AddShadowProjector { .. } => "AddShadowProjector",
+            Self::AddGraphData { .. } => "AddGraphData",
+            Self::ChangeMaterial { .. } => "ChangeMaterial",
+            Self::UpdateTexture2D { .. } => "UpdateTexture2D",
+            Self::UpdateTextureCube { .. } => "UpdateTextureCube",
+            Self::UpdateTextureCubeArray { .. } => "UpdateTextureCubeArray",
+            Self::ModifyMaterials { .. } => "ModifyMaterials",
+            Self::ChangeDirectionalLight { .. } => "ChangeDirectionalLight",
+            Self::ChangePointLight { .. } => "ChangePointLight",
+            Self::UpdateShadowProjector { .. } => "UpdateShadowProjector",
+            Self::ReplaceMesh { .. } => "ReplaceMesh",
+            Self::SwapMeshes { .. } => "SwapMeshes",
+            Self::SwapMaterials { .. } => "SwapMaterials",
+            Self::DeleteMesh { .. } => "DeleteMesh",
+            Self::DeleteSkeleton { .. } => "DeleteSkeleton",
+            Self::DeleteTexture2D { .. } => "DeleteTexture2D",
+            Self::DeleteTextureCube { .. } => "DeleteTextureCube",
+            Self::DeleteTextureCubeArray { .. } => "DeleteTextureCubeArray",
+            Self::DeleteMaterial { .. } => "DeleteMaterial",
+            Self::DeleteObject { .. } => "DeleteObject",
+            Self::DeleteDirectionalLight { .. } => "DeleteDirectionalLight",
+            Self::DeletePointLight { .. } => "DeletePointLight",
+            Self::DeleteShadowProjector { .. } => "DeleteShadowProjector",
+            Self::DeleteGraphData { .. } => "DeleteGraphData",
+            Self::SetObjectTransform { .. } => "SetObjectTransform",
+            Self::SetObjectCustomData { .. } => "SetObjectCustomData",
+            Self::SetObjectOpacity { .. } => "SetObjectOpacity",
+            Self::SetObjectStatic { .. } => "SetObjectStatic",
+            Self::SetObjectShadowSettings { .. } => "SetObjectShadowSettings",
+            Self::SetObjectRenderOrder { .. } => "SetObjectRenderOrder",
+            Self::SetObjectLod { .. } => "SetObjectLod",
+            Self::SetObjectFadeMode { .. } => "SetObjectFadeMode",
+            Self::SetObjectLabel { .. } => "SetObjectLabel",
+            Self::SetMaterialLabel { .. } => "SetMaterialLabel",
+            Self::SetObjectParent { .. } => "SetObjectParent",
+            Self::SetObjectTransforms { .. } => "SetObjectTransforms",
+            Self::SetInstances { .. } => "SetInstances",
+            Self::SetInstanceTransforms { .. } => "SetInstanceTransforms",
+            Self::SetSkeletonJointDeltas { .. } => "SetSkeletonJointDeltas",
+            Self::SetAspectRatio { .. } => "SetAspectRatio",
+            Self::SetCameraData { .. } => "SetCameraData",
+            Self::SetCameraJitter { .. } => "SetCameraJitter",
+            Self::RebaseWorld { .. } => "RebaseWorld",
+            Self::AdvanceTime { .. } => "AdvanceTime",
+            Self::SetTimePaused { .. } => "SetTimePaused",
+            Self::SetTime { .. } => "SetTime",
+            Self::DuplicateObject { .. } => "DuplicateObject",
+        }
+    }
+
+    /// Whether the instruction adds, changes or deletes a resource.
+    pub fn operation(&self) -> InstructionOperation {
+        match self {
+            Self::AddSkeleton { .. }
+            | Self::AddTexture2D { .. }
+            | Self::AddTexture2DFromTexture { .. }
+            | Self::AddTextureCube { .. }
+            | Self::AddTextureCubeArray { .. }
+            | Self::AddMaterial { .. }
+            | Self::AddObject { .. }
+            | Self::AddObjects { .. }
+            | Self::AddDirectionalLight { .. }
+            | Self::AddPointLight { .. }
+            | Self::AddShadowProjector { .. }
+            | Self::AddGraphData { .. }
+            | Self::DuplicateObject { .. } => InstructionOperation::Add,
+            Self::DeleteMesh { .. }
+            | Self::DeleteSkeleton { .. }
+            | Self::DeleteTexture2D { .. }
+            | Self::DeleteTextureCube { .. }
+            | Self::DeleteTextureCubeArray { .. }
+            | Self::DeleteMaterial { .. }
+            | Self::DeleteObject { .. }
+            | Self::DeleteDirectionalLight { .. }
+            | Self::DeletePointLight { .. }
+            | Self::DeleteShadowProjector { .. }
+            | Self::DeleteGraphData { .. } => InstructionOperation::Delete,
+            _ => InstructionOperation::Update,
+        }
+    }
+}
+
 pub struct InstructionStreamPair {
     pub producer: Mutex<Vec<Instruction>>,
     pub consumer: Mutex<Vec<Instruction>>,
@@ -144,6 +376,11 @@ impl InstructionStreamPair {
     pub fn push(&self, kind: InstructionKind, location: Location<'static>) {
         self.producer.lock().push(Instruction { kind, location })
     }
+
+    /// Pushes many instructions while taking the lock once.
+    pub fn push_many(&self, kinds: impl IntoIterator<Item = InstructionKind>, location: Location<'static>) {
+        self.producer.lock().extend(kinds.into_iter().map(|kind| Instruction { kind, location }))
+    }
 }
 
 /// Allows RawResourceHandle<T> to be turned into a delete instruction.
@@ -175,6 +412,12 @@ impl DeletableRawResourceHandle for RawTextureCubeHandle {
     }
 }
 
+impl DeletableRawResourceHandle for RawTextureCubeArrayHandle {
+    fn into_delete_instruction_kind(self) -> InstructionKind {
+        InstructionKind::DeleteTextureCubeArray { handle: self }
+    }
+}
+
 impl DeletableRawResourceHandle for RawMaterialHandle {
     fn into_delete_instruction_kind(self) -> InstructionKind {
         InstructionKind::DeleteMaterial { handle: self }
@@ -199,6 +442,12 @@ impl DeletableRawResourceHandle for RawPointLightHandle {
     }
 }
 
+impl DeletableRawResourceHandle for RawShadowProjectorHandle {
+    fn into_delete_instruction_kind(self) -> InstructionKind {
+        InstructionKind::DeleteShadowProjector { handle: self }
+    }
+}
+
 impl DeletableRawResourceHandle for RawGraphDataHandleUntyped {
     fn into_delete_instruction_kind(self) -> InstructionKind {
         InstructionKind::DeleteGraphData { handle: self }
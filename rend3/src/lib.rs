@@ -52,6 +52,7 @@
 //! the rest of the ecosystem.
 //!
 //! - `rend3-anim`: Skeletal animation playback utilities. Currently tied to rend3-gltf.
+//! - `rend3-ecs`: Keeps the scene in sync with the entities of an ECS.
 //! - `rend3-egui`: Integration with the [egui](https://github.com/emilk/egui)
 //!   immediate mode gui.
 //!
@@ -69,6 +70,15 @@
 //!     `rend3-framework` can help you. This will always be optional and is just there to help
 //!     with the limited set of cases it can help.
 //!
+//! # Platform Support
+//!
+//! rend3 runs on Vulkan, Metal, DX12 and WebGPU, and on OpenGL drivers with
+//! compute shaders. Devices with more features run the GpuDriven profile,
+//! others the CpuDriven profile. Both need compute shaders and storage buffers
+//! in vertex shaders ([`REQUIRED_DOWNLEVEL_FLAGS`]), which WebGL2 doesn't have,
+//! so WebGL2 isn't supported. [`IadReport::rejected`] says why each unusable
+//! adapter was rejected.
+//!
 //! # Future Plans
 //!
 //! I have grand plans for this library. An overview can be found in the issue
@@ -98,6 +108,7 @@ mod renderer;
 /// Managers for various type of resources.
 pub mod managers {
     mod camera;
+    mod clock;
     mod directional;
     mod graph_storage;
     mod handle_alloc;
@@ -109,6 +120,7 @@ pub mod managers {
     mod texture;
 
     pub use camera::*;
+    pub use clock::*;
     pub use directional::*;
     pub use graph_storage::*;
     pub(crate) use handle_alloc::*;
@@ -150,14 +162,21 @@ pub mod util {
 }
 
 pub mod graph;
+pub mod inspect;
 mod instruction;
 mod profile;
+pub mod scene;
 mod setup;
 mod shader;
 mod surface;
 
 pub use profile::*;
-pub use renderer::{error::*, Renderer, RendererDataCore};
+pub use renderer::{
+    error::*, Capability, CapabilityFallback, DeviceLostInfo, Diagnostic, DiagnosticKind, FrameStats, GpuPassTime,
+    GpuScopeVerbosity, InstanceError, InstancedObjectHandle, InstructionOperation, InstructionStats, ObjectVisibility,
+    PassStats, PipelineStatistics, ProfilerSettings, ReadbackError, ReadbackFuture, Renderer, RendererCapabilities,
+    RendererDataCore, RestoredResources, ViewStats,
+};
 pub use setup::*;
 pub use shader::*;
 pub use surface::*;
@@ -1,4 +1,4 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use rend3_types::Handedness;
 
 use crate::{
@@ -16,6 +16,8 @@ pub struct CameraState {
     world_frustum: Frustum,
     data: Camera,
     aspect_ratio: f32,
+    jitter: Vec2,
+    previous_view_proj: Mat4,
 }
 impl CameraState {
     /// Builds a new camera, using the given aspect ratio. If no aspect ratio is
@@ -29,7 +31,17 @@ impl CameraState {
 
         let frustum = Frustum::from_matrix(proj * data.view);
 
-        Self { handedness, orig_view, proj, inv_view: data.view.inverse(), world_frustum: frustum, data, aspect_ratio }
+        Self {
+            handedness,
+            orig_view,
+            proj,
+            inv_view: data.view.inverse(),
+            world_frustum: frustum,
+            data,
+            aspect_ratio,
+            jitter: Vec2::ZERO,
+            previous_view_proj: proj * data.view,
+        }
     }
 
     /// Sets the camera data, rebuilding the using the given aspect ratio. If no
@@ -43,8 +55,21 @@ impl CameraState {
         self.set_aspect_data(self.data, aspect_ratio.unwrap_or(1.0));
     }
 
+    /// Moves the camera by `offset`, see
+    /// [`Renderer::set_world_origin`](crate::Renderer::set_world_origin).
+    pub fn translate(&mut self, offset: Vec3) {
+        self.set_data(Camera { view: self.data.view * Mat4::from_translation(-offset), ..self.data });
+        self.previous_view_proj *= Mat4::from_translation(-offset);
+    }
+
+    /// Offsets the projection by `jitter` in normalized device coordinates.
+    pub fn set_jitter(&mut self, jitter: Vec2) {
+        self.jitter = jitter;
+        self.set_aspect_data(self.data, self.aspect_ratio);
+    }
+
     pub fn set_aspect_data(&mut self, data: Camera, aspect_ratio: f32) {
-        self.proj = compute_projection_matrix(data, self.handedness, aspect_ratio);
+        self.proj = jitter_projection(compute_projection_matrix(data, self.handedness, aspect_ratio), self.jitter);
         self.orig_view = compute_origin_matrix(data);
         self.inv_view = data.view.inverse();
         self.world_frustum = Frustum::from_matrix(self.proj * data.view);
@@ -52,6 +77,18 @@ impl CameraState {
         self.aspect_ratio = aspect_ratio;
     }
 
+    /// The same camera with the near plane of its perspective projection at
+    /// `near`. `None` for other projections.
+    pub fn with_near_plane(&self, near: f32) -> Option<Self> {
+        let CameraProjection::Perspective { vfov, .. } = self.data.projection else {
+            return None;
+        };
+        let mut camera = self.clone();
+        let data = Camera { projection: CameraProjection::Perspective { vfov, near }, ..self.data };
+        camera.set_aspect_data(data, self.aspect_ratio);
+        Some(camera)
+    }
+
     pub fn get_data(&self) -> Camera {
         self.data
     }
@@ -76,6 +113,28 @@ impl CameraState {
         self.proj
     }
 
+    /// The jitter set with [`Self::set_jitter`], already applied to
+    /// [`Self::proj`].
+    pub fn jitter(&self) -> Vec2 {
+        self.jitter
+    }
+
+    /// [`Self::view_proj`] without the jitter.
+    pub fn unjittered_view_proj(&self) -> Mat4 {
+        jitter_projection(self.proj, -self.jitter) * self.data.view
+    }
+
+    /// [`Self::unjittered_view_proj`] of the last frame, for motion vectors.
+    pub fn previous_view_proj(&self) -> Mat4 {
+        self.previous_view_proj
+    }
+
+    /// Makes the current camera the camera of the last frame. Must be called
+    /// at the start of every frame, before any camera changes.
+    pub fn begin_frame(&mut self) {
+        self.previous_view_proj = self.unjittered_view_proj();
+    }
+
     pub fn world_frustum(&self) -> Frustum {
         self.world_frustum
     }
@@ -106,9 +165,51 @@ fn compute_projection_matrix(data: Camera, handedness: Handedness, aspect_ratio:
     }
 }
 
+fn jitter_projection(proj: Mat4, jitter: Vec2) -> Mat4 {
+    if jitter == Vec2::ZERO {
+        return proj;
+    }
+    Mat4::from_translation(jitter.extend(0.0)) * proj
+}
+
 fn compute_origin_matrix(data: Camera) -> Mat4 {
     let mut view = data.view;
 
     view.w_axis = glam::Vec4::W;
     view
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec2, Vec3};
+    use rend3_types::Handedness;
+
+    use super::CameraState;
+    use crate::types::{Camera, CameraProjection};
+
+    #[test]
+    fn previous_view_proj_follows_frames_and_origin_shifts() {
+        let camera = |x: f32| Camera {
+            projection: CameraProjection::Perspective { vfov: 60.0, near: 0.1 },
+            view: Mat4::from_translation(Vec3::new(-x, 0.0, 0.0)),
+        };
+        let mut state = CameraState::new(camera(0.0), Handedness::Left, Some(1.0));
+        let first = state.unjittered_view_proj();
+
+        state.begin_frame();
+        state.set_jitter(Vec2::new(0.01, -0.01));
+        state.set_data(camera(1.0));
+        assert_eq!(state.previous_view_proj(), first);
+        assert_ne!(state.unjittered_view_proj(), first);
+        assert_ne!(state.unjittered_view_proj(), state.view_proj());
+
+        // Shifting the origin moves the point, which must stay where it was
+        // on screen last frame.
+        let point = Vec3::new(1.0, 2.0, 5.0);
+        let offset = Vec3::new(100.0, 0.0, -50.0);
+        state.translate(offset);
+        let before = first.project_point3(point);
+        let after = state.previous_view_proj().project_point3(point + offset);
+        assert!(before.abs_diff_eq(after, 1e-4), "{before} != {after}");
+    }
+}
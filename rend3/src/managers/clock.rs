@@ -0,0 +1,94 @@
+/// Time and frame count that shaders animate with.
+///
+/// Time only moves when it is advanced through
+/// [`Renderer::advance_time`](crate::Renderer::advance_time), so it can be
+/// driven by a fixed timestep, slowed down, or paused without touching the
+/// shaders. The frame index counts every frame, paused or not.
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    elapsed: f64,
+    delta: f32,
+    frame_index: u32,
+    paused: bool,
+}
+impl FrameClock {
+    pub fn new() -> Self {
+        Self {
+            elapsed: 0.0,
+            delta: 0.0,
+            // Wraps to 0 when the first frame begins.
+            frame_index: u32::MAX,
+            paused: false,
+        }
+    }
+
+    /// Starts a new frame, which hasn't advanced yet.
+    pub fn begin_frame(&mut self) {
+        self.frame_index = self.frame_index.wrapping_add(1);
+        self.delta = 0.0;
+    }
+
+    /// Advances time by `delta` seconds, unless paused.
+    pub fn advance(&mut self, delta: f32) {
+        if !self.paused {
+            self.elapsed += delta as f64;
+            self.delta += delta;
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Jumps to `elapsed` seconds, without counting the jump as time passed
+    /// this frame.
+    pub fn set_elapsed(&mut self, elapsed: f64) {
+        self.elapsed = elapsed;
+    }
+
+    /// Seconds the clock advanced since the renderer was created.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Seconds the clock advanced this frame. Zero while paused.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Number of frames before this one, wrapping on overflow.
+    pub fn frame_index(&self) -> u32 {
+        self.frame_index
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameClock;
+
+    #[test]
+    fn paused_clock_keeps_counting_frames() {
+        let mut clock = FrameClock::new();
+        clock.begin_frame();
+        clock.advance(0.5);
+        assert_eq!(clock.frame_index(), 0);
+        assert_eq!(clock.delta(), 0.5);
+
+        clock.set_paused(true);
+        clock.begin_frame();
+        clock.advance(0.5);
+        assert_eq!(clock.frame_index(), 1);
+        assert_eq!(clock.delta(), 0.0);
+        assert_eq!(clock.elapsed(), 0.5);
+    }
+}
@@ -1,6 +1,8 @@
 use encase::{ArrayLength, ShaderType};
 use glam::{Mat4, UVec2, Vec2, Vec3};
-use rend3_types::{DirectionalLightChange, RawDirectionalLightHandle};
+use rend3_types::{
+    DirectionalLightChange, RawDirectionalLightHandle, RawShadowProjectorHandle, ShadowFitting, ShadowProjector,
+};
 use wgpu::{
     BindingType, BufferBindingType, BufferUsages, Device, Extent3d, ShaderStages, TextureDescriptor, TextureDimension,
     TextureUsages, TextureView, TextureViewDescriptor,
@@ -12,6 +14,7 @@ use crate::{
     util::{
         bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
         buffer::WrappedPotBuffer,
+        frustum::BoundingBox,
     },
     Renderer, INTERNAL_SHADOW_DEPTH_FORMAT,
 };
@@ -19,13 +22,14 @@ use crate::{
 mod shadow_alloc;
 mod shadow_camera;
 
-pub use shadow_alloc::ShadowMap;
+pub use shadow_alloc::{ShadowAtlasUsage, ShadowMap};
 
 const MINIMUM_SHADOW_MAP_SIZE: UVec2 = UVec2::splat(32);
 
 /// Internal representation of a directional light.
 pub struct InternalDirectionalLight {
     pub inner: DirectionalLight,
+    pub fitting: ShadowFitting,
 }
 
 #[derive(Debug, Clone, ShaderType)]
@@ -50,21 +54,71 @@ struct ShaderDirectionalLight {
     pub atlas_offset: Vec2,
     /// [0, 1] size of the shadow map in the atlas.
     pub atlas_size: Vec2,
+    /// Distance from the center of the shadow map, in clip space, where
+    /// shadows start fading out.
+    pub fade_start: f32,
+}
+
+#[derive(Debug, Clone, ShaderType)]
+struct ShaderShadowProjectorBuffer {
+    count: ArrayLength,
+    #[size(runtime)]
+    array: Vec<ShaderShadowProjector>,
+}
+
+/// Indexed by the projector's handle. Slots without a projector have an empty
+/// `atlas_size`.
+#[derive(Debug, Copy, Clone, Default, ShaderType)]
+struct ShaderShadowProjector {
+    /// View/Projection of the projector, outputting [-1, 1] like
+    /// [`ShaderDirectionalLight::view_proj`].
+    pub view_proj: Mat4,
+    /// 1 / resolution of whole shadow map
+    pub inv_resolution: Vec2,
+    /// [0, 1] offset of the shadow map in the atlas.
+    pub atlas_offset: Vec2,
+    /// [0, 1] size of the shadow map in the atlas.
+    pub atlas_size: Vec2,
+}
+
+/// What a shadow map in the atlas is rendered for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShadowSource {
+    Directional(RawDirectionalLightHandle),
+    Projector(RawShadowProjectorHandle),
+}
+
+/// The matrices a directional light's shadow map was rendered with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalShadowView {
+    pub handle: RawDirectionalLightHandle,
+    /// World space to the light's view space.
+    pub view: Mat4,
+    /// The light's view space to the shadow map's clip space.
+    pub projection: Mat4,
+    pub view_proj: Mat4,
+    /// Where the shadow map is in the atlas, in texels.
+    pub map: ShadowMap,
 }
 
 #[derive(Debug, Clone)]
 pub struct ShadowDesc {
-    pub map: ShadowMap,
+    pub map: ShadowMap<ShadowSource>,
     pub camera: CameraState,
 }
 
-/// Manages directional lights and their associated shadow maps.
+/// Manages directional lights and their associated shadow maps, along with
+/// the shadow projectors sharing the shadow atlas.
 pub struct DirectionalLightManager {
     data: Vec<Option<InternalDirectionalLight>>,
     data_buffer: WrappedPotBuffer<ShaderDirectionalLightBuffer>,
+    projectors: Vec<Option<ShadowProjector>>,
+    projector_buffer: WrappedPotBuffer<ShaderShadowProjectorBuffer>,
 
     texture_size: UVec2,
     texture_view: TextureView,
+    usage: ShadowAtlasUsage,
+    views: Vec<DirectionalShadowView>,
 }
 impl DirectionalLightManager {
     pub fn new(device: &Device) -> Self {
@@ -76,8 +130,12 @@ impl DirectionalLightManager {
         Self {
             data: Vec::new(),
             data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "shadow data buffer"),
+            projectors: Vec::new(),
+            projector_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "shadow projector buffer"),
             texture_size,
             texture_view,
+            usage: ShadowAtlasUsage::default(),
+            views: Vec::new(),
         }
     }
 
@@ -85,30 +143,83 @@ impl DirectionalLightManager {
         if handle.idx >= self.data.len() {
             self.data.resize_with(handle.idx + 1, || None);
         }
-        self.data[handle.idx] = Some(InternalDirectionalLight { inner: light })
+        self.data[handle.idx] = Some(InternalDirectionalLight { inner: light, fitting: ShadowFitting::default() })
     }
 
     pub fn update(&mut self, handle: RawDirectionalLightHandle, change: DirectionalLightChange) {
         self.data[handle.idx].as_mut().unwrap().inner.update_from_changes(change);
     }
 
+    pub fn set_shadow_fitting(&mut self, handle: RawDirectionalLightHandle, fitting: ShadowFitting) {
+        self.data[handle.idx].as_mut().unwrap().fitting = fitting;
+    }
+
     pub fn remove(&mut self, handle: RawDirectionalLightHandle) {
         self.data[handle.idx].take().unwrap();
     }
 
-    pub fn evaluate(&mut self, renderer: &Renderer, user_camera: &CameraState) -> (UVec2, Vec<ShadowDesc>) {
+    pub fn add_projector(&mut self, handle: RawShadowProjectorHandle, projector: ShadowProjector) {
+        if handle.idx >= self.projectors.len() {
+            self.projectors.resize_with(handle.idx + 1, || None);
+        }
+        self.projectors[handle.idx] = Some(projector);
+    }
+
+    pub fn update_projector(&mut self, handle: RawShadowProjectorHandle, projector: ShadowProjector) {
+        *self.projectors[handle.idx].as_mut().unwrap() = projector;
+    }
+
+    pub fn remove_projector(&mut self, handle: RawShadowProjectorHandle) {
+        self.projectors[handle.idx].take().unwrap();
+    }
+
+    /// Moves every shadow projector by `offset`, see
+    /// [`Renderer::set_world_origin`](crate::Renderer::set_world_origin).
+    /// Directional lights are fitted around the camera, so they follow it.
+    pub fn translate_projectors(&mut self, offset: Vec3) {
+        for projector in self.projectors.iter_mut().flatten() {
+            projector.camera.view *= Mat4::from_translation(-offset);
+        }
+    }
+
+    /// How much of the shadow atlas was used by the last evaluation.
+    pub fn atlas_usage(&self) -> ShadowAtlasUsage {
+        self.usage
+    }
+
+    /// The shadow maps of the last evaluation.
+    pub fn shadow_views(&self) -> &[DirectionalShadowView] {
+        &self.views
+    }
+
+    /// Iterate over all current directional lights.
+    pub fn lights(&self) -> impl Iterator<Item = &DirectionalLight> {
+        self.data.iter().flatten().map(|light| &light.inner)
+    }
+
+    /// `scene_bounds` is only used by lights whose shadow map is clamped to the
+    /// scene.
+    pub fn evaluate(
+        &mut self,
+        renderer: &Renderer,
+        user_camera: &CameraState,
+        scene_bounds: impl FnOnce() -> Option<BoundingBox>,
+    ) -> (UVec2, Vec<ShadowDesc>) {
         profiling::scope!("DirectionalLightManager::evaluate");
 
-        let shadow_maps: Vec<_> = self
-            .data
-            .iter()
-            .enumerate()
-            .filter_map(|(idx, light)| Some((RawDirectionalLightHandle::new(idx), light.as_ref()?.inner.resolution)))
-            .collect();
-        let shadow_atlas = shadow_alloc::allocate_shadow_atlas(shadow_maps, renderer.limits.max_texture_dimension_2d);
+        let lights = self.data.iter().enumerate().filter_map(|(idx, light)| {
+            let source = ShadowSource::Directional(RawDirectionalLightHandle::new(idx));
+            Some((source, light.as_ref()?.inner.resolution))
+        });
+        let projectors = self.projectors.iter().enumerate().filter_map(|(idx, projector)| {
+            let source = ShadowSource::Projector(RawShadowProjectorHandle::new(idx));
+            Some((source, projector.as_ref()?.resolution))
+        });
+        let shadow_maps: Vec<_> = lights.chain(projectors).collect();
+        let shadow_atlas = shadow_alloc::fit_shadow_atlas(shadow_maps, renderer.limits.max_texture_dimension_2d);
 
         let new_shadow_map_size = match shadow_atlas {
-            Some(ref m) => m.texture_dimensions.max(MINIMUM_SHADOW_MAP_SIZE),
+            Some((ref m, _)) => m.texture_dimensions.max(MINIMUM_SHADOW_MAP_SIZE),
             None => MINIMUM_SHADOW_MAP_SIZE,
         };
         let new_shadow_map_size_f32 = new_shadow_map_size.as_vec2();
@@ -118,26 +229,62 @@ impl DirectionalLightManager {
             self.texture_view = create_shadow_texture(&renderer.device, self.texture_size);
         }
 
+        let was_downscaled = self.usage.downscaled;
+        self.usage = ShadowAtlasUsage { texture_size: new_shadow_map_size, ..ShadowAtlasUsage::default() };
         let coordinates = match shadow_atlas {
-            Some(m) => m.maps,
-            None => return (new_shadow_map_size, Vec::new()),
+            Some((m, downscaled)) => {
+                if downscaled && !was_downscaled {
+                    log::warn!("Shadow maps don't fit in the maximum texture size, lowering their resolution");
+                }
+                self.usage.map_count = m.maps.len();
+                self.usage.used_texels = m.maps.iter().map(|map| map.size as u64 * map.size as u64).sum();
+                self.usage.downscaled = downscaled;
+                m.maps
+            }
+            None => Vec::new(),
         };
 
+        let scene_bounds = self.data.iter().flatten().any(|l| l.fitting.clamp_to_scene).then(scene_bounds).flatten();
         let shadow_data: Vec<_> = coordinates
             .into_iter()
             .map(|map| {
-                let camera = shadow_camera::shadow_camera(self.data[map.handle.idx].as_ref().unwrap(), user_camera);
+                let camera = match map.handle {
+                    ShadowSource::Directional(handle) => {
+                        let light = self.data[handle.idx].as_ref().unwrap();
+                        shadow_camera::shadow_camera(light, user_camera, scene_bounds)
+                    }
+                    ShadowSource::Projector(handle) => {
+                        let projector = self.projectors[handle.idx].as_ref().unwrap();
+                        CameraState::new(projector.camera, user_camera.handedness(), None)
+                    }
+                };
 
                 ShadowDesc { map, camera }
             })
             .collect();
 
+        let directional_shadows = || {
+            shadow_data.iter().filter_map(|desc| match desc.map.handle {
+                ShadowSource::Directional(handle) => Some((handle, desc)),
+                ShadowSource::Projector(_) => None,
+            })
+        };
+
+        self.views = directional_shadows()
+            .map(|(handle, desc)| DirectionalShadowView {
+                handle,
+                view: desc.camera.view(),
+                projection: desc.camera.proj(),
+                view_proj: desc.camera.view_proj(),
+                map: desc.map.with_handle(handle),
+            })
+            .collect();
+
         let buffer = ShaderDirectionalLightBuffer {
             count: ArrayLength,
-            array: shadow_data
-                .iter()
-                .map(|desc| {
-                    let light = &self.data[desc.map.handle.idx].as_ref().unwrap().inner;
+            array: directional_shadows()
+                .map(|(handle, desc)| {
+                    let light = &self.data[handle.idx].as_ref().unwrap().inner;
 
                     ShaderDirectionalLight {
                         view_proj: desc.camera.view_proj(),
@@ -146,12 +293,32 @@ impl DirectionalLightManager {
                         inv_resolution: 1.0 / new_shadow_map_size_f32,
                         atlas_offset: desc.map.offset.as_vec2() / new_shadow_map_size_f32,
                         atlas_size: desc.map.size as f32 / new_shadow_map_size_f32,
+                        fade_start: 1.0 - light.shadow_fade.clamp(0.0, 1.0),
                     }
                 })
                 .collect(),
         };
 
-        self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+        let bytes = self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+        renderer.record_buffer_upload(bytes);
+
+        let mut projector_buffer = ShaderShadowProjectorBuffer {
+            count: ArrayLength,
+            array: vec![ShaderShadowProjector::default(); self.projectors.len()],
+        };
+        for desc in &shadow_data {
+            if let ShadowSource::Projector(handle) = desc.map.handle {
+                projector_buffer.array[handle.idx] = ShaderShadowProjector {
+                    view_proj: desc.camera.view_proj(),
+                    inv_resolution: 1.0 / new_shadow_map_size_f32,
+                    atlas_offset: desc.map.offset.as_vec2() / new_shadow_map_size_f32,
+                    atlas_size: desc.map.size as f32 / new_shadow_map_size_f32,
+                };
+            }
+        }
+
+        let bytes = self.projector_buffer.write_to_buffer(&renderer.device, &renderer.queue, &projector_buffer);
+        renderer.record_buffer_upload(bytes);
 
         (new_shadow_map_size, shadow_data)
     }
@@ -171,6 +338,25 @@ impl DirectionalLightManager {
     pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
         bgb.append_buffer(&self.data_buffer);
     }
+
+    /// Add the shadow projector buffer to the given bind group layout
+    /// builder. It is separate from the lights, as only passes reading the
+    /// shadow atlas need it.
+    pub fn add_projectors_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: Some(ShaderShadowProjectorBuffer::min_size()),
+            },
+            None,
+        );
+    }
+
+    pub fn add_projectors_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_buffer(&self.projector_buffer);
+    }
 }
 
 fn create_shadow_texture(device: &Device, size: UVec2) -> TextureView {
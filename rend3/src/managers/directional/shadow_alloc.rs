@@ -4,19 +4,14 @@ use glam::UVec2;
 use rend3_types::RawDirectionalLightHandle;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
-enum ShadowNode {
+enum ShadowNode<K> {
     Vacant,
-    Leaf(RawDirectionalLightHandle),
+    Leaf(K),
     Children([usize; 4]),
 }
 
-impl ShadowNode {
-    fn try_alloc(
-        nodes: &mut Vec<ShadowNode>,
-        node_idx: usize,
-        relative_order: u32,
-        handle: RawDirectionalLightHandle,
-    ) -> bool {
+impl<K: Copy> ShadowNode<K> {
+    fn try_alloc(nodes: &mut Vec<ShadowNode<K>>, node_idx: usize, relative_order: u32, handle: K) -> bool {
         let this = &mut nodes[node_idx];
         match *this {
             ShadowNode::Vacant => {
@@ -44,22 +39,85 @@ impl ShadowNode {
     }
 }
 
-pub(super) struct ShadowAtlas {
+pub(super) struct ShadowAtlas<K> {
     pub texture_dimensions: UVec2,
-    pub maps: Vec<ShadowMap>,
+    pub maps: Vec<ShadowMap<K>>,
 }
 
+/// Where a shadow map is in the atlas, in texels, and what it belongs to.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub struct ShadowMap {
+pub struct ShadowMap<K = RawDirectionalLightHandle> {
     pub offset: UVec2,
     pub size: u32,
-    pub handle: RawDirectionalLightHandle,
+    pub handle: K,
 }
 
-pub(super) fn allocate_shadow_atlas(
-    mut maps: Vec<(RawDirectionalLightHandle, u16)>,
-    max_dimension: u32,
-) -> Option<ShadowAtlas> {
+impl<K> ShadowMap<K> {
+    /// The same region of the atlas, belonging to `handle`.
+    pub fn with_handle<T>(self, handle: T) -> ShadowMap<T> {
+        ShadowMap { offset: self.offset, size: self.size, handle }
+    }
+}
+
+/// How much of the shadow atlas is covered by shadow maps.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ShadowAtlasUsage {
+    /// Size of the atlas texture.
+    pub texture_size: UVec2,
+    /// Number of shadow maps in the atlas.
+    pub map_count: usize,
+    /// Texels covered by shadow maps.
+    pub used_texels: u64,
+    /// The requested resolutions were lowered to keep the atlas within the
+    /// maximum texture size.
+    pub downscaled: bool,
+}
+
+impl ShadowAtlasUsage {
+    /// Fraction of the atlas covered by shadow maps.
+    pub fn utilization(&self) -> f32 {
+        let total = self.texture_size.x as u64 * self.texture_size.y as u64;
+        if total == 0 {
+            return 0.0;
+        }
+        self.used_texels as f32 / total as f32
+    }
+}
+
+/// Packs the shadow maps into an atlas no larger than `max_dimension`.
+///
+/// Resolutions are rounded up to a power of two. If the maps don't fit, every
+/// resolution is halved until they do, and the second value is true.
+pub(super) fn fit_shadow_atlas<K: Copy>(maps: Vec<(K, u16)>, max_dimension: u32) -> Option<(ShadowAtlas<K>, bool)> {
+    if max_dimension == 0 {
+        return None;
+    }
+    let max_resolution = 1_u16 << (31 - max_dimension.leading_zeros()).min(15);
+
+    let mut maps: Vec<_> = maps
+        .into_iter()
+        .map(|(handle, resolution)| {
+            let resolution = resolution.max(1).checked_next_power_of_two().unwrap_or(1 << 15);
+            (handle, resolution.min(max_resolution))
+        })
+        .collect();
+
+    let mut downscaled = false;
+    loop {
+        let atlas = allocate_shadow_atlas(maps.clone(), max_dimension)?;
+        let fits = atlas.texture_dimensions.cmple(UVec2::splat(max_dimension)).all();
+        if fits || maps.iter().all(|&(_, resolution)| resolution == 1) {
+            return Some((atlas, downscaled));
+        }
+
+        downscaled = true;
+        for (_, resolution) in &mut maps {
+            *resolution = (*resolution / 2).max(1);
+        }
+    }
+}
+
+pub(super) fn allocate_shadow_atlas<K: Copy>(mut maps: Vec<(K, u16)>, max_dimension: u32) -> Option<ShadowAtlas<K>> {
     if maps.is_empty() {
         return None;
     }
@@ -141,7 +199,7 @@ mod tests {
     use rend3_types::RawDirectionalLightHandle as RDLH;
 
     use super::ShadowNode;
-    use crate::managers::directional::shadow_alloc::{allocate_shadow_atlas, ShadowMap};
+    use crate::managers::directional::shadow_alloc::{allocate_shadow_atlas, fit_shadow_atlas, ShadowMap};
 
     #[test]
     fn chunk_subdivision_single() {
@@ -317,4 +375,30 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn fit_rounds_to_power_of_two() {
+        let maps = vec![(RDLH::new(0), 100)];
+
+        let (res, downscaled) = fit_shadow_atlas(maps, 1024).unwrap();
+        assert!(!downscaled);
+        assert_eq!(res.texture_dimensions, UVec2::splat(128));
+        assert_eq!(res.maps, &[ShadowMap { offset: UVec2::splat(0), size: 128, handle: RDLH::new(0) }]);
+    }
+
+    #[test]
+    fn fit_downscales_to_max_dimension() {
+        let maps = vec![(RDLH::new(0), 16), (RDLH::new(1), 16)];
+
+        let (res, downscaled) = fit_shadow_atlas(maps, 16).unwrap();
+        assert!(downscaled);
+        assert_eq!(res.texture_dimensions, UVec2::new(16, 8));
+        assert_eq!(
+            res.maps,
+            &[
+                ShadowMap { offset: UVec2::splat(0), size: 8, handle: RDLH::new(0) },
+                ShadowMap { offset: UVec2::new(8, 0), size: 8, handle: RDLH::new(1) },
+            ]
+        );
+    }
 }
@@ -1,12 +1,21 @@
 use glam::{Mat4, Vec3, Vec3A};
 use rend3_types::{Camera, CameraProjection, Handedness};
 
-use crate::managers::{CameraState, InternalDirectionalLight};
+use crate::{
+    managers::{CameraState, InternalDirectionalLight},
+    util::frustum::BoundingBox,
+};
 
-pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraState) -> CameraState {
+pub(super) fn shadow_camera(
+    l: &InternalDirectionalLight,
+    user_camera: &CameraState,
+    scene_bounds: Option<BoundingBox>,
+) -> CameraState {
     let camera_location = user_camera.location();
+    let fitting = l.fitting;
 
-    let shadow_texel_size = l.inner.distance / l.inner.resolution as f32;
+    let extent = l.inner.distance + fitting.padding * 2.0;
+    let shadow_texel_size = extent / l.inner.resolution as f32;
 
     let look_at = match user_camera.handedness() {
         Handedness::Left => Mat4::look_at_lh,
@@ -16,18 +25,58 @@ pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraSt
     let origin_view = look_at(Vec3::ZERO, l.inner.direction, Vec3::Y);
     let camera_origin_view = origin_view.transform_point3(camera_location);
 
-    let offset = camera_origin_view.truncate() % shadow_texel_size;
-    let shadow_location = camera_origin_view - Vec3::from((offset, 0.0));
+    let shadow_location = if fitting.texel_snapping {
+        let offset = camera_origin_view.truncate() % shadow_texel_size;
+        camera_origin_view - Vec3::from((offset, 0.0))
+    } else {
+        camera_origin_view
+    };
 
     let inv_origin_view = origin_view.inverse();
     let new_shadow_location = inv_origin_view.transform_point3(shadow_location);
+    let view = look_at(new_shadow_location, new_shadow_location + l.inner.direction, Vec3::Y);
+
+    let clamped = scene_bounds
+        .filter(|_| fitting.clamp_to_scene)
+        .and_then(|bounds| clamp_to_scene(view, extent, bounds, shadow_texel_size, fitting.texel_snapping));
+    let projection = match clamped {
+        Some((min, max)) => CameraProjection::Raw(orthographic(user_camera.handedness(), min, max)),
+        None => CameraProjection::Orthographic { size: Vec3A::splat(extent) },
+    };
+
+    CameraState::new(Camera { projection, view }, user_camera.handedness(), None)
+}
+
+/// The part of the shadow map's box in view space covering the scene, with
+/// the depth range of the scene, or `None` if the box misses the scene.
+fn clamp_to_scene(view: Mat4, extent: f32, bounds: BoundingBox, texel: f32, snap: bool) -> Option<(Vec3, Vec3)> {
+    let (scene_min, scene_max) = (0..8)
+        .map(|i| {
+            let corner = Vec3::select(glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), bounds.max, bounds.min);
+            view.transform_point3(corner)
+        })
+        .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| (min.min(p), max.max(p)));
+
+    let half = extent * 0.5;
+    let mut min = scene_min.max(Vec3::splat(-half));
+    let mut max = scene_max.min(Vec3::splat(half));
+    if snap {
+        min = (min / texel).floor() * texel;
+        max = (max / texel).ceil() * texel;
+    }
+    // Depth only needs to cover the scene, whatever the distance.
+    min.z = scene_min.z;
+    max.z = scene_max.z;
+
+    (!min.cmpge(max).any()).then_some((min, max))
+}
 
-    CameraState::new(
-        Camera {
-            projection: CameraProjection::Orthographic { size: Vec3A::splat(l.inner.distance) },
-            view: look_at(new_shadow_location, new_shadow_location + l.inner.direction, Vec3::Y),
-        },
-        user_camera.handedness(),
-        None,
-    )
+/// Orthographic projection of a view space box, with the same depth
+/// direction as [`CameraProjection::Orthographic`].
+fn orthographic(handedness: Handedness, min: Vec3, max: Vec3) -> Mat4 {
+    match handedness {
+        Handedness::Left => Mat4::orthographic_lh(min.x, max.x, min.y, max.y, max.z, min.z),
+        // Right handed view space looks down -z.
+        Handedness::Right => Mat4::orthographic_rh(min.x, max.x, min.y, max.y, -min.z, -max.z),
+    }
 }
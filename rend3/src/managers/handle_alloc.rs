@@ -33,6 +33,25 @@ where
         let maybe_idx = self.freelist.lock().pop();
         let idx = maybe_idx.unwrap_or_else(|| self.max_allocated.fetch_add(1, Ordering::Relaxed));
 
+        Self::create_handle(renderer, idx)
+    }
+
+    /// Allocates `count` handles while taking the freelist lock once.
+    pub fn allocate_many(&self, renderer: &Arc<Renderer>, count: usize) -> Vec<ResourceHandle<T>> {
+        let mut freelist = self.freelist.lock();
+        let kept = freelist.len().saturating_sub(count);
+        let mut indices: Vec<usize> = freelist.drain(kept..).rev().collect();
+        let reused = indices.len();
+        drop(freelist);
+
+        let fresh = count - reused;
+        let start = self.max_allocated.fetch_add(fresh, Ordering::Relaxed);
+        indices.extend(start..start + fresh);
+
+        indices.into_iter().map(|idx| Self::create_handle(renderer, idx)).collect()
+    }
+
+    fn create_handle(renderer: &Arc<Renderer>, idx: usize) -> ResourceHandle<T> {
         let renderer = Arc::clone(renderer);
         let destroy_fn = move |handle: RawResourceHandle<T>| {
             renderer.instructions.push(handle.into_delete_instruction_kind(), *Location::caller())
@@ -45,4 +64,9 @@ where
         let idx = handle.idx;
         self.freelist.lock().push(idx);
     }
+
+    /// Deallocates many handles while taking the freelist lock once.
+    pub fn deallocate_many(&self, handles: &[RawResourceHandle<T>]) {
+        self.freelist.lock().extend(handles.iter().map(|handle| handle.idx));
+    }
 }
@@ -2,13 +2,17 @@ use std::{
     any::TypeId,
     mem,
     num::{NonZeroU32, NonZeroU64},
+    sync::Arc,
 };
 
 use encase::{ShaderSize, ShaderType};
-use rend3_types::{Material, MaterialArray, RawMaterialHandle, RawTexture2DHandle, VertexAttributeId, WasmVecAny};
+use rend3_types::{
+    Material, MaterialArray, MaterialParameter, RawMaterialHandle, RawTexture2DHandle, VertexAttributeId, WasmVecAny,
+};
 use wgpu::{BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, CommandEncoder, Device, ShaderStages};
 
 use crate::{
+    inspect::MaterialInspection,
     managers::{object_add_callback, ObjectAddCallbackArgs, TextureManager},
     profile::ProfileData,
     util::{
@@ -46,6 +50,7 @@ struct MaterialArchetype {
     // Inner type is Option<InnerMaterial<M>>
     data_vec: WasmVecAny,
     remove_data: fn(&mut WasmVecAny, RawMaterialHandle) -> ProfileData<TextureBindGroupIndex, ()>,
+    swap_data: fn(&mut WasmVecAny, RawMaterialHandle, RawMaterialHandle),
     apply_data_cpu: fn(&mut FreelistDerivedBuffer, &Device, &mut CommandEncoder, &ScatterCopy, &mut WasmVecAny),
     apply_data_gpu: fn(
         &mut FreelistDerivedBuffer,
@@ -58,6 +63,11 @@ struct MaterialArchetype {
     #[allow(clippy::type_complexity)]
     get_attributes: fn(&mut dyn FnMut(&[&'static VertexAttributeId], &[&'static VertexAttributeId])),
     object_add_callback_wrapper: fn(&WasmVecAny, usize, ObjectAddCallbackArgs<'_>),
+    #[allow(clippy::type_complexity)]
+    inspect: fn(&WasmVecAny, RawMaterialHandle) -> (Vec<(&'static str, MaterialParameter)>, Vec<Option<usize>>),
+    type_name: &'static str,
+    /// Bytes of each material in `buffer`.
+    material_size: u64,
 }
 
 pub struct MaterialArchetypeView<'a, M: Material> {
@@ -96,6 +106,8 @@ pub struct MaterialKeyPair {
 pub struct MaterialManager {
     handle_to_typeid: FastHashMap<RawMaterialHandle, TypeId>,
     archetypes: FastHashMap<TypeId, MaterialArchetype>,
+    /// Materials with a label.
+    labels: FastHashMap<RawMaterialHandle, Arc<str>>,
 
     texture_deduplicator: texture_dedupe::TextureDeduplicator,
 }
@@ -106,7 +118,12 @@ impl MaterialManager {
 
         let texture_deduplicator = texture_dedupe::TextureDeduplicator::new(device);
 
-        Self { handle_to_typeid: FastHashMap::default(), archetypes: FastHashMap::default(), texture_deduplicator }
+        Self {
+            handle_to_typeid: FastHashMap::default(),
+            archetypes: FastHashMap::default(),
+            labels: FastHashMap::default(),
+            texture_deduplicator,
+        }
     }
 
     pub fn ensure_archetype<M: Material>(&mut self, device: &Device, profile: RendererProfile) {
@@ -129,10 +146,17 @@ impl MaterialManager {
             },
             data_vec: WasmVecAny::new::<Option<InternalMaterial<M>>>(),
             remove_data: remove_data::<M>,
+            swap_data: swap_data::<M>,
             apply_data_cpu: apply_buffer_cpu::<M>,
             apply_data_gpu: apply_buffer_gpu::<M>,
             get_attributes: get_attributes::<M>,
             object_add_callback_wrapper: object_add_callback_wrapper::<M>,
+            inspect: inspect::<M>,
+            type_name: std::any::type_name::<M>(),
+            material_size: match profile {
+                RendererProfile::CpuDriven => CpuPoweredShaderWrapper::<M>::SHADER_SIZE.get(),
+                RendererProfile::GpuDriven => GpuPoweredShaderWrapper::<M>::SHADER_SIZE.get(),
+            },
         })
     }
 
@@ -144,6 +168,8 @@ impl MaterialManager {
         handle: RawMaterialHandle,
         material: M,
     ) {
+        profiling::scope!("MaterialManager::add");
+
         let bind_group_index = if profile == RendererProfile::CpuDriven {
             let textures = material.to_textures();
 
@@ -175,6 +201,8 @@ impl MaterialManager {
         handle: RawMaterialHandle,
         material: M,
     ) {
+        profiling::scope!("MaterialManager::update");
+
         let type_id = self.handle_to_typeid[&handle];
 
         assert_eq!(type_id, TypeId::of::<M>());
@@ -196,8 +224,106 @@ impl MaterialManager {
         internal.inner = material;
     }
 
+    /// Modifies materials in place, only re-uploading their data. Bind groups
+    /// are only rebuilt if the textures of a material changed.
+    pub fn modify<M: Material>(
+        &mut self,
+        device: &Device,
+        texture_manager_2d: &TextureManager<crate::types::Texture2DTag>,
+        handles: &[RawMaterialHandle],
+        mut modify: impl FnMut(usize, &mut M),
+    ) {
+        let archetype = self.archetypes.get_mut(&TypeId::of::<M>()).expect("no materials of this type exist");
+        let data_vec = archetype.data_vec.downcast_slice_mut::<Option<InternalMaterial<M>>>().unwrap();
+
+        for (i, &handle) in handles.iter().enumerate() {
+            assert_eq!(self.handle_to_typeid[&handle], TypeId::of::<M>());
+            let internal = data_vec[handle.idx].as_mut().unwrap();
+
+            match internal.bind_group_index {
+                ProfileData::Cpu(ref mut index) => {
+                    let old_textures = internal.inner.to_textures().as_ref().to_vec();
+                    modify(i, &mut internal.inner);
+                    let textures = internal.inner.to_textures();
+                    if textures.as_ref() != old_textures.as_slice() {
+                        let bind_group_index =
+                            self.texture_deduplicator.get_or_insert(device, texture_manager_2d, textures.as_ref());
+                        self.texture_deduplicator.remove(*index);
+                        *index = bind_group_index;
+                    }
+                }
+                ProfileData::Gpu(()) => modify(i, &mut internal.inner),
+            }
+            archetype.buffer.use_index(handle.idx);
+        }
+    }
+
+    /// Swaps the data of two materials of the same type. Returns false if
+    /// their types differ.
+    pub fn swap(&mut self, a: RawMaterialHandle, b: RawMaterialHandle) -> bool {
+        let type_id = self.handle_to_typeid[&a];
+        if self.handle_to_typeid[&b] != type_id {
+            return false;
+        }
+
+        let archetype = self.archetypes.get_mut(&type_id).unwrap();
+        (archetype.swap_data)(&mut archetype.data_vec, a, b);
+        archetype.buffer.use_index(a.idx);
+        archetype.buffer.use_index(b.idx);
+        true
+    }
+
+    /// Recreates the bind groups of the CpuDriven profile that use a texture
+    /// whose data was replaced.
+    pub fn texture_replaced(
+        &mut self,
+        device: &Device,
+        texture_manager_2d: &TextureManager<crate::types::Texture2DTag>,
+        handle: RawTexture2DHandle,
+    ) {
+        self.texture_deduplicator.texture_replaced(device, texture_manager_2d, handle);
+    }
+
+    pub fn set_label(&mut self, handle: RawMaterialHandle, label: Option<Arc<str>>) {
+        match label {
+            Some(label) => self.labels.insert(handle, label),
+            None => self.labels.remove(&handle),
+        };
+    }
+
+    /// The label set with
+    /// [`Renderer::set_material_label`](crate::Renderer::set_material_label).
+    pub fn label(&self, handle: RawMaterialHandle) -> Option<&str> {
+        self.labels.get(&handle).map(|label| &**label)
+    }
+
+    /// Every material, see [`Renderer::inspect`](crate::Renderer::inspect).
+    pub fn inspect(&self) -> Vec<MaterialInspection> {
+        let mut handles: Vec<_> = self.handle_to_typeid.keys().copied().collect();
+        handles.sort_unstable_by_key(|handle| handle.idx);
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                let archetype = &self.archetypes[&self.handle_to_typeid[&handle]];
+                let (parameters, textures) = (archetype.inspect)(&archetype.data_vec, handle);
+                MaterialInspection {
+                    index: handle.idx,
+                    label: self.label(handle).map(String::from),
+                    ty: archetype.type_name.to_owned(),
+                    parameters: parameters.into_iter().map(|(name, value)| (name.to_owned(), value)).collect(),
+                    textures,
+                    memory: archetype.material_size,
+                }
+            })
+            .collect()
+    }
+
     pub fn remove(&mut self, handle: RawMaterialHandle) {
+        profiling::scope!("MaterialManager::remove");
+
         let type_id = self.handle_to_typeid.remove(&handle).unwrap();
+        self.labels.remove(&handle);
 
         let archetype = self.archetypes.get_mut(&type_id).unwrap();
         let bind_group_index = (archetype.remove_data)(&mut archetype.data_vec, handle);
@@ -259,6 +385,11 @@ impl MaterialManager {
         );
     }
 
+    /// Type of the material, which objects are grouped by.
+    pub(super) fn material_type_id(&self, handle: RawMaterialHandle) -> TypeId {
+        self.handle_to_typeid[&handle]
+    }
+
     pub(super) fn call_object_add_callback(&self, handle: RawMaterialHandle, args: ObjectAddCallbackArgs) {
         let type_id = self.handle_to_typeid[&handle];
 
@@ -296,6 +427,11 @@ fn remove_data<M: Material>(
     internal.bind_group_index
 }
 
+fn swap_data<M: Material>(data_vec: &mut WasmVecAny, a: RawMaterialHandle, b: RawMaterialHandle) {
+    let data_vec = data_vec.downcast_slice_mut::<Option<InternalMaterial<M>>>().unwrap();
+    data_vec.swap(a.idx, b.idx);
+}
+
 fn apply_buffer_cpu<M: Material>(
     buffer: &mut FreelistDerivedBuffer,
     device: &Device,
@@ -349,6 +485,16 @@ fn get_attributes<M: Material>(callback: &mut dyn FnMut(&[&'static VertexAttribu
     callback(M::required_attributes().as_ref(), M::supported_attributes().as_ref())
 }
 
+fn inspect<M: Material>(
+    data_vec: &WasmVecAny,
+    handle: RawMaterialHandle,
+) -> (Vec<(&'static str, MaterialParameter)>, Vec<Option<usize>>) {
+    let data_vec = data_vec.downcast_slice::<Option<InternalMaterial<M>>>().unwrap();
+    let material = &data_vec[handle.idx].as_ref().unwrap().inner;
+    let textures = material.to_textures().as_ref().iter().map(|texture| texture.map(|texture| texture.idx)).collect();
+    (material.parameters(), textures)
+}
+
 fn object_add_callback_wrapper<M: Material>(vec_any: &WasmVecAny, idx: usize, args: ObjectAddCallbackArgs) {
     let data_vec = vec_any.downcast_slice::<Option<InternalMaterial<M>>>().unwrap();
 
@@ -82,6 +82,40 @@ impl TextureDeduplicator {
             return index;
         }
 
+        let bg = self.create_bind_group(device, texture_manager_2d, array);
+
+        let index = self.storage.push(StoredBindGroup { refcount: 1, inner: bg });
+        let index = TextureBindGroupIndex(index);
+
+        self.deduplication_map.insert(array.to_vec(), index);
+
+        index
+    }
+
+    /// Recreates the bind groups using a texture whose data was replaced.
+    pub fn texture_replaced(
+        &mut self,
+        device: &Device,
+        texture_manager_2d: &TextureManager<crate::types::Texture2DTag>,
+        handle: RawTexture2DHandle,
+    ) {
+        let stale: Vec<_> = self
+            .deduplication_map
+            .iter()
+            .filter(|(array, _)| array.contains(&Some(handle)))
+            .map(|(array, &index)| (array.clone(), index))
+            .collect();
+        for (array, index) in stale {
+            self.storage[index.0].inner = self.create_bind_group(device, texture_manager_2d, &array);
+        }
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &Device,
+        texture_manager_2d: &TextureManager<crate::types::Texture2DTag>,
+        array: &[Option<RawTexture2DHandle>],
+    ) -> BindGroup {
         let entries: ArrayVec<_, 32> = array
             .iter()
             .enumerate()
@@ -96,18 +130,11 @@ impl TextureDeduplicator {
             })
             .collect();
 
-        let bg = device.create_bind_group(&BindGroupDescriptor {
+        device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: &self.bgls[array.len()],
             entries: &entries,
-        });
-
-        let index = self.storage.push(StoredBindGroup { refcount: 1, inner: bg });
-        let index = TextureBindGroupIndex(index);
-
-        self.deduplication_map.insert(array.to_vec(), index);
-
-        index
+        })
     }
 
     pub fn remove(&mut self, index: TextureBindGroupIndex) {
@@ -4,9 +4,14 @@ use std::{
     sync::Arc,
 };
 
+use glam::{Vec3, Vec4};
 use parking_lot::{Mutex, MutexGuard};
 use range_alloc::RangeAllocator;
-use rend3_types::{RawMeshHandle, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_POSITION};
+use rend3_types::{
+    IndexFormat, MeshValidationError, RawMeshHandle, VertexAttribute, VertexAttributeId, VertexFormat, VertexPacking,
+    MAX_UINT16_VERTEX_COUNT, MAX_VERTEX_COUNT, PACKED_VERTEX_ATTRIBUTE_BIT, VERTEX_ATTRIBUTE_JOINT_INDICES,
+    VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+};
 use thiserror::Error;
 use wgpu::{
     Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandBuffer, CommandEncoder, CommandEncoderDescriptor,
@@ -14,8 +19,15 @@ use wgpu::{
 };
 
 use crate::{
+    inspect::MeshInspection,
     types::{Mesh, MeshHandle},
-    util::{error_scope::AllocationErrorScope, frustum::BoundingSphere, sync::WaitGroup, upload::UploadChainer},
+    util::{
+        error_scope::AllocationErrorScope,
+        frustum::{BoundingBox, BoundingSphere},
+        sync::WaitGroup,
+        upload::UploadChainer,
+    },
+    RendererProfile,
 };
 
 /// Vertex buffer slot for object indices
@@ -32,12 +44,77 @@ pub struct InternalMesh {
     /// Vertex count
     pub vertex_count: u32,
     /// Range in the mesh data buffer where index data for this mesh resides.
+    ///
+    /// 16-bit indices are padded to a multiple of 4 bytes, so the range may
+    /// be longer than the indices.
     pub index_range: Range<u64>,
+    /// Index count
+    pub index_count: u32,
+    /// Indices of each lower level of detail, relative to
+    /// [`Self::first_index`]. They are stored after the indices of the mesh.
+    pub lod_index_ranges: Vec<Range<u32>>,
+    /// Format of the indices in the mesh data buffer.
+    pub index_format: IndexFormat,
+    /// Whether the attributes with a [`VertexPacking`] are stored packed.
+    pub packed_attributes: bool,
     /// For skinned meshes, stores the maximum joint index present in the joint
     /// index buffer. None means it has no joint index buffer.
     pub required_joint_count: Option<u16>,
     /// The bounding sphere of this mesh. Used for culling.
     pub bounding_sphere: BoundingSphere,
+    /// The bounding box of this mesh. Used for culling.
+    pub bounding_box: BoundingBox,
+    /// CPU copy of the vertex data skinning reads, kept for skinned meshes in
+    /// the CpuDriven profile, which skins on the CPU.
+    pub skinning_source: Option<SkinningSource>,
+    /// Label shown in graphics debuggers on the draws of the objects using
+    /// the mesh.
+    pub label: Option<Arc<str>>,
+}
+
+/// The unskinned vertex data of a skinned mesh.
+#[derive(Debug, Clone)]
+pub struct SkinningSource {
+    pub positions: Vec<Vec3>,
+    /// Empty if the mesh has no normals.
+    pub normals: Vec<Vec3>,
+    /// Empty if the mesh has no tangents.
+    pub tangents: Vec<Vec3>,
+    pub joint_indices: Vec<[u16; 4]>,
+    pub joint_weights: Vec<Vec4>,
+}
+
+impl SkinningSource {
+    /// Copies the vertex data of the mesh, if it has joints.
+    fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        fn find<T: VertexFormat>(mesh: &Mesh, attribute: &'static VertexAttribute<T>) -> Option<Vec<T>> {
+            mesh.attributes.iter().find_map(|a| a.typed_data(attribute)).map(<[T]>::to_vec)
+        }
+
+        Some(Self {
+            positions: find(mesh, &VERTEX_ATTRIBUTE_POSITION)?,
+            normals: find(mesh, &VERTEX_ATTRIBUTE_NORMAL).unwrap_or_default(),
+            tangents: find(mesh, &VERTEX_ATTRIBUTE_TANGENT).unwrap_or_default(),
+            joint_indices: find(mesh, &VERTEX_ATTRIBUTE_JOINT_INDICES)?,
+            joint_weights: find(mesh, &VERTEX_ATTRIBUTE_JOINT_WEIGHTS)?,
+        })
+    }
+
+    /// Overwrites the copy of an attribute starting at vertex `start`, if it
+    /// is one skinning reads.
+    fn update<T: VertexFormat>(&mut self, attribute: &'static VertexAttribute<T>, start: usize, data: &[T]) {
+        let bytes: &mut [u8] = match *attribute.id() {
+            id if id == *VERTEX_ATTRIBUTE_POSITION.id() => bytemuck::cast_slice_mut(&mut self.positions),
+            id if id == *VERTEX_ATTRIBUTE_NORMAL.id() => bytemuck::cast_slice_mut(&mut self.normals),
+            id if id == *VERTEX_ATTRIBUTE_TANGENT.id() => bytemuck::cast_slice_mut(&mut self.tangents),
+            id if id == *VERTEX_ATTRIBUTE_JOINT_INDICES.id() => bytemuck::cast_slice_mut(&mut self.joint_indices),
+            id if id == *VERTEX_ATTRIBUTE_JOINT_WEIGHTS.id() => bytemuck::cast_slice_mut(&mut self.joint_weights),
+            _ => return,
+        };
+        let data: &[u8] = bytemuck::cast_slice(data);
+        let offset = start * mem::size_of::<T>();
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+    }
 }
 
 impl InternalMesh {
@@ -47,14 +124,124 @@ impl InternalMesh {
             vertex_attribute_ranges: Vec::new(),
             vertex_count: 0,
             index_range: 0..0,
+            index_count: 0,
+            lod_index_ranges: Vec::new(),
+            index_format: IndexFormat::Uint32,
+            packed_attributes: false,
             required_joint_count: None,
             bounding_sphere: BoundingSphere::from_mesh(&[]),
+            bounding_box: BoundingBox::from_mesh(&[]),
+            skinning_source: None,
+            label: None,
         }
     }
 
     pub fn get_attribute(&self, attribute: &VertexAttributeId) -> Option<Range<u64>> {
         self.vertex_attribute_ranges.iter().find_map(|(id, range)| (*id == *attribute).then_some(range.clone()))
     }
+
+    /// How the given attribute is stored, if it's packed.
+    pub fn attribute_packing(&self, attribute: &VertexAttributeId) -> Option<VertexPacking> {
+        attribute.packing().filter(|_| self.packed_attributes)
+    }
+
+    /// Index of the first index of this mesh in the mesh data buffer, in
+    /// units of its index format.
+    pub fn first_index(&self) -> u32 {
+        (self.index_range.start / index_size(self.index_format)) as u32
+    }
+}
+
+/// Size of an index in bytes.
+fn index_size(format: IndexFormat) -> u64 {
+    match format {
+        IndexFormat::Uint16 => 2,
+        IndexFormat::Uint32 => 4,
+    }
+}
+
+/// Converts indices to the bytes of the given format, padded to a multiple of
+/// [`wgpu::COPY_BUFFER_ALIGNMENT`].
+/// A mesh validated and converted to the bytes it is uploaded as, before any
+/// space is allocated for it.
+struct PreparedMesh {
+    mesh: Mesh,
+    /// Packed data of each attribute, `None` for attributes uploaded as is.
+    packed_data: Vec<Option<Vec<u8>>>,
+    packed_attributes: bool,
+    required_joint_count: Option<u16>,
+    lod_index_ranges: Vec<Range<u32>>,
+    /// Indices of the mesh followed by those of its levels of detail.
+    indices: Vec<u8>,
+}
+
+impl PreparedMesh {
+    fn new(mesh: Mesh) -> Result<Self, MeshCreationError> {
+        if mesh.vertex_count == 0 || mesh.indices.is_empty() {
+            return Ok(Self {
+                mesh,
+                packed_data: Vec::new(),
+                packed_attributes: false,
+                required_joint_count: None,
+                lod_index_ranges: Vec::new(),
+                indices: Vec::new(),
+            });
+        }
+
+        let index_format = mesh.index_format;
+        if index_format == IndexFormat::Uint16 && mesh.vertex_count > MAX_UINT16_VERTEX_COUNT as usize {
+            return Err(MeshValidationError::ExceededUint16VertexCount { count: mesh.vertex_count }.into());
+        }
+
+        // This value is used later when setting joints, to make sure all indices are
+        // in-bounds with the specified amount of joints.
+        let mut required_joint_count = None;
+        let joint_indices_attribute =
+            mesh.attributes.iter().find_map(|attribute| attribute.typed_data(&VERTEX_ATTRIBUTE_JOINT_INDICES));
+        if let Some(joint_indices) = joint_indices_attribute {
+            required_joint_count = Some(joint_indices.iter().flatten().max().map_or(0, |v| v + 1));
+        }
+
+        // Skinning reads and writes unpacked attributes.
+        let packed_attributes = mesh.packed_attributes && required_joint_count.is_none();
+
+        let packed_data = mesh
+            .attributes
+            .iter()
+            .map(|attribute| {
+                let packing = attribute.id().packing().filter(|_| packed_attributes);
+                packing.map(|packing| packing.pack(attribute.untyped_data()))
+            })
+            .collect();
+
+        let mut all_indices = mesh.indices.clone();
+        let mut lod_index_ranges = Vec::with_capacity(mesh.lods.len());
+        for lod in &mesh.lods {
+            let start = all_indices.len() as u32;
+            all_indices.extend_from_slice(lod);
+            lod_index_ranges.push(start..all_indices.len() as u32);
+        }
+        let indices = index_bytes(index_format, &all_indices);
+
+        Ok(Self { mesh, packed_data, packed_attributes, required_joint_count, lod_index_ranges, indices })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+fn index_bytes(format: IndexFormat, indices: &[u32]) -> Vec<u8> {
+    match format {
+        IndexFormat::Uint16 => {
+            let mut narrowed: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+            if narrowed.len() % 2 != 0 {
+                narrowed.push(0);
+            }
+            bytemuck::cast_slice(&narrowed).to_vec()
+        }
+        IndexFormat::Uint32 => bytemuck::cast_slice(indices).to_vec(),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -72,6 +259,57 @@ pub enum MeshCreationError {
         #[source]
         inner: wgpu::Error,
     },
+    #[error("Mesh data is invalid")]
+    Validation(#[from] MeshValidationError),
+    #[error("Meshes created from buffers must have a vertex position attribute")]
+    MissingPositions,
+    #[error("Buffer offset {offset} is not a multiple of {}", wgpu::COPY_BUFFER_ALIGNMENT)]
+    UnalignedBufferOffset { offset: u64 },
+    #[error("Buffers meshes are created from must have the COPY_SRC usage")]
+    BufferNotCopySource,
+}
+
+#[derive(Debug, Error)]
+pub enum MeshUpdateError {
+    #[error("Mesh does not have the vertex attribute {name}")]
+    MissingAttribute { name: &'static str },
+    #[error("Tried to write {requested:?} when the mesh only has {len} {what}")]
+    OutOfBounds { what: &'static str, requested: Range<usize>, len: usize },
+    #[error("Index at position {index} has the value {value} which is out of bounds for a mesh of {max} vertices")]
+    IndexOutOfBounds { index: usize, value: u32, max: u32 },
+    #[error(
+        "Tried to write the 16-bit indices {requested:?}, which must start and end at an even index, \
+         or at the end of the indices"
+    )]
+    UnalignedIndices { requested: Range<usize> },
+    #[error("Failed to write mesh data to buffer. Failed to allocate staging buffer.")]
+    BufferWriteFailed {
+        #[source]
+        inner: wgpu::Error,
+    },
+}
+
+/// A vertex attribute stored in a user owned buffer.
+pub struct GpuVertexAttribute<'a> {
+    pub id: &'static VertexAttributeId,
+    /// Buffer holding `vertex_count` tightly packed values of the attribute.
+    pub buffer: &'a Buffer,
+    pub offset: BufferAddress,
+}
+
+/// Mesh data stored in user owned buffers, for data generated on the GPU.
+pub struct GpuMeshSource<'a> {
+    /// Vertex attributes of the mesh, which must include the vertex positions.
+    pub attributes: Vec<GpuVertexAttribute<'a>>,
+    pub vertex_count: u32,
+    /// Buffer holding `index_count` u32 indices.
+    pub indices: &'a Buffer,
+    pub index_offset: BufferAddress,
+    pub index_count: u32,
+    /// Bounds of the vertex positions, used for culling.
+    pub bounding_sphere: BoundingSphere,
+    /// For skinned meshes, one more than the largest joint index.
+    pub required_joint_count: Option<u16>,
 }
 
 /// Contains all the state for the mesh buffer.
@@ -94,10 +332,13 @@ pub struct MeshManager {
     buffer_state: Mutex<BufferState>,
 
     data: Mutex<Vec<Option<InternalMesh>>>,
+
+    /// Keep a [`SkinningSource`] for skinned meshes.
+    keep_skinning_sources: bool,
 }
 
 impl MeshManager {
-    pub fn new(device: &Device) -> Self {
+    pub fn new(device: &Device, profile: RendererProfile) -> Self {
         profiling::scope!("MeshManager::new");
 
         let buffer = Arc::new(device.create_buffer(&BufferDescriptor {
@@ -117,43 +358,52 @@ impl MeshManager {
         Self {
             buffer_state: Mutex::new(BufferState { buffer, allocator, encoder, wait_group: WaitGroup::new() }),
             data,
+            keep_skinning_sources: profile == RendererProfile::CpuDriven,
         }
     }
 
     pub fn add(&self, device: &Device, mesh: Mesh) -> Result<InternalMesh, MeshCreationError> {
         profiling::scope!("MeshManager::add");
 
-        let vertex_count = mesh.vertex_count;
-        let index_count = mesh.indices.len();
-
-        if vertex_count == 0 || index_count == 0 {
-            return Ok(InternalMesh::new_empty());
-        }
+        Ok(self.add_many(device, vec![mesh])?.pop().unwrap())
+    }
 
-        // This value is used later when setting joints, to make sure all indices are
-        // in-bounds with the specified amount of joints.
-        let mut required_joint_count = None;
-        let joint_indices_attribute =
-            mesh.attributes.iter().find_map(|attribute| attribute.typed_data(&VERTEX_ATTRIBUTE_JOINT_INDICES));
-        if let Some(joint_indices) = joint_indices_attribute {
-            required_joint_count = Some(joint_indices.iter().flatten().max().map_or(0, |v| v + 1));
+    /// Adds many meshes at once. The mesh data buffer is locked once, and the
+    /// data of all meshes goes through a single staging buffer.
+    ///
+    /// Validation and packing happen before the lock is taken, so threads
+    /// adding meshes only contend while space is allocated for them.
+    pub fn add_many(&self, device: &Device, meshes: Vec<Mesh>) -> Result<Vec<InternalMesh>, MeshCreationError> {
+        profiling::scope!("MeshManager::add_many");
+
+        let prepared = meshes.into_iter().map(PreparedMesh::new).collect::<Result<Vec<_>, _>>()?;
+        if prepared.iter().all(PreparedMesh::is_empty) {
+            return Ok(prepared.into_iter().map(|_| InternalMesh::new_empty()).collect());
         }
 
-        let mut vertex_attribute_ranges = Vec::with_capacity(mesh.attributes.len());
+        let mut vertex_attribute_ranges = Vec::with_capacity(prepared.len());
+        let mut index_ranges = Vec::with_capacity(prepared.len());
         let mut upload = UploadChainer::new();
 
         // Need to deref to allow split borrows
         let mut buffer_state_guard = self.buffer_state.lock();
         let buffer_state = &mut *buffer_state_guard;
 
-        for attribute in &mesh.attributes {
-            let range = self.allocate_range_impl(device, buffer_state, attribute.bytes())?;
-            upload.add(range.start, attribute.untyped_data());
-            vertex_attribute_ranges.push((*attribute.id(), range));
+        for mesh in prepared.iter().filter(|mesh| !mesh.is_empty()) {
+            let mut ranges = Vec::with_capacity(mesh.mesh.attributes.len());
+            for (attribute, packed) in mesh.mesh.attributes.iter().zip(&mesh.packed_data) {
+                let data = packed.as_deref().unwrap_or_else(|| attribute.untyped_data());
+                let range = self.allocate_range_impl(device, buffer_state, data.len() as u64)?;
+                upload.add(range.start, data);
+                ranges.push((*attribute.id(), range));
+            }
+            vertex_attribute_ranges.push(ranges);
+
+            let index_range = self.allocate_range_impl(device, buffer_state, mesh.indices.len() as u64)?;
+            upload.add(index_range.start, &mesh.indices);
+            index_ranges.push(index_range);
         }
 
-        let index_range = self.allocate_range_impl(device, buffer_state, index_count as u64 * 4)?;
-        upload.add(index_range.start, bytemuck::cast_slice(&mesh.indices));
         upload.create_staging_buffer(device).map_err(|e| MeshCreationError::BufferWriteFailed { inner: e })?;
         upload.encode_upload(&mut buffer_state.encoder, &buffer_state.buffer);
 
@@ -164,25 +414,240 @@ impl MeshManager {
         // the mutex, as we merely need to complete this before the next submission.
         upload.stage();
         drop(staging_guard);
+        drop(upload);
+
+        let mut ranges = vertex_attribute_ranges.into_iter().zip(index_ranges);
+        Ok(prepared
+            .into_iter()
+            .map(|mesh| match mesh.is_empty() {
+                true => InternalMesh::new_empty(),
+                false => {
+                    let (vertex_attribute_ranges, index_range) = ranges.next().unwrap();
+                    self.finish_mesh(mesh, vertex_attribute_ranges, index_range)
+                }
+            })
+            .collect())
+    }
+
+    fn finish_mesh(
+        &self,
+        prepared: PreparedMesh,
+        vertex_attribute_ranges: Vec<(VertexAttributeId, Range<u64>)>,
+        index_range: Range<u64>,
+    ) -> InternalMesh {
+        let PreparedMesh { mesh, packed_attributes, required_joint_count, lod_index_ranges, .. } = prepared;
 
         // We can cheat here as we know vertex positions are always the first attribute as they must exist.
-        let bounding_sphere = BoundingSphere::from_mesh(
-            mesh.attributes
-                .first()
-                .expect("Meshes first attributes must always exist")
-                .typed_data(&VERTEX_ATTRIBUTE_POSITION)
-                .expect("Meshes must have positions"),
-        );
+        let positions = mesh
+            .attributes
+            .first()
+            .expect("Meshes first attributes must always exist")
+            .typed_data(&VERTEX_ATTRIBUTE_POSITION)
+            .expect("Meshes must have positions");
+        let bounding_sphere = BoundingSphere::from_mesh(positions);
+        let bounding_box = BoundingBox::from_mesh(positions);
+
+        let skinning_source = match self.keep_skinning_sources {
+            true => SkinningSource::from_mesh(&mesh),
+            false => None,
+        };
 
-        Ok(InternalMesh {
+        InternalMesh {
             vertex_attribute_ranges,
             vertex_count: mesh.vertex_count as u32,
             index_range,
+            index_count: mesh.indices.len() as u32,
+            lod_index_ranges,
+            index_format: mesh.index_format,
+            packed_attributes,
             required_joint_count,
             bounding_sphere,
+            bounding_box,
+            skinning_source,
+            label: mesh.label.as_deref().map(Arc::from),
+        }
+    }
+
+    /// Copies mesh data from user owned buffers on the GPU, without a round
+    /// trip through the CPU.
+    pub fn add_from_buffers(
+        &self,
+        device: &Device,
+        source: GpuMeshSource<'_>,
+    ) -> Result<InternalMesh, MeshCreationError> {
+        profiling::scope!("MeshManager::add_from_buffers");
+
+        let vertex_count = source.vertex_count;
+        let index_count = source.index_count;
+
+        if vertex_count > MAX_VERTEX_COUNT {
+            return Err(MeshValidationError::ExceededMaxVertexCount { count: vertex_count as usize }.into());
+        }
+        if index_count % 3 != 0 {
+            return Err(MeshValidationError::IndexCountNotMultipleOfThree { count: index_count as usize }.into());
+        }
+        if !source.attributes.iter().any(|a| *a.id == *VERTEX_ATTRIBUTE_POSITION.id()) {
+            return Err(MeshCreationError::MissingPositions);
+        }
+        let buffers = source.attributes.iter().map(|a| (a.buffer, a.offset));
+        for (buffer, offset) in buffers.chain([(source.indices, source.index_offset)]) {
+            if offset % wgpu::COPY_BUFFER_ALIGNMENT != 0 {
+                return Err(MeshCreationError::UnalignedBufferOffset { offset });
+            }
+            if !buffer.usage().contains(BufferUsages::COPY_SRC) {
+                return Err(MeshCreationError::BufferNotCopySource);
+            }
+        }
+
+        if vertex_count == 0 || index_count == 0 {
+            return Ok(InternalMesh::new_empty());
+        }
+
+        let mut vertex_attribute_ranges = Vec::with_capacity(source.attributes.len());
+
+        let mut buffer_state_guard = self.buffer_state.lock();
+        let buffer_state = &mut *buffer_state_guard;
+
+        for attribute in &source.attributes {
+            let bytes = attribute.id.metadata().size as u64 * vertex_count as u64;
+            let range = self.allocate_range_impl(device, buffer_state, bytes)?;
+            buffer_state.encoder.copy_buffer_to_buffer(
+                attribute.buffer,
+                attribute.offset,
+                &buffer_state.buffer,
+                range.start,
+                bytes,
+            );
+            vertex_attribute_ranges.push((*attribute.id, range));
+        }
+
+        let index_bytes = index_count as u64 * 4;
+        let index_range = self.allocate_range_impl(device, buffer_state, index_bytes)?;
+        buffer_state.encoder.copy_buffer_to_buffer(
+            source.indices,
+            source.index_offset,
+            &buffer_state.buffer,
+            index_range.start,
+            index_bytes,
+        );
+
+        Ok(InternalMesh {
+            vertex_attribute_ranges,
+            vertex_count,
+            index_range,
+            index_count,
+            lod_index_ranges: Vec::new(),
+            index_format: IndexFormat::Uint32,
+            packed_attributes: false,
+            required_joint_count: source.required_joint_count,
+            bounding_sphere: source.bounding_sphere,
+            bounding_box: BoundingBox::from_sphere(source.bounding_sphere),
+            // The data never reaches the CPU, so can't be skinned there.
+            skinning_source: None,
+            label: None,
         })
     }
 
+    /// Overwrites the values of a vertex attribute starting at vertex `start`.
+    pub fn update_vertices<T: VertexFormat>(
+        &self,
+        device: &Device,
+        handle: RawMeshHandle,
+        attribute: &'static VertexAttribute<T>,
+        start: usize,
+        data: &[T],
+    ) -> Result<(), MeshUpdateError> {
+        profiling::scope!("MeshManager::update_vertices");
+
+        let mut guard = self.data.lock();
+        let mesh = guard[handle.idx].as_mut().unwrap();
+        let range =
+            mesh.get_attribute(attribute.id()).ok_or(MeshUpdateError::MissingAttribute { name: attribute.name() })?;
+        let len = mesh.vertex_count as usize;
+        let packing = mesh.attribute_packing(attribute);
+
+        let requested = start..start + data.len();
+        if requested.end > len {
+            return Err(MeshUpdateError::OutOfBounds { what: "vertices", requested, len });
+        }
+        if let Some(source) = &mut mesh.skinning_source {
+            source.update(attribute, start, data);
+        }
+        drop(guard);
+
+        match packing {
+            Some(packing) => {
+                let offset = range.start + start as u64 * packing.metadata().size as u64;
+                self.upload(device, offset, &packing.pack(bytemuck::cast_slice(data)))
+            }
+            None => {
+                let offset = range.start + (start * mem::size_of::<T>()) as u64;
+                self.upload(device, offset, bytemuck::cast_slice(data))
+            }
+        }
+    }
+
+    /// Overwrites the indices starting at index `start`.
+    pub fn update_indices(
+        &self,
+        device: &Device,
+        handle: RawMeshHandle,
+        start: usize,
+        data: &[u32],
+    ) -> Result<(), MeshUpdateError> {
+        profiling::scope!("MeshManager::update_indices");
+
+        let guard = self.data.lock();
+        let mesh = guard[handle.idx].as_ref().unwrap();
+        let range = mesh.index_range.clone();
+        let vertex_count = mesh.vertex_count;
+        let index_format = mesh.index_format;
+        let len = mesh.index_count as usize;
+        let padded_end = mesh.lod_index_ranges.is_empty();
+        drop(guard);
+
+        let requested = start..start + data.len();
+        if requested.end > len {
+            return Err(MeshUpdateError::OutOfBounds { what: "indices", requested, len });
+        }
+        if let Some((index, &value)) = data.iter().enumerate().find(|(_, &v)| v >= vertex_count) {
+            return Err(MeshUpdateError::IndexOutOfBounds { index: start + index, value, max: vertex_count });
+        }
+        // Copies must be a multiple of 4 bytes, which is two 16-bit indices. The
+        // indices are padded, so the last one can be written alone, unless the
+        // levels of detail follow it.
+        let odd_end = requested.end % 2 != 0 && !(padded_end && requested.end == len);
+        if index_format == IndexFormat::Uint16 && (start % 2 != 0 || odd_end) {
+            return Err(MeshUpdateError::UnalignedIndices { requested });
+        }
+
+        let offset = range.start + start as u64 * index_size(index_format);
+        self.upload(device, offset, &index_bytes(index_format, data))
+    }
+
+    fn upload(&self, device: &Device, offset: u64, data: &[u8]) -> Result<(), MeshUpdateError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut upload = UploadChainer::new();
+        upload.add(offset, data);
+
+        // Need to deref to allow split borrows
+        let mut buffer_state_guard = self.buffer_state.lock();
+        let buffer_state = &mut *buffer_state_guard;
+        upload.create_staging_buffer(device).map_err(|e| MeshUpdateError::BufferWriteFailed { inner: e })?;
+        upload.encode_upload(&mut buffer_state.encoder, &buffer_state.buffer);
+
+        let staging_guard = buffer_state.wait_group.increment();
+        drop(buffer_state_guard);
+
+        upload.stage();
+        drop(staging_guard);
+
+        Ok(())
+    }
+
     pub fn fill(&self, handle: &MeshHandle, mesh: InternalMesh) {
         profiling::scope!("MeshManager::fill");
 
@@ -194,9 +659,37 @@ impl MeshManager {
         drop(data_guard);
     }
 
+    /// Fills many meshes while taking the lock once.
+    pub fn fill_many<'a>(&self, meshes: impl IntoIterator<Item = (&'a MeshHandle, InternalMesh)>) {
+        profiling::scope!("MeshManager::fill_many");
+
+        let mut data_guard = self.data.lock();
+        for (handle, mesh) in meshes {
+            if handle.idx >= data_guard.len() {
+                data_guard.resize_with(handle.idx + 1, || None);
+            }
+            data_guard[handle.idx] = Some(mesh);
+        }
+    }
+
+    /// Replaces the data of a mesh, returning the old data, which must be
+    /// given to [`Self::free`] once nothing uses it.
+    pub fn replace(&self, handle: RawMeshHandle, mesh: InternalMesh) -> InternalMesh {
+        self.data.lock()[handle.idx].replace(mesh).unwrap()
+    }
+
+    /// Swaps the data of two meshes.
+    pub fn swap(&self, a: RawMeshHandle, b: RawMeshHandle) {
+        self.data.lock().swap(a.idx, b.idx);
+    }
+
     pub fn remove(&self, object_id: RawMeshHandle) {
         let mesh = self.data.lock()[object_id.idx].take().unwrap();
+        self.free(mesh);
+    }
 
+    /// Frees the regions of the mesh data buffer used by a mesh.
+    pub fn free(&self, mesh: InternalMesh) {
         let mut buffer_state = self.buffer_state.lock();
         for (_id, range) in mesh.vertex_attribute_ranges {
             if range.is_empty() {
@@ -261,6 +754,12 @@ impl MeshManager {
         LockedInternalMeshDataArray(self.data.lock())
     }
 
+    /// Every mesh, see [`Renderer::inspect`](crate::Renderer::inspect).
+    pub fn inspect(&self) -> Vec<MeshInspection> {
+        let data = self.data.lock();
+        data.iter().enumerate().filter_map(|(index, mesh)| Some(MeshInspection::new(index, mesh.as_ref()?))).collect()
+    }
+
     fn reallocate_buffers(
         &self,
         device: &Device,
@@ -276,7 +775,8 @@ impl MeshManager {
             .checked_next_power_of_two()
             .expect("Using more than 2^63 bytes of mesh data");
 
-        let max_buffer_size = device.limits().max_storage_buffer_binding_size;
+        // Attribute offsets flag packed attributes in their top bit.
+        let max_buffer_size = device.limits().max_storage_buffer_binding_size.min(PACKED_VERTEX_ATTRIBUTE_BIT);
 
         let new_bytes = desired_bytes.min(max_buffer_size as u64);
 
@@ -317,3 +817,39 @@ impl<'a> Index<RawMeshHandle> for LockedInternalMeshDataArray<'a> {
         self.0[handle.idx].as_ref().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rend3_types::{IndexFormat, MeshBuilder, MAX_UINT16_VERTEX_COUNT};
+
+    use super::index_bytes;
+    use crate::types::Handedness;
+
+    #[test]
+    fn odd_uint16_index_counts_are_padded() {
+        let bytes = index_bytes(IndexFormat::Uint16, &[1, 2, 3]);
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(bytemuck::cast_slice::<u8, u16>(&bytes), [1, 2, 3, 0]);
+
+        let bytes = index_bytes(IndexFormat::Uint16, &[1, 2, 3, 4]);
+        assert_eq!(bytemuck::cast_slice::<u8, u16>(&bytes), [1, 2, 3, 4]);
+
+        let bytes = index_bytes(IndexFormat::Uint32, &[1, 2, 3]);
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&bytes), [1, 2, 3]);
+    }
+
+    #[test]
+    fn index_format_follows_vertex_count() {
+        let build = |vertex_count: u32| {
+            MeshBuilder::new(vec![Vec3::ZERO; vertex_count as usize], Handedness::Left)
+                .with_indices(vec![0, 1, vertex_count - 1])
+                .build()
+                .unwrap()
+                .index_format
+        };
+        assert_eq!(build(3), IndexFormat::Uint16);
+        assert_eq!(build(MAX_UINT16_VERTEX_COUNT), IndexFormat::Uint16);
+        assert_eq!(build(MAX_UINT16_VERTEX_COUNT + 1), IndexFormat::Uint32);
+    }
+}
@@ -1,21 +1,25 @@
-use std::{any::TypeId, ops::Range};
+use std::{any::TypeId, ops::Range, sync::Arc};
 
 use bytemuck::Zeroable;
-use encase::ShaderType;
-use glam::{Mat4, Vec3A};
+use encase::{ShaderSize, ShaderType, StorageBuffer};
+use glam::{Mat4, Vec3, Vec3A, Vec4};
 use rend3_types::{
-    Material, MaterialArray, MaterialHandle, ObjectChange, ObjectMeshKind, RawObjectHandle, VertexAttributeId,
-    WasmVecAny,
+    IndexFormat, Instance, Material, MaterialArray, MaterialHandle, ObjectChange, ObjectFadeMode, ObjectLod,
+    ObjectMeshKind, ObjectRenderOrder, ObjectShadowSettings, RawMaterialHandle, RawObjectHandle, RawSkeletonHandle,
+    Sorting, VertexAttributeId, WasmVecAny, PACKED_VERTEX_ATTRIBUTE_BIT, VERTEX_ATTRIBUTE_POSITION,
 };
-use wgpu::{Buffer, CommandEncoder, Device};
+use wgpu::{Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Queue};
 
 use super::SkeletonManager;
 use crate::{
-    managers::{InternalMesh, MaterialManager, MeshManager},
+    managers::{InternalMesh, LockedInternalMeshDataArray, MaterialManager, MeshManager},
     types::Object,
     util::{
-        freelist::FreelistDerivedBuffer, frustum::BoundingSphere, iter::ExactSizerIterator, scatter_copy::ScatterCopy,
-        typedefs::FastHashMap,
+        freelist::FreelistDerivedBuffer,
+        frustum::{BoundingBox, BoundingSphere},
+        iter::ExactSizerIterator,
+        scatter_copy::ScatterCopy,
+        typedefs::{FastHashMap, FastHashSet},
     },
 };
 
@@ -24,10 +28,28 @@ use crate::{
 pub struct ShaderObject<M: Material> {
     /// Model -> World matrix
     pub transform: Mat4,
+    /// Model -> World matrix of the last frame.
+    pub previous_transform: Mat4,
     /// Bounding sphere in world space.
     pub bounding_sphere: BoundingSphere,
+    /// Data set with [`Renderer::set_object_custom_data`](crate::Renderer::set_object_custom_data).
+    pub custom_data: Vec4,
+    /// Opacity set with [`Renderer::set_object_opacity`](crate::Renderer::set_object_opacity).
+    pub opacity: f32,
+    /// Weight of the level of detail fading in, set with
+    /// [`Renderer::set_object_lod`](crate::Renderer::set_object_lod). 1 when
+    /// the object isn't cross-fading.
+    pub lod_fade: f32,
+    /// 1 if the object receives shadows, set with
+    /// [`Renderer::set_object_shadow_settings`](crate::Renderer::set_object_shadow_settings).
+    pub receives_shadows: u32,
+    /// Offset of the vertex positions of the last frame, which differ from
+    /// the current positions for skinned meshes.
+    pub previous_position_offset: u32,
     pub first_index: u32,
     pub index_count: u32,
+    /// Bytes per index of the mesh, 2 or 4, which `first_index` counts in.
+    pub index_size: u32,
     pub material_index: u32,
     pub vertex_attribute_start_offsets:
         <M::SupportedAttributeArrayType as MaterialArray<&'static VertexAttributeId>>::U32Array,
@@ -37,9 +59,16 @@ impl<M: Material> Default for ShaderObject<M> {
     fn default() -> Self {
         Self {
             transform: Default::default(),
+            previous_transform: Default::default(),
             bounding_sphere: Default::default(),
+            custom_data: Default::default(),
+            opacity: 1.0,
+            lod_fade: 1.0,
+            receives_shadows: 1,
+            previous_position_offset: Default::default(),
             first_index: Default::default(),
             index_count: Default::default(),
+            index_size: 4,
             material_index: Default::default(),
             vertex_attribute_start_offsets: Zeroable::zeroed(),
         }
@@ -56,6 +85,56 @@ impl<M: Material> Clone for ShaderObject<M> {
     }
 }
 
+/// An instance of an instanced object, in the instance buffer.
+#[derive(Debug, Copy, Clone, ShaderType)]
+pub struct ShaderInstance {
+    /// Model -> World matrix
+    pub transform: Mat4,
+    /// Model -> World matrix of the last frame.
+    pub previous_transform: Mat4,
+    pub color: Vec4,
+    pub custom_data: Vec4,
+}
+
+impl ShaderInstance {
+    fn new(instance: Instance, previous_transform: Mat4) -> Self {
+        Self {
+            transform: instance.transform,
+            previous_transform,
+            color: instance.color,
+            custom_data: instance.custom_data,
+        }
+    }
+}
+
+/// Instances of an instanced object, see [`ObjectManager::set_instances`].
+struct InstanceList {
+    instances: Vec<ShaderInstance>,
+    /// Index of the first instance in the instance buffer.
+    offset: u32,
+    /// Instances changed since they were last written to the instance buffer.
+    dirty: Option<Range<usize>>,
+}
+
+impl InstanceList {
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+}
+
+/// Writes `instances` to the instance buffer, starting at instance `offset`.
+fn write_instances(queue: &Queue, buffer: &Buffer, offset: usize, instances: &[ShaderInstance]) {
+    if instances.is_empty() {
+        return;
+    }
+    let mut data = StorageBuffer::new(Vec::with_capacity(instances.len() * ShaderInstance::SHADER_SIZE.get() as usize));
+    data.write(instances).unwrap();
+    queue.write_buffer(buffer, offset as u64 * ShaderInstance::SHADER_SIZE.get(), &data.into_inner());
+}
+
 /// Internal representation of a Object.
 pub struct InternalObject<M: Material> {
     pub mesh_kind: ObjectMeshKind,
@@ -64,17 +143,40 @@ pub struct InternalObject<M: Material> {
     /// World space
     pub location: Vec3A,
     pub mesh_bounding_sphere: BoundingSphere,
+    pub mesh_bounding_box: BoundingBox,
+    /// World space bounding box, for CPU culling.
+    pub bounding_box: BoundingBox,
+    /// Format of the indices of the mesh, which `inner.first_index` counts in.
+    pub index_format: IndexFormat,
+    /// Indices of the lower levels of detail of the mesh, in the same units
+    /// as `inner.first_index`.
+    pub lod_index_ranges: Vec<Range<u32>>,
     pub inner: ShaderObject<M>,
 }
 
+impl<M: Material> InternalObject<M> {
+    /// Indices of the given level of detail of the mesh, clamped to its least
+    /// detailed level. Level 0 is the mesh itself.
+    pub fn lod_index_range(&self, lod: u32) -> Range<u32> {
+        match (lod as usize).min(self.lod_index_ranges.len()) {
+            0 => self.inner.first_index..self.inner.first_index + self.inner.index_count,
+            lod => self.lod_index_ranges[lod - 1].clone(),
+        }
+    }
+}
+
 // Manual impl so that M: !Clone
 impl<M: Material> Clone for InternalObject<M> {
     fn clone(&self) -> Self {
         Self {
             mesh_kind: self.mesh_kind.clone(),
             mesh_bounding_sphere: self.mesh_bounding_sphere,
+            mesh_bounding_box: self.mesh_bounding_box,
+            bounding_box: self.bounding_box,
             material_handle: self.material_handle.clone(),
             location: self.location,
+            index_format: self.index_format,
+            lod_index_ranges: self.lod_index_ranges.clone(),
             inner: self.inner,
         }
     }
@@ -85,22 +187,98 @@ struct ObjectArchetype {
     data_vec: WasmVecAny,
     object_count: usize,
     buffer: FreelistDerivedBuffer,
-    set_object_transform: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, Mat4),
-    duplicate_object: fn(&WasmVecAny, usize, ObjectChange) -> Object,
+    set_object_transform: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, Mat4, bool),
+    sync_previous_transform: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize),
+    translate_object: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, Vec3),
+    set_object_custom_data: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, Vec4),
+    set_object_opacity: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, f32),
+    set_object_lod_fade: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, f32),
+    set_object_receives_shadows: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, bool),
+    set_instance_bounds: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, &[ShaderInstance]),
+    duplicate_object: fn(&WasmVecAny, usize, ObjectChange) -> (Object, Vec4, f32),
+    object_transform: fn(&WasmVecAny, usize) -> (Mat4, Option<RawSkeletonHandle>),
+    bounds: fn(&WasmVecAny) -> Option<BoundingBox>,
     remove: fn(&mut ObjectArchetype, usize),
     evaluate: fn(&mut ObjectArchetype, &Device, &mut CommandEncoder, &ScatterCopy),
 }
 
+/// Where an object is attached, see [`ObjectManager::set_object_parent`].
+struct HierarchyNode {
+    parent: RawObjectHandle,
+    joint: Option<usize>,
+    /// Transform relative to the parent.
+    local_transform: Mat4,
+}
+
 /// Manages objects. That's it. ¯\\\_(ツ)\_/¯
 pub struct ObjectManager {
     archetype: FastHashMap<TypeId, ObjectArchetype>,
     handle_to_typeid: FastHashMap<RawObjectHandle, TypeId>,
+    static_objects: FastHashSet<RawObjectHandle>,
+    static_generation: u64,
+    /// Objects with shadow settings other than the default.
+    shadow_settings: FastHashMap<RawObjectHandle, ObjectShadowSettings>,
+    /// Objects with a render order other than the default.
+    render_orders: FastHashMap<RawObjectHandle, ObjectRenderOrder>,
+    /// Objects with a level of detail other than the default.
+    lods: FastHashMap<RawObjectHandle, ObjectLod>,
+    /// Objects with a fade mode other than the default.
+    fade_modes: FastHashMap<RawObjectHandle, ObjectFadeMode>,
+    /// Objects with a label.
+    labels: FastHashMap<RawObjectHandle, Arc<str>>,
+    /// Objects whose mesh had a label when they were added.
+    mesh_labels: FastHashMap<RawObjectHandle, Arc<str>>,
+    /// Objects whose transform changed this frame, and so differs from the
+    /// previous transform.
+    moved: FastHashSet<RawObjectHandle>,
+    /// Objects attached to a parent.
+    parents: FastHashMap<RawObjectHandle, HierarchyNode>,
+    children: FastHashMap<RawObjectHandle, Vec<RawObjectHandle>>,
+    /// Objects in the hierarchy whose world transform needs updating, along
+    /// with their descendants.
+    hierarchy_dirty: FastHashSet<RawObjectHandle>,
+    /// Instances of instanced objects.
+    instances: FastHashMap<RawObjectHandle, InstanceList>,
+    /// Instanced objects whose instances moved this frame.
+    moved_instances: FastHashSet<RawObjectHandle>,
+    /// Instances of all instanced objects. Only grows, and only the changed
+    /// instances are written to it.
+    instance_buffer: Option<Buffer>,
+    /// Number of instances [`Self::instance_buffer`] has room for.
+    instance_capacity: usize,
+    /// Instanced objects whose instances changed without changing the number
+    /// of them.
+    dirty_instances: FastHashSet<RawObjectHandle>,
+    /// Set when instanced objects are added, removed or resized, so the
+    /// instances need to be packed again.
+    instances_relayout: bool,
 }
 impl ObjectManager {
     pub fn new() -> Self {
         profiling::scope!("ObjectManager::new");
 
-        Self { archetype: FastHashMap::default(), handle_to_typeid: FastHashMap::default() }
+        Self {
+            archetype: FastHashMap::default(),
+            handle_to_typeid: FastHashMap::default(),
+            static_objects: FastHashSet::default(),
+            static_generation: 0,
+            shadow_settings: FastHashMap::default(),
+            render_orders: FastHashMap::default(),
+            lods: FastHashMap::default(),
+            fade_modes: FastHashMap::default(),
+            labels: FastHashMap::default(),
+            mesh_labels: FastHashMap::default(),
+            moved: FastHashSet::default(),
+            parents: FastHashMap::default(),
+            children: FastHashMap::default(),
+            hierarchy_dirty: FastHashSet::default(),
+            instances: FastHashMap::default(),
+            moved_instances: FastHashSet::default(),
+            instance_buffer: None,
+            instance_capacity: 0,
+            dirty_instances: FastHashSet::default(),
+            instances_relayout: false,
+        }
     }
 
     fn ensure_archetype<M: Material>(&mut self, device: &Device) -> &mut ObjectArchetype {
@@ -110,7 +288,16 @@ impl ObjectManager {
             object_count: 0,
             buffer: FreelistDerivedBuffer::new::<ShaderObject<M>>(device),
             set_object_transform: set_object_transform::<M>,
+            sync_previous_transform: sync_previous_transform::<M>,
+            translate_object: translate_object::<M>,
+            set_object_custom_data: set_object_custom_data::<M>,
+            set_object_opacity: set_object_opacity::<M>,
+            set_object_lod_fade: set_object_lod_fade::<M>,
+            set_object_receives_shadows: set_object_receives_shadows::<M>,
+            set_instance_bounds: set_instance_bounds::<M>,
             duplicate_object: duplicate_object::<M>,
+            object_transform: object_transform::<M>,
+            bounds: bounds::<M>,
             remove: remove::<M>,
             evaluate: evaluate::<M>,
         })
@@ -125,45 +312,610 @@ impl ObjectManager {
         skeleton_manager: &SkeletonManager,
         material_manager: &mut MaterialManager,
     ) {
+        profiling::scope!("ObjectManager::add");
+
         let mesh_manager_guard = mesh_manager.lock_internal_data();
-        let (internal_mesh, skeleton_ranges) = match &object.mesh_kind {
+        let material = *object.material;
+        let pending = self.pending_object(&mesh_manager_guard, skeleton_manager, handle, object);
+
+        material_manager.call_object_add_callback(
+            material,
+            ObjectAddCallbackArgs { device, manager: self, objects: vec![pending] },
+        );
+    }
+
+    /// Adds many objects at once, like [`Self::add`] on each of them.
+    ///
+    /// The mesh data is locked once, and the objects are added a material
+    /// type at a time, building the objects of large batches in parallel.
+    pub fn add_many(
+        &mut self,
+        device: &Device,
+        objects: Vec<(RawObjectHandle, Object)>,
+        mesh_manager: &MeshManager,
+        skeleton_manager: &SkeletonManager,
+        material_manager: &mut MaterialManager,
+    ) {
+        profiling::scope!("ObjectManager::add_many");
+
+        let mesh_manager_guard = mesh_manager.lock_internal_data();
+        let mut by_material_type = FastHashMap::<TypeId, (RawMaterialHandle, Vec<PendingObject<'_>>)>::default();
+        for (handle, object) in objects {
+            let material = *object.material;
+            let pending = self.pending_object(&mesh_manager_guard, skeleton_manager, handle, object);
+            by_material_type
+                .entry(material_manager.material_type_id(material))
+                .or_insert_with(|| (material, Vec::new()))
+                .1
+                .push(pending);
+        }
+
+        for (material, objects) in by_material_type.into_values() {
+            material_manager
+                .call_object_add_callback(material, ObjectAddCallbackArgs { device, manager: self, objects });
+        }
+    }
+
+    /// Looks up the mesh data `object` is added from.
+    fn pending_object<'a>(
+        &mut self,
+        mesh_manager_guard: &'a LockedInternalMeshDataArray<'_>,
+        skeleton_manager: &'a SkeletonManager,
+        handle: RawObjectHandle,
+        object: Object,
+    ) -> PendingObject<'a> {
+        let (internal_mesh, skeleton_ranges, previous_positions) = match &object.mesh_kind {
             ObjectMeshKind::Animated(skeleton) => {
                 let skeleton = skeleton_manager.internal_data(**skeleton);
                 let mesh = &mesh_manager_guard[*skeleton.mesh_handle];
-                (mesh, &*skeleton.overridden_attribute_ranges)
+                (mesh, &*skeleton.overridden_attribute_ranges, skeleton.previous_position_range.clone())
             }
             ObjectMeshKind::Static(mesh) => {
                 let mesh = &mesh_manager_guard[**mesh];
-                (mesh, &[][..])
+                (mesh, &[][..], None)
             }
         };
+        let previous_position_offset = previous_positions
+            .or_else(|| internal_mesh.get_attribute(VERTEX_ATTRIBUTE_POSITION.id()))
+            .map_or(u32::MAX, |range| range.start as u32);
+        if let Some(label) = &internal_mesh.label {
+            self.mesh_labels.insert(handle, Arc::clone(label));
+        }
 
-        material_manager.call_object_add_callback(
-            *object.material,
-            ObjectAddCallbackArgs { device, manager: self, internal_mesh, skeleton_ranges, handle, object },
-        );
+        PendingObject { internal_mesh, skeleton_ranges, previous_position_offset, handle, object }
     }
 
+    /// Sets the transform of an object, which is relative to its parent if it
+    /// has one.
     pub fn set_object_transform(&mut self, handle: RawObjectHandle, transform: Mat4) {
+        if let Some(node) = self.parents.get_mut(&handle) {
+            node.local_transform = transform;
+            self.hierarchy_dirty.insert(handle);
+            return;
+        }
+
+        self.set_world_transform(handle, transform);
+        if self.children.contains_key(&handle) {
+            self.hierarchy_dirty.insert(handle);
+        }
+    }
+
+    fn set_world_transform(&mut self, handle: RawObjectHandle, transform: Mat4) {
         let type_id = self.handle_to_typeid[&handle];
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
-        (archetype.set_object_transform)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, transform);
+        let first_this_frame = self.moved.insert(handle);
+        (archetype.set_object_transform)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            handle.idx,
+            transform,
+            first_this_frame,
+        );
+
+        self.static_changed(handle);
     }
 
-    pub fn remove(&mut self, handle: RawObjectHandle) {
+    pub fn set_object_transforms(&mut self, transforms: &[(RawObjectHandle, Mat4)]) {
+        profiling::scope!("ObjectManager::set_object_transforms");
+
+        for &(handle, transform) in transforms {
+            self.set_object_transform(handle, transform);
+        }
+    }
+
+    /// Moves every object by `offset`, along with its previous transform, so
+    /// rebasing the world origin doesn't show up in motion vectors. Local
+    /// transforms of attached objects are unchanged.
+    pub fn translate_all(&mut self, offset: Vec3) {
+        profiling::scope!("ObjectManager::translate_all");
+
+        for (&handle, type_id) in &self.handle_to_typeid {
+            let archetype = self.archetype.get_mut(type_id).unwrap();
+            (archetype.translate_object)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, offset);
+        }
+        let translation = Mat4::from_translation(offset);
+        for (&handle, list) in &mut self.instances {
+            for instance in &mut list.instances {
+                instance.transform = translation * instance.transform;
+                instance.previous_transform = translation * instance.previous_transform;
+            }
+            list.mark_dirty(0..list.instances.len());
+            self.dirty_instances.insert(handle);
+        }
+        if !self.static_objects.is_empty() {
+            self.static_generation += 1;
+        }
+    }
+
+    /// Replaces the instances of an instanced object, making it one if it
+    /// wasn't. Instances that already existed keep their previous transform.
+    pub fn set_instances(&mut self, handle: RawObjectHandle, instances: Vec<Instance>) {
+        profiling::scope!("ObjectManager::set_instances");
+
+        let first_this_frame = self.moved_instances.insert(handle);
+        let list = self.instances.entry(handle).or_insert_with(|| {
+            self.instances_relayout = true;
+            InstanceList { instances: Vec::new(), offset: 0, dirty: None }
+        });
+        let old = std::mem::take(&mut list.instances);
+        list.instances = instances
+            .into_iter()
+            .enumerate()
+            .map(|(idx, instance)| {
+                let previous_transform = match old.get(idx) {
+                    Some(old) if first_this_frame => old.transform,
+                    Some(old) => old.previous_transform,
+                    None => instance.transform,
+                };
+                ShaderInstance::new(instance, previous_transform)
+            })
+            .collect();
+        let len = list.instances.len();
+        if len != old.len() {
+            self.instances_relayout = true;
+        }
+
+        self.instances_changed(handle, 0..len);
+    }
+
+    /// Sets the transforms of the instances of an instanced object starting
+    /// at instance `start`, which must all exist.
+    pub fn set_instance_transforms(&mut self, handle: RawObjectHandle, start: usize, transforms: &[Mat4]) {
+        profiling::scope!("ObjectManager::set_instance_transforms");
+
+        let first_this_frame = self.moved_instances.insert(handle);
+        let list = self.instances.get_mut(&handle).unwrap();
+        let range = start..start + transforms.len();
+        for (instance, &transform) in list.instances[range.clone()].iter_mut().zip(transforms) {
+            if first_this_frame {
+                instance.previous_transform = instance.transform;
+            }
+            instance.transform = transform;
+        }
+
+        self.instances_changed(handle, range);
+    }
+
+    /// Schedules the changed instances of an instanced object to be written
+    /// to the instance buffer, and fits its bounds around them.
+    fn instances_changed(&mut self, handle: RawObjectHandle, range: Range<usize>) {
+        self.instances.get_mut(&handle).unwrap().mark_dirty(range);
+        self.dirty_instances.insert(handle);
+        self.fit_instance_bounds(handle);
+    }
+
+    /// Fits the bounds of an instanced object around its instances, so it is
+    /// culled as a whole on the CPU.
+    fn fit_instance_bounds(&mut self, handle: RawObjectHandle) {
+        let archetype = self.archetype.get_mut(&self.handle_to_typeid[&handle]).unwrap();
+        (archetype.set_instance_bounds)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            handle.idx,
+            &self.instances[&handle].instances,
+        );
+        self.static_changed(handle);
+    }
+
+    /// Whether the object was added with
+    /// [`Renderer::add_instanced_object`](crate::Renderer::add_instanced_object).
+    pub fn is_instanced(&self, handle: RawObjectHandle) -> bool {
+        self.instances.contains_key(&handle)
+    }
+
+    /// The instances of an instanced object, along with the index of the
+    /// first one in [`Self::instance_buffer`].
+    pub fn instances(&self, handle: RawObjectHandle) -> Option<(u32, &[ShaderInstance])> {
+        let list = self.instances.get(&handle)?;
+        Some((list.offset, &list.instances))
+    }
+
+    /// Buffer of the [`ShaderInstance`]s of every instanced object. `None`
+    /// until the first instances are added.
+    pub fn instance_buffer(&self) -> Option<&Buffer> {
+        self.instance_buffer.as_ref()
+    }
+
+    pub fn set_object_custom_data(&mut self, handle: RawObjectHandle, custom_data: Vec4) {
+        let type_id = self.handle_to_typeid[&handle];
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        (archetype.set_object_custom_data)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, custom_data);
+    }
+
+    pub fn set_object_opacity(&mut self, handle: RawObjectHandle, opacity: f32) {
         let type_id = self.handle_to_typeid[&handle];
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
+        (archetype.set_object_opacity)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, opacity);
+
+        self.static_changed(handle);
+    }
+
+    pub fn set_object_static(&mut self, handle: RawObjectHandle, is_static: bool) {
+        let changed = match is_static {
+            true => self.static_objects.insert(handle),
+            false => self.static_objects.remove(&handle),
+        };
+        if changed {
+            self.static_generation += 1;
+        }
+    }
+
+    /// Whether the object was marked static with
+    /// [`Renderer::set_object_static`](crate::Renderer::set_object_static).
+    pub fn is_static(&self, handle: RawObjectHandle) -> bool {
+        self.static_objects.contains(&handle)
+    }
+
+    /// Changes every time a static object is added, changed, or removed.
+    pub fn static_generation(&self) -> u64 {
+        self.static_generation
+    }
+
+    pub fn set_object_shadow_settings(&mut self, handle: RawObjectHandle, settings: ObjectShadowSettings) {
+        let type_id = self.handle_to_typeid[&handle];
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        (archetype.set_object_receives_shadows)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            handle.idx,
+            settings.receives_shadows,
+        );
+
+        let previous = match settings == ObjectShadowSettings::default() {
+            true => self.shadow_settings.remove(&handle),
+            false => self.shadow_settings.insert(handle, settings),
+        };
+        if previous.unwrap_or_default() != settings {
+            self.static_changed(handle);
+        }
+    }
+
+    /// The settings set with
+    /// [`Renderer::set_object_shadow_settings`](crate::Renderer::set_object_shadow_settings).
+    pub fn shadow_settings(&self, handle: RawObjectHandle) -> ObjectShadowSettings {
+        self.shadow_settings.get(&handle).copied().unwrap_or_default()
+    }
+
+    pub fn set_object_render_order(&mut self, handle: RawObjectHandle, order: ObjectRenderOrder) {
+        match order == ObjectRenderOrder::default() {
+            true => self.render_orders.remove(&handle),
+            false => self.render_orders.insert(handle, order),
+        };
+    }
+
+    /// The order set with
+    /// [`Renderer::set_object_render_order`](crate::Renderer::set_object_render_order).
+    pub fn render_order(&self, handle: RawObjectHandle) -> ObjectRenderOrder {
+        self.render_orders.get(&handle).copied().unwrap_or_default()
+    }
+
+    pub fn set_object_lod(&mut self, handle: RawObjectHandle, lod: ObjectLod) {
+        let type_id = self.handle_to_typeid[&handle];
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        let fade = if lod.is_cross_fading() { lod.fade } else { 1.0 };
+        (archetype.set_object_lod_fade)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, fade);
+
+        match lod == ObjectLod::default() {
+            true => self.lods.remove(&handle),
+            false => self.lods.insert(handle, lod),
+        };
+
+        self.static_changed(handle);
+    }
+
+    /// The level of detail set with
+    /// [`Renderer::set_object_lod`](crate::Renderer::set_object_lod).
+    pub fn lod(&self, handle: RawObjectHandle) -> ObjectLod {
+        self.lods.get(&handle).copied().unwrap_or_default()
+    }
+
+    pub fn set_object_fade_mode(&mut self, handle: RawObjectHandle, mode: ObjectFadeMode) {
+        let previous = match mode == ObjectFadeMode::default() {
+            true => self.fade_modes.remove(&handle),
+            false => self.fade_modes.insert(handle, mode),
+        };
+        // Blended objects don't cast shadows.
+        if previous.unwrap_or_default() != mode {
+            self.static_changed(handle);
+        }
+    }
+
+    /// The mode set with
+    /// [`Renderer::set_object_fade_mode`](crate::Renderer::set_object_fade_mode).
+    pub fn fade_mode(&self, handle: RawObjectHandle) -> ObjectFadeMode {
+        self.fade_modes.get(&handle).copied().unwrap_or_default()
+    }
+
+    /// Key and sorting the object is drawn with: those of its material, or
+    /// the faded ones while its opacity is below 1.
+    pub fn material_key<M: Material>(
+        &self,
+        handle: RawObjectHandle,
+        object: &InternalObject<M>,
+        material: &M,
+    ) -> (u64, Sorting) {
+        match object.inner.opacity < 1.0 {
+            true => {
+                let mode = self.fade_mode(handle);
+                (material.faded_key(mode), material.faded_sorting(mode))
+            }
+            false => (material.key(), material.sorting()),
+        }
+    }
+
+    pub fn set_object_label(&mut self, handle: RawObjectHandle, label: Option<Arc<str>>) {
+        match label {
+            Some(label) => self.labels.insert(handle, label),
+            None => self.labels.remove(&handle),
+        };
+    }
+
+    /// The label set with
+    /// [`Renderer::set_object_label`](crate::Renderer::set_object_label).
+    pub fn label(&self, handle: RawObjectHandle) -> Option<&str> {
+        self.labels.get(&handle).map(|label| &**label)
+    }
+
+    /// The label of the mesh of the object, see
+    /// [`MeshBuilder::with_label`](crate::types::MeshBuilder::with_label).
+    pub fn mesh_label(&self, handle: RawObjectHandle) -> Option<&str> {
+        self.mesh_labels.get(&handle).map(|label| &**label)
+    }
+
+    /// Attaches `child` to a parent object and optionally one of the parent's
+    /// joints, or detaches it. The current transform of the child becomes its
+    /// transform relative to the parent; detached children keep their world
+    /// transform.
+    pub fn set_object_parent(&mut self, child: RawObjectHandle, parent: Option<(RawObjectHandle, Option<usize>)>) {
+        if let Some(node) = self.parents.remove(&child) {
+            self.remove_child(node.parent, child);
+        }
+        let Some((parent, joint)) = parent else {
+            return;
+        };
+        if self.is_ancestor(child, parent) {
+            log::warn!("Object {} can't be attached to itself or its descendant {}", child.idx, parent.idx);
+            return;
+        }
+
+        let (local_transform, _) = self.object_transform(child);
+        self.parents.insert(child, HierarchyNode { parent, joint, local_transform });
+        self.children.entry(parent).or_default().push(child);
+        self.hierarchy_dirty.insert(child);
+    }
+
+    /// Updates the world transforms of the objects whose parent or transform
+    /// changed, and their descendants. Objects attached to joints are always
+    /// updated, as joints move without the object manager knowing.
+    pub fn update_hierarchy(&mut self, skeleton_manager: &SkeletonManager) {
+        profiling::scope!("ObjectManager::update_hierarchy");
+
+        let mut dirty = std::mem::take(&mut self.hierarchy_dirty);
+        dirty.extend(self.parents.iter().filter(|(_, node)| node.joint.is_some()).map(|(&handle, _)| handle));
+
+        // Descendants are updated along with their ancestor.
+        let mut stack: Vec<_> = dirty
+            .iter()
+            .copied()
+            .filter(|&handle| {
+                let mut handle = handle;
+                while let Some(node) = self.parents.get(&handle) {
+                    if dirty.contains(&node.parent) {
+                        return false;
+                    }
+                    handle = node.parent;
+                }
+                true
+            })
+            .collect();
+
+        while let Some(handle) = stack.pop() {
+            if let Some(node) = self.parents.get(&handle) {
+                let (parent_transform, skeleton) = self.object_transform(node.parent);
+                let joint_matrix = match (node.joint, skeleton) {
+                    (Some(joint), Some(skeleton)) => {
+                        let joint_matrices = &skeleton_manager.internal_data(skeleton).joint_matrices;
+                        joint_matrices.get(joint).copied().unwrap_or(Mat4::IDENTITY)
+                    }
+                    _ => Mat4::IDENTITY,
+                };
+                let transform = parent_transform * joint_matrix * node.local_transform;
+                self.set_world_transform(handle, transform);
+            }
+            if let Some(children) = self.children.get(&handle) {
+                stack.extend_from_slice(children);
+            }
+        }
+    }
+
+    /// Whether `ancestor` is `handle` or one of its ancestors.
+    fn is_ancestor(&self, ancestor: RawObjectHandle, mut handle: RawObjectHandle) -> bool {
+        loop {
+            if handle == ancestor {
+                return true;
+            }
+            match self.parents.get(&handle) {
+                Some(node) => handle = node.parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn remove_child(&mut self, parent: RawObjectHandle, child: RawObjectHandle) {
+        if let Some(children) = self.children.get_mut(&parent) {
+            children.retain(|&c| c != child);
+            if children.is_empty() {
+                self.children.remove(&parent);
+            }
+        }
+    }
+
+    fn object_transform(&self, handle: RawObjectHandle) -> (Mat4, Option<RawSkeletonHandle>) {
+        let archetype = &self.archetype[&self.handle_to_typeid[&handle]];
+        (archetype.object_transform)(&archetype.data_vec, handle.idx)
+    }
+
+    fn static_changed(&mut self, handle: RawObjectHandle) {
+        if self.static_objects.contains(&handle) {
+            self.static_generation += 1;
+        }
+    }
+
+    pub fn remove(&mut self, handle: RawObjectHandle) {
+        profiling::scope!("ObjectManager::remove");
+
+        self.remove_object(handle);
+    }
+
+    /// Removes many objects at once, like [`Self::remove`] on each of them.
+    pub fn remove_many(&mut self, handles: &[RawObjectHandle]) {
+        profiling::scope!("ObjectManager::remove_many");
+
+        for &handle in handles {
+            self.remove_object(handle);
+        }
+    }
+
+    fn remove_object(&mut self, handle: RawObjectHandle) {
+        let type_id = self.handle_to_typeid.remove(&handle).unwrap();
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
         (archetype.remove)(archetype, handle.idx);
+
+        self.set_object_static(handle, false);
+        self.shadow_settings.remove(&handle);
+        self.render_orders.remove(&handle);
+        self.lods.remove(&handle);
+        self.fade_modes.remove(&handle);
+        self.labels.remove(&handle);
+        self.mesh_labels.remove(&handle);
+        self.moved.remove(&handle);
+        if self.instances.remove(&handle).is_some() {
+            self.moved_instances.remove(&handle);
+            self.dirty_instances.remove(&handle);
+            self.instances_relayout = true;
+        }
+
+        if let Some(node) = self.parents.remove(&handle) {
+            self.remove_child(node.parent, handle);
+        }
+        for child in self.children.remove(&handle).unwrap_or_default() {
+            self.parents.remove(&child);
+        }
+        self.hierarchy_dirty.remove(&handle);
     }
 
-    pub fn evaluate(&mut self, device: &Device, encoder: &mut CommandEncoder, scatter: &ScatterCopy) {
+    /// Makes the previous transform of the objects that moved last frame
+    /// match their current transform. Must be called at the start of every
+    /// frame, before any transform changes.
+    pub fn begin_frame(&mut self) {
+        for handle in self.moved.drain() {
+            let archetype = self.archetype.get_mut(&self.handle_to_typeid[&handle]).unwrap();
+            (archetype.sync_previous_transform)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx);
+        }
+        for handle in self.moved_instances.drain() {
+            let list = self.instances.get_mut(&handle).unwrap();
+            for instance in &mut list.instances {
+                instance.previous_transform = instance.transform;
+            }
+            list.mark_dirty(0..list.instances.len());
+            self.dirty_instances.insert(handle);
+        }
+    }
+
+    pub fn evaluate(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, scatter: &ScatterCopy) {
+        profiling::scope!("ObjectManager::evaluate");
+
         for archetype in self.archetype.values_mut() {
             (archetype.evaluate)(archetype, device, encoder, scatter);
         }
+
+        if self.instances_relayout {
+            self.relayout_instances(device, queue);
+        } else {
+            self.write_dirty_instances(queue);
+        }
+    }
+
+    /// Packs the instances of every instanced object and writes all of them,
+    /// growing the instance buffer if they don't fit.
+    fn relayout_instances(&mut self, device: &Device, queue: &Queue) {
+        profiling::scope!("ObjectManager::relayout_instances");
+
+        self.instances_relayout = false;
+        self.dirty_instances.clear();
+        let count: usize = self.instances.values().map(|list| list.instances.len()).sum();
+        if count == 0 {
+            return;
+        }
+
+        let mut instances = Vec::with_capacity(count);
+        for list in self.instances.values_mut() {
+            list.offset = instances.len() as u32;
+            list.dirty = None;
+            instances.extend_from_slice(&list.instances);
+        }
+
+        if count > self.instance_capacity {
+            self.instance_capacity = count.next_power_of_two();
+            self.instance_buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some("instance buffer"),
+                size: self.instance_capacity as u64 * ShaderInstance::SHADER_SIZE.get(),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+
+        write_instances(queue, self.instance_buffer.as_ref().unwrap(), 0, &instances);
+    }
+
+    /// Writes the instances that changed in place, leaving the rest of the
+    /// instance buffer alone.
+    fn write_dirty_instances(&mut self, queue: &Queue) {
+        profiling::scope!("ObjectManager::write_dirty_instances");
+
+        let Some(buffer) = &self.instance_buffer else {
+            self.dirty_instances.clear();
+            return;
+        };
+        for handle in self.dirty_instances.drain() {
+            let list = self.instances.get_mut(&handle).unwrap();
+            if let Some(range) = list.dirty.take() {
+                let offset = list.offset as usize + range.start;
+                write_instances(queue, buffer, offset, &list.instances[range]);
+            }
+        }
     }
 
     pub fn buffer<M: Material>(&self) -> Option<&Buffer> {
@@ -188,6 +940,79 @@ impl ObjectManager {
         Some(ExactSizerIterator::new(iter, archetype.object_count))
     }
 
+    /// Re-adds the objects whose mesh matches `uses`, picking up the new data
+    /// of meshes and skeletons that were replaced. Everything else about the
+    /// objects is kept.
+    pub fn refresh_objects(
+        &mut self,
+        device: &Device,
+        mesh_manager: &MeshManager,
+        skeleton_manager: &SkeletonManager,
+        material_manager: &mut MaterialManager,
+        uses: impl Fn(&ObjectMeshKind) -> bool,
+    ) {
+        profiling::scope!("ObjectManager::refresh_objects");
+
+        for (handle, object, custom_data, opacity) in self.objects() {
+            if !uses(&object.mesh_kind) {
+                continue;
+            }
+            let archetype = self.archetype.get_mut(&self.handle_to_typeid[&handle]).unwrap();
+            (archetype.remove)(archetype, handle.idx);
+
+            self.add(device, handle, object, mesh_manager, skeleton_manager, material_manager);
+            if custom_data != Vec4::ZERO {
+                self.set_object_custom_data(handle, custom_data);
+            }
+            if opacity != 1.0 {
+                self.set_object_opacity(handle, opacity);
+            }
+            if let Some(&lod) = self.lods.get(&handle) {
+                self.set_object_lod(handle, lod);
+            }
+            if let Some(&settings) = self.shadow_settings.get(&handle) {
+                self.set_object_shadow_settings(handle, settings);
+            }
+            if self.instances.contains_key(&handle) {
+                self.fit_instance_bounds(handle);
+            }
+            self.static_changed(handle);
+        }
+    }
+
+    /// World space bounding box of all objects.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        self.archetype
+            .values()
+            .filter_map(|archetype| (archetype.bounds)(&archetype.data_vec))
+            .reduce(BoundingBox::union)
+    }
+
+    /// Every object deformed by a skeleton, with its world transform and
+    /// skeleton.
+    pub fn skinned_objects(&self) -> impl Iterator<Item = (RawObjectHandle, Mat4, RawSkeletonHandle)> + '_ {
+        self.handle_to_typeid.keys().filter_map(|&handle| match self.object_transform(handle) {
+            (transform, Some(skeleton)) => Some((handle, transform, skeleton)),
+            (_, None) => None,
+        })
+    }
+
+    /// Every object ordered by handle, with its custom data and opacity.
+    pub fn objects(&self) -> Vec<(RawObjectHandle, Object, Vec4, f32)> {
+        let mut handles: Vec<_> = self.handle_to_typeid.keys().copied().collect();
+        handles.sort_unstable_by_key(|handle| handle.idx);
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                let archetype = &self.archetype[&self.handle_to_typeid[&handle]];
+                let (object, custom_data, opacity) =
+                    (archetype.duplicate_object)(&archetype.data_vec, handle.idx, ObjectChange::default());
+                (handle, object, custom_data, opacity)
+            })
+            .collect()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn duplicate_object(
         &mut self,
@@ -199,13 +1024,40 @@ impl ObjectManager {
         skeleton_manager: &SkeletonManager,
         material_manager: &mut MaterialManager,
     ) {
+        profiling::scope!("ObjectManager::duplicate_object");
+
         let type_id = self.handle_to_typeid[&src_handle];
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
-        let dst_obj = (archetype.duplicate_object)(&mut archetype.data_vec, src_handle.idx, change);
+        let (dst_obj, custom_data, opacity) =
+            (archetype.duplicate_object)(&mut archetype.data_vec, src_handle.idx, change);
 
         self.add(device, dst_handle, dst_obj, mesh_manager, skeleton_manager, material_manager);
+        if custom_data != Vec4::ZERO {
+            self.set_object_custom_data(dst_handle, custom_data);
+        }
+        if opacity != 1.0 {
+            self.set_object_opacity(dst_handle, opacity);
+        }
+        if self.is_static(src_handle) {
+            self.set_object_static(dst_handle, true);
+        }
+        if let Some(&settings) = self.shadow_settings.get(&src_handle) {
+            self.set_object_shadow_settings(dst_handle, settings);
+        }
+        if let Some(&order) = self.render_orders.get(&src_handle) {
+            self.set_object_render_order(dst_handle, order);
+        }
+        if let Some(&lod) = self.lods.get(&src_handle) {
+            self.set_object_lod(dst_handle, lod);
+        }
+        if let Some(&mode) = self.fade_modes.get(&src_handle) {
+            self.set_object_fade_mode(dst_handle, mode);
+        }
+        if let Some(label) = self.labels.get(&src_handle) {
+            self.set_object_label(dst_handle, Some(Arc::clone(label)));
+        }
     }
 }
 
@@ -215,24 +1067,94 @@ impl Default for ObjectManager {
     }
 }
 
-pub(super) struct ObjectAddCallbackArgs<'a> {
-    device: &'a Device,
-    manager: &'a mut ObjectManager,
+/// An object about to be added, with the mesh data it is added from.
+pub(super) struct PendingObject<'a> {
     internal_mesh: &'a InternalMesh,
     skeleton_ranges: &'a [(VertexAttributeId, Range<u64>)],
+    previous_position_offset: u32,
     handle: RawObjectHandle,
     object: Object,
 }
 
+pub(super) struct ObjectAddCallbackArgs<'a> {
+    device: &'a Device,
+    manager: &'a mut ObjectManager,
+    /// Objects whose materials are all of the type of the callback.
+    objects: Vec<PendingObject<'a>>,
+}
+
+/// Batches at least this large are built on several threads.
+#[cfg(not(target_arch = "wasm32"))]
+const PARALLEL_ADD_THRESHOLD: usize = 4096;
+
 pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCallbackArgs<'_>) {
+    let ObjectAddCallbackArgs { device, manager, objects } = args;
+
+    let Some(max_idx) = objects.iter().map(|pending| pending.handle.idx).max() else {
+        return;
+    };
+    let count = objects.len();
+    let handles: Vec<_> = objects.iter().map(|pending| pending.handle).collect();
+    let internal_objects = build_internal_objects::<M>(objects);
+
+    let type_id = TypeId::of::<M>();
+    manager.handle_to_typeid.extend(handles.iter().map(|&handle| (handle, type_id)));
+    let archetype = manager.ensure_archetype::<M>(device);
+
+    let mut data_vec = archetype.data_vec.downcast_mut::<Option<InternalObject<M>>>().unwrap();
+    if max_idx >= data_vec.len() {
+        data_vec.resize_with((max_idx + 1).next_power_of_two(), || None);
+    }
+    for (handle, internal_object) in handles.into_iter().zip(internal_objects) {
+        data_vec[handle.idx] = Some(internal_object);
+        archetype.buffer.use_index(handle.idx);
+    }
+    archetype.object_count += count;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn build_internal_objects<M: Material>(objects: Vec<PendingObject<'_>>) -> Vec<InternalObject<M>> {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    if objects.len() < PARALLEL_ADD_THRESHOLD || threads == 1 {
+        return objects.into_iter().map(internal_object::<M>).collect();
+    }
+
+    profiling::scope!("build objects in parallel");
+
+    let chunk_size = (objects.len() + threads - 1) / threads;
+    let mut chunks = Vec::with_capacity(threads);
+    let mut rest = objects;
+    while rest.len() > chunk_size {
+        let tail = rest.split_off(chunk_size);
+        chunks.push(rest);
+        rest = tail;
+    }
+    chunks.push(rest);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || chunk.into_iter().map(internal_object::<M>).collect::<Vec<_>>()))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn build_internal_objects<M: Material>(objects: Vec<PendingObject<'_>>) -> Vec<InternalObject<M>> {
+    objects.into_iter().map(internal_object::<M>).collect()
+}
+
+fn internal_object<M: Material>(pending: PendingObject<'_>) -> InternalObject<M> {
     // Make sure all required attributes are in the mesh and the supported attribute list.
     for &required_attribute in M::required_attributes().into_iter() {
         // We can just directly use the internal mesh, as every attribute in the skeleton is also in the mesh.
-        let found_in_mesh = args.internal_mesh.vertex_attribute_ranges.iter().any(|&(id, _)| id == required_attribute);
+        let found_in_mesh =
+            pending.internal_mesh.vertex_attribute_ranges.iter().any(|&(id, _)| id == required_attribute);
 
         // Check that our required attributes are in the supported one.
         let found_in_supported =
-            args.internal_mesh.vertex_attribute_ranges.iter().any(|&(id, _)| id == required_attribute);
+            pending.internal_mesh.vertex_attribute_ranges.iter().any(|&(id, _)| id == required_attribute);
 
         assert!(found_in_mesh);
         assert!(found_in_supported);
@@ -241,14 +1163,17 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
     let vertex_attribute_start_offsets = M::supported_attributes().map_to_u32(|&supported_attribute| {
         // We first check the skeleton for the attribute's base offset.
         let found_start_offset =
-            args.skeleton_ranges.iter().find_map(|(id, range)| (*id == supported_attribute).then_some(range.start));
+            pending.skeleton_ranges.iter().find_map(|(id, range)| (*id == supported_attribute).then_some(range.start));
 
         if let Some(start_offset) = found_start_offset {
             return start_offset as u32;
         }
 
         // After the skeleton, check the mesh for non-overriden attributes.
-        match args.internal_mesh.get_attribute(&supported_attribute) {
+        match pending.internal_mesh.get_attribute(&supported_attribute) {
+            Some(range) if pending.internal_mesh.attribute_packing(&supported_attribute).is_some() => {
+                range.start as u32 | PACKED_VERTEX_ATTRIBUTE_BIT
+            }
             Some(range) => range.start as u32,
             // If the attribute isn't there, push u32::MAX.
             None => u32::MAX,
@@ -256,37 +1181,45 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
     });
 
     // Transform the bounding sphere from model to world space.
-    let mesh_bounding_sphere = args.internal_mesh.bounding_sphere;
-    let bounding_sphere = mesh_bounding_sphere.apply_transform(args.object.transform);
-    let index_range = args.internal_mesh.index_range.clone();
+    let mesh_bounding_sphere = pending.internal_mesh.bounding_sphere;
+    let bounding_sphere = mesh_bounding_sphere.apply_transform(pending.object.transform);
+    let mesh_bounding_box = pending.internal_mesh.bounding_box;
+    let bounding_box = mesh_bounding_box.apply_transform(pending.object.transform);
 
-    let internal_object = InternalObject::<M> {
+    let first_index = pending.internal_mesh.first_index();
+    InternalObject::<M> {
         location: bounding_sphere.center.into(),
         mesh_bounding_sphere,
+        mesh_bounding_box,
+        bounding_box,
         inner: ShaderObject {
-            material_index: args.object.material.idx as u32,
-            transform: args.object.transform,
+            material_index: pending.object.material.idx as u32,
+            transform: pending.object.transform,
+            previous_transform: pending.object.transform,
             bounding_sphere,
-            first_index: (index_range.start / 4) as u32,
-            index_count: ((index_range.end - index_range.start) / 4) as u32,
+            custom_data: Vec4::ZERO,
+            opacity: 1.0,
+            lod_fade: 1.0,
+            receives_shadows: 1,
+            previous_position_offset: pending.previous_position_offset,
+            first_index,
+            index_count: pending.internal_mesh.index_count,
+            index_size: match pending.internal_mesh.index_format {
+                IndexFormat::Uint16 => 2,
+                IndexFormat::Uint32 => 4,
+            },
             vertex_attribute_start_offsets,
         },
-        material_handle: args.object.material,
-        mesh_kind: args.object.mesh_kind,
-    };
-
-    let type_id = TypeId::of::<M>();
-
-    args.manager.handle_to_typeid.insert(args.handle, type_id);
-    let archetype = args.manager.ensure_archetype::<M>(args.device);
-
-    let mut data_vec = archetype.data_vec.downcast_mut::<Option<InternalObject<M>>>().unwrap();
-    if args.handle.idx >= data_vec.len() {
-        data_vec.resize_with((args.handle.idx + 1).next_power_of_two(), || None);
+        material_handle: pending.object.material,
+        mesh_kind: pending.object.mesh_kind,
+        index_format: pending.internal_mesh.index_format,
+        lod_index_ranges: pending
+            .internal_mesh
+            .lod_index_ranges
+            .iter()
+            .map(|range| first_index + range.start..first_index + range.end)
+            .collect(),
     }
-    data_vec[args.handle.idx] = Some(internal_object);
-    archetype.object_count += 1;
-    archetype.buffer.use_index(args.handle.idx);
 }
 
 fn set_object_transform<M: Material>(
@@ -294,27 +1227,147 @@ fn set_object_transform<M: Material>(
     buffer: &mut FreelistDerivedBuffer,
     idx: usize,
     transform: Mat4,
+    first_this_frame: bool,
 ) {
     let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
 
     let object = data_vec[idx].as_mut().unwrap();
+    if first_this_frame {
+        object.inner.previous_transform = object.inner.transform;
+    }
     object.inner.transform = transform;
     object.inner.bounding_sphere = object.mesh_bounding_sphere.apply_transform(transform);
+    object.bounding_box = object.mesh_bounding_box.apply_transform(transform);
     object.location = transform.transform_point3a(Vec3A::ZERO);
 
     buffer.use_index(idx);
 }
 
-fn duplicate_object<M: Material>(data: &WasmVecAny, idx: usize, change: ObjectChange) -> Object {
+fn sync_previous_transform<M: Material>(data: &mut WasmVecAny, buffer: &mut FreelistDerivedBuffer, idx: usize) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    if let Some(object) = data_vec[idx].as_mut() {
+        object.inner.previous_transform = object.inner.transform;
+        buffer.use_index(idx);
+    }
+}
+
+fn translate_object<M: Material>(data: &mut WasmVecAny, buffer: &mut FreelistDerivedBuffer, idx: usize, offset: Vec3) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_mut().unwrap();
+    let translation = Mat4::from_translation(offset);
+    object.inner.previous_transform = translation * object.inner.previous_transform;
+    object.inner.transform = translation * object.inner.transform;
+    object.inner.bounding_sphere = object.mesh_bounding_sphere.apply_transform(object.inner.transform);
+    object.bounding_box = object.mesh_bounding_box.apply_transform(object.inner.transform);
+    object.location += Vec3A::from(offset);
+
+    buffer.use_index(idx);
+}
+
+fn set_object_custom_data<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    idx: usize,
+    custom_data: Vec4,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    data_vec[idx].as_mut().unwrap().inner.custom_data = custom_data;
+
+    buffer.use_index(idx);
+}
+
+fn set_object_opacity<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    idx: usize,
+    opacity: f32,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    data_vec[idx].as_mut().unwrap().inner.opacity = opacity;
+
+    buffer.use_index(idx);
+}
+
+fn set_object_lod_fade<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    idx: usize,
+    lod_fade: f32,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    data_vec[idx].as_mut().unwrap().inner.lod_fade = lod_fade;
+
+    buffer.use_index(idx);
+}
+
+fn set_object_receives_shadows<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    idx: usize,
+    receives_shadows: bool,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    data_vec[idx].as_mut().unwrap().inner.receives_shadows = receives_shadows as u32;
+
+    buffer.use_index(idx);
+}
+
+fn set_instance_bounds<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    idx: usize,
+    instances: &[ShaderInstance],
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_mut().unwrap();
+    let bounding_box = instances
+        .iter()
+        .map(|instance| object.mesh_bounding_box.apply_transform(instance.transform))
+        .reduce(BoundingBox::union)
+        .unwrap_or_default();
+    let center = bounding_box.center();
+    object.bounding_box = bounding_box;
+    object.inner.bounding_sphere = BoundingSphere { center, radius: bounding_box.max.distance(center) };
+    object.location = center.into();
+
+    buffer.use_index(idx);
+}
+
+fn duplicate_object<M: Material>(data: &WasmVecAny, idx: usize, change: ObjectChange) -> (Object, Vec4, f32) {
     let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
 
     let src_obj = data_vec[idx].as_ref().unwrap();
 
-    Object {
+    let object = Object {
         mesh_kind: change.mesh_kind.unwrap_or_else(|| src_obj.mesh_kind.clone()),
         material: change.material.unwrap_or_else(|| src_obj.material_handle.clone()),
         transform: change.transform.unwrap_or(src_obj.inner.transform),
-    }
+    };
+    (object, src_obj.inner.custom_data, src_obj.inner.opacity)
+}
+
+fn object_transform<M: Material>(data: &WasmVecAny, idx: usize) -> (Mat4, Option<RawSkeletonHandle>) {
+    let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_ref().unwrap();
+    let skeleton = match object.mesh_kind {
+        ObjectMeshKind::Animated(ref skeleton) => Some(skeleton.get_raw()),
+        ObjectMeshKind::Static(_) => None,
+    };
+    (object.inner.transform, skeleton)
+}
+
+fn bounds<M: Material>(data: &WasmVecAny) -> Option<BoundingBox> {
+    let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
+
+    data_vec.iter().flatten().map(|object| object.bounding_box).reduce(BoundingBox::union)
 }
 
 fn remove<M: Material>(archetype: &mut ObjectArchetype, idx: usize) {
@@ -339,3 +1392,111 @@ fn evaluate<M: Material>(
 
     archetype.buffer.apply(device, encoder, scatter, |idx| data_vec[idx].as_ref().map(|o| o.inner).unwrap_or_default())
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+    use rend3_types::{
+        IndexFormat, Material, MaterialHandle, MeshHandle, Object, ObjectMeshKind, RawObjectHandle, RawTexture2DHandle,
+        Sorting, VertexAttributeId, VERTEX_ATTRIBUTE_POSITION,
+    };
+
+    use super::{build_internal_objects, PendingObject, PARALLEL_ADD_THRESHOLD};
+    use crate::{
+        managers::InternalMesh,
+        util::frustum::{BoundingBox, BoundingSphere},
+    };
+
+    struct PlainMaterial;
+    impl Material for PlainMaterial {
+        type DataType = glam::Vec4;
+        type TextureArrayType = [Option<RawTexture2DHandle>; 0];
+        type RequiredAttributeArrayType = [&'static VertexAttributeId; 1];
+        type SupportedAttributeArrayType = [&'static VertexAttributeId; 1];
+
+        fn required_attributes() -> Self::RequiredAttributeArrayType {
+            [&VERTEX_ATTRIBUTE_POSITION]
+        }
+
+        fn supported_attributes() -> Self::SupportedAttributeArrayType {
+            [&VERTEX_ATTRIBUTE_POSITION]
+        }
+
+        fn key(&self) -> u64 {
+            0
+        }
+
+        fn sorting(&self) -> Sorting {
+            Sorting::OPAQUE
+        }
+
+        fn to_textures(&self) -> Self::TextureArrayType {
+            []
+        }
+
+        fn to_data(&self) -> Self::DataType {
+            glam::Vec4::ZERO
+        }
+    }
+
+    fn internal_mesh() -> InternalMesh {
+        InternalMesh {
+            vertex_attribute_ranges: vec![(*VERTEX_ATTRIBUTE_POSITION.id(), 0..36)],
+            vertex_count: 3,
+            index_range: 36..48,
+            index_count: 3,
+            lod_index_ranges: Vec::new(),
+            index_format: IndexFormat::Uint32,
+            packed_attributes: false,
+            required_joint_count: None,
+            bounding_sphere: BoundingSphere { center: Vec3::ZERO, radius: 1.0 },
+            bounding_box: BoundingBox { min: Vec3::splat(-1.0), max: Vec3::ONE },
+            skinning_source: None,
+            label: None,
+        }
+    }
+
+    fn translation(idx: usize) -> Mat4 {
+        Mat4::from_translation(Vec3::new(idx as f32, 0.0, 0.0))
+    }
+
+    /// Builds `count` objects, one unit apart, and checks each one ends up
+    /// with its own transform, in the order the batch was given in.
+    fn check_batch(count: usize) {
+        let mesh = internal_mesh();
+        let mesh_handle = MeshHandle::new(|_| {}, 0);
+        let material_handle = MaterialHandle::new(|_| {}, 0);
+
+        let pending = (0..count)
+            .map(|idx| PendingObject {
+                internal_mesh: &mesh,
+                skeleton_ranges: &[],
+                previous_position_offset: 0,
+                handle: RawObjectHandle::new(idx),
+                object: Object {
+                    mesh_kind: ObjectMeshKind::Static(mesh_handle.clone()),
+                    material: material_handle.clone(),
+                    transform: translation(idx),
+                },
+            })
+            .collect();
+        let objects = build_internal_objects::<PlainMaterial>(pending);
+
+        assert_eq!(objects.len(), count);
+        for (idx, object) in objects.iter().enumerate() {
+            assert_eq!(object.inner.transform, translation(idx));
+            assert_eq!(object.location, Vec3::new(idx as f32, 0.0, 0.0).into());
+            assert_eq!(object.inner.first_index, 9);
+        }
+    }
+
+    #[test]
+    fn batch_below_parallel_threshold() {
+        check_batch(PARALLEL_ADD_THRESHOLD - 1);
+    }
+
+    #[test]
+    fn batch_above_parallel_threshold() {
+        check_batch(PARALLEL_ADD_THRESHOLD * 3 + 7);
+    }
+}
@@ -33,6 +33,8 @@ pub struct PointLightManager {
 
 impl PointLightManager {
     pub fn new(device: &Device) -> Self {
+        profiling::scope!("PointLightManager::new");
+
         Self {
             data: Vec::new(),
             data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "point light buffer"),
@@ -55,7 +57,21 @@ impl PointLightManager {
         self.data[handle.idx].take().unwrap();
     }
 
+    /// Moves every light by `offset`, see [`Renderer::set_world_origin`](crate::Renderer::set_world_origin).
+    pub fn translate_all(&mut self, offset: Vec3) {
+        for light in self.data.iter_mut().flatten() {
+            light.position += offset;
+        }
+    }
+
+    /// Iterate over all current point lights.
+    pub fn lights(&self) -> impl Iterator<Item = &PointLight> {
+        self.data.iter().flatten()
+    }
+
     pub fn evaluate(&mut self, renderer: &Renderer) {
+        profiling::scope!("PointLightManager::evaluate");
+
         let buffer = ShaderPointLightBuffer {
             count: ArrayLength,
             array: self
@@ -70,7 +86,8 @@ impl PointLightManager {
                 .collect(),
         };
 
-        self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+        let bytes = self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+        renderer.record_buffer_upload(bytes);
     }
 
     pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
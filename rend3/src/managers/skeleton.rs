@@ -3,15 +3,19 @@ use std::ops::Range;
 use arrayvec::ArrayVec;
 use glam::Mat4;
 use rend3_types::{
-    MeshHandle, RawSkeletonHandle, Skeleton, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES,
-    VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+    MeshHandle, RawMeshHandle, RawSkeletonHandle, Skeleton, SkeletonHandle, VertexAttributeId,
+    VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION,
+    VERTEX_ATTRIBUTE_TANGENT,
 };
 use thiserror::Error;
 use wgpu::Device;
 
 use crate::{
     managers::{MeshCreationError, MeshManager},
-    util::iter::ExactSizerIterator,
+    util::{
+        iter::ExactSizerIterator,
+        typedefs::{FastHashMap, FastHashSet},
+    },
 };
 
 /// Internal representation of a Skeleton
@@ -22,14 +26,21 @@ pub struct InternalSkeleton {
     /// The list of per-joint transformation matrices that will be applied to
     /// vertices.
     pub joint_matrices: Vec<Mat4>,
+    /// The joint matrices of the last frame.
+    pub previous_joint_matrices: Vec<Mat4>,
     /// There are 5 different ranges we need to store here:
     /// Position, Normals, Tangent, Joint Index, Joint Weight
     pub source_attribute_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 5>,
     /// There are three attributes that we can possibly override here:
     /// Position, Normals, and Tangent
     pub overridden_attribute_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 3>,
+    /// Range of the skinned positions of the last frame, if the mesh has
+    /// positions.
+    pub previous_position_range: Option<Range<u64>>,
     /// Amount of vertices in the pointed to mesh
     pub vertex_count: u32,
+    /// The skeleton whose joint matrices this skeleton follows, if shared.
+    pub source: Option<SkeletonHandle>,
 }
 
 #[derive(Debug, Error)]
@@ -56,12 +67,22 @@ pub struct SkeletonManager {
     skeleton_count: usize,
     /// The number of joints of all the skeletons in this manager
     global_joint_count: usize,
+    /// Skeletons that follow the joint matrices of another skeleton.
+    followers: FastHashMap<RawSkeletonHandle, Vec<RawSkeletonHandle>>,
+    /// Skeletons whose joint matrices changed this frame.
+    moved: FastHashSet<RawSkeletonHandle>,
 }
 impl SkeletonManager {
     pub fn new() -> Self {
         profiling::scope!("SkeletonManager::new");
 
-        Self { data: Vec::new(), skeleton_count: 0, global_joint_count: 0 }
+        Self {
+            data: Vec::new(),
+            skeleton_count: 0,
+            global_joint_count: 0,
+            followers: FastHashMap::default(),
+            moved: FastHashSet::default(),
+        }
     }
 
     pub fn validate_skeleton(
@@ -112,20 +133,67 @@ impl SkeletonManager {
             overridden_attribute_ranges.push((*attribute_id, skeleton_range));
         }
 
+        let previous_position_range = match internal_mesh.get_attribute(VERTEX_ATTRIBUTE_POSITION.id()) {
+            Some(range) => Some(mesh_manager.allocate_range(device, range.end - range.start)?),
+            None => None,
+        };
+
         // Ensure there will be exactly `num_joints` matrices.
         let mut joint_matrices = skeleton.joint_matrices;
         joint_matrices.truncate(required_joint_count as _);
 
         Ok(InternalSkeleton {
+            previous_joint_matrices: joint_matrices.clone(),
             joint_matrices,
+            previous_position_range,
             mesh_handle: skeleton.mesh,
             source_attribute_ranges,
             overridden_attribute_ranges,
             vertex_count: internal_mesh.vertex_count,
+            source: None,
         })
     }
 
-    pub fn add(&mut self, handle: RawSkeletonHandle, internal: InternalSkeleton) {
+    /// Validates a skeleton for `mesh` that follows the joint matrices of
+    /// `source`.
+    pub fn validate_shared_skeleton(
+        device: &Device,
+        mesh_manager: &MeshManager,
+        source: SkeletonHandle,
+        mesh: MeshHandle,
+    ) -> Result<InternalSkeleton, SkeletonCreationError> {
+        let required_joint_count = mesh_manager.lock_internal_data()[mesh.get_raw()]
+            .required_joint_count
+            .ok_or(SkeletonCreationError::MissingAttributesJointIndices)?;
+
+        // The matrices are replaced by the source's when the skeleton is added.
+        let skeleton = Skeleton { joint_matrices: vec![Mat4::IDENTITY; required_joint_count as usize], mesh };
+        let internal = Self::validate_skeleton(device, mesh_manager, skeleton)?;
+
+        Ok(InternalSkeleton { source: Some(source), ..internal })
+    }
+
+    pub fn add(&mut self, handle: RawSkeletonHandle, mut internal: InternalSkeleton) {
+        profiling::scope!("SkeletonManager::add");
+
+        if let Some(ref source) = internal.source {
+            let source_matrices = &self.data[source.get_raw().idx].as_ref().unwrap().joint_matrices;
+            let joint_count = internal.joint_matrices.len();
+            if source_matrices.len() < joint_count {
+                log::error!(
+                    "Skeleton {} needs {joint_count} joints but shares the {} joints of skeleton {}; \
+                     the remaining joints keep their bind pose",
+                    handle.idx,
+                    source_matrices.len(),
+                    source.get_raw().idx
+                );
+            }
+            let shared = joint_count.min(source_matrices.len());
+            internal.joint_matrices[..shared].copy_from_slice(&source_matrices[..shared]);
+            internal.previous_joint_matrices = internal.joint_matrices.clone();
+            self.followers.entry(source.get_raw()).or_default().push(handle);
+        }
+
         self.global_joint_count += internal.joint_matrices.len();
 
         if handle.idx >= self.data.len() {
@@ -136,32 +204,119 @@ impl SkeletonManager {
         self.skeleton_count += 1;
     }
 
+    /// Recreates the skeletons deforming the given meshes after their data was
+    /// replaced, keeping their joint matrices. Returns the recreated
+    /// skeletons. If any skeleton doesn't fit its new mesh, none are changed.
+    pub fn rebuild_for_meshes(
+        &mut self,
+        device: &Device,
+        mesh_manager: &MeshManager,
+        meshes: &[RawMeshHandle],
+    ) -> Result<Vec<RawSkeletonHandle>, SkeletonCreationError> {
+        profiling::scope!("SkeletonManager::rebuild_for_meshes");
+
+        let mut rebuilt = Vec::new();
+        for (idx, skeleton) in self.data.iter().enumerate() {
+            let Some(skeleton) = skeleton else {
+                continue;
+            };
+            if !meshes.contains(&skeleton.mesh_handle.get_raw()) {
+                continue;
+            }
+            let new = Self::validate_skeleton(
+                device,
+                mesh_manager,
+                Skeleton { mesh: skeleton.mesh_handle.clone(), joint_matrices: skeleton.joint_matrices.clone() },
+            );
+            match new {
+                Ok(new) => rebuilt.push((RawSkeletonHandle::new(idx), new)),
+                Err(e) => {
+                    for (_, new) in rebuilt {
+                        free_ranges(mesh_manager, new);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(rebuilt
+            .into_iter()
+            .map(|(handle, new)| {
+                let slot = self.data[handle.idx].as_mut().unwrap();
+                let source = slot.source.take();
+                let old = std::mem::replace(slot, InternalSkeleton { source, ..new });
+                self.global_joint_count =
+                    self.global_joint_count - old.joint_matrices.len() + slot.joint_matrices.len();
+                free_ranges(mesh_manager, old);
+                handle
+            })
+            .collect())
+    }
+
     pub fn remove(&mut self, mesh_manager: &MeshManager, handle: RawSkeletonHandle) {
+        profiling::scope!("SkeletonManager::remove");
+
         let skeleton = self.data[handle.idx].take().unwrap();
         self.global_joint_count -= skeleton.joint_matrices.len();
 
-        // Free the owned regions of the mesh data buffer
-        for (_, range) in skeleton.overridden_attribute_ranges {
-            mesh_manager.free_range(range);
+        if let Some(ref source) = skeleton.source {
+            if let Some(followers) = self.followers.get_mut(&source.get_raw()) {
+                followers.retain(|&f| f != handle);
+                if followers.is_empty() {
+                    self.followers.remove(&source.get_raw());
+                }
+            }
         }
 
+        free_ranges(mesh_manager, skeleton);
+        self.moved.remove(&handle);
+
         self.skeleton_count -= 1;
     }
 
     pub fn set_joint_matrices(&mut self, handle: RawSkeletonHandle, mut joint_matrices: Vec<Mat4>) {
-        let skeleton = self.data[handle.idx].as_mut().unwrap();
+        profiling::scope!("SkeletonManager::set_joint_matrices");
+
+        let joint_count = self.data[handle.idx].as_ref().unwrap().joint_matrices.len();
         assert!(
-            skeleton.joint_matrices.len() <= joint_matrices.len(),
+            joint_count <= joint_matrices.len(),
             "Not enough joints to update this skeleton. The mesh has {} joints, \
             but only {} joint matrices were provided.",
-            skeleton.joint_matrices.len(),
+            joint_count,
             joint_matrices.len(),
         );
         // Truncate to avoid storing any extra joint matrices
-        joint_matrices.truncate(skeleton.joint_matrices.len());
+        joint_matrices.truncate(joint_count);
+
+        if let Some(followers) = self.followers.get(&handle) {
+            for &follower_handle in followers {
+                let follower = self.data[follower_handle.idx].as_mut().unwrap();
+                if self.moved.insert(follower_handle) {
+                    follower.previous_joint_matrices.clone_from(&follower.joint_matrices);
+                }
+                let shared = follower.joint_matrices.len().min(joint_matrices.len());
+                follower.joint_matrices[..shared].copy_from_slice(&joint_matrices[..shared]);
+            }
+        }
+
+        let skeleton = self.data[handle.idx].as_mut().unwrap();
+        if self.moved.insert(handle) {
+            skeleton.previous_joint_matrices = std::mem::take(&mut skeleton.joint_matrices);
+        }
         skeleton.joint_matrices = joint_matrices;
     }
 
+    /// Makes the previous joint matrices of the skeletons that moved last
+    /// frame match their current matrices. Must be called at the start of
+    /// every frame, before any joint matrices change.
+    pub fn begin_frame(&mut self) {
+        for handle in self.moved.drain() {
+            if let Some(skeleton) = self.data[handle.idx].as_mut() {
+                skeleton.previous_joint_matrices.clone_from(&skeleton.joint_matrices);
+            }
+        }
+    }
+
     pub fn internal_data(&self, handle: RawSkeletonHandle) -> &InternalSkeleton {
         self.data[handle.idx].as_ref().unwrap()
     }
@@ -176,6 +331,16 @@ impl SkeletonManager {
     }
 }
 
+/// Frees the regions of the mesh data buffer owned by a skeleton.
+fn free_ranges(mesh_manager: &MeshManager, skeleton: InternalSkeleton) {
+    for (_, range) in skeleton.overridden_attribute_ranges {
+        mesh_manager.free_range(range);
+    }
+    if let Some(range) = skeleton.previous_position_range {
+        mesh_manager.free_range(range);
+    }
+}
+
 impl Default for SkeletonManager {
     fn default() -> Self {
         Self::new()
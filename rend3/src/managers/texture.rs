@@ -4,12 +4,14 @@ use rend3_types::{MipmapCount, MipmapSource, RawResourceHandle, TextureFormat, T
 use thiserror::Error;
 use wgpu::{
     util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, CommandBuffer, CommandEncoder, CommandEncoderDescriptor,
-    Device, Extent3d, Features, ImageCopyTexture, ImageDataLayout, Origin3d, ShaderStages, Texture, TextureAspect,
-    TextureDescriptor, TextureDimension, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension,
+    BindGroupLayoutEntry, BindingResource, BindingType, BufferDescriptor, BufferUsages, CommandBuffer, CommandEncoder,
+    CommandEncoderDescriptor, Device, DownlevelFlags, Extent3d, Features, ImageCopyBuffer, ImageCopyTexture,
+    ImageDataLayout, Origin3d, ShaderStages, Texture, TextureAspect, TextureDescriptor, TextureDimension,
+    TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension, COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 
 use crate::{
+    inspect::TextureInspection,
     profile::ProfileData,
     util::{error_scope::AllocationErrorScope, math::round_up, mipmap::MipmapGenerationError},
     Renderer, RendererProfile,
@@ -45,11 +47,90 @@ pub enum TextureCreationError {
     TextureFormatNotFloat { format: TextureFormat, sample_type: Option<TextureSampleType> },
     #[error("Mipmap creation failed")]
     MipmapCreationFailed(#[from] MipmapGenerationError),
+    #[error("External textures must have the TEXTURE_BINDING usage. The texture has usages {usage:?}.")]
+    TextureNotBindable { usage: TextureUsages },
+    #[error("The descriptor does not match the external texture: expected {expected:?}, found {found:?}")]
+    DescriptorMismatch { expected: Box<TextureDescriptor<'static>>, found: Box<TextureDescriptor<'static>> },
+    #[error("External {kind} textures must be 2D with {layers} array layers, found {dimension:?} with {found} layers")]
+    InvalidTextureShape { kind: &'static str, layers: u32, dimension: TextureDimension, found: u32 },
+    #[error("Cube array textures must hold at least one cube")]
+    EmptyCubeArray,
+    #[error("The device does not support cube array textures")]
+    CubeArraysUnsupported,
 }
 
 unsafe impl Send for TextureCreationError {}
 unsafe impl Sync for TextureCreationError {}
 
+#[derive(Debug, Error)]
+pub enum TextureUpdateError {
+    #[error("Tried to update mip level {mip} of a texture with {count} mip levels")]
+    MipOutOfBounds { mip: u32, count: u32 },
+    #[error("Region {origin:?} + {extent:?} does not fit in a mip level of size {size:?}")]
+    RegionOutOfBounds { origin: Origin3d, extent: Extent3d, size: Extent3d },
+    #[error("Region {origin:?} + {extent:?} is not aligned to the {block_width}x{block_height} blocks of the format")]
+    UnalignedRegion { origin: Origin3d, extent: Extent3d, block_width: u32, block_height: u32 },
+    #[error("Region needs {expected} bytes of tightly packed data, found {found} bytes")]
+    DataSizeMismatch { expected: usize, found: usize },
+}
+
+/// Data to write into a region of a single mip level of an existing texture.
+pub struct TextureUpdate {
+    pub mip: u32,
+    /// For cube textures, `z` is the face to write into, and for cube array
+    /// textures the face of the cube, `cube * 6 + face`.
+    pub origin: Origin3d,
+    pub extent: Extent3d,
+    pub data: Vec<u8>,
+}
+
+/// Layout of the array layers of a texture, and the view it's bound with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureShape {
+    D2,
+    /// Six faces, in the order +X, -X, +Y, -Y, +Z, -Z.
+    Cube,
+    /// The given number of cubes, one after the other.
+    CubeArray(u32),
+}
+
+impl TextureShape {
+    /// Number of array layers of the texture.
+    pub fn layers(self) -> u32 {
+        match self {
+            Self::D2 => 1,
+            Self::Cube => 6,
+            Self::CubeArray(cubes) => cubes * 6,
+        }
+    }
+
+    pub fn view_dimension(self) -> TextureViewDimension {
+        match self {
+            Self::D2 => TextureViewDimension::D2,
+            Self::Cube => TextureViewDimension::Cube,
+            Self::CubeArray(_) => TextureViewDimension::CubeArray,
+        }
+    }
+
+    fn kind(self) -> &'static str {
+        match self {
+            Self::D2 => "2D",
+            Self::Cube => "cube",
+            Self::CubeArray(_) => "cube array",
+        }
+    }
+
+    fn validate(self, renderer: &Renderer) -> Result<(), TextureCreationError> {
+        match self {
+            Self::CubeArray(0) => Err(TextureCreationError::EmptyCubeArray),
+            Self::CubeArray(_) if !renderer.downlevel.flags.contains(DownlevelFlags::CUBE_ARRAY_TEXTURES) => {
+                Err(TextureCreationError::CubeArraysUnsupported)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Preallocation count of texture view array
 const TEXTURE_PREALLOCATION: usize = 1024;
 /// What we divide the texture limit by to get the count supplied in the BGL.
@@ -95,29 +176,27 @@ impl<T: 'static> TextureManager<T> {
         }
     }
 
+    /// Creates a texture of the given shape. The data holds every layer with
+    /// all its uploaded mip levels, one layer after the other.
     pub fn add(
         renderer: &Renderer,
         texture: crate::types::Texture,
-        cube: bool,
+        shape: TextureShape,
     ) -> Result<(Option<CommandBuffer>, InternalTexture), TextureCreationError> {
         validate_texture_format(texture.format, renderer.features)?;
+        shape.validate(renderer)?;
 
         let (block_x, block_y) = texture.format.block_dimensions();
         let size = Extent3d {
             width: round_up(texture.size.x, block_x),
             height: round_up(texture.size.y, block_y),
-            depth_or_array_layers: match cube {
-                true => 6,
-                false => 1,
-            },
+            depth_or_array_layers: shape.layers(),
         };
 
         let mip_level_count = match texture.mip_count {
             MipmapCount::Specific(v) => v.get(),
-            MipmapCount::Maximum => size.max_mips(match cube {
-                true => wgpu::TextureDimension::D3,
-                false => wgpu::TextureDimension::D2,
-            }),
+            // Layers don't shrink along with the mips.
+            MipmapCount::Maximum => size.max_mips(TextureDimension::D2),
         };
 
         let desc = TextureDescriptor {
@@ -131,6 +210,8 @@ impl<T: 'static> TextureManager<T> {
             view_formats: &[],
         };
 
+        renderer.record_texture_upload(texture.data.len() as u64);
+
         let (buffer, tex) = match texture.mip_source {
             MipmapSource::Uploaded => {
                 let scope = AllocationErrorScope::new(&renderer.device);
@@ -144,18 +225,16 @@ impl<T: 'static> TextureManager<T> {
                 (None, texture)
             }
             MipmapSource::Generated => {
-                assert!(!cube, "Cannot generate mipmaps from cubemaps currently");
-
                 let desc = TextureDescriptor { usage: desc.usage | TextureUsages::RENDER_ATTACHMENT, ..desc };
                 let scope = AllocationErrorScope::new(&renderer.device);
                 let tex = renderer.device.create_texture(&desc);
                 scope.end().map_err(TextureCreationError::TextureAllocationFailed)?;
 
-                let (block_width, _) = texture.format.block_dimensions();
+                let (block_width, block_height) = texture.format.block_dimensions();
                 let block_size = texture.format.block_copy_size(None).unwrap();
 
                 let scope = AllocationErrorScope::new(&renderer.device);
-                // write first level
+                // write first level of every layer
                 renderer.queue.write_texture(
                     ImageCopyTexture {
                         texture: &tex,
@@ -167,7 +246,7 @@ impl<T: 'static> TextureManager<T> {
                     ImageDataLayout {
                         offset: 0,
                         bytes_per_row: Some(block_size * (size.width / block_width)),
-                        rows_per_image: None,
+                        rows_per_image: Some(size.height / block_height),
                     },
                     size,
                 );
@@ -183,18 +262,57 @@ impl<T: 'static> TextureManager<T> {
         };
 
         let scope = AllocationErrorScope::new(&renderer.device);
-        let view = tex.create_view(&TextureViewDescriptor {
-            dimension: match cube {
-                true => Some(TextureViewDimension::Cube),
-                false => Some(TextureViewDimension::D2),
-            },
-            ..Default::default()
-        });
+        let view =
+            tex.create_view(&TextureViewDescriptor { dimension: Some(shape.view_dimension()), ..Default::default() });
         scope.end().map_err(TextureCreationError::TextureViewCreationFailed)?;
 
         Ok((buffer, InternalTexture { texture: tex, view, desc }))
     }
 
+    /// Wraps a texture created outside of rend3, validating that it can be
+    /// used as a texture of the given shape.
+    pub fn wrap(
+        renderer: &Renderer,
+        texture: Texture,
+        desc: TextureDescriptor<'static>,
+        shape: TextureShape,
+    ) -> Result<InternalTexture, TextureCreationError> {
+        validate_texture_format(desc.format, renderer.features)?;
+        shape.validate(renderer)?;
+
+        let found = TextureDescriptor {
+            label: desc.label,
+            size: texture.size(),
+            mip_level_count: texture.mip_level_count(),
+            sample_count: texture.sample_count(),
+            dimension: texture.dimension(),
+            format: texture.format(),
+            usage: texture.usage(),
+            view_formats: desc.view_formats,
+        };
+        if found != desc {
+            return Err(TextureCreationError::DescriptorMismatch { expected: Box::new(desc), found: Box::new(found) });
+        }
+        if !desc.usage.contains(TextureUsages::TEXTURE_BINDING) {
+            return Err(TextureCreationError::TextureNotBindable { usage: desc.usage });
+        }
+
+        let layers = shape.layers();
+        if desc.dimension != TextureDimension::D2 || desc.size.depth_or_array_layers != layers {
+            return Err(TextureCreationError::InvalidTextureShape {
+                kind: shape.kind(),
+                layers,
+                dimension: desc.dimension,
+                found: desc.size.depth_or_array_layers,
+            });
+        }
+
+        let view = texture
+            .create_view(&TextureViewDescriptor { dimension: Some(shape.view_dimension()), ..Default::default() });
+
+        Ok(InternalTexture { texture, view, desc })
+    }
+
     pub fn fill_from_texture(
         &mut self,
         device: &Device,
@@ -250,6 +368,82 @@ impl<T: 'static> TextureManager<T> {
         self.data[handle.idx] = Some(internal_texture);
     }
 
+    /// Records a copy of the update into the texture from a staging buffer.
+    pub fn update(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        handle: RawResourceHandle<T>,
+        update: &TextureUpdate,
+    ) -> Result<(), TextureUpdateError> {
+        let InternalTexture { texture, desc, .. } = self.data[handle.idx].as_ref().unwrap();
+        let TextureUpdate { mip, origin, extent, ref data } = *update;
+
+        let size =
+            desc.mip_level_size(mip).ok_or(TextureUpdateError::MipOutOfBounds { mip, count: desc.mip_level_count })?;
+        if origin.x + extent.width > size.width
+            || origin.y + extent.height > size.height
+            || origin.z + extent.depth_or_array_layers > size.depth_or_array_layers
+        {
+            return Err(TextureUpdateError::RegionOutOfBounds { origin, extent, size });
+        }
+
+        let (block_width, block_height) = desc.format.block_dimensions();
+        if origin.x % block_width != 0
+            || origin.y % block_height != 0
+            || extent.width % block_width != 0
+            || extent.height % block_height != 0
+        {
+            return Err(TextureUpdateError::UnalignedRegion { origin, extent, block_width, block_height });
+        }
+
+        let block_size = desc.format.block_copy_size(None).unwrap();
+        let bytes_per_row = block_size * (extent.width / block_width);
+        let rows_per_image = extent.height / block_height;
+        let expected = (bytes_per_row * rows_per_image * extent.depth_or_array_layers) as usize;
+        if data.len() != expected {
+            return Err(TextureUpdateError::DataSizeMismatch { expected, found: data.len() });
+        }
+
+        if expected == 0 {
+            return Ok(());
+        }
+
+        // Copied through the encoder rather than written by the queue, so the
+        // update lands after the mipmaps generated by adds of the same frame.
+        let padded_bytes_per_row = round_up(bytes_per_row, COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staging = device.create_buffer(&BufferDescriptor {
+            label: Some("texture update staging buffer"),
+            size: padded_bytes_per_row as u64 * rows_per_image as u64 * extent.depth_or_array_layers as u64,
+            usage: BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
+            mapped_at_creation: true,
+        });
+        {
+            let mut mapping = staging.slice(..).get_mapped_range_mut();
+            let rows =
+                mapping.chunks_exact_mut(padded_bytes_per_row as usize).zip(data.chunks_exact(bytes_per_row as usize));
+            for (staged, row) in rows {
+                staged[..row.len()].copy_from_slice(row);
+            }
+        }
+        staging.unmap();
+
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: &staging,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(rows_per_image),
+                },
+            },
+            ImageCopyTexture { texture, mip_level: mip, origin, aspect: TextureAspect::All },
+            extent,
+        );
+
+        Ok(())
+    }
+
     pub fn remove(&mut self, handle: RawResourceHandle<T>) {
         self.group_dirty = self.group_dirty.map_gpu(|_| true);
 
@@ -278,6 +472,17 @@ impl<T: 'static> TextureManager<T> {
         self.data[handle.idx].as_ref().unwrap()
     }
 
+    /// Returns the texture, or None if it hasn't been added yet.
+    pub fn try_get_internal(&self, handle: RawResourceHandle<T>) -> Option<&InternalTexture> {
+        self.data.get(handle.idx)?.as_ref()
+    }
+
+    /// Every texture, see [`Renderer::inspect`](crate::Renderer::inspect).
+    pub fn inspect(&self) -> Vec<TextureInspection> {
+        let textures = self.data.iter().enumerate();
+        textures.filter_map(|(index, texture)| Some(TextureInspection::new(index, texture.as_ref()?))).collect()
+    }
+
     pub fn get_view(&self, handle: RawResourceHandle<T>) -> &TextureView {
         &self.data[handle.idx].as_ref().unwrap().view
     }
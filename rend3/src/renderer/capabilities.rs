@@ -0,0 +1,148 @@
+use parking_lot::Mutex;
+use wgpu::{Adapter, DownlevelFlags, Features, TextureFormat};
+
+use crate::RendererProfile;
+
+/// Optional features of the renderer, available depending on the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// All textures are bound at once in a binding array, instead of a bind
+    /// group per material.
+    Bindless,
+    /// Compute shaders can cull and write indirect draws on the GPU.
+    ComputeCulling,
+    /// Render passes can render to multiple array layers at once.
+    Multiview,
+    /// Color and depth targets can be multisampled with 8 samples.
+    Msaa8x,
+    /// BC compressed textures can be sampled.
+    BcCompression,
+    /// ETC2 compressed textures can be sampled.
+    Etc2Compression,
+    /// ASTC compressed textures can be sampled.
+    AstcCompression,
+}
+
+/// A routine taking a slower or simpler path because a [`Capability`] is
+/// missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityFallback {
+    /// The missing capability.
+    pub capability: Capability,
+    /// Name of the routine falling back.
+    pub routine: &'static str,
+    /// What the routine does instead.
+    pub fallback: &'static str,
+}
+
+/// Which optional features the renderer has on this device.
+///
+/// Returned by [`Renderer::capabilities`](crate::Renderer::capabilities).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RendererCapabilities {
+    /// The profile the renderer runs in.
+    pub profile: RendererProfile,
+    /// See [`Capability::Bindless`]. Only in the GpuDriven profile.
+    pub bindless: bool,
+    /// See [`Capability::ComputeCulling`]. Only if the device has
+    /// [`DownlevelFlags::COMPUTE_SHADERS`],
+    /// [`DownlevelFlags::INDIRECT_EXECUTION`] and
+    /// [`Features::INDIRECT_FIRST_INSTANCE`].
+    pub compute_culling: bool,
+    /// See [`Capability::Multiview`]. Only if [`Features::MULTIVIEW`] was
+    /// requested when creating the device.
+    pub multiview: bool,
+    /// See [`Capability::Msaa8x`]. Checked for `Rgba16Float` and
+    /// `Depth32Float`, the formats of the base render graph. Only if
+    /// [`Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`] was requested
+    /// when creating the device, as other devices only allow the sample
+    /// counts every adapter supports.
+    pub msaa_8x: bool,
+    /// See [`Capability::BcCompression`].
+    pub bc_compression: bool,
+    /// See [`Capability::Etc2Compression`].
+    pub etc2_compression: bool,
+    /// See [`Capability::AstcCompression`].
+    pub astc_compression: bool,
+    /// Fallbacks registered by routines through
+    /// [`Renderer::register_capability_fallback`](crate::Renderer::register_capability_fallback).
+    pub fallbacks: Vec<CapabilityFallback>,
+}
+
+impl RendererCapabilities {
+    pub(crate) fn new(
+        adapter: &Adapter,
+        profile: RendererProfile,
+        features: Features,
+        downlevel: DownlevelFlags,
+    ) -> Self {
+        let supports_8x =
+            |format: TextureFormat| adapter.get_texture_format_features(format).flags.sample_count_supported(8);
+
+        Self {
+            profile,
+            bindless: profile.is_gpu_driven(),
+            compute_culling: downlevel.contains(DownlevelFlags::COMPUTE_SHADERS | DownlevelFlags::INDIRECT_EXECUTION)
+                && features.contains(Features::INDIRECT_FIRST_INSTANCE),
+            multiview: features.contains(Features::MULTIVIEW),
+            msaa_8x: features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                && supports_8x(TextureFormat::Rgba16Float)
+                && supports_8x(TextureFormat::Depth32Float),
+            bc_compression: features.contains(Features::TEXTURE_COMPRESSION_BC),
+            etc2_compression: features.contains(Features::TEXTURE_COMPRESSION_ETC2),
+            astc_compression: features.contains(Features::TEXTURE_COMPRESSION_ASTC),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// Returns true if the renderer has the given capability.
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Bindless => self.bindless,
+            Capability::ComputeCulling => self.compute_culling,
+            Capability::Multiview => self.multiview,
+            Capability::Msaa8x => self.msaa_8x,
+            Capability::BcCompression => self.bc_compression,
+            Capability::Etc2Compression => self.etc2_compression,
+            Capability::AstcCompression => self.astc_compression,
+        }
+    }
+
+    /// Returns true if any routine fell back because of the given missing
+    /// capability.
+    pub fn has_fallback(&self, capability: Capability) -> bool {
+        self.fallbacks.iter().any(|f| f.capability == capability)
+    }
+}
+
+pub(crate) struct CapabilityState {
+    capabilities: RendererCapabilities,
+    fallbacks: Mutex<Vec<CapabilityFallback>>,
+}
+
+impl CapabilityState {
+    pub fn new(capabilities: RendererCapabilities) -> Self {
+        Self { capabilities, fallbacks: Mutex::new(Vec::new()) }
+    }
+
+    pub fn capabilities(&self) -> RendererCapabilities {
+        RendererCapabilities { fallbacks: self.fallbacks.lock().clone(), ..self.capabilities.clone() }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.supports(capability)
+    }
+
+    pub fn register_fallback(&self, fallback: CapabilityFallback) {
+        let mut fallbacks = self.fallbacks.lock();
+        if !fallbacks.contains(&fallback) {
+            log::debug!(
+                "{} falls back because {:?} is missing: {}",
+                fallback.routine,
+                fallback.capability,
+                fallback.fallback
+            );
+            fallbacks.push(fallback);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use parking_lot::Mutex;
+use wgpu::Device;
+
+#[derive(Default)]
+struct GpuCaptureState {
+    /// Frames requested by the user that have not started capturing.
+    requested: u32,
+    /// Frames left in the capture in progress.
+    remaining: u32,
+}
+
+/// Drives device captures, which wgpu forwards to RenderDoc's in-application
+/// API, or to Xcode's capture manager on Metal.
+#[derive(Default)]
+pub(crate) struct GpuCapture {
+    state: Mutex<GpuCaptureState>,
+}
+
+impl GpuCapture {
+    pub fn trigger(&self, frames: u32) {
+        self.state.lock().requested = frames;
+    }
+
+    pub fn is_active(&self) -> bool {
+        let state = self.state.lock();
+        state.requested != 0 || state.remaining != 0
+    }
+
+    /// Starts a requested capture, called before any work of a frame is recorded.
+    pub fn begin_frame(&self, device: &Device) {
+        let mut state = self.state.lock();
+        if state.remaining == 0 && state.requested != 0 {
+            profiling::scope!("Start GPU Capture");
+            device.start_capture();
+            state.remaining = std::mem::take(&mut state.requested);
+        }
+    }
+
+    /// Stops the capture in progress once enough frames have been submitted.
+    pub fn end_frame(&self, device: &Device) {
+        let mut state = self.state.lock();
+        if state.remaining != 0 {
+            state.remaining -= 1;
+            if state.remaining == 0 {
+                profiling::scope!("Stop GPU Capture");
+                device.stop_capture();
+            }
+        }
+    }
+}
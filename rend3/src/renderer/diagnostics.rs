@@ -0,0 +1,201 @@
+use std::{
+    fmt::Write,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use glam::{Mat3, Mat4};
+use parking_lot::Mutex;
+use wgpu::{Features, TextureSampleType};
+
+use crate::{
+    managers::{MaterialManager, ObjectManager, TextureManager},
+    types::{RawMaterialHandle, RawObjectHandle, RawTexture2DHandle, Texture2DTag},
+    util::typedefs::FastHashMap,
+};
+
+/// Kind of misuse found by the diagnostics mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    /// A material was used after it was removed in the same frame, usually
+    /// by an instruction from another thread racing the drop of its last
+    /// handle.
+    MissingMaterial,
+    /// A transform contains NaN or infinite values.
+    NonFiniteTransform,
+    /// A transform scales at least one axis to zero.
+    ZeroScaleTransform,
+    /// A material uses a texture whose format can't be sampled by material
+    /// shaders.
+    IncompatibleTextureFormat,
+    /// A render graph node reads a render target that no earlier node wrote.
+    UnwrittenRenderTarget,
+}
+
+/// A misuse of the renderer found by the diagnostics mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Human readable description of the problem.
+    pub message: String,
+}
+
+struct MaterialTextureCheck {
+    handle: RawMaterialHandle,
+    textures: Vec<Option<RawTexture2DHandle>>,
+    location: Location<'static>,
+}
+
+struct RemovedMaterial {
+    label: Option<Arc<str>>,
+    location: Location<'static>,
+}
+
+/// Names a resource by its kind, index and label, like `material 3 "Bark"`.
+fn describe(kind: &str, idx: usize, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{kind} {idx} \"{label}\""),
+        None => format!("{kind} {idx}"),
+    }
+}
+
+/// Names an object by its index, its label and the label of its mesh.
+pub(crate) fn describe_object(object_manager: &ObjectManager, handle: RawObjectHandle) -> String {
+    let mut what = describe("object", handle.idx, object_manager.label(handle));
+    if let Some(mesh) = object_manager.mesh_label(handle) {
+        write!(what, " with mesh \"{mesh}\"").unwrap();
+    }
+    what
+}
+
+/// Opt-in validation of things wgpu can't know about.
+#[derive(Default)]
+pub(crate) struct Diagnostics {
+    enabled: AtomicBool,
+    reports: Mutex<Vec<Diagnostic>>,
+    material_textures: Mutex<Vec<MaterialTextureCheck>>,
+    /// Materials removed by the instructions of this frame so far.
+    removed_materials: Mutex<FastHashMap<RawMaterialHandle, RemovedMaterial>>,
+}
+
+impl Diagnostics {
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, kind: DiagnosticKind, message: String) {
+        log::warn!("rend3 diagnostics: {message}");
+        self.reports.lock().push(Diagnostic { kind, message });
+    }
+
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.reports.lock())
+    }
+
+    pub fn check_transform(&self, transform: &Mat4, what: &str, location: &Location<'_>) {
+        if !transform.is_finite() {
+            self.report(
+                DiagnosticKind::NonFiniteTransform,
+                format!("{what} has a transform containing NaN or infinity, set at {location}: {transform:?}"),
+            );
+        } else if Mat3::from_mat4(*transform).determinant().abs() <= f32::EPSILON * f32::EPSILON {
+            self.report(
+                DiagnosticKind::ZeroScaleTransform,
+                format!("{what} has a transform with zero scale on at least one axis, set at {location}"),
+            );
+        }
+    }
+
+    /// Forgets the materials removed last frame.
+    pub fn begin_frame(&self) {
+        self.removed_materials.lock().clear();
+    }
+
+    /// Records a material being removed, before it is.
+    pub fn material_removed(
+        &self,
+        material_manager: &MaterialManager,
+        handle: RawMaterialHandle,
+        location: Location<'static>,
+    ) {
+        let label = material_manager.label(handle).map(Arc::from);
+        self.removed_materials.lock().insert(handle, RemovedMaterial { label, location });
+    }
+
+    /// Records a material being added, which may reuse the handle of one
+    /// removed earlier.
+    pub fn material_added(&self, handle: RawMaterialHandle) {
+        self.removed_materials.lock().remove(&handle);
+    }
+
+    /// Reports `what` using a material that was removed earlier this frame.
+    pub fn check_material(&self, handle: RawMaterialHandle, what: &str, location: &Location<'_>) {
+        if let Some(removed) = self.removed_materials.lock().get(&handle) {
+            self.report(
+                DiagnosticKind::MissingMaterial,
+                format!(
+                    "{what} uses {} at {location}, which was removed earlier this frame by the drop of its last \
+                     handle at {}",
+                    describe("material", handle.idx, removed.label.as_deref()),
+                    removed.location
+                ),
+            );
+        }
+    }
+
+    /// Queues a check of the formats of a material's textures, which may not
+    /// have been added yet.
+    pub fn queue_material_textures(
+        &self,
+        handle: RawMaterialHandle,
+        textures: Vec<Option<RawTexture2DHandle>>,
+        location: Location<'static>,
+    ) {
+        self.material_textures.lock().push(MaterialTextureCheck { handle, textures, location });
+    }
+
+    /// Checks the queued material textures. Checks of textures that haven't
+    /// been added yet are kept for the next frame.
+    pub fn check_material_textures(
+        &self,
+        material_manager: &MaterialManager,
+        texture_manager: &TextureManager<Texture2DTag>,
+        features: Features,
+    ) {
+        let mut checks = self.material_textures.lock();
+        checks.retain(|check| {
+            if check.textures.iter().flatten().any(|&t| texture_manager.try_get_internal(t).is_none()) {
+                return true;
+            }
+
+            for (slot, texture) in check.textures.iter().enumerate() {
+                let Some(texture) = texture else {
+                    continue;
+                };
+                let desc = &texture_manager.get_internal(*texture).desc;
+                let format = desc.format;
+                let sample_type = format.sample_type(None, Some(features));
+                if sample_type != Some(TextureSampleType::Float { filterable: true }) {
+                    self.report(
+                        DiagnosticKind::IncompatibleTextureFormat,
+                        format!(
+                            "{} uses {} in slot {slot}, whose format {format:?} can't be sampled with a filtering \
+                             sampler, set at {}",
+                            describe("material", check.handle.idx, material_manager.label(check.handle)),
+                            describe("texture", texture.idx, desc.label),
+                            check.location
+                        ),
+                    );
+                }
+            }
+            false
+        });
+    }
+}
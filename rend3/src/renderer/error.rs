@@ -1,5 +1,5 @@
 use thiserror::Error;
-use wgpu::Features;
+use wgpu::{DownlevelFlags, Features};
 use wgpu_profiler::CreationError;
 
 /// Enum mapping to each of a device's limit.
@@ -43,8 +43,12 @@ pub enum RendererInitializationError {
     MissingAdapter,
     #[error("The device limit of {:?} is {} but renderer requires at least {}", ty, device_limit, required_limit)]
     LowDeviceLimit { ty: LimitType, device_limit: u64, required_limit: u64 },
+    #[error("The device limit {} is {} but {} was requested", name, device_limit, required_limit)]
+    LowRequestedLimit { name: &'static str, device_limit: u64, required_limit: u64 },
     #[error("Device is missing required features: {:?}", features)]
     MissingDeviceFeatures { features: Features },
+    #[error("Device is missing required downlevel capabilities: {:?}", flags)]
+    MissingDownlevelFlags { flags: DownlevelFlags },
     #[error("Requesting a device failed")]
     RequestDeviceFailed,
     #[error("Failed to create GpuProfiler")]
@@ -1,14 +1,58 @@
+use std::panic::Location;
+
+use glam::Vec2;
 use wgpu::CommandEncoderDescriptor;
 
 use crate::{
     graph::InstructionEvaluationOutput,
     instruction::{Instruction, InstructionKind},
-    Renderer,
+    managers::SkeletonCreationError,
+    renderer::{diagnostics::describe_object, GpuScopeVerbosity, RendererDataCore},
+    types::{ObjectMeshKind, RawMeshHandle},
+    util::typedefs::FastHashMap,
+    InstructionOperation, InstructionStats, Renderer,
 };
 
+/// Recreates the skeletons and objects using meshes whose data changed.
+fn refresh_mesh_users(
+    renderer: &Renderer,
+    data_core: &mut RendererDataCore,
+    meshes: &[RawMeshHandle],
+) -> Result<(), SkeletonCreationError> {
+    let skeletons = data_core.skeleton_manager.rebuild_for_meshes(&renderer.device, &renderer.mesh_manager, meshes)?;
+    data_core.object_manager.refresh_objects(
+        &renderer.device,
+        &renderer.mesh_manager,
+        &data_core.skeleton_manager,
+        &mut data_core.material_manager,
+        |mesh_kind| match mesh_kind {
+            ObjectMeshKind::Static(mesh) => meshes.contains(&mesh.get_raw()),
+            ObjectMeshKind::Animated(skeleton) => skeletons.contains(&skeleton.get_raw()),
+        },
+    );
+    Ok(())
+}
+
+fn count_instructions(
+    counts: &mut FastHashMap<(&'static str, InstructionOperation), FastHashMap<String, u32>>,
+    key: (&'static str, InstructionOperation),
+    file: &str,
+    count: u32,
+) {
+    let files = counts.entry(key).or_default();
+    match files.get_mut(file) {
+        Some(total) => *total += count,
+        None => {
+            files.insert(file.to_owned(), count);
+        }
+    }
+}
+
 pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput {
     profiling::scope!("Renderer::evaluate_instructions");
 
+    renderer.gpu_capture.begin_frame(&renderer.device);
+
     let mut instructions = renderer.instructions.consumer.lock();
 
     // 16 encoders is a reasonable default
@@ -22,22 +66,66 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
 
     {
         profiling::scope!("Instruction Processing");
-        for Instruction { kind, location: _ } in instructions.drain(..) {
+
+        // What was current last frame becomes the previous frame.
+        data_core.viewport_camera_state.begin_frame();
+        data_core.object_manager.begin_frame();
+        data_core.skeleton_manager.begin_frame();
+        data_core.clock.begin_frame();
+        renderer.diagnostics.begin_frame();
+
+        let diagnostics = renderer.diagnostics.enabled().then_some(&renderer.diagnostics);
+        // Keyed by kind, then file, so the file is only allocated once.
+        let mut instruction_counts = FastHashMap::<_, FastHashMap<String, u32>>::default();
+        // Streaming pushes long runs of the same instruction, which are
+        // counted together.
+        let mut run: Option<((&'static str, InstructionOperation), Location<'static>, u32)> = None;
+        // Deleted objects are removed together once all instructions ran. No
+        // instruction of this frame can use them, as their handles are gone.
+        let mut deleted_objects = Vec::new();
+        // Advancing time only changes the scene if the clock isn't paused,
+        // which is checked once all instructions ran.
+        let mut changed = false;
+        for Instruction { kind, location } in instructions.drain(..) {
+            changed |= !matches!(kind, InstructionKind::AdvanceTime { .. });
+            let key = (kind.name(), kind.operation());
+            match &mut run {
+                Some((run_key, run_location, count)) if *run_key == key && run_location.file() == location.file() => {
+                    *count += 1
+                }
+                _ => {
+                    if let Some((key, run_location, count)) = run.replace((key, location, 1)) {
+                        count_instructions(&mut instruction_counts, key, run_location.file(), count);
+                    }
+                }
+            }
             match kind {
                 InstructionKind::AddSkeleton { handle, skeleton } => {
                     profiling::scope!("Add Skeleton");
-                    let profiler_query = data_core.profiler.try_lock().unwrap().begin_query(
+                    let profiler_query = renderer.profiler_state.begin_gpu_scope(
+                        &data_core.profiler,
+                        GpuScopeVerbosity::All,
                         "Add Skeleton",
                         &mut encoder,
                         &renderer.device,
                     );
                     data_core.skeleton_manager.add(handle, *skeleton);
-                    data_core.profiler.try_lock().unwrap().end_query(&mut encoder, profiler_query);
+                    renderer.profiler_state.end_gpu_scope(&data_core.profiler, &mut encoder, profiler_query);
                 }
                 InstructionKind::AddTexture2D { handle, internal_texture, cmd_buf } => {
                     cmd_bufs.extend(cmd_buf);
                     data_core.d2_texture_manager.fill(handle, internal_texture);
                 }
+                InstructionKind::ReplaceTexture2D { handle, internal_texture, cmd_buf } => {
+                    profiling::scope!("Replace Texture 2D");
+                    cmd_bufs.extend(cmd_buf);
+                    data_core.d2_texture_manager.fill(handle, internal_texture);
+                    data_core.material_manager.texture_replaced(
+                        &renderer.device,
+                        &data_core.d2_texture_manager,
+                        handle,
+                    );
+                }
                 InstructionKind::AddTexture2DFromTexture { handle, texture } => {
                     data_core.d2_texture_manager.fill_from_texture(&renderer.device, &mut encoder, handle, texture)
                 }
@@ -45,8 +133,15 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                     cmd_bufs.extend(cmd_buf);
                     data_core.d2c_texture_manager.fill(handle, internal_texture);
                 }
+                InstructionKind::AddTextureCubeArray { handle, internal_texture, cmd_buf } => {
+                    cmd_bufs.extend(cmd_buf);
+                    data_core.d2ca_texture_manager.fill(handle, internal_texture);
+                }
                 InstructionKind::AddMaterial { handle, fill_invoke } => {
                     profiling::scope!("Add Material");
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.material_added(handle);
+                    }
                     fill_invoke(
                         &mut data_core.material_manager,
                         &renderer.device,
@@ -60,6 +155,9 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 }
                 InstructionKind::ChangeMaterial { handle, change_invoke } => {
                     profiling::scope!("Change Material");
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.check_material(handle, "a material change", &location);
+                    }
 
                     change_invoke(
                         &mut data_core.material_manager,
@@ -68,7 +166,47 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                         handle,
                     );
                 }
+                InstructionKind::ModifyMaterials { handles, modify_invoke } => {
+                    profiling::scope!("Modify Materials");
+                    if let Some(diagnostics) = diagnostics {
+                        for &handle in &handles {
+                            diagnostics.check_material(handle, "a material modification", &location);
+                        }
+                    }
+
+                    modify_invoke(
+                        &mut data_core.material_manager,
+                        &renderer.device,
+                        &data_core.d2_texture_manager,
+                        &handles,
+                    );
+                }
+                InstructionKind::UpdateTexture2D { handle, update } => {
+                    profiling::scope!("Update Texture 2D");
+                    if let Err(e) = data_core.d2_texture_manager.update(&renderer.device, &mut encoder, handle, &update)
+                    {
+                        log::error!("Failed to update 2D texture {} at {location}: {e}", handle.idx);
+                    }
+                }
+                InstructionKind::UpdateTextureCube { handle, update } => {
+                    profiling::scope!("Update Texture Cube");
+                    if let Err(e) =
+                        data_core.d2c_texture_manager.update(&renderer.device, &mut encoder, handle, &update)
+                    {
+                        log::error!("Failed to update cube texture {} at {location}: {e}", handle.idx);
+                    }
+                }
+                InstructionKind::UpdateTextureCubeArray { handle, update } => {
+                    profiling::scope!("Update Texture Cube Array");
+                    if let Err(e) =
+                        data_core.d2ca_texture_manager.update(&renderer.device, &mut encoder, handle, &update)
+                    {
+                        log::error!("Failed to update cube array texture {} at {location}: {e}", handle.idx);
+                    }
+                }
                 InstructionKind::AddObject { handle, object } => {
+                    // Checked once added, so the label of the mesh is known.
+                    let checked = (object.transform, *object.material);
                     data_core.object_manager.add(
                         &renderer.device,
                         handle,
@@ -77,10 +215,109 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                         &data_core.skeleton_manager,
                         &mut data_core.material_manager,
                     );
+                    if let Some(diagnostics) = diagnostics {
+                        let (transform, material) = checked;
+                        let what = describe_object(&data_core.object_manager, handle);
+                        diagnostics.check_transform(&transform, &what, &location);
+                        diagnostics.check_material(material, &what, &location);
+                    }
+                }
+                InstructionKind::AddObjects { objects } => {
+                    let checked: Vec<_> = match diagnostics {
+                        Some(_) => objects
+                            .iter()
+                            .map(|(handle, object)| (*handle, object.transform, *object.material))
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    data_core.object_manager.add_many(
+                        &renderer.device,
+                        objects,
+                        &renderer.mesh_manager,
+                        &data_core.skeleton_manager,
+                        &mut data_core.material_manager,
+                    );
+                    if let Some(diagnostics) = diagnostics {
+                        for (handle, transform, material) in checked {
+                            let what = describe_object(&data_core.object_manager, handle);
+                            diagnostics.check_transform(&transform, &what, &location);
+                            diagnostics.check_material(material, &what, &location);
+                        }
+                    }
                 }
                 InstructionKind::SetObjectTransform { handle, transform } => {
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.check_transform(
+                            &transform,
+                            &describe_object(&data_core.object_manager, handle),
+                            &location,
+                        );
+                    }
                     data_core.object_manager.set_object_transform(handle, transform);
                 }
+                InstructionKind::SetObjectCustomData { handle, data } => {
+                    data_core.object_manager.set_object_custom_data(handle, data);
+                }
+                InstructionKind::SetObjectOpacity { handle, opacity } => {
+                    data_core.object_manager.set_object_opacity(handle, opacity);
+                }
+                InstructionKind::SetObjectStatic { handle, is_static } => {
+                    data_core.object_manager.set_object_static(handle, is_static);
+                }
+                InstructionKind::SetObjectShadowSettings { handle, settings } => {
+                    data_core.object_manager.set_object_shadow_settings(handle, settings);
+                }
+                InstructionKind::SetObjectRenderOrder { handle, order } => {
+                    data_core.object_manager.set_object_render_order(handle, order);
+                }
+                InstructionKind::SetObjectLod { handle, lod } => {
+                    data_core.object_manager.set_object_lod(handle, lod);
+                }
+                InstructionKind::SetObjectFadeMode { handle, mode } => {
+                    data_core.object_manager.set_object_fade_mode(handle, mode);
+                }
+                InstructionKind::SetObjectLabel { handle, label } => {
+                    data_core.object_manager.set_object_label(handle, label);
+                }
+                InstructionKind::SetMaterialLabel { handle, label } => {
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.check_material(handle, "a material label change", &location);
+                    }
+                    data_core.material_manager.set_label(handle, label);
+                }
+                InstructionKind::SetObjectParent { handle, parent } => {
+                    data_core.object_manager.set_object_parent(handle, parent);
+                }
+                InstructionKind::SetObjectTransforms { transforms } => {
+                    profiling::scope!("Set Object Transforms");
+                    if let Some(diagnostics) = diagnostics {
+                        for (handle, transform) in &transforms {
+                            let what = describe_object(&data_core.object_manager, *handle);
+                            diagnostics.check_transform(transform, &what, &location);
+                        }
+                    }
+                    data_core.object_manager.set_object_transforms(&transforms);
+                }
+                InstructionKind::SetInstances { handle, instances } => {
+                    profiling::scope!("Set Instances");
+                    if let Some(diagnostics) = diagnostics {
+                        let what = describe_object(&data_core.object_manager, handle);
+                        for instance in &instances {
+                            diagnostics.check_transform(&instance.transform, &what, &location);
+                        }
+                    }
+                    data_core.object_manager.set_instances(handle, instances);
+                }
+                InstructionKind::SetInstanceTransforms { handle, start, transforms } => {
+                    profiling::scope!("Set Instance Transforms");
+                    if let Some(diagnostics) = diagnostics {
+                        let what = describe_object(&data_core.object_manager, handle);
+                        for transform in &transforms {
+                            diagnostics.check_transform(transform, &what, &location);
+                        }
+                    }
+                    data_core.object_manager.set_instance_transforms(handle, start, &transforms);
+                }
                 InstructionKind::SetSkeletonJointDeltas { handle, joint_matrices } => {
                     data_core.skeleton_manager.set_joint_matrices(handle, joint_matrices);
                 }
@@ -90,6 +327,15 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::ChangeDirectionalLight { handle, change } => {
                     data_core.directional_light_manager.update(handle, change);
                 }
+                InstructionKind::SetDirectionalShadowFitting { handle, fitting } => {
+                    data_core.directional_light_manager.set_shadow_fitting(handle, fitting);
+                }
+                InstructionKind::AddShadowProjector { handle, projector } => {
+                    data_core.directional_light_manager.add_projector(handle, projector);
+                }
+                InstructionKind::UpdateShadowProjector { handle, projector } => {
+                    data_core.directional_light_manager.update_projector(handle, projector);
+                }
                 InstructionKind::AddPointLight { handle, light } => {
                     data_core.point_light_manager.add(handle, light);
                 }
@@ -102,7 +348,31 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::SetCameraData { data } => {
                     data_core.viewport_camera_state.set_data(data);
                 }
+                InstructionKind::RebaseWorld { offset } => {
+                    data_core.object_manager.translate_all(offset);
+                    data_core.point_light_manager.translate_all(offset);
+                    data_core.directional_light_manager.translate_projectors(offset);
+                    data_core.viewport_camera_state.translate(offset);
+                }
+                InstructionKind::AdvanceTime { delta_time } => {
+                    data_core.clock.advance(delta_time);
+                }
+                InstructionKind::SetTimePaused { paused } => {
+                    data_core.clock.set_paused(paused);
+                }
+                InstructionKind::SetTime { elapsed } => {
+                    data_core.clock.set_elapsed(elapsed);
+                }
+                InstructionKind::SetCameraJitter { jitter } => {
+                    // Jitter changes the image every frame.
+                    let jitter = match renderer.determinism_enabled() {
+                        true => Vec2::ZERO,
+                        false => jitter,
+                    };
+                    data_core.viewport_camera_state.set_jitter(jitter);
+                }
                 InstructionKind::DuplicateObject { src_handle, dst_handle, change } => {
+                    let checked = (change.transform, change.material.as_deref().copied());
                     data_core.object_manager.duplicate_object(
                         &renderer.device,
                         src_handle,
@@ -112,50 +382,147 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                         &data_core.skeleton_manager,
                         &mut data_core.material_manager,
                     );
+                    if let Some(diagnostics) = diagnostics {
+                        let what = describe_object(&data_core.object_manager, dst_handle);
+                        if let Some(transform) = checked.0 {
+                            diagnostics.check_transform(&transform, &what, &location);
+                        }
+                        if let Some(material) = checked.1 {
+                            diagnostics.check_material(material, &what, &location);
+                        }
+                    }
+                }
+                InstructionKind::ReplaceMesh { handle, internal_mesh } => {
+                    profiling::scope!("Replace Mesh");
+                    let old = renderer.mesh_manager.replace(handle, *internal_mesh);
+                    match refresh_mesh_users(renderer, data_core, &[handle]) {
+                        Ok(()) => renderer.mesh_manager.free(old),
+                        Err(e) => {
+                            log::error!("Failed to replace mesh {} at {location}: {e}", handle.idx);
+                            let new = renderer.mesh_manager.replace(handle, old);
+                            renderer.mesh_manager.free(new);
+                        }
+                    }
+                }
+                InstructionKind::SwapMeshes { a, b } => {
+                    profiling::scope!("Swap Meshes");
+                    renderer.mesh_manager.swap(a, b);
+                    if let Err(e) = refresh_mesh_users(renderer, data_core, &[a, b]) {
+                        log::error!("Failed to swap meshes {} and {} at {location}: {e}", a.idx, b.idx);
+                        renderer.mesh_manager.swap(a, b);
+                    }
+                }
+                InstructionKind::SwapMaterials { a, b } => {
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.check_material(a, "a material swap", &location);
+                        diagnostics.check_material(b, "a material swap", &location);
+                    }
+                    if !data_core.material_manager.swap(a, b) {
+                        log::error!(
+                            "Failed to swap materials {} and {} at {location}: they are of different types",
+                            a.idx,
+                            b.idx
+                        );
+                    }
                 }
                 InstructionKind::DeleteMesh { handle } => {
+                    renderer.retained.remove_mesh(handle);
                     renderer.resource_handle_allocators.mesh.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     renderer.mesh_manager.remove(handle)
                 }
                 InstructionKind::DeleteSkeleton { handle } => {
                     renderer.resource_handle_allocators.skeleton.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.skeleton_manager.remove(&renderer.mesh_manager, handle)
                 }
                 InstructionKind::DeleteTexture2D { handle } => {
+                    renderer.retained.remove_texture_2d(handle);
                     renderer.resource_handle_allocators.d2_texture.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.d2_texture_manager.remove(handle)
                 }
                 InstructionKind::DeleteTextureCube { handle } => {
+                    renderer.retained.remove_texture_cube(handle);
                     renderer.resource_handle_allocators.d2c_texture.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.d2c_texture_manager.remove(handle)
                 }
+                InstructionKind::DeleteTextureCubeArray { handle } => {
+                    renderer.resource_handle_allocators.d2ca_texture.deallocate(handle);
+                    renderer.user_data.remove(handle);
+                    data_core.d2ca_texture_manager.remove(handle)
+                }
                 InstructionKind::DeleteMaterial { handle } => {
+                    if let Some(diagnostics) = diagnostics {
+                        diagnostics.material_removed(&data_core.material_manager, handle, location);
+                    }
+                    renderer.retained.remove_material(handle);
                     renderer.resource_handle_allocators.material.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.material_manager.remove(handle)
                 }
-                InstructionKind::DeleteObject { handle } => {
-                    renderer.resource_handle_allocators.object.deallocate(handle);
-                    data_core.object_manager.remove(handle)
-                }
+                InstructionKind::DeleteObject { handle } => deleted_objects.push(handle),
                 InstructionKind::DeleteDirectionalLight { handle } => {
                     renderer.resource_handle_allocators.directional_light.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.directional_light_manager.remove(handle)
                 }
+                InstructionKind::DeleteShadowProjector { handle } => {
+                    renderer.resource_handle_allocators.shadow_projector.deallocate(handle);
+                    renderer.user_data.remove(handle);
+                    data_core.directional_light_manager.remove_projector(handle)
+                }
                 InstructionKind::DeletePointLight { handle } => {
                     renderer.resource_handle_allocators.point_light.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.point_light_manager.remove(handle);
                 }
                 InstructionKind::DeleteGraphData { handle } => {
                     renderer.resource_handle_allocators.graph_storage.deallocate(handle);
+                    renderer.user_data.remove(handle);
                     data_core.graph_storage.remove(&handle);
                 }
             }
         }
+        if let Some((key, location, count)) = run {
+            count_instructions(&mut instruction_counts, key, location.file(), count);
+        }
+
+        if !deleted_objects.is_empty() {
+            renderer.resource_handle_allocators.object.deallocate_many(&deleted_objects);
+            renderer.user_data.remove_many(&deleted_objects);
+            data_core.object_manager.remove_many(&deleted_objects);
+        }
+
+        data_core.frames_since_change = match changed || data_core.clock.delta() != 0.0 {
+            true => 0,
+            false => data_core.frames_since_change.saturating_add(1),
+        };
+
+        let mut instruction_stats: Vec<_> = instruction_counts
+            .into_iter()
+            .flat_map(|((kind, operation), files)| {
+                files.into_iter().map(move |(file, count)| InstructionStats { kind, operation, file, count })
+            })
+            .collect();
+        instruction_stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.kind.cmp(b.kind)).then(a.file.cmp(&b.file)));
+        renderer.frame_stats.record_instructions(instruction_stats);
     }
 
+    let upload_query = renderer.profiler_state.begin_gpu_scope(
+        &data_core.profiler,
+        GpuScopeVerbosity::All,
+        "Resource Uploads",
+        &mut encoder,
+        &renderer.device,
+    );
+
+    data_core.object_manager.update_hierarchy(&data_core.skeleton_manager);
+
     // Do these in dependency order
     // Level 3
-    data_core.object_manager.evaluate(&renderer.device, &mut encoder, &renderer.scatter);
+    data_core.object_manager.evaluate(&renderer.device, &renderer.queue, &mut encoder, &renderer.scatter);
 
     // Level 2
     let d2_texture = data_core.d2_texture_manager.evaluate(&renderer.device);
@@ -171,11 +538,22 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
         &data_core.d2_texture_manager,
     );
 
+    if renderer.diagnostics.enabled() {
+        renderer.diagnostics.check_material_textures(
+            &data_core.material_manager,
+            &data_core.d2_texture_manager,
+            renderer.features,
+        );
+    }
+
     // Level 0
     let d2c_texture = data_core.d2c_texture_manager.evaluate(&renderer.device);
     let (shadow_target_size, shadows) =
-        data_core.directional_light_manager.evaluate(renderer, &data_core.viewport_camera_state);
+        data_core
+            .directional_light_manager
+            .evaluate(renderer, &data_core.viewport_camera_state, || data_core.object_manager.bounds());
     data_core.point_light_manager.evaluate(renderer);
+    renderer.profiler_state.end_gpu_scope(&data_core.profiler, &mut encoder, upload_query);
     let (mesh_buffer, mesh_cmd_buf) = renderer.mesh_manager.evaluate(&renderer.device);
 
     cmd_bufs.push(mesh_cmd_buf);
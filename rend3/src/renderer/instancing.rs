@@ -0,0 +1,48 @@
+use thiserror::Error;
+
+use crate::types::{MaterialHandle, ObjectHandle, ObjectMeshKind};
+
+/// Handle to an instanced object, returned from
+/// [`Renderer::add_instanced_object`](crate::Renderer::add_instanced_object).
+///
+/// All instances belong to a single object in the renderer, drawn with one
+/// instanced draw per pass after its instances are culled.
+/// Dropping the handle removes all instances.
+#[derive(Debug)]
+pub struct InstancedObjectHandle {
+    pub(super) object: ObjectHandle,
+    pub(super) mesh_kind: ObjectMeshKind,
+    pub(super) material: MaterialHandle,
+    pub(super) len: usize,
+}
+
+impl InstancedObjectHandle {
+    /// Number of instances.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if there are no instances.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The mesh all instances use.
+    pub fn mesh_kind(&self) -> &ObjectMeshKind {
+        &self.mesh_kind
+    }
+
+    /// The material all instances use.
+    pub fn material(&self) -> &MaterialHandle {
+        &self.material
+    }
+}
+
+/// Error updating the instances of an [`InstancedObjectHandle`].
+#[derive(Debug, Clone, Error)]
+pub enum InstanceError {
+    #[error("Instances {start}..{end} are out of range of the {len} instances of the object")]
+    OutOfRange { start: usize, end: usize, len: usize },
+    #[error("{count} instances starting at instance {start} go past the largest possible index")]
+    Overflow { start: usize, count: usize },
+}
@@ -1,37 +1,98 @@
-use std::{marker::PhantomData, panic::Location, sync::Arc};
+use std::{
+    any::Any,
+    marker::PhantomData,
+    ops::Range,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use glam::Mat4;
+use glam::{DMat4, DVec3, Mat4, UVec2, Vec2, Vec4};
 use parking_lot::Mutex;
 use rend3_types::{
-    GraphDataHandle, GraphDataTag, Handedness, Material, MaterialTag, ObjectChange, PointLight, PointLightChange,
-    PointLightHandle, Skeleton, SkeletonHandle, Texture2DTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
-    WasmNotSend,
+    GraphDataHandle, GraphDataTag, Handedness, Instance, InstancedObject, Material, MaterialTag, ObjectChange,
+    PointLight, PointLightChange, PointLightHandle, RawResourceHandle, ResourceHandle, Skeleton, SkeletonHandle,
+    Texture2DTag, TextureCubeArrayHandle, TextureCubeArrayTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
+    VertexAttribute, VertexFormat, WasmNotSend, WasmNotSync,
+};
+use wgpu::{
+    Buffer, CommandEncoder, Device, DeviceLostReason, DownlevelCapabilities, Extent3d, Features, ImageCopyTexture,
+    Limits, Origin3d, Queue,
 };
-use wgpu::{Device, DownlevelCapabilities, Features, Limits, Queue};
 use wgpu_profiler::GpuProfiler;
 
 use crate::{
     graph::{GraphTextureStore, InstructionEvaluationOutput},
     instruction::{InstructionKind, InstructionStreamPair},
     managers::{
-        CameraState, DirectionalLightManager, GraphStorage, HandleAllocator, MaterialManager, MeshCreationError,
-        MeshManager, ObjectManager, PointLightManager, SkeletonCreationError, SkeletonManager, TextureCreationError,
-        TextureManager,
+        CameraState, DirectionalLightManager, DirectionalShadowView, FrameClock, GpuMeshSource, GraphStorage,
+        HandleAllocator, MaterialManager, MeshCreationError, MeshManager, MeshUpdateError, ObjectManager,
+        PointLightManager, ShadowAtlasUsage, SkeletonCreationError, SkeletonManager, TextureCreationError,
+        TextureManager, TextureShape, TextureUpdate,
     },
     types::{
         Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, MaterialHandle, Mesh, MeshHandle,
-        Object, ObjectHandle, Texture, Texture2DHandle,
+        Object, ObjectFadeMode, ObjectHandle, ObjectLod, ObjectParent, ObjectRenderOrder, ObjectShadowSettings,
+        RawMaterialHandle, RawObjectHandle, ShadowFitting, ShadowProjector, ShadowProjectorHandle, Texture,
+        Texture2DHandle,
     },
     util::{mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
     ExtendedAdapterInfo, InstanceAdapterDevice, RendererInitializationError, RendererProfile,
 };
 
+mod capabilities;
+mod capture;
+mod diagnostics;
 pub mod error;
 mod eval;
+mod instancing;
+mod pipeline_stats;
+mod profiler;
+mod readback;
+mod recovery;
 mod setup;
+mod stats;
+mod user_data;
+mod visibility;
+
+use capabilities::CapabilityState;
+pub use capabilities::{Capability, CapabilityFallback, RendererCapabilities};
+use capture::GpuCapture;
+use diagnostics::Diagnostics;
+pub use diagnostics::{Diagnostic, DiagnosticKind};
+pub use instancing::{InstanceError, InstancedObjectHandle};
+pub use pipeline_stats::PipelineStatistics;
+use pipeline_stats::PipelineStatisticsCollector;
+pub(crate) use profiler::ProfilerState;
+pub use profiler::{GpuScopeVerbosity, ProfilerSettings};
+use readback::ReadbackManager;
+pub use readback::{ReadbackError, ReadbackFuture};
+pub use recovery::{DeviceLostInfo, RestoredResources};
+use recovery::{DeviceLostState, RetainedResources};
+use stats::FrameStatsRecorder;
+pub use stats::{FrameStats, GpuPassTime, InstructionOperation, InstructionStats, PassStats, ViewStats};
+use user_data::UserDataStore;
+pub use visibility::ObjectVisibility;
 
 /// Core struct which contains the renderer world. Primary way to interact with
 /// the world.
+///
+/// # Thread Safety
+///
+/// On native targets, the renderer is `Send + Sync`, and every function taking
+/// `&self` may be called from any thread while another thread renders.
+/// Changes are recorded as instructions, which take effect in the order they
+/// were recorded when the next frame evaluates them.
+///
+/// Resource creation validates and uploads data without holding renderer wide
+/// locks. Each call only briefly locks the handle allocator of its resource
+/// kind and the instruction stream, and meshes also lock the mesh data
+/// buffer while space is allocated for them. Loaders creating many resources
+/// from worker threads should use the bulk functions, like
+/// [`Self::add_meshes`] and [`Self::add_textures_2d`], which take each lock
+/// once per batch.
 pub struct Renderer {
     pub(crate) instructions: InstructionStreamPair,
 
@@ -50,6 +111,8 @@ pub struct Renderer {
     pub limits: Limits,
     /// Downlevel limits of the device
     pub downlevel: DownlevelCapabilities,
+    /// Optional features in use and fallbacks taken by routines.
+    pub(crate) capabilities: CapabilityState,
     /// Handedness of all parts of this renderer.
     pub handedness: Handedness,
 
@@ -64,6 +127,29 @@ pub struct Renderer {
     pub mipmap_generator: MipmapGenerator,
     /// Tool which allows scatter uploads to happen.
     pub scatter: ScatterCopy,
+
+    /// Statistics of the frame in flight and the last frame.
+    pub(crate) frame_stats: FrameStatsRecorder,
+    /// Requested and in progress GPU captures.
+    pub(crate) gpu_capture: GpuCapture,
+    /// Pipeline statistics queries of recent frames.
+    pub(crate) pipeline_stats: PipelineStatisticsCollector,
+    /// Opt-in validation of renderer usage.
+    pub(crate) diagnostics: Diagnostics,
+    /// Runtime profiling settings.
+    pub(crate) profiler_state: ProfilerState,
+    /// Set when frames must be reproducible, see [`Self::set_determinism_enabled`].
+    pub(crate) determinism: AtomicBool,
+    /// Readbacks waiting for their buffers to be mapped.
+    pub(crate) readback: ReadbackManager,
+    /// Set when the device is lost.
+    pub(crate) device_lost: Arc<DeviceLostState>,
+    /// CPU copies of resources, to restore them after device loss.
+    pub(crate) retained: RetainedResources,
+    /// Application data attached to resources.
+    pub(crate) user_data: UserDataStore,
+    /// Origin transforms are relative to, see [`Self::set_world_origin`].
+    pub(crate) world_origin: Mutex<DVec3>,
 }
 
 /// Handle allocators
@@ -72,10 +158,12 @@ struct HandleAllocators {
     pub skeleton: HandleAllocator<Skeleton>,
     pub d2_texture: HandleAllocator<Texture2DTag>,
     pub d2c_texture: HandleAllocator<TextureCubeTag>,
+    pub d2ca_texture: HandleAllocator<TextureCubeArrayTag>,
     pub material: HandleAllocator<MaterialTag>,
     pub object: HandleAllocator<Object>,
     pub directional_light: HandleAllocator<DirectionalLight>,
     pub point_light: HandleAllocator<PointLight>,
+    pub shadow_projector: HandleAllocator<ShadowProjector>,
     pub graph_storage: HandleAllocator<GraphDataTag>,
 }
 
@@ -86,10 +174,12 @@ impl Default for HandleAllocators {
             skeleton: HandleAllocator::new(),
             d2_texture: HandleAllocator::new(),
             d2c_texture: HandleAllocator::new(),
+            d2ca_texture: HandleAllocator::new(),
             material: HandleAllocator::new(),
             object: HandleAllocator::new(),
             directional_light: HandleAllocator::new(),
             point_light: HandleAllocator::new(),
+            shadow_projector: HandleAllocator::new(),
             graph_storage: HandleAllocator::new(),
         }
     }
@@ -99,10 +189,18 @@ impl Default for HandleAllocators {
 pub struct RendererDataCore {
     /// Position and settings of the viewport camera.
     pub viewport_camera_state: CameraState,
+    /// Time and frame index shaders animate with.
+    pub clock: FrameClock,
+    /// Frames evaluated since the last one that changed the scene, see
+    /// [`Renderer::needs_redraw`].
+    pub frames_since_change: u32,
     /// Manages all 2D textures, including bindless bind group.
     pub d2_texture_manager: TextureManager<Texture2DTag>,
     /// Manages all Cube textures, including bindless bind groups.
     pub d2c_texture_manager: TextureManager<TextureCubeTag>,
+    /// Manages all Cube array textures. They are only bound one at a time,
+    /// never in bindless bind groups.
+    pub d2ca_texture_manager: TextureManager<TextureCubeArrayTag>,
     /// Manages all materials, including material bind groups when CpuDriven.
     pub material_manager: MaterialManager,
     /// Manages all objects.
@@ -146,16 +244,114 @@ impl Renderer {
     /// the mesh alive.
     #[track_caller]
     pub fn add_mesh(self: &Arc<Self>, mesh: Mesh) -> Result<MeshHandle, MeshCreationError> {
+        let attribute_bytes = mesh.attributes.iter().map(|a| a.bytes()).sum::<u64>();
         let internal_mesh = self.mesh_manager.add(&self.device, mesh)?;
+        let upload_bytes = attribute_bytes + internal_mesh.index_range.end - internal_mesh.index_range.start;
 
         // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
         let handle = self.resource_handle_allocators.mesh.allocate(self);
 
         self.mesh_manager.fill(&handle, internal_mesh);
+        self.frame_stats.record_buffer_upload(upload_bytes);
 
         Ok(handle)
     }
 
+    /// Adds many meshes at once, like [`Self::add_mesh`] on each of them.
+    ///
+    /// The mesh data buffer and the handle allocator are locked once for all
+    /// meshes, and their data is uploaded through a single staging buffer, so
+    /// loaders adding many meshes from worker threads contend far less than
+    /// with one call per mesh. If any mesh is invalid, none are added.
+    #[track_caller]
+    pub fn add_meshes(
+        self: &Arc<Self>,
+        meshes: impl IntoIterator<Item = Mesh>,
+    ) -> Result<Vec<MeshHandle>, MeshCreationError> {
+        let meshes: Vec<_> = meshes.into_iter().collect();
+        let attribute_bytes = meshes.iter().flat_map(|mesh| &mesh.attributes).map(|a| a.bytes()).sum::<u64>();
+        let internal_meshes = self.mesh_manager.add_many(&self.device, meshes)?;
+        let index_bytes = internal_meshes.iter().map(|mesh| mesh.index_range.end - mesh.index_range.start).sum::<u64>();
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handles = self.resource_handle_allocators.mesh.allocate_many(self, internal_meshes.len());
+
+        self.mesh_manager.fill_many(handles.iter().zip(internal_meshes));
+        self.frame_stats.record_buffer_upload(attribute_bytes + index_bytes);
+
+        Ok(handles)
+    }
+
+    /// Adds a mesh whose data lives in user owned buffers, such as the output
+    /// of a procedural generator running on the GPU. The data is copied on
+    /// the GPU into rend3's mesh buffer when the next frame is submitted, so
+    /// the work writing the buffers must be submitted before then.
+    ///
+    /// Unlike [`Self::add_mesh`], the index values can't be validated, and
+    /// must all be less than the vertex count.
+    #[track_caller]
+    pub fn add_mesh_from_buffers(self: &Arc<Self>, source: GpuMeshSource<'_>) -> Result<MeshHandle, MeshCreationError> {
+        let internal_mesh = self.mesh_manager.add_from_buffers(&self.device, source)?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handle = self.resource_handle_allocators.mesh.allocate(self);
+
+        self.mesh_manager.fill(&handle, internal_mesh);
+
+        Ok(handle)
+    }
+
+    /// Replaces the data of a mesh, keeping its handle. Objects and skeletons
+    /// using the mesh pick up the new data when the next frame is evaluated.
+    ///
+    /// If a skeleton's joints don't fit the new mesh, the replacement is
+    /// logged and ignored when the instruction is evaluated.
+    #[track_caller]
+    pub fn replace_mesh(&self, handle: &MeshHandle, mesh: Mesh) -> Result<(), MeshCreationError> {
+        profiling::scope!("Replace Mesh");
+
+        self.retained.replace_mesh(**handle, &mesh);
+        let internal_mesh = self.mesh_manager.add(&self.device, mesh)?;
+
+        self.instructions.push(
+            InstructionKind::ReplaceMesh { handle: **handle, internal_mesh: Box::new(internal_mesh) },
+            *Location::caller(),
+        );
+
+        Ok(())
+    }
+
+    /// Swaps the data of two meshes, so each handle refers to the other's
+    /// data.
+    #[track_caller]
+    pub fn swap_meshes(&self, a: &MeshHandle, b: &MeshHandle) {
+        self.retained.swap_meshes(**a, **b);
+        self.instructions.push(InstructionKind::SwapMeshes { a: **a, b: **b }, *Location::caller());
+    }
+
+    /// Overwrites the values of one vertex attribute of a mesh, starting at
+    /// vertex `start`, without reallocating the mesh. The new data is used
+    /// from the next frame on.
+    ///
+    /// The bounding sphere used for culling is not updated, so edited
+    /// positions should stay within the original bounds. Skeletons made from
+    /// the mesh keep their own copy of the vertex data and are not affected.
+    pub fn update_mesh_vertices<T: VertexFormat>(
+        &self,
+        handle: &MeshHandle,
+        attribute: &'static VertexAttribute<T>,
+        start: usize,
+        data: &[T],
+    ) -> Result<(), MeshUpdateError> {
+        self.mesh_manager.update_vertices(&self.device, handle.get_raw(), attribute, start, data)
+    }
+
+    /// Overwrites the indices of a mesh, starting at index `start`, without
+    /// reallocating the mesh. The new data is used from the next frame on.
+    pub fn update_mesh_indices(&self, handle: &MeshHandle, start: usize, data: &[u32]) -> Result<(), MeshUpdateError> {
+        self.mesh_manager.update_indices(&self.device, handle.get_raw(), start, data)
+    }
+
     /// Adds a skeleton into the renderer. This combines a [`Mesh`] with a set
     /// of joints that can be used to animate that mesh.
     ///
@@ -183,7 +379,7 @@ impl Renderer {
     pub fn add_texture_2d(self: &Arc<Self>, texture: Texture) -> Result<Texture2DHandle, TextureCreationError> {
         profiling::scope!("Add Texture 2D");
 
-        let (cmd_buf, internal_texture) = TextureManager::<Texture2DTag>::add(self, texture, false)?;
+        let (cmd_buf, internal_texture) = TextureManager::<Texture2DTag>::add(self, texture, TextureShape::D2)?;
 
         // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
         let handle = self.resource_handle_allocators.d2_texture.allocate(self);
@@ -194,6 +390,54 @@ impl Renderer {
         Ok(handle)
     }
 
+    /// Adds many 2D textures at once, like [`Self::add_texture_2d`] on each of
+    /// them.
+    ///
+    /// The handle allocator and the instruction stream are locked once for all
+    /// textures. If any texture is invalid, none are added.
+    #[track_caller]
+    pub fn add_textures_2d(
+        self: &Arc<Self>,
+        textures: impl IntoIterator<Item = Texture>,
+    ) -> Result<Vec<Texture2DHandle>, TextureCreationError> {
+        profiling::scope!("Add Textures 2D");
+
+        let internal_textures = textures
+            .into_iter()
+            .map(|texture| TextureManager::<Texture2DTag>::add(self, texture, TextureShape::D2))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handles = self.resource_handle_allocators.d2_texture.allocate_many(self, internal_textures.len());
+
+        self.instructions.push_many(
+            handles.iter().zip(internal_textures).map(|(handle, (cmd_buf, internal_texture))| {
+                InstructionKind::AddTexture2D { handle: **handle, internal_texture, cmd_buf }
+            }),
+            *Location::caller(),
+        );
+
+        Ok(handles)
+    }
+
+    /// Replaces a 2D texture, keeping its handle. The new texture may have a
+    /// different size and format. Materials using it pick it up when the next
+    /// frame is evaluated.
+    #[track_caller]
+    pub fn replace_texture_2d(&self, handle: &Texture2DHandle, texture: Texture) -> Result<(), TextureCreationError> {
+        profiling::scope!("Replace Texture 2D");
+
+        self.retained.replace_texture_2d(**handle, &texture);
+        let (cmd_buf, internal_texture) = TextureManager::<Texture2DTag>::add(self, texture, TextureShape::D2)?;
+
+        self.instructions.push(
+            InstructionKind::ReplaceTexture2D { handle: **handle, internal_texture, cmd_buf },
+            *Location::caller(),
+        );
+
+        Ok(())
+    }
+
     /// Add a 2D texture to the renderer by copying a set of mipmaps from an
     /// existing texture. This new can be used in a [`Material`].
     ///
@@ -211,6 +455,128 @@ impl Renderer {
         handle
     }
 
+    /// Overwrites a region of one mip level of a 2D texture with tightly
+    /// packed texels in the texture's format. The write is copied from a
+    /// staging buffer with the GPU work of the next frame, after the textures
+    /// added before it, so a texture can be updated right after it is added.
+    ///
+    /// `origin` and `extent` must be aligned to the blocks of compressed
+    /// formats. Mipmaps are not regenerated. Invalid updates are logged and
+    /// ignored when the instruction is evaluated.
+    #[track_caller]
+    pub fn update_texture_2d(&self, handle: &Texture2DHandle, mip: u32, origin: UVec2, extent: UVec2, data: Vec<u8>) {
+        let update = TextureUpdate {
+            mip,
+            origin: Origin3d { x: origin.x, y: origin.y, z: 0 },
+            extent: Extent3d { width: extent.x, height: extent.y, depth_or_array_layers: 1 },
+            data,
+        };
+        self.record_texture_upload(update.data.len() as u64);
+        self.instructions
+            .push(InstructionKind::UpdateTexture2D { handle: handle.get_raw(), update }, *Location::caller());
+    }
+
+    /// Overwrites a region of one mip level of one face of a cube texture. See
+    /// [`Renderer::update_texture_2d`].
+    #[track_caller]
+    pub fn update_texture_cube(
+        &self,
+        handle: &TextureCubeHandle,
+        face: u32,
+        mip: u32,
+        origin: UVec2,
+        extent: UVec2,
+        data: Vec<u8>,
+    ) {
+        let update = TextureUpdate {
+            mip,
+            origin: Origin3d { x: origin.x, y: origin.y, z: face },
+            extent: Extent3d { width: extent.x, height: extent.y, depth_or_array_layers: 1 },
+            data,
+        };
+        self.record_texture_upload(update.data.len() as u64);
+        self.instructions
+            .push(InstructionKind::UpdateTextureCube { handle: handle.get_raw(), update }, *Location::caller());
+    }
+
+    /// Overwrites a region of one mip level of one face of a cube array
+    /// texture. `layer` is the face of the cube, `cube * 6 + face`. See
+    /// [`Renderer::update_texture_2d`].
+    #[track_caller]
+    pub fn update_texture_cube_array(
+        &self,
+        handle: &TextureCubeArrayHandle,
+        layer: u32,
+        mip: u32,
+        origin: UVec2,
+        extent: UVec2,
+        data: Vec<u8>,
+    ) {
+        let update = TextureUpdate {
+            mip,
+            origin: Origin3d { x: origin.x, y: origin.y, z: layer },
+            extent: Extent3d { width: extent.x, height: extent.y, depth_or_array_layers: 1 },
+            data,
+        };
+        self.record_texture_upload(update.data.len() as u64);
+        self.instructions
+            .push(InstructionKind::UpdateTextureCubeArray { handle: handle.get_raw(), update }, *Location::caller());
+    }
+
+    /// Add a 2D texture created outside of rend3, such as by a video decoder
+    /// or a compute pipeline, without copying it. This can be used in a
+    /// [`Material`].
+    ///
+    /// `desc` must be the descriptor the texture was created with. The
+    /// texture must have a filterable float format, the TEXTURE_BINDING
+    /// usage, and a single array layer.
+    #[track_caller]
+    pub fn add_texture_2d_from_wgpu(
+        self: &Arc<Self>,
+        texture: wgpu::Texture,
+        desc: wgpu::TextureDescriptor<'static>,
+    ) -> Result<Texture2DHandle, TextureCreationError> {
+        profiling::scope!("Add Texture 2D From wgpu");
+
+        let internal_texture = TextureManager::<Texture2DTag>::wrap(self, texture, desc, TextureShape::D2)?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handle = self.resource_handle_allocators.d2_texture.allocate(self);
+
+        self.instructions.push(
+            InstructionKind::AddTexture2D { handle: *handle, internal_texture, cmd_buf: None },
+            *Location::caller(),
+        );
+
+        Ok(handle)
+    }
+
+    /// Add a cube texture created outside of rend3 without copying it. This
+    /// can be used as a skybox or environment map by a render routine.
+    ///
+    /// Same requirements as [`Self::add_texture_2d_from_wgpu`], except the
+    /// texture must have six array layers.
+    #[track_caller]
+    pub fn add_texture_cube_from_wgpu(
+        self: &Arc<Self>,
+        texture: wgpu::Texture,
+        desc: wgpu::TextureDescriptor<'static>,
+    ) -> Result<TextureCubeHandle, TextureCreationError> {
+        profiling::scope!("Add Texture Cube From wgpu");
+
+        let internal_texture = TextureManager::<TextureCubeTag>::wrap(self, texture, desc, TextureShape::Cube)?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handle = self.resource_handle_allocators.d2c_texture.allocate(self);
+
+        self.instructions.push(
+            InstructionKind::AddTextureCube { handle: *handle, internal_texture, cmd_buf: None },
+            *Location::caller(),
+        );
+
+        Ok(handle)
+    }
+
     /// Adds a Cube texture to the renderer. This can be used as a cube
     /// environment map by a render routine.
     ///
@@ -219,7 +585,7 @@ impl Renderer {
     pub fn add_texture_cube(self: &Arc<Self>, texture: Texture) -> Result<TextureCubeHandle, TextureCreationError> {
         profiling::scope!("Add Texture Cube");
 
-        let (cmd_buf, internal_texture) = TextureManager::<TextureCubeTag>::add(self, texture, true)?;
+        let (cmd_buf, internal_texture) = TextureManager::<TextureCubeTag>::add(self, texture, TextureShape::Cube)?;
 
         // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
         let handle = self.resource_handle_allocators.d2c_texture.allocate(self);
@@ -230,6 +596,129 @@ impl Renderer {
         Ok(handle)
     }
 
+    /// Adds a texture holding `cubes` cube textures, for render routines that
+    /// index cubes in a shader, like reflection probes or point light shadows.
+    ///
+    /// The data holds the faces of every cube one after the other, each face
+    /// with all its uploaded mip levels, like [`Self::add_texture_cube`].
+    /// Generated mipmaps are generated for every face. Fails on devices
+    /// without [`DownlevelFlags::CUBE_ARRAY_TEXTURES`](wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES).
+    ///
+    /// The handle will keep the texture alive.
+    #[track_caller]
+    pub fn add_texture_cube_array(
+        self: &Arc<Self>,
+        texture: Texture,
+        cubes: u32,
+    ) -> Result<TextureCubeArrayHandle, TextureCreationError> {
+        profiling::scope!("Add Texture Cube Array");
+
+        let (cmd_buf, internal_texture) =
+            TextureManager::<TextureCubeArrayTag>::add(self, texture, TextureShape::CubeArray(cubes))?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handle = self.resource_handle_allocators.d2ca_texture.allocate(self);
+
+        self.instructions.push(
+            InstructionKind::AddTextureCubeArray { handle: *handle, internal_texture, cmd_buf },
+            *Location::caller(),
+        );
+
+        Ok(handle)
+    }
+
+    /// Add a cube array texture created outside of rend3 without copying it,
+    /// such as one rendered to by a render routine.
+    ///
+    /// Same requirements as [`Self::add_texture_2d_from_wgpu`], except the
+    /// texture must have six array layers per cube.
+    #[track_caller]
+    pub fn add_texture_cube_array_from_wgpu(
+        self: &Arc<Self>,
+        texture: wgpu::Texture,
+        desc: wgpu::TextureDescriptor<'static>,
+    ) -> Result<TextureCubeArrayHandle, TextureCreationError> {
+        profiling::scope!("Add Texture Cube Array From wgpu");
+
+        let shape = TextureShape::CubeArray(desc.size.depth_or_array_layers / 6);
+        let internal_texture = TextureManager::<TextureCubeArrayTag>::wrap(self, texture, desc, shape)?;
+
+        // Handle allocation must be done _after_ any validation to prevent deletion of a handle that never gets fully added.
+        let handle = self.resource_handle_allocators.d2ca_texture.allocate(self);
+
+        self.instructions.push(
+            InstructionKind::AddTextureCubeArray { handle: *handle, internal_texture, cmd_buf: None },
+            *Location::caller(),
+        );
+
+        Ok(handle)
+    }
+
+    /// Adds a mesh like [`Self::add_mesh`], keeping a CPU copy so it can be
+    /// restored into a new renderer with [`Self::restore_retained_into`]
+    /// after device loss.
+    #[track_caller]
+    pub fn add_mesh_retained(self: &Arc<Self>, mesh: Mesh) -> Result<MeshHandle, MeshCreationError> {
+        let handle = self.add_mesh(mesh.clone())?;
+        self.retained.retain_mesh(*handle, mesh);
+        Ok(handle)
+    }
+
+    /// Adds a 2D texture like [`Self::add_texture_2d`], keeping a CPU copy so
+    /// it can be restored after device loss.
+    #[track_caller]
+    pub fn add_texture_2d_retained(
+        self: &Arc<Self>,
+        texture: Texture,
+    ) -> Result<Texture2DHandle, TextureCreationError> {
+        let handle = self.add_texture_2d(texture.clone())?;
+        self.retained.retain_texture_2d(*handle, texture);
+        Ok(handle)
+    }
+
+    /// Adds a cube texture like [`Self::add_texture_cube`], keeping a CPU copy
+    /// so it can be restored after device loss.
+    #[track_caller]
+    pub fn add_texture_cube_retained(
+        self: &Arc<Self>,
+        texture: Texture,
+    ) -> Result<TextureCubeHandle, TextureCreationError> {
+        let handle = self.add_texture_cube(texture.clone())?;
+        self.retained.retain_texture_cube(*handle, texture);
+        Ok(handle)
+    }
+
+    /// Adds a material like [`Self::add_material`], keeping a copy so it can
+    /// be restored after device loss.
+    ///
+    /// Materials refer to textures by handle, so `remap` is called when
+    /// restoring to build the material for the new renderer, looking up the
+    /// restored textures in the given [`RestoredResources`].
+    #[track_caller]
+    pub fn add_material_retained<M, F>(self: &Arc<Self>, material: M, remap: F) -> MaterialHandle
+    where
+        M: Material + Clone,
+        F: Fn(&M, &RestoredResources) -> M + WasmNotSend + WasmNotSync,
+    {
+        self.add_material_retained_inner(material, Arc::new(remap))
+    }
+
+    #[track_caller]
+    fn add_material_retained_inner<M, F>(self: &Arc<Self>, material: M, remap: Arc<F>) -> MaterialHandle
+    where
+        M: Material + Clone,
+        F: Fn(&M, &RestoredResources) -> M + WasmNotSend + WasmNotSync,
+    {
+        let handle = self.add_material(material.clone());
+        self.retained.retain_material(
+            *handle,
+            Box::new(move |renderer: &Arc<Renderer>, restored: &RestoredResources| {
+                renderer.add_material_retained_inner(remap(&material, restored), Arc::clone(&remap))
+            }),
+        );
+        handle
+    }
+
     /// Adds a material to the renderer. This can be used in an [`Object`].
     ///
     /// The handle will keep the material alive. All objects created with this
@@ -239,6 +728,7 @@ impl Renderer {
     #[track_caller]
     pub fn add_material<M: Material>(self: &Arc<Self>, material: M) -> MaterialHandle {
         let handle = self.resource_handle_allocators.material.allocate(self);
+        self.queue_material_diagnostics(*handle, &material);
         self.instructions.push(
             InstructionKind::AddMaterial {
                 handle: *handle,
@@ -251,9 +741,39 @@ impl Renderer {
         handle
     }
 
+    /// Changes some parameters of many materials of the same type at once,
+    /// such as emissive intensity, albedo tint, or a UV offset. Cheap enough
+    /// to call every frame: it is a single instruction that only re-uploads
+    /// the material data, and bind groups are left alone unless the textures
+    /// change.
+    ///
+    /// `modify` is called with the index into `handles` and the material.
+    /// Panics during evaluation if a material isn't of type `M`.
+    #[track_caller]
+    pub fn modify_materials<'a, M, F>(&self, handles: impl IntoIterator<Item = &'a MaterialHandle>, modify: F)
+    where
+        M: Material,
+        F: FnMut(usize, &mut M) + WasmNotSend + WasmNotSync + 'static,
+    {
+        let handles: Vec<_> = handles.into_iter().map(|handle| **handle).collect();
+        if handles.is_empty() {
+            return;
+        }
+        self.instructions.push(
+            InstructionKind::ModifyMaterials {
+                handles,
+                modify_invoke: Box::new(move |material_manager, device, d2_manager, handles| {
+                    material_manager.modify::<M>(device, d2_manager, handles, modify)
+                }),
+            },
+            *Location::caller(),
+        )
+    }
+
     /// Updates a given material. Old references will be dropped.
     #[track_caller]
     pub fn update_material<M: Material>(&self, handle: &MaterialHandle, material: M) {
+        self.queue_material_diagnostics(**handle, &material);
         self.instructions.push(
             InstructionKind::ChangeMaterial {
                 handle: **handle,
@@ -265,6 +785,33 @@ impl Renderer {
         )
     }
 
+    /// Swaps the data of two materials of the same type, so each handle refers
+    /// to the other's data. Materials of different types are logged and left
+    /// alone when the instruction is evaluated.
+    #[track_caller]
+    pub fn swap_materials(&self, a: &MaterialHandle, b: &MaterialHandle) {
+        self.retained.swap_materials(**a, **b);
+        self.instructions.push(InstructionKind::SwapMaterials { a: **a, b: **b }, *Location::caller());
+    }
+
+    /// Sets the label of a material, shown in graphics debuggers on the draws
+    /// of the objects using it. `None` removes the label.
+    #[track_caller]
+    pub fn set_material_label(&self, handle: &MaterialHandle, label: Option<&str>) {
+        self.instructions.push(
+            InstructionKind::SetMaterialLabel { handle: **handle, label: label.map(Arc::from) },
+            *Location::caller(),
+        );
+    }
+
+    #[track_caller]
+    fn queue_material_diagnostics<M: Material>(&self, handle: RawMaterialHandle, material: &M) {
+        if self.diagnostics.enabled() {
+            let textures = material.to_textures().as_ref().to_vec();
+            self.diagnostics.queue_material_textures(handle, textures, *Location::caller());
+        }
+    }
+
     /// Adds an object to the renderer. This will create a visible object using
     /// the given mesh and materal.
     ///
@@ -278,6 +825,20 @@ impl Renderer {
         handle
     }
 
+    /// Adds many objects at once, like [`Self::add_object`] on each of them.
+    ///
+    /// The handles are allocated together and the objects are added with a
+    /// single instruction, which builds large batches in parallel, so
+    /// streaming in tens of thousands of objects a frame stays cheap.
+    #[track_caller]
+    pub fn add_objects(self: &Arc<Self>, objects: impl IntoIterator<Item = Object>) -> Vec<ObjectHandle> {
+        let objects: Vec<_> = objects.into_iter().collect();
+        let handles = self.resource_handle_allocators.object.allocate_many(self, objects.len());
+        let objects = handles.iter().map(|handle| **handle).zip(objects).collect();
+        self.instructions.push(InstructionKind::AddObjects { objects }, *Location::caller());
+        handles
+    }
+
     /// Duplicates an existing object in the renderer, returning the new
     /// object's handle. Any changes specified in the `change` struct will be
     /// applied to the duplicated object, and the same mesh, material and
@@ -292,6 +853,63 @@ impl Renderer {
         dst_handle
     }
 
+    /// Adds many instances of the same mesh and material as a single object.
+    /// The instances are stored in a storage buffer and culled one at a time
+    /// on the GPU, and all of them can be updated with a single instruction.
+    #[track_caller]
+    pub fn add_instanced_object(self: &Arc<Self>, object: InstancedObject) -> InstancedObjectHandle {
+        let InstancedObject { mesh_kind, material, instances } = object;
+        let handle = self.resource_handle_allocators.object.allocate(self);
+        let location = *Location::caller();
+        self.instructions.push(
+            InstructionKind::AddObject {
+                handle: *handle,
+                object: Object { mesh_kind: mesh_kind.clone(), material: material.clone(), transform: Mat4::IDENTITY },
+            },
+            location,
+        );
+        let len = instances.len();
+        self.instructions.push(InstructionKind::SetInstances { handle: *handle, instances }, location);
+        InstancedObjectHandle { object: handle, mesh_kind, material, len }
+    }
+
+    /// Sets the transforms of the instances starting at instance `start`,
+    /// keeping their color and custom data.
+    ///
+    /// Fails without changing anything if the transforms go past the last
+    /// instance.
+    #[track_caller]
+    pub fn set_instance_transforms(
+        &self,
+        handle: &InstancedObjectHandle,
+        start: usize,
+        transforms: &[Mat4],
+    ) -> Result<(), InstanceError> {
+        let count = transforms.len();
+        let end = start.checked_add(count).ok_or(InstanceError::Overflow { start, count })?;
+        if end > handle.len {
+            return Err(InstanceError::OutOfRange { start, end, len: handle.len });
+        }
+        self.instructions.push(
+            InstructionKind::SetInstanceTransforms {
+                handle: handle.object.get_raw(),
+                start,
+                transforms: transforms.to_vec(),
+            },
+            *Location::caller(),
+        );
+        Ok(())
+    }
+
+    /// Replaces all instances, changing the number of instances to the
+    /// number given.
+    #[track_caller]
+    pub fn set_instances(&self, handle: &mut InstancedObjectHandle, instances: Vec<Instance>) {
+        handle.len = instances.len();
+        self.instructions
+            .push(InstructionKind::SetInstances { handle: handle.object.get_raw(), instances }, *Location::caller());
+    }
+
     /// Move the given object to a new transform location.
     #[track_caller]
     pub fn set_object_transform(&self, handle: &ObjectHandle, transform: Mat4) {
@@ -299,6 +917,156 @@ impl Renderer {
             .push(InstructionKind::SetObjectTransform { handle: handle.get_raw(), transform }, *Location::caller());
     }
 
+    /// Sets four floats of per-object data, such as a tint color, dissolve
+    /// factor, or wind phase, for custom material shaders. Objects start
+    /// with all zeros, and duplicated objects keep the data of their source.
+    ///
+    /// Shaders that include `rend3-routine/structures_object.wgsl` read it as
+    /// `object_buffer[indices.object].custom_data`.
+    #[track_caller]
+    pub fn set_object_custom_data(&self, handle: &ObjectHandle, data: Vec4) {
+        self.instructions
+            .push(InstructionKind::SetObjectCustomData { handle: handle.get_raw(), data }, *Location::caller());
+    }
+
+    /// Sets the opacity of an object, from 0 to 1. Objects start fully
+    /// opaque, and duplicated objects keep the opacity of their source.
+    ///
+    /// Below 1, the object fades as set with [`Self::set_object_fade_mode`],
+    /// moving to the pipelines of its material that can fade it, and back
+    /// once it is fully opaque again. By default it is dithered, so it still
+    /// writes depth and doesn't need sorting. This can be used for fading
+    /// objects in and for cross-fading LODs.
+    #[track_caller]
+    pub fn set_object_opacity(&self, handle: &ObjectHandle, opacity: f32) {
+        self.instructions.push(
+            InstructionKind::SetObjectOpacity { handle: handle.get_raw(), opacity: opacity.clamp(0.0, 1.0) },
+            *Location::caller(),
+        );
+    }
+
+    /// Marks an object as static, so cached shadow maps can keep it between
+    /// frames. Objects start dynamic, and duplicated objects keep the flag of
+    /// their source.
+    ///
+    /// Moving a static object or changing its opacity invalidates the cache,
+    /// so objects that move often should stay dynamic. Animating the skeleton
+    /// of a static object does not invalidate the cache.
+    #[track_caller]
+    pub fn set_object_static(&self, handle: &ObjectHandle, is_static: bool) {
+        self.instructions
+            .push(InstructionKind::SetObjectStatic { handle: handle.get_raw(), is_static }, *Location::caller());
+    }
+
+    /// Attaches an object to a parent object or one of the parent's joints, or
+    /// detaches it with `None`.
+    ///
+    /// While attached, transforms set with [`Self::set_object_transform`] are
+    /// relative to the parent, starting with the object's current transform,
+    /// and the object follows the parent as it moves. Detached objects, and
+    /// children of removed objects, keep their world transform. Attaching an
+    /// object to itself or one of its descendants is ignored.
+    #[track_caller]
+    pub fn set_object_parent(&self, handle: &ObjectHandle, parent: Option<ObjectParent>) {
+        let parent = parent.map(|parent| match parent {
+            ObjectParent::Object(object) => (object.get_raw(), None),
+            ObjectParent::Joint { object, joint } => (object.get_raw(), Some(joint)),
+        });
+        self.instructions
+            .push(InstructionKind::SetObjectParent { handle: handle.get_raw(), parent }, *Location::caller());
+    }
+
+    /// Sets whether an object casts and receives shadows, how far away it
+    /// casts them, into which shadow maps, and at which level of detail.
+    /// Duplicated objects keep the settings of their source.
+    ///
+    /// Objects start with [`ObjectShadowSettings::default`], which casts into
+    /// every shadow map at any distance, one level of detail lower than the
+    /// main passes if the mesh has one, and receives shadows.
+    #[track_caller]
+    pub fn set_object_shadow_settings(&self, handle: &ObjectHandle, settings: ObjectShadowSettings) {
+        self.instructions
+            .push(InstructionKind::SetObjectShadowSettings { handle: handle.get_raw(), settings }, *Location::caller());
+    }
+
+    /// Sets where an object is drawn relative to other objects of the same
+    /// pass. Objects start with [`ObjectRenderOrder::default`], drawn in the
+    /// order their material asks for.
+    #[track_caller]
+    pub fn set_object_render_order(&self, handle: &ObjectHandle, order: ObjectRenderOrder) {
+        self.instructions
+            .push(InstructionKind::SetObjectRenderOrder { handle: handle.get_raw(), order }, *Location::caller());
+    }
+
+    /// Sets how an object fades while its opacity is below 1, see
+    /// [`Self::set_object_opacity`]. Objects start with
+    /// [`ObjectFadeMode::Dither`], and duplicated objects keep the mode of
+    /// their source.
+    #[track_caller]
+    pub fn set_object_fade_mode(&self, handle: &ObjectHandle, mode: ObjectFadeMode) {
+        self.instructions
+            .push(InstructionKind::SetObjectFadeMode { handle: handle.get_raw(), mode }, *Location::caller());
+    }
+
+    /// Sets the level of detail an object is drawn with, and cross-fades
+    /// between levels. Objects start with the mesh itself, and duplicated
+    /// objects keep the level of their source.
+    ///
+    /// Like [`Self::set_object_opacity`], only cutout materials dither the
+    /// cross-fade. Other materials switch levels halfway through it.
+    #[track_caller]
+    pub fn set_object_lod(&self, handle: &ObjectHandle, lod: ObjectLod) {
+        let lod = ObjectLod { fade: lod.fade.clamp(0.0, 1.0), ..lod };
+        self.instructions.push(InstructionKind::SetObjectLod { handle: handle.get_raw(), lod }, *Location::caller());
+    }
+
+    /// Sets the label of an object, shown in graphics debuggers on its draws
+    /// along with the labels of its mesh and material. `None` removes the
+    /// label. Duplicated objects keep the label of their source.
+    #[track_caller]
+    pub fn set_object_label(&self, handle: &ObjectHandle, label: Option<&str>) {
+        self.instructions.push(
+            InstructionKind::SetObjectLabel { handle: handle.get_raw(), label: label.map(Arc::from) },
+            *Location::caller(),
+        );
+    }
+
+    /// Move many objects at once. This is a single instruction, so it is much
+    /// cheaper than calling [`Self::set_object_transform`] for each object.
+    #[track_caller]
+    pub fn set_object_transforms<'a>(&self, transforms: impl IntoIterator<Item = (&'a ObjectHandle, Mat4)>) {
+        let transforms: Vec<_> =
+            transforms.into_iter().map(|(handle, transform)| (handle.get_raw(), transform)).collect();
+        if transforms.is_empty() {
+            return;
+        }
+        self.instructions.push(InstructionKind::SetObjectTransforms { transforms }, *Location::caller());
+    }
+
+    /// Adds a skeleton deforming `mesh` that shares the joint matrices of
+    /// `source`, so separate meshes such as a body and its clothes deform
+    /// together. The meshes must use the same joint order; see
+    /// [`JointRemap`](crate::types::JointRemap) for skeletons that don't.
+    ///
+    /// Setting the joint matrices of `source` also sets them on every skeleton
+    /// sharing them. The new skeleton keeps `source` alive.
+    #[track_caller]
+    pub fn add_shared_skeleton(
+        self: &Arc<Self>,
+        source: &SkeletonHandle,
+        mesh: MeshHandle,
+    ) -> Result<SkeletonHandle, SkeletonCreationError> {
+        let internal =
+            SkeletonManager::validate_shared_skeleton(&self.device, &self.mesh_manager, source.clone(), mesh)?;
+
+        let handle = self.resource_handle_allocators.skeleton.allocate(self);
+
+        self.instructions
+            .push(InstructionKind::AddSkeleton { handle: *handle, skeleton: Box::new(internal) }, *Location::caller());
+
+        Ok(handle)
+    }
+
     /// Sets the joint positions for a skeleton. See
     /// [Renderer::set_skeleton_joint_matrices] to set the vertex
     /// transformations directly, without having to supply two separate
@@ -371,6 +1139,40 @@ impl Renderer {
             .push(InstructionKind::ChangeDirectionalLight { handle: handle.get_raw(), change }, *Location::caller())
     }
 
+    /// Sets how the shadow map of a directional light is fit around the
+    /// camera. Lights start with [`ShadowFitting::default`].
+    #[track_caller]
+    pub fn set_directional_light_shadow_fitting(&self, handle: &DirectionalLightHandle, fitting: ShadowFitting) {
+        self.instructions.push(
+            InstructionKind::SetDirectionalShadowFitting { handle: handle.get_raw(), fitting },
+            *Location::caller(),
+        )
+    }
+
+    /// Add a custom view rendering the shadow casters into the shadow atlas,
+    /// such as a top-down projector for blob shadows.
+    ///
+    /// Materials read the result as a projected texture through
+    /// `rend3-routine/shadow/projector.wgsl`, indexing `shadow_projectors`
+    /// with the `idx` of the handle.
+    ///
+    /// The handle will keep the projector alive.
+    #[track_caller]
+    pub fn add_shadow_projector(self: &Arc<Self>, projector: ShadowProjector) -> ShadowProjectorHandle {
+        let handle = self.resource_handle_allocators.shadow_projector.allocate(self);
+
+        self.instructions.push(InstructionKind::AddShadowProjector { handle: *handle, projector }, *Location::caller());
+
+        handle
+    }
+
+    /// Replaces the view and resolution of the given shadow projector.
+    #[track_caller]
+    pub fn update_shadow_projector(&self, handle: &ShadowProjectorHandle, projector: ShadowProjector) {
+        self.instructions
+            .push(InstructionKind::UpdateShadowProjector { handle: handle.get_raw(), projector }, *Location::caller())
+    }
+
     /// Updates the settings for given point light.
     #[track_caller]
     pub fn update_point_light(&self, handle: &PointLightHandle, change: PointLightChange) {
@@ -392,6 +1194,27 @@ impl Renderer {
         GraphDataHandle(handle, PhantomData)
     }
 
+    /// Attaches application data to a resource, like the entity an object or
+    /// material belongs to, replacing the data it had. The data is dropped
+    /// along with the resource.
+    ///
+    /// Works with the handles of every resource. The data can be looked up
+    /// from raw handles, as found by GPU picking or in debug reports, with
+    /// [`Self::user_data`].
+    pub fn set_user_data<T: 'static, D: Any + Send + Sync>(&self, handle: &ResourceHandle<T>, data: D) {
+        self.user_data.insert(handle.get_raw(), Arc::new(data));
+    }
+
+    /// Removes the application data attached to a resource.
+    pub fn remove_user_data<T: 'static>(&self, handle: &ResourceHandle<T>) {
+        self.user_data.remove(handle.get_raw());
+    }
+
+    /// The application data attached to a resource, if it has any of type `D`.
+    pub fn user_data<T: 'static, D: Any + Send + Sync>(&self, handle: RawResourceHandle<T>) -> Option<Arc<D>> {
+        self.user_data.get(handle)?.downcast().ok()
+    }
+
     /// Sets the aspect ratio of the camera. This should correspond with the
     /// aspect ratio of the user.
     #[track_caller]
@@ -399,12 +1222,98 @@ impl Renderer {
         self.instructions.push(InstructionKind::SetAspectRatio { ratio }, *Location::caller())
     }
 
+    /// Advances the time shaders animate with by `delta_time` seconds, unless
+    /// it is paused. Call once a frame, before rendering it. rend3-framework
+    /// advances it by the time between frames.
+    #[track_caller]
+    pub fn advance_time(&self, delta_time: f32) {
+        self.instructions.push(InstructionKind::AdvanceTime { delta_time }, *Location::caller())
+    }
+
+    /// Pauses or resumes the time shaders animate with. Paused frames have a
+    /// delta time of zero, but still count in the frame index.
+    #[track_caller]
+    pub fn set_time_paused(&self, paused: bool) {
+        self.instructions.push(InstructionKind::SetTimePaused { paused }, *Location::caller())
+    }
+
+    /// Sets the time shaders animate with to `elapsed` seconds, such as to
+    /// restart effects or seek through a replay.
+    #[track_caller]
+    pub fn set_time(&self, elapsed: f64) {
+        self.instructions.push(InstructionKind::SetTime { elapsed }, *Location::caller())
+    }
+
     /// Sets the position, pov, or projection mode of the camera.
     #[track_caller]
     pub fn set_camera_data(&self, data: Camera) {
         self.instructions.push(InstructionKind::SetCameraData { data }, *Location::caller())
     }
 
+    /// Offsets the projection of the camera by `jitter` in normalized device
+    /// coordinates, for temporal antialiasing and upscaling. Stays until it is
+    /// set again.
+    #[track_caller]
+    pub fn set_camera_jitter(&self, jitter: Vec2) {
+        self.instructions.push(InstructionKind::SetCameraJitter { jitter }, *Location::caller())
+    }
+
+    /// Moves the origin that all positions are relative to, for worlds too
+    /// large for f32 precision. Everything in the renderer, objects, lights,
+    /// shadow projectors, and the camera, is moved with the origin when the
+    /// instruction is evaluated, so rebasing near the camera every so often
+    /// keeps nearby positions precise without visible jumps.
+    ///
+    /// Transforms and positions given to the renderer after this call must be
+    /// relative to the new origin, see [`Self::relative_transform`].
+    #[track_caller]
+    pub fn set_world_origin(&self, origin: DVec3) {
+        let mut world_origin = self.world_origin.lock();
+        let offset = (*world_origin - origin).as_vec3();
+        *world_origin = origin;
+        drop(world_origin);
+
+        self.instructions.push(InstructionKind::RebaseWorld { offset }, *Location::caller())
+    }
+
+    /// The origin all positions are relative to, see
+    /// [`Self::set_world_origin`].
+    pub fn world_origin(&self) -> DVec3 {
+        *self.world_origin.lock()
+    }
+
+    /// Converts a transform in world space to one relative to the world
+    /// origin, ready to be given to the renderer.
+    pub fn relative_transform(&self, transform: DMat4) -> Mat4 {
+        (DMat4::from_translation(-self.world_origin()) * transform).as_mat4()
+    }
+
+    /// Whether the next frame may look different from the last one, for
+    /// applications that only render when something changed.
+    ///
+    /// Call before [`Self::swap_instruction_buffers`]. The scene changed if
+    /// instructions are waiting, other than advancing a paused clock.
+    /// Temporal effects, like TAA and auto exposure, keep changing for a few
+    /// frames after the scene stops, so a redraw is needed until
+    /// `settle_frames` frames were evaluated without a change.
+    ///
+    /// Changes the renderer doesn't see, like resizing the surface or
+    /// changing the settings of the render graph, also need a redraw.
+    pub fn needs_redraw(&self, settle_frames: u32) -> bool {
+        let data_core = self.data_core.lock();
+        if data_core.frames_since_change < settle_frames {
+            return true;
+        }
+        let paused = data_core.clock.paused();
+        drop(data_core);
+
+        self.instructions
+            .producer
+            .lock()
+            .iter()
+            .any(|instruction| !(paused && matches!(instruction.kind, InstructionKind::AdvanceTime { .. })))
+    }
+
     /// Swaps the front and back instruction buffer. Any world-modifiying functions
     /// called after this will be recorded for the next frame.
     ///
@@ -421,4 +1330,259 @@ impl Renderer {
     pub fn evaluate_instructions(&self) -> InstructionEvaluationOutput {
         eval::evaluate_instructions(self)
     }
+
+    /// Records the work done by a pass in the statistics of the current frame.
+    ///
+    /// Called by render routines while the render graph executes.
+    pub fn record_pass_stats(&self, stats: PassStats) {
+        self.frame_stats.record_pass(stats)
+    }
+
+    /// Records objects the viewport camera draws in the current frame, see
+    /// [`Self::last_frame_visible_objects`].
+    ///
+    /// Called by render routines while the render graph executes.
+    pub fn record_visible_objects(&self, handles: impl IntoIterator<Item = RawObjectHandle>) {
+        self.frame_stats.record_visible_objects(handles)
+    }
+
+    /// Records bytes uploaded into buffers in the statistics of the current frame.
+    pub fn record_buffer_upload(&self, bytes: u64) {
+        self.frame_stats.record_buffer_upload(bytes)
+    }
+
+    /// Records bytes uploaded into textures in the statistics of the current frame.
+    pub fn record_texture_upload(&self, bytes: u64) {
+        self.frame_stats.record_texture_upload(bytes)
+    }
+
+    /// Statistics of the last frame rendered with a render graph.
+    ///
+    /// Uploads are counted from the end of the previous frame, so this
+    /// includes resources added between frames.
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.frame_stats.last()
+    }
+
+    /// Objects the viewport camera drew in the last frame rendered with a
+    /// render graph, after culling. Shadow-only objects and objects drawn by
+    /// other cameras, like reflections, are not included.
+    pub fn last_frame_visible_objects(&self) -> Arc<ObjectVisibility> {
+        self.frame_stats.last_visible_objects()
+    }
+
+    /// GPU durations of the passes of the most recent frame whose timestamp
+    /// queries have been read back, usually a few frames behind.
+    ///
+    /// Every render graph node is timed, so this includes the built-in
+    /// shadow, forward, skinning, and tonemapping passes. Empty if the device
+    /// does not support [`Features::TIMESTAMP_QUERY`](wgpu::Features::TIMESTAMP_QUERY).
+    pub fn last_frame_gpu_times(&self) -> Vec<GpuPassTime> {
+        self.frame_stats.gpu_times()
+    }
+
+    /// Pipeline statistics of every render graph node that records into a
+    /// renderpass, from the most recent frame that has been read back.
+    ///
+    /// Read back a few frames late, like [`Self::last_frame_gpu_times`].
+    /// Empty if the device does not support
+    /// [`Features::PIPELINE_STATISTICS_QUERY`](wgpu::Features::PIPELINE_STATISTICS_QUERY).
+    pub fn last_frame_pipeline_statistics(&self) -> Vec<PipelineStatistics> {
+        self.pipeline_stats.last()
+    }
+
+    /// How much of the directional light shadow atlas is used, as of the last
+    /// instruction evaluation.
+    ///
+    /// The atlas is repacked every frame to fit the shadow maps of the current
+    /// lights, growing and shrinking as lights are added and removed.
+    pub fn shadow_atlas_usage(&self) -> ShadowAtlasUsage {
+        self.data_core.lock().directional_light_manager.atlas_usage()
+    }
+
+    /// The matrices and atlas regions the directional light shadow maps were
+    /// rendered with, as of the last instruction evaluation, for debugging
+    /// shadow fitting.
+    pub fn directional_shadow_views(&self) -> Vec<DirectionalShadowView> {
+        self.data_core.lock().directional_light_manager.shadow_views().to_vec()
+    }
+
+    /// Copies `range` of `buffer` into CPU memory. Call this while recording a
+    /// frame, such as from a render graph node with an encoder; the copy runs
+    /// when the frame is submitted and the data arrives a frame or two later,
+    /// without blocking on the GPU.
+    ///
+    /// `range` must start and end on a multiple of
+    /// [`COPY_BUFFER_ALIGNMENT`](wgpu::COPY_BUFFER_ALIGNMENT). The buffer needs
+    /// the COPY_SRC usage.
+    pub fn readback_buffer(
+        &self,
+        encoder: &mut CommandEncoder,
+        buffer: &Buffer,
+        range: Range<u64>,
+    ) -> Result<ReadbackFuture, ReadbackError> {
+        self.readback.request_buffer(&self.device, encoder, buffer, range)
+    }
+
+    /// Copies a region of a texture into CPU memory, like
+    /// [`Self::readback_buffer`]. The data is tightly packed, without the row
+    /// padding wgpu requires for copies.
+    ///
+    /// The texture needs the COPY_SRC usage.
+    pub fn readback_texture(
+        &self,
+        encoder: &mut CommandEncoder,
+        texture: ImageCopyTexture<'_>,
+        extent: Extent3d,
+    ) -> Result<ReadbackFuture, ReadbackError> {
+        self.readback.request_texture(&self.device, encoder, texture, extent)
+    }
+
+    /// Sets the device lost and uncaptured error callbacks of the device, so
+    /// device loss is recorded for [`Self::device_lost`] and wgpu errors
+    /// caused by the loss are logged instead of panicking.
+    ///
+    /// This replaces any callbacks already set on the device. Applications
+    /// with their own callbacks call [`Self::notify_device_lost`] from them
+    /// instead.
+    pub fn install_device_lost_callbacks(&self) {
+        let lost = Arc::clone(&self.device_lost);
+        self.device.set_device_lost_callback(move |reason, message| lost.lost(reason, message));
+        let lost = Arc::clone(&self.device_lost);
+        self.device.on_uncaptured_error(Box::new(move |error| {
+            if lost.info().is_some() {
+                log::warn!("wgpu error after device loss: {error}");
+            } else {
+                panic!("wgpu error: {error}");
+            }
+        }));
+    }
+
+    /// Records the loss of the device, for applications handling the device
+    /// lost callback of wgpu themselves. Losses from dropping the device or
+    /// replacing the callback are ignored.
+    pub fn notify_device_lost(&self, reason: DeviceLostReason, message: String) {
+        self.device_lost.lost(reason, message);
+    }
+
+    /// Returns the reason the device was lost, or None if it hasn't been.
+    ///
+    /// Loss is only known through [`Self::install_device_lost_callbacks`] or
+    /// [`Self::notify_device_lost`]. Once lost, the renderer can't be used
+    /// anymore. Create a new [`InstanceAdapterDevice`] and renderer, then call
+    /// [`Self::restore_retained_into`] to re-upload retained resources.
+    pub fn device_lost(&self) -> Option<DeviceLostInfo> {
+        self.device_lost.info()
+    }
+
+    /// Returns true if the device has been lost.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.info().is_some()
+    }
+
+    /// Re-adds every resource added with the `_retained` functions to another
+    /// renderer, usually one made after this renderer's device was lost.
+    ///
+    /// The restored resources are retained in the new renderer as well.
+    /// Resources that fail to be re-added are logged and skipped.
+    pub fn restore_retained_into(&self, renderer: &Arc<Renderer>) -> RestoredResources {
+        self.retained.restore_into(renderer)
+    }
+
+    /// Enables or disables the diagnostics mode.
+    ///
+    /// When enabled, the renderer checks for misuse that wgpu can't detect:
+    /// materials used after their removal in the same frame, NaN or zero scale
+    /// transforms, material textures with formats that can't be sampled, and
+    /// render graph nodes reading render targets that no earlier node wrote.
+    /// Problems are logged as warnings, naming resources by their labels, and
+    /// collected for [`Self::take_diagnostics`].
+    pub fn set_diagnostics_enabled(&self, enabled: bool) {
+        self.diagnostics.set_enabled(enabled)
+    }
+
+    /// Returns true if the diagnostics mode is enabled.
+    pub fn diagnostics_enabled(&self) -> bool {
+        self.diagnostics.enabled()
+    }
+
+    /// Which optional features the renderer has on this device, and which
+    /// fallbacks routines have taken because of missing ones.
+    pub fn capabilities(&self) -> RendererCapabilities {
+        self.capabilities.capabilities()
+    }
+
+    /// Returns true if the renderer has the given capability.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.supports(capability)
+    }
+
+    /// Records that a routine takes a different path because a capability is
+    /// missing, reported in [`Self::capabilities`]. Registering the same
+    /// fallback again does nothing.
+    pub fn register_capability_fallback(&self, fallback: CapabilityFallback) {
+        self.capabilities.register_fallback(fallback);
+    }
+
+    /// Enables or disables the determinism mode.
+    ///
+    /// When enabled, rendering the same scene twice gives the same pixels on
+    /// the same adapter, for golden image tests. Camera jitter is ignored,
+    /// temporal upscaling doesn't jitter, tiled light culling builds its
+    /// lists in light order, and rend3-framework hands applications a fixed
+    /// frame time. Time and randomness supplied by the application must be
+    /// fixed by the application.
+    pub fn set_determinism_enabled(&self, enabled: bool) {
+        self.determinism.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns true if the determinism mode is enabled.
+    pub fn determinism_enabled(&self) -> bool {
+        self.determinism.load(Ordering::Relaxed)
+    }
+
+    /// Changes which CPU and GPU profiling scopes the renderer emits.
+    ///
+    /// GPU scopes are what [`Self::frame_stats`] and the statistics returned
+    /// by the render graph are built from, so turning them off also turns
+    /// off GPU pass times.
+    pub fn set_profiler_settings(&self, settings: ProfilerSettings) {
+        self.profiler_state.set_settings(settings);
+        self.data_core
+            .lock()
+            .profiler
+            .lock()
+            .change_settings(settings.gpu_profiler_settings(self.features))
+            .expect("rend3 always keeps pending profiler frames");
+    }
+
+    /// Returns the current profiling settings.
+    pub fn profiler_settings(&self) -> ProfilerSettings {
+        self.profiler_state.settings()
+    }
+
+    /// Takes all problems found by the diagnostics mode since the last call.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    /// Requests a GPU capture of the next `n_frames` frames.
+    ///
+    /// The capture starts in the next call to [`Self::evaluate_instructions`]
+    /// and stops once the last frame's render graph has been submitted. wgpu
+    /// forwards this to the RenderDoc in-application API when the program is
+    /// running under RenderDoc, or to the capture manager on Metal. Otherwise
+    /// this does nothing.
+    ///
+    /// Requesting while a capture is in progress starts a new capture once
+    /// the current one has finished. Requesting zero frames cancels a pending
+    /// request.
+    pub fn trigger_gpu_capture(&self, n_frames: u32) {
+        self.gpu_capture.trigger(n_frames)
+    }
+
+    /// Returns true if a GPU capture is pending or in progress.
+    pub fn is_gpu_capture_active(&self) -> bool {
+        self.gpu_capture.is_active()
+    }
 }
@@ -0,0 +1,180 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Features, MapMode, PipelineStatisticsTypes,
+    QuerySet, QuerySetDescriptor, QueryType, RenderPass,
+};
+
+/// Statistics returned by the graphics pipeline.
+const STATISTICS: PipelineStatisticsTypes = PipelineStatisticsTypes::from_bits_truncate(
+    PipelineStatisticsTypes::VERTEX_SHADER_INVOCATIONS.bits()
+        | PipelineStatisticsTypes::CLIPPER_INVOCATIONS.bits()
+        | PipelineStatisticsTypes::CLIPPER_PRIMITIVES_OUT.bits()
+        | PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS.bits(),
+);
+/// One u64 for each statistic in [`STATISTICS`].
+const QUERY_SIZE: u64 = 4 * 8;
+/// Frames that can be waiting on readback before the oldest is dropped.
+const MAX_PENDING_FRAMES: usize = 4;
+
+/// Pipeline statistics of a single render graph node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    /// Label of the node.
+    pub label: String,
+    /// Number of times the vertex shader was invoked.
+    pub vertex_shader_invocations: u64,
+    /// Number of primitives that reached the clipper.
+    pub clipper_invocations: u64,
+    /// Number of primitives that left the clipper, after culling and clipping.
+    pub clipper_primitives_out: u64,
+    /// Number of times the fragment shader was invoked. Much higher than the
+    /// pixels covered by the pass indicates overdraw.
+    pub fragment_shader_invocations: u64,
+}
+
+/// Query set of the frame being recorded.
+pub(crate) struct PipelineStatisticsFrame {
+    query_set: QuerySet,
+    labels: RefCell<Vec<String>>,
+}
+
+impl PipelineStatisticsFrame {
+    pub fn begin<'a>(&'a self, rpass: &mut RenderPass<'a>, label: &str) {
+        let mut labels = self.labels.borrow_mut();
+        rpass.begin_pipeline_statistics_query(&self.query_set, labels.len() as u32);
+        labels.push(label.to_owned());
+    }
+
+    pub fn end(&self, rpass: &mut RenderPass<'_>) {
+        rpass.end_pipeline_statistics_query();
+    }
+}
+
+struct PendingReadback {
+    buffer: Buffer,
+    labels: Vec<String>,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+/// Collects pipeline statistics for every render graph node that records into
+/// a renderpass, reading them back a few frames later.
+pub(crate) struct PipelineStatisticsCollector {
+    enabled: bool,
+    pending: Mutex<VecDeque<PendingReadback>>,
+    last: Mutex<Vec<PipelineStatistics>>,
+}
+
+impl PipelineStatisticsCollector {
+    pub fn new(features: Features) -> Self {
+        Self {
+            enabled: features.contains(Features::PIPELINE_STATISTICS_QUERY),
+            pending: Mutex::new(VecDeque::new()),
+            last: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates the query set for a frame with `count` renderpass nodes.
+    pub fn begin_frame(&self, device: &Device, count: usize) -> Option<PipelineStatisticsFrame> {
+        if !self.enabled || count == 0 {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("pipeline statistics"),
+            ty: QueryType::PipelineStatistics(STATISTICS),
+            count: count as u32,
+        });
+
+        Some(PipelineStatisticsFrame { query_set, labels: RefCell::new(Vec::with_capacity(count)) })
+    }
+
+    /// Resolves the queries of the frame into a buffer that is mapped once
+    /// the frame is submitted.
+    pub fn resolve(&self, device: &Device, encoder: &mut CommandEncoder, frame: PipelineStatisticsFrame) {
+        let labels = frame.labels.into_inner();
+        if labels.is_empty() {
+            return;
+        }
+
+        let size = labels.len() as u64 * QUERY_SIZE;
+        let resolve = device.create_buffer(&BufferDescriptor {
+            label: Some("pipeline statistics resolve"),
+            size,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("pipeline statistics readback"),
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        encoder.resolve_query_set(&frame.query_set, 0..labels.len() as u32, &resolve, 0);
+        encoder.copy_buffer_to_buffer(&resolve, 0, &readback, 0, size);
+
+        let mut pending = self.pending.lock();
+        if pending.len() == MAX_PENDING_FRAMES {
+            pending.pop_front();
+        }
+        pending.push_back(PendingReadback {
+            buffer: readback,
+            labels,
+            map_requested: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    /// Maps newly resolved frames, and reads back every frame that has
+    /// finished mapping. Must be called after the resolve is submitted.
+    pub fn end_frame(&self) {
+        let mut pending = self.pending.lock();
+
+        for readback in pending.iter_mut().filter(|p| !p.map_requested) {
+            let mapped = Arc::clone(&readback.mapped);
+            readback.buffer.slice(..).map_async(MapMode::Read, move |res| {
+                if res.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+            readback.map_requested = true;
+        }
+
+        while pending.front().is_some_and(|p| p.mapped.load(Ordering::Acquire)) {
+            let readback = pending.pop_front().unwrap();
+
+            let data = readback.buffer.slice(..).get_mapped_range();
+            let values: &[u64] = bytemuck::cast_slice(&data);
+            let stats = readback
+                .labels
+                .into_iter()
+                .zip(values.chunks_exact(4))
+                .map(|(label, v)| PipelineStatistics {
+                    label,
+                    vertex_shader_invocations: v[0],
+                    clipper_invocations: v[1],
+                    clipper_primitives_out: v[2],
+                    fragment_shader_invocations: v[3],
+                })
+                .collect();
+            drop(data);
+            readback.buffer.unmap();
+
+            *self.last.lock() = stats;
+        }
+    }
+
+    pub fn last(&self) -> Vec<PipelineStatistics> {
+        self.last.lock().clone()
+    }
+}
@@ -0,0 +1,107 @@
+use parking_lot::Mutex;
+use wgpu::{Device, Features};
+use wgpu_profiler::{GpuProfiler, GpuProfilerQuery, GpuProfilerSettings, ProfilerCommandRecorder};
+
+/// Which work the GPU profiler wraps in scopes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GpuScopeVerbosity {
+    /// No GPU scopes.
+    Off,
+    /// One scope per render graph node.
+    Nodes,
+    /// Render graph nodes, and the instruction processing and resource
+    /// uploads before the graph.
+    #[default]
+    All,
+}
+
+/// Profiling settings that can be changed while the renderer runs.
+///
+/// Which CPU profiler receives scopes is still chosen at compile time by the
+/// features of the `profiling` crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProfilerSettings {
+    /// Emits CPU scopes with names built at runtime, such as one per render
+    /// graph node. Formatting these names has a cost even when no profiler
+    /// is attached.
+    pub cpu_scopes: bool,
+    /// Which work gets GPU scopes.
+    pub gpu_scopes: GpuScopeVerbosity,
+    /// Times GPU scopes with timestamp queries. Ignored when the device lacks
+    /// [`Features::TIMESTAMP_QUERY`].
+    pub gpu_timer_queries: bool,
+    /// Marks GPU scopes as debug groups, shown by graphics debuggers.
+    pub gpu_debug_groups: bool,
+}
+
+impl Default for ProfilerSettings {
+    fn default() -> Self {
+        Self { cpu_scopes: true, gpu_scopes: GpuScopeVerbosity::All, gpu_timer_queries: true, gpu_debug_groups: true }
+    }
+}
+
+impl ProfilerSettings {
+    pub(crate) fn gpu_profiler_settings(&self, features: Features) -> GpuProfilerSettings {
+        let scopes = self.gpu_scopes != GpuScopeVerbosity::Off;
+        GpuProfilerSettings {
+            enable_timer_queries: scopes
+                && self.gpu_timer_queries
+                && features.contains(Features::TIMESTAMP_QUERY)
+                && !cfg!(target_arch = "wasm32"),
+            enable_debug_groups: scopes && self.gpu_debug_groups,
+            max_num_pending_frames: 4,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ProfilerState {
+    settings: Mutex<ProfilerSettings>,
+}
+
+impl ProfilerState {
+    pub fn settings(&self) -> ProfilerSettings {
+        *self.settings.lock()
+    }
+
+    pub fn set_settings(&self, settings: ProfilerSettings) {
+        *self.settings.lock() = settings;
+    }
+
+    /// Begins a GPU scope if the verbosity asks for scopes of this level.
+    pub fn begin_gpu_scope<Recorder: ProfilerCommandRecorder>(
+        &self,
+        profiler: &Mutex<GpuProfiler>,
+        level: GpuScopeVerbosity,
+        label: &str,
+        recorder: &mut Recorder,
+        device: &Device,
+    ) -> Option<GpuProfilerQuery> {
+        self.begin_nested_gpu_scope(profiler, level, label, None, recorder, device)
+    }
+
+    /// Like [`Self::begin_gpu_scope`], with the scope nested in `parent`.
+    pub fn begin_nested_gpu_scope<Recorder: ProfilerCommandRecorder>(
+        &self,
+        profiler: &Mutex<GpuProfiler>,
+        level: GpuScopeVerbosity,
+        label: &str,
+        parent: Option<&GpuProfilerQuery>,
+        recorder: &mut Recorder,
+        device: &Device,
+    ) -> Option<GpuProfilerQuery> {
+        (self.settings().gpu_scopes >= level)
+            .then(|| profiler.try_lock().unwrap().begin_query(label, recorder, device).with_parent(parent))
+    }
+
+    pub fn end_gpu_scope<Recorder: ProfilerCommandRecorder>(
+        &self,
+        profiler: &Mutex<GpuProfiler>,
+        recorder: &mut Recorder,
+        query: Option<GpuProfilerQuery>,
+    ) {
+        if let Some(query) = query {
+            profiler.try_lock().unwrap().end_query(recorder, query);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use parking_lot::Mutex;
+use thiserror::Error;
+use wgpu::{
+    Buffer, BufferAsyncError, BufferDescriptor, BufferUsages, CommandEncoder, Device, Extent3d, ImageCopyBuffer,
+    ImageCopyTexture, ImageDataLayout, MapMode, COPY_BUFFER_ALIGNMENT, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::util::math::round_up;
+
+#[derive(Debug, Clone, Error)]
+pub enum ReadbackError {
+    #[error("Buffer readback range {range:?} must start and end on a multiple of {COPY_BUFFER_ALIGNMENT} bytes")]
+    UnalignedBufferRange { range: Range<u64> },
+    #[error(
+        "Texture readback extent {extent:?} must be aligned to the {block_width}x{block_height} blocks of the format"
+    )]
+    UnalignedTextureRegion { extent: Extent3d, block_width: u32, block_height: u32 },
+    #[error("Texture format can't be copied with the requested aspect")]
+    UncopyableFormat,
+    #[error("Failed to map the readback buffer")]
+    MapFailed(#[source] BufferAsyncError),
+}
+
+#[derive(Default)]
+struct ReadbackState {
+    result: Mutex<Option<Result<Vec<u8>, ReadbackError>>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ReadbackState {
+    fn complete(&self, result: Result<Vec<u8>, ReadbackError>) {
+        *self.result.lock() = Some(result);
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Data requested with [`Renderer::readback_buffer`](crate::Renderer::readback_buffer)
+/// or [`Renderer::readback_texture`](crate::Renderer::readback_texture).
+///
+/// Resolves a frame or two after the request, once the copy has finished on
+/// the GPU and the renderer has rendered another frame. Can be awaited, or
+/// polled once a frame with [`ReadbackFuture::try_take`].
+pub struct ReadbackFuture {
+    state: Arc<ReadbackState>,
+}
+
+impl ReadbackFuture {
+    /// Takes the data if it has arrived.
+    pub fn try_take(&self) -> Option<Result<Vec<u8>, ReadbackError>> {
+        self.state.result.lock().take()
+    }
+}
+
+impl Future for ReadbackFuture {
+    type Output = Result<Vec<u8>, ReadbackError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Register before checking so a completion in between still wakes us.
+        *self.state.waker.lock() = Some(cx.waker().clone());
+        match self.state.result.lock().take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// How to turn the rows of the readback buffer into tightly packed data.
+struct RowLayout {
+    padded_bytes_per_row: u32,
+    bytes_per_row: u32,
+}
+
+struct PendingReadback {
+    buffer: Buffer,
+    rows: Option<RowLayout>,
+    state: Arc<ReadbackState>,
+    map_requested: bool,
+    mapped: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+}
+
+/// Copies requested data into mappable buffers and hands it back to the
+/// requesters once mapped.
+#[derive(Default)]
+pub(crate) struct ReadbackManager {
+    pending: Mutex<Vec<PendingReadback>>,
+}
+
+impl ReadbackManager {
+    pub fn request_buffer(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        buffer: &Buffer,
+        range: Range<u64>,
+    ) -> Result<ReadbackFuture, ReadbackError> {
+        profiling::scope!("ReadbackManager::request_buffer");
+
+        if range.start % COPY_BUFFER_ALIGNMENT != 0 || range.end % COPY_BUFFER_ALIGNMENT != 0 {
+            return Err(ReadbackError::UnalignedBufferRange { range });
+        }
+
+        let size = range.end - range.start;
+        let readback = create_readback_buffer(device, size);
+        encoder.copy_buffer_to_buffer(buffer, range.start, &readback, 0, size);
+
+        Ok(self.push(readback, None))
+    }
+
+    pub fn request_texture(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: ImageCopyTexture<'_>,
+        extent: Extent3d,
+    ) -> Result<ReadbackFuture, ReadbackError> {
+        profiling::scope!("ReadbackManager::request_texture");
+
+        let format = texture.texture.format();
+        let (block_width, block_height) = format.block_dimensions();
+        if extent.width % block_width != 0 || extent.height % block_height != 0 {
+            return Err(ReadbackError::UnalignedTextureRegion { extent, block_width, block_height });
+        }
+        let block_size = format.block_copy_size(Some(texture.aspect)).ok_or(ReadbackError::UncopyableFormat)?;
+
+        let bytes_per_row = block_size * (extent.width / block_width);
+        let padded_bytes_per_row = round_up(bytes_per_row, COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows_per_image = extent.height / block_height;
+        let size = padded_bytes_per_row as u64 * rows_per_image as u64 * extent.depth_or_array_layers as u64;
+
+        let readback = create_readback_buffer(device, size);
+        encoder.copy_texture_to_buffer(
+            texture,
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(rows_per_image),
+                },
+            },
+            extent,
+        );
+
+        Ok(self.push(readback, Some(RowLayout { padded_bytes_per_row, bytes_per_row })))
+    }
+
+    fn push(&self, buffer: Buffer, rows: Option<RowLayout>) -> ReadbackFuture {
+        let state = Arc::new(ReadbackState::default());
+        // Empty buffers can't be mapped.
+        if buffer.size() == 0 {
+            state.complete(Ok(Vec::new()));
+            return ReadbackFuture { state };
+        }
+        self.pending.lock().push(PendingReadback {
+            buffer,
+            rows,
+            state: Arc::clone(&state),
+            map_requested: false,
+            mapped: Arc::new(Mutex::new(None)),
+        });
+        ReadbackFuture { state }
+    }
+
+    /// Maps the buffers of newly submitted requests, and completes every
+    /// request whose buffer has finished mapping. Must be called after the
+    /// copies are submitted.
+    pub fn end_frame(&self) {
+        profiling::scope!("ReadbackManager::end_frame");
+
+        let mut pending = self.pending.lock();
+
+        for readback in pending.iter_mut().filter(|p| !p.map_requested) {
+            let mapped = Arc::clone(&readback.mapped);
+            readback.buffer.slice(..).map_async(MapMode::Read, move |res| {
+                *mapped.lock() = Some(res);
+            });
+            readback.map_requested = true;
+        }
+
+        pending.retain(|readback| {
+            let Some(res) = readback.mapped.lock().take() else {
+                return true;
+            };
+            let result = res.map_err(ReadbackError::MapFailed).map(|()| {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                let output = match readback.rows {
+                    Some(RowLayout { padded_bytes_per_row, bytes_per_row }) => data
+                        .chunks_exact(padded_bytes_per_row as usize)
+                        .flat_map(|row| &row[..bytes_per_row as usize])
+                        .copied()
+                        .collect(),
+                    None => data.to_vec(),
+                };
+                drop(data);
+                readback.buffer.unmap();
+                output
+            });
+            readback.state.complete(result);
+            false
+        });
+    }
+}
+
+fn create_readback_buffer(device: &Device, size: u64) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("readback buffer"),
+        size,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use rend3_types::{trait_supertrait_alias, WasmNotSend, WasmNotSync};
+use wgpu::DeviceLostReason;
+
+use crate::{
+    types::{
+        MaterialHandle, Mesh, MeshHandle, RawMaterialHandle, RawMeshHandle, RawTexture2DHandle, RawTextureCubeHandle,
+        Texture, Texture2DHandle, TextureCubeHandle,
+    },
+    util::typedefs::FastHashMap,
+    Renderer,
+};
+
+trait_supertrait_alias!(pub(crate) RestoreMaterialFn: Fn(&Arc<Renderer>, &RestoredResources) -> MaterialHandle + WasmNotSend + WasmNotSync);
+
+/// Why the device was lost.
+#[derive(Debug, Clone)]
+pub struct DeviceLostInfo {
+    pub reason: DeviceLostReason,
+    pub message: String,
+}
+
+/// Handles of the resources restored into a new renderer, keyed by the raw
+/// handles they had in the lost renderer.
+///
+/// Materials are restored after meshes and textures, so material remap
+/// functions can look up the new textures here.
+#[derive(Default)]
+pub struct RestoredResources {
+    pub meshes: FastHashMap<RawMeshHandle, MeshHandle>,
+    pub textures_2d: FastHashMap<RawTexture2DHandle, Texture2DHandle>,
+    pub textures_cube: FastHashMap<RawTextureCubeHandle, TextureCubeHandle>,
+    pub materials: FastHashMap<RawMaterialHandle, MaterialHandle>,
+}
+
+impl RestoredResources {
+    /// The restored mesh of a mesh in the lost renderer.
+    pub fn mesh(&self, old: &MeshHandle) -> Option<&MeshHandle> {
+        self.meshes.get(&old.get_raw())
+    }
+
+    /// The restored texture of a 2D texture in the lost renderer.
+    pub fn texture_2d(&self, old: &Texture2DHandle) -> Option<&Texture2DHandle> {
+        self.textures_2d.get(&old.get_raw())
+    }
+
+    /// The restored texture of a cube texture in the lost renderer.
+    pub fn texture_cube(&self, old: &TextureCubeHandle) -> Option<&TextureCubeHandle> {
+        self.textures_cube.get(&old.get_raw())
+    }
+
+    /// The restored material of a material in the lost renderer.
+    pub fn material(&self, old: &MaterialHandle) -> Option<&MaterialHandle> {
+        self.materials.get(&old.get_raw())
+    }
+}
+
+/// CPU copies of resources that were added with the `_retained` functions.
+#[derive(Default)]
+pub(crate) struct RetainedResources {
+    meshes: Mutex<FastHashMap<RawMeshHandle, Mesh>>,
+    textures_2d: Mutex<FastHashMap<RawTexture2DHandle, Texture>>,
+    textures_cube: Mutex<FastHashMap<RawTextureCubeHandle, Texture>>,
+    materials: Mutex<FastHashMap<RawMaterialHandle, Box<dyn RestoreMaterialFn>>>,
+}
+
+impl RetainedResources {
+    pub fn retain_mesh(&self, handle: RawMeshHandle, mesh: Mesh) {
+        self.meshes.lock().insert(handle, mesh);
+    }
+
+    pub fn retain_texture_2d(&self, handle: RawTexture2DHandle, texture: Texture) {
+        self.textures_2d.lock().insert(handle, texture);
+    }
+
+    pub fn retain_texture_cube(&self, handle: RawTextureCubeHandle, texture: Texture) {
+        self.textures_cube.lock().insert(handle, texture);
+    }
+
+    pub fn retain_material(&self, handle: RawMaterialHandle, restore: Box<dyn RestoreMaterialFn>) {
+        self.materials.lock().insert(handle, restore);
+    }
+
+    /// Replaces the copy of a mesh, if it's retained.
+    pub fn replace_mesh(&self, handle: RawMeshHandle, mesh: &Mesh) {
+        if let Some(retained) = self.meshes.lock().get_mut(&handle) {
+            *retained = mesh.clone();
+        }
+    }
+
+    /// Replaces the copy of a 2D texture, if it's retained.
+    pub fn replace_texture_2d(&self, handle: RawTexture2DHandle, texture: &Texture) {
+        if let Some(retained) = self.textures_2d.lock().get_mut(&handle) {
+            *retained = texture.clone();
+        }
+    }
+
+    pub fn swap_meshes(&self, a: RawMeshHandle, b: RawMeshHandle) {
+        swap_entries(&mut self.meshes.lock(), a, b);
+    }
+
+    pub fn swap_materials(&self, a: RawMaterialHandle, b: RawMaterialHandle) {
+        swap_entries(&mut self.materials.lock(), a, b);
+    }
+
+    pub fn remove_mesh(&self, handle: RawMeshHandle) {
+        self.meshes.lock().remove(&handle);
+    }
+
+    pub fn remove_texture_2d(&self, handle: RawTexture2DHandle) {
+        self.textures_2d.lock().remove(&handle);
+    }
+
+    pub fn remove_texture_cube(&self, handle: RawTextureCubeHandle) {
+        self.textures_cube.lock().remove(&handle);
+    }
+
+    pub fn remove_material(&self, handle: RawMaterialHandle) {
+        self.materials.lock().remove(&handle);
+    }
+
+    /// Re-adds every retained resource to `renderer`, retaining them there too.
+    pub fn restore_into(&self, renderer: &Arc<Renderer>) -> RestoredResources {
+        profiling::scope!("RetainedResources::restore_into");
+
+        let mut restored = RestoredResources::default();
+
+        for (&old, mesh) in self.meshes.lock().iter() {
+            match renderer.add_mesh_retained(mesh.clone()) {
+                Ok(handle) => drop(restored.meshes.insert(old, handle)),
+                Err(e) => log::error!("Failed to restore mesh {}: {e}", old.idx),
+            }
+        }
+        for (&old, texture) in self.textures_2d.lock().iter() {
+            match renderer.add_texture_2d_retained(texture.clone()) {
+                Ok(handle) => drop(restored.textures_2d.insert(old, handle)),
+                Err(e) => log::error!("Failed to restore 2D texture {}: {e}", old.idx),
+            }
+        }
+        for (&old, texture) in self.textures_cube.lock().iter() {
+            match renderer.add_texture_cube_retained(texture.clone()) {
+                Ok(handle) => drop(restored.textures_cube.insert(old, handle)),
+                Err(e) => log::error!("Failed to restore cube texture {}: {e}", old.idx),
+            }
+        }
+        for (&old, restore) in self.materials.lock().iter() {
+            let handle = restore(renderer, &restored);
+            restored.materials.insert(old, handle);
+        }
+
+        restored
+    }
+}
+
+fn swap_entries<K: Copy + Eq + std::hash::Hash, V>(map: &mut FastHashMap<K, V>, a: K, b: K) {
+    let value_a = map.remove(&a);
+    let value_b = map.remove(&b);
+    map.extend(value_b.map(|v| (a, v)));
+    map.extend(value_a.map(|v| (b, v)));
+}
+
+/// Records the loss of the device, ignoring the callbacks wgpu makes when the
+/// device is dropped normally.
+#[derive(Default)]
+pub(crate) struct DeviceLostState {
+    info: Mutex<Option<DeviceLostInfo>>,
+}
+
+impl DeviceLostState {
+    pub fn lost(&self, reason: DeviceLostReason, message: String) {
+        if matches!(reason, DeviceLostReason::Dropped | DeviceLostReason::ReplacedCallback) {
+            return;
+        }
+        log::error!("Device lost ({reason:?}): {message}");
+        *self.info.lock() = Some(DeviceLostInfo { reason, message });
+    }
+
+    pub fn info(&self) -> Option<DeviceLostInfo> {
+        self.info.lock().clone()
+    }
+}
@@ -1,20 +1,24 @@
-use std::sync::Arc;
+use std::sync::{atomic::AtomicBool, Arc};
 
+use glam::DVec3;
 use parking_lot::Mutex;
 use rend3_types::{Camera, Handedness, TextureFormat};
-use wgpu::TextureViewDimension;
-use wgpu_profiler::GpuProfilerSettings;
+use wgpu::{DownlevelFlags, TextureViewDimension};
 
 use crate::{
     graph::GraphTextureStore,
     instruction::InstructionStreamPair,
     managers::{
-        CameraState, DirectionalLightManager, GraphStorage, MaterialManager, MeshManager, ObjectManager,
+        CameraState, DirectionalLightManager, FrameClock, GraphStorage, MaterialManager, MeshManager, ObjectManager,
         PointLightManager, SkeletonManager, TextureManager,
     },
-    renderer::{HandleAllocators, RendererDataCore},
+    renderer::{
+        CapabilityState, DeviceLostState, Diagnostics, FrameStatsRecorder, GpuCapture, HandleAllocators,
+        PipelineStatisticsCollector, ProfilerSettings, ProfilerState, ReadbackManager, RendererCapabilities,
+        RendererDataCore, RetainedResources, UserDataStore,
+    },
     util::{mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
-    InstanceAdapterDevice, Renderer, RendererInitializationError,
+    InstanceAdapterDevice, Renderer, RendererInitializationError, RendererProfile,
 };
 
 pub fn create_renderer(
@@ -27,6 +31,8 @@ pub fn create_renderer(
     let features = iad.device.features();
     let limits = iad.device.limits();
     let downlevel = iad.adapter.get_downlevel_capabilities();
+    let capabilities =
+        CapabilityState::new(RendererCapabilities::new(&iad.adapter, iad.profile, features, downlevel.flags));
 
     let camera_state = CameraState::new(Camera::default(), handedness, aspect_ratio);
 
@@ -42,7 +48,18 @@ pub fn create_renderer(
         limits.max_sampled_textures_per_shader_stage,
         TextureViewDimension::Cube,
     );
-    let mesh_manager = MeshManager::new(&iad.device);
+    // Cube arrays are bound one at a time. Devices without them never get
+    // any, so the manager only needs a null view they can create.
+    let d2ca_texture_manager = TextureManager::new(
+        &iad.device,
+        RendererProfile::CpuDriven,
+        limits.max_sampled_textures_per_shader_stage,
+        match downlevel.flags.contains(DownlevelFlags::CUBE_ARRAY_TEXTURES) {
+            true => TextureViewDimension::CubeArray,
+            false => TextureViewDimension::Cube,
+        },
+    );
+    let mesh_manager = MeshManager::new(&iad.device, iad.profile);
     let material_manager = MaterialManager::new(&iad.device);
     let object_manager = ObjectManager::new();
     let directional_light_manager = DirectionalLightManager::new(&iad.device);
@@ -62,16 +79,14 @@ pub fn create_renderer(
     );
 
     let profiler = Mutex::new(
-        wgpu_profiler::GpuProfiler::new(GpuProfilerSettings {
-            enable_timer_queries: features.contains(wgpu::Features::TIMESTAMP_QUERY) && !cfg!(target_arch = "wasm32"),
-            enable_debug_groups: true,
-            max_num_pending_frames: 4,
-        })
-        .map_err(RendererInitializationError::GpuProfilerCreation)?,
+        wgpu_profiler::GpuProfiler::new(ProfilerSettings::default().gpu_profiler_settings(features))
+            .map_err(RendererInitializationError::GpuProfilerCreation)?,
     );
 
     let scatter = ScatterCopy::new(&iad.device);
 
+    let device_lost = Arc::new(DeviceLostState::default());
+
     Ok(Arc::new(Renderer {
         instructions: InstructionStreamPair::new(),
 
@@ -83,14 +98,18 @@ pub fn create_renderer(
         features,
         limits,
         downlevel,
+        capabilities,
         handedness,
 
         resource_handle_allocators: HandleAllocators::default(),
         mesh_manager,
         data_core: Mutex::new(RendererDataCore {
             viewport_camera_state: camera_state,
+            clock: FrameClock::new(),
+            frames_since_change: 0,
             d2_texture_manager,
             d2c_texture_manager,
+            d2ca_texture_manager,
             material_manager,
             object_manager,
             directional_light_manager,
@@ -103,5 +122,17 @@ pub fn create_renderer(
 
         mipmap_generator,
         scatter,
+
+        frame_stats: FrameStatsRecorder::default(),
+        gpu_capture: GpuCapture::default(),
+        pipeline_stats: PipelineStatisticsCollector::new(features),
+        diagnostics: Diagnostics::default(),
+        profiler_state: ProfilerState::default(),
+        determinism: AtomicBool::new(false),
+        readback: ReadbackManager::default(),
+        device_lost,
+        retained: RetainedResources::default(),
+        user_data: UserDataStore::default(),
+        world_origin: Mutex::new(DVec3::ZERO),
     }))
 }
@@ -0,0 +1,250 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use wgpu_profiler::GpuTimerQueryResult;
+
+use crate::{renderer::ObjectVisibility, types::RawObjectHandle};
+
+/// Work done by a single pass, usually a single graph node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PassStats {
+    /// Label of the pass.
+    pub label: String,
+    /// Name of the view the pass rendered, such as `"viewport"` or `"shadow 0"`.
+    /// Empty for passes that don't render a view.
+    pub view: String,
+    /// Number of draw calls issued.
+    pub draw_calls: u32,
+    /// Number of objects that were considered for drawing.
+    pub objects_submitted: u32,
+    /// Number of submitted objects that were culled instead of drawn.
+    pub objects_culled: u32,
+    /// Number of triangles drawn, summed over all instances.
+    pub triangles: u64,
+    /// Number of instances drawn.
+    pub instances: u32,
+    /// Number of compute dispatches issued.
+    pub compute_dispatches: u32,
+}
+
+/// Statistics about the work done in a frame.
+///
+/// Get the statistics of the last rendered frame with
+/// [`Renderer::last_frame_stats`](crate::Renderer::last_frame_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Per-pass statistics, in the order the passes were recorded.
+    pub passes: Vec<PassStats>,
+    /// Bytes uploaded into buffers since the previous frame, including mesh data.
+    pub buffer_upload_bytes: u64,
+    /// Bytes uploaded into textures since the previous frame.
+    pub texture_upload_bytes: u64,
+    /// Instructions evaluated at the start of the frame, grouped by kind and
+    /// the file that issued them, most frequent first.
+    pub instructions: Vec<InstructionStats>,
+}
+
+impl FrameStats {
+    /// Objects submitted to and culled by every view, summed over the passes
+    /// rendering the view, in the order the views were first rendered.
+    pub fn views(&self) -> Vec<ViewStats> {
+        let mut views: Vec<ViewStats> = Vec::new();
+        for pass in self.passes.iter().filter(|p| !p.view.is_empty()) {
+            let index = match views.iter().position(|v| v.view == pass.view) {
+                Some(index) => index,
+                None => {
+                    views.push(ViewStats { view: pass.view.clone(), ..ViewStats::default() });
+                    views.len() - 1
+                }
+            };
+            views[index].objects_submitted += pass.objects_submitted;
+            views[index].objects_culled += pass.objects_culled;
+        }
+        views
+    }
+
+    /// Sum of all draw calls.
+    pub fn draw_calls(&self) -> u32 {
+        self.passes.iter().map(|p| p.draw_calls).sum()
+    }
+
+    /// Sum of all submitted objects.
+    pub fn objects_submitted(&self) -> u32 {
+        self.passes.iter().map(|p| p.objects_submitted).sum()
+    }
+
+    /// Sum of all culled objects.
+    pub fn objects_culled(&self) -> u32 {
+        self.passes.iter().map(|p| p.objects_culled).sum()
+    }
+
+    /// Sum of all triangles drawn.
+    pub fn triangles(&self) -> u64 {
+        self.passes.iter().map(|p| p.triangles).sum()
+    }
+
+    /// Sum of all instances drawn.
+    pub fn instances(&self) -> u32 {
+        self.passes.iter().map(|p| p.instances).sum()
+    }
+
+    /// Sum of all compute dispatches.
+    pub fn compute_dispatches(&self) -> u32 {
+        self.passes.iter().map(|p| p.compute_dispatches).sum()
+    }
+
+    /// Sum of all evaluated instructions.
+    pub fn instruction_count(&self) -> u32 {
+        self.instructions.iter().map(|i| i.count).sum()
+    }
+
+    /// Sum of the evaluated instructions with the given operation.
+    pub fn instruction_count_of(&self, operation: InstructionOperation) -> u32 {
+        self.instructions.iter().filter(|i| i.operation == operation).map(|i| i.count).sum()
+    }
+}
+
+/// What an instruction does to the resource it refers to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InstructionOperation {
+    /// Adds a resource, including duplicated objects.
+    Add,
+    /// Changes an existing resource, or state like the camera.
+    Update,
+    /// Deletes a resource, usually because its last handle was dropped.
+    Delete,
+}
+
+/// Instructions of one kind issued from one file, see
+/// [`FrameStats::instructions`].
+///
+/// Instructions record the location of the renderer call that issued them, so
+/// the file tells which part of the application caused them. Instructions
+/// issued inside rend3 itself, like deletions of dropped handles, have the
+/// file of the rend3 code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionStats {
+    /// Kind of instruction, such as `"SetObjectTransform"`.
+    pub kind: &'static str,
+    pub operation: InstructionOperation,
+    /// Source file of the call that issued the instructions.
+    pub file: String,
+    /// Number of instructions.
+    pub count: u32,
+}
+
+/// Culling done for a single view, see [`FrameStats::views`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ViewStats {
+    /// Name of the view, see [`PassStats::view`].
+    pub view: String,
+    /// Number of objects considered for drawing in the view.
+    pub objects_submitted: u32,
+    /// Number of submitted objects that were outside of the view.
+    pub objects_culled: u32,
+}
+
+/// GPU time spent in a single pass, measured with timestamp queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuPassTime {
+    /// Label of the pass. For render graph nodes this is the node label, and
+    /// for groups of nodes the group label, see
+    /// [`RenderGraph::set_group`](crate::graph::RenderGraph::set_group).
+    pub label: String,
+    /// Number of enclosing passes, zero for top level passes.
+    pub depth: u32,
+    /// Time between the start and end of the pass on the GPU.
+    pub duration: Duration,
+}
+
+impl GpuPassTime {
+    /// Flattens the nested query results, parents before their children.
+    pub(crate) fn flatten(results: &[GpuTimerQueryResult]) -> Vec<Self> {
+        fn inner(results: &[GpuTimerQueryResult], depth: u32, output: &mut Vec<GpuPassTime>) {
+            for result in results {
+                output.push(GpuPassTime {
+                    label: result.label.clone(),
+                    depth,
+                    duration: Duration::from_secs_f64((result.time.end - result.time.start).max(0.0)),
+                });
+                inner(&result.nested_queries, depth + 1, output);
+            }
+        }
+
+        let mut output = Vec::new();
+        inner(results, 0, &mut output);
+        output
+    }
+}
+
+/// Accumulates the stats of the frame in flight.
+#[derive(Default)]
+pub(crate) struct FrameStatsRecorder {
+    passes: Mutex<Vec<PassStats>>,
+    instructions: Mutex<Vec<InstructionStats>>,
+    buffer_upload_bytes: AtomicU64,
+    texture_upload_bytes: AtomicU64,
+    last: Mutex<FrameStats>,
+    gpu_times: Mutex<Vec<GpuPassTime>>,
+    visible_objects: Mutex<ObjectVisibility>,
+    last_visible_objects: Mutex<Arc<ObjectVisibility>>,
+}
+
+impl FrameStatsRecorder {
+    pub fn record_pass(&self, stats: PassStats) {
+        self.passes.lock().push(stats);
+    }
+
+    pub fn record_instructions(&self, instructions: Vec<InstructionStats>) {
+        *self.instructions.lock() = instructions;
+    }
+
+    pub fn record_visible_objects(&self, handles: impl IntoIterator<Item = RawObjectHandle>) {
+        let mut visible = self.visible_objects.lock();
+        for handle in handles {
+            visible.insert(handle);
+        }
+    }
+
+    pub fn record_buffer_upload(&self, bytes: u64) {
+        self.buffer_upload_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_texture_upload(&self, bytes: u64) {
+        self.texture_upload_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Moves the frame in flight to be the last frame.
+    pub fn finish_frame(&self) {
+        let stats = FrameStats {
+            passes: std::mem::take(&mut *self.passes.lock()),
+            buffer_upload_bytes: self.buffer_upload_bytes.swap(0, Ordering::Relaxed),
+            texture_upload_bytes: self.texture_upload_bytes.swap(0, Ordering::Relaxed),
+            instructions: std::mem::take(&mut *self.instructions.lock()),
+        };
+        *self.last.lock() = stats;
+        *self.last_visible_objects.lock() = Arc::new(std::mem::take(&mut *self.visible_objects.lock()));
+    }
+
+    pub fn last(&self) -> FrameStats {
+        self.last.lock().clone()
+    }
+
+    pub fn last_visible_objects(&self) -> Arc<ObjectVisibility> {
+        self.last_visible_objects.lock().clone()
+    }
+
+    pub fn set_gpu_times(&self, results: &[GpuTimerQueryResult]) {
+        *self.gpu_times.lock() = GpuPassTime::flatten(results);
+    }
+
+    pub fn gpu_times(&self) -> Vec<GpuPassTime> {
+        self.gpu_times.lock().clone()
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    any::{Any, TypeId},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{types::RawResourceHandle, util::typedefs::FastHashMap};
+
+/// Application data attached to resources, see
+/// [`Renderer::set_user_data`](crate::Renderer::set_user_data).
+///
+/// Keyed by the kind of resource and the index of its handle. Entries are
+/// removed when the resource is deleted, before its index can be reused.
+#[derive(Default)]
+pub(crate) struct UserDataStore {
+    data: Mutex<FastHashMap<(TypeId, usize), Arc<dyn Any + Send + Sync>>>,
+}
+
+impl UserDataStore {
+    pub fn insert<T: 'static>(&self, handle: RawResourceHandle<T>, data: Arc<dyn Any + Send + Sync>) {
+        self.data.lock().insert((TypeId::of::<T>(), handle.idx), data);
+    }
+
+    pub fn get<T: 'static>(&self, handle: RawResourceHandle<T>) -> Option<Arc<dyn Any + Send + Sync>> {
+        self.data.lock().get(&(TypeId::of::<T>(), handle.idx)).cloned()
+    }
+
+    pub fn remove<T: 'static>(&self, handle: RawResourceHandle<T>) {
+        self.data.lock().remove(&(TypeId::of::<T>(), handle.idx));
+    }
+
+    /// Removes the data of many handles while taking the lock once.
+    pub fn remove_many<T: 'static>(&self, handles: &[RawResourceHandle<T>]) {
+        let mut data = self.data.lock();
+        if data.is_empty() {
+            return;
+        }
+        for handle in handles {
+            data.remove(&(TypeId::of::<T>(), handle.idx));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::types::RawObjectHandle;
+
+/// Objects the viewport camera drew in a frame, after culling.
+///
+/// Get the objects visible in the last rendered frame with
+/// [`Renderer::last_frame_visible_objects`](crate::Renderer::last_frame_visible_objects),
+/// to reuse the renderer's culling for gameplay, audio or AI instead of
+/// repeating the frustum tests.
+///
+/// Handles are raw, so a handle may refer to an object added after the frame
+/// in the slot of an object deleted since.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectVisibility {
+    /// One bit per object slot.
+    bits: Vec<u64>,
+    count: usize,
+}
+
+impl ObjectVisibility {
+    pub(crate) fn insert(&mut self, handle: RawObjectHandle) {
+        let (word, bit) = (handle.idx / 64, handle.idx % 64);
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        if self.bits[word] & (1 << bit) == 0 {
+            self.bits[word] |= 1 << bit;
+            self.count += 1;
+        }
+    }
+
+    /// Whether the object was visible.
+    pub fn contains(&self, handle: RawObjectHandle) -> bool {
+        let (word, bit) = (handle.idx / 64, handle.idx % 64);
+        self.bits.get(word).is_some_and(|bits| bits & (1 << bit) != 0)
+    }
+
+    /// Number of visible objects.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The visible objects, in handle order.
+    pub fn iter(&self) -> impl Iterator<Item = RawObjectHandle> + '_ {
+        self.bits.iter().enumerate().flat_map(|(word, &bits)| {
+            (0..64).filter(move |bit| bits & (1 << bit) != 0).map(move |bit| RawObjectHandle::new(word * 64 + bit))
+        })
+    }
+}
@@ -0,0 +1,265 @@
+//! Saving and restoring the logical state of the scene.
+//!
+//! A [`SceneSnapshot`] holds the camera, objects and lights of a renderer.
+//! Meshes, skeletons and materials aren't stored in it; objects refer to them
+//! by ids the application gives them in a [`SceneAssets`]. The snapshot can be
+//! serialized with any serde format, and restored into a renderer that has
+//! assets with the same ids, for save games, editor scenes and reproducing
+//! bugs.
+
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+
+use glam::{Mat4, Vec4};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    types::{
+        Camera, DirectionalLight, DirectionalLightHandle, MaterialHandle, MeshHandle, Object, ObjectHandle,
+        ObjectMeshKind, PointLight, PointLightHandle, RawMaterialHandle, RawMeshHandle, RawObjectHandle,
+        RawSkeletonHandle, SkeletonHandle,
+    },
+    util::typedefs::FastHashMap,
+    Renderer,
+};
+
+/// Mesh of an object in a [`SceneSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObjectMeshSnapshot<Id> {
+    /// Id of the mesh.
+    Static(Id),
+    /// Id of the skeleton.
+    Animated(Id),
+}
+
+/// An object in a [`SceneSnapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectSnapshot<Id> {
+    /// Index of the object's handle in the renderer the snapshot was taken
+    /// from, to match it up with application data.
+    pub index: usize,
+    pub mesh: ObjectMeshSnapshot<Id>,
+    /// Id of the material.
+    pub material: Id,
+    pub transform: Mat4,
+    pub custom_data: Vec4,
+    pub opacity: f32,
+    pub is_static: bool,
+}
+
+/// The camera, objects and lights of a renderer.
+///
+/// See the [module](self) for documentation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneSnapshot<Id> {
+    pub camera: Camera,
+    /// Objects, ordered by the index of their handle.
+    pub objects: Vec<ObjectSnapshot<Id>>,
+    pub directional_lights: Vec<DirectionalLight>,
+    pub point_lights: Vec<PointLight>,
+}
+
+/// Handles created by [`Renderer::restore_scene`], in the order of the
+/// snapshot they were restored from.
+#[derive(Debug)]
+pub struct RestoredScene {
+    pub objects: Vec<ObjectHandle>,
+    pub directional_lights: Vec<DirectionalLightHandle>,
+    pub point_lights: Vec<PointLightHandle>,
+}
+
+/// Error taking a [`SceneSnapshot`].
+#[derive(Debug, Error)]
+pub enum SceneSnapshotError {
+    #[error("Object {object:?} uses mesh {mesh:?}, which has no id")]
+    UnknownMesh { object: RawObjectHandle, mesh: RawMeshHandle },
+    #[error("Object {object:?} uses skeleton {skeleton:?}, which has no id")]
+    UnknownSkeleton { object: RawObjectHandle, skeleton: RawSkeletonHandle },
+    #[error("Object {object:?} uses material {material:?}, which has no id")]
+    UnknownMaterial { object: RawObjectHandle, material: RawMaterialHandle },
+}
+
+/// Error restoring a [`SceneSnapshot`].
+#[derive(Debug, Error)]
+pub enum SceneRestoreError<Id: Debug> {
+    #[error("There is no mesh with id {0:?}")]
+    MissingMesh(Id),
+    #[error("There is no skeleton with id {0:?}")]
+    MissingSkeleton(Id),
+    #[error("There is no material with id {0:?}")]
+    MissingMaterial(Id),
+}
+
+/// Meshes, skeletons and materials by the ids scene snapshots refer to them
+/// by.
+pub struct SceneAssets<Id> {
+    meshes: FastHashMap<Id, MeshHandle>,
+    mesh_ids: FastHashMap<RawMeshHandle, Id>,
+    skeletons: FastHashMap<Id, SkeletonHandle>,
+    skeleton_ids: FastHashMap<RawSkeletonHandle, Id>,
+    materials: FastHashMap<Id, MaterialHandle>,
+    material_ids: FastHashMap<RawMaterialHandle, Id>,
+}
+
+impl<Id: Clone + Eq + Hash> Default for SceneAssets<Id> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Id: Clone + Eq + Hash> SceneAssets<Id> {
+    pub fn new() -> Self {
+        Self {
+            meshes: FastHashMap::default(),
+            mesh_ids: FastHashMap::default(),
+            skeletons: FastHashMap::default(),
+            skeleton_ids: FastHashMap::default(),
+            materials: FastHashMap::default(),
+            material_ids: FastHashMap::default(),
+        }
+    }
+
+    pub fn insert_mesh(&mut self, id: Id, mesh: &MeshHandle) {
+        self.mesh_ids.insert(mesh.get_raw(), id.clone());
+        self.meshes.insert(id, mesh.clone());
+    }
+
+    pub fn insert_skeleton(&mut self, id: Id, skeleton: &SkeletonHandle) {
+        self.skeleton_ids.insert(skeleton.get_raw(), id.clone());
+        self.skeletons.insert(id, skeleton.clone());
+    }
+
+    pub fn insert_material(&mut self, id: Id, material: &MaterialHandle) {
+        self.material_ids.insert(material.get_raw(), id.clone());
+        self.materials.insert(id, material.clone());
+    }
+
+    pub fn mesh(&self, id: &Id) -> Option<&MeshHandle> {
+        self.meshes.get(id)
+    }
+
+    pub fn skeleton(&self, id: &Id) -> Option<&SkeletonHandle> {
+        self.skeletons.get(id)
+    }
+
+    pub fn material(&self, id: &Id) -> Option<&MaterialHandle> {
+        self.materials.get(id)
+    }
+}
+
+impl Renderer {
+    /// Takes a snapshot of the scene, with assets referred to by their id in
+    /// `assets`.
+    ///
+    /// Holds the scene as of the last call to
+    /// [`Self::evaluate_instructions`]; changes made since are missing.
+    pub fn snapshot_scene<Id: Clone + Eq + Hash>(
+        &self,
+        assets: &SceneAssets<Id>,
+    ) -> Result<SceneSnapshot<Id>, SceneSnapshotError> {
+        profiling::scope!("Renderer::snapshot_scene");
+
+        let data_core = self.data_core.lock();
+
+        let objects = data_core
+            .object_manager
+            .objects()
+            .into_iter()
+            .map(|(handle, object, custom_data, opacity)| {
+                let mesh = match object.mesh_kind {
+                    ObjectMeshKind::Static(ref mesh) => assets
+                        .mesh_ids
+                        .get(&mesh.get_raw())
+                        .map(|id| ObjectMeshSnapshot::Static(id.clone()))
+                        .ok_or(SceneSnapshotError::UnknownMesh { object: handle, mesh: mesh.get_raw() })?,
+                    ObjectMeshKind::Animated(ref skeleton) => assets
+                        .skeleton_ids
+                        .get(&skeleton.get_raw())
+                        .map(|id| ObjectMeshSnapshot::Animated(id.clone()))
+                        .ok_or(SceneSnapshotError::UnknownSkeleton { object: handle, skeleton: skeleton.get_raw() })?,
+                };
+                let material = assets.material_ids.get(&object.material.get_raw()).cloned().ok_or(
+                    SceneSnapshotError::UnknownMaterial { object: handle, material: object.material.get_raw() },
+                )?;
+
+                Ok(ObjectSnapshot {
+                    index: handle.idx,
+                    mesh,
+                    material,
+                    transform: object.transform,
+                    custom_data,
+                    opacity,
+                    is_static: data_core.object_manager.is_static(handle),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SceneSnapshot {
+            camera: data_core.viewport_camera_state.get_data(),
+            objects,
+            directional_lights: data_core.directional_light_manager.lights().cloned().collect(),
+            point_lights: data_core.point_light_manager.lights().cloned().collect(),
+        })
+    }
+
+    /// Adds the objects and lights of a snapshot to the scene and sets the
+    /// camera, looking up assets by their id in `assets`.
+    ///
+    /// Nothing is added if any asset is missing.
+    pub fn restore_scene<Id: Clone + Eq + Hash + Debug>(
+        self: &Arc<Self>,
+        snapshot: &SceneSnapshot<Id>,
+        assets: &SceneAssets<Id>,
+    ) -> Result<RestoredScene, SceneRestoreError<Id>> {
+        profiling::scope!("Renderer::restore_scene");
+
+        let objects = snapshot
+            .objects
+            .iter()
+            .map(|object| {
+                let mesh_kind = match object.mesh {
+                    ObjectMeshSnapshot::Static(ref id) => ObjectMeshKind::Static(
+                        assets.mesh(id).cloned().ok_or_else(|| SceneRestoreError::MissingMesh(id.clone()))?,
+                    ),
+                    ObjectMeshSnapshot::Animated(ref id) => ObjectMeshKind::Animated(
+                        assets.skeleton(id).cloned().ok_or_else(|| SceneRestoreError::MissingSkeleton(id.clone()))?,
+                    ),
+                };
+                let material = assets
+                    .material(&object.material)
+                    .cloned()
+                    .ok_or_else(|| SceneRestoreError::MissingMaterial(object.material.clone()))?;
+                Ok((Object { mesh_kind, material, transform: object.transform }, object))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let objects = objects
+            .into_iter()
+            .map(|(object, snapshot)| {
+                let handle = self.add_object(object);
+                if snapshot.custom_data != Vec4::ZERO {
+                    self.set_object_custom_data(&handle, snapshot.custom_data);
+                }
+                if snapshot.opacity != 1.0 {
+                    self.set_object_opacity(&handle, snapshot.opacity);
+                }
+                if snapshot.is_static {
+                    self.set_object_static(&handle, true);
+                }
+                handle
+            })
+            .collect();
+
+        self.set_camera_data(snapshot.camera);
+
+        Ok(RestoredScene {
+            objects,
+            directional_lights: snapshot
+                .directional_lights
+                .iter()
+                .map(|l| self.add_directional_light(l.clone()))
+                .collect(),
+            point_lights: snapshot.point_lights.iter().map(|l| self.add_point_light(l.clone())).collect(),
+        })
+    }
+}
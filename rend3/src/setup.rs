@@ -3,16 +3,13 @@
 use std::sync::Arc;
 
 use wgpu::{
-    Adapter, AdapterInfo, Backend, Backends, BufferAddress, Device, DeviceDescriptor, DeviceType, Features,
-    Gles3MinorVersion, Instance, InstanceFlags, Limits, Queue,
+    Adapter, AdapterInfo, Backend, Backends, BufferAddress, Device, DeviceDescriptor, DeviceType, DownlevelFlags,
+    Features, Gles3MinorVersion, Instance, InstanceFlags, Limits, PowerPreference, Queue,
 };
 
 #[allow(unused_imports)]
 use crate::format_sso;
-use crate::{
-    managers::STARTING_2D_TEXTURES, util::typedefs::FastHashMap, LimitType, RendererInitializationError,
-    RendererProfile,
-};
+use crate::{managers::STARTING_2D_TEXTURES, LimitType, RendererInitializationError, RendererProfile};
 
 /// Largest uniform buffer binding needed to run rend3.
 pub const MAX_UNIFORM_BUFFER_BINDING_SIZE: BufferAddress = 1024;
@@ -43,9 +40,30 @@ pub const OPTIONAL_FEATURES: Features = Features::from_bits_truncate(
         | Features::TEXTURE_COMPRESSION_ETC2.bits()
         | Features::TEXTURE_COMPRESSION_ASTC.bits()
         | Features::TIMESTAMP_QUERY.bits()
-        | Features::TIMESTAMP_QUERY_INSIDE_PASSES.bits(),
+        | Features::TIMESTAMP_QUERY_INSIDE_PASSES.bits()
+        | Features::PIPELINE_STATISTICS_QUERY.bits()
+        | Features::INDIRECT_FIRST_INSTANCE.bits()
+        | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES.bits(),
 );
 
+/// Downlevel capabilities required to run in either profile.
+///
+/// Uploads and skinning run in compute shaders and vertices are fetched from
+/// storage buffers, so WebGL2 and other GLES 3.0 devices, which have neither,
+/// can't run rend3.
+pub const REQUIRED_DOWNLEVEL_FLAGS: DownlevelFlags =
+    DownlevelFlags::from_bits_truncate(DownlevelFlags::COMPUTE_SHADERS.bits() | DownlevelFlags::VERTEX_STORAGE.bits());
+
+/// Check that the device has [`REQUIRED_DOWNLEVEL_FLAGS`].
+pub fn check_downlevel_flags(device: DownlevelFlags) -> Result<(), RendererInitializationError> {
+    let missing = REQUIRED_DOWNLEVEL_FLAGS - device;
+    if !missing.is_empty() {
+        Err(RendererInitializationError::MissingDownlevelFlags { flags: missing })
+    } else {
+        Ok(())
+    }
+}
+
 /// Check that all required features for a given profile are present in the feature
 /// set given.
 pub fn check_features(profile: RendererProfile, device: Features) -> Result<Features, RendererInitializationError> {
@@ -228,7 +246,7 @@ pub fn check_limits(profile: RendererProfile, device_limits: &Limits) -> Result<
         max_uniform_buffers_per_shader_stage: check_limit_unlimited(
             device_limits.max_uniform_buffers_per_shader_stage,
             required_limits.max_uniform_buffers_per_shader_stage,
-            LimitType::StorageTexturesPerShaderStages,
+            LimitType::UniformBuffersPerShaderStages,
         )?,
         max_uniform_buffer_binding_size: check_limit_unlimited(
             device_limits.max_uniform_buffer_binding_size,
@@ -328,10 +346,13 @@ impl<T> PotentialAdapter<T> {
         inner_info: AdapterInfo,
         inner_limits: Limits,
         inner_features: Features,
+        inner_downlevel: DownlevelFlags,
         desired_profile: Option<RendererProfile>,
     ) -> Result<Self, RendererInitializationError> {
         let info = ExtendedAdapterInfo::from(inner_info);
 
+        check_downlevel_flags(inner_downlevel)?;
+
         let mut features = check_features(RendererProfile::GpuDriven, inner_features);
         let mut limits = check_limits(RendererProfile::GpuDriven, &inner_limits);
         let mut profile = RendererProfile::GpuDriven;
@@ -411,129 +432,377 @@ pub struct InstanceAdapterDevice {
     pub info: ExtendedAdapterInfo,
 }
 
+/// How [`IadBuilder`] picks between multiple usable adapters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelection {
+    /// Prefer adapters of a given power class. `HighPerformance` prefers
+    /// discrete gpus, `LowPower` prefers integrated gpus, `None` keeps enumeration order.
+    PowerPreference(PowerPreference),
+    /// Use the first adapter whose name contains the given string, ignoring case.
+    Name(String),
+    /// Use the adapter at the given index into [`IadReport::adapters`].
+    Index(usize),
+}
+
+impl Default for AdapterSelection {
+    fn default() -> Self {
+        Self::PowerPreference(PowerPreference::HighPerformance)
+    }
+}
+
+/// What [`IadBuilder`] found and what it actually got.
+#[derive(Debug, Clone)]
+pub struct IadReport {
+    /// Every adapter that was enumerated, usable or not, in backend preference order.
+    pub adapters: Vec<ExtendedAdapterInfo>,
+    /// Why each adapter that can't run rend3 was rejected, by index into
+    /// [`Self::adapters`].
+    pub rejected: Vec<(usize, Arc<RendererInitializationError>)>,
+    /// Information about the adapter that was chosen.
+    pub chosen: ExtendedAdapterInfo,
+    /// Profile the renderer will run in.
+    pub profile: RendererProfile,
+    /// Features enabled on the device.
+    pub features: Features,
+    /// Optional features that were asked for but the adapter didn't have.
+    pub missing_optional_features: Features,
+    /// Limits the device was created with.
+    pub limits: Limits,
+}
+
+/// Builder for an [`InstanceAdapterDevice`] with explicit control over how
+/// the adapter and device are chosen.
+///
+/// [`create_iad`] is a thin wrapper around this with default settings.
+#[derive(Debug, Clone)]
+pub struct IadBuilder {
+    backends: Backends,
+    backend_order: Vec<Backend>,
+    selection: AdapterSelection,
+    profile: Option<RendererProfile>,
+    required_features: Features,
+    optional_features: Features,
+    forced_features: Features,
+    required_limits: Option<Limits>,
+    instance_flags: InstanceFlags,
+}
+
+impl Default for IadBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IadBuilder {
+    pub fn new() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = Backends::VULKAN |
+             // Backends::DX12 | https://github.com/gfx-rs/wgpu/issues/4423
+             Backends::METAL;
+        #[cfg(target_arch = "wasm32")]
+        let backends = Backends::BROWSER_WEBGPU;
+        #[cfg(not(target_arch = "wasm32"))]
+        let backend_order = vec![Backend::Vulkan, Backend::Metal, Backend::Dx12, Backend::Gl];
+        #[cfg(target_arch = "wasm32")]
+        let backend_order = vec![Backend::BrowserWebGpu];
+
+        Self {
+            backends,
+            backend_order,
+            selection: AdapterSelection::default(),
+            profile: None,
+            required_features: Features::empty(),
+            optional_features: Features::empty(),
+            forced_features: Features::empty(),
+            required_limits: None,
+            instance_flags: InstanceFlags::default(),
+        }
+    }
+
+    /// Set of backends the instance is created with.
+    pub fn backends(mut self, backends: Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    /// Only consider adapters from the given backend.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backends |= Backends::from(backend);
+        self.backend_order.retain(|&b| b == backend);
+        if self.backend_order.is_empty() {
+            self.backend_order.push(backend);
+        }
+        self
+    }
+
+    /// How to choose between the usable adapters.
+    pub fn adapter(mut self, selection: AdapterSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Force a specific renderer profile. If unset, GpuDriven is preferred.
+    pub fn profile(mut self, profile: RendererProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Features the device must have. Adapters without them are rejected.
+    pub fn required_features(mut self, features: Features) -> Self {
+        self.required_features |= features;
+        self
+    }
+
+    /// Features that are enabled if the adapter supports them.
+    pub fn optional_features(mut self, features: Features) -> Self {
+        self.optional_features |= features;
+        self
+    }
+
+    /// Features requested from the device without checking if the adapter
+    /// advertises them. Some adapters will not advertise all of their supported
+    /// features. Device creation fails if they truly aren't supported.
+    pub fn forced_features(mut self, features: Features) -> Self {
+        self.forced_features |= features;
+        self
+    }
+
+    /// Limits the device must meet, on top of what rend3 itself requires.
+    pub fn required_limits(mut self, limits: Limits) -> Self {
+        self.required_limits = Some(limits);
+        self
+    }
+
+    /// Flags the instance is created with.
+    pub fn instance_flags(mut self, flags: InstanceFlags) -> Self {
+        self.instance_flags = flags;
+        self
+    }
+
+    /// Create the instance, adapter, and device.
+    pub async fn build(self) -> Result<InstanceAdapterDevice, RendererInitializationError> {
+        self.build_with_report().await.map(|(iad, _)| iad)
+    }
+
+    /// Create the instance, adapter, and device, returning a report of what was obtained.
+    pub async fn build_with_report(self) -> Result<(InstanceAdapterDevice, IadReport), RendererInitializationError> {
+        profiling::scope!("create_iad");
+
+        let instance = Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends,
+            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+            gles_minor_version: Gles3MinorVersion::default(),
+            flags: self.instance_flags,
+        });
+
+        let mut all_adapters = Vec::new();
+        let mut rejected = Vec::new();
+        let mut usable_adapters = Vec::<(usize, PotentialAdapter<Adapter>)>::new();
+
+        for (backend_index, backend) in self.backend_order.iter().enumerate() {
+            profiling::scope!("enumerating backend");
+            #[cfg(not(target_arch = "wasm32"))]
+            let adapters = instance.enumerate_adapters(Backends::from(*backend));
+            #[cfg(target_arch = "wasm32")]
+            let adapters = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter: false,
+                    compatible_surface: None,
+                })
+                .await
+                .into_iter();
+
+            for adapter in adapters {
+                let index = all_adapters.len();
+                let info = adapter.get_info();
+                all_adapters.push(ExtendedAdapterInfo::from(info.clone()));
+
+                let limits = adapter.limits();
+                let features = adapter.features();
+                let downlevel = adapter.get_downlevel_capabilities().flags;
+                let potential = PotentialAdapter::new(adapter, info, limits, features, downlevel, self.profile)
+                    .and_then(|p| self.check_requirements(p));
+
+                log::info!("{:?} Adapter {}: {:#?}", backend, index, potential.as_ref().map(|p| &p.info));
+
+                let desired = match self.selection {
+                    AdapterSelection::PowerPreference(_) => true,
+                    AdapterSelection::Name(ref name) => {
+                        all_adapters[index].name.to_lowercase().contains(&name.to_lowercase())
+                    }
+                    AdapterSelection::Index(desired_index) => desired_index == index,
+                };
+
+                match (potential, desired) {
+                    (Ok(potential), true) => {
+                        log::debug!("Adapter usable in the {:?} profile", potential.profile);
+                        usable_adapters.push((backend_index, potential));
+                    }
+                    (Err(e), true) => {
+                        log::debug!("Adapter not usable: {}", e);
+                        rejected.push((index, Arc::new(e)));
+                    }
+                    (Err(e), false) => {
+                        log::debug!("Adapter not selected");
+                        rejected.push((index, Arc::new(e)));
+                    }
+                    (Ok(_), false) => log::debug!("Adapter not selected"),
+                }
+            }
+        }
+
+        // Backends are always tried in order, the power preference only orders adapters within a backend.
+        let preference = match self.selection {
+            AdapterSelection::PowerPreference(preference) => preference,
+            _ => PowerPreference::None,
+        };
+        usable_adapters
+            .sort_by_key(|(backend_index, a)| (*backend_index, device_type_rank(preference, a.info.device_type)));
+
+        let Some((_, adapter)) = usable_adapters.into_iter().next() else {
+            return Err(RendererInitializationError::MissingAdapter);
+        };
+
+        let optional = self.optional_features & adapter.inner.features();
+        let features = adapter.features | self.required_features | optional | self.forced_features;
+
+        log::debug!("Chosen adapter: {:#?}", adapter.info);
+        log::debug!("Chosen features: {:#?}", features);
+        log::debug!("Chosen limits: {:#?}", adapter.limits);
+        log::debug!("Chosen profile: {:#?}", adapter.profile);
+
+        let (device, queue) = adapter
+            .inner
+            .request_device(
+                &DeviceDescriptor { label: None, required_features: features, required_limits: adapter.limits.clone() },
+                None,
+            )
+            .await
+            .map_err(|_| RendererInitializationError::RequestDeviceFailed)?;
+
+        let report = IadReport {
+            adapters: all_adapters,
+            rejected,
+            chosen: adapter.info.clone(),
+            profile: adapter.profile,
+            features,
+            missing_optional_features: self.optional_features - optional,
+            limits: adapter.limits,
+        };
+
+        let iad = InstanceAdapterDevice {
+            instance: Arc::new(instance),
+            adapter: Arc::new(adapter.inner),
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            profile: adapter.profile,
+            info: adapter.info,
+        };
+
+        Ok((iad, report))
+    }
+
+    /// Rejects adapters that don't meet the user's own requirements.
+    fn check_requirements(
+        &self,
+        potential: PotentialAdapter<Adapter>,
+    ) -> Result<PotentialAdapter<Adapter>, RendererInitializationError> {
+        let missing = self.required_features - potential.inner.features();
+        if !missing.is_empty() {
+            return Err(RendererInitializationError::MissingDeviceFeatures { features: missing });
+        }
+
+        if let Some(ref required_limits) = self.required_limits {
+            let mut error = None;
+            required_limits.check_limits_with_fail_fn(&potential.limits, true, |name, requested, allowed| {
+                error = Some(RendererInitializationError::LowRequestedLimit {
+                    name,
+                    device_limit: allowed,
+                    required_limit: requested,
+                })
+            });
+            if let Some(error) = error {
+                return Err(error);
+            }
+        }
+
+        Ok(potential)
+    }
+}
+
+fn device_type_rank(preference: PowerPreference, device_type: DeviceType) -> u8 {
+    match (preference, device_type) {
+        (PowerPreference::None, _) => 0,
+        (PowerPreference::HighPerformance, DeviceType::DiscreteGpu) => 0,
+        (PowerPreference::HighPerformance, DeviceType::IntegratedGpu) => 1,
+        (PowerPreference::LowPower, DeviceType::IntegratedGpu) => 0,
+        (PowerPreference::LowPower, DeviceType::DiscreteGpu) => 1,
+        (_, DeviceType::VirtualGpu) => 2,
+        (_, DeviceType::Cpu) => 3,
+        (_, DeviceType::Other) => 4,
+    }
+}
+
 /// Creates an Instance/Adapter/Device/Queue using the given choices. Tries to
 /// get the best combination.
 ///
 /// **NOTE:** Some adapters will not advertise all of its supported features.
 /// The `additional_features` parameter can be used to explicitly request
 /// additional features during device creation.
+///
+/// Use [`IadBuilder`] for more control.
 pub async fn create_iad(
     desired_backend: Option<Backend>,
     desired_device: Option<String>,
     desired_profile: Option<RendererProfile>,
     additional_features: Option<Features>,
 ) -> Result<InstanceAdapterDevice, RendererInitializationError> {
-    profiling::scope!("create_iad");
-    #[cfg(not(target_arch = "wasm32"))]
-    let backend_bits = Backends::VULKAN |
-         // Backends::DX12 | https://github.com/gfx-rs/wgpu/issues/4423
-         Backends::METAL;
-    #[cfg(target_arch = "wasm32")]
-    let backend_bits = Backends::BROWSER_WEBGPU;
-    #[cfg(not(target_arch = "wasm32"))]
-    let default_backend_order = [Backend::Vulkan, Backend::Metal, Backend::Dx12, Backend::Gl];
-    #[cfg(target_arch = "wasm32")]
-    let default_backend_order = [Backend::BrowserWebGpu];
-
-    let instance = Instance::new(wgpu::InstanceDescriptor {
-        backends: backend_bits,
-        dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-        gles_minor_version: Gles3MinorVersion::default(),
-        flags: InstanceFlags::default(),
-    });
-
-    let mut valid_adapters = FastHashMap::<Backend, Vec<PotentialAdapter<Adapter>>>::default();
-
-    for backend in &default_backend_order {
-        profiling::scope!("enumerating backend");
-        #[cfg(not(target_arch = "wasm32"))]
-        let adapters = instance.enumerate_adapters(Backends::from(*backend));
-        #[cfg(target_arch = "wasm32")]
-        let adapters = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .await
-            .into_iter();
-
-        let mut potential_adapters = Vec::new();
-        for (idx, adapter) in adapters.into_iter().enumerate() {
-            let info = adapter.get_info();
-            let limits = adapter.limits();
-            let features = adapter.features();
-            let potential = PotentialAdapter::new(adapter, info, limits, features, desired_profile);
-
-            log::info!("{:?} Adapter {}: {:#?}", backend, idx, potential.as_ref().map(|p| &p.info));
-
-            let desired = if let Some(ref desired_device) = desired_device {
-                potential.as_ref().map(|i| i.info.name.to_lowercase().contains(desired_device)).unwrap_or(false)
-            } else {
-                true
-            };
-
-            if let (Ok(potential), true) = (potential, desired) {
-                log::debug!("Adapter usable in the {:?} profile", potential.profile);
-                potential_adapters.push(potential)
-            } else {
-                log::debug!("Adapter not usable");
-            }
-        }
-        valid_adapters.insert(*backend, potential_adapters);
+    let mut builder = IadBuilder::new();
+    if let Some(backend) = desired_backend {
+        builder = builder.backend(backend);
     }
+    if let Some(name) = desired_device {
+        builder = builder.adapter(AdapterSelection::Name(name));
+    }
+    if let Some(profile) = desired_profile {
+        builder = builder.profile(profile);
+    }
+    if let Some(features) = additional_features {
+        builder = builder.forced_features(features);
+    }
+    builder.build().await
+}
 
-    for backend_adapters in valid_adapters.values_mut() {
-        backend_adapters.sort_by_key(|a: &PotentialAdapter<Adapter>| match a.info.device_type {
-            DeviceType::DiscreteGpu => 0,
-            DeviceType::IntegratedGpu => 1,
-            DeviceType::VirtualGpu => 2,
-            DeviceType::Cpu => 3,
-            DeviceType::Other => 4,
-        });
+#[cfg(test)]
+mod tests {
+    use wgpu::{AdapterInfo, Backend, DeviceType, DownlevelFlags, Features, Limits};
+
+    use super::{PotentialAdapter, REQUIRED_DOWNLEVEL_FLAGS};
+    use crate::RendererInitializationError;
+
+    fn adapter(downlevel: DownlevelFlags) -> Result<PotentialAdapter<()>, RendererInitializationError> {
+        let info = AdapterInfo {
+            name: String::from("test adapter"),
+            vendor: 0,
+            device: 0,
+            device_type: DeviceType::Other,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: Backend::Gl,
+        };
+        PotentialAdapter::new((), info, Limits::default(), Features::all(), downlevel, None)
     }
 
-    for backend in &default_backend_order {
-        if let Some(desired_backend) = desired_backend {
-            if desired_backend != *backend {
-                log::debug!("Skipping unwanted backend {:?}", backend);
-                continue;
-            }
-        }
+    #[test]
+    fn adapters_without_compute_or_vertex_storage_are_rejected() {
+        assert!(adapter(DownlevelFlags::all()).is_ok());
 
-        let adapter: Option<PotentialAdapter<Adapter>> =
-            valid_adapters.remove(backend).and_then(|arr| arr.into_iter().next());
-
-        if let Some(adapter) = adapter {
-            log::debug!("Chosen adapter: {:#?}", adapter.info);
-            log::debug!("Chosen backend: {:?}", backend);
-            log::debug!("Chosen features: {:#?}", adapter.features);
-            log::debug!("Chosen limits: {:#?}", adapter.limits);
-            log::debug!("Chosen profile: {:#?}", adapter.profile);
-
-            let (device, queue) = adapter
-                .inner
-                .request_device(
-                    &DeviceDescriptor {
-                        label: None,
-                        required_features: adapter.features.union(additional_features.unwrap_or_else(Features::empty)),
-                        required_limits: adapter.limits,
-                    },
-                    None,
-                )
-                .await
-                .map_err(|_| RendererInitializationError::RequestDeviceFailed)?;
-
-            return Ok(InstanceAdapterDevice {
-                instance: Arc::new(instance),
-                adapter: Arc::new(adapter.inner),
-                device: Arc::new(device),
-                queue: Arc::new(queue),
-                profile: adapter.profile,
-                info: adapter.info,
-            });
+        for missing in [DownlevelFlags::COMPUTE_SHADERS, DownlevelFlags::VERTEX_STORAGE, REQUIRED_DOWNLEVEL_FLAGS] {
+            match adapter(DownlevelFlags::all() - missing) {
+                Err(RendererInitializationError::MissingDownlevelFlags { flags }) => assert_eq!(flags, missing),
+                other => panic!("adapter missing {missing:?} wasn't rejected: {:?}", other.map(|p| p.profile)),
+            }
         }
     }
-
-    Err(RendererInitializationError::MissingAdapter)
 }
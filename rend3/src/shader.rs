@@ -1,15 +1,22 @@
 //! Holds the shader processing infrastructure for all shaders.
 
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
-    fmt::Write,
+    error::Error as StdError,
+    fmt::{self, Display, Write},
 };
 
 use handlebars::{Context, Handlebars, Helper, HelperDef, Output, RenderContext, RenderError, RenderErrorReason};
+use naga::{
+    valid::{Capabilities, ValidationFlags, Validator},
+    Span,
+};
 use parking_lot::Mutex;
 use rend3_types::{Material, MaterialArray, VertexAttributeId};
 use rust_embed::RustEmbed;
 use serde::Serialize;
+use thiserror::Error;
 
 use crate::RendererProfile;
 
@@ -81,6 +88,90 @@ impl ShaderPreProcessor {
         user_config: &T,
         buffer_config: Option<&ShaderVertexBufferConfig>,
     ) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        self.render_shader_inner(base, user_config, buffer_config, None)
+    }
+
+    /// Renders a shader like [`Self::render_shader`], keeping track of the
+    /// file and line every part of it came from.
+    pub fn render_shader_mapped<T>(
+        &self,
+        base: &str,
+        user_config: &T,
+        buffer_config: Option<&ShaderVertexBufferConfig>,
+    ) -> Result<PreprocessedShader, RenderError>
+    where
+        T: Serialize,
+    {
+        let files = Mutex::new(vec![base.to_owned()]);
+        let annotated = self.render_shader_inner(base, user_config, buffer_config, Some(&files))?;
+        Ok(PreprocessedShader::new(&annotated, files.into_inner()))
+    }
+
+    /// Renders a shader, then parses and validates it with naga, reporting
+    /// errors at the file and line they are in rather than in the rendered
+    /// shader.
+    ///
+    /// Validation allows every capability, so shaders needing features the
+    /// device lacks still pass here.
+    pub fn check_shader<T>(
+        &self,
+        base: &str,
+        user_config: &T,
+        buffer_config: Option<&ShaderVertexBufferConfig>,
+    ) -> Result<PreprocessedShader, ShaderError>
+    where
+        T: Serialize,
+    {
+        let shader = self.render_shader_mapped(base, user_config, buffer_config)?;
+
+        let module = naga::front::wgsl::parse_str(&shader.source)
+            .map_err(|e| ShaderError::Parse(shader.diagnostic(e.message().to_owned(), e.labels())))?;
+
+        Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module).map_err(|e| {
+            let mut message = e.as_inner().to_string();
+            let mut source = e.as_inner().source();
+            while let Some(inner) = source {
+                write!(message, ": {inner}").unwrap();
+                source = inner.source();
+            }
+            ShaderError::Validation(shader.diagnostic(message, e.spans().map(|(span, label)| (*span, label.as_str()))))
+        })?;
+
+        Ok(shader)
+    }
+
+    /// Renders a shader like [`Self::render_shader`] after checking it with
+    /// [`Self::check_shader`], for creating modules of shaders that must work.
+    ///
+    /// # Panics
+    ///
+    /// If the shader fails to render, parse, or validate, with the file and
+    /// line of the error.
+    pub fn render_valid_shader<T>(
+        &self,
+        base: &str,
+        user_config: &T,
+        buffer_config: Option<&ShaderVertexBufferConfig>,
+    ) -> String
+    where
+        T: Serialize,
+    {
+        match self.check_shader(base, user_config, buffer_config) {
+            Ok(shader) => shader.source,
+            Err(e) => panic!("Invalid shader {base}: {e}"),
+        }
+    }
+
+    fn render_shader_inner<T>(
+        &self,
+        base: &str,
+        user_config: &T,
+        buffer_config: Option<&ShaderVertexBufferConfig>,
+        mapped_files: Option<&Mutex<Vec<String>>>,
+    ) -> Result<String, RenderError>
     where
         T: Serialize,
     {
@@ -98,7 +189,7 @@ impl ShaderPreProcessor {
         registry.set_strict_mode(true);
         registry.set_dev_mode(cfg!(debug_assertions));
         registry.register_escape_fn(handlebars::no_escape);
-        registry.register_helper("include", Box::new(ShaderIncluder::new(base, &self.files)));
+        registry.register_helper("include", Box::new(ShaderIncluder::new(base, &self.files, mapped_files)));
         if let Some(config) = buffer_config {
             registry.register_helper("vertex_fetch", Box::new(ShaderVertexBufferHelper::new(config)));
         }
@@ -109,9 +200,14 @@ impl ShaderPreProcessor {
             ))
         })?;
 
+        let contents = match mapped_files {
+            Some(_) => Cow::Owned(annotate_lines(0, contents)),
+            None => Cow::Borrowed(contents),
+        };
+
         let vertex_array_counts = if let Some(buffer_config) = buffer_config { buffer_config.specs.len() } else { 0 };
 
-        registry.render_template(contents, &BufferConfigWrapper { vertex_array_counts, user_config })
+        registry.render_template(&contents, &BufferConfigWrapper { vertex_array_counts, user_config })
     }
 }
 
@@ -130,11 +226,14 @@ fn registered_shader_string(files: &HashMap<String, String>) -> String {
 struct ShaderIncluder<'a> {
     files: &'a HashMap<String, String>,
     include_state: Mutex<HashSet<String>>,
+    /// Files included so far, when rendering a [`PreprocessedShader`].
+    mapped_files: Option<&'a Mutex<Vec<String>>>,
 }
 impl<'a> ShaderIncluder<'a> {
-    fn new(base: &str, files: &'a HashMap<String, String>) -> Self {
+    fn new(base: &str, files: &'a HashMap<String, String>, mapped_files: Option<&'a Mutex<Vec<String>>>) -> Self {
         Self {
             files,
+            mapped_files,
             include_state: Mutex::new({
                 let mut set = HashSet::new();
                 set.insert(base.to_owned());
@@ -177,12 +276,187 @@ impl<'a> HelperDef for ShaderIncluder<'a> {
             ))
         })?;
 
-        out.write(&r.render_template(contents, ctx.data())?)?;
+        let contents = match self.mapped_files {
+            Some(mapped_files) => {
+                let mut mapped_files = mapped_files.lock();
+                mapped_files.push(file_name.clone());
+                Cow::Owned(annotate_lines(mapped_files.len() - 1, contents))
+            }
+            None => Cow::Borrowed(contents),
+        };
+
+        out.write(&r.render_template(&contents, ctx.data())?)?;
 
         Ok(())
     }
 }
 
+// Marks the source line of the text following it as `{file index}:{line}`
+// while rendering a `PreprocessedShader`. Private use characters, so they
+// never clash with shader code.
+const LINE_MARKER_START: char = '\u{E000}';
+const LINE_MARKER_END: char = '\u{E001}';
+
+/// Prefixes every line of a template with a marker of where it came from,
+/// except lines starting inside a handlebars expression.
+fn annotate_lines(file: usize, contents: &str) -> String {
+    let mut annotated = String::with_capacity(contents.len() * 2);
+    let mut in_expression = false;
+    for (line, text) in contents.split_inclusive('\n').enumerate() {
+        if !in_expression {
+            write!(annotated, "{LINE_MARKER_START}{file}:{line}{LINE_MARKER_END}").unwrap();
+        }
+        annotated.push_str(text);
+
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        while i + 1 < bytes.len() {
+            match &bytes[i..i + 2] {
+                b"{{" => in_expression = true,
+                b"}}" => in_expression = false,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            i += 2;
+        }
+    }
+    annotated
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LineMarker {
+    /// Byte offset in the rendered shader.
+    offset: usize,
+    file: usize,
+    /// Zero based.
+    line: usize,
+}
+
+/// A shader rendered by [`ShaderPreProcessor::render_shader_mapped`], which
+/// knows the file and line each part of it came from.
+#[derive(Debug, Clone)]
+pub struct PreprocessedShader {
+    source: String,
+    files: Vec<String>,
+    /// Ordered by offset.
+    markers: Vec<LineMarker>,
+}
+
+impl PreprocessedShader {
+    fn new(annotated: &str, files: Vec<String>) -> Self {
+        let mut source = String::with_capacity(annotated.len());
+        let mut markers = Vec::new();
+        let mut rest = annotated;
+        while let Some(start) = rest.find(LINE_MARKER_START) {
+            source.push_str(&rest[..start]);
+            rest = &rest[start + LINE_MARKER_START.len_utf8()..];
+            let end = rest.find(LINE_MARKER_END).unwrap();
+            let (file, line) = rest[..end].split_once(':').unwrap();
+            markers.push(LineMarker { offset: source.len(), file: file.parse().unwrap(), line: line.parse().unwrap() });
+            rest = &rest[end + LINE_MARKER_END.len_utf8()..];
+        }
+        source.push_str(rest);
+
+        Self { source, files, markers }
+    }
+
+    /// The rendered shader. Lines may be laid out differently than in the
+    /// output of [`ShaderPreProcessor::render_shader`].
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Where the byte at `offset` in [`Self::source`] came from. Code
+    /// generated by helpers, like `vertex_fetch`, is located at the helper.
+    pub fn locate(&self, offset: usize) -> Option<ShaderSourceLocation> {
+        let index = self.markers.partition_point(|marker| marker.offset <= offset).checked_sub(1)?;
+        let marker = self.markers[index];
+        let column = match self.source[marker.offset..offset.min(self.source.len())].contains('\n') {
+            true => 1,
+            false => offset - marker.offset + 1,
+        };
+        Some(ShaderSourceLocation { file: self.files[marker.file].clone(), line: marker.line + 1, column })
+    }
+
+    /// The rendered shader with a `// file:line` comment before every line
+    /// that doesn't follow the line before it, for dumping to disk.
+    pub fn annotated_source(&self) -> String {
+        let mut annotated = String::with_capacity(self.source.len() * 2);
+        let mut previous: Option<LineMarker> = None;
+        let mut written = 0;
+        for &marker in &self.markers {
+            let follows = previous.is_some_and(|p| p.file == marker.file && p.line + 1 == marker.line);
+            let line_start = marker.offset == 0 || self.source.as_bytes()[marker.offset - 1] == b'\n';
+            if !follows && line_start {
+                annotated.push_str(&self.source[written..marker.offset]);
+                writeln!(annotated, "// {}:{}", self.files[marker.file], marker.line + 1).unwrap();
+                written = marker.offset;
+            }
+            previous = Some(marker);
+        }
+        annotated.push_str(&self.source[written..]);
+        annotated
+    }
+
+    fn diagnostic<'a>(&self, message: String, labels: impl Iterator<Item = (Span, &'a str)>) -> ShaderDiagnostic {
+        let labels = labels
+            .map(|(span, label)| (span.to_range().and_then(|range| self.locate(range.start)), label.to_owned()))
+            .collect();
+        ShaderDiagnostic { message, labels }
+    }
+}
+
+/// A place in the files a [`PreprocessedShader`] was rendered from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderSourceLocation {
+    pub file: String,
+    /// One based.
+    pub line: usize,
+    /// One based, in bytes.
+    pub column: usize,
+}
+
+impl Display for ShaderSourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// A naga error, with the places it points to located in the original files.
+#[derive(Debug, Clone)]
+pub struct ShaderDiagnostic {
+    pub message: String,
+    /// Where the error is, with what is wrong there. `None` if naga gave no
+    /// location.
+    pub labels: Vec<(Option<ShaderSourceLocation>, String)>,
+}
+
+impl Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        for (location, label) in &self.labels {
+            match location {
+                Some(location) => write!(f, "\n  {location}: {label}")?,
+                None => write!(f, "\n  {label}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error from [`ShaderPreProcessor::check_shader`].
+#[derive(Debug, Error)]
+pub enum ShaderError {
+    #[error("Failed to render shader template: {0}")]
+    Template(#[from] RenderError),
+    #[error("Failed to parse shader: {0}")]
+    Parse(ShaderDiagnostic),
+    #[error("Shader failed validation: {0}")]
+    Validation(ShaderDiagnostic),
+}
+
 struct ShaderVertexBufferHelper<'a> {
     config: &'a ShaderVertexBufferConfig,
 }
@@ -215,8 +489,20 @@ impl<'a> HelperDef for ShaderVertexBufferHelper<'a> {
             _ => Err(RenderErrorReason::Other("Vertex buffer helper's first argument must be a string".to_string()))?,
         };
 
+        let mut specs = Vec::with_capacity(h.params().len() - 1);
+        for requested_attribute in &h.params()[1..] {
+            let requested_name = requested_attribute.relative_path().map(String::as_str).unwrap_or_default();
+            let spec = self.config.specs.iter().enumerate().find(|(_, s)| s.attribute.name() == requested_name);
+            specs.push(spec.ok_or_else(|| {
+                RenderErrorReason::Other(format!(
+                    "Vertex buffer helper was asked for the attribute \"{requested_name}\", which isn't one of the \
+                     material's supported attributes"
+                ))
+            })?);
+        }
+
         let template = self
-            .generate_template(h, object_buffer)
+            .generate_template(&specs, object_buffer)
             .map_err(|_| RenderErrorReason::Other("Failed to writeln vertex template string".to_string()))?;
 
         out.write(&r.render_template(&template, ctx.data())?)?;
@@ -226,7 +512,11 @@ impl<'a> HelperDef for ShaderVertexBufferHelper<'a> {
 }
 
 impl<'a> ShaderVertexBufferHelper<'a> {
-    fn generate_template(&self, h: &Helper, object_buffer: &str) -> Result<String, std::fmt::Error> {
+    fn generate_template(
+        &self,
+        specs: &[(usize, &VertexBufferSpec)],
+        object_buffer: &str,
+    ) -> Result<String, std::fmt::Error> {
         let includes = r#"{{include "rend3/vertex_attributes.wgsl"}}"#;
 
         let unpack_function = String::new();
@@ -236,18 +526,7 @@ impl<'a> ShaderVertexBufferHelper<'a> {
         let mut input_function = String::new();
         writeln!(input_function, "fn get_vertices(indices: Indices) -> VertexInput {{")?;
         writeln!(input_function, "    var verts: VertexInput;")?;
-        for requested_attribute in &h.params()[1..] {
-            let (attr_idx, spec) = self
-                .config
-                .specs
-                .iter()
-                .enumerate()
-                .find_map(|(idx, s)| match s.attribute.name() == requested_attribute.relative_path().unwrap() {
-                    true => Some((idx, s)),
-                    false => None,
-                })
-                .unwrap();
-
+        for &(attr_idx, spec) in specs {
             writeln!(input_struct, "    {}: {},", spec.attribute.name(), spec.attribute.metadata().shader_type)?;
 
             writeln!(
@@ -256,15 +535,22 @@ impl<'a> ShaderVertexBufferHelper<'a> {
                 spec.attribute.name(),
             )?;
 
+            let name = spec.attribute.name();
+            let extract_fn = spec.attribute.metadata().shader_extract_fn;
+            // Meshes storing the attribute packed flag it in the top bit of the offset.
+            let fetch = match spec.attribute.packing() {
+                Some(packing) => format!(
+                    "if (({name}_offset & 0x80000000u) != 0u) {{ verts.{name} = {}({name}_offset & 0x7FFFFFFFu, \
+                     indices.vertex); }} else {{ verts.{name} = {extract_fn}({name}_offset, indices.vertex); }}",
+                    packing.metadata().shader_extract_fn,
+                ),
+                None => format!("verts.{name} = {extract_fn}({name}_offset, indices.vertex);"),
+            };
+
             if spec.optional {
                 writeln!(input_function, "    if ({}_offset != 0xFFFFFFFFu) {{", spec.attribute.name())?;
 
-                writeln!(
-                    input_function,
-                    "        verts.{name} = {}({name}_offset, indices.vertex);",
-                    spec.attribute.metadata().shader_extract_fn,
-                    name = spec.attribute.name(),
-                )?;
+                writeln!(input_function, "        {fetch}")?;
 
                 writeln!(input_function, "    }}")?;
 
@@ -276,12 +562,7 @@ impl<'a> ShaderVertexBufferHelper<'a> {
                     )?;
                 }
             } else {
-                writeln!(
-                    input_function,
-                    "    verts.{name} = {}({name}_offset, indices.vertex);",
-                    spec.attribute.metadata().shader_extract_fn,
-                    name = spec.attribute.name(),
-                )?;
+                writeln!(input_function, "    {fetch}")?;
             }
         }
         writeln!(input_struct, "}}")?;
@@ -296,7 +577,45 @@ impl<'a> ShaderVertexBufferHelper<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{ShaderConfig, ShaderPreProcessor};
+    use rend3_types::{
+        Material, RawTexture2DHandle, Sorting, VertexAttribute, VertexAttributeId, VERTEX_ATTRIBUTE_POSITION,
+    };
+
+    use crate::{ShaderConfig, ShaderError, ShaderPreProcessor, ShaderVertexBufferConfig};
+
+    static VERTEX_ATTRIBUTE_WIND_WEIGHT: VertexAttribute<f32> = VertexAttribute::new("wind_weight", Some("0.0"));
+
+    struct WindMaterial;
+    impl Material for WindMaterial {
+        type DataType = glam::Vec4;
+        type TextureArrayType = [Option<RawTexture2DHandle>; 0];
+        type RequiredAttributeArrayType = [&'static VertexAttributeId; 1];
+        type SupportedAttributeArrayType = [&'static VertexAttributeId; 2];
+
+        fn required_attributes() -> Self::RequiredAttributeArrayType {
+            [&VERTEX_ATTRIBUTE_POSITION]
+        }
+
+        fn supported_attributes() -> Self::SupportedAttributeArrayType {
+            [&VERTEX_ATTRIBUTE_POSITION, &VERTEX_ATTRIBUTE_WIND_WEIGHT]
+        }
+
+        fn key(&self) -> u64 {
+            0
+        }
+
+        fn sorting(&self) -> Sorting {
+            Sorting::OPAQUE
+        }
+
+        fn to_textures(&self) -> Self::TextureArrayType {
+            []
+        }
+
+        fn to_data(&self) -> Self::DataType {
+            glam::Vec4::ZERO
+        }
+    }
 
     #[test]
     fn simple_include() {
@@ -330,6 +649,30 @@ mod tests {
         assert!(output.is_err(), "Expected error, got {output:?}");
     }
 
+    #[test]
+    fn custom_attribute_fetch() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shader("simple", "{{vertex_fetch object_buffer position wind_weight}}");
+        let config = ShaderConfig { profile: None, position_attribute_offset: 0 };
+        let buffer_config = ShaderVertexBufferConfig::from_material::<WindMaterial>();
+        let output = pp.render_shader("simple", &config, Some(&buffer_config)).unwrap();
+
+        assert!(output.contains("    wind_weight: f32,"), "{output}");
+        assert!(output.contains("verts.wind_weight = extract_attribute_f32(wind_weight_offset, indices.vertex);"));
+        assert!(output.contains("verts.wind_weight = 0.0;"));
+    }
+
+    #[test]
+    fn unsupported_attribute_fetch() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shader("simple", "{{vertex_fetch object_buffer bake_id}}");
+        let config = ShaderConfig { profile: None, position_attribute_offset: 0 };
+        let buffer_config = ShaderVertexBufferConfig::from_material::<WindMaterial>();
+        let output = pp.render_shader("simple", &config, Some(&buffer_config));
+
+        assert!(output.is_err(), "Expected error, got {output:?}");
+    }
+
     #[test]
     fn no_arg_include() {
         let mut pp = ShaderPreProcessor::new();
@@ -339,4 +682,38 @@ mod tests {
 
         assert!(output.is_err(), "Expected error, got {output:?}");
     }
+
+    #[test]
+    fn mapped_include() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shader("simple", "a\n{{include \"other\"}}\nb");
+        pp.add_shader("other", "c\nd");
+        let config = ShaderConfig { profile: None, position_attribute_offset: 0 };
+        let shader = pp.render_shader_mapped("simple", &config, None).unwrap();
+
+        assert_eq!(shader.source(), pp.render_shader("simple", &config, None).unwrap());
+        let location = |offset| {
+            let location = shader.locate(offset).unwrap();
+            (location.file, location.line, location.column)
+        };
+        assert_eq!(location(0), ("simple".to_owned(), 1, 1));
+        assert_eq!(location(4), ("other".to_owned(), 2, 1));
+        assert_eq!(location(6), ("simple".to_owned(), 3, 1));
+        assert_eq!(shader.annotated_source(), "// simple:1\na\n// other:1\nc\nd\n// simple:3\nb");
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shader("simple", "{{include \"other\"}}\nfn main() {}");
+        pp.add_shader("other", "fn a() {}\n\nfn b() {\n    let x = 1;\n    let y = ;\n}\n");
+        let config = ShaderConfig { profile: None, position_attribute_offset: 0 };
+        let error = pp.check_shader("simple", &config, None).unwrap_err();
+
+        let ShaderError::Parse(diagnostic) = error else {
+            panic!("Expected parse error, got {error:?}");
+        };
+        let location = diagnostic.labels[0].0.as_ref().unwrap();
+        assert_eq!((location.file.as_str(), location.line), ("other", 5));
+    }
 }
@@ -58,13 +58,16 @@ where
         }
     }
 
-    pub fn write_to_buffer(&mut self, device: &Device, queue: &Queue, data: &T) {
+    /// Write the data to the buffer, returning the number of bytes written.
+    pub fn write_to_buffer(&mut self, device: &Device, queue: &Queue, data: &T) -> u64 {
         let size = data.size();
         self.ensure_size(device, size.get());
 
         let mut mapped = queue.write_buffer_with(&self.inner, 0, size).unwrap();
         encase::StorageBuffer::new(&mut *mapped).write(data).unwrap();
         drop(mapped);
+
+        size.get()
     }
 }
 
@@ -46,8 +46,8 @@ impl FreelistDerivedBuffer {
     }
 
     pub fn use_index(&mut self, index: usize) {
-        if index > self.reserved_count {
-            self.reserved_count = index.next_power_of_two();
+        if index >= self.reserved_count {
+            self.reserved_count = (index + 1).next_power_of_two();
         }
 
         self.stale.push(index);
@@ -89,6 +89,10 @@ impl FreelistDerivedBuffer {
             return;
         }
 
+        // Indices changed several times in a frame are only uploaded once.
+        self.stale.sort_unstable();
+        self.stale.dedup();
+
         let data = self.stale.drain(..).map(|idx| {
             let data = get_value(idx);
             ScatterData { word_offset: u32::try_from((idx as u64 * self.rounded_size) / 4).unwrap(), data }
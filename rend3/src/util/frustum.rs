@@ -1,4 +1,4 @@
-//! Frustums and bounding spheres.
+//! Frustums, bounding spheres and bounding boxes.
 //!
 //! This entire module only exists because of <https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf>.
 
@@ -32,6 +32,48 @@ impl BoundingSphere {
     }
 }
 
+/// Axis aligned box containing a mesh.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+impl BoundingBox {
+    pub fn from_mesh(mesh: &[Vec3]) -> Self {
+        let Some(&first) = mesh.first() else {
+            return Self::default();
+        };
+        let (min, max) = mesh.iter().fold((first, first), |(min, max), &pos| (min.min(pos), max.max(pos)));
+
+        Self { min, max }
+    }
+
+    /// The box around a sphere, for meshes only known by their bounding sphere.
+    pub fn from_sphere(sphere: BoundingSphere) -> Self {
+        Self { min: sphere.center - sphere.radius, max: sphere.center + sphere.radius }
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(self, other: Self) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn center(self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// The axis aligned box containing this box after transforming it.
+    pub fn apply_transform(self, matrix: Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_extent = (self.max - self.min) / 2.0;
+        let extent = matrix.x_axis.xyz().abs() * half_extent.x
+            + matrix.y_axis.xyz().abs() * half_extent.y
+            + matrix.z_axis.xyz().abs() * half_extent.z;
+
+        Self { min: center - extent, max: center + extent }
+    }
+}
+
 fn find_mesh_center(mesh: &[Vec3]) -> Vec3A {
     let first = if let Some(first) = mesh.first() {
         *first
@@ -159,4 +201,20 @@ impl Frustum {
 
         true
     }
+
+    /// Determines if the box is at all inside the frustum. May return true for
+    /// boxes near the corners of the frustum which are outside of it.
+    pub fn contains_box(&self, aabb: BoundingBox) -> bool {
+        let array = [self.left, self.right, self.top, self.bottom, self.near];
+
+        for plane in &array {
+            // The corner furthest along the normal of the plane.
+            let positive = Vec3::select(plane.abc.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            if plane.distance(positive) < 0.0 {
+                return false;
+            }
+        }
+
+        true
+    }
 }
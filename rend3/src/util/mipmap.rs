@@ -144,21 +144,32 @@ impl MipmapGenerator {
         desc: &TextureDescriptor,
     ) -> Result<(), MipmapGenerationError> {
         profiling::scope!("generating mipmaps");
-        let mips: Result<ArrayVec<_, 14>, _> = (0..desc.size.max_mips(desc.dimension))
-            .map(|mip_level| {
-                let scope = AllocationErrorScope::new(device);
-                let view = texture.create_view(&TextureViewDescriptor {
-                    label: None,
-                    base_mip_level: mip_level,
-                    mip_level_count: Some(1),
-                    ..Default::default()
-                });
-                scope.end().map_err(|source| MipmapGenerationError::TextureViewCreationFailed { mip_level, source })?;
-
-                Ok(view)
+
+        // Every layer, like the faces of cube textures, gets its own chain.
+        let layers: Result<Vec<ArrayVec<_, 14>>, _> = (0..desc.array_layer_count())
+            .map(|layer| {
+                (0..desc.mip_level_count)
+                    .map(|mip_level| {
+                        let scope = AllocationErrorScope::new(device);
+                        let view = texture.create_view(&TextureViewDescriptor {
+                            label: None,
+                            dimension: Some(TextureViewDimension::D2),
+                            base_mip_level: mip_level,
+                            mip_level_count: Some(1),
+                            base_array_layer: layer,
+                            array_layer_count: Some(1),
+                            ..Default::default()
+                        });
+                        scope
+                            .end()
+                            .map_err(|source| MipmapGenerationError::TextureViewCreationFailed { mip_level, source })?;
+
+                        Ok(view)
+                    })
+                    .collect()
             })
             .collect();
-        let mips = mips?;
+        let layers = layers?;
 
         let mut read_pipelines = self.pipelines.read();
         let pipeline = match read_pipelines.get(&desc.format) {
@@ -176,7 +187,7 @@ impl MipmapGenerator {
             }
         };
 
-        for (idx, view_window) in mips.windows(2).enumerate() {
+        for (idx, view_window) in layers.iter().flat_map(|mips| mips.windows(2).enumerate()) {
             let src_view = &view_window[0];
             let dst_view = &view_window[1];
 
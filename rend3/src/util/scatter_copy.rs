@@ -1,4 +1,7 @@
-use std::num::NonZeroU64;
+use std::{
+    num::NonZeroU64,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
 
 use encase::{private::WriteInto, ShaderSize};
 use wgpu::{
@@ -24,6 +27,9 @@ impl<T> ScatterData<T> {
 pub struct ScatterCopy {
     pipeline: ComputePipeline,
     bgl: BindGroupLayout,
+    /// Bytes copied and dispatches issued since the last call to take_stats.
+    uploaded_bytes: AtomicU64,
+    dispatches: AtomicU32,
 }
 impl ScatterCopy {
     pub fn new(device: &Device) -> Self {
@@ -63,7 +69,7 @@ impl ScatterCopy {
             entry_point: "cs_main",
         });
 
-        Self { pipeline, bgl }
+        Self { pipeline, bgl, uploaded_bytes: AtomicU64::new(0), dispatches: AtomicU32::new(0) }
     }
 
     pub fn execute_copy<T, D>(
@@ -77,6 +83,8 @@ impl ScatterCopy {
         D: IntoIterator<Item = ScatterData<T>>,
         D::IntoIter: ExactSizeIterator,
     {
+        profiling::scope!("ScatterCopy::execute_copy");
+
         let data_iterator = data.into_iter();
 
         let size_of_t = T::SHADER_SIZE.get();
@@ -132,6 +140,14 @@ impl ScatterCopy {
         cpass.set_bind_group(0, &bg, &[]);
         cpass.dispatch_workgroups(div_round_up(count_u32, 64), 1, 1);
         drop(cpass);
+
+        self.uploaded_bytes.fetch_add(buffer_size, Ordering::Relaxed);
+        self.dispatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the bytes uploaded and dispatches issued since the last call.
+    pub(crate) fn take_stats(&self) -> (u64, u32) {
+        (self.uploaded_bytes.swap(0, Ordering::Relaxed), self.dispatches.swap(0, Ordering::Relaxed))
     }
 }
 
@@ -25,6 +25,8 @@ impl<'a> UploadChainer<'a> {
     }
 
     pub fn create_staging_buffer(&mut self, device: &Device) -> Result<(), wgpu::Error> {
+        profiling::scope!("UploadChainer::create_staging_buffer");
+
         let scope = AllocationErrorScope::new(device);
         self.staging_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("mesh staging buffer"),
@@ -38,6 +40,8 @@ impl<'a> UploadChainer<'a> {
     }
 
     pub fn encode_upload(&self, encoder: &mut CommandEncoder, buffer: &Buffer) {
+        profiling::scope!("UploadChainer::encode_upload");
+
         let staging_buffer = self.staging_buffer.as_ref().unwrap();
 
         for upload in &self.uploads {